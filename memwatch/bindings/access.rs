@@ -0,0 +1,531 @@
+// Read-access tracking
+//
+// A region watched with WatchOptions::track_reads has its pages set to
+// PROT_NONE, so the first access after each check faults into a SIGSEGV
+// handler of ours, which records an AccessEvent (Read or Write, from the
+// fault's error code) and opens the page again: readable in Protect mode,
+// where the native backend keeps catching the writes, fully accessible
+// otherwise. Every check_changes() re-arms the pages, so each page reports
+// at most one read and one write per check, enough to find who consumes a
+// config blob without faulting on every load.
+//
+// Accesses queue up with the watcher (take_accesses()); check_events()
+// returns them together with the changes as one Event stream. Accesses
+// made by the watcher itself (its polls, peek(), hooks and sinks) are not
+// reported. The handler chains to whatever handler it replaced, the native
+// core's included, for faults it does not own. x86_64 Linux only: other
+// targets do not expose read/write in the fault context.
+
+use std::collections::HashMap;
+
+use crate::{ChangeEvent, MemWatch};
+
+/// What a tracked access did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+impl AccessKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccessKind::Read => "read",
+            AccessKind::Write => "write",
+        }
+    }
+}
+
+/// First access to a read-tracked page since the last check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessEvent {
+    pub timestamp_ns: u64,
+    pub region_id: u32,
+    pub variable_name: Option<String>,
+    pub kind: AccessKind,
+    /// Accessed address and its offset in the region
+    pub addr: u64,
+    pub offset: usize,
+    pub tid: u32,
+    pub fault_ip: u64,
+}
+
+/// Anything the watcher reports; see check_events()
+#[derive(Debug, Clone)]
+// Nearly every event is a change; boxing them would cost an allocation each
+#[allow(clippy::large_enum_variant)]
+pub enum Event {
+    Change(ChangeEvent),
+    Access(AccessEvent),
+}
+
+impl Event {
+    pub fn timestamp_ns(&self) -> u64 {
+        match self {
+            Event::Change(change) => change.timestamp_ns,
+            Event::Access(access) => access.timestamp_ns,
+        }
+    }
+
+    pub fn region_id(&self) -> u32 {
+        match self {
+            Event::Change(change) => change.region_id,
+            Event::Access(access) => access.region_id,
+        }
+    }
+}
+
+/// Accesses kept for take_accesses(); older ones are dropped
+const MAX_PENDING: usize = 4096;
+
+/// Read-tracked regions of one watcher
+#[derive(Default)]
+pub(crate) struct ReadTracker {
+    /// region id -> (addr, size, name)
+    regions: HashMap<u32, (u64, usize, String)>,
+    /// Next entry of the process-wide access log to read
+    cursor: u64,
+    pending: Vec<AccessEvent>,
+}
+
+impl ReadTracker {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// Track reads of a region; `writes_fault` keeps opened pages
+    /// read-only for the native backend
+    pub(crate) fn track(&mut self, region_id: u32, addr: u64, size: usize, name: &str, writes_fault: bool) -> Result<(), String> {
+        if self.regions.is_empty() {
+            // Only accesses from now on belong to this watcher
+            self.cursor = sys::log_head();
+        }
+        sys::track(addr as usize, size, writes_fault)?;
+        self.regions.insert(region_id, (addr, size, name.to_string()));
+        Ok(())
+    }
+
+    pub(crate) fn untrack(&mut self, region_id: u32) {
+        if let Some((addr, size, _)) = self.regions.remove(&region_id) {
+            sys::untrack(addr as usize, size);
+        }
+    }
+
+    /// Move new log entries for our regions to the pending queue
+    pub(crate) fn collect(&mut self) {
+        if self.regions.is_empty() {
+            return;
+        }
+        for entry in sys::read_log(&mut self.cursor) {
+            let hit = self.regions.iter().find(|(_, (addr, size, _))| (*addr..*addr + *size as u64).contains(&entry.addr));
+            if let Some((&region_id, (addr, _, name))) = hit {
+                self.pending.push(AccessEvent {
+                    timestamp_ns: entry.timestamp_ns,
+                    region_id,
+                    variable_name: Some(name.clone()),
+                    kind: entry.kind,
+                    addr: entry.addr,
+                    offset: (entry.addr - addr) as usize,
+                    tid: entry.tid,
+                    fault_ip: entry.fault_ip,
+                });
+            }
+        }
+        let excess = self.pending.len().saturating_sub(MAX_PENDING);
+        self.pending.drain(..excess);
+    }
+
+    pub(crate) fn take(&mut self) -> Vec<AccessEvent> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Protect every opened page again
+    pub(crate) fn rearm(&self) {
+        for (addr, size, _) in self.regions.values() {
+            sys::rearm(*addr as usize, *size);
+        }
+    }
+}
+
+impl Drop for ReadTracker {
+    fn drop(&mut self) {
+        for (addr, size, _) in self.regions.values() {
+            sys::untrack(*addr as usize, *size);
+        }
+    }
+}
+
+/// Run `f` as the watcher's own work: its accesses are not reported and
+/// TrackingAllocator does not watch what it allocates
+pub(crate) fn internal<R>(f: impl FnOnce() -> R) -> R {
+    crate::allocator::inside(|| sys::internal(f))
+}
+
+/// Address of our SIGSEGV handler, 0 before the first tracked region
+pub(crate) fn handler_address() -> usize {
+    sys::handler_address()
+}
+
+impl MemWatch {
+    /// Accesses to read-tracked regions found by checks since the last call
+    pub fn take_accesses(&self) -> Vec<AccessEvent> {
+        self.reads.lock().unwrap().take()
+    }
+
+    /// check_changes() plus the accesses it found (see the access module),
+    /// ordered by time
+    pub fn check_events(&self) -> Result<Vec<Event>, crate::CheckError> {
+        let changes = self.check_changes()?;
+        let mut events: Vec<Event> = changes.into_iter().map(Event::Change).collect();
+        events.extend(self.take_accesses().into_iter().map(Event::Access));
+        events.sort_by_key(Event::timestamp_ns);
+        Ok(events)
+    }
+}
+
+struct LogEntry {
+    timestamp_ns: u64,
+    addr: u64,
+    kind: AccessKind,
+    tid: u32,
+    fault_ip: u64,
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod sys {
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+    use std::sync::{Mutex, OnceLock};
+
+    use super::{AccessKind, LogEntry};
+
+    const MAX_PAGES: usize = 1024;
+    const LOG_SLOTS: usize = 4096;
+    /// Page fault error code bit set for writes
+    const PF_WRITE: i64 = 2;
+
+    struct PageSlot {
+        /// Page start, 0 when free
+        page: AtomicUsize,
+        refs: AtomicU32,
+        armed: AtomicBool,
+        open_prot: AtomicI32,
+    }
+
+    struct LogSlot {
+        /// Log index + 1 once written
+        seq: AtomicU64,
+        timestamp_ns: AtomicU64,
+        addr: AtomicU64,
+        kind: AtomicU8,
+        tid: AtomicU32,
+        fault_ip: AtomicU64,
+    }
+
+    #[allow(clippy::declare_interior_mutable_const)]
+    const FREE_PAGE: PageSlot = PageSlot {
+        page: AtomicUsize::new(0),
+        refs: AtomicU32::new(0),
+        armed: AtomicBool::new(false),
+        open_prot: AtomicI32::new(0),
+    };
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY_LOG: LogSlot = LogSlot {
+        seq: AtomicU64::new(0),
+        timestamp_ns: AtomicU64::new(0),
+        addr: AtomicU64::new(0),
+        kind: AtomicU8::new(0),
+        tid: AtomicU32::new(0),
+        fault_ip: AtomicU64::new(0),
+    };
+
+    // Fixed tables of atomics: the handler may neither lock nor allocate
+    static PAGES: [PageSlot; MAX_PAGES] = [FREE_PAGE; MAX_PAGES];
+    static LOG: [LogSlot; LOG_SLOTS] = [EMPTY_LOG; LOG_SLOTS];
+    static LOG_HEAD: AtomicU64 = AtomicU64::new(0);
+    static PAGE_SIZE: AtomicUsize = AtomicUsize::new(4096);
+    /// Handler replaced by ours
+    static PREVIOUS: OnceLock<libc::sigaction> = OnceLock::new();
+    /// Serializes slot updates outside the handler
+    static TABLE: Mutex<()> = Mutex::new(());
+
+    thread_local! {
+        static INTERNAL: Cell<bool> = const { Cell::new(false) };
+    }
+
+    pub(super) fn internal<R>(f: impl FnOnce() -> R) -> R {
+        let outer = INTERNAL.with(|flag| flag.replace(true));
+        let result = f();
+        INTERNAL.with(|flag| flag.set(outer));
+        result
+    }
+
+    pub(super) fn handler_address() -> usize {
+        match PREVIOUS.get() {
+            Some(_) => handler as *const () as usize,
+            None => 0,
+        }
+    }
+
+    fn install() -> Result<(), String> {
+        if PREVIOUS.get().is_some() {
+            return Ok(());
+        }
+        // SAFETY: sysconf has no preconditions; the sigaction structs are
+        // fully initialized before use
+        unsafe {
+            let page_size = libc::sysconf(libc::_SC_PAGESIZE);
+            if page_size > 0 {
+                PAGE_SIZE.store(page_size as usize, Ordering::SeqCst);
+            }
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handler as *const () as usize;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            if libc::sigaction(libc::SIGSEGV, &action, &mut previous) != 0 {
+                return Err(format!("Failed to install the read-tracking handler: {}", std::io::Error::last_os_error()));
+            }
+            let _ = PREVIOUS.set(previous);
+        }
+        Ok(())
+    }
+
+    fn pages(addr: usize, size: usize) -> impl Iterator<Item = usize> {
+        let page_size = PAGE_SIZE.load(Ordering::Relaxed);
+        (addr / page_size * page_size..addr + size.max(1)).step_by(page_size)
+    }
+
+    fn protect(page: usize, prot: i32) {
+        // SAFETY: page is a page start inside a region the caller watches
+        unsafe { libc::mprotect(page as *mut libc::c_void, PAGE_SIZE.load(Ordering::Relaxed), prot) };
+    }
+
+    pub(super) fn track(addr: usize, size: usize, writes_fault: bool) -> Result<(), String> {
+        install()?;
+        let open_prot = if writes_fault { libc::PROT_READ } else { libc::PROT_READ | libc::PROT_WRITE };
+        let _table = TABLE.lock().unwrap_or_else(|e| e.into_inner());
+        let needed = pages(addr, size).filter(|&page| !PAGES.iter().any(|s| s.page.load(Ordering::SeqCst) == page)).count();
+        if PAGES.iter().filter(|s| s.page.load(Ordering::SeqCst) == 0).count() < needed {
+            return Err(format!("Cannot track reads of more than {} pages", MAX_PAGES));
+        }
+        for page in pages(addr, size) {
+            if let Some(slot) = PAGES.iter().find(|s| s.page.load(Ordering::SeqCst) == page) {
+                slot.refs.fetch_add(1, Ordering::SeqCst);
+                continue;
+            }
+            let slot = PAGES.iter().find(|s| s.page.load(Ordering::SeqCst) == 0).expect("free slots counted above");
+            slot.refs.store(1, Ordering::SeqCst);
+            slot.open_prot.store(open_prot, Ordering::SeqCst);
+            slot.armed.store(true, Ordering::SeqCst);
+            slot.page.store(page, Ordering::SeqCst);
+            protect(page, libc::PROT_NONE);
+        }
+        Ok(())
+    }
+
+    pub(super) fn untrack(addr: usize, size: usize) {
+        let _table = TABLE.lock().unwrap_or_else(|e| e.into_inner());
+        for page in pages(addr, size) {
+            let Some(slot) = PAGES.iter().find(|s| s.page.load(Ordering::SeqCst) == page) else {
+                continue;
+            };
+            if slot.refs.fetch_sub(1, Ordering::SeqCst) == 1 {
+                protect(page, slot.open_prot.load(Ordering::SeqCst));
+                slot.armed.store(false, Ordering::SeqCst);
+                slot.page.store(0, Ordering::SeqCst);
+            }
+        }
+    }
+
+    pub(super) fn rearm(addr: usize, size: usize) {
+        let _table = TABLE.lock().unwrap_or_else(|e| e.into_inner());
+        for page in pages(addr, size) {
+            if let Some(slot) = PAGES.iter().find(|s| s.page.load(Ordering::SeqCst) == page) {
+                if !slot.armed.swap(true, Ordering::SeqCst) {
+                    protect(page, libc::PROT_NONE);
+                }
+            }
+        }
+    }
+
+    pub(super) fn log_head() -> u64 {
+        LOG_HEAD.load(Ordering::SeqCst)
+    }
+
+    /// Entries from `cursor` on; stops at one still being written
+    pub(super) fn read_log(cursor: &mut u64) -> Vec<LogEntry> {
+        let head = LOG_HEAD.load(Ordering::SeqCst);
+        let mut index = (*cursor).max(head.saturating_sub(LOG_SLOTS as u64));
+        let mut entries = Vec::new();
+        while index < head {
+            let slot = &LOG[(index % LOG_SLOTS as u64) as usize];
+            if slot.seq.load(Ordering::Acquire) != index + 1 {
+                break;
+            }
+            entries.push(LogEntry {
+                timestamp_ns: slot.timestamp_ns.load(Ordering::Relaxed),
+                addr: slot.addr.load(Ordering::Relaxed),
+                kind: if slot.kind.load(Ordering::Relaxed) == 1 { AccessKind::Write } else { AccessKind::Read },
+                tid: slot.tid.load(Ordering::Relaxed),
+                fault_ip: slot.fault_ip.load(Ordering::Relaxed),
+            });
+            index += 1;
+        }
+        *cursor = index;
+        entries
+    }
+
+    fn now_ns() -> u64 {
+        let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+        // SAFETY: clock_gettime is async-signal-safe and writes into ts
+        unsafe { libc::clock_gettime(libc::CLOCK_REALTIME, &mut ts) };
+        ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+    }
+
+    extern "C" fn handler(signal: libc::c_int, info: *mut libc::siginfo_t, context: *mut libc::c_void) {
+        // SAFETY: the kernel passes a valid siginfo and ucontext for
+        // SA_SIGINFO handlers; only atomics and async-signal-safe calls below
+        unsafe {
+            let addr = (*info).si_addr() as usize;
+            let page = addr & !(PAGE_SIZE.load(Ordering::Relaxed) - 1);
+            let context = context as *mut libc::ucontext_t;
+            let error = (*context).uc_mcontext.gregs[libc::REG_ERR as usize];
+            let write = error & PF_WRITE != 0;
+            let Some(slot) = PAGES.iter().find(|s| s.page.load(Ordering::SeqCst) == page) else {
+                return chain(signal, info, context as *mut libc::c_void);
+            };
+            let open_prot = slot.open_prot.load(Ordering::SeqCst);
+            if !slot.armed.swap(false, Ordering::SeqCst) {
+                // Another thread opened the page first; only writes to a
+                // page we leave read-only belong to the native core
+                if write && open_prot & libc::PROT_WRITE == 0 {
+                    return chain(signal, info, context as *mut libc::c_void);
+                }
+                return;
+            }
+            if !INTERNAL.with(|flag| flag.get()) {
+                let index = LOG_HEAD.fetch_add(1, Ordering::SeqCst);
+                let entry = &LOG[(index % LOG_SLOTS as u64) as usize];
+                entry.seq.store(0, Ordering::Release);
+                entry.timestamp_ns.store(now_ns(), Ordering::Relaxed);
+                entry.addr.store(addr as u64, Ordering::Relaxed);
+                entry.kind.store(write as u8, Ordering::Relaxed);
+                entry.tid.store(libc::gettid() as u32, Ordering::Relaxed);
+                entry.fault_ip.store((*context).uc_mcontext.gregs[libc::REG_RIP as usize] as u64, Ordering::Relaxed);
+                entry.seq.store(index + 1, Ordering::Release);
+            }
+            protect(page, open_prot);
+        }
+    }
+
+    /// Hand a fault we do not own to the handler we replaced
+    unsafe fn chain(signal: libc::c_int, info: *mut libc::siginfo_t, context: *mut libc::c_void) {
+        let Some(previous) = PREVIOUS.get() else {
+            return;
+        };
+        match previous.sa_sigaction {
+            libc::SIG_DFL | libc::SIG_IGN => {
+                // Fault again with the default action: a genuine crash
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = libc::SIG_DFL;
+                libc::sigaction(signal, &action, std::ptr::null_mut());
+            }
+            address if previous.sa_flags & libc::SA_SIGINFO != 0 => {
+                let previous = std::mem::transmute::<usize, extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void)>(address);
+                previous(signal, info, context);
+            }
+            address => {
+                let previous = std::mem::transmute::<usize, extern "C" fn(libc::c_int)>(address);
+                previous(signal);
+            }
+        }
+    }
+}
+
+#[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+mod sys {
+    use super::LogEntry;
+
+    pub(super) fn internal<R>(f: impl FnOnce() -> R) -> R {
+        f()
+    }
+
+    pub(super) fn handler_address() -> usize {
+        0
+    }
+
+    pub(super) fn track(_addr: usize, _size: usize, _writes_fault: bool) -> Result<(), String> {
+        Err("Read tracking needs x86_64 Linux".to_string())
+    }
+
+    pub(super) fn untrack(_addr: usize, _size: usize) {}
+
+    pub(super) fn rearm(_addr: usize, _size: usize) {}
+
+    pub(super) fn log_head() -> u64 {
+        0
+    }
+
+    pub(super) fn read_log(_cursor: &mut u64) -> Vec<LogEntry> {
+        Vec::new()
+    }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure")), target_os = "linux", target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native;
+    use crate::WatchOptions;
+
+    #[test]
+    fn test_reads_and_writes_are_reported_once_per_check() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        let page = watcher.capabilities.page_size;
+        let layout = std::alloc::Layout::from_size_align(page, page).unwrap();
+        let config = unsafe { std::alloc::alloc_zeroed(layout) };
+        let bytes = unsafe { std::slice::from_raw_parts(config, 64) };
+        let options = WatchOptions { track_reads: true, ..WatchOptions::default() };
+        let id = watcher.watch_with_options(bytes, "config", &options).unwrap();
+        watcher.check_changes().unwrap();
+
+        let read = |offset: usize| unsafe { std::ptr::read_volatile(config.add(offset)) };
+        assert_eq!((read(8), read(9)), (0, 0));
+        let events = watcher.check_events().unwrap();
+        let accesses: Vec<&AccessEvent> = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Access(access) => Some(access),
+                Event::Change(_) => None,
+            })
+            .collect();
+        assert_eq!(accesses.len(), 1);
+        assert_eq!((accesses[0].region_id, accesses[0].kind, accesses[0].offset), (id, AccessKind::Read, 8));
+        assert_eq!(accesses[0].tid, crate::process::current_tid());
+
+        unsafe { std::ptr::write_volatile(config.add(3), 7) };
+        let events = watcher.check_events().unwrap();
+        assert!(events.iter().any(|e| matches!(e, Event::Access(a) if a.kind == AccessKind::Write && a.offset == 3)));
+        assert!(events.iter().any(|e| matches!(e, Event::Change(c) if c.region_id == id)));
+
+        // The watcher's own reads do not count
+        let capture = std::env::temp_dir().join(format!("memwatch_access_capture_{}", std::process::id()));
+        let own_reads: [&dyn Fn(); 3] = [
+            &|| drop(watcher.peek(id).unwrap()),
+            &|| drop(watcher.scan_region(id, &[crate::scan::Pattern::Email]).unwrap()),
+            &|| crate::forensics::capture(&watcher, &[id], &capture).unwrap(),
+        ];
+        for own_read in own_reads {
+            own_read();
+            assert!(watcher.check_changes().is_ok() && watcher.take_accesses().is_empty());
+        }
+        let _ = std::fs::remove_dir_all(&capture);
+        watcher.unwatch(id).unwrap();
+        assert_eq!(read(3), 7);
+        assert!(watcher.check_events().unwrap().iter().all(|e| matches!(e, Event::Change(_))));
+        unsafe { std::alloc::dealloc(config, layout) };
+    }
+}
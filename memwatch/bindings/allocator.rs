@@ -0,0 +1,304 @@
+// Watching heap blocks without watch() calls
+//
+// TrackingAllocator wraps a GlobalAlloc (System by default). While a watcher
+// is attached, every block of at least `threshold` bytes is watched as
+// "heap@0x<addr>" from allocation until it is freed. Blocks that size are
+// always given zeroed pages of their own, so watching one never reads
+// uninitialized memory and its faults are never caused by small
+// allocations around it (see the isolation module). Allocations made inside
+// the watcher (in watch, unwatch and check calls, including the ones this
+// allocator makes to register a block) go straight to the wrapped
+// allocator, since watching them there would lock the watcher against
+// itself. A block whose unwatch fails, or that is freed inside the watcher
+// while watched, is leaked rather than handed back while still protected.
+// A watched block that realloc moves keeps its region, relocated (see
+// MemWatch::relocate) rather than unwatched and watched anew.
+//
+//     #[global_allocator]
+//     static ALLOC: TrackingAllocator = TrackingAllocator::new(64 * 1024);
+//
+//     let watcher: &'static MemWatch = Box::leak(Box::new(MemWatch::new()?));
+//     ALLOC.attach(watcher);
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Mutex;
+
+use crate::{capabilities, MemWatch};
+
+thread_local! {
+    /// Set while this thread is inside the watcher
+    static BUSY: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Run `f` as the watcher's own work: blocks it allocates are not watched
+pub(crate) fn inside<R>(f: impl FnOnce() -> R) -> R {
+    let _reentry = Reentry::enter();
+    f()
+}
+
+/// Clears BUSY when the watcher call returns
+struct Reentry;
+
+impl Reentry {
+    /// None when the thread is already inside the watcher (or exiting)
+    fn enter() -> Option<Reentry> {
+        BUSY.try_with(|busy| !busy.replace(true)).unwrap_or(false).then_some(Reentry)
+    }
+}
+
+impl Drop for Reentry {
+    fn drop(&mut self) {
+        let _ = BUSY.try_with(|busy| busy.set(false));
+    }
+}
+
+/// Global allocator that watches large heap blocks; see attach()
+pub struct TrackingAllocator<A = System> {
+    inner: A,
+    threshold: usize,
+    watcher: AtomicPtr<MemWatch>,
+    /// Watched blocks: address -> (region id, watcher that registered it)
+    blocks: Mutex<BTreeMap<usize, (u32, &'static MemWatch)>>,
+}
+
+impl TrackingAllocator<System> {
+    /// Track blocks of at least `threshold` bytes from the system allocator
+    pub const fn new(threshold: usize) -> Self {
+        TrackingAllocator::wrapping(System, threshold)
+    }
+}
+
+impl<A: GlobalAlloc> TrackingAllocator<A> {
+    /// Track blocks of at least `threshold` bytes from `inner`
+    pub const fn wrapping(inner: A, threshold: usize) -> Self {
+        TrackingAllocator {
+            inner,
+            threshold: if threshold == 0 { 1 } else { threshold },
+            watcher: AtomicPtr::new(ptr::null_mut()),
+            blocks: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Watch large blocks allocated from now on with `watcher`
+    pub fn attach(&self, watcher: &'static MemWatch) {
+        self.watcher.store(watcher as *const MemWatch as *mut MemWatch, Ordering::Release);
+    }
+
+    /// Stop watching new blocks; blocks already watched stay watched until
+    /// they are freed
+    pub fn detach(&self) {
+        self.watcher.store(ptr::null_mut(), Ordering::Release);
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Region of the watched block starting at `ptr`, if any
+    pub fn region_of(&self, ptr: *const u8) -> Option<u32> {
+        let _reentry = Reentry::enter()?;
+        self.blocks.lock().unwrap_or_else(|e| e.into_inner()).get(&(ptr as usize)).map(|&(region_id, _)| region_id)
+    }
+
+    /// Number of blocks currently watched
+    pub fn tracked_blocks(&self) -> usize {
+        let Some(_reentry) = Reentry::enter() else {
+            return 0;
+        };
+        self.blocks.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Page-aligned layout padded to whole pages for blocks worth tracking;
+    /// depends on the size only, so alloc and dealloc always agree
+    fn tracked_layout(&self, layout: Layout) -> Option<Layout> {
+        if layout.size() < self.threshold {
+            return None;
+        }
+        let align = capabilities::page_size().max(layout.align());
+        Layout::from_size_align(layout.size().div_ceil(align) * align, align).ok()
+    }
+
+    unsafe fn track(&self, block: *mut u8, size: usize) {
+        let watcher = self.watcher.load(Ordering::Acquire);
+        if block.is_null() || watcher.is_null() {
+            return;
+        }
+        let Some(_reentry) = Reentry::enter() else {
+            return;
+        };
+        // SAFETY: attach() only stores &'static MemWatch
+        let watcher: &'static MemWatch = &*watcher;
+        // SAFETY: tracked blocks are zeroed, so all `size` bytes are initialized
+        let bytes = std::slice::from_raw_parts(block as *const u8, size);
+        if let Ok(region_id) = watcher.watch(bytes, &format!("heap@0x{:x}", block as usize)) {
+            self.blocks.lock().unwrap_or_else(|e| e.into_inner()).insert(block as usize, (region_id, watcher));
+        }
+    }
+
+    /// Unwatch the block at `block`; false if it is still watched and must
+    /// not be released
+    fn untrack(&self, block: *mut u8) -> bool {
+        let Some(_reentry) = Reentry::enter() else {
+            // Unwatching would need the watcher this thread is inside of
+            return !self.blocks.lock().unwrap_or_else(|e| e.into_inner()).contains_key(&(block as usize));
+        };
+        let entry = self.blocks.lock().unwrap_or_else(|e| e.into_inner()).remove(&(block as usize));
+        match entry {
+            Some((region_id, watcher)) => watcher.unwatch(region_id).is_ok(),
+            None => true,
+        }
+    }
+
+    /// Move the watched block at `block` into a new `new` block and relocate
+    /// its region; None (and nothing done) when the block is not watched or
+    /// the region cannot move
+    unsafe fn move_tracked(&self, block: *mut u8, old: Layout, old_size: usize, new: Layout, new_size: usize) -> Option<*mut u8> {
+        let _reentry = Reentry::enter()?;
+        let (region_id, watcher) = *self.blocks.lock().unwrap_or_else(|e| e.into_inner()).get(&(block as usize))?;
+        let new_block = self.inner.alloc_zeroed(new);
+        if new_block.is_null() {
+            return Some(new_block);
+        }
+        ptr::copy_nonoverlapping(block, new_block, old_size.min(new_size));
+        // SAFETY: zeroed, then partly copied: all `new_size` bytes are initialized
+        let bytes = std::slice::from_raw_parts(new_block as *const u8, new_size);
+        match watcher.relocate(region_id, bytes) {
+            Ok(new_id) => {
+                let mut blocks = self.blocks.lock().unwrap_or_else(|e| e.into_inner());
+                blocks.remove(&(block as usize));
+                blocks.insert(new_block as usize, (new_id, watcher));
+                drop(blocks);
+                self.inner.dealloc(block, old);
+                Some(new_block)
+            }
+            Err(_) => {
+                self.inner.dealloc(new_block, new);
+                None
+            }
+        }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.tracked_layout(layout) {
+            Some(tracked) => {
+                let block = self.inner.alloc_zeroed(tracked);
+                self.track(block, layout.size());
+                block
+            }
+            None => self.inner.alloc(layout),
+        }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        match self.tracked_layout(layout) {
+            Some(_) => self.alloc(layout),
+            None => self.inner.alloc_zeroed(layout),
+        }
+    }
+
+    unsafe fn dealloc(&self, block: *mut u8, layout: Layout) {
+        match self.tracked_layout(layout) {
+            Some(tracked) => {
+                if self.untrack(block) {
+                    self.inner.dealloc(block, tracked);
+                }
+            }
+            None => self.inner.dealloc(block, layout),
+        }
+    }
+
+    unsafe fn realloc(&self, block: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        if self.tracked_layout(layout).is_none() && self.tracked_layout(new_layout).is_none() {
+            return self.inner.realloc(block, layout, new_size);
+        }
+        if let (Some(old), Some(new)) = (self.tracked_layout(layout), self.tracked_layout(new_layout)) {
+            if let Some(moved) = self.move_tracked(block, old, layout.size(), new, new_size) {
+                return moved;
+            }
+        }
+        let new_block = self.alloc(new_layout);
+        if !new_block.is_null() {
+            ptr::copy_nonoverlapping(block, new_block, layout.size().min(new_size));
+            self.dealloc(block, layout);
+        }
+        new_block
+    }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::fake_native;
+    use crate::EventKind;
+
+    #[test]
+    fn test_tracks_large_blocks_while_attached() {
+        let _guard = fake_native::lock();
+        let watcher: &'static MemWatch = Box::leak(Box::new(MemWatch::new().unwrap()));
+        let alloc = TrackingAllocator::new(4096);
+        let small = Layout::from_size_align(64, 8).unwrap();
+        let large = Layout::from_size_align(10_000, 8).unwrap();
+
+        unsafe {
+            let before = alloc.alloc(large);
+            alloc.attach(watcher);
+            let tiny = alloc.alloc(small);
+            let block = alloc.alloc(large);
+            assert_eq!(alloc.region_of(tiny), None);
+            assert_eq!(alloc.region_of(before), None);
+            let region_id = alloc.region_of(block).unwrap();
+            assert_eq!(block as usize % capabilities::page_size(), 0);
+            assert_eq!(watcher.regions.lock().unwrap()[&region_id].size, 10_000);
+
+            // Growing moves the block and relocates its watch
+            let grown = alloc.realloc(block, large, 20_000);
+            assert!(!watcher.regions.lock().unwrap().contains_key(&region_id));
+            let region_id = alloc.region_of(grown).unwrap();
+            assert_eq!(alloc.tracked_blocks(), 1);
+            assert_eq!(watcher.regions.lock().unwrap()[&region_id].size, 20_000);
+            let relocated = watcher.markers.lock().unwrap().iter().any(|marker| {
+                marker.region_id == region_id && marker.kind == (EventKind::Relocated { old_addr: block as u64, new_addr: grown as u64 })
+            });
+            assert!(relocated);
+
+            alloc.detach();
+            let untracked = alloc.alloc(large);
+            assert_eq!(alloc.region_of(untracked), None);
+            alloc.dealloc(grown, Layout::from_size_align(20_000, 8).unwrap());
+            assert!(!watcher.regions.lock().unwrap().contains_key(&region_id));
+            assert_eq!(alloc.tracked_blocks(), 0);
+            for (block, layout) in [(before, large), (tiny, small), (untracked, large)] {
+                alloc.dealloc(block, layout);
+            }
+        }
+    }
+
+    #[test]
+    fn test_blocks_inside_the_watcher_are_left_alone() {
+        let _guard = fake_native::lock();
+        let watcher: &'static MemWatch = Box::leak(Box::new(MemWatch::new().unwrap()));
+        let alloc = TrackingAllocator::new(4096);
+        let large = Layout::from_size_align(10_000, 8).unwrap();
+        alloc.attach(watcher);
+
+        unsafe {
+            let inner = crate::access::internal(|| alloc.alloc(large));
+            assert_eq!(alloc.region_of(inner), None);
+            alloc.dealloc(inner, large);
+
+            // Freed inside the watcher while watched: kept, and still watched
+            let block = alloc.alloc(large);
+            let region_id = alloc.region_of(block).unwrap();
+            crate::access::internal(|| alloc.dealloc(block, large));
+            assert_eq!(alloc.region_of(block), Some(region_id));
+            assert!(watcher.regions.lock().unwrap().contains_key(&region_id));
+        }
+    }
+}
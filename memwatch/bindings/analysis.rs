@@ -0,0 +1,190 @@
+// Cross-session analysis
+//
+// compare_sessions() lines up two recorded event streams by variable name
+// (region ids are not stable across runs) and reports the regions whose
+// behaviour differs: change rate, the set of writing code locations, or the
+// distribution of written values.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use crate::ChangeEvent;
+
+/// Minimum changes on each side before rates and distributions are compared
+const MIN_EVENTS: u64 = 5;
+/// |z| above which the change rates are considered different
+const RATE_Z_THRESHOLD: f64 = 3.0;
+/// Total variation distance above which value distributions differ
+const DISTRIBUTION_THRESHOLD: f64 = 0.5;
+
+/// How a region differs between session A and session B
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    /// Region only changed in A
+    OnlyInA,
+    /// Region only changed in B
+    OnlyInB,
+    /// Changes per second differ (Poisson z-score)
+    ChangeRate { rate_a: f64, rate_b: f64, z: f64 },
+    /// Code locations writing the region differ
+    Writers { only_a: Vec<String>, only_b: Vec<String> },
+    /// Written values follow different distributions (total variation distance)
+    ValueDistribution { distance: f64 },
+}
+
+/// One finding of compare_sessions()
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionDifference {
+    pub region: String,
+    pub difference: Difference,
+}
+
+#[derive(Default)]
+struct RegionProfile {
+    changes: u64,
+    writers: HashSet<String>,
+    values: HashMap<Vec<u8>, u64>,
+}
+
+struct SessionProfile {
+    seconds: f64,
+    regions: BTreeMap<String, RegionProfile>,
+}
+
+impl SessionProfile {
+    fn build(events: &[ChangeEvent]) -> Self {
+        let mut regions: BTreeMap<String, RegionProfile> = BTreeMap::new();
+        let (mut first, mut last) = (u64::MAX, 0);
+        for event in events.iter().filter(|e| !e.kind.is_marker()) {
+            first = first.min(event.timestamp_ns);
+            last = last.max(event.timestamp_ns);
+            let region = regions.entry(region_key(event)).or_default();
+            region.changes += 1;
+            if let Some(writer) = writer(event) {
+                region.writers.insert(writer);
+            }
+            let value = if event.new_value.is_empty() { &event.new_preview } else { &event.new_value };
+            *region.values.entry(value.clone()).or_insert(0) += 1;
+        }
+        // A one-second floor keeps rates finite for very short sessions
+        let seconds = (last.saturating_sub(first) as f64 / 1e9).max(1.0);
+        SessionProfile { seconds, regions }
+    }
+}
+
+/// Regions whose change rate, writers or value distribution differ
+/// significantly between two recorded sessions
+pub fn compare_sessions(a: &[ChangeEvent], b: &[ChangeEvent]) -> Vec<SessionDifference> {
+    let a = SessionProfile::build(a);
+    let b = SessionProfile::build(b);
+    let names: BTreeSet<&String> = a.regions.keys().chain(b.regions.keys()).collect();
+
+    let mut out = Vec::new();
+    for name in names {
+        let push = |out: &mut Vec<SessionDifference>, difference| {
+            out.push(SessionDifference { region: name.clone(), difference })
+        };
+        let (ra, rb) = match (a.regions.get(name), b.regions.get(name)) {
+            (Some(ra), Some(rb)) => (ra, rb),
+            (Some(_), None) => {
+                push(&mut out, Difference::OnlyInA);
+                continue;
+            }
+            (None, _) => {
+                push(&mut out, Difference::OnlyInB);
+                continue;
+            }
+        };
+
+        let mut only_a: Vec<String> = ra.writers.difference(&rb.writers).cloned().collect();
+        let mut only_b: Vec<String> = rb.writers.difference(&ra.writers).cloned().collect();
+        if !only_a.is_empty() || !only_b.is_empty() {
+            only_a.sort();
+            only_b.sort();
+            push(&mut out, Difference::Writers { only_a, only_b });
+        }
+
+        if ra.changes < MIN_EVENTS || rb.changes < MIN_EVENTS {
+            continue;
+        }
+
+        let rate_a = ra.changes as f64 / a.seconds;
+        let rate_b = rb.changes as f64 / b.seconds;
+        let variance = ra.changes as f64 / (a.seconds * a.seconds) + rb.changes as f64 / (b.seconds * b.seconds);
+        let z = (rate_a - rate_b) / variance.sqrt();
+        if z.abs() > RATE_Z_THRESHOLD {
+            push(&mut out, Difference::ChangeRate { rate_a, rate_b, z });
+        }
+
+        let distance = total_variation(&ra.values, ra.changes, &rb.values, rb.changes);
+        if distance > DISTRIBUTION_THRESHOLD {
+            push(&mut out, Difference::ValueDistribution { distance });
+        }
+    }
+    out
+}
+
+fn region_key(event: &ChangeEvent) -> String {
+    event
+        .variable_name
+        .clone()
+        .unwrap_or_else(|| format!("region {}", event.region_id))
+}
+
+/// Stable key for the code location that wrote an event
+pub(crate) fn writer(event: &ChangeEvent) -> Option<String> {
+    let loc = &event.where_;
+    match (&loc.file, &loc.function) {
+        (Some(file), function) => Some(format!("{}:{}:{}", file, function.as_deref().unwrap_or("?"), loc.line)),
+        (None, Some(function)) => Some(function.clone()),
+        (None, None) if loc.fault_ip != 0 => Some(format!("{:#x}", loc.fault_ip)),
+        (None, None) => None,
+    }
+}
+
+fn total_variation(a: &HashMap<Vec<u8>, u64>, total_a: u64, b: &HashMap<Vec<u8>, u64>, total_b: u64) -> f64 {
+    let keys: HashSet<&Vec<u8>> = a.keys().chain(b.keys()).collect();
+    keys.into_iter()
+        .map(|k| {
+            let pa = a.get(k).copied().unwrap_or(0) as f64 / total_a as f64;
+            let pb = b.get(k).copied().unwrap_or(0) as f64 / total_b as f64;
+            (pa - pb).abs()
+        })
+        .sum::<f64>()
+        / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::marker;
+    use crate::EventKind;
+
+    fn session(name: &str, count: u64, spacing_ns: u64, value: u8, function: &str) -> Vec<ChangeEvent> {
+        (0..count)
+            .map(|i| {
+                let mut event = marker(1, name, EventKind::Change);
+                event.timestamp_ns = i * spacing_ns;
+                event.new_value = vec![value];
+                event.where_.function = Some(function.to_string());
+                event
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_reports_rate_writer_and_value_changes() {
+        let mut a = session("balance", 100, 10_000_000, 1, "deposit");
+        let mut b = session("balance", 10, 100_000_000, 2, "refund");
+        a.extend(session("stable", 10, 100_000_000, 7, "tick"));
+        b.extend(session("stable", 10, 100_000_000, 7, "tick"));
+        a.extend(session("legacy", 1, 0, 0, "old"));
+
+        let diffs = compare_sessions(&a, &b);
+        let kinds: Vec<(&str, &Difference)> = diffs.iter().map(|d| (d.region.as_str(), &d.difference)).collect();
+        assert!(kinds.iter().all(|(region, _)| *region != "stable"));
+        assert!(kinds.contains(&("legacy", &Difference::OnlyInA)));
+        assert!(kinds.iter().any(|(r, d)| *r == "balance" && matches!(d, Difference::ChangeRate { .. })));
+        assert!(kinds.iter().any(|(r, d)| *r == "balance" && matches!(d, Difference::Writers { .. })));
+        assert!(kinds.iter().any(|(r, d)| *r == "balance" && matches!(d, Difference::ValueDistribution { .. })));
+    }
+}
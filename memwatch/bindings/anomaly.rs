@@ -0,0 +1,237 @@
+// Anomaly detection
+//
+// Detectors see every change returned by check_changes() before it is stamped
+// and delivered; each finding is inserted into the stream right after the
+// event that triggered it as an `EventKind::Anomaly` event carrying that
+// event's region and source location.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use crate::analysis::writer;
+use crate::{lifecycle, ChangeEvent, EventKind};
+
+/// What a detector flagged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum AnomalyKind {
+    /// Change rate far above the region's recent average
+    ChangeRate,
+    /// Region written from a code location never seen before
+    NewWriter,
+    /// Write to a lock-paired region while its lock was not held
+    UnlockedWrite,
+    /// Finding of a user-supplied detector
+    Custom(&'static str),
+}
+
+impl AnomalyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnomalyKind::ChangeRate => "change_rate",
+            AnomalyKind::NewWriter => "new_writer",
+            AnomalyKind::UnlockedWrite => "unlocked_write",
+            AnomalyKind::Custom(name) => name,
+        }
+    }
+}
+
+/// A finding, reported for the event that triggered it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Anomaly {
+    pub kind: AnomalyKind,
+    /// Detector-specific strength (z-score for ChangeRate, 1.0 otherwise)
+    pub score: f64,
+}
+
+/// Pluggable detector fed with every change event
+pub trait AnomalyDetector: Send {
+    fn observe(&mut self, event: &ChangeEvent) -> Option<Anomaly>;
+
+    /// Forget everything learned so far
+    fn reset(&mut self) {}
+}
+
+/// Run `detectors` over a batch, inserting anomaly events after their triggers
+pub(crate) fn detect(detectors: &mut [Box<dyn AnomalyDetector>], events: Vec<ChangeEvent>) -> Vec<ChangeEvent> {
+    if detectors.is_empty() {
+        return events;
+    }
+    let mut out = Vec::with_capacity(events.len());
+    for event in events {
+        let findings: Vec<Anomaly> = if event.kind.is_marker() {
+            Vec::new()
+        } else {
+            detectors.iter_mut().filter_map(|d| d.observe(&event)).collect()
+        };
+        let name = event.variable_name.clone().unwrap_or_default();
+        let (region_id, timestamp_ns, where_) = (event.region_id, event.timestamp_ns, event.where_.clone());
+        out.push(event);
+        for anomaly in findings {
+            let mut marker = lifecycle::marker(region_id, &name, EventKind::Anomaly(anomaly.kind));
+            marker.timestamp_ns = timestamp_ns;
+            marker.where_ = where_.clone();
+            out.push(marker);
+        }
+    }
+    out
+}
+
+struct RateState {
+    bucket: u64,
+    count: u64,
+    mean: f64,
+    variance: f64,
+    seen_buckets: u32,
+    flagged: bool,
+}
+
+/// EWMA/z-score detector over per-region changes per window
+pub struct EwmaRateDetector {
+    window_ns: u64,
+    alpha: f64,
+    threshold: f64,
+    warmup: u32,
+    regions: HashMap<u32, RateState>,
+}
+
+impl EwmaRateDetector {
+    /// `window` is the rate bucket; `threshold` the z-score that triggers
+    pub fn new(window: Duration, threshold: f64) -> Self {
+        EwmaRateDetector {
+            window_ns: (window.as_nanos() as u64).max(1),
+            alpha: 0.3,
+            threshold,
+            warmup: 5,
+            regions: HashMap::new(),
+        }
+    }
+
+    /// Smoothing factor (0..1) and number of windows to learn before alerting
+    pub fn with_smoothing(mut self, alpha: f64, warmup: u32) -> Self {
+        self.alpha = alpha;
+        self.warmup = warmup;
+        self
+    }
+}
+
+impl Default for EwmaRateDetector {
+    fn default() -> Self {
+        EwmaRateDetector::new(Duration::from_secs(1), 3.0)
+    }
+}
+
+impl AnomalyDetector for EwmaRateDetector {
+    fn observe(&mut self, event: &ChangeEvent) -> Option<Anomaly> {
+        let bucket = event.timestamp_ns / self.window_ns;
+        let alpha = self.alpha;
+        let state = self.regions.entry(event.region_id).or_insert(RateState {
+            bucket,
+            count: 0,
+            mean: 0.0,
+            variance: 0.0,
+            seen_buckets: 0,
+            flagged: false,
+        });
+
+        if bucket > state.bucket {
+            // Fold the finished window, plus (a bounded number of) empty ones
+            let empty = (bucket - state.bucket - 1).min(64);
+            for x in std::iter::once(state.count).chain(std::iter::repeat_n(0, empty as usize)) {
+                let diff = x as f64 - state.mean;
+                state.mean += alpha * diff;
+                state.variance = (1.0 - alpha) * (state.variance + alpha * diff * diff);
+                state.seen_buckets += 1;
+            }
+            state.bucket = bucket;
+            state.count = 0;
+            state.flagged = false;
+        }
+
+        state.count += 1;
+        if state.flagged || state.seen_buckets < self.warmup {
+            return None;
+        }
+        let z = (state.count as f64 - state.mean) / state.variance.sqrt().max(1.0);
+        if z > self.threshold {
+            state.flagged = true;
+            return Some(Anomaly { kind: AnomalyKind::ChangeRate, score: z });
+        }
+        None
+    }
+
+    fn reset(&mut self) {
+        self.regions.clear();
+    }
+}
+
+/// Flags writes from code locations not seen during a region's learning period
+pub struct NewWriterDetector {
+    learning_events: u64,
+    regions: HashMap<u32, (u64, HashSet<String>)>,
+}
+
+impl NewWriterDetector {
+    /// Learn writers from the first `learning_events` changes of each region
+    pub fn new(learning_events: u64) -> Self {
+        NewWriterDetector { learning_events, regions: HashMap::new() }
+    }
+}
+
+impl Default for NewWriterDetector {
+    fn default() -> Self {
+        NewWriterDetector::new(100)
+    }
+}
+
+impl AnomalyDetector for NewWriterDetector {
+    fn observe(&mut self, event: &ChangeEvent) -> Option<Anomaly> {
+        let writer = writer(event)?;
+        let (seen, writers) = self.regions.entry(event.region_id).or_default();
+        *seen += 1;
+        let learning = *seen <= self.learning_events;
+        if writers.insert(writer) && !learning {
+            return Some(Anomaly { kind: AnomalyKind::NewWriter, score: 1.0 });
+        }
+        None
+    }
+
+    fn reset(&mut self) {
+        self.regions.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(timestamp_ns: u64, function: &str) -> ChangeEvent {
+        let mut event = lifecycle::marker(1, "balance", EventKind::Change);
+        event.timestamp_ns = timestamp_ns;
+        event.where_.function = Some(function.to_string());
+        event
+    }
+
+    #[test]
+    fn test_ewma_flags_burst_once() {
+        let mut detector = EwmaRateDetector::new(Duration::from_secs(1), 3.0);
+        for second in 0..10u64 {
+            for i in 0..2 {
+                assert!(detector.observe(&change(second * 1_000_000_000 + i, "tick")).is_none());
+            }
+        }
+        let burst: Vec<Option<Anomaly>> = (0..20).map(|i| detector.observe(&change(10_000_000_000 + i, "tick"))).collect();
+        assert_eq!(burst.iter().flatten().count(), 1);
+        assert_eq!(burst.iter().flatten().next().unwrap().kind, AnomalyKind::ChangeRate);
+    }
+
+    #[test]
+    fn test_new_writer_after_learning_becomes_event() {
+        let mut detectors: Vec<Box<dyn AnomalyDetector>> = vec![Box::new(NewWriterDetector::new(2))];
+        let batch = vec![change(1, "deposit"), change(2, "withdraw"), change(3, "deposit"), change(4, "debug_poke")];
+        let out = detect(&mut detectors, batch);
+        assert_eq!(out.len(), 5);
+        assert_eq!(out[4].kind, EventKind::Anomaly(AnomalyKind::NewWriter));
+        assert_eq!(out[4].where_.function.as_deref(), Some("debug_poke"));
+    }
+}
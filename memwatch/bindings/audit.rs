@@ -0,0 +1,254 @@
+// Tamper-evident audit log
+//
+// Each line is "<hash>\t<record>" where hash = SHA-256(previous hash || record),
+// starting from 32 zero bytes. Deleting, reordering or editing any line breaks
+// every hash after it. Periodic checkpoint records carry the chain head and,
+// when a key is configured, an HMAC-SHA256 over it. A new log starts with a
+// header record describing the writing process, written with the first
+// record so it carries the watcher's metadata.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use crate::export::{hex, ValueMode};
+use crate::process;
+use crate::sink::EventSink;
+use crate::ChangeEvent;
+
+type Hash = [u8; 32];
+
+/// Sink writing a hash-chained audit log
+pub struct AuditSink {
+    out: BufWriter<File>,
+    head: Hash,
+    records: u64,
+    since_checkpoint: u64,
+    checkpoint_every: u64,
+    signing_key: Option<Vec<u8>>,
+    values: ValueMode,
+    /// Metadata of the header still to be written, None once it was (or
+    /// when resuming a log)
+    header: Option<Vec<(String, String)>>,
+}
+
+/// Result of `verify_audit_log`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditVerification {
+    pub records: u64,
+    pub checkpoints: u64,
+    /// Hex of the final chain hash
+    pub head: String,
+}
+
+impl AuditSink {
+    /// Open (or create) an audit log, resuming the chain of an existing file
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let (head, records) = if path.exists() {
+            let report = verify_audit_log(path, None)?;
+            (parse_hash(&report.head).ok_or("Corrupt audit head")?, report.records)
+        } else {
+            ([0u8; 32], 0)
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open audit log {}: {}", path.display(), e))?;
+        let mut sink = AuditSink {
+            out: BufWriter::new(file),
+            head,
+            records,
+            since_checkpoint: 0,
+            checkpoint_every: 0,
+            signing_key: None,
+            values: ValueMode::Raw,
+            header: None,
+        };
+        if records == 0 {
+            sink.header = Some(Vec::new());
+        }
+        Ok(sink)
+    }
+
+    /// Write a checkpoint every `every` records, signed when `signing_key` is set
+    pub fn with_checkpoints(mut self, every: u64, signing_key: Option<Vec<u8>>) -> Self {
+        self.checkpoint_every = every;
+        self.signing_key = signing_key;
+        self
+    }
+
+    /// Value representation in logged events (e.g. hashed for sensitive data)
+    pub fn with_value_mode(mut self, values: ValueMode) -> Self {
+        self.values = values;
+        self
+    }
+
+    /// Current chain head as hex
+    pub fn head(&self) -> String {
+        hex(&self.head)
+    }
+
+    /// Append an explicit checkpoint record
+    pub fn checkpoint(&mut self) -> Result<(), String> {
+        self.write_header()?;
+        let head = hex(&self.head);
+        let sig = match &self.signing_key {
+            Some(key) => hex(&hmac_sha256(key, head.as_bytes())),
+            None => "-".to_string(),
+        };
+        self.since_checkpoint = 0;
+        self.append(&format!("checkpoint {} {} {}", self.records, head, sig))
+    }
+
+    fn write_header(&mut self) -> Result<(), String> {
+        match self.header.take() {
+            Some(metadata) => self.append(&format!("header {}", process::current().to_json_with(&metadata))),
+            None => Ok(()),
+        }
+    }
+
+    fn append(&mut self, record: &str) -> Result<(), String> {
+        self.head = chain(&self.head, record);
+        self.records += 1;
+        writeln!(self.out, "{}\t{}", hex(&self.head), record).map_err(|e| format!("Audit write failed: {}", e))
+    }
+}
+
+impl EventSink for AuditSink {
+    fn write(&mut self, event: &ChangeEvent) -> Result<(), String> {
+        self.write_header()?;
+        let record = format!("event {}", self.values.export(event).to_json());
+        self.append(&record)?;
+        self.since_checkpoint += 1;
+        if self.checkpoint_every > 0 && self.since_checkpoint >= self.checkpoint_every {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    fn metadata(&mut self, metadata: &[(String, String)]) {
+        if let Some(header) = self.header.as_mut() {
+            *header = metadata.to_vec();
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.write_header()?;
+        self.out.flush().map_err(|e| format!("Audit flush failed: {}", e))
+    }
+}
+
+/// Verify the chain of an audit log (and checkpoint signatures when `key` is given)
+pub fn verify_audit_log(path: impl AsRef<Path>, key: Option<&[u8]>) -> Result<AuditVerification, String> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| format!("Failed to open audit log {}: {}", path.display(), e))?;
+
+    let mut head = [0u8; 32];
+    let mut records = 0;
+    let mut checkpoints = 0;
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("Failed to read audit log: {}", e))?;
+        let (hash, record) = line
+            .split_once('\t')
+            .ok_or_else(|| format!("Malformed audit line {}", i + 1))?;
+
+        let expected = chain(&head, record);
+        if hex(&expected) != hash {
+            return Err(format!("Audit chain broken at line {}", i + 1));
+        }
+
+        if let Some(rest) = record.strip_prefix("checkpoint ") {
+            let fields: Vec<&str> = rest.split(' ').collect();
+            if fields.len() != 3 || fields[1] != hex(&head) {
+                return Err(format!("Checkpoint at line {} does not match the chain", i + 1));
+            }
+            if let Some(key) = key {
+                if fields[2] != hex(&hmac_sha256(key, fields[1].as_bytes())) {
+                    return Err(format!("Bad checkpoint signature at line {}", i + 1));
+                }
+            }
+            checkpoints += 1;
+        }
+
+        head = expected;
+        records += 1;
+    }
+
+    Ok(AuditVerification {
+        records,
+        checkpoints,
+        head: hex(&head),
+    })
+}
+
+fn chain(prev: &Hash, record: &str) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(prev);
+    hasher.update(record.as_bytes());
+    hasher.finalize().into()
+}
+
+fn parse_hash(value: &str) -> Option<Hash> {
+    if value.len() != 64 {
+        return None;
+    }
+    let mut out = [0u8; 32];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&value[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(out)
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Hash {
+    const BLOCK: usize = 64;
+    let mut block_key = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        block_key[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(block_key.map(|b| b ^ 0x36));
+    inner.update(message);
+    let mut outer = Sha256::new();
+    outer.update(block_key.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::marker;
+    use crate::EventKind;
+
+    #[test]
+    fn test_chain_verifies_and_detects_tampering() {
+        let path = std::env::temp_dir().join(format!("memwatch_audit_{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut sink = AuditSink::open(&path).unwrap().with_checkpoints(2, Some(b"key".to_vec()));
+        for id in 1..=3 {
+            sink.write(&marker(id, "balance", EventKind::Unwatched)).unwrap();
+        }
+        sink.flush().unwrap();
+        let head = sink.head();
+        drop(sink);
+
+        let report = verify_audit_log(&path, Some(b"key")).unwrap();
+        assert_eq!((report.records, report.checkpoints), (5, 1));
+        assert_eq!(report.head, head);
+        assert!(verify_audit_log(&path, Some(b"other")).is_err());
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, content.replacen("balance", "b4lance", 1)).unwrap();
+        assert!(verify_audit_log(&path, None).is_err());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,567 @@
+// Watch backends
+//
+// A backend watches raw regions and hands back change events; the rest of
+// the pipeline (markers, shadow pages, hooks, sinks, ...) is shared. Two
+// backends exist:
+//
+//   NativeBackend  page protection in the C core (WatchMode::Protect)
+//   PureBackend    snapshot comparison in Rust (WatchMode::Snapshot): no
+//                  faults, no source locations, one event per changed
+//                  region per poll
+//
+// With the `pure` feature the native backend and its FFI are compiled out,
+// so the crate builds and links without libmemwatch (musl, minimal
+// containers) and always runs in Snapshot mode. Targets the C core does not
+// support (anything but Linux, e.g. wasm32) also run in Snapshot mode; a
+// StubBackend takes the native backend's place there and answers with
+// Unsupported errors.
+
+use std::sync::{Arc, Mutex};
+
+use crate::error::{CheckError, NativeError};
+use crate::polling::PollingRegions;
+use crate::{CallbackSlot, ChangeEvent, Stats};
+
+/// Core settings applied before init; see MemWatchBuilder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BackendConfig {
+    /// Event ring slots; None keeps the core's default
+    pub ring_capacity: Option<u32>,
+    /// Resolve fault addresses to file/function/line
+    pub symbolication: bool,
+    /// Return addresses captured per event (0 = none)
+    pub backtrace_depth: u32,
+}
+
+impl Default for BackendConfig {
+    fn default() -> Self {
+        BackendConfig {
+            ring_capacity: None,
+            symbolication: true,
+            backtrace_depth: 0,
+        }
+    }
+}
+
+pub(crate) trait Backend: Send + Sync {
+    /// Settings for the next init. Backends without a ring or symbolizer
+    /// ignore them.
+    fn configure(&self, _config: &BackendConfig) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Start (or restart, after shutdown) the backend
+    fn init(&self) -> Result<(), String>;
+
+    fn shutdown(&self);
+
+    /// Start watching `buffer`; returns a region id above zero
+    fn watch(&self, buffer: &[u8], name: &str, max_value_bytes: i32) -> Result<u32, String>;
+
+    fn unwatch(&self, region_id: u32) -> Result<(), NativeError>;
+
+    /// Drain up to `max_events` changes
+    fn poll(&self, max_events: usize) -> Result<Vec<ChangeEvent>, CheckError>;
+
+    fn stats(&self) -> Result<Stats, String>;
+
+    /// Treat the live contents of a region as reported (after a write the
+    /// backend must not report, e.g. poke())
+    fn resync(&self, _region_id: u32) {}
+
+    /// Stop watching a region without forgetting it: no changes, same id
+    fn pause(&self, _region_id: u32) -> Result<(), String> {
+        Err("This backend cannot pause regions".to_string())
+    }
+
+    /// Watch a paused region again, its live contents as the baseline
+    fn resume(&self, _region_id: u32) -> Result<(), String> {
+        Err("This backend cannot pause regions".to_string())
+    }
+
+    /// Deliver changes to `slot` as they are recorded, or stop with None.
+    /// Backends without a worker only report through poll().
+    fn set_callback(&self, _slot: Option<&Arc<CallbackSlot>>) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// A value the backend stored itself under a storage key of its events
+    fn fetch_value(&self, key: &str) -> Result<Vec<u8>, String> {
+        Err(format!("No value stored under {:?}", key))
+    }
+}
+
+/// Snapshot comparison in Rust
+#[derive(Default)]
+pub(crate) struct PureBackend {
+    regions: Mutex<PollingRegions>,
+}
+
+impl Backend for PureBackend {
+    fn init(&self) -> Result<(), String> {
+        *self.regions.lock().unwrap() = PollingRegions::default();
+        Ok(())
+    }
+
+    fn shutdown(&self) {}
+
+    fn watch(&self, buffer: &[u8], name: &str, max_value_bytes: i32) -> Result<u32, String> {
+        Ok(self.regions.lock().unwrap().watch(buffer, name, max_value_bytes))
+    }
+
+    fn unwatch(&self, region_id: u32) -> Result<(), NativeError> {
+        self.regions.lock().unwrap().unwatch(region_id);
+        Ok(())
+    }
+
+    fn poll(&self, max_events: usize) -> Result<Vec<ChangeEvent>, CheckError> {
+        Ok(self.regions.lock().unwrap().poll(max_events))
+    }
+
+    fn stats(&self) -> Result<Stats, String> {
+        let regions = self.regions.lock().unwrap();
+        Ok(Stats {
+            num_tracked_regions: regions.len() as u32,
+            num_active_watchpoints: regions.len() as u32,
+            total_events: regions.total_events(),
+            ring_write_count: 0,
+            ring_drop_count: 0,
+            storage_bytes_used: 0,
+            mprotect_page_count: 0,
+            worker_thread_id: 0,
+            worker_cycles: 0,
+            sampled_out_count: 0,
+            consumers: Vec::new(),
+            events_per_thread: Vec::new(),
+        })
+    }
+
+    fn resync(&self, region_id: u32) {
+        self.regions.lock().unwrap().resync(region_id);
+    }
+
+    fn pause(&self, region_id: u32) -> Result<(), String> {
+        if self.regions.lock().unwrap().pause(region_id) {
+            Ok(())
+        } else {
+            Err(format!("Region {} is not watched", region_id))
+        }
+    }
+
+    fn resume(&self, region_id: u32) -> Result<(), String> {
+        if self.regions.lock().unwrap().resume(region_id) {
+            Ok(())
+        } else {
+            Err(format!("Region {} is not watched", region_id))
+        }
+    }
+}
+
+#[cfg(native_backend)]
+pub(crate) use native::NativeBackend;
+#[cfg(all(not(native_backend), not(feature = "pure")))]
+pub(crate) use StubBackend as NativeBackend;
+
+/// The native backend on targets without the C core
+#[cfg(all(not(native_backend), not(feature = "pure")))]
+pub(crate) struct StubBackend;
+
+#[cfg(all(not(native_backend), not(feature = "pure")))]
+impl Backend for StubBackend {
+    fn init(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn shutdown(&self) {}
+
+    fn watch(&self, _buffer: &[u8], _name: &str, _max_value_bytes: i32) -> Result<u32, String> {
+        Err(CheckError::Unsupported("page-protection watching").to_string())
+    }
+
+    fn unwatch(&self, _region_id: u32) -> Result<(), NativeError> {
+        Err(NativeError { call: "memwatch_unwatch", code: 0 })
+    }
+
+    fn poll(&self, _max_events: usize) -> Result<Vec<ChangeEvent>, CheckError> {
+        Err(CheckError::Unsupported("page-protection watching"))
+    }
+
+    fn stats(&self) -> Result<Stats, String> {
+        Err(CheckError::Unsupported("native statistics").to_string())
+    }
+}
+
+#[cfg(native_backend)]
+mod native {
+    use std::ffi::{CStr, CString};
+    use std::os::raw::{c_char, c_int, c_void};
+    use std::ptr;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    use super::Backend;
+    use crate::error::{CheckError, NativeError};
+    use crate::lifecycle::EventSource;
+    use crate::panics;
+    use super::BackendConfig;
+    use crate::{
+        memwatch_check_changes, memwatch_config_set_backtrace_depth, memwatch_config_set_ring_capacity,
+        memwatch_config_set_symbolication, memwatch_fetch_value, memwatch_free_event, memwatch_get_stats, memwatch_init, memwatch_pause_region,
+        memwatch_resume_region, memwatch_set_callback, memwatch_shutdown, memwatch_unwatch, memwatch_watch_with_max_value_bytes, CallbackSlot, ChangeEvent, ChangeEventC, EventKind,
+        Location, Stats, StatsC,
+    };
+
+    /// Largest preview or value the native ring can produce; anything above
+    /// is garbage
+    const MAX_NATIVE_PREVIEW: usize = 1 << 20;
+    /// Config call the core cannot honour (MEMWATCH_ERR_UNSUPPORTED)
+    const MEMWATCH_ERR_UNSUPPORTED: c_int = -6;
+
+    /// Page protection in the C core
+    pub(crate) struct NativeBackend;
+
+    impl Backend for NativeBackend {
+        fn configure(&self, config: &BackendConfig) -> Result<(), String> {
+            if let Some(capacity) = config.ring_capacity {
+                let result = unsafe { memwatch_config_set_ring_capacity(capacity) };
+                if result != 0 {
+                    return Err(format!("Failed to set ring capacity {}: {}", capacity, result));
+                }
+            }
+            let result = unsafe { memwatch_config_set_symbolication(config.symbolication) };
+            // Symbolication is best effort: a core without a symbolizer
+            // reports events without locations
+            if result != 0 && !(result == MEMWATCH_ERR_UNSUPPORTED && config.symbolication) {
+                return Err(format!("Failed to configure symbolication: {}", result));
+            }
+            let result = unsafe { memwatch_config_set_backtrace_depth(config.backtrace_depth) };
+            if result == MEMWATCH_ERR_UNSUPPORTED {
+                return Err(format!("Backtrace depth {} needs a core that captures backtraces", config.backtrace_depth));
+            }
+            if result != 0 {
+                return Err(format!("Failed to set backtrace depth {}: {}", config.backtrace_depth, result));
+            }
+            Ok(())
+        }
+
+        fn init(&self) -> Result<(), String> {
+            let result = unsafe { memwatch_init() };
+            if result != 0 {
+                return Err(format!("Failed to initialize memwatch: {}", result));
+            }
+            Ok(())
+        }
+
+        fn shutdown(&self) {
+            unsafe { memwatch_shutdown() }
+        }
+
+        fn watch(&self, buffer: &[u8], name: &str, max_value_bytes: i32) -> Result<u32, String> {
+            let c_name = CString::new(name).map_err(|e| e.to_string())?;
+            let region_id = unsafe {
+                memwatch_watch_with_max_value_bytes(
+                    buffer.as_ptr() as u64,
+                    buffer.len(),
+                    c_name.as_ptr(),
+                    ptr::null_mut(),
+                    max_value_bytes,
+                )
+            };
+            if region_id > 0 {
+                Ok(region_id)
+            } else {
+                Err("Failed to watch buffer".to_string())
+            }
+        }
+
+        fn unwatch(&self, region_id: u32) -> Result<(), NativeError> {
+            if unsafe { memwatch_unwatch(region_id) } {
+                Ok(())
+            } else {
+                Err(NativeError { call: "memwatch_unwatch", code: 0 })
+            }
+        }
+
+        fn pause(&self, region_id: u32) -> Result<(), String> {
+            let result = unsafe { memwatch_pause_region(region_id) };
+            if result != 0 {
+                return Err(format!("Failed to pause region {}: {}", region_id, result));
+            }
+            Ok(())
+        }
+
+        fn resume(&self, region_id: u32) -> Result<(), String> {
+            let result = unsafe { memwatch_resume_region(region_id) };
+            if result != 0 {
+                return Err(format!("Failed to resume region {}: {}", region_id, result));
+            }
+            Ok(())
+        }
+
+        fn poll(&self, max_events: usize) -> Result<Vec<ChangeEvent>, CheckError> {
+            poll_native(max_events)
+        }
+
+        fn fetch_value(&self, key: &str) -> Result<Vec<u8>, String> {
+            let c_key = CString::new(key).map_err(|e| e.to_string())?;
+            let missing = || format!("No value stored under {:?}", key);
+            let mut size = 0usize;
+            if unsafe { memwatch_fetch_value(c_key.as_ptr(), ptr::null_mut(), &mut size) } != 0 {
+                return Err(missing());
+            }
+            let mut value = vec![0u8; size];
+            // A key names one value; it may be gone by now, but never replaced
+            if unsafe { memwatch_fetch_value(c_key.as_ptr(), value.as_mut_ptr(), &mut size) } != 0 || size != value.len() {
+                return Err(missing());
+            }
+            Ok(value)
+        }
+
+        fn stats(&self) -> Result<Stats, String> {
+            unsafe {
+                let mut c_stats = std::mem::zeroed::<StatsC>();
+                let result = memwatch_get_stats(&mut c_stats);
+
+                if result != 0 {
+                    return Err(format!("Failed to get stats: {}", result));
+                }
+
+                Ok(Stats {
+                    num_tracked_regions: c_stats.num_tracked_regions,
+                    num_active_watchpoints: c_stats.num_active_watchpoints,
+                    total_events: c_stats.total_events,
+                    ring_write_count: c_stats.ring_write_count,
+                    ring_drop_count: c_stats.ring_drop_count,
+                    storage_bytes_used: c_stats.storage_bytes_used,
+                    mprotect_page_count: c_stats.mprotect_page_count,
+                    worker_thread_id: c_stats.worker_thread_id,
+                    worker_cycles: c_stats.worker_cycles,
+                    sampled_out_count: 0,
+                    consumers: Vec::new(),
+                    events_per_thread: Vec::new(),
+                })
+            }
+        }
+
+        fn set_callback(&self, slot: Option<&Arc<CallbackSlot>>) -> Result<(), String> {
+            // The slot is owned by the MemWatch, which unregisters before
+            // dropping it
+            let result = match slot {
+                Some(slot) => unsafe { memwatch_set_callback(Some(trampoline), Arc::as_ptr(slot) as *mut c_void) },
+                None => unsafe { memwatch_set_callback(None, ptr::null_mut()) },
+            };
+            if result != 0 {
+                return Err(format!("Failed to set callback: {}", result));
+            }
+            Ok(())
+        }
+    }
+
+    /// memwatch_callback_t handed to the core; `user_ctx` is a CallbackSlot
+    unsafe extern "C" fn trampoline(event: *const ChangeEventC, user_ctx: *mut c_void) {
+        if event.is_null() || user_ctx.is_null() || malformed(&*event).is_some() {
+            return;
+        }
+        let slot = &*(user_ctx as *const CallbackSlot);
+        let event = event_from_c(&*event);
+        // Count-only chunks are counted, not delivered
+        if let Some(writes) = slot.counted.lock().unwrap_or_else(|e| e.into_inner()).get_mut(&event.region_id) {
+            *writes += event.write_count as u64;
+            return;
+        }
+        if let Some(filter) = slot.filter.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            if !filter.accepts(&event) {
+                return;
+            }
+        }
+        let mut callback = slot.callback.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(run) = callback.as_ref() {
+            // Unwinding into C is undefined behavior: the panic is caught
+            if let Err(message) = panics::guarded(|| run(&event)) {
+                // Reported as a CallbackPanicked marker by the next check
+                let failures = slot.failures.fetch_add(1, Ordering::SeqCst) + 1;
+                let disabled = panics::exhausted(failures, slot.failure_limit.load(Ordering::SeqCst));
+                if disabled {
+                    *callback = None;
+                }
+                slot.panics.lock().unwrap_or_else(|e| e.into_inner()).push((failures, disabled, message));
+            }
+        }
+        drop(callback);
+        slot.wakers.wake_all();
+    }
+
+    /// Drain up to `max_events` events from the native ring
+    pub(crate) fn poll_native(max_events: usize) -> Result<Vec<ChangeEvent>, CheckError> {
+        let mut c_events = vec![
+            ChangeEventC {
+                seq: 0,
+                timestamp_ns: 0,
+                adapter_id: 0,
+                region_id: 0,
+                variable_name: ptr::null(),
+                file: ptr::null(),
+                function: ptr::null(),
+                line: 0,
+                fault_ip: 0,
+                old_preview: ptr::null(),
+                old_preview_size: 0,
+                new_preview: ptr::null(),
+                new_preview_size: 0,
+                old_value: ptr::null(),
+                old_value_size: 0,
+                new_value: ptr::null(),
+                new_value_size: 0,
+                storage_key_old: ptr::null(),
+                storage_key_new: ptr::null(),
+                user_data: ptr::null(),
+                thread_id: 0,
+                thread_name: ptr::null(),
+                backtrace: ptr::null(),
+                backtrace_len: 0,
+            };
+            max_events
+        ];
+
+        unsafe {
+            let count = memwatch_check_changes(c_events.as_mut_ptr(), max_events as c_int);
+            if count < 0 {
+                return Err(CheckError::Native(NativeError { call: "memwatch_check_changes", code: count }));
+            }
+            let count = count as usize;
+            if count > max_events {
+                return Err(CheckError::Malformed { index: max_events, reason: "more events than requested" });
+            }
+            let returned = &mut c_events[..count];
+
+            if let Some((index, reason)) = returned.iter().enumerate().find_map(|(i, e)| malformed(e).map(|r| (i, r))) {
+                for c_evt in returned.iter_mut() {
+                    memwatch_free_event(c_evt);
+                }
+                return Err(CheckError::Malformed { index, reason });
+            }
+
+            let mut result = Vec::with_capacity(count);
+            for c_evt in returned.iter_mut() {
+                result.push(event_from_c(c_evt));
+
+                // Strings and previews stay owned by the native event
+                memwatch_free_event(c_evt);
+            }
+
+            Ok(result)
+        }
+    }
+
+    /// Copy a native event that passed malformed()
+    unsafe fn event_from_c(c_evt: &ChangeEventC) -> ChangeEvent {
+        ChangeEvent {
+            seq: c_evt.seq,
+            timestamp_ns: c_evt.timestamp_ns,
+            adapter_id: c_evt.adapter_id,
+            region_id: c_evt.region_id,
+            variable_name: c_string(c_evt.variable_name),
+            group: None,
+            where_: Location {
+                file: c_string(c_evt.file),
+                function: c_string(c_evt.function),
+                line: c_evt.line,
+                fault_ip: c_evt.fault_ip,
+            },
+            old_preview: c_bytes(c_evt.old_preview, c_evt.old_preview_size),
+            new_preview: c_bytes(c_evt.new_preview, c_evt.new_preview_size),
+            old_value: c_bytes(c_evt.old_value, c_evt.old_value_size),
+            new_value: c_bytes(c_evt.new_value, c_evt.new_value_size),
+            storage_key_old: c_string(c_evt.storage_key_old),
+            storage_key_new: c_string(c_evt.storage_key_new),
+            kind: EventKind::Change,
+            epoch: 0,
+            global_seq: 0,
+            tid: (c_evt.thread_id != 0).then_some(c_evt.thread_id),
+            thread_name: c_string(c_evt.thread_name),
+            frames: c_frames(c_evt.backtrace, c_evt.backtrace_len),
+            allocated_at: None,
+            source: EventSource::Observed,
+            preview_limit: None,
+            deltas: Vec::new(),
+            span_id: None,
+            span_name: None,
+            write_count: 1,
+        }
+    }
+
+    fn malformed(event: &ChangeEventC) -> Option<&'static str> {
+        if event.old_preview.is_null() && event.old_preview_size > 0 {
+            return Some("null old_preview with nonzero size");
+        }
+        if event.new_preview.is_null() && event.new_preview_size > 0 {
+            return Some("null new_preview with nonzero size");
+        }
+        if event.old_preview_size > MAX_NATIVE_PREVIEW || event.new_preview_size > MAX_NATIVE_PREVIEW {
+            return Some("implausible preview size");
+        }
+        if event.old_value.is_null() && event.old_value_size > 0 {
+            return Some("null old_value with nonzero size");
+        }
+        if event.new_value.is_null() && event.new_value_size > 0 {
+            return Some("null new_value with nonzero size");
+        }
+        if event.old_value_size > MAX_NATIVE_PREVIEW || event.new_value_size > MAX_NATIVE_PREVIEW {
+            return Some("implausible value size");
+        }
+        if event.backtrace.is_null() && event.backtrace_len > 0 {
+            return Some("null backtrace with nonzero length");
+        }
+        if event.backtrace_len > crate::backtrace::MAX_BACKTRACE_DEPTH as usize {
+            return Some("implausible backtrace length");
+        }
+        None
+    }
+
+    unsafe fn c_string(ptr: *const c_char) -> Option<String> {
+        if ptr.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(ptr).to_string_lossy().into_owned())
+        }
+    }
+
+    unsafe fn c_bytes(ptr: *const u8, len: usize) -> Vec<u8> {
+        if ptr.is_null() {
+            Vec::new()
+        } else {
+            std::slice::from_raw_parts(ptr, len).to_vec()
+        }
+    }
+
+    unsafe fn c_frames(ptr: *const u64, len: usize) -> Vec<u64> {
+        if ptr.is_null() {
+            Vec::new()
+        } else {
+            std::slice::from_raw_parts(ptr, len).to_vec()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pure_backend_detects_writes() {
+        let backend = PureBackend::default();
+        let mut buffer = vec![0u8; 16];
+        let id = backend.watch(&buffer, "buffer", -1).unwrap();
+        assert!(backend.poll(8).unwrap().is_empty());
+
+        buffer[3] = 9;
+        let events = backend.poll(8).unwrap();
+        assert_eq!((events.len(), events[0].region_id, events[0].new_preview[3]), (1, id, 9));
+        assert_eq!(backend.stats().unwrap().num_tracked_regions, 1);
+
+        backend.unwatch(id).unwrap();
+        buffer[4] = 1;
+        assert!(backend.poll(8).unwrap().is_empty());
+        assert_eq!(backend.stats().unwrap().num_tracked_regions, 0);
+    }
+}
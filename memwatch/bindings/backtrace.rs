@@ -0,0 +1,130 @@
+// Backtraces per change
+//
+// With MemWatchBuilder::backtrace_depth(n) the native core walks the stack
+// of the writing thread in its fault handler and hands up to n return
+// addresses with each change (ChangeEvent::frames). They stay raw addresses
+// until ChangeEvent::backtrace() is asked for them, so capture costs one
+// stack walk per fault and symbols are only looked up for events someone
+// reads:
+//
+//   let watcher = MemWatch::builder().backtrace_depth(16).build()?;
+//   ...
+//   for frame in event.backtrace() {
+//       println!("{}", frame);
+//   }
+//
+// Resolution needs the `backtrace` feature and runs through the backtrace
+// crate's symbolizer (DWARF when the binary has it, the symbol table
+// otherwise); results are cached per address for the life of the process.
+// Without the feature, frames carry their address only. Snapshot polling
+// and custom backends see writes after the fact and record no stack.
+
+#[cfg(feature = "backtrace")]
+use std::collections::HashMap;
+use std::fmt;
+#[cfg(feature = "backtrace")]
+use std::sync::Mutex;
+
+use crate::ChangeEvent;
+
+/// Deepest stack the core records per change
+pub const MAX_BACKTRACE_DEPTH: u32 = 64;
+
+/// One resolved return address of a change's backtrace
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Frame {
+    pub ip: u64,
+    /// Demangled function name
+    pub function: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:016x} {}", self.ip, self.function.as_deref().unwrap_or("??"))?;
+        if let Some(file) = &self.file {
+            write!(f, " at {}", file)?;
+            if let Some(line) = self.line {
+                write!(f, ":{}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ChangeEvent {
+    /// The writer's stack at fault time, innermost frame first, resolved
+    /// on first use; empty when no backtrace was recorded
+    pub fn backtrace(&self) -> Vec<Frame> {
+        self.frames.iter().map(|&ip| resolve(ip)).collect()
+    }
+}
+
+#[cfg(feature = "backtrace")]
+static SYMBOLS: Mutex<Option<HashMap<u64, Frame>>> = Mutex::new(None);
+
+#[cfg(feature = "backtrace")]
+fn resolve(ip: u64) -> Frame {
+    let mut symbols = SYMBOLS.lock().unwrap_or_else(|e| e.into_inner());
+    let symbols = symbols.get_or_insert_with(HashMap::new);
+    if let Some(frame) = symbols.get(&ip) {
+        return frame.clone();
+    }
+    let mut frame = Frame { ip, function: None, file: None, line: None };
+    // Inlined calls produce several symbols; the first is the innermost
+    ::backtrace::resolve(ip as usize as *mut std::ffi::c_void, |symbol| {
+        if frame.function.is_none() {
+            frame.function = symbol.name().map(|name| name.to_string());
+            frame.file = symbol.filename().map(|path| path.display().to_string());
+            frame.line = symbol.lineno();
+        }
+    });
+    symbols.insert(ip, frame.clone());
+    frame
+}
+
+#[cfg(not(feature = "backtrace"))]
+fn resolve(ip: u64) -> Frame {
+    Frame { ip, function: None, file: None, line: None }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use crate::capabilities::WatchMode;
+    use crate::fake_native::{self, FakeEvent};
+    use crate::MemWatch;
+
+    #[inline(never)]
+    fn writer() -> u64 {
+        std::hint::black_box(7)
+    }
+
+    #[test]
+    fn test_backtraces_resolve_on_demand() {
+        let _guard = fake_native::lock();
+        assert!(MemWatch::builder().backtrace_depth(65).build().is_err());
+        let mut watcher = MemWatch::builder().backtrace_depth(8).build().unwrap();
+        assert_eq!(fake_native::state().backtrace_depth, 8);
+        watcher.capabilities.mode = WatchMode::Protect;
+        let buffer = [0u8; 4];
+        let region_id = watcher.watch(&buffer, "buffer").unwrap();
+        // A return address one instruction into writer()
+        let ip = writer as fn() -> u64 as usize as u64 + 1;
+        fake_native::state().events.push(FakeEvent { backtrace: vec![ip, 0], ..FakeEvent::change(region_id, &[1]) });
+        fake_native::state().events.push(FakeEvent::change(region_id, &[2]));
+
+        let events = watcher.check_changes().unwrap();
+        assert_eq!(events[1].frames, [ip, 0]);
+        assert!(events[2].backtrace().is_empty());
+        let frames = events[1].backtrace();
+        assert_eq!(frames.iter().map(|f| f.ip).collect::<Vec<_>>(), [ip, 0]);
+        assert_eq!(frames[1].function, None);
+        if cfg!(feature = "backtrace") {
+            assert!(frames[0].function.as_deref().unwrap().contains("writer"), "{}", frames[0]);
+        } else {
+            assert_eq!(frames[0].to_string(), format!("0x{:016x} ??", ip));
+        }
+    }
+}
@@ -0,0 +1,63 @@
+// Offline checks over recorded sessions
+//
+// Usage:
+//   memwatch-cli check --policy <policy.toml> <session-dir> [--json]
+//   memwatch-cli verify <session-dir>
+//
+// A session directory holds events.jsonl and optionally sql.jsonl (see
+// memwatch::ci and memwatch::session). Exit status: 0 when the session is
+// clean, 1 when a rule is violated, 2 when the policy or session cannot be
+// read or a bundle does not match its manifest. With --json each violation
+// is printed as {"rule":..,"file":..,"line":N,"detail":..}.
+
+use std::process::ExitCode;
+
+use memwatch::ci::Policy;
+use memwatch::session::SessionBundle;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(0) => ExitCode::SUCCESS,
+        Ok(count) => {
+            eprintln!("memwatch-cli: {} violation(s)", count);
+            ExitCode::from(1)
+        }
+        Err(e) => {
+            eprintln!("memwatch-cli: {}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Number of violations found
+fn run(args: &[String]) -> Result<usize, String> {
+    let json = args.iter().any(|arg| arg == "--json");
+    let args: Vec<&str> = args.iter().map(String::as_str).filter(|arg| *arg != "--json").collect();
+    let (policy, session) = match args.as_slice() {
+        ["check", "--policy", policy, session] | ["check", session, "--policy", policy] => (*policy, *session),
+        ["verify", session] => return verify(session),
+        _ => return Err(usage()),
+    };
+    let violations = Policy::load(policy)?.check_session(session)?;
+    for violation in &violations {
+        if json {
+            println!("{}", violation.to_json());
+        } else {
+            println!("{}:{}: [{}] {}", violation.file, violation.line, violation.rule, violation.detail);
+        }
+    }
+    Ok(violations.len())
+}
+
+fn verify(session: &str) -> Result<usize, String> {
+    let bundle = SessionBundle::open(session)?;
+    for file in &bundle.files {
+        println!("{}  {}  {} bytes  {}", file.sha256, file.path, file.bytes, file.schema);
+    }
+    Ok(0)
+}
+
+fn usage() -> String {
+    "usage: memwatch-cli check --policy <policy.toml> <session-dir> [--json] | verify <session-dir>".to_string()
+}
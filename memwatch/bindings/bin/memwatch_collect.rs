@@ -0,0 +1,76 @@
+// Collector for event streams from many instrumented processes
+//
+// Usage:
+//   memwatch-collect serve <store> [--listen <addr>]     (default 0.0.0.0:7077)
+//   memwatch-collect query <store> [--process <name>] [--json]
+//
+// Processes send events with `memwatch::collect::StreamSink::connect`, or
+// from inside a VM with `connect_vsock`; a collector on the host listens for
+// those with --listen vsock:any:<port> (Linux). With --json, query prints
+// one object per event:
+//   {"host":"..","pid":N,"name":"..","event":{..exported event..}}
+
+use std::net::TcpListener;
+use std::process::ExitCode;
+
+use memwatch::collect::{read_store, Collector};
+#[cfg(target_os = "linux")]
+use memwatch::vsock::VsockListener;
+
+const DEFAULT_LISTEN: &str = "0.0.0.0:7077";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("memwatch-collect: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let json = args.iter().any(|arg| arg == "--json");
+    let args: Vec<String> = args.iter().filter(|arg| *arg != "--json").cloned().collect();
+    let (command, store, options) = match args.as_slice() {
+        [command, store, options @ ..] => (command.as_str(), store, options),
+        _ => return Err(usage()),
+    };
+    match (command, options) {
+        ("serve", []) => serve(store, DEFAULT_LISTEN),
+        ("serve", [flag, addr]) if flag == "--listen" => serve(store, addr),
+        ("query", []) => query(store, None, json),
+        ("query", [flag, name]) if flag == "--process" => query(store, Some(name), json),
+        _ => Err(usage()),
+    }
+}
+
+fn serve(store: &str, addr: &str) -> Result<(), String> {
+    let collector = Collector::open(store)?.with_error_handler(|peer, e| eprintln!("memwatch-collect: {}: {}", peer, e));
+    #[cfg(target_os = "linux")]
+    if addr.starts_with("vsock:") {
+        let vsock_addr = addr.parse()?;
+        let listener = VsockListener::bind(vsock_addr).map_err(|e| format!("Failed to listen on {}: {}", addr, e))?;
+        eprintln!("memwatch-collect: listening on {}, writing {}", addr, store);
+        return collector.serve_vsock(listener);
+    }
+    let listener = TcpListener::bind(addr).map_err(|e| format!("Failed to listen on {}: {}", addr, e))?;
+    eprintln!("memwatch-collect: listening on {}, writing {}", addr, store);
+    collector.serve(listener)
+}
+
+fn query(store: &str, process: Option<&str>, json: bool) -> Result<(), String> {
+    for event in read_store(store, process)? {
+        if json {
+            println!("{}", event.to_json());
+        } else {
+            println!("{}:{} {} {}", event.process.host, event.process.pid, event.process.name, event.event_json);
+        }
+    }
+    Ok(())
+}
+
+fn usage() -> String {
+    "usage: memwatch-collect serve <store> [--listen <addr>] | query <store> [--process <name>] [--json]".to_string()
+}
@@ -0,0 +1,115 @@
+// Adaptive preview sizing
+//
+// With a budget set, previews of all regions together may use about
+// `bytes_per_sec` bytes per second. Within each one-second window the
+// per-event preview limit is halved (down to `min_preview`) whenever the
+// next event would overrun the budget; at each window boundary it doubles
+// again (up to `max_preview`) if the last window used less than half the
+// budget. Every event records the limit applied to it. Full values, when
+// captured, are left alone.
+
+use std::time::{Duration, Instant};
+
+use crate::ChangeEvent;
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Preview bytes allowed per second across all regions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreviewBudget {
+    pub bytes_per_sec: usize,
+    /// Previews never shrink below this many bytes (each of old and new)
+    pub min_preview: usize,
+    /// Limit when the budget is not under pressure
+    pub max_preview: usize,
+}
+
+#[derive(Default)]
+pub(crate) struct PreviewThrottle {
+    budget: Option<PreviewBudget>,
+    limit: usize,
+    window_start: Option<Instant>,
+    spent: usize,
+}
+
+impl PreviewThrottle {
+    pub(crate) fn set_budget(&mut self, budget: Option<PreviewBudget>) {
+        *self = PreviewThrottle {
+            limit: budget.map_or(0, |b| b.max_preview),
+            budget,
+            ..PreviewThrottle::default()
+        };
+    }
+
+    /// Truncate the previews of a batch to the current limit
+    pub(crate) fn apply(&mut self, events: &mut [ChangeEvent], now: Instant) {
+        let Some(budget) = self.budget else {
+            return;
+        };
+        let start = *self.window_start.get_or_insert(now);
+        if now.duration_since(start) >= WINDOW {
+            if self.spent < budget.bytes_per_sec / 2 {
+                self.limit = (self.limit * 2).clamp(budget.min_preview, budget.max_preview);
+            }
+            self.window_start = Some(now);
+            self.spent = 0;
+        }
+
+        for event in events.iter_mut().filter(|e| !e.kind.is_marker()) {
+            while self.limit > budget.min_preview && self.spent + 2 * self.limit > budget.bytes_per_sec {
+                self.limit = (self.limit / 2).max(budget.min_preview);
+            }
+            event.old_preview.truncate(self.limit);
+            event.new_preview.truncate(self.limit);
+            event.preview_limit = Some(self.limit);
+            self.spent += event.old_preview.len() + event.new_preview.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::marker;
+    use crate::EventKind;
+
+    fn changes(count: usize) -> Vec<ChangeEvent> {
+        (0..count)
+            .map(|_| {
+                let mut event = marker(1, "buf", EventKind::Change);
+                event.old_preview = vec![0; 256];
+                event.new_preview = vec![1; 256];
+                event
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_limit_shrinks_under_burst_and_recovers() {
+        let mut throttle = PreviewThrottle::default();
+        throttle.set_budget(Some(PreviewBudget {
+            bytes_per_sec: 4096,
+            min_preview: 16,
+            max_preview: 256,
+        }));
+        let start = Instant::now();
+
+        let mut burst = changes(40);
+        throttle.apply(&mut burst, start);
+        assert_eq!(burst[0].preview_limit, Some(256));
+        assert_eq!(burst[39].preview_limit, Some(16));
+        assert_eq!(burst[39].new_preview.len(), 16);
+        assert!(burst.windows(2).all(|w| w[0].preview_limit >= w[1].preview_limit));
+
+        let mut quiet = changes(1);
+        throttle.apply(&mut quiet, start + WINDOW);
+        assert_eq!(quiet[0].preview_limit, Some(16));
+        for second in 2..6 {
+            throttle.apply(&mut changes(1), start + WINDOW * second);
+        }
+        let mut recovered = changes(1);
+        throttle.apply(&mut recovered, start + WINDOW * 6);
+        assert_eq!(recovered[0].preview_limit, Some(256));
+        assert_eq!(recovered[0].old_preview.len(), 256);
+    }
+}
@@ -0,0 +1,13 @@
+fn main() {
+    // The C core (page protection plus a SIGSEGV handler and /proc) only
+    // exists for Linux. Elsewhere, e.g. wasm32 or embedded triples, and with
+    // the `pure` feature, its FFI is compiled out; see backend.rs.
+    println!("cargo::rustc-check-cfg=cfg(native_backend)");
+    let os = std::env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    if std::env::var_os("CARGO_FEATURE_PURE").is_none() && matches!(os.as_str(), "linux" | "android") {
+        println!("cargo::rustc-cfg=native_backend");
+    }
+
+    #[cfg(feature = "napi")]
+    napi_build::setup();
+}
@@ -0,0 +1,174 @@
+// Watcher construction with global settings
+//
+// MemWatch::new() is MemWatch::builder().build(). The builder sets what has
+// to be decided before the core starts (ring size, symbolication, backtrace
+// depth, all handed to the C core through memwatch_config_* before
+// memwatch_init) and wires sinks and a filter in before the first event can
+// arrive.
+
+use crate::backend::BackendConfig;
+use crate::capabilities::WatchMode;
+use crate::custom_backend::{CustomBackend, WatchBackend};
+use crate::dispatch::{DispatchConfig, DispatchPool};
+use crate::policy::{Decision, Hook};
+use crate::sink::EventSink;
+use crate::{ChangeEvent, MemWatch};
+
+/// Configures and creates a MemWatch; see MemWatch::builder()
+pub struct MemWatchBuilder {
+    config: BackendConfig,
+    max_value_bytes: i32,
+    sinks: Vec<Box<dyn EventSink>>,
+    filter: Option<Hook<ChangeEvent>>,
+    dispatch: Option<DispatchConfig>,
+    backend: Option<Box<dyn WatchBackend>>,
+}
+
+impl Default for MemWatchBuilder {
+    fn default() -> Self {
+        MemWatchBuilder {
+            config: BackendConfig::default(),
+            max_value_bytes: 256,
+            sinks: Vec::new(),
+            filter: None,
+            dispatch: None,
+            backend: None,
+        }
+    }
+}
+
+impl MemWatchBuilder {
+    /// Slots in the native event ring; writes beyond it are dropped and
+    /// counted in Stats::ring_drop_count
+    pub fn ring_capacity(mut self, capacity: u32) -> Self {
+        self.config.ring_capacity = Some(capacity);
+        self
+    }
+
+    /// Value limit of watch(), watch_vec() and watch_tagged():
+    /// 0 = no values, >0 = limit to N bytes, -1 = full values (default 256)
+    pub fn max_value_bytes_default(mut self, max_value_bytes: i32) -> Self {
+        self.max_value_bytes = max_value_bytes;
+        self
+    }
+
+    /// Deliver events to `sink`, in addition to any added later with add_sink()
+    pub fn event_sink<S: EventSink + 'static>(mut self, sink: S) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Resolve fault addresses to file/function/line (default on, where the
+    /// core has a symbolizer). Off saves the lookup per fault; events then
+    /// only carry fault_ip.
+    pub fn symbolication(mut self, enabled: bool) -> Self {
+        self.config.symbolication = enabled;
+        self
+    }
+
+    /// Record up to `depth` return addresses of the writing thread with
+    /// each change (default 0, at most MAX_BACKTRACE_DEPTH); see
+    /// ChangeEvent::backtrace(). Native backend only; build() fails on a
+    /// core that captures no backtraces (the minimal core).
+    pub fn backtrace_depth(mut self, depth: u32) -> Self {
+        self.config.backtrace_depth = depth;
+        self
+    }
+
+    /// Record only changes `keep` accepts; markers always pass. Runs as the
+    /// first pre-persist hook.
+    pub fn filter<F>(mut self, keep: F) -> Self
+    where
+        F: Fn(&ChangeEvent) -> bool + Send + 'static,
+    {
+        self.filter = Some(Box::new(move |event| if keep(event) { Decision::Record } else { Decision::Veto }));
+        self
+    }
+
+    /// Run Delivery::Worker subscribers on a shared pool of
+    /// `config.workers` threads with bounded queues, instead of a thread
+    /// each; see the dispatch module
+    pub fn dispatch(mut self, config: DispatchConfig) -> Self {
+        self.dispatch = Some(config);
+        self
+    }
+
+    /// Take changes from `backend` instead of the native or snapshot
+    /// backend; see the custom_backend module
+    pub fn backend<B: WatchBackend + 'static>(mut self, backend: B) -> Self {
+        self.backend = Some(Box::new(backend));
+        self
+    }
+
+    pub fn build(self) -> Result<MemWatch, String> {
+        let mut watch = MemWatch::with_config(self.config, self.max_value_bytes)?;
+        if let Some(backend) = self.backend {
+            watch.custom = Some(CustomBackend(backend));
+            watch.capabilities.mode = WatchMode::Snapshot;
+        }
+        if let Some(config) = self.dispatch {
+            watch.dispatch = Some(DispatchPool::start(config)?);
+        }
+        if let Some(filter) = self.filter {
+            watch.hooks.lock().unwrap().push(filter);
+        }
+        watch.sinks.lock().unwrap().extend(self.sinks.into_iter().map(|sink| (sink, 0)));
+        Ok(watch)
+    }
+}
+
+impl MemWatch {
+    /// Configure a watcher before creating it
+    pub fn builder() -> MemWatchBuilder {
+        MemWatchBuilder::default()
+    }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native::{self, FakeEvent};
+    use std::sync::{Arc, Mutex};
+
+    struct Collect(Arc<Mutex<Vec<u32>>>);
+
+    impl EventSink for Collect {
+        fn write(&mut self, event: &ChangeEvent) -> Result<(), String> {
+            self.0.lock().unwrap().push(event.region_id);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_builder_configures_core_and_pipeline() {
+        let _guard = fake_native::lock();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut watcher = MemWatch::builder()
+            .ring_capacity(1024)
+            .symbolication(false)
+            .max_value_bytes_default(0)
+            .event_sink(Collect(Arc::clone(&seen)))
+            .filter(|event| event.new_preview != [2])
+            .build()
+            .unwrap();
+        watcher.capabilities.mode = WatchMode::Protect;
+        assert_eq!(fake_native::state().ring_capacity, Some(1024));
+        assert!(!fake_native::state().symbolication);
+
+        let (a, b) = ([0u8; 4], [0u8; 4]);
+        let first = watcher.watch(&a, "a").unwrap();
+        let second = watcher.watch(&b, "b").unwrap();
+        assert_eq!(watcher.regions.lock().unwrap()[&first].max_value_bytes, 0);
+        watcher.check_changes().unwrap();
+        seen.lock().unwrap().clear();
+
+        fake_native::state().events = vec![FakeEvent::change(first, &[1]), FakeEvent::change(second, &[2])];
+        let events = watcher.check_changes().unwrap();
+        assert_eq!(events.iter().map(|e| e.region_id).collect::<Vec<_>>(), [first]);
+        assert_eq!(events[0].where_.function, None);
+        assert_eq!(*seen.lock().unwrap(), [first]);
+
+        assert!(MemWatch::builder().ring_capacity(0).build().is_err());
+    }
+}
@@ -0,0 +1,222 @@
+// Memory bus adapter for emulators and VMs
+//
+// BusBackend is a WatchBackend for guest memory: the emulator forwards each
+// guest store through a BusHandle and gets the whole pipeline (filters,
+// history, sinks, reports, anomaly detection) for guest-memory debugging:
+//
+//   let bus = BusBackend::new(&guest_ram, 0x8000_0000);
+//   let cpu_bus = bus.handle();                      // into the store path
+//   let watcher = MemWatch::builder().backend(bus).build()?;
+//   watcher.watch(&guest_ram[0x1000..0x1010], "task_struct")?;
+//   ...
+//   cpu_bus.write_from(0x8000_1004, &value.to_le_bytes(), cpu.pc);
+//
+// Regions are registered as slices of the guest RAM buffer given to new(),
+// which fixes their guest address. Writes are reported with the bytes
+// written, not read back, so the RAM buffer may be updated before or after
+// the call; each watched region keeps its own copy of its last contents.
+// The guest program counter of write_from() lands in Location::fault_ip.
+//
+// Pending changes are capped at BUS_CAPACITY; stores beyond it are dropped
+// and counted in Stats::ring_drop_count, as the native ring does.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::custom_backend::{change_event, WatchBackend};
+use crate::{ChangeEvent, Stats};
+
+/// Changes held until check_changes() takes them
+pub const BUS_CAPACITY: usize = 65536;
+
+struct BusRegion {
+    name: String,
+    guest_addr: u64,
+    /// Last contents, as of the forwarded writes
+    contents: Vec<u8>,
+    max_value_bytes: i32,
+}
+
+#[derive(Default)]
+struct Bus {
+    /// Host address and length of the guest RAM buffer
+    host_start: usize,
+    host_len: usize,
+    guest_base: u64,
+    regions: BTreeMap<u32, BusRegion>,
+    next_id: u32,
+    pending: VecDeque<ChangeEvent>,
+    writes: u64,
+    changes: u64,
+    dropped: u64,
+}
+
+impl Bus {
+    fn write(&mut self, guest_addr: u64, bytes: &[u8], pc: u64) {
+        self.writes += 1;
+        let end = guest_addr.saturating_add(bytes.len() as u64);
+        let mut changed = Vec::new();
+        for (&id, region) in self.regions.iter_mut() {
+            let region_end = region.guest_addr + region.contents.len() as u64;
+            if guest_addr >= region_end || end <= region.guest_addr {
+                continue;
+            }
+            let start = guest_addr.max(region.guest_addr);
+            let stop = end.min(region_end);
+            let into = (start - region.guest_addr) as usize..(stop - region.guest_addr) as usize;
+            let from = (start - guest_addr) as usize..(stop - guest_addr) as usize;
+            if region.contents[into.clone()] == bytes[from.clone()] {
+                continue;
+            }
+            let old = region.contents.clone();
+            region.contents[into].copy_from_slice(&bytes[from]);
+            let mut event = change_event(id, &region.name, &old, &region.contents);
+            event.where_.fault_ip = pc;
+            limit_values(&mut event, region.max_value_bytes);
+            changed.push(event);
+        }
+        for event in changed {
+            self.changes += 1;
+            if self.pending.len() >= BUS_CAPACITY {
+                self.dropped += 1;
+            } else {
+                self.pending.push_back(event);
+            }
+        }
+    }
+}
+
+/// Apply a watch's value limit (0 = none, >0 = first N bytes, -1 = all)
+fn limit_values(event: &mut ChangeEvent, max_value_bytes: i32) {
+    if max_value_bytes < 0 {
+        return;
+    }
+    let limit = max_value_bytes as usize;
+    event.old_value.truncate(limit);
+    event.new_value.truncate(limit);
+}
+
+/// WatchBackend fed by an emulator's stores; see the bus module
+pub struct BusBackend {
+    bus: Arc<Mutex<Bus>>,
+}
+
+/// Forwards guest stores to a BusBackend; cheap to clone
+#[derive(Clone)]
+pub struct BusHandle {
+    bus: Arc<Mutex<Bus>>,
+}
+
+impl BusBackend {
+    /// Backend for the guest RAM in `ram`, whose first byte is at
+    /// `guest_base` in guest physical memory
+    pub fn new(ram: &[u8], guest_base: u64) -> BusBackend {
+        let bus = Bus {
+            host_start: ram.as_ptr() as usize,
+            host_len: ram.len(),
+            guest_base,
+            ..Bus::default()
+        };
+        BusBackend { bus: Arc::new(Mutex::new(bus)) }
+    }
+
+    pub fn handle(&self) -> BusHandle {
+        BusHandle { bus: Arc::clone(&self.bus) }
+    }
+}
+
+impl BusHandle {
+    /// A guest store of `bytes` at `guest_addr`
+    pub fn write(&self, guest_addr: u64, bytes: &[u8]) {
+        self.write_from(guest_addr, bytes, 0);
+    }
+
+    /// A guest store made by the instruction at `pc`
+    pub fn write_from(&self, guest_addr: u64, bytes: &[u8], pc: u64) {
+        self.bus.lock().unwrap().write(guest_addr, bytes, pc);
+    }
+}
+
+impl WatchBackend for BusBackend {
+    fn watch(&self, buffer: &[u8], name: &str, max_value_bytes: i32) -> Result<u32, String> {
+        let mut bus = self.bus.lock().unwrap();
+        let offset = (buffer.as_ptr() as usize)
+            .checked_sub(bus.host_start)
+            .filter(|offset| offset + buffer.len() <= bus.host_len)
+            .ok_or_else(|| format!("{} is not in guest RAM", name))?;
+        bus.next_id += 1;
+        let id = bus.next_id;
+        let region = BusRegion {
+            name: name.to_string(),
+            guest_addr: bus.guest_base + offset as u64,
+            contents: buffer.to_vec(),
+            max_value_bytes,
+        };
+        bus.regions.insert(id, region);
+        Ok(id)
+    }
+
+    fn unwatch(&self, region_id: u32) -> Result<(), String> {
+        let mut bus = self.bus.lock().unwrap();
+        bus.regions.remove(&region_id).ok_or_else(|| format!("Unknown region {}", region_id))?;
+        bus.pending.retain(|event| event.region_id != region_id);
+        Ok(())
+    }
+
+    fn drain(&self, max_events: usize) -> Result<Vec<ChangeEvent>, String> {
+        let mut bus = self.bus.lock().unwrap();
+        let count = bus.pending.len().min(max_events);
+        Ok(bus.pending.drain(..count).collect())
+    }
+
+    fn stats(&self) -> Result<Stats, String> {
+        let bus = self.bus.lock().unwrap();
+        Ok(Stats {
+            num_tracked_regions: bus.regions.len() as u32,
+            num_active_watchpoints: bus.regions.len() as u32,
+            total_events: bus.changes,
+            ring_write_count: bus.writes,
+            ring_drop_count: bus.dropped,
+            storage_bytes_used: bus.regions.values().map(|r| r.contents.len() as u64).sum(),
+            mprotect_page_count: 0,
+            worker_thread_id: 0,
+            worker_cycles: 0,
+            sampled_out_count: 0,
+            consumers: Vec::new(),
+            events_per_thread: Vec::new(),
+        })
+    }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::fake_native;
+    use crate::{EventKind, MemWatch};
+
+    #[test]
+    fn test_guest_stores_reach_the_pipeline() {
+        let _guard = fake_native::lock();
+        let ram = vec![0u8; 0x100];
+        let bus = BusBackend::new(&ram, 0x8000_0000);
+        let cpu = bus.handle();
+        let watcher = MemWatch::builder().backend(bus).build().unwrap();
+        let counter = watcher.watch_with_max_value_bytes(&ram[0x10..0x14], "counter", 2).unwrap();
+        watcher.watch(&ram[0x20..0x28], "flags").unwrap();
+        assert!(watcher.watch(&[0u8; 4], "host").is_err());
+        watcher.check_changes().unwrap();
+
+        cpu.write_from(0x8000_0012, &[0xaa, 0xbb, 0xcc], 0x400);
+        cpu.write(0x8000_0020, &[0]);
+        cpu.write(0x8000_0080, &[1]);
+        let events = watcher.check_changes().unwrap();
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!((event.region_id, event.kind, event.where_.fault_ip), (counter, EventKind::Change, 0x400));
+        assert_eq!(event.new_preview, [0, 0, 0xaa, 0xbb]);
+        assert_eq!(event.new_value, [0, 0]);
+
+        let stats = watcher.get_stats().unwrap();
+        assert_eq!((stats.num_tracked_regions, stats.ring_write_count, stats.total_events), (2, 3, 1));
+    }
+}
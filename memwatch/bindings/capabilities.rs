@@ -0,0 +1,298 @@
+// Environment capability detection
+//
+// Some sandboxes (gVisor, seccomp-filtered containers, serverless runtimes)
+// block mprotect. MemWatch checks once at init and falls back to snapshot
+// polling there instead of failing at the first watch call.
+//
+// Hardware differences are probed rather than assumed: the page size comes
+// from sysconf (16K on Apple Silicon, 4K or 64K on Graviton kernels), and
+// hosts enforcing W^X (macOS on arm64, OpenBSD, SELinux without execmem)
+// refuse pages that are both writable and executable. Watching such a page
+// would leave it without its execute bit, so watch() rejects regions in
+// executable mappings there.
+//
+// Page size can also differ per region: memory from hugetlbfs (MAP_HUGETLB,
+// hugetlbfs files) can only be protected in whole huge pages, e.g. 2MB. Its
+// KernelPageSize in /proc/self/smaps says so. Transparent huge pages are
+// split by mprotect and keep the base granularity.
+
+/// How watched regions are observed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchMode {
+    /// Page protection + fault handler in the native core (precise)
+    Protect,
+    /// Rust-side snapshot comparison on every poll (no faults, no attribution)
+    Snapshot,
+}
+
+/// How the OS reports a write to a protected page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultDelivery {
+    /// SIGSEGV to the process handler (Linux)
+    Signal,
+    /// EXC_BAD_ACCESS on a Mach exception port, before any signal (macOS);
+    /// the SIGBUS that follows is shared with crash reporters
+    MachException,
+    /// No supported fault mechanism on this target
+    Unavailable,
+}
+
+/// What the current environment supports
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    pub mprotect: bool,
+    /// errno from the mprotect probe when it failed
+    pub mprotect_errno: Option<i32>,
+    /// Seccomp mode from /proc/self/status (1 = strict, 2 = filter)
+    pub seccomp_mode: Option<u32>,
+    /// Detected sandbox/runtime, e.g. "gvisor", "aws-lambda", "container"
+    pub sandbox: Option<String>,
+    /// CPU architecture, e.g. "x86_64", "aarch64"
+    pub arch: &'static str,
+    /// Granularity of page protection, in bytes
+    pub page_size: usize,
+    /// Mechanism a native backend receives faults through
+    pub fault_delivery: FaultDelivery,
+    /// Pages cannot be writable and executable at once
+    pub wx_exclusive: bool,
+    /// Mode MemWatch selected for this environment
+    pub mode: WatchMode,
+}
+
+impl Capabilities {
+    /// Probe the current process
+    pub fn detect() -> Self {
+        let page = page_size();
+        let (mprotect_errno, wx_exclusive) = match std::alloc::Layout::from_size_align(page, page) {
+            Ok(layout) => unsafe {
+                let scratch = std::alloc::alloc_zeroed(layout);
+                if scratch.is_null() {
+                    (None, false)
+                } else {
+                    let result = (probe_mprotect(scratch, page).err(), probe_wx_exclusive(scratch, page));
+                    std::alloc::dealloc(scratch, layout);
+                    result
+                }
+            },
+            Err(_) => (None, false),
+        };
+        let mprotect = mprotect_errno.is_none();
+
+        Capabilities {
+            mprotect,
+            mprotect_errno,
+            seccomp_mode: seccomp_mode(),
+            sandbox: detect_sandbox(),
+            arch: std::env::consts::ARCH,
+            page_size: page,
+            fault_delivery: fault_delivery(),
+            wx_exclusive,
+            // Without the native backend there is nothing to protect pages with
+            mode: if mprotect && cfg!(native_backend) { WatchMode::Protect } else { WatchMode::Snapshot },
+        }
+    }
+
+    /// True when watching runs with reduced precision
+    pub fn is_degraded(&self) -> bool {
+        self.mode != WatchMode::Protect
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn page_size() -> usize {
+    let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    if size > 0 {
+        size as usize
+    } else {
+        4096
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn page_size() -> usize {
+    4096
+}
+
+#[cfg(unix)]
+pub(crate) fn probe_mprotect(page: *mut u8, len: usize) -> Result<(), i32> {
+    unsafe {
+        let addr = page as *mut libc::c_void;
+        if libc::mprotect(addr, len, libc::PROT_READ) != 0 {
+            return Err(std::io::Error::last_os_error().raw_os_error().unwrap_or(0));
+        }
+        if libc::mprotect(addr, len, libc::PROT_READ | libc::PROT_WRITE) != 0 {
+            return Err(std::io::Error::last_os_error().raw_os_error().unwrap_or(0));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn probe_mprotect(_page: *mut u8, _len: usize) -> Result<(), i32> {
+    Err(0)
+}
+
+/// Whether the scratch page refuses to become writable and executable
+#[cfg(unix)]
+fn probe_wx_exclusive(page: *mut u8, len: usize) -> bool {
+    unsafe {
+        let addr = page as *mut libc::c_void;
+        let rwx = libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC;
+        if libc::mprotect(addr, len, rwx) != 0 {
+            return true;
+        }
+        libc::mprotect(addr, len, libc::PROT_READ | libc::PROT_WRITE);
+    }
+    false
+}
+
+#[cfg(not(unix))]
+fn probe_wx_exclusive(_page: *mut u8, _len: usize) -> bool {
+    false
+}
+
+fn fault_delivery() -> FaultDelivery {
+    if cfg!(any(target_os = "linux", target_os = "android")) {
+        FaultDelivery::Signal
+    } else if cfg!(target_os = "macos") {
+        FaultDelivery::MachException
+    } else {
+        FaultDelivery::Unavailable
+    }
+}
+
+/// Whether [addr, addr + len) touches an executable mapping of this process
+#[cfg(target_os = "linux")]
+pub(crate) fn overlaps_executable(addr: usize, len: usize) -> bool {
+    std::fs::read_to_string("/proc/self/maps")
+        .map(|maps| maps_overlap_executable(&maps, addr, len))
+        .unwrap_or(false)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn overlaps_executable(_addr: usize, _len: usize) -> bool {
+    false
+}
+
+/// Lines look like "55d0c8a00000-55d0c8a21000 r-xp 00000000 08:01 1234 /usr/bin/x"
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn maps_overlap_executable(maps: &str, addr: usize, len: usize) -> bool {
+    let end = addr.saturating_add(len.max(1));
+    maps.lines().any(|line| {
+        let mut fields = line.split_whitespace();
+        let (Some(range), Some(perms)) = (fields.next(), fields.next()) else {
+            return false;
+        };
+        let Some((start, stop)) = range.split_once('-') else {
+            return false;
+        };
+        let (Ok(start), Ok(stop)) = (usize::from_str_radix(start, 16), usize::from_str_radix(stop, 16)) else {
+            return false;
+        };
+        perms.as_bytes().get(2) == Some(&b'x') && start < end && addr < stop
+    })
+}
+
+/// Protection granularity of the mapping holding `addr`; `base` when it is
+/// not backed by huge pages or cannot be told
+#[cfg(target_os = "linux")]
+pub(crate) fn region_page_size(addr: usize, base: usize) -> usize {
+    std::fs::read_to_string("/proc/self/smaps")
+        .ok()
+        .and_then(|smaps| smaps_page_size(&smaps, addr))
+        .unwrap_or(base)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn region_page_size(_addr: usize, base: usize) -> usize {
+    base
+}
+
+/// KernelPageSize of the smaps entry holding `addr`, in bytes
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn smaps_page_size(smaps: &str, addr: usize) -> Option<usize> {
+    let mut inside = false;
+    for line in smaps.lines() {
+        let mut fields = line.split_whitespace();
+        let Some(first) = fields.next() else {
+            continue;
+        };
+        if let Some((start, stop)) = first.split_once('-') {
+            if let (Ok(start), Ok(stop)) = (usize::from_str_radix(start, 16), usize::from_str_radix(stop, 16)) {
+                inside = start <= addr && addr < stop;
+                continue;
+            }
+        }
+        if inside && first == "KernelPageSize:" {
+            return fields.next()?.parse::<usize>().ok().map(|kb| kb * 1024);
+        }
+    }
+    None
+}
+
+pub(crate) fn seccomp_mode() -> Option<u32> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    parse_seccomp_mode(&status)
+}
+
+fn parse_seccomp_mode(status: &str) -> Option<u32> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Seccomp:"))
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|&mode| mode != 0)
+}
+
+fn detect_sandbox() -> Option<String> {
+    // gVisor reports a fixed fake kernel build in /proc/version
+    if let Ok(version) = std::fs::read_to_string("/proc/version") {
+        if version.contains("#1 SMP Sun Jan 10 15:06:54 PST 2016") {
+            return Some("gvisor".to_string());
+        }
+    }
+    if std::env::var_os("AWS_LAMBDA_FUNCTION_NAME").is_some() {
+        return Some("aws-lambda".to_string());
+    }
+    if std::env::var_os("K_SERVICE").is_some() {
+        return Some("cloud-run".to_string());
+    }
+    if std::path::Path::new("/.dockerenv").exists() || std::path::Path::new("/run/.containerenv").exists() {
+        return Some("container".to_string());
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_seccomp_mode() {
+        assert_eq!(parse_seccomp_mode("Name:\tcat\nSeccomp:\t2\n"), Some(2));
+        assert_eq!(parse_seccomp_mode("Seccomp:\t0\n"), None);
+        assert_eq!(parse_seccomp_mode("Name:\tcat\n"), None);
+    }
+
+    #[test]
+    fn test_smaps_page_size() {
+        let smaps = "7f0000000000-7f0000400000 rw-s 00000000 00:0f 1001 /anon_hugepage (deleted)\n\
+                     Size:               4096 kB\n\
+                     KernelPageSize:     2048 kB\n\
+                     7f0000400000-7f0000401000 rw-p 00000000 00:00 0\n\
+                     Size:                  4 kB\n\
+                     KernelPageSize:        4 kB\n";
+        assert_eq!(smaps_page_size(smaps, 0x7f0000000010), Some(2 << 20));
+        assert_eq!(smaps_page_size(smaps, 0x7f0000400000), Some(4096));
+        assert_eq!(smaps_page_size(smaps, 0x1000), None);
+    }
+
+    #[test]
+    fn test_maps_overlap_executable() {
+        let maps = "00400000-00452000 r-xp 00000000 08:02 173521 /usr/bin/dbus-daemon\n\
+                    00651000-00652000 rw-p 00051000 08:02 173521 /usr/bin/dbus-daemon\n";
+        assert!(maps_overlap_executable(maps, 0x00451ff0, 0x20));
+        assert!(!maps_overlap_executable(maps, 0x00452000, 0x10));
+        assert!(!maps_overlap_executable(maps, 0x00651000, 0x1000));
+        assert!(!maps_overlap_executable("garbage\n", 0x00400000, 1));
+    }
+}
@@ -0,0 +1,493 @@
+// C ABI re-export of the Rust layer (feature = "capi")
+//
+// C/C++ applications that already link the native core can link the crate
+// built as a cdylib (`make build-rust-capi`, i.e. `cargo rustc --lib
+// --crate-type cdylib --features capi`) to get the Rust-side functionality
+// as well: the check_changes() pipeline, filters (memwatch_rs_set_filter),
+// sinks (memwatch_rs_add_sink), byte diffs (memwatch_rs_diff) and SQL
+// parsing (memwatch_rs_sql_parse).
+// The header is generated with:
+// cbindgen --config cbindgen.toml -o ../include/memwatch_rs.h
+
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+use std::sync::Mutex;
+
+use crate::sink::EventSink;
+use crate::{diff, sql_parser, ChangeEvent, EventKind, Filter, MemWatch, Unwatch};
+
+/// A watcher plus the events check_changes() returned beyond what the
+/// caller had room for
+pub struct MemwatchRs {
+    watcher: MemWatch,
+    pending: Mutex<VecDeque<ChangeEvent>>,
+}
+
+/// Change event owned by the Rust layer; release with `memwatch_rs_event_free`
+#[repr(C)]
+pub struct MemwatchRsEvent {
+    pub seq: u32,
+    pub timestamp_ns: u64,
+    pub adapter_id: u32,
+    pub region_id: u32,
+    pub variable_name: *mut c_char,
+    pub file: *mut c_char,
+    pub function: *mut c_char,
+    pub line: u32,
+    pub fault_ip: u64,
+    pub old_preview: *mut u8,
+    pub old_preview_size: usize,
+    pub new_preview: *mut u8,
+    pub new_preview_size: usize,
+    /// 0 = change, 1 = watched, 2 = unwatched, 3 = paused, 4 = resumed, 5 = relocated, 6 = anomaly, 7 = freed, 8 = use after unwatch, 9 = alias warning, 10 = worker stalled, 11 = huge page warning, 12 = shared page warning, 13 = callback panicked, 14 = triggered
+    pub kind: u32,
+    pub epoch: u32,
+    pub global_seq: u64,
+}
+
+fn kind_code(kind: &EventKind) -> u32 {
+    match kind {
+        EventKind::Change => 0,
+        EventKind::Watched { .. } => 1,
+        EventKind::Unwatched => 2,
+        EventKind::Paused => 3,
+        EventKind::Resumed => 4,
+        EventKind::Relocated { .. } => 5,
+        EventKind::Anomaly(_) => 6,
+        EventKind::Freed => 7,
+        EventKind::UseAfterUnwatch => 8,
+        EventKind::AliasWarning { .. } => 9,
+        EventKind::WorkerStalled { .. } => 10,
+        EventKind::HugePageWarning { .. } => 11,
+        EventKind::SharedPageWarning { .. } => 12,
+        EventKind::CallbackPanicked { .. } => 13,
+        EventKind::Triggered { .. } => 14,
+    }
+}
+
+fn string_into_raw(value: Option<&str>) -> *mut c_char {
+    value
+        .and_then(|s| CString::new(s).ok())
+        .map(CString::into_raw)
+        .unwrap_or(ptr::null_mut())
+}
+
+fn bytes_into_raw(bytes: &[u8]) -> (*mut u8, usize) {
+    if bytes.is_empty() {
+        return (ptr::null_mut(), 0);
+    }
+    let boxed: Box<[u8]> = bytes.into();
+    let len = boxed.len();
+    (Box::into_raw(boxed) as *mut u8, len)
+}
+
+impl From<&ChangeEvent> for MemwatchRsEvent {
+    fn from(event: &ChangeEvent) -> Self {
+        let (old_preview, old_preview_size) = bytes_into_raw(&event.old_preview);
+        let (new_preview, new_preview_size) = bytes_into_raw(&event.new_preview);
+        MemwatchRsEvent {
+            seq: event.seq,
+            timestamp_ns: event.timestamp_ns,
+            adapter_id: event.adapter_id,
+            region_id: event.region_id,
+            variable_name: string_into_raw(event.variable_name.as_deref()),
+            file: string_into_raw(event.where_.file.as_deref()),
+            function: string_into_raw(event.where_.function.as_deref()),
+            line: event.where_.line,
+            fault_ip: event.where_.fault_ip,
+            old_preview,
+            old_preview_size,
+            new_preview,
+            new_preview_size,
+            kind: kind_code(&event.kind),
+            epoch: event.epoch,
+            global_seq: event.global_seq,
+        }
+    }
+}
+
+/// Create a watcher handle. Returns NULL if the native core fails to initialize.
+#[no_mangle]
+pub extern "C" fn memwatch_rs_new() -> *mut MemwatchRs {
+    match MemWatch::new() {
+        Ok(watcher) => Box::into_raw(Box::new(MemwatchRs { watcher, pending: Mutex::new(VecDeque::new()) })),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Destroy a handle created by `memwatch_rs_new`
+///
+/// # Safety
+/// `handle` must come from `memwatch_rs_new` and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn memwatch_rs_free(handle: *mut MemwatchRs) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Watch `size` bytes at `addr`. Returns region_id > 0 on success, 0 on error.
+///
+/// # Safety
+/// `handle` must be valid, `addr` must point to `size` readable bytes and
+/// `name` must be NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn memwatch_rs_watch(
+    handle: *const MemwatchRs,
+    addr: *const u8,
+    size: usize,
+    name: *const c_char,
+    max_value_bytes: i32,
+) -> u32 {
+    let Some(MemwatchRs { watcher, .. }) = handle.as_ref() else {
+        return 0;
+    };
+    if addr.is_null() {
+        return 0;
+    }
+    let name = if name.is_null() {
+        ""
+    } else {
+        CStr::from_ptr(name).to_str().unwrap_or("")
+    };
+    let buffer = std::slice::from_raw_parts(addr, size);
+    watcher
+        .watch_with_max_value_bytes(buffer, name, max_value_bytes)
+        .unwrap_or(0)
+}
+
+/// Stop watching a region. Returns 0 when removed, 1 when the region was
+/// not tracked, or a negative value on error.
+///
+/// # Safety
+/// `handle` must be a valid handle or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn memwatch_rs_unwatch(handle: *const MemwatchRs, region_id: u32) -> c_int {
+    match handle.as_ref().map(|handle| handle.watcher.unwatch(region_id)) {
+        Some(Ok(Unwatch::Removed)) => 0,
+        Some(Ok(Unwatch::NotTracked)) => 1,
+        Some(Err(_)) | None => -1,
+    }
+}
+
+/// Poll for changes. Returns the number of events written to `out_events`
+/// (at most `max_events`), or a negative value on error. Events beyond
+/// `max_events` are kept for the next call, which returns them before
+/// polling again.
+///
+/// # Safety
+/// `handle` must be valid and `out_events` must have room for `max_events`
+/// entries. Each returned event must be released with `memwatch_rs_event_free`.
+#[no_mangle]
+pub unsafe extern "C" fn memwatch_rs_check_changes(
+    handle: *const MemwatchRs,
+    out_events: *mut MemwatchRsEvent,
+    max_events: c_int,
+) -> c_int {
+    let Some(handle) = handle.as_ref() else {
+        return -1;
+    };
+    if out_events.is_null() || max_events <= 0 {
+        return -1;
+    }
+    let mut pending = handle.pending.lock().unwrap();
+    if pending.is_empty() {
+        match handle.watcher.check_changes_with_capacity(max_events as usize) {
+            Ok(events) => pending.extend(events),
+            Err(_) => return -1,
+        }
+    }
+    let count = pending.len().min(max_events as usize);
+    for (i, event) in pending.drain(..count).enumerate() {
+        out_events.add(i).write(MemwatchRsEvent::from(&event));
+    }
+    count as c_int
+}
+
+/// Drop changes the filter expression rejects (see the filter module for
+/// the syntax); NULL removes the filter. Returns 0, or -1 if the expression
+/// does not parse.
+///
+/// # Safety
+/// `handle` must be valid and `expr` NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn memwatch_rs_set_filter(handle: *const MemwatchRs, expr: *const c_char) -> c_int {
+    let Some(handle) = handle.as_ref() else {
+        return -1;
+    };
+    if expr.is_null() {
+        handle.watcher.set_filter(None);
+        return 0;
+    }
+    match CStr::from_ptr(expr).to_str().map_err(|e| e.to_string()).and_then(Filter::parse) {
+        Ok(filter) => {
+            handle.watcher.set_filter(Some(filter));
+            0
+        }
+        Err(_) => -1,
+    }
+}
+
+/// Sink callback: the event is only valid during the call. Return 0 on
+/// success; anything else counts as a failed delivery.
+pub type MemwatchRsSinkFn = unsafe extern "C" fn(event: *const MemwatchRsEvent, user_ctx: *mut c_void) -> c_int;
+
+struct CallbackSink {
+    callback: MemwatchRsSinkFn,
+    user_ctx: *mut c_void,
+}
+
+// SAFETY: memwatch_rs_add_sink() requires user_ctx to be usable from the
+// threads that call memwatch_rs_check_changes()
+unsafe impl Send for CallbackSink {}
+
+impl EventSink for CallbackSink {
+    fn write(&mut self, event: &ChangeEvent) -> Result<(), String> {
+        let mut c_event = MemwatchRsEvent::from(event);
+        let status = unsafe { (self.callback)(&c_event, self.user_ctx) };
+        unsafe { memwatch_rs_event_free(&mut c_event) };
+        match status {
+            0 => Ok(()),
+            status => Err(format!("C sink returned {}", status)),
+        }
+    }
+}
+
+/// Deliver every event check_changes() returns to `callback`, after
+/// filtering and sequencing. Returns 0, or -1 on a NULL handle.
+///
+/// # Safety
+/// `handle` must be valid; `user_ctx` is passed to `callback` as is and must
+/// stay valid, and usable from any thread polling the handle, while the
+/// handle lives.
+#[no_mangle]
+pub unsafe extern "C" fn memwatch_rs_add_sink(
+    handle: *const MemwatchRs,
+    callback: MemwatchRsSinkFn,
+    user_ctx: *mut c_void,
+) -> c_int {
+    let Some(handle) = handle.as_ref() else {
+        return -1;
+    };
+    handle.watcher.add_sink(CallbackSink { callback, user_ctx });
+    0
+}
+
+/// A changed byte range: `len` bytes from `offset`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemwatchRsRange {
+    pub offset: usize,
+    pub len: usize,
+}
+
+/// Byte ranges that differ between two values, ascending. Writes up to
+/// `max_ranges` to `out_ranges` and returns how many there are in total
+/// (more than `max_ranges` means the output was cut short), or -1 on error.
+///
+/// # Safety
+/// `old_value`/`new_value` must point to `old_size`/`new_size` readable bytes (or be
+/// NULL with size 0) and `out_ranges` must have room for `max_ranges`.
+#[no_mangle]
+pub unsafe extern "C" fn memwatch_rs_diff(
+    old_value: *const u8,
+    old_size: usize,
+    new_value: *const u8,
+    new_size: usize,
+    out_ranges: *mut MemwatchRsRange,
+    max_ranges: c_int,
+) -> c_int {
+    if (old_value.is_null() && old_size > 0) || (new_value.is_null() && new_size > 0) || (out_ranges.is_null() && max_ranges > 0) {
+        return -1;
+    }
+    let bytes = |p: *const u8, len: usize| if len == 0 { &[][..] } else { std::slice::from_raw_parts(p, len) };
+    let ranges = diff::compute_delta(bytes(old_value, old_size), bytes(new_value, new_size));
+    for (i, range) in ranges.iter().take(max_ranges.max(0) as usize).enumerate() {
+        out_ranges.add(i).write(MemwatchRsRange { offset: range.offset, len: range.len });
+    }
+    ranges.len().min(c_int::MAX as usize) as c_int
+}
+
+/// A parsed SQL statement; release with `memwatch_rs_statement_free`
+#[repr(C)]
+pub struct MemwatchRsStatement {
+    /// 0 = unknown, 1 = insert, 2 = update, 3 = delete, 4 = select
+    pub operation: u32,
+    /// NULL when the statement names no table
+    pub table: *mut c_char,
+    /// Columns written (INSERT, UPDATE) or read (SELECT); "*" when not listed
+    pub columns: *mut *mut c_char,
+    /// Source text of the value assigned to each column, NULL where none
+    pub values: *mut *mut c_char,
+    pub columns_len: usize,
+    pub has_where: bool,
+}
+
+fn strings_into_raw<'a>(strings: impl ExactSizeIterator<Item = Option<&'a str>>) -> *mut *mut c_char {
+    if strings.len() == 0 {
+        return ptr::null_mut();
+    }
+    let boxed: Box<[*mut c_char]> = strings.map(string_into_raw).collect();
+    Box::into_raw(boxed) as *mut *mut c_char
+}
+
+/// Parse one INSERT, UPDATE, DELETE or SELECT statement into `out`; other
+/// statements parse as unknown. Returns 0, or -1 on error.
+///
+/// # Safety
+/// `sql` must be a NUL-terminated string and `out` point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn memwatch_rs_sql_parse(sql: *const c_char, out: *mut MemwatchRsStatement) -> c_int {
+    if sql.is_null() || out.is_null() {
+        return -1;
+    }
+    let Ok(sql) = CStr::from_ptr(sql).to_str() else {
+        return -1;
+    };
+    let statement = sql_parser::parse(sql);
+    out.write(MemwatchRsStatement {
+        operation: statement.operation as u32,
+        table: string_into_raw(statement.table.as_deref()),
+        columns: strings_into_raw(statement.columns.iter().map(|(column, _)| Some(column.as_str()))),
+        values: strings_into_raw(statement.columns.iter().map(|(_, value)| value.as_deref())),
+        columns_len: statement.columns.len(),
+        has_where: statement.has_where,
+    });
+    0
+}
+
+/// Release what a parsed statement owns. Idempotent.
+///
+/// # Safety
+/// `statement` must be NULL or filled by `memwatch_rs_sql_parse`.
+#[no_mangle]
+pub unsafe extern "C" fn memwatch_rs_statement_free(statement: *mut MemwatchRsStatement) {
+    let Some(statement) = statement.as_mut() else {
+        return;
+    };
+    if !statement.table.is_null() {
+        drop(CString::from_raw(statement.table));
+        statement.table = ptr::null_mut();
+    }
+    for list in [&mut statement.columns, &mut statement.values] {
+        if list.is_null() {
+            continue;
+        }
+        let strings = Box::from_raw(ptr::slice_from_raw_parts_mut(*list, statement.columns_len));
+        for &s in strings.iter().filter(|s| !s.is_null()) {
+            drop(CString::from_raw(s));
+        }
+        *list = ptr::null_mut();
+    }
+    statement.columns_len = 0;
+}
+
+/// Release the strings and previews owned by an event. Idempotent.
+///
+/// # Safety
+/// `event` must be NULL or point to an event filled by this library.
+#[no_mangle]
+pub unsafe extern "C" fn memwatch_rs_event_free(event: *mut MemwatchRsEvent) {
+    let Some(event) = event.as_mut() else {
+        return;
+    };
+    for s in [&mut event.variable_name, &mut event.file, &mut event.function] {
+        if !s.is_null() {
+            drop(CString::from_raw(*s));
+            *s = ptr::null_mut();
+        }
+    }
+    for (p, len) in [
+        (&mut event.old_preview, &mut event.old_preview_size),
+        (&mut event.new_preview, &mut event.new_preview_size),
+    ] {
+        if !p.is_null() {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(*p, *len)));
+            *p = ptr::null_mut();
+            *len = 0;
+        }
+    }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native::{self, FakeEvent};
+
+    unsafe extern "C" fn count_changes(event: *const MemwatchRsEvent, user_ctx: *mut c_void) -> c_int {
+        if (*event).kind == 0 {
+            *(user_ctx as *mut u32) += 1;
+        }
+        0
+    }
+
+    #[test]
+    fn test_check_changes_keeps_what_does_not_fit() {
+        let _guard = fake_native::lock();
+        let handle = memwatch_rs_new();
+        unsafe {
+            (*handle).watcher.capabilities.mode = WatchMode::Protect;
+            let buffer = [0u8; 8];
+            let region = memwatch_rs_watch(handle, buffer.as_ptr(), buffer.len(), c"buf".as_ptr(), 64);
+            let mut delivered = 0u32;
+            assert_eq!(memwatch_rs_add_sink(handle, count_changes, &mut delivered as *mut u32 as *mut c_void), 0);
+            assert_eq!(memwatch_rs_set_filter(handle, c"size >= 2".as_ptr()), 0);
+            assert_eq!(memwatch_rs_set_filter(handle, c"size >>".as_ptr()), -1);
+            fake_native::state().events.extend([
+                FakeEvent::change(region, &[1, 2]),
+                FakeEvent::change(region, &[3, 4]),
+                FakeEvent::change(region, &[5]),
+                FakeEvent::change(region, &[6, 7]),
+            ]);
+
+            // The Watched marker and the first two changes come from one poll;
+            // the second change waits for the next call
+            let mut out: Vec<MemwatchRsEvent> = Vec::with_capacity(2);
+            let mut seen = Vec::new();
+            loop {
+                let count = memwatch_rs_check_changes(handle, out.as_mut_ptr(), 2);
+                assert!((0..=2).contains(&count));
+                if count == 0 {
+                    break;
+                }
+                out.set_len(count as usize);
+                for event in out.iter_mut() {
+                    let preview = if event.new_preview.is_null() { Vec::new() } else { std::slice::from_raw_parts(event.new_preview, event.new_preview_size).to_vec() };
+                    seen.push((event.kind, preview));
+                    memwatch_rs_event_free(event);
+                }
+                out.set_len(0);
+            }
+            let kinds: Vec<u32> = seen.iter().map(|(kind, _)| *kind).collect();
+            assert_eq!(kinds, [1, 0, 0, 0]);
+            assert_eq!(seen[3].1, [6, 7]);
+            assert_eq!(delivered, 3);
+            memwatch_rs_free(handle);
+        }
+    }
+
+    #[test]
+    fn test_diff_and_sql_parse() {
+        unsafe {
+            let (old, new) = ([0u8, 0, 0, 0, 0], [1u8, 0, 0, 2, 2]);
+            let mut ranges = [MemwatchRsRange { offset: 0, len: 0 }; 1];
+            assert_eq!(memwatch_rs_diff(old.as_ptr(), old.len(), new.as_ptr(), new.len(), ranges.as_mut_ptr(), 1), 2);
+            assert_eq!(ranges[0], MemwatchRsRange { offset: 0, len: 1 });
+            assert_eq!(memwatch_rs_diff(ptr::null(), 1, new.as_ptr(), new.len(), ranges.as_mut_ptr(), 1), -1);
+
+            let mut statement = std::mem::MaybeUninit::<MemwatchRsStatement>::uninit();
+            assert_eq!(memwatch_rs_sql_parse(c"UPDATE accounts SET balance = 10 WHERE id = 1".as_ptr(), statement.as_mut_ptr()), 0);
+            let mut statement = statement.assume_init();
+            assert_eq!((statement.operation, statement.columns_len, statement.has_where), (2, 1, true));
+            assert_eq!(CStr::from_ptr(statement.table).to_str(), Ok("accounts"));
+            assert_eq!(CStr::from_ptr(*statement.columns).to_str(), Ok("balance"));
+            assert_eq!(CStr::from_ptr(*statement.values).to_str(), Ok("10"));
+            memwatch_rs_statement_free(&mut statement);
+            memwatch_rs_statement_free(&mut statement);
+            assert!(statement.columns.is_null());
+        }
+    }
+}
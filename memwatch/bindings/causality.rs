@@ -0,0 +1,255 @@
+// Change causality between regions
+//
+// With enable_causality(window), each change returned by check_changes() is
+// matched against the latest change of every other region on the same
+// thread: when region B changes within `window` after region A, A's change
+// is credited with having been followed by B. An edge A -> B is reported
+// once it is consistent: at least MIN_OCCURRENCES such changes, making up at
+// least MIN_CONSISTENCY of A's changes. The graph shows how updates
+// propagate through data structures (an index rewritten after every insert
+// into its table, a cache invalidated after each config write):
+//
+//   watcher.enable_causality(Duration::from_micros(200));
+//   ...
+//   std::fs::write("causality.dot", watcher.causality_graph().to_dot())?;
+//
+// Changes without a thread (snapshot polling, hardware breakpoints) say
+// nothing about causality and are skipped. graph() builds the same from a
+// recorded stream, e.g. one loaded with replay.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::{ChangeEvent, MemWatch};
+
+/// Changes of A followed by B before A -> B is reported
+pub const MIN_OCCURRENCES: u64 = 3;
+/// Share of A's changes followed by B before A -> B is reported
+pub const MIN_CONSISTENCY: f64 = 0.5;
+
+/// Region B tends to change shortly after region A, on the same thread
+#[derive(Debug, Clone, PartialEq)]
+pub struct CausalEdge {
+    pub from: u32,
+    pub to: u32,
+    pub from_name: String,
+    pub to_name: String,
+    /// Changes of `from` followed by `to` within the window
+    pub count: u64,
+    /// `count` over all changes of `from`
+    pub consistency: f64,
+    /// Mean time from the change of `from` to the change of `to`
+    pub mean_lag: Duration,
+}
+
+/// Consistent edges, strongest first
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CausalityGraph {
+    pub edges: Vec<CausalEdge>,
+}
+
+impl CausalityGraph {
+    /// Graphviz source: regions as nodes, edges labelled with count and
+    /// mean lag, thicker for more consistent ones
+    pub fn to_dot(&self) -> String {
+        let mut nodes = BTreeMap::new();
+        for edge in &self.edges {
+            nodes.insert(edge.from, edge.from_name.as_str());
+            nodes.insert(edge.to, edge.to_name.as_str());
+        }
+        let mut dot = String::from("digraph causality {\n    rankdir=LR;\n    node [shape=box];\n");
+        for (id, name) in nodes {
+            let _ = writeln!(dot, "    r{} [label={}];", id, dot_str(name));
+        }
+        for edge in &self.edges {
+            let _ = writeln!(
+                dot,
+                "    r{} -> r{} [label=\"{}x, {}us\", penwidth={:.1}];",
+                edge.from,
+                edge.to,
+                edge.count,
+                edge.mean_lag.as_micros(),
+                1.0 + 3.0 * edge.consistency
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// A quoted DOT string
+pub(crate) fn dot_str(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// A change not yet out of the window
+struct Recent {
+    timestamp_ns: u64,
+    /// Regions already credited as following it
+    followed_by: HashSet<u32>,
+}
+
+pub(crate) struct Causality {
+    window_ns: u64,
+    /// Latest change of each region, per thread
+    recent: HashMap<u32, HashMap<u32, Recent>>,
+    changes: HashMap<u32, u64>,
+    names: HashMap<u32, String>,
+    /// (from, to) -> (count, summed lag)
+    pairs: HashMap<(u32, u32), (u64, u64)>,
+}
+
+impl Causality {
+    pub(crate) fn new(window: Duration) -> Self {
+        Causality {
+            window_ns: window.as_nanos() as u64,
+            recent: HashMap::new(),
+            changes: HashMap::new(),
+            names: HashMap::new(),
+            pairs: HashMap::new(),
+        }
+    }
+
+    /// Forget every change, keeping the window
+    pub(crate) fn reset(&mut self) {
+        *self = Causality::new(Duration::from_nanos(self.window_ns));
+    }
+
+    pub(crate) fn record(&mut self, event: &ChangeEvent) {
+        let Some(tid) = event.tid.filter(|_| !event.kind.is_marker()) else {
+            return;
+        };
+        let region = event.region_id;
+        *self.changes.entry(region).or_default() += 1;
+        if let Some(name) = &event.variable_name {
+            self.names.entry(region).or_insert_with(|| name.clone());
+        }
+        let window_ns = self.window_ns;
+        let recent = self.recent.entry(tid).or_default();
+        recent.retain(|_, change| event.timestamp_ns.saturating_sub(change.timestamp_ns) <= window_ns);
+        for (&from, change) in recent.iter_mut() {
+            if from == region || event.timestamp_ns < change.timestamp_ns || !change.followed_by.insert(region) {
+                continue;
+            }
+            let pair = self.pairs.entry((from, region)).or_default();
+            pair.0 += 1;
+            pair.1 += event.timestamp_ns - change.timestamp_ns;
+        }
+        recent.insert(region, Recent { timestamp_ns: event.timestamp_ns, followed_by: HashSet::new() });
+    }
+
+    pub(crate) fn graph(&self) -> CausalityGraph {
+        let name = |id: u32| self.names.get(&id).cloned().unwrap_or_else(|| format!("region {}", id));
+        let mut edges: Vec<CausalEdge> = self
+            .pairs
+            .iter()
+            .filter_map(|(&(from, to), &(count, lag_ns))| {
+                let consistency = count as f64 / self.changes.get(&from).copied().unwrap_or(count).max(1) as f64;
+                (count >= MIN_OCCURRENCES && consistency >= MIN_CONSISTENCY).then(|| CausalEdge {
+                    from,
+                    to,
+                    from_name: name(from),
+                    to_name: name(to),
+                    count,
+                    consistency,
+                    mean_lag: Duration::from_nanos(lag_ns / count),
+                })
+            })
+            .collect();
+        edges.sort_by(|a, b| {
+            b.consistency.total_cmp(&a.consistency).then(b.count.cmp(&a.count)).then((a.from, a.to).cmp(&(b.from, b.to)))
+        });
+        CausalityGraph { edges }
+    }
+}
+
+/// Causality graph of a recorded stream, in timestamp order
+pub fn graph(events: &[ChangeEvent], window: Duration) -> CausalityGraph {
+    let mut causality = Causality::new(window);
+    let mut ordered: Vec<&ChangeEvent> = events.iter().collect();
+    ordered.sort_by_key(|e| e.timestamp_ns);
+    for event in ordered {
+        causality.record(event);
+    }
+    causality.graph()
+}
+
+impl MemWatch {
+    /// Start building the causality graph, pairing changes up to `window`
+    /// apart; restarts it if already on. See the causality module.
+    pub fn enable_causality(&self, window: Duration) {
+        *self.causality.lock().unwrap() = Some(Causality::new(window));
+    }
+
+    /// Stop and drop the causality graph
+    pub fn disable_causality(&self) {
+        *self.causality.lock().unwrap() = None;
+    }
+
+    /// Consistent edges so far; empty unless enabled
+    pub fn causality_graph(&self) -> CausalityGraph {
+        self.causality.lock().unwrap().as_ref().map(Causality::graph).unwrap_or_default()
+    }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native::{self, FakeEvent};
+    use crate::lifecycle;
+    use crate::EventKind;
+
+    fn change(region_id: u32, name: &str, tid: u32, timestamp_ns: u64) -> ChangeEvent {
+        let mut event = lifecycle::marker(region_id, name, EventKind::Change);
+        (event.tid, event.timestamp_ns) = (Some(tid), timestamp_ns);
+        event
+    }
+
+    #[test]
+    fn test_graph_keeps_consistent_edges() {
+        let mut events = Vec::new();
+        for i in 0..4u64 {
+            let t = i * 1_000_000;
+            events.push(change(1, "table", 7, t));
+            events.push(change(2, "index", 7, t + 20_000));
+            // Same lag, other thread: not caused by table
+            events.push(change(3, "log", 8, t + 20_000));
+        }
+        // Too late to count
+        events.push(change(1, "table", 7, 10_000_000));
+        events.push(change(2, "index", 7, 10_500_000));
+
+        let graph = graph(&events, Duration::from_micros(100));
+        assert_eq!(graph.edges.len(), 1);
+        let edge = &graph.edges[0];
+        assert_eq!((edge.from_name.as_str(), edge.to_name.as_str(), edge.count), ("table", "index", 4));
+        assert_eq!((edge.consistency, edge.mean_lag), (0.8, Duration::from_micros(20)));
+        let dot = graph.to_dot();
+        assert!(dot.contains("r1 [label=\"table\"];"), "{}", dot);
+        assert!(dot.contains("r1 -> r2 [label=\"4x, 20us\", penwidth=3.4];"), "{}", dot);
+        assert_eq!(dot_str("a \"b\""), "\"a \\\"b\\\"\"");
+    }
+
+    #[test]
+    fn test_causality_follows_check_changes() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Protect;
+        let buffer = [0u8; 8];
+        let a = watcher.watch(&buffer[..4], "a").unwrap();
+        let b = watcher.watch(&buffer[4..], "b").unwrap();
+        assert!(watcher.causality_graph().edges.is_empty());
+        watcher.enable_causality(Duration::from_millis(1));
+        for i in 0..3u64 {
+            let at = |region_id, offset: u64| FakeEvent { tid: 5, timestamp_ns: i * 10_000_000 + offset, ..FakeEvent::change(region_id, &[1]) };
+            fake_native::state().events.extend([at(a, 0), at(b, 1_000)]);
+            watcher.check_changes().unwrap();
+        }
+        let edges: Vec<(u32, u32)> = watcher.causality_graph().edges.iter().map(|e| (e.from, e.to)).collect();
+        assert_eq!(edges, [(a, b)]);
+        watcher.disable_causality();
+        assert!(watcher.causality_graph().edges.is_empty());
+    }
+}
@@ -0,0 +1,264 @@
+// Policy checks over recorded sessions, for CI gating
+//
+// A session directory holds `events.jsonl` (exported events, one per line)
+// and optionally `sql.jsonl` (SQLChange::to_json() lines); finished session
+// bundles are verified against their manifest first. A policy file is a
+// small TOML subset: `[[rule]]` tables of `key = "string"` pairs.
+//
+//   [[rule]]
+//   name = "ledger is only written by the ledger module"
+//   kind = "write_outside"
+//   region = "ledger*"          # region name; a trailing * matches a prefix
+//   module = "bank::ledger"     # writer function (or file) prefix
+//
+//   [[rule]]
+//   kind = "update_without_where"
+//   table = "accounts"          # optional; any table when omitted
+//
+// write_outside flags changes to matching regions whose writer is outside the
+// module, including writes without a known location. update_without_where
+// flags UPDATE (and DELETE) statements without a WHERE clause.
+
+use std::fs;
+use std::path::Path;
+
+use crate::json::JsonValue;
+use crate::session::{SessionBundle, EVENTS_FILE, SQL_FILE};
+
+/// One rule of a policy file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    WriteOutside { name: String, region: String, module: String },
+    UpdateWithoutWhere { name: String, table: Option<String> },
+}
+
+/// Rules loaded from a policy file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Policy {
+    pub rules: Vec<Rule>,
+}
+
+/// A recorded change that breaks a rule
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub rule: String,
+    /// Line of the offending record in its file, from 1
+    pub line: usize,
+    pub file: &'static str,
+    pub detail: String,
+}
+
+impl Rule {
+    pub fn name(&self) -> &str {
+        match self {
+            Rule::WriteOutside { name, .. } | Rule::UpdateWithoutWhere { name, .. } => name,
+        }
+    }
+}
+
+impl Violation {
+    /// `{"rule":..,"file":..,"line":N,"detail":..}`
+    pub fn to_json(&self) -> String {
+        use crate::export::json_str;
+        format!(
+            "{{\"rule\":{},\"file\":{},\"line\":{},\"detail\":{}}}",
+            json_str(&self.rule),
+            json_str(self.file),
+            self.line,
+            json_str(&self.detail)
+        )
+    }
+}
+
+impl Policy {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read policy {}: {}", path.display(), e))?;
+        Policy::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut tables: Vec<Vec<(String, String)>> = Vec::new();
+        for (index, raw) in text.lines().enumerate() {
+            let line = strip_comment(raw).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line == "[[rule]]" {
+                tables.push(Vec::new());
+                continue;
+            }
+            let error = || format!("Policy line {}: expected [[rule]] or key = \"value\"", index + 1);
+            let (key, value) = line.split_once('=').ok_or_else(error)?;
+            let value = value.trim().strip_prefix('"').and_then(|v| v.strip_suffix('"')).ok_or_else(error)?;
+            tables.last_mut().ok_or_else(error)?.push((key.trim().to_string(), value.replace("\\\"", "\"")));
+        }
+        let rules = tables.iter().enumerate().map(|(i, table)| rule(i + 1, table)).collect::<Result<_, _>>()?;
+        Ok(Policy { rules })
+    }
+
+    /// Every violation in the session at `dir`, in file order
+    pub fn check_session(&self, dir: impl AsRef<Path>) -> Result<Vec<Violation>, String> {
+        let dir = dir.as_ref();
+        if dir.join("manifest.json").exists() {
+            SessionBundle::open(dir)?;
+        }
+        let mut violations = Vec::new();
+        for (line, event) in read_jsonl(&dir.join(EVENTS_FILE), true)? {
+            for rule in &self.rules {
+                if let Some(detail) = rule.check_event(&event) {
+                    violations.push(Violation { rule: rule.name().to_string(), line, file: EVENTS_FILE, detail });
+                }
+            }
+        }
+        for (line, change) in read_jsonl(&dir.join(SQL_FILE), false)? {
+            for rule in &self.rules {
+                if let Some(detail) = rule.check_sql(&change) {
+                    violations.push(Violation { rule: rule.name().to_string(), line, file: SQL_FILE, detail });
+                }
+            }
+        }
+        Ok(violations)
+    }
+}
+
+impl Rule {
+    fn check_event(&self, event: &JsonValue) -> Option<String> {
+        let Rule::WriteOutside { region, module, .. } = self else {
+            return None;
+        };
+        let field = |key| event.get(key).and_then(JsonValue::as_str);
+        if field("kind") != Some("change") || !matches_pattern(region, field("variable_name")?) {
+            return None;
+        }
+        let (function, file) = (field("function"), field("file"));
+        if function.is_some_and(|f| f.starts_with(module.as_str())) || file.is_some_and(|f| f.starts_with(module.as_str())) {
+            return None;
+        }
+        let writer = match (function, file) {
+            (Some(function), _) => function.to_string(),
+            (None, Some(file)) => file.to_string(),
+            (None, None) => "an unknown location".to_string(),
+        };
+        Some(format!("{} written from {}", field("variable_name")?, writer))
+    }
+
+    fn check_sql(&self, change: &JsonValue) -> Option<String> {
+        let Rule::UpdateWithoutWhere { table, .. } = self else {
+            return None;
+        };
+        let field = |key| change.get(key).and_then(JsonValue::as_str);
+        let changed_table = field("table_name")?;
+        if !matches!(field("operation")?, "UPDATE" | "DELETE") || table.as_ref().is_some_and(|t| t != changed_table) {
+            return None;
+        }
+        let query = field("full_query")?;
+        if crate::sql_parser::parse(query).has_where {
+            return None;
+        }
+        Some(format!("{} without WHERE: {}", changed_table, query))
+    }
+}
+
+fn rule(number: usize, table: &[(String, String)]) -> Result<Rule, String> {
+    let get = |key: &str| table.iter().rev().find(|(k, _)| k == key).map(|(_, v)| v.clone());
+    let require = |key: &str| get(key).ok_or_else(|| format!("Rule {} needs {}", number, key));
+    let kind = require("kind")?;
+    let name = get("name").unwrap_or_else(|| format!("{} #{}", kind, number));
+    match kind.as_str() {
+        "write_outside" => Ok(Rule::WriteOutside { name, region: require("region")?, module: require("module")? }),
+        "update_without_where" => Ok(Rule::UpdateWithoutWhere { name, table: get("table") }),
+        other => Err(format!("Rule {}: unknown kind {}", number, other)),
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == pattern,
+    }
+}
+
+/// (line number, value) of each non-empty line; a missing optional file is
+/// an empty session part
+fn read_jsonl(path: &Path, required: bool) -> Result<Vec<(usize, JsonValue)>, String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            JsonValue::parse(line)
+                .map(|value| (i + 1, value))
+                .ok_or_else(|| format!("{}:{}: invalid JSON", path.display(), i + 1))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = r#"
+# gate for the ledger
+[[rule]]
+name = "ledger writers"   # shown in reports
+kind = "write_outside"
+region = "ledger*"
+module = "bank::ledger"
+
+[[rule]]
+kind = "update_without_where"
+table = "accounts"
+"#;
+
+    #[test]
+    fn test_session_violations() {
+        let policy = Policy::parse(POLICY).unwrap();
+        assert_eq!(policy.rules[1].name(), "update_without_where #2");
+        assert!(Policy::parse("kind = \"write_outside\"").is_err());
+        assert!(Policy::parse("[[rule]]\nkind = \"nope\"").is_err());
+
+        let dir = std::env::temp_dir().join(format!("memwatch_ci_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let event = |name: &str, function: &str| {
+            format!("{{\"kind\":\"change\",\"variable_name\":\"{}\",\"function\":\"{}\",\"file\":null}}\n", name, function)
+        };
+        let events = [
+            event("ledger.entries", "bank::ledger::post"),
+            event("ledger.entries", "bank::admin::fixup"),
+            event("cache", "bank::admin::fixup"),
+            "{\"kind\":\"watched\",\"variable_name\":\"ledger\"}\n".to_string(),
+        ];
+        fs::write(dir.join("events.jsonl"), events.concat()).unwrap();
+        fs::write(
+            dir.join("sql.jsonl"),
+            "{\"table_name\":\"accounts\",\"operation\":\"UPDATE\",\"full_query\":\"UPDATE accounts SET frozen = 1\"}\n\
+             {\"table_name\":\"accounts\",\"operation\":\"UPDATE\",\"full_query\":\"UPDATE accounts SET frozen = 1 WHERE id = 2\"}\n",
+        )
+        .unwrap();
+
+        let violations = policy.check_session(&dir).unwrap();
+        assert_eq!(violations.len(), 2);
+        assert_eq!((violations[0].rule.as_str(), violations[0].line), ("ledger writers", 2));
+        assert_eq!(violations[0].detail, "ledger.entries written from bank::admin::fixup");
+        assert_eq!((violations[1].file, violations[1].line), ("sql.jsonl", 1));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
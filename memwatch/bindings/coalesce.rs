@@ -0,0 +1,205 @@
+// Coalescing bursts of writes
+//
+// A memcpy into a watched buffer can fault once per byte, and each fault is
+// a change. With WatchOptions::coalesce_ms, changes of the region made
+// within that many milliseconds of the first one are merged into a single
+// change instead:
+//
+//   let options = WatchOptions { coalesce_ms: Some(5), ..Default::default() };
+//   watcher.watch_with_options(&frame, "frame", &options)?;
+//
+// The merged change keeps the first change's timestamp, writer and old
+// value, takes the last change's new value, and counts the writes it stands
+// for in write_count; its deltas span everything the burst changed. It is
+// held back until the window has passed, so check_changes() returns it one
+// window (or one call) late. A marker of the region (such as Unwatched)
+// releases it early, ahead of the marker, and drain_all_changes() releases
+// everything held.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::{diff, ChangeEvent, EventKind};
+
+/// A merged change waiting for its window to pass
+struct Pending {
+    event: ChangeEvent,
+    /// Timestamp of the first change, on the backend's clock
+    first_ns: u64,
+    window: Duration,
+    /// When the first change reached the Rust layer
+    held_since: Instant,
+}
+
+#[derive(Default)]
+pub(crate) struct Coalescer {
+    pending: HashMap<u32, Pending>,
+}
+
+impl Coalescer {
+    /// Merge changes of regions with a window (as told by `window`), and
+    /// release the merged changes whose window has passed by `now`
+    pub(crate) fn apply(
+        &mut self,
+        events: Vec<ChangeEvent>,
+        window: impl Fn(u32) -> Option<Duration>,
+        now: Instant,
+    ) -> Vec<ChangeEvent> {
+        let mut out = Vec::with_capacity(events.len());
+        for event in events {
+            let region_id = event.region_id;
+            if event.kind != EventKind::Change {
+                out.extend(self.pending.remove(&region_id).map(|p| p.event));
+                out.push(event);
+                continue;
+            }
+            let Some(window) = window(region_id) else {
+                out.push(event);
+                continue;
+            };
+            match self.pending.get_mut(&region_id) {
+                Some(pending) if event.timestamp_ns.saturating_sub(pending.first_ns) <= pending.window.as_nanos() as u64 => {
+                    merge(&mut pending.event, event);
+                }
+                _ => {
+                    let first_ns = event.timestamp_ns;
+                    let pending = Pending { event, first_ns, window, held_since: now };
+                    out.extend(self.pending.insert(region_id, pending).map(|p| p.event));
+                }
+            }
+        }
+        let mut due: Vec<u32> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| now.duration_since(p.held_since) >= p.window)
+            .map(|(&id, _)| id)
+            .collect();
+        due.sort_by_key(|id| self.pending[id].first_ns);
+        out.extend(due.iter().filter_map(|id| self.pending.remove(id)).map(|p| p.event));
+        out
+    }
+
+    /// Every held change, window passed or not, oldest first
+    pub(crate) fn flush(&mut self) -> Vec<ChangeEvent> {
+        let mut held: Vec<Pending> = self.pending.drain().map(|(_, p)| p).collect();
+        held.sort_by_key(|p| p.first_ns);
+        held.into_iter().map(|p| p.event).collect()
+    }
+}
+
+fn merge(into: &mut ChangeEvent, later: ChangeEvent) {
+    into.new_preview = later.new_preview;
+    into.new_value = later.new_value;
+    into.storage_key_new = later.storage_key_new;
+    into.write_count = into.write_count.saturating_add(later.write_count);
+    if !into.old_value.is_empty() && !into.new_value.is_empty() {
+        into.deltas = diff::compute_delta(&into.old_value, &into.new_value);
+    } else {
+        into.deltas.extend(later.deltas);
+        normalize(&mut into.deltas);
+    }
+}
+
+/// Sort ranges by offset and join overlapping or adjacent ones, as
+/// compute_delta() would have returned them
+fn normalize(deltas: &mut Vec<diff::ByteRange>) {
+    deltas.sort_by_key(|range| range.offset);
+    let mut joined: Vec<diff::ByteRange> = Vec::with_capacity(deltas.len());
+    for range in deltas.drain(..) {
+        match joined.last_mut() {
+            Some(last) if range.offset <= last.end() => last.len = last.len.max(range.end() - last.offset),
+            _ => joined.push(range),
+        }
+    }
+    *deltas = joined;
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use crate::capabilities::WatchMode;
+    use crate::diff::ByteRange;
+    use crate::fake_native::{self, FakeEvent};
+    use crate::options::WatchOptions;
+    use crate::{ChangeEvent, EventKind, MemWatch};
+
+    fn changes(events: Vec<ChangeEvent>) -> Vec<ChangeEvent> {
+        events.into_iter().filter(|e| e.kind == EventKind::Change).collect()
+    }
+
+    #[test]
+    fn test_bursts_merge_into_one_change() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Protect;
+        let buffer = [0u8; 8];
+        let options = WatchOptions { coalesce_ms: Some(1), ..Default::default() };
+        let burst = watcher.watch_with_options(&buffer[..4], "burst", &options).unwrap();
+        let plain = watcher.watch(&buffer[4..], "plain").unwrap();
+
+        let at = |region_id, byte: u8, timestamp_ns| FakeEvent { timestamp_ns, ..FakeEvent::change(region_id, &[byte]) };
+        fake_native::state().events.extend([at(burst, 1, 100), at(plain, 9, 150), at(burst, 2, 200), at(burst, 3, 300)]);
+        let events = changes(watcher.check_changes().unwrap());
+        assert_eq!(events.iter().map(|e| (e.region_id, e.write_count)).collect::<Vec<_>>(), [(plain, 1)]);
+
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        // Past the window of the first: a change of its own
+        fake_native::state().events.push(at(burst, 4, 5_000_000));
+        let events = changes(watcher.check_changes().unwrap());
+        assert_eq!(events.len(), 1);
+        let merged = &events[0];
+        assert_eq!((merged.region_id, merged.write_count, merged.timestamp_ns), (burst, 3, 100));
+        assert_eq!(merged.new_preview, [3]);
+
+        // Unwatching releases what is held, before the marker
+        watcher.unwatch(burst).unwrap();
+        let events: Vec<ChangeEvent> = watcher.check_changes().unwrap().into_iter().filter(|e| e.region_id == burst).collect();
+        let kinds: Vec<(EventKind, u32)> = events.iter().map(|e| (e.kind, e.write_count)).collect();
+        assert_eq!(kinds, [(EventKind::Change, 1), (EventKind::Unwatched, 1)]);
+        assert_eq!(events[0].new_preview, [4]);
+    }
+
+    #[test]
+    fn test_merged_deltas_span_the_burst() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        let mut buffer = vec![0u8; 16];
+        let options = WatchOptions { coalesce_ms: Some(20), max_value_bytes: Some(-1), ..Default::default() };
+        watcher.watch_with_options(&buffer, "buffer", &options).unwrap();
+
+        buffer[0] = 1;
+        assert!(changes(watcher.check_changes().unwrap()).is_empty());
+        buffer[8] = 1;
+        assert!(changes(watcher.check_changes().unwrap()).is_empty());
+        std::thread::sleep(std::time::Duration::from_millis(25));
+        let events = changes(watcher.check_changes().unwrap());
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].write_count, 2);
+        assert_eq!(events[0].deltas, [ByteRange { offset: 0, len: 1 }, ByteRange { offset: 8, len: 1 }]);
+    }
+
+    #[test]
+    fn test_deltas_without_values_are_normalized() {
+        let range = |offset, len| ByteRange { offset, len };
+        let change = |deltas| ChangeEvent { deltas, ..crate::custom_backend::change_event(1, "buffer", &[], &[]) };
+        let mut first = change(vec![range(8, 2), range(20, 1)]);
+        let later = change(vec![range(0, 1), range(9, 4), range(13, 1), range(30, 0)]);
+        super::merge(&mut first, later);
+        assert_eq!(first.deltas, [range(0, 1), range(8, 6), range(20, 1), range(30, 0)]);
+    }
+
+    #[test]
+    fn test_drain_releases_held_changes() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Protect;
+        let buffer = [0u8; 4];
+        let options = WatchOptions { coalesce_ms: Some(60_000), ..Default::default() };
+        let region = watcher.watch_with_options(&buffer, "buffer", &options).unwrap();
+
+        fake_native::state().events.extend([FakeEvent::change(region, &[1]), FakeEvent::change(region, &[2])]);
+        assert!(changes(watcher.check_changes().unwrap()).is_empty());
+        let events = changes(watcher.drain_all_changes().unwrap().events);
+        assert_eq!(events.iter().map(|e| (e.write_count, e.new_preview.clone())).collect::<Vec<_>>(), [(2, vec![2])]);
+    }
+}
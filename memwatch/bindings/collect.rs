@@ -0,0 +1,331 @@
+// Multi-process event collection
+//
+// Instrumented processes stream events to a collector with `StreamSink`. A
+// stream starts with a hello line "MEMWATCH 1\t<host>\t<pid>\t<name>" and the
+// sender's process header, followed by one exported event (JSON) per line.
+// The collector appends every record to a single combined store as
+// "<host>\t<pid>\t<name>\t<json>", in arrival order, so events of a fleet can
+// be queried together but still told apart.
+//
+// Streams run over TCP, or on Linux over vsock (connect_vsock() and
+// serve_vsock()) for processes inside VMs; see the vsock module.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::export::{json_str, ValueMode};
+use crate::process;
+use crate::sink::EventSink;
+#[cfg(target_os = "linux")]
+use crate::vsock::{VsockAddr, VsockListener, VsockStream};
+use crate::ChangeEvent;
+
+const HELLO: &str = "MEMWATCH 1";
+
+/// Called with the peer and the error of a failed stream
+type ErrorHandler = Arc<dyn Fn(&str, &str) + Send + Sync>;
+
+/// Who sent an event
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ProcessIdentity {
+    pub host: String,
+    pub pid: u32,
+    /// Executable name unless set explicitly
+    pub name: String,
+}
+
+impl ProcessIdentity {
+    /// Identity of the current process
+    pub fn current() -> Self {
+        let name = std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.file_stem().map(|s| s.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "unknown".to_string());
+        ProcessIdentity {
+            host: process::hostname(),
+            pid: std::process::id(),
+            name,
+        }
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    fn to_fields(&self) -> String {
+        format!("{}\t{}\t{}", field(&self.host), self.pid, field(&self.name))
+    }
+
+    fn from_fields(host: &str, pid: &str, name: &str) -> Option<Self> {
+        Some(ProcessIdentity {
+            host: host.to_string(),
+            pid: pid.parse().ok()?,
+            name: name.to_string(),
+        })
+    }
+}
+
+/// One event in a combined store
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollectedEvent {
+    pub process: ProcessIdentity,
+    /// The event as exported by the sending process
+    pub event_json: String,
+}
+
+impl CollectedEvent {
+    /// `{"host":..,"pid":..,"name":..,"event":{..}}`
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"host\":{},\"pid\":{},\"name\":{},\"event\":{}}}",
+            json_str(&self.process.host),
+            self.process.pid,
+            json_str(&self.process.name),
+            self.event_json
+        )
+    }
+}
+
+/// Sink streaming events to a collector (or any writer)
+pub struct StreamSink<W: Write + Send> {
+    out: BufWriter<W>,
+    values: ValueMode,
+    /// Metadata of the process header still to be sent, None once it was
+    header: Option<Vec<(String, String)>>,
+}
+
+impl StreamSink<TcpStream> {
+    /// Connect to a `memwatch-collect` instance
+    pub fn connect(addr: impl ToSocketAddrs, identity: &ProcessIdentity) -> Result<Self, String> {
+        let stream = TcpStream::connect(addr).map_err(|e| format!("Failed to connect to collector: {}", e))?;
+        StreamSink::new(stream, identity)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl StreamSink<VsockStream> {
+    /// Connect to a `memwatch-collect` instance over vsock, e.g. on the
+    /// host from inside a VM
+    pub fn connect_vsock(addr: VsockAddr, identity: &ProcessIdentity) -> Result<Self, String> {
+        let stream = VsockStream::connect(addr).map_err(|e| format!("Failed to connect to collector at {}: {}", addr, e))?;
+        StreamSink::new(stream, identity)
+    }
+}
+
+impl<W: Write + Send> StreamSink<W> {
+    /// Start a stream on `out` by sending the hello line; the process
+    /// header follows with the first event
+    pub fn new(out: W, identity: &ProcessIdentity) -> Result<Self, String> {
+        let mut out = BufWriter::new(out);
+        writeln!(out, "{}\t{}", HELLO, identity.to_fields())
+            .and_then(|_| out.flush())
+            .map_err(|e| format!("Stream write failed: {}", e))?;
+        Ok(StreamSink {
+            out,
+            values: ValueMode::Raw,
+            header: Some(Vec::new()),
+        })
+    }
+
+    fn write_header(&mut self) -> Result<(), String> {
+        match self.header.take() {
+            Some(metadata) => writeln!(self.out, "{}", process::current().to_json_with(&metadata))
+                .map_err(|e| format!("Stream write failed: {}", e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Value representation in streamed events (e.g. hashed for sensitive data)
+    pub fn with_value_mode(mut self, values: ValueMode) -> Self {
+        self.values = values;
+        self
+    }
+}
+
+impl<W: Write + Send> EventSink for StreamSink<W> {
+    fn write(&mut self, event: &ChangeEvent) -> Result<(), String> {
+        self.write_header()?;
+        writeln!(self.out, "{}", self.values.export(event).to_json()).map_err(|e| format!("Stream write failed: {}", e))
+    }
+
+    fn metadata(&mut self, metadata: &[(String, String)]) {
+        if let Some(header) = self.header.as_mut() {
+            *header = metadata.to_vec();
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.write_header()?;
+        self.out.flush().map_err(|e| format!("Stream flush failed: {}", e))
+    }
+}
+
+/// Receives streams and merges them into one combined store
+#[derive(Clone)]
+pub struct Collector {
+    store: Arc<Mutex<BufWriter<File>>>,
+    /// See with_error_handler()
+    on_error: Option<ErrorHandler>,
+}
+
+impl Collector {
+    /// Open (or create) a combined store; new records are appended
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open store {}: {}", path.display(), e))?;
+        Ok(Collector {
+            store: Arc::new(Mutex::new(BufWriter::new(file))),
+            on_error: None,
+        })
+    }
+
+    /// Call `handler` with the peer and the error when serving a stream
+    /// fails; without one, failed streams are just closed
+    pub fn with_error_handler(mut self, handler: impl Fn(&str, &str) + Send + Sync + 'static) -> Self {
+        self.on_error = Some(Arc::new(handler));
+        self
+    }
+
+    /// Accept connections forever, one thread per sending process
+    pub fn serve(&self, listener: TcpListener) -> Result<(), String> {
+        self.serve_streams(listener.incoming().map(|stream| {
+            let stream = stream?;
+            let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+            Ok((stream, peer))
+        }))
+    }
+
+    /// serve(), for streams over vsock
+    #[cfg(target_os = "linux")]
+    pub fn serve_vsock(&self, listener: VsockListener) -> Result<(), String> {
+        self.serve_streams(std::iter::repeat_with(|| listener.accept().map(|(stream, peer)| (stream, peer.to_string()))))
+    }
+
+    fn serve_streams<S: Read + Send + 'static>(&self, incoming: impl Iterator<Item = io::Result<(S, String)>>) -> Result<(), String> {
+        for accepted in incoming {
+            let (stream, peer) = accepted.map_err(|e| format!("Accept failed: {}", e))?;
+            let collector = self.clone();
+            thread::spawn(move || {
+                if let (Err(e), Some(on_error)) = (collector.ingest(stream), &collector.on_error) {
+                    on_error(&peer, &e);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Read one stream to its end; returns the number of records stored
+    pub fn ingest(&self, stream: impl Read) -> Result<u64, String> {
+        let mut lines = BufReader::new(stream).lines();
+        let hello = lines
+            .next()
+            .ok_or("Stream closed before hello")?
+            .map_err(|e| format!("Stream read failed: {}", e))?;
+        let process = parse_hello(&hello).ok_or_else(|| format!("Bad hello: {:?}", hello))?;
+        let prefix = process.to_fields();
+
+        let mut stored = 0;
+        for line in lines {
+            let line = line.map_err(|e| format!("Stream read failed: {}", e))?;
+            if line.is_empty() {
+                continue;
+            }
+            let mut store = self.store.lock().unwrap();
+            writeln!(store, "{}\t{}", prefix, line)
+                .and_then(|_| store.flush())
+                .map_err(|e| format!("Store write failed: {}", e))?;
+            stored += 1;
+        }
+        Ok(stored)
+    }
+}
+
+/// Read a combined store, keeping records whose process name matches
+/// `process` (all records when `None`). Each stream's first record is the
+/// sender's process header.
+pub fn read_store(path: impl AsRef<Path>, process: Option<&str>) -> Result<Vec<CollectedEvent>, String> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| format!("Failed to open store {}: {}", path.display(), e))?;
+    let mut events = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("Failed to read store: {}", e))?;
+        let mut fields = line.splitn(4, '\t');
+        let parsed = match (fields.next(), fields.next(), fields.next(), fields.next()) {
+            (Some(host), Some(pid), Some(name), Some(json)) => {
+                ProcessIdentity::from_fields(host, pid, name).map(|p| (p, json))
+            }
+            _ => None,
+        };
+        let (identity, json) = parsed.ok_or_else(|| format!("Malformed store line {}", i + 1))?;
+        if process.is_none_or(|name| name == identity.name) {
+            events.push(CollectedEvent {
+                process: identity,
+                event_json: json.to_string(),
+            });
+        }
+    }
+    Ok(events)
+}
+
+fn parse_hello(line: &str) -> Option<ProcessIdentity> {
+    let rest = line.strip_prefix(HELLO)?.strip_prefix('\t')?;
+    let mut fields = rest.split('\t');
+    let identity = ProcessIdentity::from_fields(fields.next()?, fields.next()?, fields.next()?)?;
+    fields.next().is_none().then_some(identity)
+}
+
+/// Identity fields are tab separated, so tabs and newlines become spaces
+fn field(value: &str) -> String {
+    value.replace(['\t', '\n', '\r'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::marker;
+    use crate::EventKind;
+
+    #[test]
+    fn test_streams_merge_with_process_identity() {
+        let path = std::env::temp_dir().join(format!("memwatch_collect_{}.store", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let collector = Collector::open(&path).unwrap();
+
+        for (name, pid) in [("api", 10), ("worker\tb", 11)] {
+            let identity = ProcessIdentity { host: "node-1".to_string(), pid, name: name.to_string() };
+            let mut sink = StreamSink::new(Vec::new(), &identity).unwrap();
+            sink.write(&marker(1, "balance", EventKind::Unwatched)).unwrap();
+            sink.flush().unwrap();
+            let bytes = sink.out.into_inner().map_err(|e| e.to_string()).unwrap();
+            assert_eq!(collector.ingest(bytes.as_slice()).unwrap(), 2);
+        }
+        assert!(collector.ingest("HELLO\n".as_bytes()).is_err());
+
+        let (tx, failed) = std::sync::mpsc::channel();
+        let tx = Mutex::new(tx);
+        let reporting = collector.clone().with_error_handler(move |peer, e| tx.lock().unwrap().send(format!("{}: {}", peer, e)).unwrap());
+        reporting.serve_streams(std::iter::once(Ok(("HELLO\n".as_bytes(), "10.0.0.2:4000".to_string())))).unwrap();
+        let failure = failed.recv_timeout(std::time::Duration::from_secs(5)).unwrap();
+        assert!(failure.starts_with("10.0.0.2:4000: Bad hello"));
+
+        let all = read_store(&path, None).unwrap();
+        assert_eq!(all.len(), 4);
+        assert_eq!(all[3].process.name, "worker b");
+        assert!(all[0].event_json.starts_with("{\"process\":"));
+        assert!(all[1].event_json.contains("\"kind\":\"unwatched\""));
+        let api = read_store(&path, Some("api")).unwrap();
+        assert_eq!((api.len(), api[1].process.pid), (2, 10));
+        assert!(api[1].to_json().starts_with("{\"host\":\"node-1\",\"pid\":10,\"name\":\"api\",\"event\":{"));
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,270 @@
+// Count-only watching
+//
+// For workloads where "how often" matters and payloads are too expensive.
+// A count-only region is split into page-sized chunks, each with its own
+// counter, and no previews, values or events of it reach the caller.
+//
+// Where a backend observes individual writes, each chunk is watched there:
+// with a hardware breakpoint when it fits one (no page protection, so hot
+// neighbours do not fault), else by the Protect-mode backend without values.
+// The events of those watches never leave the check that drains them; each
+// adds its write_count to the chunk's counter, so several writes between
+// two checks count as several.
+//
+// In Snapshot mode nothing observes single writes: each chunk keeps a 64-bit
+// fingerprint, and every check bumps the counter of chunks whose fingerprint
+// changed. Such a counter counts checks that observed a write, not stores.
+
+use std::collections::HashMap;
+
+use crate::{ChangeEvent, EventKind};
+
+/// Count-only ids live in the upper half so they never collide with ids
+/// handed out by the native core or the snapshot backend
+const COUNT_ONLY_ID_BASE: u32 = 1 << 31;
+
+/// Write counters of one count-only region
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteCount {
+    pub region_id: u32,
+    pub name: String,
+    /// Sum over all pages
+    pub total: u64,
+    /// One counter per page touched by the region, in address order
+    pub pages: Vec<u64>,
+}
+
+struct CountedRegion {
+    name: String,
+    /// (start, len) of each page-sized chunk
+    chunks: Vec<(usize, usize)>,
+    /// Backend region of each chunk; empty when fingerprinted instead
+    backed: Vec<u32>,
+    fingerprints: Vec<u64>,
+    counts: Vec<u64>,
+}
+
+#[derive(Default)]
+pub(crate) struct CountingRegions {
+    next_region_id: u32,
+    regions: HashMap<u32, CountedRegion>,
+    /// Backend region id -> (count-only id, chunk)
+    backing: HashMap<u32, (u32, usize)>,
+}
+
+/// (start, len) of the page-sized chunks of `buffer`
+pub(crate) fn chunks(buffer: &[u8], page_size: usize) -> Vec<(usize, usize)> {
+    let addr = buffer.as_ptr() as usize;
+    let end = addr + buffer.len();
+    let mut chunks = Vec::new();
+    let mut start = addr;
+    while start < end {
+        let page_end = (start / page_size + 1) * page_size;
+        let chunk_end = page_end.min(end);
+        chunks.push((start, chunk_end - start));
+        start = chunk_end;
+    }
+    chunks
+}
+
+impl CountingRegions {
+    pub(crate) fn is_count_only(region_id: u32) -> bool {
+        region_id >= COUNT_ONLY_ID_BASE
+    }
+
+    /// Count writes to `chunks`, each watched by the backend region at the
+    /// same index of `backed`, or fingerprinted when `backed` is empty
+    pub(crate) fn watch(&mut self, name: &str, chunks: Vec<(usize, usize)>, backed: Vec<u32>) -> u32 {
+        let fingerprints = chunks.iter().map(|&(start, len)| fingerprint(start, len)).collect();
+        self.next_region_id += 1;
+        let region_id = COUNT_ONLY_ID_BASE + self.next_region_id;
+        for (chunk, &backend_id) in backed.iter().enumerate() {
+            self.backing.insert(backend_id, (region_id, chunk));
+        }
+        self.regions.insert(
+            region_id,
+            CountedRegion {
+                name: name.to_string(),
+                counts: vec![0; chunks.len()],
+                chunks,
+                backed,
+                fingerprints,
+            },
+        );
+        region_id
+    }
+
+    /// Forget a region; returns the backend regions of its chunks, to be
+    /// unwatched there, or None when it is not watched
+    pub(crate) fn unwatch(&mut self, region_id: u32) -> Option<Vec<u32>> {
+        let region = self.regions.remove(&region_id)?;
+        for backend_id in &region.backed {
+            self.backing.remove(backend_id);
+        }
+        Some(region.backed)
+    }
+
+    /// Ids of every region
+    pub(crate) fn ids(&self) -> Vec<u32> {
+        let mut ids: Vec<u32> = self.regions.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// (backend id, start, len, name) of chunks the backend watches, e.g. to
+    /// watch them again after a restart
+    pub(crate) fn backed_chunks(&self) -> Vec<(u32, usize, usize, String)> {
+        self.regions
+            .values()
+            .flat_map(|region| {
+                region.backed.iter().zip(&region.chunks).map(|(&backend_id, &(start, len))| (backend_id, start, len, region.name.clone()))
+            })
+            .collect()
+    }
+
+    /// Chunk watches came back under new backend ids (old -> new)
+    pub(crate) fn rebind(&mut self, moved: &HashMap<u32, u32>) {
+        self.backing.clear();
+        for (&region_id, region) in self.regions.iter_mut() {
+            for (chunk, backend_id) in region.backed.iter_mut().enumerate() {
+                *backend_id = moved.get(backend_id).copied().unwrap_or(*backend_id);
+                self.backing.insert(*backend_id, (region_id, chunk));
+            }
+        }
+    }
+
+    /// Add `writes` to the chunk the backend watches as `backend_id`; false
+    /// when that is no count-only chunk
+    pub(crate) fn record(&mut self, backend_id: u32, writes: u64) -> bool {
+        let Some(&(region_id, chunk)) = self.backing.get(&backend_id) else {
+            return false;
+        };
+        if let Some(region) = self.regions.get_mut(&region_id) {
+            region.counts[chunk] += writes;
+        }
+        true
+    }
+
+    /// Count the changes of count-only chunks and take them out of `events`
+    pub(crate) fn count(&mut self, events: &mut Vec<ChangeEvent>) {
+        if self.backing.is_empty() {
+            return;
+        }
+        events.retain(|event| event.kind != EventKind::Change || !self.record(event.region_id, event.write_count as u64));
+    }
+
+    pub(crate) fn reset_counts(&mut self) {
+        for region in self.regions.values_mut() {
+            region.counts.iter_mut().for_each(|c| *c = 0);
+        }
+    }
+
+    /// Compare fingerprints and bump the counters of changed pages
+    pub(crate) fn tick(&mut self) {
+        for region in self.regions.values_mut().filter(|region| region.backed.is_empty()) {
+            for (i, &(start, len)) in region.chunks.iter().enumerate() {
+                let current = fingerprint(start, len);
+                if current != region.fingerprints[i] {
+                    region.fingerprints[i] = current;
+                    region.counts[i] += 1;
+                }
+            }
+        }
+    }
+
+    /// (region_id, start, len, writes) of every page-sized chunk
+    pub(crate) fn chunks(&self) -> Vec<(u32, usize, usize, u64)> {
+        self.regions
+            .iter()
+            .flat_map(|(&region_id, region)| {
+                region.chunks.iter().zip(&region.counts).map(move |(&(start, len), &count)| (region_id, start, len, count))
+            })
+            .collect()
+    }
+
+    pub(crate) fn counts(&self) -> Vec<WriteCount> {
+        let mut counts: Vec<WriteCount> = self
+            .regions
+            .iter()
+            .map(|(&region_id, region)| WriteCount {
+                region_id,
+                name: region.name.clone(),
+                total: region.counts.iter().sum(),
+                pages: region.counts.clone(),
+            })
+            .collect();
+        counts.sort_by_key(|c| c.region_id);
+        counts
+    }
+}
+
+/// FNV-1a over the live bytes
+fn fingerprint(start: usize, len: usize) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for i in 0..len {
+        let byte = unsafe { std::ptr::read_volatile((start + i) as *const u8) };
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_per_page() {
+        let mut data = vec![0u8; 64];
+        let mut regions = CountingRegions::default();
+        let id = regions.watch("hits", chunks(&data, 32), Vec::new());
+        assert!(CountingRegions::is_count_only(id));
+
+        regions.tick();
+        data[0] = 1;
+        regions.tick();
+        data[0] = 2;
+        data[1] = 2;
+        regions.tick();
+
+        let counts = &regions.counts()[0];
+        assert_eq!(counts.total, 2);
+        assert_eq!(counts.pages.iter().filter(|&&c| c == 2).count(), 1);
+    }
+
+    #[cfg(not(any(feature = "native", feature = "pure")))]
+    #[test]
+    fn test_backed_chunks_count_every_write() {
+        use crate::capabilities::WatchMode;
+        use crate::fake_native::{self, FakeEvent};
+        use crate::MemWatch;
+
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Protect;
+        let data = vec![0u8; 16];
+        let id = watcher.watch_count_only(&data, "hits").unwrap();
+        let backend_id = fake_native::state().next_region_id;
+        let total = |watcher: &MemWatch| watcher.write_counts().iter().find(|c| c.region_id == id).map(|c| c.total);
+
+        // Three writes between two checks count three times, and none of
+        // them is handed out
+        fake_native::state().events = (0..3).map(|_| FakeEvent::change(backend_id, &[1])).collect();
+        let events = watcher.check_changes().unwrap();
+        assert!(events.iter().all(|e| e.region_id != backend_id));
+        assert_eq!(total(&watcher), Some(3));
+        fake_native::state().events = vec![FakeEvent::change(backend_id, &[2])];
+        watcher.check_changes().unwrap();
+        assert_eq!(total(&watcher), Some(4));
+
+        // Through the callback as well
+        watcher.set_callback(Some(|e: &ChangeEvent| panic!("count-only change {} delivered", e.region_id))).unwrap();
+        assert!(fake_native::fire(FakeEvent::change(backend_id, &[3])));
+        assert!(fake_native::fire(FakeEvent::change(backend_id, &[4])));
+        assert_eq!(total(&watcher), Some(6));
+        assert!(watcher.check_changes().unwrap().iter().all(|e| !matches!(e.kind, EventKind::CallbackPanicked { .. })));
+
+        assert_eq!(watcher.unwatch(id), Ok(crate::Unwatch::Removed));
+        assert_eq!(total(&watcher), None);
+    }
+}
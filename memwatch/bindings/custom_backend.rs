@@ -0,0 +1,224 @@
+// Third-party backends
+//
+// WatchBackend lets a platform-specific or simulated source of writes (an
+// emulator's memory bus, a remote target, a test double) stand in for the
+// native and snapshot backends without forking the crate:
+//
+//   let watcher = MemWatch::builder().backend(GuestMemory::new(&machine)).build()?;
+//   let id = watcher.watch(&guest_ram[0x4000..0x4010], "vram")?;
+//
+// The bus module has a ready-made one for emulators.
+//
+// The whole Rust pipeline (sampling, filters, hooks, sequencing, callbacks,
+// subscriptions, sinks) runs on top of it as for the built-in backends.
+// Regions watched with RegionBackend::HardwareBreakpoint still go to the
+// hardware backend. A custom backend is polled, never page-protected:
+// watch mode is Snapshot and set_callback() closures are not called (use
+// watch_with_callback() or subscribe()).
+//
+// Region ids are the backend's own and must stay below 1 << 30, where the
+// hardware backend's ids start; watches returning others are undone and
+// fail. change_event() builds the events drain()
+// returns; the pipeline fills in sequence numbers, allocation sites and
+// deltas.
+
+use crate::backend::Backend;
+use crate::error::{CheckError, NativeError};
+use crate::hwbreak::HW_ID_BASE;
+use crate::lifecycle::marker;
+use crate::polling::PREVIEW_SIZE;
+use crate::{ChangeEvent, EventKind, Stats};
+
+/// A source of change events; see the custom_backend module
+pub trait WatchBackend: Send + Sync {
+    /// Start watching `buffer`; returns a region id below 1 << 30.
+    /// `max_value_bytes` is the value limit of the watch (0 = no values,
+    /// -1 = full values).
+    fn watch(&self, buffer: &[u8], name: &str, max_value_bytes: i32) -> Result<u32, String>;
+
+    fn unwatch(&self, region_id: u32) -> Result<(), String>;
+
+    /// Take up to `max_events` pending changes, oldest first
+    fn drain(&self, max_events: usize) -> Result<Vec<ChangeEvent>, String>;
+
+    fn stats(&self) -> Result<Stats, String>;
+}
+
+/// A change of `region_id` from `old` to `new`, with previews, for
+/// WatchBackend::drain()
+pub fn change_event(region_id: u32, name: &str, old: &[u8], new: &[u8]) -> ChangeEvent {
+    let mut event = marker(region_id, name, EventKind::Change);
+    event.tid = None;
+    event.old_preview = old[..old.len().min(PREVIEW_SIZE)].to_vec();
+    event.new_preview = new[..new.len().min(PREVIEW_SIZE)].to_vec();
+    event.old_value = old.to_vec();
+    event.new_value = new.to_vec();
+    event
+}
+
+/// Adapts a WatchBackend to the internal backend interface
+pub(crate) struct CustomBackend(pub(crate) Box<dyn WatchBackend>);
+
+impl Backend for CustomBackend {
+    fn init(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn shutdown(&self) {}
+
+    fn watch(&self, buffer: &[u8], name: &str, max_value_bytes: i32) -> Result<u32, String> {
+        let region_id = self.0.watch(buffer, name, max_value_bytes)?;
+        if region_id >= HW_ID_BASE {
+            let _ = self.0.unwatch(region_id);
+            return Err(format!("WatchBackend returned region id {}, at or above {}", region_id, HW_ID_BASE));
+        }
+        Ok(region_id)
+    }
+
+    fn unwatch(&self, region_id: u32) -> Result<(), NativeError> {
+        // NativeError has no room for a message; the backend reports its own
+        self.0.unwatch(region_id).map_err(|_| NativeError { call: "WatchBackend::unwatch", code: -1 })
+    }
+
+    fn poll(&self, max_events: usize) -> Result<Vec<ChangeEvent>, CheckError> {
+        self.0.drain(max_events).map_err(CheckError::Pipeline)
+    }
+
+    fn stats(&self) -> Result<Stats, String> {
+        self.0.stats()
+    }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::fake_native;
+    use crate::MemWatch;
+
+    /// Simulated memory bus: 64 bytes of guest RAM, addressed by offset
+    #[derive(Default)]
+    struct Bus {
+        ram: Mutex<Vec<u8>>,
+        /// Region id -> (name, offset, length)
+        regions: Mutex<HashMap<u32, (String, usize, usize)>>,
+        pending: Mutex<VecDeque<ChangeEvent>>,
+        writes: Mutex<u64>,
+    }
+
+    impl Bus {
+        fn write(&self, offset: usize, bytes: &[u8]) {
+            let mut ram = self.ram.lock().unwrap();
+            let old = ram.clone();
+            ram[offset..offset + bytes.len()].copy_from_slice(bytes);
+            *self.writes.lock().unwrap() += 1;
+            for (&id, (name, start, len)) in self.regions.lock().unwrap().iter() {
+                if offset < start + len && *start < offset + bytes.len() {
+                    let (old, new) = (&old[*start..start + len], &ram[*start..start + len]);
+                    self.pending.lock().unwrap().push_back(change_event(id, name, old, new));
+                }
+            }
+        }
+    }
+
+    struct RamBackend(Arc<Bus>);
+
+    impl WatchBackend for RamBackend {
+        fn watch(&self, buffer: &[u8], name: &str, _max_value_bytes: i32) -> Result<u32, String> {
+            let ram = self.0.ram.lock().unwrap();
+            let offset = (buffer.as_ptr() as usize).checked_sub(ram.as_ptr() as usize).filter(|&o| o + buffer.len() <= ram.len());
+            let offset = offset.ok_or("Buffer is not in guest RAM")?;
+            let mut regions = self.0.regions.lock().unwrap();
+            let id = regions.len() as u32 + 1;
+            regions.insert(id, (name.to_string(), offset, buffer.len()));
+            Ok(id)
+        }
+
+        fn unwatch(&self, region_id: u32) -> Result<(), String> {
+            self.0.regions.lock().unwrap().remove(&region_id).map(|_| ()).ok_or_else(|| format!("Unknown region {}", region_id))
+        }
+
+        fn drain(&self, max_events: usize) -> Result<Vec<ChangeEvent>, String> {
+            let mut pending = self.0.pending.lock().unwrap();
+            let n = pending.len().min(max_events);
+            Ok(pending.drain(..n).collect())
+        }
+
+        fn stats(&self) -> Result<Stats, String> {
+            Ok(Stats {
+                num_tracked_regions: self.0.regions.lock().unwrap().len() as u32,
+                num_active_watchpoints: 0,
+                total_events: *self.0.writes.lock().unwrap(),
+                ring_write_count: 0,
+                ring_drop_count: 0,
+                storage_bytes_used: 0,
+                mprotect_page_count: 0,
+                worker_thread_id: 0,
+                worker_cycles: 0,
+                sampled_out_count: 0,
+                consumers: Vec::new(),
+                events_per_thread: Vec::new(),
+            })
+        }
+    }
+
+    #[test]
+    fn test_pipeline_runs_on_a_custom_backend() {
+        let _guard = fake_native::lock();
+        let bus = Arc::new(Bus { ram: Mutex::new(vec![0u8; 64]), ..Bus::default() });
+        let watcher = MemWatch::builder().backend(RamBackend(Arc::clone(&bus))).build().unwrap();
+        let vram = {
+            let ram = bus.ram.lock().unwrap();
+            // SAFETY: the RAM vector is never reallocated
+            unsafe { std::slice::from_raw_parts(ram.as_ptr().add(16), 8) }
+        };
+        let id = watcher.watch(vram, "vram").unwrap();
+        watcher.check_changes().unwrap();
+
+        bus.write(0, &[1, 2]);
+        bus.write(18, &[0xab]);
+        let events = watcher.check_changes().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].region_id, events[0].kind, events[0].variable_name.as_deref()), (id, EventKind::Change, Some("vram")));
+        assert_eq!(events[0].new_preview, [0, 0, 0xab, 0, 0, 0, 0, 0]);
+        assert_eq!(events[0].deltas.len(), 1);
+        assert_eq!(watcher.get_stats().unwrap().total_events, 2);
+
+        watcher.unwatch(id).unwrap();
+        assert!(bus.regions.lock().unwrap().is_empty());
+        assert!(watcher.watch(&[0u8; 4], "outside").is_err());
+    }
+
+    /// Hands out ids from the hardware backend's range
+    struct HighIds(Arc<Mutex<Vec<u32>>>);
+
+    impl WatchBackend for HighIds {
+        fn watch(&self, _buffer: &[u8], _name: &str, _max_value_bytes: i32) -> Result<u32, String> {
+            self.0.lock().unwrap().push(HW_ID_BASE);
+            Ok(HW_ID_BASE)
+        }
+
+        fn unwatch(&self, region_id: u32) -> Result<(), String> {
+            self.0.lock().unwrap().retain(|&id| id != region_id);
+            Ok(())
+        }
+
+        fn drain(&self, _max_events: usize) -> Result<Vec<ChangeEvent>, String> {
+            Ok(Vec::new())
+        }
+
+        fn stats(&self) -> Result<Stats, String> {
+            Err("no stats".to_string())
+        }
+    }
+
+    #[test]
+    fn test_ids_in_the_hardware_range_are_rejected() {
+        let watched = Arc::new(Mutex::new(Vec::new()));
+        let backend = CustomBackend(Box::new(HighIds(Arc::clone(&watched))));
+        assert!(backend.watch(&[0u8; 4], "high", 0).unwrap_err().contains("region id"));
+        assert!(watched.lock().unwrap().is_empty());
+    }
+}
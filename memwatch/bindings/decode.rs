@@ -0,0 +1,310 @@
+// Structured preview decoding
+//
+// Regions that hold serialized messages can be given a PreviewDecoder, so
+// reports and viewers show fields instead of hex. Decoders are registered
+// programmatically or loaded from a shared library exporting:
+//
+//   const char *memwatch_decoder_name(void);
+//   long memwatch_decode(const uint8_t *bytes, size_t len,
+//                        char *out, size_t out_cap);
+//
+// memwatch_decode writes "<field>=<value>" lines (UTF-8) into `out` and
+// returns their length, or -1 when the bytes do not decode. Loaded libraries
+// stay loaded for the life of the process.
+//
+// Built in: ProtobufWireDecoder (schema-less protobuf wire format) and
+// TlvDecoder (fixed-width tag/length headers).
+
+use std::collections::HashMap;
+#[cfg(unix)]
+use std::ffi::{CStr, CString};
+#[cfg(unix)]
+use std::os::raw::c_void;
+use std::os::raw::{c_char, c_long};
+use std::sync::Arc;
+
+use crate::export::hex;
+use crate::ChangeEvent;
+
+/// One decoded field, e.g. ("2", "varint 150")
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedField {
+    pub name: String,
+    pub value: String,
+}
+
+/// Decoding of both sides of a change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedChange {
+    pub decoder: String,
+    /// None when that side does not decode (e.g. a half-written message)
+    pub old: Option<Vec<DecodedField>>,
+    pub new: Option<Vec<DecodedField>>,
+}
+
+/// Turns region bytes into fields
+pub trait PreviewDecoder: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// None when `bytes` are not a valid encoding
+    fn decode(&self, bytes: &[u8]) -> Option<Vec<DecodedField>>;
+}
+
+#[derive(Default)]
+pub(crate) struct DecoderRegistry {
+    decoders: HashMap<String, Arc<dyn PreviewDecoder>>,
+    regions: HashMap<u32, Arc<dyn PreviewDecoder>>,
+}
+
+impl DecoderRegistry {
+    /// Register a decoder; replaces one of the same name
+    pub(crate) fn add(&mut self, decoder: Arc<dyn PreviewDecoder>) -> String {
+        let name = decoder.name().to_string();
+        self.decoders.insert(name.clone(), decoder);
+        name
+    }
+
+    pub(crate) fn assign(&mut self, region_id: u32, decoder: &str) -> Result<(), String> {
+        let decoder = self.decoders.get(decoder).ok_or_else(|| format!("Unknown decoder {}", decoder))?;
+        self.regions.insert(region_id, decoder.clone());
+        Ok(())
+    }
+
+    pub(crate) fn forget(&mut self, region_id: u32) {
+        self.regions.remove(&region_id);
+    }
+
+    /// Full values when captured, previews otherwise
+    pub(crate) fn decode(&self, event: &ChangeEvent) -> Option<DecodedChange> {
+        let decoder = self.regions.get(&event.region_id)?;
+        let old = if event.old_value.is_empty() { &event.old_preview } else { &event.old_value };
+        let new = if event.new_value.is_empty() { &event.new_preview } else { &event.new_value };
+        Some(DecodedChange {
+            decoder: decoder.name().to_string(),
+            old: decoder.decode(old),
+            new: decoder.decode(new),
+        })
+    }
+}
+
+/// Protobuf wire format without a schema: fields are named by number and
+/// shown by wire type; repeated numbers repeat
+pub struct ProtobufWireDecoder;
+
+impl PreviewDecoder for ProtobufWireDecoder {
+    fn name(&self) -> &str {
+        "protobuf"
+    }
+
+    fn decode(&self, mut bytes: &[u8]) -> Option<Vec<DecodedField>> {
+        let mut fields = Vec::new();
+        while !bytes.is_empty() {
+            let key = read_varint(&mut bytes)?;
+            let value = match key & 7 {
+                0 => format!("varint {}", read_varint(&mut bytes)?),
+                1 => format!("fixed64 {}", u64::from_le_bytes(take(&mut bytes, 8)?.try_into().ok()?)),
+                2 => {
+                    let len = usize::try_from(read_varint(&mut bytes)?).ok()?;
+                    let payload = take(&mut bytes, len)?;
+                    match std::str::from_utf8(payload) {
+                        Ok(text) if !text.chars().any(char::is_control) => format!("{:?}", text),
+                        _ => format!("bytes {}", hex(payload)),
+                    }
+                }
+                5 => format!("fixed32 {}", u32::from_le_bytes(take(&mut bytes, 4)?.try_into().ok()?)),
+                _ => return None,
+            };
+            let number = key >> 3;
+            if number == 0 {
+                return None;
+            }
+            fields.push(DecodedField {
+                name: number.to_string(),
+                value,
+            });
+        }
+        Some(fields)
+    }
+}
+
+/// Tag-length-value records with big-endian tag and length fields of
+/// `tag_bytes` and `len_bytes` (1 to 8); zero padding after the last record
+/// is accepted
+pub struct TlvDecoder {
+    pub tag_bytes: usize,
+    pub len_bytes: usize,
+}
+
+impl PreviewDecoder for TlvDecoder {
+    fn name(&self) -> &str {
+        "tlv"
+    }
+
+    fn decode(&self, mut bytes: &[u8]) -> Option<Vec<DecodedField>> {
+        let mut fields = Vec::new();
+        while !bytes.is_empty() && bytes.iter().any(|&b| b != 0) {
+            let tag = read_be(take(&mut bytes, self.tag_bytes)?)?;
+            let len = usize::try_from(read_be(take(&mut bytes, self.len_bytes)?)?).ok()?;
+            fields.push(DecodedField {
+                name: tag.to_string(),
+                value: hex(take(&mut bytes, len)?),
+            });
+        }
+        Some(fields)
+    }
+}
+
+/// Decoder implemented by a shared library (see the module comment)
+pub(crate) struct LibraryDecoder {
+    name: String,
+    decode: unsafe extern "C" fn(*const u8, usize, *mut c_char, usize) -> c_long,
+}
+
+impl LibraryDecoder {
+    /// Output buffer for one decoding
+    const OUT_CAP: usize = 64 * 1024;
+
+    #[cfg(unix)]
+    pub(crate) fn load(path: &str) -> Result<Self, String> {
+        let c_path = CString::new(path).map_err(|_| format!("Invalid library path {}", path))?;
+        // SAFETY: dlopen with a valid C string; the handle is never closed
+        let handle = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if handle.is_null() {
+            return Err(format!("Failed to load decoder {}: {}", path, dl_error()));
+        }
+        let symbol = |name: &CStr| {
+            // SAFETY: handle is a live library handle
+            let ptr = unsafe { libc::dlsym(handle, name.as_ptr()) };
+            if ptr.is_null() {
+                Err(format!("Decoder {} lacks {}", path, name.to_string_lossy()))
+            } else {
+                Ok(ptr)
+            }
+        };
+        let name_fn = symbol(c"memwatch_decoder_name")?;
+        let decode_fn = symbol(c"memwatch_decode")?;
+        // SAFETY: the symbols follow the documented signatures
+        let (name_fn, decode) = unsafe {
+            (
+                std::mem::transmute::<*mut c_void, unsafe extern "C" fn() -> *const c_char>(name_fn),
+                std::mem::transmute::<*mut c_void, unsafe extern "C" fn(*const u8, usize, *mut c_char, usize) -> c_long>(
+                    decode_fn,
+                ),
+            )
+        };
+        // SAFETY: the library returns a static NUL-terminated name
+        let name = unsafe {
+            let name = name_fn();
+            if name.is_null() {
+                return Err(format!("Decoder {} has no name", path));
+            }
+            CStr::from_ptr(name).to_string_lossy().into_owned()
+        };
+        Ok(LibraryDecoder { name, decode })
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn load(path: &str) -> Result<Self, String> {
+        Err(format!("Failed to load decoder {}: shared libraries are not supported on this target", path))
+    }
+}
+
+impl PreviewDecoder for LibraryDecoder {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<Vec<DecodedField>> {
+        let mut out = vec![0u8; Self::OUT_CAP];
+        // SAFETY: both buffers are valid for the lengths passed
+        let written = unsafe { (self.decode)(bytes.as_ptr(), bytes.len(), out.as_mut_ptr() as *mut c_char, out.len()) };
+        let written = usize::try_from(written).ok().filter(|&n| n <= out.len())?;
+        let text = String::from_utf8_lossy(&out[..written]);
+        Some(
+            text.lines()
+                .filter_map(|line| line.split_once('='))
+                .map(|(name, value)| DecodedField {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(unix)]
+fn dl_error() -> String {
+    // SAFETY: dlerror returns NULL or a valid C string
+    unsafe {
+        let error = libc::dlerror();
+        if error.is_null() {
+            "unknown error".to_string()
+        } else {
+            CStr::from_ptr(error).to_string_lossy().into_owned()
+        }
+    }
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if bytes.len() < len {
+        return None;
+    }
+    let (head, tail) = bytes.split_at(len);
+    *bytes = tail;
+    Some(head)
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *take(bytes, 1)?.first()?;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn read_be(bytes: &[u8]) -> Option<u64> {
+    if bytes.is_empty() || bytes.len() > 8 {
+        return None;
+    }
+    Some(bytes.iter().fold(0, |acc, &b| acc << 8 | u64::from(b)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(pairs: &[(&str, &str)]) -> Option<Vec<DecodedField>> {
+        Some(
+            pairs
+                .iter()
+                .map(|(name, value)| DecodedField {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_builtin_decoders() {
+        // field 1 = 150, field 2 = "hi", field 3 = fixed32 1
+        let message = [0x08, 0x96, 0x01, 0x12, 0x02, b'h', b'i', 0x1d, 1, 0, 0, 0];
+        assert_eq!(
+            ProtobufWireDecoder.decode(&message),
+            fields(&[("1", "varint 150"), ("2", "\"hi\""), ("3", "fixed32 1")])
+        );
+        assert_eq!(ProtobufWireDecoder.decode(&message[..5]), None);
+
+        let tlv = TlvDecoder {
+            tag_bytes: 1,
+            len_bytes: 2,
+        };
+        assert_eq!(tlv.decode(&[7, 0, 2, 0xab, 0xcd, 0, 0]), fields(&[("7", "abcd")]));
+        assert_eq!(tlv.decode(&[7, 0, 9, 0xab]), None);
+        assert!(LibraryDecoder::load("/nonexistent/libdecoder.so").is_err());
+    }
+}
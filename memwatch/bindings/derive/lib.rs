@@ -0,0 +1,54 @@
+// #[derive(Watchable)]
+//
+// Lists a struct's fields for memwatch::watchable::Watchable, with offsets
+// from core::mem::offset_of!. Tuple struct fields are named by index.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Index, Member};
+
+#[proc_macro_derive(Watchable)]
+pub fn derive_watchable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return syn::Error::new_spanned(&input.ident, "Watchable can only be derived for structs")
+                .to_compile_error()
+                .into()
+        }
+    };
+
+    let entries = match fields {
+        Fields::Named(named) => named.named.iter().map(|f| (Member::Named(f.ident.clone().unwrap()), &f.ty)).collect(),
+        Fields::Unnamed(unnamed) => unnamed
+            .unnamed
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (Member::Unnamed(Index::from(i)), &f.ty))
+            .collect(),
+        Fields::Unit => Vec::new(),
+    };
+    let infos = entries.iter().map(|(member, ty)| {
+        let name = match member {
+            Member::Named(ident) => ident.to_string(),
+            Member::Unnamed(index) => index.index.to_string(),
+        };
+        quote! {
+            ::memwatch::watchable::FieldInfo {
+                name: #name,
+                offset: ::core::mem::offset_of!(Self, #member),
+                size: ::core::mem::size_of::<#ty>(),
+            }
+        }
+    });
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::memwatch::watchable::Watchable for #ident #ty_generics #where_clause {
+            const FIELDS: &'static [::memwatch::watchable::FieldInfo] = &[#(#infos),*];
+        }
+    }
+    .into()
+}
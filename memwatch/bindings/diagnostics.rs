@@ -0,0 +1,161 @@
+// Initialization diagnostics and end-to-end self-test
+
+use std::alloc::{alloc_zeroed, dealloc, Layout};
+use std::time::{Duration, Instant};
+
+use crate::capabilities::{probe_mprotect, seccomp_mode};
+use crate::capabilities::WatchMode;
+use crate::{EventKind, MemWatch};
+
+/// Reason the environment cannot support watching
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SelfTestIssue {
+    /// SIGSEGV disposition changed since init (another library replaced the handler)
+    SignalHandlerConflict { installed: usize, current: usize },
+    /// mprotect on a scratch page failed (EPERM usually means a sandbox policy)
+    MprotectDenied { errno: i32 },
+    /// Process runs under seccomp (1 = strict, 2 = filter)
+    Seccomp { mode: u32 },
+    /// The native layer refused the scratch region
+    WatchFailed(String),
+    /// Polling the native layer failed
+    PollFailed(String),
+    /// The write went through but no event arrived before the deadline
+    NoEventObserved { waited: Duration },
+}
+
+/// Structured result of `MemWatch::self_test()`
+#[derive(Debug, Clone)]
+pub struct SelfTestReport {
+    pub passed: bool,
+    /// Time from the scratch write until the event was polled
+    pub latency: Option<Duration>,
+    pub issues: Vec<SelfTestIssue>,
+}
+
+const SELF_TEST_TIMEOUT: Duration = Duration::from_millis(500);
+
+impl MemWatch {
+    /// Watch a scratch page, write to it and verify the event arrives end-to-end
+    /// (fault -> ring -> FFI -> Rust). Environment problems found on the way are
+    /// reported instead of crashing on the first watched write.
+    pub fn self_test(&self) -> SelfTestReport {
+        let mut issues = Vec::new();
+
+        if let Some(mode) = seccomp_mode() {
+            issues.push(SelfTestIssue::Seccomp { mode });
+        }
+        let current = current_segv_handler();
+        // Read tracking chains to the handler it replaced
+        if current != self.segv_handler && current != crate::access::handler_address() {
+            issues.push(SelfTestIssue::SignalHandlerConflict {
+                installed: self.segv_handler,
+                current,
+            });
+        }
+
+        let page = self.capabilities.page_size;
+        let layout = Layout::from_size_align(page, page).expect("page size is a power of two");
+        let scratch = unsafe { alloc_zeroed(layout) };
+        if scratch.is_null() {
+            issues.push(SelfTestIssue::WatchFailed("scratch page allocation failed".to_string()));
+            return SelfTestReport { passed: false, latency: None, issues };
+        }
+
+        if let Err(errno) = probe_mprotect(scratch, page) {
+            issues.push(SelfTestIssue::MprotectDenied { errno });
+        }
+
+        // Writing to a protected page without a working handler would kill the
+        // process, so only run the live round-trip when the probes are clean.
+        // Snapshot mode never protects pages and is always safe to exercise.
+        let blocking = self.capabilities.mode == WatchMode::Protect
+            && issues.iter().any(|i| matches!(i, SelfTestIssue::SignalHandlerConflict { .. } | SelfTestIssue::MprotectDenied { .. }));
+        let latency = if blocking {
+            None
+        } else {
+            self.round_trip(scratch, page, &mut issues)
+        };
+
+        unsafe { dealloc(scratch, layout) };
+
+        SelfTestReport {
+            passed: latency.is_some(),
+            latency,
+            issues,
+        }
+    }
+
+    fn round_trip(&self, scratch: *mut u8, len: usize, issues: &mut Vec<SelfTestIssue>) -> Option<Duration> {
+        let buffer = unsafe { std::slice::from_raw_parts(scratch, len) };
+        let region_id = match self.watch(buffer, "memwatch_self_test") {
+            Ok(id) => id,
+            Err(e) => {
+                issues.push(SelfTestIssue::WatchFailed(e));
+                return None;
+            }
+        };
+
+        let written = Instant::now();
+        unsafe { std::ptr::write_volatile(scratch, 0xA5) };
+
+        let mut latency = None;
+        while written.elapsed() < SELF_TEST_TIMEOUT {
+            match self.check_changes() {
+                // The Watched marker comes without any write; only a change
+                // proves the round trip
+                Ok(events) if events.iter().any(|e| e.region_id == region_id && e.kind == EventKind::Change) => {
+                    latency = Some(written.elapsed());
+                    break;
+                }
+                Ok(_) => std::thread::sleep(Duration::from_millis(5)),
+                Err(e) => {
+                    issues.push(SelfTestIssue::PollFailed(e.to_string()));
+                    break;
+                }
+            }
+        }
+        let _ = self.unwatch(region_id);
+
+        if latency.is_none() && !issues.iter().any(|i| matches!(i, SelfTestIssue::PollFailed(_))) {
+            issues.push(SelfTestIssue::NoEventObserved { waited: SELF_TEST_TIMEOUT });
+        }
+        latency
+    }
+}
+
+/// Address of the current SIGSEGV handler (0 = SIG_DFL, 1 = SIG_IGN)
+#[cfg(unix)]
+pub(crate) fn current_segv_handler() -> usize {
+    unsafe {
+        let mut old: libc::sigaction = std::mem::zeroed();
+        if libc::sigaction(libc::SIGSEGV, std::ptr::null(), &mut old) != 0 {
+            return 0;
+        }
+        old.sa_sigaction
+    }
+}
+
+/// No signals to chain with outside unix
+#[cfg(not(unix))]
+pub(crate) fn current_segv_handler() -> usize {
+    0
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::fake_native;
+
+    #[test]
+    fn test_self_test_fails_without_a_change() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        // The fake core registers the page but never reports writes to it
+        watcher.capabilities.mode = WatchMode::Protect;
+        let report = watcher.self_test();
+        assert!(!report.passed);
+        assert_eq!(report.latency, None);
+        assert!(report.issues.iter().any(|i| matches!(i, SelfTestIssue::NoEventObserved { .. })), "{:?}", report.issues);
+    }
+}
@@ -0,0 +1,109 @@
+// Byte-range deltas between old and new values
+//
+// compute_delta() lists the maximal runs of bytes that differ. Values of
+// different lengths differ in every byte past the shorter one. Large values
+// are compared a block at a time: equal blocks (the common case, a write
+// touches a few bytes of a region) are skipped with one slice comparison,
+// which compiles to the platform's vectorized memcmp, and only differing
+// blocks are scanned byte by byte.
+//
+// check_changes() fills ChangeEvent::deltas for every change that carries
+// both old and new values (see watch_with_max_value_bytes()); offsets are
+// relative to the start of those values.
+
+use crate::ChangeEvent;
+
+/// Bytes skipped per comparison while old and new agree
+const BLOCK: usize = 64;
+
+/// `len` changed bytes starting at `offset`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ByteRange {
+    pub offset: usize,
+    pub len: usize,
+}
+
+impl ByteRange {
+    /// One past the last changed byte
+    pub fn end(&self) -> usize {
+        self.offset + self.len
+    }
+}
+
+/// Runs of bytes that differ between `old` and `new`, ascending and
+/// never adjacent
+pub fn compute_delta(old: &[u8], new: &[u8]) -> Vec<ByteRange> {
+    let common = old.len().min(new.len());
+    let mut ranges: Vec<ByteRange> = Vec::new();
+    let mut push = |offset: usize, len: usize| match ranges.last_mut() {
+        Some(last) if last.end() == offset => last.len += len,
+        _ => ranges.push(ByteRange { offset, len }),
+    };
+
+    let mut start = 0;
+    while start < common {
+        let end = (start + BLOCK).min(common);
+        if old[start..end] != new[start..end] {
+            let mut run: Option<usize> = None;
+            for i in start..end {
+                match (old[i] != new[i], run) {
+                    (true, None) => run = Some(i),
+                    (false, Some(from)) => {
+                        push(from, i - from);
+                        run = None;
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(from) = run {
+                push(from, end - from);
+            }
+        }
+        start = end;
+    }
+    if old.len() != new.len() {
+        push(common, old.len().max(new.len()) - common);
+    }
+    ranges
+}
+
+/// Fill in deltas for changes carrying both values
+pub(crate) fn annotate(events: &mut [ChangeEvent]) {
+    for event in events.iter_mut() {
+        if !event.kind.is_marker() && !event.old_value.is_empty() && !event.new_value.is_empty() {
+            event.deltas = compute_delta(&event.old_value, &event.new_value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(offset: usize, len: usize) -> ByteRange {
+        ByteRange { offset, len }
+    }
+
+    #[test]
+    fn test_delta_runs_across_blocks_and_lengths() {
+        assert_eq!(compute_delta(b"same", b"same"), []);
+        assert_eq!(compute_delta(b"abcdef", b"aXYdeZ"), [range(1, 2), range(5, 1)]);
+        assert_eq!(compute_delta(b"abc", b"abcde"), [range(3, 2)]);
+        assert_eq!(compute_delta(b"abcde", b"abX"), [range(2, 3)]);
+
+        // A run spanning a block boundary stays one range
+        let old = vec![0u8; 4096];
+        let mut new = old.clone();
+        new[BLOCK - 2..BLOCK + 3].fill(1);
+        new[3000] = 7;
+        assert_eq!(compute_delta(&old, &new), [range(BLOCK - 2, 5), range(3000, 1)]);
+
+        // Agrees with a plain byte-by-byte comparison
+        let old: Vec<u8> = (0..1000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let new: Vec<u8> = old.iter().enumerate().map(|(i, &b)| if i % 97 < 3 || i % 130 == 0 { !b } else { b }).collect();
+        let expected: Vec<usize> = (0..1000).filter(|&i| old[i] != new[i]).collect();
+        let covered: Vec<usize> = compute_delta(&old, &new).iter().flat_map(|r| r.offset..r.end()).collect();
+        assert_eq!(covered, expected);
+    }
+}
@@ -0,0 +1,270 @@
+// Shared dispatch pool for expensive consumers
+//
+// By default every Delivery::Worker subscriber gets a thread of its own.
+// With a pool configured on the builder, they share `workers` threads
+// instead:
+//
+//   let watcher = MemWatch::builder().dispatch(DispatchConfig { workers: 4, queue: 4096 }).build()?;
+//   watcher.subscribe(None, Delivery::Worker, move |event| exporter.send(event))?;
+//
+// Each consumer keeps its own queue of up to `queue` events and runs on at
+// most one worker at a time, so it sees its events in order while different
+// consumers run concurrently. check_changes() never waits for a consumer:
+// an event arriving at a full queue is dropped for that consumer alone and
+// counted.
+//
+// Stats::consumers reports, per subscriber (inline ones included), events
+// delivered and dropped and the latency from check_changes() handing an
+// event over to the callback returning.
+
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use crate::panics;
+use crate::{CallbackSlot, ChangeEvent, ChangeEventCallback};
+
+/// Pool settings; see MemWatchBuilder::dispatch()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DispatchConfig {
+    /// Threads shared by all worker subscribers
+    pub workers: usize,
+    /// Events waiting per consumer before new ones are dropped
+    pub queue: usize,
+}
+
+impl Default for DispatchConfig {
+    fn default() -> Self {
+        DispatchConfig { workers: 2, queue: 1024 }
+    }
+}
+
+/// Delivery figures of one subscriber
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConsumerStats {
+    /// Id from subscribe()
+    pub subscription: u32,
+    pub delivered: u64,
+    /// Dropped at a full queue
+    pub dropped: u64,
+    pub mean_latency_ns: u64,
+    pub max_latency_ns: u64,
+}
+
+/// Running figures behind ConsumerStats
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    delivered: u64,
+    dropped: u64,
+    total_latency_ns: u128,
+    max_latency_ns: u64,
+}
+
+impl Metrics {
+    /// One delivery of an event handed over at `since`
+    pub(crate) fn delivered(&mut self, since: Instant) {
+        let latency = since.elapsed().as_nanos().min(u64::MAX as u128) as u64;
+        self.delivered += 1;
+        self.total_latency_ns += latency as u128;
+        self.max_latency_ns = self.max_latency_ns.max(latency);
+    }
+
+    pub(crate) fn stats(&self, subscription: u32) -> ConsumerStats {
+        ConsumerStats {
+            subscription,
+            delivered: self.delivered,
+            dropped: self.dropped,
+            mean_latency_ns: self.total_latency_ns.checked_div(self.delivered as u128).unwrap_or(0) as u64,
+            max_latency_ns: self.max_latency_ns,
+        }
+    }
+}
+
+/// A subscriber running on the pool
+pub(crate) struct Consumer {
+    callback: Mutex<ChangeEventCallback>,
+    state: Mutex<ConsumerState>,
+    /// Signalled whenever the consumer goes idle
+    idle: Condvar,
+    metrics: Arc<Mutex<Metrics>>,
+    slot: Arc<CallbackSlot>,
+    /// (failures, removed, message) of panics not yet reported
+    pub(crate) panics: Mutex<Vec<(u32, bool, String)>>,
+}
+
+#[derive(Default)]
+struct ConsumerState {
+    pending: VecDeque<(ChangeEvent, Instant)>,
+    /// Queued on the pool or running
+    scheduled: bool,
+    failures: u32,
+    disabled: bool,
+}
+
+impl Consumer {
+    /// Deliver what is pending, on a pool thread
+    fn run(&self) {
+        let callback = self.callback.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            let (event, since) = {
+                let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+                match state.pending.pop_front().filter(|_| !state.disabled) {
+                    Some(next) => next,
+                    None => {
+                        state.pending.clear();
+                        state.scheduled = false;
+                        self.idle.notify_all();
+                        return;
+                    }
+                }
+            };
+            match panics::guarded(|| (*callback)(&event)) {
+                Ok(()) => self.metrics.lock().unwrap_or_else(|e| e.into_inner()).delivered(since),
+                Err(message) => {
+                    let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+                    state.failures += 1;
+                    state.disabled = panics::exhausted(state.failures, self.slot.failure_limit.load(Ordering::SeqCst));
+                    self.panics.lock().unwrap_or_else(|e| e.into_inner()).push((state.failures, state.disabled, message));
+                }
+            }
+        }
+    }
+
+    pub(crate) fn disabled(&self) -> bool {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).disabled
+    }
+
+    /// Wait until nothing is pending or running, unless called from the
+    /// consumer itself
+    pub(crate) fn wait_idle(&self) {
+        if IN_POOL.with(|flag| flag.get()) {
+            return;
+        }
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        while state.scheduled {
+            state = self.idle.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+thread_local! {
+    /// Set on pool threads
+    static IN_POOL: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// The worker threads and the queue of consumers with pending events
+pub(crate) struct DispatchPool {
+    config: DispatchConfig,
+    ready: Option<Sender<Arc<Consumer>>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl DispatchPool {
+    pub(crate) fn start(config: DispatchConfig) -> Result<DispatchPool, String> {
+        let (ready, consumers) = mpsc::channel::<Arc<Consumer>>();
+        let consumers: Arc<Mutex<Receiver<Arc<Consumer>>>> = Arc::new(Mutex::new(consumers));
+        let threads = (0..config.workers.max(1))
+            .map(|i| {
+                let consumers = Arc::clone(&consumers);
+                thread::Builder::new()
+                    .name(format!("memwatch-dispatch-{}", i))
+                    .spawn(move || {
+                        IN_POOL.with(|flag| flag.set(true));
+                        loop {
+                            let next = consumers.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                            match next {
+                                Ok(consumer) => consumer.run(),
+                                Err(_) => return,
+                            }
+                        }
+                    })
+                    .map_err(|e| format!("Failed to start dispatch thread: {}", e))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(DispatchPool { config, ready: Some(ready), threads })
+    }
+
+    pub(crate) fn consumer(&self, callback: ChangeEventCallback, slot: Arc<CallbackSlot>, metrics: Arc<Mutex<Metrics>>) -> Arc<Consumer> {
+        Arc::new(Consumer {
+            callback: Mutex::new(callback),
+            state: Mutex::new(ConsumerState::default()),
+            idle: Condvar::new(),
+            metrics,
+            slot,
+            panics: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Queue `event` for `consumer`, or count it dropped if the queue is full
+    pub(crate) fn enqueue(&self, consumer: &Arc<Consumer>, event: &ChangeEvent) {
+        let mut state = consumer.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.disabled {
+            return;
+        }
+        if state.pending.len() >= self.config.queue {
+            consumer.metrics.lock().unwrap_or_else(|e| e.into_inner()).dropped += 1;
+            return;
+        }
+        state.pending.push_back((event.clone(), Instant::now()));
+        if !state.scheduled {
+            state.scheduled = true;
+            if let Some(ready) = &self.ready {
+                let _ = ready.send(Arc::clone(consumer));
+            }
+        }
+    }
+}
+
+impl Drop for DispatchPool {
+    /// Let the workers finish what is queued, then stop them
+    fn drop(&mut self) {
+        self.ready.take();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::{marker, EventKind};
+    use std::time::Duration;
+
+    #[test]
+    fn test_pool_keeps_order_and_drops_past_the_queue() {
+        let pool = DispatchPool::start(DispatchConfig { workers: 2, queue: 4 }).unwrap();
+        let (release, gate) = mpsc::channel::<()>();
+        let gate = Mutex::new(gate);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let record = Arc::clone(&seen);
+        let callback: ChangeEventCallback = Box::new(move |event: &ChangeEvent| {
+            let _ = gate.lock().unwrap().recv_timeout(Duration::from_secs(5));
+            record.lock().unwrap().push(event.region_id);
+        });
+        let metrics = Arc::new(Mutex::new(Metrics::default()));
+        let consumer = pool.consumer(callback, Arc::new(CallbackSlot::default()), Arc::clone(&metrics));
+
+        // The first event is taken at once; four more fill the queue
+        for region_id in 1..=7 {
+            pool.enqueue(&consumer, &marker(region_id, "buf", EventKind::Change));
+            if region_id == 1 {
+                while consumer.state.lock().unwrap().pending.len() == 1 {
+                    thread::yield_now();
+                }
+            }
+        }
+        for _ in 0..7 {
+            let _ = release.send(());
+        }
+        consumer.wait_idle();
+        assert_eq!(*seen.lock().unwrap(), [1, 2, 3, 4, 5]);
+        let stats = metrics.lock().unwrap().stats(9);
+        assert_eq!((stats.subscription, stats.delivered, stats.dropped), (9, 5, 2));
+        assert!(stats.max_latency_ns >= stats.mean_latency_ns);
+    }
+}
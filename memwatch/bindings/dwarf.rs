@@ -0,0 +1,369 @@
+// Variable names from debug info (feature `dwarf`)
+//
+// Watching with an empty name names the region after the variable it lies
+// in, read from the DWARF info of the running executable:
+//
+//   let config: &Config = registry.lookup("config");
+//   watcher.watch(as_bytes(config), "")?;   // named "myapp::GLOBAL_CONFIG"
+//
+// Addresses inside a variable get a byte offset ("myapp::TABLE+16");
+// addresses in no variable are named by their address ("0x7f3a...").
+// watch_expr() walks the same info through members and array elements.
+//
+// Only statics are indexed: locals are described relative to their frame,
+// which is gone (or elsewhere) by the time a pointer reaches watch(), and
+// heap blocks have no DWARF name at all (see watch_tagged() for those).
+// The index covers the main executable, not shared libraries, and is built
+// on first use: one pass over .debug_info, kept for the life of the
+// process. A stripped binary or one built without debug info resolves
+// nothing. Linux only (ELF, /proc/self/exe).
+
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+use gimli::{AttributeValue, EndianSlice, Operation, RunTimeEndian, Unit};
+use object::{Object, ObjectSection};
+
+use crate::expr::Step;
+use crate::process;
+
+/// A static variable as placed in this process
+#[derive(Debug, Clone)]
+struct Variable {
+    addr: u64,
+    size: u64,
+    /// Qualified with its namespaces, e.g. "myapp::config::CURRENT"
+    name: String,
+    /// Index of its unit in DebugInfo::units, and its type there
+    unit: usize,
+    ty: Option<gimli::UnitOffset>,
+}
+
+/// The executable's debug info, kept for expressions (see expr)
+struct DebugInfo {
+    dwarf: gimli::Dwarf<Reader<'static>>,
+    units: Vec<Unit<Reader<'static>>>,
+    /// By address
+    variables: Vec<Variable>,
+}
+
+static DEBUG_INFO: OnceLock<Option<DebugInfo>> = OnceLock::new();
+
+fn debug_info() -> Option<&'static DebugInfo> {
+    DEBUG_INFO.get_or_init(|| load().ok()).as_ref()
+}
+
+/// Name of the static variable containing `addr`, with "+offset" when it is
+/// not the first byte
+pub fn variable_at(addr: u64) -> Option<String> {
+    let variables = &debug_info()?.variables;
+    let variable = variables[..variables.partition_point(|v| v.addr <= addr)].last()?;
+    let offset = addr - variable.addr;
+    if offset >= variable.size.max(1) {
+        return None;
+    }
+    Some(if offset == 0 {
+        variable.name.clone()
+    } else {
+        format!("{}+{}", variable.name, offset)
+    })
+}
+
+/// Name for a region watched without one
+pub(crate) fn region_name(addr: u64) -> String {
+    variable_at(addr).unwrap_or_else(|| format!("0x{:x}", addr))
+}
+
+/// Address and size of `root` followed by `steps`; `root` is a static's
+/// qualified name or, when unambiguous, its last component
+pub(crate) fn resolve(root: &str, steps: &[Step]) -> Result<(u64, usize), String> {
+    let info = debug_info().ok_or("No debug info for this executable")?;
+    let mut matches = info.variables.iter().filter(|v| v.name == root);
+    let variable = match (matches.next(), matches.next()) {
+        (Some(variable), _) => variable,
+        (None, _) => {
+            let mut matches = info.variables.iter().filter(|v| v.name.rsplit("::").next() == Some(root));
+            match (matches.next(), matches.next()) {
+                (Some(variable), None) => variable,
+                (Some(a), Some(b)) => return Err(format!("{} is ambiguous: {} or {}", root, a.name, b.name)),
+                (None, _) => return Err(format!("No static named {}", root)),
+            }
+        }
+    };
+    let unit = &info.units[variable.unit];
+    let mut addr = variable.addr;
+    let mut ty = variable.ty.ok_or_else(|| format!("{} has no type information", root))?;
+    let mut path = root.to_string();
+    for step in steps {
+        let stripped = strip(unit, ty).map_err(|e| e.to_string())?;
+        let (offset, next) = match step {
+            Step::Field(name) => member(&info.dwarf, unit, stripped, name),
+            Step::Index(index) => element(unit, stripped, *index as u64),
+        }
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("{} has no {}", path, step))?;
+        addr += offset;
+        ty = next;
+        path.push_str(&step.to_string());
+    }
+    let size = size_of(unit, ty, 0).map_err(|e| e.to_string())?.ok_or_else(|| format!("Size of {} is unknown", path))?;
+    Ok((addr, size as usize))
+}
+
+/// Skip typedefs and qualifiers
+fn strip(unit: &Unit<Reader>, mut offset: gimli::UnitOffset) -> gimli::Result<gimli::UnitOffset> {
+    for _ in 0..16 {
+        let entry = unit.entry(offset)?;
+        if !matches!(entry.tag(), gimli::DW_TAG_typedef | gimli::DW_TAG_const_type | gimli::DW_TAG_volatile_type) {
+            break;
+        }
+        match entry.attr_value(gimli::DW_AT_type)? {
+            Some(AttributeValue::UnitRef(next)) => offset = next,
+            _ => break,
+        }
+    }
+    Ok(offset)
+}
+
+/// Offset and type of the member `name` of a struct, union or class
+fn member(
+    dwarf: &gimli::Dwarf<Reader>,
+    unit: &Unit<Reader>,
+    ty: gimli::UnitOffset,
+    name: &str,
+) -> gimli::Result<Option<(u64, gimli::UnitOffset)>> {
+    let mut tree = unit.entries_tree(Some(ty))?;
+    let mut children = tree.root()?.children();
+    while let Some(child) = children.next()? {
+        let child = child.entry();
+        if child.tag() != gimli::DW_TAG_member {
+            continue;
+        }
+        let Some(member_name) = child.attr_value(gimli::DW_AT_name)? else {
+            continue;
+        };
+        if dwarf.attr_string(unit, member_name)?.slice() != name.as_bytes() {
+            continue;
+        }
+        let offset = child.attr_value(gimli::DW_AT_data_member_location)?.and_then(|v| v.udata_value()).unwrap_or(0);
+        if let Some(AttributeValue::UnitRef(member_ty)) = child.attr_value(gimli::DW_AT_type)? {
+            return Ok(Some((offset, member_ty)));
+        }
+    }
+    Ok(None)
+}
+
+/// Offset and type of element `index` of a one-dimensional array
+fn element(unit: &Unit<Reader>, ty: gimli::UnitOffset, index: u64) -> gimli::Result<Option<(u64, gimli::UnitOffset)>> {
+    let entry = unit.entry(ty)?;
+    if entry.tag() != gimli::DW_TAG_array_type {
+        return Ok(None);
+    }
+    let Some(AttributeValue::UnitRef(element_ty)) = entry.attr_value(gimli::DW_AT_type)? else {
+        return Ok(None);
+    };
+    let lengths = array_lengths(unit, ty)?;
+    if lengths.len() != 1 || lengths[0].is_some_and(|length| index >= length) {
+        return Ok(None);
+    }
+    let stride = match entry.attr_value(gimli::DW_AT_byte_stride)?.and_then(|v| v.udata_value()) {
+        Some(stride) => Some(stride),
+        None => size_of(unit, element_ty, 0)?,
+    };
+    Ok(stride.map(|stride| (stride * index, element_ty)))
+}
+
+/// Length of each dimension of an array type, None where unbounded
+fn array_lengths(unit: &Unit<Reader>, ty: gimli::UnitOffset) -> gimli::Result<Vec<Option<u64>>> {
+    let mut lengths = Vec::new();
+    let mut tree = unit.entries_tree(Some(ty))?;
+    let mut children = tree.root()?.children();
+    while let Some(child) = children.next()? {
+        let child = child.entry();
+        if child.tag() != gimli::DW_TAG_subrange_type {
+            continue;
+        }
+        let bound = |name| child.attr_value(name).map(|value| value.and_then(|v| v.udata_value()));
+        lengths.push(match (bound(gimli::DW_AT_count)?, bound(gimli::DW_AT_upper_bound)?) {
+            (Some(count), _) => Some(count),
+            (None, Some(upper)) => Some((upper + 1).saturating_sub(bound(gimli::DW_AT_lower_bound)?.unwrap_or(0))),
+            (None, None) => None,
+        });
+    }
+    Ok(lengths)
+}
+
+fn load() -> Result<DebugInfo, String> {
+    let path = std::env::current_exe().map_err(|e| e.to_string())?;
+    let data = std::fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    // Kept for the life of the process, as the units borrow from it
+    let data: &'static [u8] = Box::leak(data.into_boxed_slice());
+    let file = object::File::parse(data).map_err(|e| e.to_string())?;
+    let endian = if file.is_little_endian() { RunTimeEndian::Little } else { RunTimeEndian::Big };
+    let sections = gimli::DwarfSections::load(|id| -> Result<Cow<'static, [u8]>, String> {
+        Ok(match file.section_by_name(id.name()) {
+            Some(section) => section.uncompressed_data().map_err(|e| e.to_string())?,
+            None => Cow::Borrowed(&[]),
+        })
+    })?;
+    let sections: &'static gimli::DwarfSections<Cow<'static, [u8]>> = Box::leak(Box::new(sections));
+    let dwarf = sections.borrow(|section| EndianSlice::new(section, endian));
+    let bias = process::load_bias();
+
+    let mut units = Vec::new();
+    let mut variables = Vec::new();
+    let mut headers = dwarf.units();
+    while let Some(header) = headers.next().map_err(|e| e.to_string())? {
+        let unit = dwarf.unit(header).map_err(|e| e.to_string())?;
+        statics(&dwarf, &unit, units.len(), bias, &mut variables).map_err(|e| e.to_string())?;
+        units.push(unit);
+    }
+    variables.sort_by_key(|v| v.addr);
+    Ok(DebugInfo { dwarf, units, variables })
+}
+
+type Reader<'a> = EndianSlice<'a, RunTimeEndian>;
+
+/// Statics of one unit: variables with a fixed address (locals are
+/// frame-relative and never have one)
+fn statics(
+    dwarf: &gimli::Dwarf<Reader>,
+    unit: &Unit<Reader>,
+    unit_index: usize,
+    bias: u64,
+    out: &mut Vec<Variable>,
+) -> gimli::Result<()> {
+    // Per enclosing entry, the namespace or type it names, if it is one
+    let mut scopes: Vec<Option<String>> = Vec::new();
+    let mut depth = 0isize;
+    let mut entries = unit.entries();
+    while let Some((delta, entry)) = entries.next_dfs()? {
+        depth += delta;
+        scopes.truncate(depth.max(0) as usize);
+        let name = match entry.attr_value(gimli::DW_AT_name)? {
+            Some(value) => Some(dwarf.attr_string(unit, value)?.to_string_lossy().into_owned()),
+            None => None,
+        };
+        if entry.tag() == gimli::DW_TAG_variable {
+            if let (Some(name), Some(addr)) = (&name, fixed_address(unit, entry)?) {
+                let path: Vec<&str> = scopes.iter().flatten().map(String::as_str).chain([name.as_str()]).collect();
+                let ty = match entry.attr_value(gimli::DW_AT_type)? {
+                    Some(AttributeValue::UnitRef(ty)) => Some(ty),
+                    _ => None,
+                };
+                let size = match ty {
+                    Some(ty) => size_of(unit, ty, 0)?.unwrap_or(0),
+                    None => 0,
+                };
+                let name = path.join("::");
+                out.push(Variable { addr: addr.wrapping_add(bias), size, name, unit: unit_index, ty });
+            }
+        }
+        let scope = matches!(
+            entry.tag(),
+            gimli::DW_TAG_namespace | gimli::DW_TAG_structure_type | gimli::DW_TAG_class_type | gimli::DW_TAG_union_type
+        );
+        scopes.push(if scope { name } else { None });
+    }
+    Ok(())
+}
+
+/// The address of a DW_OP_addr location
+fn fixed_address(unit: &Unit<Reader>, entry: &gimli::DebuggingInformationEntry<Reader>) -> gimli::Result<Option<u64>> {
+    let Some(AttributeValue::Exprloc(expr)) = entry.attr_value(gimli::DW_AT_location)? else {
+        return Ok(None);
+    };
+    let mut ops = expr.operations(unit.encoding());
+    match (ops.next()?, ops.next()?) {
+        (Some(Operation::Address { address }), None) => Ok(Some(address)),
+        _ => Ok(None),
+    }
+}
+
+/// Byte size of the type at `offset`, through typedefs, qualifiers and
+/// arrays (which rarely carry their own size)
+fn size_of(unit: &Unit<Reader>, offset: gimli::UnitOffset, depth: usize) -> gimli::Result<Option<u64>> {
+    // Bounded, in case of a malformed cycle
+    if depth > 16 {
+        return Ok(None);
+    }
+    let ty = unit.entry(offset)?;
+    if let Some(size) = ty.attr_value(gimli::DW_AT_byte_size)?.and_then(|v| v.udata_value()) {
+        return Ok(Some(size));
+    }
+    let element = match ty.attr_value(gimli::DW_AT_type)? {
+        Some(AttributeValue::UnitRef(next)) => size_of(unit, next, depth + 1)?,
+        _ => return Ok(None),
+    };
+    if ty.tag() != gimli::DW_TAG_array_type {
+        return Ok(element);
+    }
+    let mut count = 1u64;
+    for length in array_lengths(unit, offset)? {
+        let Some(length) = length else {
+            return Ok(None);
+        };
+        count = count.saturating_mul(length);
+    }
+    Ok(element.map(|size| size.saturating_mul(count)))
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use crate::capabilities::WatchMode;
+    use crate::fake_native;
+    use crate::MemWatch;
+
+    static ROUTING_TABLE: [u8; 32] = [0; 32];
+
+    #[allow(dead_code)]
+    struct Player {
+        id: u32,
+        health: u64,
+    }
+
+    #[allow(dead_code)]
+    struct GameState {
+        tick: u32,
+        players: [Player; 4],
+    }
+
+    static GAME_STATE: GameState = GameState {
+        tick: 0,
+        players: [const { Player { id: 0, health: 100 } }; 4],
+    };
+
+    #[test]
+    fn test_unnamed_regions_are_named_after_their_static() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        let table = std::hint::black_box(&ROUTING_TABLE);
+        watcher.watch(&table[..8], "").unwrap();
+        watcher.watch(&table[16..], "").unwrap();
+        let heap = vec![0u8; 8];
+        watcher.watch(&heap, "").unwrap();
+
+        let events = watcher.check_changes().unwrap();
+        let names: Vec<&str> = events.iter().map(|e| e.variable_name.as_deref().unwrap()).collect();
+        assert_eq!(names[..2], ["memwatch::dwarf::tests::ROUTING_TABLE", "memwatch::dwarf::tests::ROUTING_TABLE+16"]);
+        assert_eq!(names[2], format!("0x{:x}", heap.as_ptr() as u64));
+    }
+
+    #[test]
+    fn test_expressions_resolve_through_nested_types() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        let state = std::hint::black_box(&GAME_STATE);
+
+        let health = watcher.watch_expr("GAME_STATE.players[3].health").unwrap();
+        let regions: Vec<(u64, usize)> = watcher.regions.lock().unwrap().values().map(|r| (r.addr, r.size)).collect();
+        assert_eq!(regions, [(&state.players[3].health as *const u64 as u64, 8)]);
+        assert!(watcher.watch_expr("memwatch::dwarf::tests::GAME_STATE.tick").is_ok());
+        assert!(watcher.watch_expr("GAME_STATE.players[4]").is_err());
+        assert!(watcher.watch_expr("GAME_STATE.score").is_err());
+        assert!(watcher.watch_expr("NO_SUCH_STATIC").is_err());
+        drop(health);
+    }
+}
@@ -0,0 +1,74 @@
+// Structured errors for native calls and event draining
+
+use std::fmt;
+
+/// A call into the native core reported failure
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NativeError {
+    /// Native function that failed
+    pub call: &'static str,
+    /// Its return value (0 for calls that only report success as a bool)
+    pub code: i32,
+}
+
+impl fmt::Display for NativeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed: {}", self.call, self.code)
+    }
+}
+
+impl std::error::Error for NativeError {}
+
+impl From<NativeError> for String {
+    fn from(e: NativeError) -> String {
+        e.to_string()
+    }
+}
+
+/// Why check_changes() failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckError {
+    /// The native drain returned an error code
+    Native(NativeError),
+    /// The native drain returned an entry that cannot be read safely
+    Malformed { index: usize, reason: &'static str },
+    /// An earlier native failure poisoned the watcher; call reset()
+    Poisoned,
+    /// Sequencing or sink delivery failed
+    Pipeline(String),
+    /// The feature needs the native backend, which this target lacks
+    Unsupported(&'static str),
+}
+
+impl CheckError {
+    /// Native-side failures poison the watcher; pipeline errors do not
+    pub fn poisons(&self) -> bool {
+        matches!(self, CheckError::Native(_) | CheckError::Malformed { .. })
+    }
+}
+
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckError::Native(e) => e.fmt(f),
+            CheckError::Malformed { index, reason } => write!(f, "Malformed native event {}: {}", index, reason),
+            CheckError::Poisoned => write!(f, "Watcher poisoned by an earlier native failure; reset() required"),
+            CheckError::Pipeline(e) => f.write_str(e),
+            CheckError::Unsupported(what) => write!(f, "{} is not supported on this target", what),
+        }
+    }
+}
+
+impl std::error::Error for CheckError {}
+
+impl From<String> for CheckError {
+    fn from(e: String) -> CheckError {
+        CheckError::Pipeline(e)
+    }
+}
+
+impl From<CheckError> for String {
+    fn from(e: CheckError) -> String {
+        e.to_string()
+    }
+}
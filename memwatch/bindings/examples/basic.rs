@@ -1,7 +1,7 @@
 // Rust example demonstrating memwatch with max_value_bytes parameter
 // Usage: cargo run --example basic
 
-use memwatch::{MemWatch, ChangeEvent};
+use memwatch::MemWatch;
 use std::time::Duration;
 use std::thread;
 
@@ -21,7 +21,7 @@ fn main() -> Result<(), String> {
     
     buf1[0] = 99;
     thread::sleep(Duration::from_millis(100));
-    let events = watcher.check_changes();
+    let events = watcher.check_changes()?;
     println!("   → Events: {} (values: {})", events.len(), 
         events.iter().map(|e| format!("old:{:?}", e.old_preview)).collect::<Vec<_>>().join(", "));
 
@@ -33,7 +33,7 @@ fn main() -> Result<(), String> {
     
     buf2[3] = 99;
     thread::sleep(Duration::from_millis(100));
-    let events = watcher.check_changes();
+    let events = watcher.check_changes()?;
     println!("   → Events: {}, stored {} bytes max", events.len(), 2);
 
     // Example 3: Full value storage (max_value_bytes=-1)
@@ -44,20 +44,20 @@ fn main() -> Result<(), String> {
     
     buf3[2] = 125;
     thread::sleep(Duration::from_millis(100));
-    let events = watcher.check_changes();
+    let events = watcher.check_changes()?;
     println!("   → Events: {} (full {} bytes stored)", events.len(), buf3.len());
 
     // Get statistics
-    let stats = watcher.get_stats();
+    let stats = watcher.get_stats()?;
     println!("\n📊 Statistics:");
     println!("   - Tracked regions: {}", stats.num_tracked_regions);
     println!("   - Total events: {}", stats.total_events);
     println!("   - Storage used: {} bytes", stats.storage_bytes_used);
 
     // Cleanup
-    watcher.unwatch(region_id_1);
-    watcher.unwatch(region_id_2);
-    watcher.unwatch(region_id_3);
+    watcher.unwatch(region_id_1)?;
+    watcher.unwatch(region_id_2)?;
+    watcher.unwatch(region_id_3)?;
     println!("\n✓ All regions unwatched");
     println!("✓ SUCCESS: Rust memwatch example completed");
 
@@ -0,0 +1,225 @@
+// Export-side value handling
+//
+// In hashed mode values never leave the process: each one is replaced by a
+// salted SHA-256 digest plus its length and byte entropy, which is enough to
+// study change patterns on production streams without exporting contents.
+
+use sha2::{Digest, Sha256};
+
+use crate::{ChangeEvent, EventKind, EventSource, Location};
+
+/// How values (memory bytes, SQL literals) appear in exported records
+#[derive(Debug, Clone, Default)]
+pub enum ValueMode {
+    /// Export values as captured
+    #[default]
+    Raw,
+    /// Replace values by salted hashes plus length/entropy metadata
+    Hashed { salt: Vec<u8> },
+}
+
+/// Stand-in for a value in hashed mode
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashedValue {
+    /// Hex of the first 16 bytes of SHA-256(salt || value)
+    pub digest: String,
+    pub len: usize,
+    /// Shannon entropy in bits per byte (0.0 ..= 8.0)
+    pub entropy: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExportedValue {
+    Raw(Vec<u8>),
+    Hashed(HashedValue),
+}
+
+/// A change event prepared for export
+#[derive(Debug, Clone)]
+pub struct ExportedEvent {
+    pub epoch: u32,
+    pub global_seq: u64,
+    pub seq: u32,
+    pub timestamp_ns: u64,
+    pub pid: u32,
+    pub tid: Option<u32>,
+    pub region_id: u32,
+    pub variable_name: Option<String>,
+    pub kind: EventKind,
+    pub source: EventSource,
+    pub where_: Location,
+    /// e.g. "Vec<Packet> allocated at net/rx.rs:120"
+    pub allocated_at: Option<String>,
+    pub old: ExportedValue,
+    pub new: ExportedValue,
+}
+
+impl ExportedValue {
+    fn to_json(&self) -> String {
+        match self {
+            ExportedValue::Raw(bytes) => format!("\"{}\"", hex(bytes)),
+            ExportedValue::Hashed(h) => format!(
+                "{{\"digest\":\"{}\",\"len\":{},\"entropy\":{:.4}}}",
+                h.digest, h.len, h.entropy
+            ),
+        }
+    }
+}
+
+impl ExportedEvent {
+    /// Single-line JSON with a fixed field order (raw values are hex strings)
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"epoch\":{},\"global_seq\":{},\"seq\":{},\"timestamp_ns\":{},\"pid\":{},\"tid\":{},\"region_id\":{},\"variable_name\":{},\"kind\":\"{}\",\"source\":\"{}\",\"file\":{},\"function\":{},\"line\":{},\"fault_ip\":{},\"allocated_at\":{},\"old\":{},\"new\":{}}}",
+            self.epoch,
+            self.global_seq,
+            self.seq,
+            self.timestamp_ns,
+            self.pid,
+            self.tid.map(|tid| tid.to_string()).unwrap_or_else(|| "null".to_string()),
+            self.region_id,
+            json_opt_str(self.variable_name.as_deref()),
+            self.kind.as_str(),
+            self.source.as_str(),
+            json_opt_str(self.where_.file.as_deref()),
+            json_opt_str(self.where_.function.as_deref()),
+            self.where_.line,
+            self.where_.fault_ip,
+            json_opt_str(self.allocated_at.as_deref()),
+            self.old.to_json(),
+            self.new.to_json(),
+        )
+    }
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+pub(crate) fn json_str(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+pub(crate) fn json_opt_str(value: Option<&str>) -> String {
+    value.map(json_str).unwrap_or_else(|| "null".to_string())
+}
+
+impl ValueMode {
+    pub fn hashed(salt: impl Into<Vec<u8>>) -> Self {
+        ValueMode::Hashed { salt: salt.into() }
+    }
+
+    pub fn export_bytes(&self, value: &[u8]) -> ExportedValue {
+        match self {
+            ValueMode::Raw => ExportedValue::Raw(value.to_vec()),
+            ValueMode::Hashed { salt } => ExportedValue::Hashed(hash_value(salt, value)),
+        }
+    }
+
+    /// Same as `export_bytes` for textual values such as SQL literals
+    pub fn export_str(&self, value: &str) -> ExportedValue {
+        self.export_bytes(value.as_bytes())
+    }
+
+    /// Full values are used when captured, previews otherwise
+    pub fn export(&self, event: &ChangeEvent) -> ExportedEvent {
+        let old = if event.old_value.is_empty() { &event.old_preview } else { &event.old_value };
+        let new = if event.new_value.is_empty() { &event.new_preview } else { &event.new_value };
+        ExportedEvent {
+            epoch: event.epoch,
+            global_seq: event.global_seq,
+            seq: event.seq,
+            timestamp_ns: event.timestamp_ns,
+            pid: std::process::id(),
+            tid: event.tid,
+            region_id: event.region_id,
+            variable_name: event.variable_name.clone(),
+            kind: event.kind,
+            source: event.source,
+            where_: event.where_.clone(),
+            allocated_at: event.allocated_at.map(|site| site.to_string()),
+            old: self.export_bytes(old),
+            new: self.export_bytes(new),
+        }
+    }
+}
+
+pub fn hash_value(salt: &[u8], value: &[u8]) -> HashedValue {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(value);
+    let digest = hasher.finalize();
+    HashedValue {
+        digest: hex(&digest[..16]),
+        len: value.len(),
+        entropy: shannon_entropy(value),
+    }
+}
+
+/// Shannon entropy of a byte string in bits per byte
+pub fn shannon_entropy(bytes: &[u8]) -> f64 {
+    if bytes.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0usize; 256];
+    for &b in bytes {
+        counts[b as usize] += 1;
+    }
+    let len = bytes.len() as f64;
+    counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashed_mode_is_salted_and_stable() {
+        let a = ValueMode::hashed("salt-a");
+        let b = ValueMode::hashed("salt-b");
+        assert_eq!(a.export_str("secret"), a.export_str("secret"));
+        assert_ne!(a.export_str("secret"), b.export_str("secret"));
+        match a.export_str("secret") {
+            ExportedValue::Hashed(h) => assert_eq!(h.len, 6),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_entropy_bounds() {
+        assert_eq!(shannon_entropy(&[7; 64]), 0.0);
+        let all: Vec<u8> = (0..=255).collect();
+        assert!((shannon_entropy(&all) - 8.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,218 @@
+// Watches from debugger-style expressions
+//
+// watch_expr() takes what one would type at a debugger prompt and works out
+// the address and size itself:
+//
+//   watcher.register_layout("game_state", &GAME_STATE);   // T: Watchable
+//   let _health = watcher.watch_expr("game_state.players[3].health")?;
+//
+// An expression is a root followed by `.field` and `[index]` steps. Roots
+// registered with register_layout() are resolved through their Watchable
+// layout, which lists one level of fields: "config.timeout_ms" works,
+// anything below a field does not. Other roots are looked up as statics in
+// the executable's DWARF info (feature `dwarf`), which knows every nested
+// member and array, so the full path resolves; see the dwarf module.
+//
+// The region is named after the expression, and the returned guard
+// unwatches it when dropped. Roots are 'static since the watch outlives
+// any borrow watch_expr() could check.
+
+use std::fmt;
+
+use crate::watchable::{FieldInfo, Watchable};
+use crate::{MemWatch, Unwatch};
+
+/// One step of an expression after its root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    Field(String),
+    Index(usize),
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::Field(name) => write!(f, ".{}", name),
+            Step::Index(index) => write!(f, "[{}]", index),
+        }
+    }
+}
+
+/// A root registered with register_layout()
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LayoutRoot {
+    addr: u64,
+    size: usize,
+    fields: &'static [FieldInfo],
+}
+
+/// Split an expression into its root and steps
+pub fn parse(expr: &str) -> Result<(String, Vec<Step>), String> {
+    let expr = expr.trim();
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let root_end = expr.find(|c: char| !is_ident(c) && c != ':').unwrap_or(expr.len());
+    let root = &expr[..root_end];
+    if root.is_empty() {
+        return Err(format!("Expression {:?} has no root variable", expr));
+    }
+    let mut steps = Vec::new();
+    let mut rest = &expr[root_end..];
+    while !rest.is_empty() {
+        if let Some(field) = rest.strip_prefix('.') {
+            let end = field.find(|c: char| !is_ident(c)).unwrap_or(field.len());
+            if end == 0 {
+                return Err(format!("Expected a field name after '.' in {:?}", expr));
+            }
+            steps.push(Step::Field(field[..end].to_string()));
+            rest = &field[end..];
+        } else if let Some(index) = rest.strip_prefix('[') {
+            let (digits, after) = index.split_once(']').ok_or_else(|| format!("Unclosed '[' in {:?}", expr))?;
+            let index = digits.trim().parse().map_err(|_| format!("Bad index {:?} in {:?}", digits, expr))?;
+            steps.push(Step::Index(index));
+            rest = after;
+        } else {
+            return Err(format!("Unexpected {:?} in {:?}", rest, expr));
+        }
+    }
+    Ok((root.to_string(), steps))
+}
+
+/// A watch_expr() watch that ends when the guard is dropped
+pub struct ExprGuard<'a> {
+    watcher: &'a MemWatch,
+    region_id: u32,
+}
+
+impl ExprGuard<'_> {
+    pub fn region_id(&self) -> u32 {
+        self.region_id
+    }
+
+    /// End the watch now, reporting what the backend did
+    pub fn unwatch(self) -> Result<Unwatch, crate::NativeError> {
+        let result = self.watcher.unwatch(self.region_id);
+        std::mem::forget(self);
+        result
+    }
+}
+
+impl Drop for ExprGuard<'_> {
+    fn drop(&mut self) {
+        let _ = self.watcher.unwatch(self.region_id);
+    }
+}
+
+impl MemWatch {
+    /// Make `value` available to watch_expr() as `name`
+    pub fn register_layout<T: Watchable>(&self, name: &str, value: &'static T) {
+        let root = LayoutRoot { addr: value as *const T as u64, size: std::mem::size_of::<T>(), fields: T::FIELDS };
+        self.layout_roots.lock().unwrap().insert(name.to_string(), root);
+    }
+
+    /// Watch what `expr` designates, e.g. "game_state.players[3].health";
+    /// see the expr module
+    pub fn watch_expr(&self, expr: &str) -> Result<ExprGuard<'_>, String> {
+        let (root, steps) = parse(expr)?;
+        let registered = self.layout_roots.lock().unwrap().get(&root).copied();
+        let (addr, size) = match registered {
+            Some(layout) => resolve_layout(&root, layout, &steps)?,
+            None => resolve_static(&root, &steps)?,
+        };
+        if size == 0 {
+            return Err(format!("{} is zero-sized", expr));
+        }
+        // SAFETY: registered roots are 'static, and DWARF addresses are
+        // statics of this executable; both stay mapped for the process
+        let buffer = unsafe { std::slice::from_raw_parts(addr as *const u8, size) };
+        let region_id = self.watch(buffer, expr.trim())?;
+        Ok(ExprGuard { watcher: self, region_id })
+    }
+}
+
+fn resolve_layout(root: &str, layout: LayoutRoot, steps: &[Step]) -> Result<(u64, usize), String> {
+    match steps {
+        [] => Ok((layout.addr, layout.size)),
+        [Step::Field(name)] => {
+            let field = layout
+                .fields
+                .iter()
+                .find(|f| f.name == name)
+                .ok_or_else(|| format!("{} has no field {}", root, name))?;
+            Ok((layout.addr + field.offset as u64, field.size))
+        }
+        [Step::Index(_), ..] => Err(format!("{} is not an array", root)),
+        _ => Err(format!(
+            "{}{}: a registered layout only describes its own fields",
+            root,
+            steps.iter().map(Step::to_string).collect::<String>()
+        )),
+    }
+}
+
+#[cfg(feature = "dwarf")]
+fn resolve_static(root: &str, steps: &[Step]) -> Result<(u64, usize), String> {
+    crate::dwarf::resolve(root, steps)
+}
+
+#[cfg(not(feature = "dwarf"))]
+fn resolve_static(root: &str, _steps: &[Step]) -> Result<(u64, usize), String> {
+    Err(format!("Unknown root {}: register it with register_layout() or enable the `dwarf` feature", root))
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native;
+
+    #[allow(dead_code)]
+    struct Player {
+        id: u32,
+        health: u32,
+    }
+
+    impl Watchable for Player {
+        const FIELDS: &'static [FieldInfo] = &[
+            FieldInfo { name: "id", offset: std::mem::offset_of!(Player, id), size: 4 },
+            FieldInfo { name: "health", offset: std::mem::offset_of!(Player, health), size: 4 },
+        ];
+    }
+
+    static HERO: Player = Player { id: 1, health: 100 };
+
+    #[test]
+    fn test_parse_expressions() {
+        let (root, steps) = parse(" game_state.players[3].health ").unwrap();
+        assert_eq!(root, "game_state");
+        assert_eq!(steps, [Step::Field("players".into()), Step::Index(3), Step::Field("health".into())]);
+        assert_eq!(steps.iter().map(Step::to_string).collect::<String>(), ".players[3].health");
+        assert_eq!(parse("app::CONFIG").unwrap(), ("app::CONFIG".to_string(), Vec::new()));
+        for bad in ["", ".x", "a.", "a[", "a[x]", "a-b", "a[1]]"] {
+            assert!(parse(bad).is_err(), "{}", bad);
+        }
+    }
+    #[test]
+    fn test_registered_layouts_resolve_fields() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        watcher.register_layout("hero", &HERO);
+
+        let health = watcher.watch_expr("hero.health").unwrap();
+        let whole = watcher.watch_expr("hero").unwrap();
+        assert!(watcher.watch_expr("hero.mana").is_err());
+        assert!(watcher.watch_expr("hero.health.max").is_err());
+        assert!(watcher.watch_expr("hero[0]").is_err());
+        #[cfg(not(feature = "dwarf"))]
+        assert!(watcher.watch_expr("villain.health").err().unwrap().contains("register_layout"));
+
+        let events = watcher.check_changes().unwrap();
+        let names: Vec<&str> = events.iter().map(|e| e.variable_name.as_deref().unwrap()).collect();
+        assert_eq!(names, ["hero.health", "hero"]);
+        let regions = watcher.regions.lock().unwrap().values().map(|r| (r.addr, r.size)).collect::<Vec<_>>();
+        assert!(regions.contains(&(&HERO.health as *const u32 as u64, 4)));
+        drop(health);
+        assert_eq!(watcher.regions.lock().unwrap().len(), 1);
+        assert_eq!(whole.unwatch().unwrap(), Unwatch::Removed);
+    }
+}
@@ -0,0 +1,322 @@
+// Scriptable stand-in for libmemwatch (unit tests only)
+//
+// Provides the native symbols so tests can construct a MemWatch without the C
+// core and inject failures. Tests that use it take `lock()` first, since the
+// script is process-wide.
+
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::{ChangeEventC, NativeCallback, StatsC};
+
+/// One entry handed out by memwatch_check_changes
+pub(crate) struct FakeEvent {
+    pub region_id: u32,
+    pub new_preview: Vec<u8>,
+    /// Reported size; differs from `new_preview.len()` to fake corruption
+    pub new_preview_size: usize,
+    /// Hand out a null preview pointer
+    pub null_preview: bool,
+    pub timestamp_ns: u64,
+    /// Reported as the writing thread (0 = unknown), named "fake-worker"
+    pub tid: u32,
+    /// Return addresses of the writer, innermost first
+    pub backtrace: Vec<u64>,
+}
+
+impl FakeEvent {
+    pub(crate) fn change(region_id: u32, new_preview: &[u8]) -> Self {
+        FakeEvent {
+            region_id,
+            new_preview: new_preview.to_vec(),
+            new_preview_size: new_preview.len(),
+            null_preview: false,
+            timestamp_ns: 1,
+            tid: 0,
+            backtrace: Vec::new(),
+        }
+    }
+}
+
+pub(crate) struct FakeNative {
+    /// Returned by memwatch_check_changes instead of the event count
+    pub check_result: Option<c_int>,
+    pub events: Vec<FakeEvent>,
+    pub unwatch_ok: bool,
+    pub freed: usize,
+    /// Reported as worker_cycles
+    pub worker_cycles: u64,
+    /// Reported as ring_drop_count
+    pub ring_drops: u64,
+    /// Reported as mprotect_page_count
+    pub protected_pages: u32,
+    /// memwatch_init() calls
+    pub inits: usize,
+    /// Last memwatch_config_set_ring_capacity() value
+    pub ring_capacity: Option<u32>,
+    /// Hand out events without file/function when off
+    pub symbolication: bool,
+    /// Last memwatch_config_set_backtrace_depth() value
+    pub backtrace_depth: u32,
+    /// Regions paused with memwatch_pause_region()
+    pub paused: Vec<u32>,
+    /// Values memwatch_fetch_value() finds, by key
+    pub values: Vec<(String, Vec<u8>)>,
+    /// memwatch_set_callback() fails when off
+    pub callback_ok: bool,
+    /// Registered callback and its context (as an address)
+    callback: Option<(NativeCallback, usize)>,
+    pub next_region_id: u32,
+}
+
+static FAKE: Mutex<FakeNative> = Mutex::new(FakeNative {
+    check_result: None,
+    events: Vec::new(),
+    unwatch_ok: true,
+    freed: 0,
+    worker_cycles: 0,
+    ring_drops: 0,
+    protected_pages: 0,
+    inits: 0,
+    ring_capacity: None,
+    symbolication: true,
+    backtrace_depth: 0,
+    paused: Vec::new(),
+    values: Vec::new(),
+    callback_ok: true,
+    callback: None,
+    next_region_id: 0,
+});
+static TEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// Serialize tests using the fake and reset its script
+pub(crate) fn lock() -> MutexGuard<'static, ()> {
+    let guard = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut fake = state();
+    fake.check_result = None;
+    fake.events.clear();
+    fake.unwatch_ok = true;
+    fake.freed = 0;
+    fake.worker_cycles = 0;
+    fake.ring_drops = 0;
+    fake.protected_pages = 0;
+    fake.inits = 0;
+    fake.ring_capacity = None;
+    fake.symbolication = true;
+    fake.backtrace_depth = 0;
+    fake.paused.clear();
+    fake.values.clear();
+    fake.callback_ok = true;
+    fake.callback = None;
+    drop(fake);
+    guard
+}
+
+pub(crate) fn state() -> MutexGuard<'static, FakeNative> {
+    FAKE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[no_mangle]
+extern "C" fn memwatch_config_set_ring_capacity(capacity: u32) -> c_int {
+    if capacity == 0 {
+        return -1;
+    }
+    state().ring_capacity = Some(capacity);
+    0
+}
+
+#[no_mangle]
+extern "C" fn memwatch_config_set_symbolication(enabled: bool) -> c_int {
+    state().symbolication = enabled;
+    0
+}
+
+#[no_mangle]
+extern "C" fn memwatch_config_set_backtrace_depth(depth: u32) -> c_int {
+    if depth > crate::backtrace::MAX_BACKTRACE_DEPTH {
+        return -1;
+    }
+    state().backtrace_depth = depth;
+    0
+}
+
+#[no_mangle]
+extern "C" fn memwatch_init() -> c_int {
+    state().inits += 1;
+    0
+}
+
+#[no_mangle]
+extern "C" fn memwatch_shutdown() {}
+
+#[no_mangle]
+extern "C" fn memwatch_watch(addr: u64, size: usize, name: *const c_char, user_data: *mut c_void) -> u32 {
+    memwatch_watch_with_max_value_bytes(addr, size, name, user_data, 256)
+}
+
+#[no_mangle]
+extern "C" fn memwatch_watch_with_max_value_bytes(
+    _addr: u64,
+    _size: usize,
+    _name: *const c_char,
+    _user_data: *mut c_void,
+    _max_value_bytes: i32,
+) -> u32 {
+    let mut fake = state();
+    fake.next_region_id += 1;
+    fake.next_region_id
+}
+
+#[no_mangle]
+extern "C" fn memwatch_unwatch(_region_id: u32) -> bool {
+    state().unwatch_ok
+}
+
+#[no_mangle]
+extern "C" fn memwatch_pause_region(region_id: u32) -> c_int {
+    let mut fake = state();
+    if region_id == 0 || region_id > fake.next_region_id {
+        return -1;
+    }
+    if !fake.paused.contains(&region_id) {
+        fake.paused.push(region_id);
+    }
+    0
+}
+
+#[no_mangle]
+extern "C" fn memwatch_resume_region(region_id: u32) -> c_int {
+    let mut fake = state();
+    if region_id == 0 || region_id > fake.next_region_id {
+        return -1;
+    }
+    fake.paused.retain(|&id| id != region_id);
+    0
+}
+
+/// Invoke the registered callback as the worker would; false without one
+pub(crate) fn fire(event: FakeEvent) -> bool {
+    let Some((callback, user_ctx)) = state().callback else {
+        return false;
+    };
+    let preview = event.new_preview;
+    let backtrace = event.backtrace;
+    let c_event = ChangeEventC {
+        seq: 1,
+        timestamp_ns: event.timestamp_ns,
+        adapter_id: 0,
+        region_id: event.region_id,
+        variable_name: c"fake".as_ptr(),
+        file: ptr::null(),
+        function: c"fake_writer".as_ptr(),
+        line: 7,
+        fault_ip: 0,
+        old_preview: ptr::null(),
+        old_preview_size: 0,
+        new_preview: preview.as_ptr(),
+        new_preview_size: preview.len(),
+        old_value: ptr::null(),
+        old_value_size: 0,
+        new_value: ptr::null(),
+        new_value_size: 0,
+        storage_key_old: ptr::null(),
+        storage_key_new: ptr::null(),
+        user_data: ptr::null(),
+        thread_id: event.tid,
+        thread_name: fake_thread_name(event.tid),
+        backtrace: backtrace.as_ptr(),
+        backtrace_len: backtrace.len(),
+    };
+    unsafe { callback(&c_event, user_ctx as *mut c_void) };
+    true
+}
+
+fn fake_thread_name(tid: u32) -> *const c_char {
+    if tid == 0 {
+        ptr::null()
+    } else {
+        c"fake-worker".as_ptr()
+    }
+}
+
+#[no_mangle]
+extern "C" fn memwatch_set_callback(callback: Option<NativeCallback>, user_ctx: *mut c_void) -> c_int {
+    if !state().callback_ok {
+        return -1;
+    }
+    state().callback = callback.map(|callback| (callback, user_ctx as usize));
+    0
+}
+
+#[no_mangle]
+unsafe extern "C" fn memwatch_check_changes(out_events: *mut ChangeEventC, max_events: c_int) -> c_int {
+    let mut fake = state();
+    if let Some(result) = fake.check_result {
+        return result;
+    }
+    let count = fake.events.len().min(max_events.max(0) as usize);
+    let function = if fake.symbolication { c"fake_writer".as_ptr() } else { ptr::null() };
+    for (i, event) in fake.events.drain(..count).enumerate() {
+        // Previews and backtraces are leaked for the duration of the test process
+        let preview: &'static [u8] = Box::leak(event.new_preview.into_boxed_slice());
+        let backtrace: &'static [u64] = Box::leak(event.backtrace.into_boxed_slice());
+        *out_events.add(i) = ChangeEventC {
+            seq: i as u32 + 1,
+            timestamp_ns: event.timestamp_ns,
+            adapter_id: 0,
+            region_id: event.region_id,
+            variable_name: c"fake".as_ptr(),
+            file: ptr::null(),
+            function,
+            line: 7,
+            fault_ip: 0,
+            old_preview: ptr::null(),
+            old_preview_size: 0,
+            new_preview: if event.null_preview { ptr::null() } else { preview.as_ptr() },
+            new_preview_size: event.new_preview_size,
+            old_value: ptr::null(),
+            old_value_size: 0,
+            new_value: ptr::null(),
+            new_value_size: 0,
+            storage_key_old: ptr::null(),
+            storage_key_new: ptr::null(),
+            user_data: ptr::null(),
+            thread_id: event.tid,
+            thread_name: fake_thread_name(event.tid),
+            backtrace: backtrace.as_ptr(),
+            backtrace_len: backtrace.len(),
+        };
+    }
+    count as c_int
+}
+
+#[no_mangle]
+unsafe extern "C" fn memwatch_get_stats(out_stats: *mut StatsC) -> c_int {
+    *out_stats = std::mem::zeroed();
+    (*out_stats).worker_thread_id = 1;
+    let fake = state();
+    (*out_stats).worker_cycles = fake.worker_cycles;
+    (*out_stats).ring_drop_count = fake.ring_drops;
+    (*out_stats).mprotect_page_count = fake.protected_pages;
+    0
+}
+
+#[no_mangle]
+extern "C" fn memwatch_free_event(_event: *mut ChangeEventC) {
+    state().freed += 1;
+}
+
+#[no_mangle]
+unsafe extern "C" fn memwatch_fetch_value(key: *const c_char, out: *mut u8, inout_size: *mut usize) -> c_int {
+    let key = std::ffi::CStr::from_ptr(key).to_string_lossy();
+    let fake = state();
+    let Some((_, value)) = fake.values.iter().find(|(k, _)| *k == key) else {
+        return -1;
+    };
+    if !out.is_null() {
+        ptr::copy_nonoverlapping(value.as_ptr(), out, value.len().min(*inout_size));
+    }
+    *inout_size = value.len();
+    0
+}
@@ -0,0 +1,421 @@
+// Event filters
+//
+// A filter drops changes early, before anything downstream pays for them:
+//
+//   watcher.set_filter(Some(Filter::parse(
+//       "region_id in (3, 4) and function contains \"serialize\" and size >= 8 and rate <= 100/s",
+//   )?));
+//
+// Predicates compare a field with a value and combine with and, or, not and
+// parentheses:
+//
+//   region_id, line, size          = != < <= > >= in (..)
+//   name, group, file, function    = != contains in (..)
+//   rate <= N/s, N/min             at most N changes per region per second/minute
+//
+// size is the number of bytes changed (the deltas' total when both values
+// were captured, else the size of the new value or preview). A rate
+// predicate counts only the changes that reach it, so `a and rate <= 10/s`
+// passes ten changes matching `a` per second. Strings are quoted with ' or
+// ". Markers always pass. group is the region's WatchGroup, which the
+// native callback does not know: there, `group` predicates see none.
+//
+// The filter runs in check_changes() before anomaly detection, hooks,
+// sequencing, callbacks and sinks, and in the native callback before the
+// set_callback() closure (with its own rate counters). The C core has no
+// filter of its own, so nothing is pushed below the FFI boundary: filtered
+// changes still cost a fault and a ring slot.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::sql_parser::{self, Token, TokenKind};
+use crate::sql_value::SqlValue;
+use crate::ChangeEvent;
+
+/// A parsed filter expression; see the module comment
+#[derive(Debug, Clone)]
+pub struct Filter {
+    expr: Expr,
+    /// The source text, for Display
+    source: String,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Number(NumberField, Compare, Vec<u64>),
+    Text(TextField, Compare, Vec<String>),
+    Rate { limit: u32, window: Duration, seen: HashMap<u32, (Instant, u32)> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NumberField {
+    RegionId,
+    Line,
+    Size,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TextField {
+    Name,
+    Group,
+    File,
+    Function,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compare {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    In,
+    Contains,
+}
+
+impl Filter {
+    /// Parse a filter expression
+    pub fn parse(source: &str) -> Result<Filter, String> {
+        let tokens = sql_parser::tokenize(source);
+        let mut parser = Parser { tokens: &tokens, pos: 0 };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("Unexpected '{}' at offset {}", token.text, token.start));
+        }
+        Ok(Filter { expr, source: source.to_string() })
+    }
+
+    /// Whether `event` passes; advances rate counters
+    pub fn accepts(&mut self, event: &ChangeEvent) -> bool {
+        event.kind.is_marker() || self.expr.eval(event, Instant::now())
+    }
+}
+
+impl std::fmt::Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Expr {
+    fn eval(&mut self, event: &ChangeEvent, now: Instant) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(event, now) && b.eval(event, now),
+            Expr::Or(a, b) => a.eval(event, now) || b.eval(event, now),
+            Expr::Not(a) => !a.eval(event, now),
+            Expr::Number(field, compare, values) => {
+                let actual = match field {
+                    NumberField::RegionId => event.region_id as u64,
+                    NumberField::Line => event.where_.line as u64,
+                    NumberField::Size => change_size(event) as u64,
+                };
+                match compare {
+                    Compare::Eq | Compare::In => values.contains(&actual),
+                    Compare::Ne => !values.contains(&actual),
+                    Compare::Lt => actual < values[0],
+                    Compare::Le => actual <= values[0],
+                    Compare::Gt => actual > values[0],
+                    Compare::Ge => actual >= values[0],
+                    Compare::Contains => false,
+                }
+            }
+            Expr::Text(field, compare, values) => {
+                let actual = match field {
+                    TextField::Name => event.variable_name.as_deref(),
+                    TextField::Group => event.group.as_deref(),
+                    TextField::File => event.where_.file.as_deref(),
+                    TextField::Function => event.where_.function.as_deref(),
+                }
+                .unwrap_or_default();
+                match compare {
+                    Compare::Contains => actual.contains(values[0].as_str()),
+                    Compare::Ne => !values.iter().any(|v| v == actual),
+                    _ => values.iter().any(|v| v == actual),
+                }
+            }
+            Expr::Rate { limit, window, seen } => {
+                let (start, count) = seen.entry(event.region_id).or_insert((now, 0));
+                if now.duration_since(*start) >= *window {
+                    (*start, *count) = (now, 0);
+                }
+                *count += 1;
+                *count <= *limit
+            }
+        }
+    }
+}
+
+#[cfg(feature = "rusqlite")]
+impl Filter {
+    /// A SQL condition over storage::SqliteStore's columns that every
+    /// change this filter accepts meets, with a `?` for each of `params`
+    /// (pushed in order). size and rate cannot be told from a row and count
+    /// as met, so selected rows still go through accepts().
+    pub(crate) fn sql_condition(&self, params: &mut Vec<String>) -> String {
+        self.expr.sql(params).0
+    }
+}
+
+#[cfg(feature = "rusqlite")]
+impl Expr {
+    /// The condition, and whether it is exact (not merely implied)
+    fn sql(&self, params: &mut Vec<String>) -> (String, bool) {
+        match self {
+            Expr::And(a, b) => {
+                let ((a, a_exact), (b, b_exact)) = (a.sql(params), b.sql(params));
+                (format!("({} AND {})", a, b), a_exact && b_exact)
+            }
+            Expr::Or(a, b) => {
+                let ((a, a_exact), (b, b_exact)) = (a.sql(params), b.sql(params));
+                (format!("({} OR {})", a, b), a_exact && b_exact)
+            }
+            Expr::Not(a) => {
+                let mark = params.len();
+                match a.sql(params) {
+                    (a, true) => (format!("NOT {}", a), true),
+                    _ => {
+                        params.truncate(mark);
+                        ("1".to_string(), false)
+                    }
+                }
+            }
+            Expr::Number(NumberField::Size, _, _) | Expr::Rate { .. } => ("1".to_string(), false),
+            Expr::Number(field, compare, values) => {
+                let column = if *field == NumberField::RegionId { "region_id" } else { "line" };
+                let list = values.iter().map(u64::to_string).collect::<Vec<_>>().join(", ");
+                let condition = match compare {
+                    Compare::Eq | Compare::In => format!("{} IN ({})", column, list),
+                    Compare::Ne => format!("{} NOT IN ({})", column, list),
+                    Compare::Lt => format!("{} < {}", column, values[0]),
+                    Compare::Le => format!("{} <= {}", column, values[0]),
+                    Compare::Gt => format!("{} > {}", column, values[0]),
+                    Compare::Ge => format!("{} >= {}", column, values[0]),
+                    Compare::Contains => "0".to_string(),
+                };
+                (condition, true)
+            }
+            Expr::Text(field, compare, values) => {
+                // Missing fields compare as "", as in eval()
+                let column = match field {
+                    TextField::Name => "COALESCE(name, '')",
+                    TextField::Group => "COALESCE(group_name, '')",
+                    TextField::File => "COALESCE(file, '')",
+                    TextField::Function => "COALESCE(function, '')",
+                };
+                if *compare == Compare::Contains {
+                    params.push(values[0].clone());
+                    return (format!("instr({}, ?) > 0", column), true);
+                }
+                params.extend(values.iter().cloned());
+                let list = vec!["?"; values.len()].join(", ");
+                let not = if *compare == Compare::Ne { "NOT " } else { "" };
+                (format!("{} {}IN ({})", column, not, list), true)
+            }
+        }
+    }
+}
+
+/// Bytes changed by an event; see the module comment
+fn change_size(event: &ChangeEvent) -> usize {
+    if !event.deltas.is_empty() {
+        return event.deltas.iter().map(|range| range.len).sum();
+    }
+    event.new_value.len().max(event.new_preview.len())
+}
+
+struct Parser<'a, 't> {
+    tokens: &'t [Token<'a>],
+    pos: usize,
+}
+
+impl<'a> Parser<'a, '_> {
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token<'a>, String> {
+        let token = *self.peek().ok_or("Unexpected end of filter")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = self.peek().is_some_and(|t| t.kind == TokenKind::Word && t.text.eq_ignore_ascii_case(keyword));
+        self.pos += found as usize;
+        found
+    }
+
+    fn punct(&mut self, c: &str) -> Result<(), String> {
+        match self.next()? {
+            token if token.text == c => Ok(()),
+            token => Err(format!("Expected '{}' at offset {}, found '{}'", c, token.start, token.text)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.keyword("not") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.peek().is_some_and(|t| t.text == "(") {
+            self.pos += 1;
+            let expr = self.or()?;
+            self.punct(")")?;
+            return Ok(expr);
+        }
+        self.predicate()
+    }
+
+    fn predicate(&mut self) -> Result<Expr, String> {
+        let field = self.next()?;
+        let name = field.text.to_ascii_lowercase();
+        if name == "rate" {
+            return self.rate();
+        }
+        let compare = self.compare()?;
+        let number = match name.as_str() {
+            "region_id" => Some(NumberField::RegionId),
+            "line" => Some(NumberField::Line),
+            "size" => Some(NumberField::Size),
+            _ => None,
+        };
+        if let Some(number) = number {
+            if compare == Compare::Contains {
+                return Err(format!("'contains' needs a text field, not {}", field.text));
+            }
+            let values = self.values(compare, |token| match token.kind {
+                TokenKind::Number => token.text.parse::<u64>().ok(),
+                _ => None,
+            })?;
+            return Ok(Expr::Number(number, compare, values));
+        }
+        let text = match name.as_str() {
+            "name" | "variable" => TextField::Name,
+            "group" => TextField::Group,
+            "file" => TextField::File,
+            "function" => TextField::Function,
+            _ => return Err(format!("Unknown field '{}' at offset {}", field.text, field.start)),
+        };
+        if !matches!(compare, Compare::Eq | Compare::Ne | Compare::In | Compare::Contains) {
+            return Err(format!("{} compares text; use =, !=, in or contains", field.text));
+        }
+        let values = self.values(compare, |token| match (token.kind, SqlValue::parse(token.text)) {
+            (TokenKind::String, SqlValue::Text(text)) => Some(text),
+            (TokenKind::QuotedIdent, _) if token.text.starts_with('"') => Some(sql_parser::unquote_ident(token.text)),
+            _ => None,
+        })?;
+        Ok(Expr::Text(text, compare, values))
+    }
+
+    fn compare(&mut self) -> Result<Compare, String> {
+        let token = self.next()?;
+        let then_eq = self.peek().is_some_and(|t| t.text == "=" && t.start == token.start + 1);
+        let compare = match token.text.to_ascii_lowercase().as_str() {
+            "=" => Compare::Eq,
+            "!" if then_eq => Compare::Ne,
+            "<" if then_eq => Compare::Le,
+            ">" if then_eq => Compare::Ge,
+            "<" => Compare::Lt,
+            ">" => Compare::Gt,
+            "in" => Compare::In,
+            "contains" => Compare::Contains,
+            _ => return Err(format!("Expected a comparison at offset {}, found '{}'", token.start, token.text)),
+        };
+        self.pos += matches!(compare, Compare::Ne | Compare::Le | Compare::Ge) as usize;
+        Ok(compare)
+    }
+
+    /// One value, or a parenthesized list after `in`
+    fn values<T>(&mut self, compare: Compare, value: impl Fn(&Token) -> Option<T>) -> Result<Vec<T>, String> {
+        let one = |parser: &mut Self| {
+            let token = parser.next()?;
+            value(&token).ok_or_else(|| format!("Unexpected value '{}' at offset {}", token.text, token.start))
+        };
+        if compare != Compare::In {
+            return Ok(vec![one(self)?]);
+        }
+        self.punct("(")?;
+        let mut values = vec![one(self)?];
+        while self.peek().is_some_and(|t| t.text == ",") {
+            self.pos += 1;
+            values.push(one(self)?);
+        }
+        self.punct(")")?;
+        Ok(values)
+    }
+
+    /// `rate <= N/s` or `rate <= N/min`, "rate" already taken
+    fn rate(&mut self) -> Result<Expr, String> {
+        if self.compare()? != Compare::Le {
+            return Err("rate takes '<=', as in rate <= 100/s".to_string());
+        }
+        let count = self.next()?;
+        let limit = count.text.parse::<u32>().map_err(|_| format!("Expected a count at offset {}, found '{}'", count.start, count.text))?;
+        self.punct("/")?;
+        let unit = self.next()?;
+        let window = match unit.text.to_ascii_lowercase().as_str() {
+            "s" | "sec" => Duration::from_secs(1),
+            "min" => Duration::from_secs(60),
+            _ => return Err(format!("Unknown rate unit '{}'; use s or min", unit.text)),
+        };
+        Ok(Expr::Rate { limit, window, seen: HashMap::new() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::{marker, EventKind};
+
+    fn change(region_id: u32, function: &str, size: usize) -> ChangeEvent {
+        let mut event = marker(region_id, "buf", EventKind::Change);
+        event.where_.function = Some(function.to_string());
+        event.new_preview = vec![0; size];
+        event
+    }
+
+    #[test]
+    fn test_filter_predicates() {
+        let mut filter = Filter::parse("region_id in (3, 4) and (function contains \"serialize\" or size >= 8)").unwrap();
+        assert!(filter.accepts(&change(3, "serialize_row", 1)));
+        assert!(filter.accepts(&change(4, "main", 8)));
+        assert!(!filter.accepts(&change(4, "main", 4)));
+        assert!(!filter.accepts(&change(5, "serialize_row", 8)));
+        assert!(filter.accepts(&marker(5, "buf", EventKind::Unwatched)));
+
+        let mut filter = Filter::parse("not name = 'buf' or line != 0").unwrap();
+        assert!(!filter.accepts(&change(1, "main", 1)));
+
+        for bad in ["size contains 'x'", "name >= 'a'", "bogus = 1", "region_id in (1,", "rate < 5/s", "size >= 1 junk"] {
+            assert!(Filter::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_rate_limit_is_per_region() {
+        let mut filter = Filter::parse("rate <= 2/s").unwrap();
+        let passed = (0..5).filter(|_| filter.accepts(&change(1, "f", 1))).count();
+        assert_eq!(passed, 2);
+        assert!(filter.accepts(&change(2, "f", 1)));
+    }
+}
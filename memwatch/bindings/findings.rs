@@ -0,0 +1,216 @@
+// Audit findings across both trackers
+//
+// Report::audit() answers "what touched my sensitive data": memory changes
+// on regions tagged "sensitive" (WatchOptions::tags) and SQL reads and
+// writes of columns with a mask rule (SQLTracker::add_mask()), as one list
+// ordered by severity, then time:
+//
+//   Critical  sensitive column written without a WHERE clause; sensitive
+//             region written after it was unwatched
+//   High      sensitive region written within a second of a SQL access to
+//             a sensitive column of the same name (the value crossed
+//             between database and memory); bulk read of a sensitive
+//             column (BULK_READ_ROWS rows or more)
+//   Medium    other writes of sensitive regions or columns
+//   Low       other reads of sensitive columns
+//
+// A region and a column have "the same name" when either name contains the
+// other, ignoring case ("ssn_cache" and "users.ssn"). Memory changes come
+// from recent_events(), so only the last 1024 events are audited.
+
+use std::fmt;
+
+use crate::sql_tracker::{SQLChange, SQLOperation, SQLTracker};
+use crate::{ChangeEvent, EventKind, MemWatch};
+
+/// Region tag marking sensitive memory
+pub const SENSITIVE_TAG: &str = "sensitive";
+
+/// Rows read at once from a sensitive column that make a bulk read
+pub const BULK_READ_ROWS: i32 = 100;
+
+/// How close a memory write must follow or precede a SQL access to be
+/// attributed to it
+const CORRELATION_WINDOW_NS: u64 = 1_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+/// One finding of an audit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub timestamp_ns: u64,
+    /// Region name or `table.column`
+    pub subject: String,
+    pub message: String,
+    /// Region of a memory finding
+    pub region_id: Option<u32>,
+    /// Statement of a SQL finding (the SQL side of a correlated one)
+    pub query: Option<String>,
+}
+
+/// Prioritized findings, most severe first
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    /// Audit sensitive data access; see the module comment
+    pub fn audit(watch: &MemWatch, sql: &SQLTracker) -> Report {
+        let sensitive = watch.regions_tagged(SENSITIVE_TAG);
+        let events: Vec<ChangeEvent> = watch
+            .recent_events()
+            .into_iter()
+            .filter(|e| sensitive.contains(&e.region_id) && matches!(e.kind, EventKind::Change | EventKind::UseAfterUnwatch))
+            .collect();
+        let accesses: Vec<&SQLChange> = sql
+            .all_changes()
+            .iter()
+            .filter(|c| c.operation != SQLOperation::Unknown && sql.is_masked(&c.table_name, &c.column_name))
+            .collect();
+
+        let mut findings: Vec<Finding> = events.iter().map(|event| memory_finding(event, &accesses)).collect();
+        findings.extend(accesses.iter().map(|change| sql_finding(change)));
+        findings.sort_by(|a, b| b.severity.cmp(&a.severity).then(a.timestamp_ns.cmp(&b.timestamp_ns)));
+        Report { findings }
+    }
+
+    /// Severity of the worst finding
+    pub fn highest(&self) -> Option<Severity> {
+        self.findings.first().map(|f| f.severity)
+    }
+}
+
+impl fmt::Display for Report {
+    /// One line per finding: "[severity] timestamp subject: message"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for finding in &self.findings {
+            writeln!(f, "[{}] {} {}: {}", finding.severity.as_str(), finding.timestamp_ns, finding.subject, finding.message)?;
+        }
+        Ok(())
+    }
+}
+
+fn memory_finding(event: &ChangeEvent, accesses: &[&SQLChange]) -> Finding {
+    let name = event.variable_name.clone().unwrap_or_else(|| format!("region {}", event.region_id));
+    let writer = match (&event.where_.function, &event.where_.file) {
+        (Some(function), _) => format!(" by {}", function),
+        (None, Some(file)) => format!(" at {}:{}", file, event.where_.line),
+        (None, None) => String::new(),
+    };
+    let mut finding = Finding {
+        severity: Severity::Medium,
+        timestamp_ns: event.timestamp_ns,
+        subject: name.clone(),
+        message: format!("sensitive region written{}", writer),
+        region_id: Some(event.region_id),
+        query: None,
+    };
+    if event.kind == EventKind::UseAfterUnwatch {
+        finding.severity = Severity::Critical;
+        finding.message = format!("sensitive region written after unwatch{}", writer);
+        return finding;
+    }
+    let related = accesses
+        .iter()
+        .filter(|c| c.timestamp_ns.abs_diff(event.timestamp_ns) <= CORRELATION_WINDOW_NS && same_name(&name, &c.column_name))
+        .min_by_key(|c| c.timestamp_ns.abs_diff(event.timestamp_ns));
+    if let Some(change) = related {
+        finding.severity = Severity::High;
+        finding.message = format!(
+            "sensitive region written{} within {} ms of a SQL {} of {}.{}",
+            writer,
+            change.timestamp_ns.abs_diff(event.timestamp_ns) / 1_000_000,
+            change.operation.as_str(),
+            change.table_name,
+            change.column_name
+        );
+        finding.query = Some(change.full_query.clone());
+    }
+    finding
+}
+
+fn sql_finding(change: &SQLChange) -> Finding {
+    let (severity, message) = match change.operation {
+        SQLOperation::Select if change.rows_affected >= BULK_READ_ROWS => {
+            (Severity::High, format!("bulk read of a sensitive column ({} rows)", change.rows_affected))
+        }
+        SQLOperation::Select => (Severity::Low, "sensitive column read".to_string()),
+        SQLOperation::Update | SQLOperation::Delete if !change.has_where_clause() => {
+            (Severity::Critical, format!("sensitive column {} without a WHERE clause", change.operation.as_str()))
+        }
+        _ => (Severity::Medium, format!("sensitive column written ({})", change.operation.as_str())),
+    };
+    Finding {
+        severity,
+        timestamp_ns: change.timestamp_ns,
+        subject: format!("{}.{}", change.table_name, change.column_name),
+        message,
+        region_id: None,
+        query: Some(change.full_query.clone()),
+    }
+}
+
+/// Whether a region and a column name refer to the same data
+fn same_name(region: &str, column: &str) -> bool {
+    let (region, column) = (region.to_ascii_lowercase(), column.to_ascii_lowercase());
+    !region.is_empty() && !column.is_empty() && (region.contains(&column) || column.contains(&region))
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native;
+    use crate::sql_mask::Mask;
+    use crate::sql_value::SqlValue;
+    use crate::WatchOptions;
+
+    #[test]
+    fn test_audit_correlates_sensitive_memory_and_sql() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        let mut ssn_cache = vec![0u8; 11];
+        let mut counter = vec![0u8; 4];
+        let options = WatchOptions { tags: vec![SENSITIVE_TAG.to_string()], ..WatchOptions::default() };
+        let ssn_id = watcher.watch_with_options(&ssn_cache, "ssn_cache", &options).unwrap();
+        watcher.watch(&counter, "counter").unwrap();
+        watcher.check_changes().unwrap();
+
+        let mut sql = SQLTracker::pure_rust(None);
+        sql.add_mask("users", "ssn", Mask::Redact);
+        let row = vec![("ssn".to_string(), SqlValue::Text("123-45-6789".to_string())), ("name".to_string(), SqlValue::Text("Ann".to_string()))];
+        sql.track_query("UPDATE users SET ssn = NULL", 10, None, None, None);
+        sql.track_select_result("SELECT ssn, name FROM users WHERE id = 1", &[row]);
+        ssn_cache.copy_from_slice(b"123-45-6789");
+        counter[0] = 1;
+        watcher.check_changes().unwrap();
+
+        let report = Report::audit(&watcher, &sql);
+        let summary: Vec<(Severity, &str)> = report.findings.iter().map(|f| (f.severity, f.subject.as_str())).collect();
+        assert_eq!(summary, [(Severity::Critical, "users.ssn"), (Severity::High, "ssn_cache"), (Severity::Low, "users.ssn")]);
+        assert_eq!(report.findings[1].region_id, Some(ssn_id));
+        assert!(report.findings[1].message.contains("SQL SELECT of users.ssn"));
+        assert_eq!(report.highest(), Some(Severity::Critical));
+        assert!(report.to_string().starts_with("[critical] "));
+    }
+}
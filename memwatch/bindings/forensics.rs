@@ -0,0 +1,154 @@
+// Freeze-frame forensic capture
+//
+// capture() writes a directory package describing the process at one moment:
+//
+//   manifest.json   format version, capture time and the captured regions
+//   process.json    process header (pid, executable hash, host, container)
+//   maps.txt        copy of /proc/self/maps
+//   modules.tsv     "<load base hex>\t<path>" per loaded object
+//   regions/<id>.bin  raw bytes of each region (address and size in manifest)
+//   events.jsonl    recent events, as exported elsewhere
+//
+// Raw region files plus their addresses load directly into hex editors and
+// memory-forensics tooling; everything else is plain text or JSON.
+
+use std::fs;
+#[cfg(target_os = "linux")]
+use std::os::raw::{c_int, c_void};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use crate::access;
+use crate::export::{hex, json_str, ValueMode};
+use crate::lifecycle::now_ns;
+#[cfg(target_os = "linux")]
+use crate::presets;
+use crate::{process, MemWatch};
+
+const FORMAT: &str = "memwatch-forensics/1";
+
+/// A loaded executable or shared object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Module {
+    /// Path, or "main" for the executable
+    pub path: String,
+    pub base: usize,
+}
+
+/// Write a capture of `region_ids` to the directory `path` (created if
+/// missing). Fails on unknown region ids before writing anything.
+pub fn capture(watch: &MemWatch, region_ids: &[u32], path: impl AsRef<Path>) -> Result<(), String> {
+    let dir = path.as_ref();
+    let regions: Vec<(u32, String, u64, Vec<u8>)> = {
+        let registered = watch.regions.lock().unwrap();
+        region_ids
+            .iter()
+            .map(|&id| {
+                let info = registered.get(&id).ok_or_else(|| format!("Unknown region {}", id))?;
+                // SAFETY: registered regions are valid for reads until unwatched
+                let bytes = access::internal(|| unsafe { std::slice::from_raw_parts(info.addr as *const u8, info.size) }.to_vec());
+                Ok((id, info.name.clone(), info.addr, bytes))
+            })
+            .collect::<Result<_, String>>()?
+    };
+
+    let write = |name: &str, contents: &[u8]| {
+        fs::write(dir.join(name), contents).map_err(|e| format!("Failed to write {}: {}", dir.join(name).display(), e))
+    };
+    fs::create_dir_all(dir.join("regions")).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+    let mut entries = Vec::new();
+    for (id, name, addr, bytes) in &regions {
+        let file = format!("regions/{}.bin", id);
+        write(&file, bytes)?;
+        entries.push(format!(
+            "{{\"id\":{},\"name\":{},\"addr\":{},\"size\":{},\"file\":{},\"sha256\":\"{}\"}}",
+            id,
+            json_str(name),
+            addr,
+            bytes.len(),
+            json_str(&file),
+            hex(&Sha256::digest(bytes)),
+        ));
+    }
+
+    write("process.json", process::current().to_json_with(&watch.metadata()).as_bytes())?;
+    write("maps.txt", &fs::read("/proc/self/maps").unwrap_or_default())?;
+    let modules: String = loaded_modules().iter().map(|m| format!("{:x}\t{}\n", m.base, m.path)).collect();
+    write("modules.tsv", modules.as_bytes())?;
+    let events: String = watch
+        .recent_events()
+        .iter()
+        .map(|event| ValueMode::Raw.export(event).to_json() + "\n")
+        .collect();
+    write("events.jsonl", events.as_bytes())?;
+    write(
+        "manifest.json",
+        format!(
+            "{{\"format\":\"{}\",\"captured_ns\":{},\"regions\":[{}]}}\n",
+            FORMAT,
+            now_ns(),
+            entries.join(",")
+        )
+        .as_bytes(),
+    )
+}
+
+/// Every loaded object with its load base
+#[cfg(target_os = "linux")]
+pub fn loaded_modules() -> Vec<Module> {
+    let mut modules = Vec::new();
+    // SAFETY: the callback only reads the name and base handed to it
+    unsafe { libc::dl_iterate_phdr(Some(collect_module), &mut modules as *mut Vec<Module> as *mut c_void) };
+    modules
+}
+
+/// No loader to ask outside Linux
+#[cfg(not(target_os = "linux"))]
+pub fn loaded_modules() -> Vec<Module> {
+    Vec::new()
+}
+
+#[cfg(target_os = "linux")]
+unsafe extern "C" fn collect_module(info: *mut libc::dl_phdr_info, _size: usize, data: *mut c_void) -> c_int {
+    let info = &*info;
+    let modules = &mut *(data as *mut Vec<Module>);
+    modules.push(Module {
+        path: presets::object_name(info),
+        base: info.dlpi_addr as usize,
+    });
+    0
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native;
+
+    #[test]
+    fn test_capture_writes_package() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Protect;
+        let secret = b"launch-code".to_vec();
+        let id = watcher.watch(&secret, "secret").unwrap();
+        watcher.check_changes().unwrap();
+
+        let dir = std::env::temp_dir().join(format!("memwatch_forensics_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        assert!(capture(&watcher, &[id, 9999], &dir).is_err());
+        assert!(!dir.exists());
+        capture(&watcher, &[id], &dir).unwrap();
+
+        assert_eq!(fs::read(dir.join(format!("regions/{}.bin", id))).unwrap(), secret);
+        let manifest = fs::read_to_string(dir.join("manifest.json")).unwrap();
+        assert!(manifest.contains(&format!("\"addr\":{},\"size\":11", secret.as_ptr() as u64)));
+        assert!(fs::read_to_string(dir.join("events.jsonl")).unwrap().contains("\"kind\":\"watched\""));
+        assert!(fs::read_to_string(dir.join("modules.tsv")).unwrap().contains("\tmain\n"));
+        assert!(!fs::read_to_string(dir.join("maps.txt")).unwrap().is_empty());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,194 @@
+// Region graphs
+//
+// region_graph_dot() writes Graphviz source showing how watched regions
+// interact: one node per region, sized by its change count, dashed edges
+// between regions whose change rates rise and fall together, arrows for the
+// causality graph (when enabled), and a cluster per WatchGroup (or, for
+// regions outside any group, per first tag):
+//
+//   graph::region_graph_dot(&watcher, "regions.dot")?;
+//   // dot -Tsvg regions.dot -o regions.svg
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::causality::{dot_str, CausalityGraph};
+use crate::MemWatch;
+
+/// Bucket width when correlating change rates
+const CORRELATION_BUCKET: Duration = Duration::from_millis(10);
+/// Pearson coefficient above which two regions are drawn as correlated
+const MIN_CORRELATION: f64 = 0.7;
+/// Changes each region needs before its rate is correlated
+const MIN_CORRELATED_CHANGES: u64 = 3;
+
+struct GraphNode {
+    region_id: u32,
+    label: String,
+    changes: u64,
+    /// Group or tag the region is clustered under
+    cluster: Option<String>,
+}
+
+struct RegionGraph {
+    nodes: Vec<GraphNode>,
+    /// (region, region, Pearson coefficient), lower id first
+    correlations: Vec<(u32, u32, f64)>,
+    causality: CausalityGraph,
+}
+
+/// Write the watched regions and their relationships to `path` as DOT
+pub fn region_graph_dot(watch: &MemWatch, path: impl AsRef<Path>) -> Result<(), String> {
+    let path = path.as_ref();
+    let dot = render_dot(&collect_graph(watch));
+    std::fs::write(path, dot).map_err(|e| format!("Failed to write graph {}: {}", path.display(), e))
+}
+
+fn collect_graph(watch: &MemWatch) -> RegionGraph {
+    let regions = watch.regions.lock().unwrap().clone();
+    let mut ids: Vec<u32> = regions.keys().copied().collect();
+    ids.sort_unstable();
+    let usage = watch.usage.lock().unwrap();
+    let nodes: Vec<GraphNode> = ids
+        .iter()
+        .map(|&id| {
+            let info = &regions[&id];
+            let cluster = watch.region_group(id).or_else(|| info.tags.first().cloned());
+            GraphNode { region_id: id, label: info.name.clone(), changes: usage.writes(id), cluster }
+        })
+        .collect();
+    drop(usage);
+
+    let rates: Vec<(u32, HashMap<u64, u64>)> = nodes
+        .iter()
+        .filter(|node| node.changes >= MIN_CORRELATED_CHANGES)
+        .map(|node| {
+            let buckets = watch.timeline(node.region_id, CORRELATION_BUCKET);
+            (node.region_id, buckets.iter().filter(|b| b.events > 0).map(|b| (b.start_ns, b.events)).collect())
+        })
+        .collect();
+    let mut correlations = Vec::new();
+    for (i, (a, rate_a)) in rates.iter().enumerate() {
+        for (b, rate_b) in &rates[i + 1..] {
+            if let Some(r) = correlation(rate_a, rate_b).filter(|&r| r >= MIN_CORRELATION) {
+                correlations.push((*a, *b, r));
+            }
+        }
+    }
+    RegionGraph { nodes, correlations, causality: watch.causality_graph() }
+}
+
+/// Pearson coefficient of two sparse per-bucket change counts, over the
+/// buckets spanning both
+fn correlation(a: &HashMap<u64, u64>, b: &HashMap<u64, u64>) -> Option<f64> {
+    let bucket_ns = CORRELATION_BUCKET.as_nanos() as u64;
+    let first = a.keys().chain(b.keys()).min()?;
+    let last = a.keys().chain(b.keys()).max()?;
+    let n = ((last - first) / bucket_ns + 1) as f64;
+    let sums = |counts: &HashMap<u64, u64>| {
+        counts.values().fold((0.0, 0.0), |(sum, squares), &c| (sum + c as f64, squares + (c * c) as f64))
+    };
+    let ((sum_a, squares_a), (sum_b, squares_b)) = (sums(a), sums(b));
+    let products: f64 = a.iter().filter_map(|(start, &x)| b.get(start).map(|&y| (x * y) as f64)).sum();
+    let covariance = n * products - sum_a * sum_b;
+    let spread = ((n * squares_a - sum_a * sum_a) * (n * squares_b - sum_b * sum_b)).sqrt();
+    (spread > 0.0).then(|| covariance / spread)
+}
+
+fn render_dot(graph: &RegionGraph) -> String {
+    let max_changes = graph.nodes.iter().map(|node| node.changes).max().unwrap_or(0).max(1);
+    let mut clusters: BTreeMap<Option<&str>, Vec<String>> = BTreeMap::new();
+    for node in &graph.nodes {
+        let scale = 1.0 + 2.0 * (node.changes as f64 / max_changes as f64).sqrt();
+        let label = format!("{}\\n{} changes", node.label.replace('\\', "\\\\").replace('"', "\\\""), node.changes);
+        clusters.entry(node.cluster.as_deref()).or_default().push(format!(
+            "r{} [label=\"{}\", width={:.2}, height={:.2}];",
+            node.region_id,
+            label,
+            0.75 * scale,
+            0.5 * scale
+        ));
+    }
+
+    let mut dot = String::from("digraph regions {\n    node [shape=box];\n");
+    for (i, (tag, nodes)) in clusters.iter().enumerate() {
+        let indent = match tag {
+            Some(tag) => {
+                let _ = writeln!(dot, "    subgraph cluster_{} {{\n        label={};", i, dot_str(tag));
+                "        "
+            }
+            None => "    ",
+        };
+        for node in nodes {
+            let _ = writeln!(dot, "{}{}", indent, node);
+        }
+        if tag.is_some() {
+            dot.push_str("    }\n");
+        }
+    }
+    for &(a, b, r) in &graph.correlations {
+        let _ = writeln!(dot, "    r{} -> r{} [dir=none, style=dashed, label=\"r={:.2}\"];", a, b, r);
+    }
+    for edge in &graph.causality.edges {
+        let _ = writeln!(
+            dot,
+            "    r{} -> r{} [label=\"{}x, {}us\", penwidth={:.1}];",
+            edge.from,
+            edge.to,
+            edge.count,
+            edge.mean_lag.as_micros(),
+            1.0 + 3.0 * edge.consistency
+        );
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_correlation_of_rates() {
+        let bucket = CORRELATION_BUCKET.as_nanos() as u64;
+        let rate = |counts: &[(u64, u64)]| counts.iter().map(|&(i, c)| (i * bucket, c)).collect::<HashMap<u64, u64>>();
+        let a = rate(&[(0, 5), (2, 5), (4, 5)]);
+        assert!((correlation(&a, &rate(&[(0, 2), (2, 2), (4, 2)])).unwrap() - 1.0).abs() < 1e-9);
+        assert!(correlation(&a, &rate(&[(1, 3), (3, 3)])).unwrap() < -0.9);
+        // Constant over the span: no spread to correlate
+        assert_eq!(correlation(&rate(&[(0, 1), (1, 1)]), &rate(&[(0, 1), (1, 1)])), None);
+    }
+
+    #[test]
+    fn test_dot_sizes_nodes_and_clusters_tags() {
+        let node = |region_id, label: &str, changes, cluster: Option<&str>| GraphNode {
+            region_id,
+            label: label.to_string(),
+            changes,
+            cluster: cluster.map(str::to_string),
+        };
+        let graph = RegionGraph {
+            nodes: vec![node(1, "table", 100, Some("db")), node(2, "index", 25, Some("db")), node(3, "log", 0, None)],
+            correlations: vec![(1, 2, 0.95)],
+            causality: CausalityGraph {
+                edges: vec![crate::causality::CausalEdge {
+                    from: 1,
+                    to: 2,
+                    from_name: "table".to_string(),
+                    to_name: "index".to_string(),
+                    count: 4,
+                    consistency: 1.0,
+                    mean_lag: Duration::from_micros(20),
+                }],
+            },
+        };
+        let dot = render_dot(&graph);
+        assert!(dot.contains("subgraph cluster_1 {\n        label=\"db\";\n        r1 [label=\"table\\n100 changes\", width=2.25, height=1.50];"), "{}", dot);
+        assert!(dot.contains("        r2 [label=\"index\\n25 changes\", width=1.50, height=1.00];"), "{}", dot);
+        assert!(dot.contains("\n    r3 [label=\"log\\n0 changes\", width=0.75, height=0.50];"), "{}", dot);
+        assert!(dot.contains("r1 -> r2 [dir=none, style=dashed, label=\"r=0.95\"];"), "{}", dot);
+        assert!(dot.contains("r1 -> r2 [label=\"4x, 20us\", penwidth=4.0];"), "{}", dot);
+    }
+}
@@ -0,0 +1,264 @@
+// Region groups
+//
+// A WatchGroup gathers related regions (the fields of one cache, the
+// buffers of one connection) so they can be handled as one:
+//
+//   let cache = watcher.group("cache");
+//   cache.watch(&entries, "entries")?;
+//   cache.watch(&lru, "lru")?;
+//   cache.disable();                     // their changes are dropped
+//   ...
+//   println!("{:?}", cache.stats());
+//   cache.unwatch_all()?;
+//
+// Events of a member carry the group's name in ChangeEvent::group next to
+// the region's own name (qualified_name() joins them as "cache.entries"),
+// and filters can match on it: `group = 'cache'`. Group names may be dotted
+// paths ("net.conn.7") for a hierarchy; `group contains 'net.'` then selects
+// a whole branch.
+//
+// Disabling a group drops its members' changes as they reach the Rust
+// layer; the backend keeps watching them, so the writes still cost a fault
+// each. Markers always pass. A group lives as long as the watcher, and its
+// stats survive its members being unwatched.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::options::WatchOptions;
+use crate::{ChangeEvent, MemWatch, NativeError, Unwatch};
+
+/// Totals over what a group's members reported through check_changes()
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GroupStats {
+    /// Members currently watched
+    pub regions: usize,
+    pub events: u64,
+    /// Bytes that differ between old and new contents, summed over events
+    pub bytes_changed: u64,
+    /// Changes dropped while the group was disabled
+    pub dropped: u64,
+}
+
+#[derive(Debug, Clone)]
+struct GroupState {
+    members: BTreeSet<u32>,
+    enabled: bool,
+    stats: GroupStats,
+}
+
+impl Default for GroupState {
+    fn default() -> Self {
+        GroupState { members: BTreeSet::new(), enabled: true, stats: GroupStats::default() }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Groups {
+    groups: HashMap<String, GroupState>,
+    group_of: HashMap<u32, String>,
+}
+
+impl Groups {
+    /// Set each event's group, dropping changes of disabled groups
+    pub(crate) fn tag(&mut self, events: &mut Vec<ChangeEvent>) {
+        events.retain_mut(|event| {
+            let Some(name) = self.group_of.get(&event.region_id) else {
+                return true;
+            };
+            let state = self.groups.get_mut(name).expect("member of a known group");
+            if !state.enabled && !event.kind.is_marker() {
+                state.stats.dropped += 1;
+                return false;
+            }
+            event.group = Some(name.clone());
+            true
+        });
+    }
+
+    pub(crate) fn record(&mut self, event: &ChangeEvent) {
+        let Some(state) = event.group.as_ref().and_then(|name| self.groups.get_mut(name)) else {
+            return;
+        };
+        if !event.kind.is_marker() {
+            state.stats.events += 1;
+            state.stats.bytes_changed += crate::timeline::bytes_changed(event);
+        }
+    }
+
+    pub(crate) fn forget_region(&mut self, region_id: u32) {
+        if let Some(name) = self.group_of.remove(&region_id) {
+            if let Some(state) = self.groups.get_mut(&name) {
+                state.members.remove(&region_id);
+            }
+        }
+    }
+
+    fn join(&mut self, group: &str, region_id: u32) {
+        self.forget_region(region_id);
+        self.groups.entry(group.to_string()).or_default().members.insert(region_id);
+        self.group_of.insert(region_id, group.to_string());
+    }
+}
+
+/// Handle on a group of regions; see the group module
+pub struct WatchGroup<'a> {
+    watcher: &'a MemWatch,
+    name: String,
+}
+
+impl MemWatch {
+    /// The group called `name`, created empty (and enabled) on first use
+    pub fn group(&self, name: &str) -> WatchGroup<'_> {
+        self.groups.lock().unwrap().groups.entry(name.to_string()).or_default();
+        WatchGroup { watcher: self, name: name.to_string() }
+    }
+
+    /// Names of all groups, sorted
+    pub fn group_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.groups.lock().unwrap().groups.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// The group a region belongs to
+    pub fn region_group(&self, region_id: u32) -> Option<String> {
+        self.groups.lock().unwrap().group_of.get(&region_id).cloned()
+    }
+}
+
+impl WatchGroup<'_> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Watch a buffer as a member of the group
+    pub fn watch(&self, buffer: &[u8], name: &str) -> Result<u32, String> {
+        self.watch_with_options(buffer, name, &WatchOptions::default())
+    }
+
+    /// watch_with_options(), as a member of the group
+    pub fn watch_with_options(&self, buffer: &[u8], name: &str, options: &WatchOptions) -> Result<u32, String> {
+        let region_id = self.watcher.watch_with_options(buffer, name, options)?;
+        self.groups().join(&self.name, region_id);
+        Ok(region_id)
+    }
+
+    /// Move an already watched region into the group
+    pub fn add(&self, region_id: u32) -> Result<(), String> {
+        if !self.watcher.regions.lock().unwrap().contains_key(&region_id) {
+            return Err(format!("Region {} is not watched", region_id));
+        }
+        self.groups().join(&self.name, region_id);
+        Ok(())
+    }
+
+    /// Take a region out of the group; it stays watched
+    pub fn remove(&self, region_id: u32) {
+        let mut groups = self.groups();
+        if groups.group_of.get(&region_id) == Some(&self.name) {
+            groups.forget_region(region_id);
+        }
+    }
+
+    /// Members, by id
+    pub fn regions(&self) -> Vec<u32> {
+        self.with_state(|state| state.members.iter().copied().collect())
+    }
+
+    pub fn enable(&self) {
+        self.with_state(|state| state.enabled = true);
+    }
+
+    /// Drop the members' changes until enable()
+    pub fn disable(&self) {
+        self.with_state(|state| state.enabled = false);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.with_state(|state| state.enabled)
+    }
+
+    pub fn stats(&self) -> GroupStats {
+        self.with_state(|state| GroupStats { regions: state.members.len(), ..state.stats })
+    }
+
+    /// Unwatch every member, returning how many were removed; stops at the
+    /// first backend error
+    pub fn unwatch_all(&self) -> Result<usize, NativeError> {
+        let mut removed = 0;
+        for region_id in self.regions() {
+            if self.watcher.unwatch(region_id)? == Unwatch::Removed {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn groups(&self) -> std::sync::MutexGuard<'_, Groups> {
+        self.watcher.groups.lock().unwrap()
+    }
+
+    fn with_state<R>(&self, f: impl FnOnce(&mut GroupState) -> R) -> R {
+        f(self.groups().groups.entry(self.name.clone()).or_default())
+    }
+}
+
+impl ChangeEvent {
+    /// "group.name" for members of a group, the region's name otherwise
+    pub fn qualified_name(&self) -> Option<String> {
+        match (&self.group, &self.variable_name) {
+            (Some(group), Some(name)) => Some(format!("{}.{}", group, name)),
+            (None, name) => name.clone(),
+            (Some(group), None) => Some(group.clone()),
+        }
+    }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native::{self, FakeEvent};
+    use crate::{EventKind, Filter};
+
+    fn changes(watcher: &MemWatch) -> Vec<ChangeEvent> {
+        watcher.check_changes().unwrap().into_iter().filter(|e| e.kind == EventKind::Change).collect()
+    }
+
+    #[test]
+    fn test_group_members_share_name_switch_and_stats() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Protect;
+        let buffer = [0u8; 12];
+        let cache = watcher.group("cache");
+        let entries = cache.watch(&buffer[..4], "entries").unwrap();
+        let lru = cache.watch(&buffer[4..8], "lru").unwrap();
+        let other = watcher.watch(&buffer[8..], "other").unwrap();
+        assert_eq!(cache.regions(), [entries, lru]);
+        assert_eq!(watcher.region_group(lru).as_deref(), Some("cache"));
+        assert_eq!(watcher.group_names(), ["cache"]);
+
+        fake_native::state().events.extend([FakeEvent::change(entries, &[1, 2]), FakeEvent::change(other, &[1])]);
+        let events = changes(&watcher);
+        let groups: Vec<(u32, Option<&str>)> = events.iter().map(|e| (e.region_id, e.group.as_deref())).collect();
+        assert_eq!(groups, [(entries, Some("cache")), (other, None)]);
+        let name = events[0].variable_name.clone().unwrap();
+        assert_eq!(events[0].qualified_name(), Some(format!("cache.{}", name)));
+        assert_eq!(events[1].qualified_name(), events[1].variable_name);
+
+        cache.disable();
+        fake_native::state().events.extend([FakeEvent::change(lru, &[1]), FakeEvent::change(other, &[2])]);
+        assert_eq!(changes(&watcher).iter().map(|e| e.region_id).collect::<Vec<_>>(), [other]);
+        cache.enable();
+        watcher.set_filter(Some(Filter::parse("group = 'cache'").unwrap()));
+        fake_native::state().events.extend([FakeEvent::change(lru, &[1]), FakeEvent::change(other, &[3])]);
+        assert_eq!(changes(&watcher).iter().map(|e| e.region_id).collect::<Vec<_>>(), [lru]);
+
+        assert_eq!(cache.stats(), GroupStats { regions: 2, events: 2, bytes_changed: 3, dropped: 1 });
+        assert_eq!(cache.unwatch_all().unwrap(), 2);
+        assert!(cache.regions().is_empty());
+        assert_eq!(watcher.regions.lock().unwrap().keys().copied().collect::<Vec<_>>(), [other]);
+        assert_eq!(cache.stats().events, 2);
+    }
+}
@@ -0,0 +1,57 @@
+// Scoped watches
+//
+// `MemWatch::watch` hands out a bare region id, so nothing ties the watched
+// buffer to the watch: dropping or moving it leaves the backend pointing at
+// stale memory. A WatchGuard takes the mutable borrow of the buffer for as
+// long as the watch exists (writes go through the guard) and unwatches when
+// dropped, so the borrow checker rules out both.
+
+use std::ops::{Deref, DerefMut};
+
+use crate::{MemWatch, Unwatch};
+
+/// A watch that ends when the guard is dropped; derefs to the buffer
+pub struct WatchGuard<'a> {
+    watcher: &'a MemWatch,
+    buffer: &'a mut [u8],
+    region_id: u32,
+}
+
+impl<'a> WatchGuard<'a> {
+    pub(crate) fn new(watcher: &'a MemWatch, buffer: &'a mut [u8], region_id: u32) -> Self {
+        WatchGuard { watcher, buffer, region_id }
+    }
+
+    pub fn region_id(&self) -> u32 {
+        self.region_id
+    }
+
+    /// End the watch now, reporting what the backend did
+    pub fn unwatch(self) -> Result<Unwatch, crate::NativeError> {
+        let result = self.watcher.unwatch(self.region_id);
+        std::mem::forget(self);
+        result
+    }
+}
+
+impl Deref for WatchGuard<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.buffer
+    }
+}
+
+impl DerefMut for WatchGuard<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.buffer
+    }
+}
+
+impl Drop for WatchGuard<'_> {
+    fn drop(&mut self) {
+        // A failed native unwatch leaves the region registered; unwatch_all()
+        // or dropping the watcher retries it
+        let _ = self.watcher.unwatch(self.region_id);
+    }
+}
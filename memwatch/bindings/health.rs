@@ -0,0 +1,177 @@
+// Health of the instrumentation layer, for orchestration
+//
+// health() is cheap enough to call from a liveness or readiness probe: one
+// stats call and a few lock reads, no watching. serve_health() answers
+// `GET /healthz` on a listener with the same information as JSON, status
+// 200 while healthy and 503 otherwise, so a degraded watcher can be taken
+// out of rotation instead of silently losing events.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::MemWatch;
+
+/// Snapshot of the watcher's health
+#[derive(Debug, Clone, PartialEq)]
+pub struct Health {
+    /// The backend answers and the watcher is not poisoned
+    pub backend_ok: bool,
+    /// Whether the native worker makes progress; None when there is no
+    /// worker (Snapshot mode) or no watchdog to tell (set_watchdog)
+    pub worker_alive: Option<bool>,
+    /// Share of native events dropped because the ring was full, 0.0-1.0
+    pub ring_pressure: f64,
+    /// How long sink delivery has been failing; zero while sinks keep up
+    pub sink_lag: Duration,
+}
+
+impl Health {
+    /// Backend working, worker not stalled and sinks delivering
+    pub fn is_healthy(&self) -> bool {
+        self.backend_ok && self.worker_alive != Some(false) && self.sink_lag.is_zero()
+    }
+
+    /// `{"healthy":..,"backend_ok":..,"worker_alive":..,"ring_pressure":..,"sink_lag_ms":..}`
+    pub fn to_json(&self) -> String {
+        let worker_alive = match self.worker_alive {
+            Some(alive) => alive.to_string(),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"healthy\":{},\"backend_ok\":{},\"worker_alive\":{},\"ring_pressure\":{:.4},\"sink_lag_ms\":{}}}",
+            self.is_healthy(),
+            self.backend_ok,
+            worker_alive,
+            self.ring_pressure,
+            self.sink_lag.as_millis()
+        )
+    }
+}
+
+impl MemWatch {
+    /// Current health of the watcher; see the health module
+    pub fn health(&self) -> Health {
+        let stats = self.backend().stats().ok();
+        let ring_pressure = match &stats {
+            Some(stats) if stats.ring_write_count > 0 => {
+                (stats.ring_drop_count as f64 / stats.ring_write_count as f64).min(1.0)
+            }
+            _ => 0.0,
+        };
+        let worker_alive = if self.capabilities.is_degraded() {
+            None
+        } else {
+            self.watchdog.lock().unwrap().worker_alive()
+        };
+        Health {
+            backend_ok: stats.is_some() && !self.poisoned.load(Ordering::SeqCst),
+            worker_alive,
+            ring_pressure,
+            sink_lag: self.sink_failing_since.lock().unwrap().map(|since| since.elapsed()).unwrap_or_default(),
+        }
+    }
+
+    /// Answer `GET /healthz` on `listener` from a background thread, for
+    /// as long as the process runs
+    pub fn serve_health(self: &Arc<Self>, listener: TcpListener) -> JoinHandle<()> {
+        let watch = Arc::clone(self);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // A broken probe connection only affects that probe
+                let _ = respond(&watch, stream);
+            }
+        })
+    }
+}
+
+fn respond(watch: &MemWatch, mut stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/healthz")) => {
+            let health = watch.health();
+            let status = if health.is_healthy() { "200 OK" } else { "503 Service Unavailable" };
+            (status, health.to_json())
+        }
+        _ => ("404 Not Found", "{\"error\":\"not found\"}".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native;
+    use crate::sink::EventSink;
+    use crate::watchdog::Watchdog;
+    use crate::ChangeEvent;
+    use std::io::Read;
+
+    struct FailingSink;
+
+    impl EventSink for FailingSink {
+        fn write(&mut self, _event: &ChangeEvent) -> Result<(), String> {
+            Err("disk full".to_string())
+        }
+    }
+
+    #[test]
+    fn test_health_reports_stalls_and_sink_failures() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Protect;
+        let health = watcher.health();
+        assert!(health.is_healthy());
+        assert_eq!((health.worker_alive, health.ring_pressure), (None, 0.0));
+
+        watcher.set_watchdog(Some(Watchdog { stall_after: Duration::ZERO, restart: false }));
+        watcher.check_changes().unwrap();
+        assert_eq!(watcher.health().worker_alive, Some(true));
+        watcher.check_changes().unwrap();
+        assert_eq!(watcher.health().worker_alive, Some(false));
+        fake_native::state().worker_cycles = 1;
+        watcher.check_changes().unwrap();
+        assert!(watcher.health().is_healthy());
+
+        let buffer = [0u8; 4];
+        watcher.add_sink(FailingSink);
+        watcher.watch(&buffer, "buffer").unwrap();
+        assert!(watcher.check_changes().is_err());
+        assert!(!watcher.health().is_healthy());
+    }
+
+    #[test]
+    fn test_healthz_endpoint() {
+        let _guard = fake_native::lock();
+        let watcher = Arc::new(MemWatch::new().unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        watcher.serve_health(listener);
+
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: test\r\n\r\n", path).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let response = get("/healthz");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("\"worker_alive\":null,\"ring_pressure\":0.0000,\"sink_lag_ms\":0}"));
+        assert!(get("/metrics").starts_with("HTTP/1.1 404"));
+    }
+}
@@ -0,0 +1,394 @@
+// Hardware watchpoints
+//
+// Page protection faults on every write to a watched page, neighbours
+// included, which is costly for small, hot values. A region watched with
+// RegionBackend::HardwareBreakpoint (see WatchOptions) uses a CPU debug
+// register instead: a write breakpoint through perf_event_open (Linux,
+// PERF_TYPE_BREAKPOINT), counting writes to exactly those bytes and nothing
+// else, with no signal and no fault handling.
+//
+// Constraints come from the hardware: 1, 2, 4 or 8 bytes, aligned to their
+// size, and a handful of debug registers per thread (4 on x86), so the
+// kernel refuses breakpoints beyond that. Debug registers are per thread:
+// each poll arms breakpoints on threads started since, so writes by a new
+// thread before its first poll are missed. Counting gives no fault address:
+// events carry the values but no location or thread, and several writes
+// between two polls make one event, counted in its write_count.
+//
+// Region ids start at HW_ID_BASE, so they never collide with the main
+// backend's ids (or CountOnly regions, above 1 << 31).
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crate::backend::Backend;
+use crate::error::{CheckError, NativeError};
+use crate::lifecycle::{now_ns, EventSource};
+use crate::polling::PREVIEW_SIZE;
+use crate::{ChangeEvent, EventKind, Location, Stats};
+
+pub(crate) const HW_ID_BASE: u32 = 1 << 30;
+
+/// Whether `region_id` belongs to the hardware backend
+pub(crate) fn owns(region_id: u32) -> bool {
+    (HW_ID_BASE..1 << 31).contains(&region_id)
+}
+
+struct HwRegion {
+    name: String,
+    addr: usize,
+    len: usize,
+    max_value_bytes: i32,
+    breakpoints: sys::Breakpoints,
+    /// Writes already reported
+    reported: u64,
+    last: Vec<u8>,
+}
+
+impl HwRegion {
+    fn current(&self) -> Vec<u8> {
+        // SAFETY: watched regions stay valid until unwatched; volatile
+        // because other threads write them
+        (0..self.len).map(|i| unsafe { std::ptr::read_volatile((self.addr + i) as *const u8) }).collect()
+    }
+
+    fn value(&self, bytes: &[u8]) -> Vec<u8> {
+        match self.max_value_bytes {
+            0 => Vec::new(),
+            n if n < 0 => bytes.to_vec(),
+            n => bytes[..bytes.len().min(n as usize)].to_vec(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct HardwareBackend {
+    regions: Mutex<BTreeMap<u32, HwRegion>>,
+    next_id: Mutex<u32>,
+    total_events: Mutex<u64>,
+}
+
+impl HardwareBackend {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.regions.lock().unwrap().is_empty()
+    }
+}
+
+impl Backend for HardwareBackend {
+    fn init(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn shutdown(&self) {
+        self.regions.lock().unwrap().clear();
+    }
+
+    fn watch(&self, buffer: &[u8], name: &str, max_value_bytes: i32) -> Result<u32, String> {
+        let (addr, len) = (buffer.as_ptr() as usize, buffer.len());
+        if !matches!(len, 1 | 2 | 4 | 8) || !addr.is_multiple_of(len) {
+            return Err(format!(
+                "Cannot watch {} with a hardware breakpoint: {} bytes at 0x{:x}; needs 1, 2, 4 or 8 bytes aligned to their size",
+                name, len, addr
+            ));
+        }
+        let breakpoints = sys::Breakpoints::arm(addr, len).map_err(|e| format!("Cannot watch {} with a hardware breakpoint: {}", name, e))?;
+        let mut next_id = self.next_id.lock().unwrap();
+        *next_id += 1;
+        let region_id = HW_ID_BASE + *next_id;
+        let region = HwRegion {
+            name: name.to_string(),
+            addr,
+            len,
+            max_value_bytes,
+            breakpoints,
+            reported: 0,
+            last: buffer.to_vec(),
+        };
+        self.regions.lock().unwrap().insert(region_id, region);
+        Ok(region_id)
+    }
+
+    fn unwatch(&self, region_id: u32) -> Result<(), NativeError> {
+        // Closing the descriptors disarms the breakpoints
+        self.regions.lock().unwrap().remove(&region_id);
+        Ok(())
+    }
+
+    fn poll(&self, max_events: usize) -> Result<Vec<ChangeEvent>, CheckError> {
+        let mut events = Vec::new();
+        let timestamp_ns = now_ns();
+        for (&region_id, region) in self.regions.lock().unwrap().iter_mut() {
+            if events.len() >= max_events {
+                break;
+            }
+            region.breakpoints.follow_threads(region.addr, region.len);
+            let writes = region.breakpoints.writes();
+            if writes <= region.reported {
+                continue;
+            }
+            // Every write since the last poll, however many made one change
+            let write_count = (writes - region.reported).min(u32::MAX as u64) as u32;
+            region.reported = writes;
+            let current = region.current();
+            events.push(ChangeEvent {
+                seq: 0,
+                timestamp_ns,
+                adapter_id: 0,
+                region_id,
+                variable_name: Some(region.name.clone()),
+                group: None,
+                where_: Location::default(),
+                old_preview: region.last[..region.len.min(PREVIEW_SIZE)].to_vec(),
+                new_preview: current[..region.len.min(PREVIEW_SIZE)].to_vec(),
+                old_value: region.value(&region.last),
+                new_value: region.value(&current),
+                storage_key_old: None,
+                storage_key_new: None,
+                kind: EventKind::Change,
+                epoch: 0,
+                global_seq: 0,
+                tid: None,
+                thread_name: None,
+                frames: Vec::new(),
+                allocated_at: None,
+                source: EventSource::Observed,
+                preview_limit: None,
+                deltas: Vec::new(),
+                span_id: None,
+                span_name: None,
+                write_count,
+            });
+            region.last = current;
+        }
+        *self.total_events.lock().unwrap() += events.len() as u64;
+        Ok(events)
+    }
+
+    fn stats(&self) -> Result<Stats, String> {
+        let regions = self.regions.lock().unwrap();
+        Ok(Stats {
+            num_tracked_regions: regions.len() as u32,
+            num_active_watchpoints: regions.values().map(|r| r.breakpoints.armed() as u32).sum(),
+            total_events: *self.total_events.lock().unwrap(),
+            ring_write_count: 0,
+            ring_drop_count: 0,
+            storage_bytes_used: 0,
+            mprotect_page_count: 0,
+            worker_thread_id: 0,
+            worker_cycles: 0,
+            sampled_out_count: 0,
+            consumers: Vec::new(),
+            events_per_thread: Vec::new(),
+        })
+    }
+
+    fn resync(&self, region_id: u32) {
+        if let Some(region) = self.regions.lock().unwrap().get_mut(&region_id) {
+            region.reported = region.breakpoints.writes();
+            region.last = region.current();
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::collections::hash_map::{Entry, HashMap};
+    use std::fs;
+    use std::io;
+
+    const PERF_TYPE_BREAKPOINT: u32 = 5;
+    const HW_BREAKPOINT_W: u32 = 2;
+    const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 8;
+    /// exclude_kernel | exclude_hv
+    const EXCLUDE_KERNEL_HV: u64 = (1 << 5) | (1 << 6);
+
+    /// struct perf_event_attr (PERF_ATTR_SIZE_VER5)
+    #[repr(C)]
+    #[derive(Default)]
+    struct PerfEventAttr {
+        kind: u32,
+        size: u32,
+        config: u64,
+        sample_period: u64,
+        sample_type: u64,
+        read_format: u64,
+        flags: u64,
+        wakeup_events: u32,
+        bp_type: u32,
+        bp_addr: u64,
+        bp_len: u64,
+        branch_sample_type: u64,
+        sample_regs_user: u64,
+        sample_stack_user: u32,
+        clockid: i32,
+        sample_regs_intr: u64,
+        aux_watermark: u32,
+        sample_max_stack: u16,
+        reserved: u16,
+    }
+
+    /// One write breakpoint per thread of the process
+    pub(super) struct Breakpoints {
+        /// tid -> counting perf event
+        fds: HashMap<i32, i32>,
+        /// Writes by threads that have exited
+        retired: u64,
+    }
+
+    impl Breakpoints {
+        pub(super) fn arm(addr: usize, len: usize) -> Result<Breakpoints, String> {
+            let mut breakpoints = Breakpoints { fds: HashMap::new(), retired: 0 };
+            for tid in threads() {
+                match open(tid, addr, len) {
+                    Ok(fd) => {
+                        breakpoints.fds.insert(tid, fd);
+                    }
+                    // The thread exited meanwhile
+                    Err(e) if e.raw_os_error() == Some(libc::ESRCH) => {}
+                    Err(e) => {
+                        let reason = match e.raw_os_error() {
+                            Some(libc::ENOSPC) => "no free debug register".to_string(),
+                            Some(libc::EACCES) | Some(libc::EPERM) => format!("perf_event_open not permitted ({})", e),
+                            _ => format!("perf_event_open failed ({})", e),
+                        };
+                        return Err(reason);
+                    }
+                }
+            }
+            Ok(breakpoints)
+        }
+
+        /// Arm threads started since, and fold in exited ones
+        pub(super) fn follow_threads(&mut self, addr: usize, len: usize) {
+            let live = threads();
+            let exited: Vec<i32> = self.fds.keys().copied().filter(|tid| !live.contains(tid)).collect();
+            for tid in exited {
+                let fd = self.fds.remove(&tid).expect("tid taken from map");
+                self.retired += count(fd);
+                unsafe { libc::close(fd) };
+            }
+            for tid in live {
+                if let Entry::Vacant(entry) = self.fds.entry(tid) {
+                    if let Ok(fd) = open(tid, addr, len) {
+                        entry.insert(fd);
+                    }
+                }
+            }
+        }
+
+        /// Writes so far, by every thread
+        pub(super) fn writes(&self) -> u64 {
+            self.retired + self.fds.values().map(|&fd| count(fd)).sum::<u64>()
+        }
+
+        pub(super) fn armed(&self) -> usize {
+            self.fds.len()
+        }
+    }
+
+    impl Drop for Breakpoints {
+        fn drop(&mut self) {
+            for &fd in self.fds.values() {
+                unsafe { libc::close(fd) };
+            }
+        }
+    }
+
+    fn threads() -> Vec<i32> {
+        let Ok(entries) = fs::read_dir("/proc/self/task") else {
+            return Vec::new();
+        };
+        entries.filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok()).collect()
+    }
+
+    fn open(tid: i32, addr: usize, len: usize) -> io::Result<i32> {
+        let attr = PerfEventAttr {
+            kind: PERF_TYPE_BREAKPOINT,
+            size: std::mem::size_of::<PerfEventAttr>() as u32,
+            flags: EXCLUDE_KERNEL_HV,
+            bp_type: HW_BREAKPOINT_W,
+            bp_addr: addr as u64,
+            bp_len: len as u64,
+            ..PerfEventAttr::default()
+        };
+        // SAFETY: attr is a valid perf_event_attr of the size it declares
+        let fd = unsafe { libc::syscall(libc::SYS_perf_event_open, &attr as *const PerfEventAttr, tid, -1, -1, PERF_FLAG_FD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(fd as i32)
+    }
+
+    fn count(fd: i32) -> u64 {
+        let mut value = 0u64;
+        // SAFETY: reading one u64 counter value into a u64
+        let read = unsafe { libc::read(fd, &mut value as *mut u64 as *mut libc::c_void, 8) };
+        if read == 8 {
+            value
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    pub(super) struct Breakpoints;
+
+    impl Breakpoints {
+        pub(super) fn arm(_addr: usize, _len: usize) -> Result<Breakpoints, String> {
+            Err("hardware breakpoints need Linux perf events".to_string())
+        }
+
+        pub(super) fn follow_threads(&mut self, _addr: usize, _len: usize) {}
+
+        pub(super) fn writes(&self) -> u64 {
+            0
+        }
+
+        pub(super) fn armed(&self) -> usize {
+            0
+        }
+    }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::fake_native;
+    use crate::options::{RegionBackend, WatchOptions};
+    use crate::MemWatch;
+
+    #[test]
+    fn test_hardware_regions_need_a_breakpoint_size() {
+        let _guard = fake_native::lock();
+        let watcher = MemWatch::new().unwrap();
+        let options = WatchOptions { backend: RegionBackend::HardwareBreakpoint, ..WatchOptions::default() };
+        let odd = [0u8; 3];
+        assert!(watcher.watch_with_options(&odd, "odd", &options).unwrap_err().contains("1, 2, 4 or 8 bytes"));
+    }
+
+    #[test]
+    #[ignore = "needs perf events (a PMU, and perf_event_paranoid allowing breakpoints)"]
+    fn test_hardware_regions_report_their_writes() {
+        let _guard = fake_native::lock();
+        let watcher = MemWatch::new().unwrap();
+        let options = WatchOptions { backend: RegionBackend::HardwareBreakpoint, ..WatchOptions::default() };
+
+        // Written only through the raw pointer, never through a reference
+        // while the watched slice is alive
+        let counter = Box::into_raw(Box::new(0u64));
+        let bytes = unsafe { std::slice::from_raw_parts(counter as *const u8, 8) };
+        let region_id = watcher.watch_with_options(bytes, "counter", &options).unwrap();
+        assert!(owns(region_id));
+        watcher.check_changes().unwrap();
+        unsafe { std::ptr::write_volatile(counter, 6) };
+        unsafe { std::ptr::write_volatile(counter, 7) };
+        let events = watcher.check_changes().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].region_id, events[0].new_preview[0], events[0].write_count), (region_id, 7, 2));
+        assert!(watcher.check_changes().unwrap().is_empty());
+        watcher.unwatch(region_id).unwrap();
+        drop(unsafe { Box::from_raw(counter) });
+    }
+}
@@ -0,0 +1,354 @@
+// Page sharing between watched regions
+//
+// Page protection works on whole pages: a write anywhere on a protected page
+// faults, and the fault is checked against every region on it. Small regions
+// the allocator packed next to each other, or next to hot data, therefore pay
+// for each other's writes. In Protect mode watching a region that shares a
+// page queues one SharedPageWarning per neighbour; shared_pages() lists every
+// such page with an estimate of the extra faults. Count-only regions
+// (watch_count_only) mark known-hot data: their writes count as neighbours'.
+//
+// IsolatedPage moves a value onto pages of its own, so watching it only
+// faults on its own writes. IsolatedBox does the same and watches the value
+// for as long as the box lives. Both expose the value's bytes, so it must
+// have no padding (bytemuck::NoUninit). If unwatching fails when it is dropped, the
+// pages are leaked rather than handed back to the allocator while still
+// protected.
+
+use std::alloc::{self, Layout};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+
+use bytemuck::NoUninit;
+
+use crate::capabilities;
+use crate::lifecycle::RegionInfo;
+use crate::{MemWatch, NativeError, Unwatch};
+
+/// A page holding more than one region
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedPage {
+    /// Start address of the page
+    pub page: u64,
+    pub page_size: usize,
+    /// Watched and count-only regions on the page, ascending
+    pub region_ids: Vec<u32>,
+    /// Estimated faults the watched regions here took for the others so
+    /// far: per watched region, the writes observed on its neighbours
+    pub extra_faults: u64,
+}
+
+impl MemWatch {
+    /// Pages shared by several regions, by address
+    pub fn shared_pages(&self) -> Vec<SharedPage> {
+        // page -> (page size, watched (id, writes), count-only (id, writes))
+        type Members = (usize, Vec<(u32, u64)>, Vec<(u32, u64)>);
+        let mut pages: BTreeMap<u64, Members> = BTreeMap::new();
+        let usage = self.usage.lock().unwrap();
+        for (&region_id, info) in self.regions.lock().unwrap().iter() {
+            for page in info.page_starts() {
+                let entry = pages.entry(page).or_insert_with(|| (info.page_size, Vec::new(), Vec::new()));
+                entry.1.push((region_id, usage.writes(region_id)));
+            }
+        }
+        for (region_id, start, _, writes) in self.counting.lock().unwrap().chunks() {
+            let start = start as u64;
+            if let Some((_, entry)) = pages.range_mut(..=start).next_back().filter(|(&page, entry)| start < page + entry.0 as u64) {
+                entry.2.push((region_id, writes));
+            }
+        }
+
+        pages
+            .into_iter()
+            .filter(|(_, (_, watched, counted))| watched.len() + counted.len() > 1)
+            .map(|(page, (page_size, watched, counted))| {
+                let total: u64 = watched.iter().chain(&counted).map(|&(_, writes)| writes).sum();
+                let mut region_ids: Vec<u32> = watched.iter().chain(&counted).map(|&(id, _)| id).collect();
+                region_ids.sort_unstable();
+                region_ids.dedup();
+                SharedPage {
+                    page,
+                    page_size,
+                    region_ids,
+                    extra_faults: watched.iter().map(|&(_, writes)| total - writes).sum(),
+                }
+            })
+            .collect()
+    }
+
+    /// Regions with bytes on the pages of `info`, with the writes observed
+    /// on each so far
+    pub(crate) fn colocated_regions(&self, info: &RegionInfo) -> Vec<(u32, u64)> {
+        let usage = self.usage.lock().unwrap();
+        let mut colocated: Vec<(u32, u64)> = self
+            .regions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(&region_id, other)| !crate::hwbreak::owns(region_id) && info.shares_page(other.addr, other.size))
+            .map(|(&region_id, _)| (region_id, usage.writes(region_id)))
+            .collect();
+        let mut counted: BTreeMap<u32, u64> = BTreeMap::new();
+        for (region_id, start, len, writes) in self.counting.lock().unwrap().chunks() {
+            if info.shares_page(start as u64, len) {
+                *counted.entry(region_id).or_insert(0) += writes;
+            }
+        }
+        colocated.extend(counted);
+        colocated.sort_unstable();
+        colocated
+    }
+}
+
+/// A value on page(s) of its own, e.g. for watching without neighbours;
+/// see relocate_to_isolated_page()
+pub struct IsolatedPage<T> {
+    ptr: NonNull<T>,
+    layout: Layout,
+}
+
+// SAFETY: IsolatedPage owns its T like a Box does
+unsafe impl<T: Send> Send for IsolatedPage<T> {}
+unsafe impl<T: Sync> Sync for IsolatedPage<T> {}
+
+impl<T> IsolatedPage<T> {
+    /// Move `value` to the start of freshly allocated, page-aligned memory,
+    /// padded to whole pages
+    pub fn new(value: T) -> Self {
+        let page = capabilities::page_size();
+        let align = page.max(std::mem::align_of::<T>());
+        let size = std::mem::size_of::<T>().max(1).div_ceil(align) * align;
+        let layout = Layout::from_size_align(size, align).expect("page size is a power of two");
+        // SAFETY: layout has a non-zero size; zeroed so the padding never
+        // holds stale data
+        let raw = unsafe { alloc::alloc_zeroed(layout) } as *mut T;
+        let Some(ptr) = NonNull::new(raw) else {
+            alloc::handle_alloc_error(layout);
+        };
+        // SAFETY: ptr is valid for writes of T and suitably aligned
+        unsafe { ptr.as_ptr().write(value) };
+        IsolatedPage { ptr, layout }
+    }
+
+    /// Bytes reserved for the value, including padding to whole pages
+    pub fn reserved_bytes(&self) -> usize {
+        self.layout.size()
+    }
+
+    /// Move the value back out, releasing the pages
+    pub fn into_inner(self) -> T {
+        let this = std::mem::ManuallyDrop::new(self);
+        // SAFETY: the value is read once and the memory released without
+        // dropping it again
+        unsafe {
+            let value = this.ptr.as_ptr().read();
+            alloc::dealloc(this.ptr.as_ptr() as *mut u8, this.layout);
+            value
+        }
+    }
+}
+
+impl<T: NoUninit> IsolatedPage<T> {
+    /// Bytes of the value, for watch()
+    pub fn as_bytes(&self) -> &[u8] {
+        bytemuck::bytes_of(&**self)
+    }
+}
+
+impl<T> Deref for IsolatedPage<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: ptr holds an initialized T for the lifetime of self
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for IsolatedPage<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: as in deref, and &mut self makes the access unique
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T> Drop for IsolatedPage<T> {
+    fn drop(&mut self) {
+        // SAFETY: the value is initialized and dropped once, then the
+        // allocation is released with the layout it was made with
+        unsafe {
+            std::ptr::drop_in_place(self.ptr.as_ptr());
+            alloc::dealloc(self.ptr.as_ptr() as *mut u8, self.layout);
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for IsolatedPage<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("IsolatedPage").field(&**self).finish()
+    }
+}
+
+/// Copy `value` onto dedicated page(s) so watching it cannot fault on
+/// unrelated writes
+pub fn relocate_to_isolated_page<T>(value: T) -> IsolatedPage<T> {
+    IsolatedPage::new(value)
+}
+
+/// A value on page(s) of its own, watched until the box is dropped; writes
+/// go through the box
+pub struct IsolatedBox<'a, T> {
+    watcher: &'a MemWatch,
+    page: ManuallyDrop<IsolatedPage<T>>,
+    region_id: u32,
+}
+
+impl<'a, T: NoUninit> IsolatedBox<'a, T> {
+    /// Move `value` onto its own pages and watch it as `name`
+    pub fn new(watcher: &'a MemWatch, value: T, name: &str) -> Result<Self, String> {
+        let page = IsolatedPage::new(value);
+        let region_id = watcher.watch(page.as_bytes(), name)?;
+        Ok(IsolatedBox {
+            watcher,
+            page: ManuallyDrop::new(page),
+            region_id,
+        })
+    }
+}
+
+impl<T> IsolatedBox<'_, T> {
+    pub fn region_id(&self) -> u32 {
+        self.region_id
+    }
+
+    /// End the watch now, reporting what the backend did
+    pub fn unwatch(self) -> Result<Unwatch, NativeError> {
+        self.into_parts().1
+    }
+
+    /// End the watch and move the value out
+    pub fn into_inner(self) -> T {
+        self.into_parts().0
+    }
+
+    fn into_parts(self) -> (T, Result<Unwatch, NativeError>) {
+        let mut this = ManuallyDrop::new(self);
+        let result = this.watcher.unwatch(this.region_id);
+        // SAFETY: `this` is never dropped, so the page is taken once
+        let page = unsafe { ManuallyDrop::take(&mut this.page) };
+        let value = if result.is_ok() {
+            page.into_inner()
+        } else {
+            // SAFETY: the value is moved out once; the still-watched pages
+            // are leaked, never dropped or freed
+            let value = unsafe { std::ptr::read(&*page) };
+            std::mem::forget(page);
+            value
+        };
+        (value, result)
+    }
+}
+
+impl<T> Deref for IsolatedBox<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.page
+    }
+}
+
+impl<T> DerefMut for IsolatedBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.page
+    }
+}
+
+impl<T> Drop for IsolatedBox<'_, T> {
+    fn drop(&mut self) {
+        // SAFETY: drop runs once; the page is not used afterwards
+        let page = unsafe { ManuallyDrop::take(&mut self.page) };
+        if self.watcher.unwatch(self.region_id).is_err() {
+            // Still protected: the value is dropped but the pages leak
+            // SAFETY: read once, the page itself is forgotten
+            drop(unsafe { std::ptr::read(&*page) });
+            std::mem::forget(page);
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for IsolatedBox<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IsolatedBox").field("region_id", &self.region_id).field("value", &**self).finish()
+    }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native::{self, FakeEvent};
+    use crate::EventKind;
+
+    #[test]
+    fn test_isolated_page_alignment_and_drop() {
+        let page = capabilities::page_size();
+        let mut value = relocate_to_isolated_page([7u64; 3]);
+        assert_eq!(value.as_bytes().as_ptr() as usize % page, 0);
+        assert_eq!((value.as_bytes().len(), value.reserved_bytes()), (24, page));
+        value[1] = 9;
+        assert_eq!(value.into_inner(), [7, 9, 7]);
+
+        let shared = std::sync::Arc::new(());
+        drop(IsolatedPage::new(std::sync::Arc::clone(&shared)));
+        assert_eq!(std::sync::Arc::strong_count(&shared), 1);
+    }
+
+    #[test]
+    fn test_isolated_box_watches_until_dropped() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Protect;
+        let mut counter = IsolatedBox::new(&watcher, 5u32, "counter").unwrap();
+        let region_id = counter.region_id();
+        assert_eq!(watcher.regions.lock().unwrap()[&region_id].addr % capabilities::page_size() as u64, 0);
+        *counter += 1;
+        assert_eq!(*counter, 6);
+        drop(counter);
+        assert!(!watcher.regions.lock().unwrap().contains_key(&region_id));
+
+        fake_native::state().unwatch_ok = false;
+        let stuck = IsolatedBox::new(&watcher, *b"kept", "stuck").unwrap();
+        let region_id = stuck.region_id();
+        assert_eq!(&stuck.into_inner(), b"kept");
+        assert!(watcher.regions.lock().unwrap().contains_key(&region_id));
+    }
+
+    #[test]
+    fn test_shared_pages_are_reported() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Protect;
+        let page = IsolatedPage::new([0u8; 64]);
+        let bytes = page.as_bytes();
+
+        let first = watcher.watch(&bytes[..8], "first").unwrap();
+        fake_native::state().events = (0..3).map(|i| FakeEvent::change(first, &[i])).collect();
+        watcher.check_changes().unwrap();
+        let second = watcher.watch(&bytes[32..40], "second").unwrap();
+        let hot = watcher.watch_count_only(&bytes[48..56], "hot").unwrap();
+
+        let markers: Vec<EventKind> = watcher.check_changes().unwrap().into_iter().map(|e| e.kind).collect();
+        assert!(markers.contains(&EventKind::SharedPageWarning { other: first, extra_faults: 3 }));
+        let shared = watcher.shared_pages();
+        assert_eq!(shared.len(), 1);
+        assert_eq!(shared[0].page, bytes.as_ptr() as u64);
+        assert_eq!(shared[0].region_ids, [first, second, hot]);
+        assert_eq!(shared[0].extra_faults, 3);
+
+        let other = IsolatedPage::new([0u8; 8]);
+        watcher.watch(other.as_bytes(), "alone").unwrap();
+        assert!(watcher.check_changes().unwrap().iter().all(|e| !matches!(e.kind, EventKind::SharedPageWarning { .. })));
+    }
+}
@@ -1,11 +1,159 @@
 // Rust binding for memwatch
 // Place in: rust/src/lib.rs
 
-use std::collections::HashMap;
-use std::ffi::CString;
-use std::os::raw::{c_char, c_void, c_int};
-use std::ptr;
-use std::sync::Mutex;
+extern crate self as memwatch;
+
+use std::collections::{HashMap, VecDeque};
+use std::os::raw::c_char;
+#[cfg(native_backend)]
+use std::os::raw::{c_int, c_void};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use access::ReadTracker;
+use anomaly::AnomalyDetector;
+use budget::{PreviewBudget, PreviewThrottle};
+use capabilities::{Capabilities, WatchMode};
+use coalesce::Coalescer;
+use counting::CountingRegions;
+use group::Groups;
+use hwbreak::HardwareBackend;
+use decode::{DecodedChange, DecoderRegistry, LibraryDecoder, PreviewDecoder};
+use lifecycle::RegionInfo;
+use locks::{LockMonitor, LockPairing, LockPairs};
+use policy::{Alert, AlertQueue, Decision, Hook};
+use backend::{Backend, BackendConfig, PureBackend};
+#[cfg(not(feature = "pure"))]
+use backend::NativeBackend;
+use quarantine::{Quarantine, QuarantineList};
+use shadow::ShadowPages;
+use sink::EventSink;
+use storage::SequenceStore;
+use custom_backend::CustomBackend;
+use dispatch::DispatchPool;
+use pipeline_trace::PipelineTrace;
+use threads::ThreadCounts;
+use trigger::Triggers;
+use subscription::Subscriptions;
+use timeline::Timeline;
+use usage::{UsageReport, UsageTracker};
+use watchdog::{Watchdog, WatchdogState};
+
+pub use error::{CheckError, NativeError};
+pub use access::{AccessEvent, AccessKind, Event};
+pub use allocator::TrackingAllocator;
+pub use filter::Filter;
+pub use findings::Report;
+pub use expr::ExprGuard;
+pub use group::{GroupStats, WatchGroup};
+pub use guard::WatchGuard;
+pub use options::{RegionBackend, WatchOptions};
+pub use pipeline_trace::{TraceEntry, TraceStep};
+pub use subscription::Delivery;
+pub use threads::ThreadStats;
+pub use trigger::Trigger;
+// `self::` since the backtrace crate shares the name under its feature
+pub use self::backtrace::Frame;
+pub use wake::WaitForChange;
+pub use builder::MemWatchBuilder;
+pub use custom_backend::WatchBackend;
+pub use dispatch::{ConsumerStats, DispatchConfig};
+pub use lifecycle::{AllocationSite, EventKind, EventSource};
+#[cfg(feature = "derive")]
+pub use memwatch_derive::Watchable;
+pub use watchable::{FieldInfo, Watchable};
+
+pub mod access;
+pub mod allocator;
+pub mod analysis;
+pub mod anomaly;
+pub mod audit;
+pub mod backtrace;
+mod backend;
+pub mod budget;
+pub mod bus;
+pub mod builder;
+pub mod capabilities;
+pub mod causality;
+pub mod coalesce;
+pub mod ci;
+pub mod collect;
+pub mod counting;
+pub mod custom_backend;
+pub mod decode;
+pub mod diagnostics;
+pub mod diff;
+pub mod dispatch;
+#[cfg(feature = "dwarf")]
+pub mod dwarf;
+pub mod error;
+pub mod export;
+pub mod expr;
+pub mod filter;
+pub mod findings;
+pub mod forensics;
+pub mod graph;
+pub mod group;
+pub mod guard;
+pub mod health;
+mod hwbreak;
+pub mod isolation;
+mod json;
+pub mod lifecycle;
+pub mod locks;
+pub mod options;
+#[cfg(feature = "otel")]
+pub mod otel;
+mod polling;
+mod sampling;
+pub mod panics;
+pub mod persistence;
+pub mod pipeline_trace;
+pub mod policy;
+pub mod presets;
+pub mod process;
+pub mod quarantine;
+pub mod replay;
+#[cfg(feature = "charts")]
+pub mod report;
+pub mod scan;
+pub mod session;
+pub mod snapshot;
+pub mod soak;
+#[cfg(feature = "tracing")]
+pub mod spans;
+mod shadow;
+#[cfg(any(feature = "serde", feature = "rusqlite"))]
+mod serde_static;
+pub mod sink;
+pub mod sql_driver;
+pub mod sql_mask;
+pub mod sql_parser;
+pub mod sql_replay;
+pub mod sql_schema;
+pub mod sql_tracker;
+pub mod sql_value;
+pub mod storage;
+pub mod template;
+pub mod threads;
+#[cfg(feature = "tokio")]
+pub mod stream;
+pub mod subscription;
+pub mod symbols;
+pub mod timeline;
+pub mod trigger;
+pub mod usage;
+#[cfg(target_os = "linux")]
+pub mod vsock;
+pub mod wake;
+pub mod watchable;
+pub mod watchdog;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod fake_native;
+#[cfg(feature = "napi")]
+pub mod napi;
 
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -23,6 +171,24 @@ pub struct ChangeEventC {
     pub old_preview_size: usize,
     pub new_preview: *const u8,
     pub new_preview_size: usize,
+    /// Whole values when the core captured them (below 4 KiB), else null
+    pub old_value: *const u8,
+    pub old_value_size: usize,
+    pub new_value: *const u8,
+    pub new_value_size: usize,
+    /// Keys of larger values in the core's storage, else null
+    pub storage_key_old: *const c_char,
+    pub storage_key_new: *const c_char,
+    /// As passed to memwatch_watch(); unused by the Rust layer
+    pub user_data: *const std::ffi::c_void,
+    /// Writing thread, captured at fault time (0 = unknown)
+    pub thread_id: u32,
+    /// Its name, or null to look it up on the Rust side
+    pub thread_name: *const c_char,
+    /// Return addresses at fault time, innermost first; null unless a
+    /// backtrace depth is set
+    pub backtrace: *const u64,
+    pub backtrace_len: usize,
 }
 
 #[repr(C)]
@@ -38,27 +204,46 @@ pub struct StatsC {
     pub worker_cycles: u64,
 }
 
+#[cfg(all(feature = "pure", feature = "native"))]
+compile_error!("features `pure` and `native` select different backends; enable one");
+#[cfg(all(feature = "napi", not(native_backend)))]
+compile_error!("the `napi` addon needs the native backend (Linux, without `pure`)");
+#[cfg(all(feature = "native", not(native_backend)))]
+compile_error!("the `native` feature links the C core, which only supports Linux");
+
 // C function bindings
+#[cfg(native_backend)]
+#[cfg_attr(feature = "native", link(name = "memwatch"))]
 extern "C" {
+    fn memwatch_config_set_ring_capacity(capacity: u32) -> c_int;
+    fn memwatch_config_set_symbolication(enabled: bool) -> c_int;
+    fn memwatch_config_set_backtrace_depth(depth: u32) -> c_int;
     fn memwatch_init() -> c_int;
     fn memwatch_shutdown();
+    #[allow(dead_code)]
     fn memwatch_watch(addr: u64, size: usize, name: *const c_char, user_data: *mut c_void) -> u32;
     fn memwatch_watch_with_max_value_bytes(addr: u64, size: usize, name: *const c_char, user_data: *mut c_void, max_value_bytes: i32) -> u32;
     fn memwatch_unwatch(region_id: u32) -> bool;
-    fn memwatch_set_callback(callback: *mut c_void, user_ctx: *mut c_void) -> c_int;
+    fn memwatch_pause_region(region_id: u32) -> c_int;
+    fn memwatch_resume_region(region_id: u32) -> c_int;
+    fn memwatch_set_callback(callback: Option<NativeCallback>, user_ctx: *mut c_void) -> c_int;
     fn memwatch_check_changes(out_events: *mut ChangeEventC, max_events: c_int) -> c_int;
     fn memwatch_get_stats(out_stats: *mut StatsC) -> c_int;
     fn memwatch_free_event(event: *mut ChangeEventC);
+    fn memwatch_fetch_value(key: *const c_char, out: *mut u8, inout_size: *mut usize) -> c_int;
 }
 
 /// Change event - unified across all languages
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ChangeEvent {
     pub seq: u32,
     pub timestamp_ns: u64,
     pub adapter_id: u32,
     pub region_id: u32,
     pub variable_name: Option<String>,
+    /// WatchGroup the region belongs to; see the group module
+    pub group: Option<String>,
     pub where_: Location,
     pub old_preview: Vec<u8>,
     pub new_preview: Vec<u8>,
@@ -66,9 +251,42 @@ pub struct ChangeEvent {
     pub new_value: Vec<u8>,
     pub storage_key_old: Option<String>,
     pub storage_key_new: Option<String>,
+    /// Change, or a region lifecycle marker
+    pub kind: EventKind,
+    /// Run number from the sequence store (0 when not persisted)
+    pub epoch: u32,
+    /// Sequence assigned by the Rust layer; `(epoch, global_seq)` orders
+    /// events across restarts, while `seq` is the native ring's own counter
+    pub global_seq: u64,
+    /// Thread behind the event when known: the writing thread for native
+    /// changes, the registering thread for markers; None for changes the
+    /// backend does not attribute. See the threads module.
+    pub tid: Option<u32>,
+    pub thread_name: Option<String>,
+    /// Return addresses of the writing thread at fault time, innermost
+    /// first; empty unless MemWatchBuilder::backtrace_depth() is set.
+    /// backtrace() resolves them.
+    pub frames: Vec<u64>,
+    /// Allocation site of the region, when it was registered with one
+    pub allocated_at: Option<AllocationSite>,
+    /// Observed, or written through poke()
+    pub source: EventSource,
+    /// Preview limit applied by the preview budget, when one is set
+    pub preview_limit: Option<usize>,
+    /// Byte ranges that differ between old_value and new_value; empty
+    /// unless both values were captured
+    pub deltas: Vec<diff::ByteRange>,
+    /// `tracing` span current where the event was delivered (feature
+    /// `tracing`); see the spans module
+    pub span_id: Option<u64>,
+    pub span_name: Option<String>,
+    /// Writes merged into this event: 1 unless the region coalesces
+    /// changes (WatchOptions::coalesce_ms); see the coalesce module
+    pub write_count: u32,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Location {
     pub file: Option<String>,
     pub function: Option<String>,
@@ -78,6 +296,7 @@ pub struct Location {
 
 /// Statistics
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Stats {
     pub num_tracked_regions: u32,
     pub num_active_watchpoints: u32,
@@ -88,215 +307,1493 @@ pub struct Stats {
     pub mprotect_page_count: u32,
     pub worker_thread_id: u32,
     pub worker_cycles: u64,
+    /// Changes dropped by WatchOptions::sample_rate and max_events_per_sec,
+    /// and by soak mode
+    pub sampled_out_count: u64,
+    /// Per-subscriber delivery figures
+    pub consumers: Vec<ConsumerStats>,
+    /// Changes per writing thread, busiest first
+    pub events_per_thread: Vec<ThreadStats>,
+}
+
+/// Result of drain_all_changes()
+#[derive(Debug, Clone)]
+pub struct DrainedChanges {
+    pub events: Vec<ChangeEvent>,
+    /// Events the native ring dropped since the previous drain, because it
+    /// was full
+    pub dropped_count: u64,
+}
+
+/// Outcome of a successful unwatch()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unwatch {
+    Removed,
+    /// No such region (never watched, or already removed)
+    NotTracked,
 }
 
 /// Callback function type
 pub type ChangeEventCallback = Box<dyn Fn(&ChangeEvent) + Send>;
 
+/// Where the native callback finds the closure and the tasks waiting for
+/// changes; shared so its address survives moves of the MemWatch
+struct CallbackSlot {
+    callback: Mutex<Option<ChangeEventCallback>>,
+    wakers: wake::ChangeWakers,
+    /// Shared with region handlers and sinks; see the panics module
+    failure_limit: AtomicU32,
+    /// Panics of the current callback so far
+    failures: AtomicU32,
+    /// (failures, removed, message) of callback panics not yet reported
+    panics: Mutex<Vec<(u32, bool, String)>>,
+    /// set_filter()'s filter, with rate counters of its own
+    filter: Mutex<Option<Filter>>,
+    /// Writes to count-only chunks the callback saw, by backend region, not
+    /// yet added to their counters; see the counting module
+    counted: Mutex<HashMap<u32, u64>>,
+}
+
+impl Default for CallbackSlot {
+    fn default() -> Self {
+        CallbackSlot {
+            callback: Mutex::new(None),
+            wakers: wake::ChangeWakers::default(),
+            failure_limit: AtomicU32::new(panics::DEFAULT_FAILURE_LIMIT),
+            failures: AtomicU32::new(0),
+            panics: Mutex::new(Vec::new()),
+            filter: Mutex::new(None),
+            counted: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+/// memwatch_callback_t
+#[cfg(native_backend)]
+type NativeCallback = unsafe extern "C" fn(event: *const ChangeEventC, user_ctx: *mut c_void);
+
 /// Memory watcher - unified API for Rust
 pub struct MemWatch {
-    tracked_objects: Mutex<HashMap<u32, Box<dyn std::any::Any>>>,
-    callback: Mutex<Option<ChangeEventCallback>>,
+    tracked_objects: Mutex<HashMap<u32, Box<dyn std::any::Any + Send>>>,
+    callback: Arc<CallbackSlot>,
+    /// Handlers of watch_with_callback() and their panics so far, by region
+    region_callbacks: Mutex<HashMap<u32, (ChangeEventCallback, u32)>>,
+    subscriptions: Mutex<Subscriptions>,
+    segv_handler: usize,
+    capabilities: Capabilities,
+    #[cfg(not(feature = "pure"))]
+    native: NativeBackend,
+    pure: PureBackend,
+    /// Regions watched with RegionBackend::HardwareBreakpoint
+    hardware: HardwareBackend,
+    /// Set with MemWatchBuilder::backend(); replaces native and pure
+    custom: Option<CustomBackend>,
+    regions: Mutex<HashMap<u32, RegionInfo>>,
+    markers: Mutex<VecDeque<ChangeEvent>>,
+    /// Events of a batch whose sequencing failed, stamped again with the
+    /// next batch
+    unstamped: Mutex<Vec<ChangeEvent>>,
+    /// Events of a batch whose blob storage or sink delivery failed,
+    /// returned ahead of the next batch
+    undelivered: Mutex<Vec<ChangeEvent>>,
+    sequence: Mutex<SequenceStore>,
+    /// See set_blob_store()
+    blobs: Mutex<Option<storage::BlobStore>>,
+    /// Sinks and their panics so far
+    sinks: Mutex<Vec<(Box<dyn EventSink>, u32)>>,
+    /// See set_metadata(), in the order keys were first set
+    metadata: Mutex<Vec<(String, String)>>,
+    shadow: Mutex<Option<ShadowPages>>,
+    counting: Mutex<CountingRegions>,
+    timeline: Mutex<Timeline>,
+    detectors: Mutex<Vec<Box<dyn AnomalyDetector>>>,
+    poisoned: AtomicBool,
+    hooks: Mutex<Vec<Hook<ChangeEvent>>>,
+    alerts: Mutex<AlertQueue>,
+    recent: Mutex<VecDeque<ChangeEvent>>,
+    usage: Mutex<UsageTracker>,
+    threads: Mutex<ThreadCounts>,
+    /// See enable_causality()
+    causality: Mutex<Option<causality::Causality>>,
+    /// Changes held back by regions with WatchOptions::coalesce_ms
+    coalescer: Mutex<Coalescer>,
+    groups: Mutex<Groups>,
+    /// See enable_soak_mode()
+    soak: Mutex<Option<soak::Soak>>,
+    /// See on_soak_report()
+    soak_handler: Mutex<Option<soak::SoakHandler>>,
+    triggers: Mutex<Triggers>,
+    quarantine: Mutex<QuarantineList>,
+    lock_pairs: LockPairs,
+    previews: Mutex<PreviewThrottle>,
+    /// Shared with template alert hooks
+    decoders: Arc<Mutex<DecoderRegistry>>,
+    layouts: Mutex<HashMap<u32, &'static [FieldInfo]>>,
+    /// Roots of watch_expr(), by name
+    layout_roots: Mutex<HashMap<String, expr::LayoutRoot>>,
+    watchdog: Mutex<WatchdogState>,
+    /// Start of the current run of failed sink deliveries
+    sink_failing_since: Mutex<Option<std::time::Instant>>,
+    /// Value limit of watch() and friends; see MemWatchBuilder
+    max_value_bytes: i32,
+    /// Regions watched with WatchOptions::track_reads
+    reads: Mutex<ReadTracker>,
+    /// ring_drop_count as of the last drain_all_changes()
+    ring_drops_seen: AtomicU64,
+    filter: Mutex<Option<Filter>>,
+    /// Changes dropped by region samplers and soak sampling
+    sampled_out: AtomicU64,
+    /// See enable_pipeline_trace()
+    tracing_pipeline: AtomicBool,
+    pipeline_trace: Mutex<PipelineTrace>,
+    /// Shared by worker subscribers; see MemWatchBuilder::dispatch().
+    /// After subscriptions, so they are dropped first.
+    dispatch: Option<DispatchPool>,
 }
 
 impl MemWatch {
-    /// Create a new memory watcher
+    /// Create a new memory watcher with default settings
     pub fn new() -> Result<Self, String> {
-        unsafe {
-            let result = memwatch_init();
-            if result != 0 {
-                return Err(format!("Failed to initialize memwatch: {}", result));
+        MemWatch::builder().build()
+    }
+
+    fn with_config(config: BackendConfig, max_value_bytes: i32) -> Result<Self, String> {
+        let watch = MemWatch {
+            tracked_objects: Mutex::new(HashMap::new()),
+            callback: Arc::new(CallbackSlot::default()),
+            region_callbacks: Mutex::new(HashMap::new()),
+            subscriptions: Mutex::new(Subscriptions::default()),
+            segv_handler: diagnostics::current_segv_handler(),
+            capabilities: Capabilities::detect(),
+            #[cfg(not(feature = "pure"))]
+            native: NativeBackend,
+            pure: PureBackend::default(),
+            hardware: HardwareBackend::default(),
+            custom: None,
+            regions: Mutex::new(HashMap::new()),
+            markers: Mutex::new(VecDeque::new()),
+            unstamped: Mutex::new(Vec::new()),
+            undelivered: Mutex::new(Vec::new()),
+            sequence: Mutex::new(SequenceStore::in_memory()),
+            blobs: Mutex::new(None),
+            sinks: Mutex::new(Vec::new()),
+            metadata: Mutex::new(Vec::new()),
+            shadow: Mutex::new(None),
+            counting: Mutex::new(CountingRegions::default()),
+            timeline: Mutex::new(Timeline::default()),
+            detectors: Mutex::new(Vec::new()),
+            poisoned: AtomicBool::new(false),
+            hooks: Mutex::new(Vec::new()),
+            alerts: Mutex::new(AlertQueue::default()),
+            recent: Mutex::new(VecDeque::new()),
+            usage: Mutex::new(UsageTracker::default()),
+            threads: Mutex::new(ThreadCounts::default()),
+            causality: Mutex::new(None),
+            coalescer: Mutex::new(Coalescer::default()),
+            groups: Mutex::new(Groups::default()),
+            soak: Mutex::new(None),
+            soak_handler: Mutex::new(None),
+            triggers: Mutex::new(Triggers::default()),
+            quarantine: Mutex::new(QuarantineList::default()),
+            lock_pairs: LockPairs::default(),
+            previews: Mutex::new(PreviewThrottle::default()),
+            decoders: Arc::new(Mutex::new(DecoderRegistry::default())),
+            layouts: Mutex::new(HashMap::new()),
+            layout_roots: Mutex::new(HashMap::new()),
+            watchdog: Mutex::new(WatchdogState::default()),
+            sink_failing_since: Mutex::new(None),
+            max_value_bytes,
+            reads: Mutex::new(ReadTracker::default()),
+            ring_drops_seen: AtomicU64::new(0),
+            filter: Mutex::new(None),
+            sampled_out: AtomicU64::new(0),
+            tracing_pipeline: AtomicBool::new(false),
+            pipeline_trace: Mutex::new(PipelineTrace::default()),
+            dispatch: None,
+        };
+        for backend in watch.backends() {
+            backend.configure(&config)?;
+            backend.init()?;
+        }
+        Ok(watch)
+    }
+    
+    /// Backend of the selected watch mode
+    fn backend(&self) -> &dyn Backend {
+        if let Some(custom) = &self.custom {
+            return custom;
+        }
+        #[cfg(not(feature = "pure"))]
+        if self.capabilities.mode == WatchMode::Protect {
+            return &self.native;
+        }
+        &self.pure
+    }
+    
+    /// Every compiled-in backend
+    fn backends(&self) -> Vec<&dyn Backend> {
+        let mut backends: Vec<&dyn Backend> = vec![
+            #[cfg(not(feature = "pure"))]
+            &self.native,
+            &self.pure,
+            &self.hardware,
+        ];
+        backends.extend(self.custom.as_ref().map(|custom| custom as &dyn Backend));
+        backends
+    }
+
+    /// Backend watching `region_id`
+    fn backend_for(&self, region_id: u32) -> &dyn Backend {
+        if hwbreak::owns(region_id) {
+            return &self.hardware;
+        }
+        self.backend()
+    }
+
+    /// Keep shadow copies of watched pages so every event in a batch gets
+    /// exact old/new values, even when several writes hit the same page
+    pub fn enable_shadow_pages(&self, enabled: bool) {
+        let mut shadow = self.shadow.lock().unwrap();
+        if !enabled {
+            *shadow = None;
+            return;
+        }
+        if shadow.is_none() {
+            let mut pages = ShadowPages::new(self.capabilities.page_size);
+            for info in self.regions.lock().unwrap().values() {
+                pages.add_region(info.addr, info.size);
             }
+            *shadow = Some(pages);
         }
-        
-        Ok(MemWatch {
-            tracked_objects: Mutex::new(HashMap::new()),
-            callback: Mutex::new(None),
-        })
     }
     
-    /// Watch a buffer for changes with optional max_value_bytes
+    /// Persist epoch/sequence state in `path` so histories from several runs
+    /// can be concatenated. Returns the epoch of this run.
+    pub fn enable_sequence_persistence(&self, path: impl AsRef<std::path::Path>) -> Result<u32, String> {
+        let store = SequenceStore::open(path)?;
+        let epoch = store.epoch();
+        *self.sequence.lock().unwrap() = store;
+        Ok(epoch)
+    }
+    
+    /// Tag exports with application metadata, e.g. ("git_sha", "..."):
+    /// the headers of sinks added afterwards, and session manifests. Setting
+    /// a key again replaces its value.
+    pub fn set_metadata(&self, key: &str, value: &str) {
+        let mut metadata = self.metadata.lock().unwrap();
+        match metadata.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value.to_string(),
+            None => metadata.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    /// Application metadata set so far
+    pub fn metadata(&self) -> Vec<(String, String)> {
+        self.metadata.lock().unwrap().clone()
+    }
+    
+    /// What the environment supports and which watch mode was selected
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+    
+    /// Watch a buffer for changes, keeping values up to the default limit
+    /// (256 bytes unless set with MemWatchBuilder::max_value_bytes_default).
+    /// With the `dwarf` feature an empty name is replaced by the static
+    /// variable the buffer lies in; see the dwarf module.
     pub fn watch(&self, buffer: &[u8], name: &str) -> Result<u32, String> {
-        self.watch_with_max_value_bytes(buffer, name, 256)
+        self.watch_with_max_value_bytes(buffer, name, self.max_value_bytes)
     }
     
     /// Watch a buffer for changes with custom value storage limit
     /// max_value_bytes: 0 = no values, >0 = limit to N bytes, -1 = full values
     pub fn watch_with_max_value_bytes(&self, buffer: &[u8], name: &str, max_value_bytes: i32) -> Result<u32, String> {
-        let addr = buffer.as_ptr() as u64;
-        let size = buffer.len();
-        let c_name = CString::new(name).map_err(|e| e.to_string())?;
-        
-        unsafe {
-            let region_id = memwatch_watch_with_max_value_bytes(addr, size, c_name.as_ptr(), ptr::null_mut(), max_value_bytes);
-            if region_id > 0 {
-                Ok(region_id)
-            } else {
-                Err("Failed to watch buffer".to_string())
+        self.watch_buffer(buffer, name, max_value_bytes, None)
+    }
+
+    /// Watch a buffer and hand its events to `callback`, e.g. one observer
+    /// per subsystem. Unlike set_callback(), the callback runs in
+    /// check_changes() on the calling thread and sees the region's
+    /// processed events, lifecycle markers included, up to its Unwatched
+    /// marker.
+    pub fn watch_with_callback<F>(&self, buffer: &[u8], name: &str, callback: F) -> Result<u32, String>
+    where
+        F: Fn(&ChangeEvent) + Send + 'static,
+    {
+        let region_id = self.watch(buffer, name)?;
+        self.region_callbacks.lock().unwrap().insert(region_id, (Box::new(callback), 0));
+        Ok(region_id)
+    }
+    
+    fn watch_buffer(&self, buffer: &[u8], name: &str, max_value_bytes: i32, site: Option<AllocationSite>) -> Result<u32, String> {
+        access::internal(|| {
+            if self.capabilities.mode == WatchMode::Protect
+                && self.capabilities.wx_exclusive
+                && capabilities::overlaps_executable(buffer.as_ptr() as usize, buffer.len())
+            {
+                return Err(format!("Cannot watch {}: it lies in executable pages and this host enforces W^X", name));
             }
-        }
+            #[cfg(feature = "dwarf")]
+            let resolved = if name.is_empty() { dwarf::region_name(buffer.as_ptr() as u64) } else { name.to_string() };
+            #[cfg(feature = "dwarf")]
+            let name = resolved.as_str();
+            let region_id = self.backend().watch(buffer, name, max_value_bytes)?;
+            self.register_region(region_id, name, buffer.as_ptr() as u64, buffer.len(), max_value_bytes, site);
+            Ok(region_id)
+        })
     }
     
     /// Watch a vector for changes
     pub fn watch_vec<T>(&self, vec: &[T], name: &str) -> Result<u32, String> {
-        self.watch_vec_with_max_value_bytes(vec, name, 256)
+        self.watch_vec_with_max_value_bytes(vec, name, self.max_value_bytes)
     }
     
     /// Watch a vector for changes with custom value storage limit
     /// max_value_bytes: 0 = no values, >0 = limit to N bytes, -1 = full values
     pub fn watch_vec_with_max_value_bytes<T>(&self, vec: &[T], name: &str, max_value_bytes: i32) -> Result<u32, String> {
-        let addr = vec.as_ptr() as u64;
-        let size = vec.len() * std::mem::size_of::<T>();
-        let c_name = CString::new(name).map_err(|e| e.to_string())?;
+        self.watch_buffer(lifecycle::slice_bytes(vec), name, max_value_bytes, None)
+    }
+    
+    /// Watch a buffer for as long as the returned guard lives. The guard
+    /// holds the buffer's borrow, so it cannot be dropped or moved while
+    /// watched; write to it through the guard.
+    pub fn watch_guarded<'a>(&'a self, buffer: &'a mut [u8], name: &str) -> Result<WatchGuard<'a>, String> {
+        let region_id = self.watch(buffer, name)?;
+        Ok(WatchGuard::new(self, buffer, region_id))
+    }
+    
+    /// Watch a struct with events per changed field, named
+    /// "<name>.<field>"; see the watchable module
+    #[track_caller]
+    pub fn watch_struct<T: Watchable>(&self, value: &T, name: &str) -> Result<u32, String> {
+        let site = AllocationSite::caller().with_type_name(std::any::type_name::<T>());
+        let bytes = lifecycle::slice_bytes(std::slice::from_ref(value));
+        let region_id = self.watch_buffer(bytes, name, -1, Some(site))?;
+        self.layouts.lock().unwrap().insert(region_id, T::FIELDS);
+        Ok(region_id)
+    }
+    
+    /// Watch a heap block and tag its events with where (and as what type)
+    /// it was allocated; see also the `watch_typed!` macro
+    pub fn watch_tagged(&self, buffer: &[u8], name: &str, site: AllocationSite) -> Result<u32, String> {
+        self.watch_buffer(buffer, name, self.max_value_bytes, Some(site))
+    }
+    
+    /// Watch a buffer in CountOnly mode: per-page write counters only, no
+    /// previews, values or events. Read the counters with write_counts().
+    /// See the counting module for what a count counts in each mode.
+    pub fn watch_count_only(&self, buffer: &[u8], name: &str) -> Result<u32, String> {
+        access::internal(|| {
+            let chunks = counting::chunks(buffer, self.capabilities.page_size);
+            let mut backed = Vec::new();
+            if self.capabilities.mode == WatchMode::Protect {
+                let base = buffer.as_ptr() as usize;
+                for &(start, len) in &chunks {
+                    let chunk = &buffer[start - base..start - base + len];
+                    match self.hardware.watch(chunk, name, 0).or_else(|_| self.backend().watch(chunk, name, 0)) {
+                        Ok(backend_id) => backed.push(backend_id),
+                        Err(e) => {
+                            for backend_id in backed {
+                                let _ = self.backend_for(backend_id).unwatch(backend_id);
+                            }
+                            return Err(e);
+                        }
+                    }
+                }
+                let mut counted = self.callback.counted.lock().unwrap();
+                for &backend_id in &backed {
+                    counted.insert(backend_id, 0);
+                }
+            }
+            Ok(self.counting.lock().unwrap().watch(name, chunks, backed))
+        })
+    }
+    
+    /// Current write counters of every CountOnly region: as of the last
+    /// check for chunks a backend watches, as of now for fingerprinted ones
+    pub fn write_counts(&self) -> Vec<counting::WriteCount> {
+        self.tick_counts();
+        self.counting.lock().unwrap().counts()
+    }
+
+    /// Fold what the callback counted into the counters, and compare
+    /// fingerprints
+    fn tick_counts(&self) {
+        let mut counting = self.counting.lock().unwrap();
+        for (&backend_id, writes) in self.callback.counted.lock().unwrap().iter_mut() {
+            counting.record(backend_id, std::mem::take(writes));
+        }
+        counting.tick();
+    }
+    
+    /// Stop watching a region. Unknown (or already removed) ids give
+    /// `NotTracked`; on native failure the region stays registered so the
+    /// call can be retried. With a quarantine set the region only ends
+    /// logically here; see set_quarantine().
+    pub fn unwatch(&self, region_id: u32) -> Result<Unwatch, NativeError> {
+        access::internal(|| {
+            if CountingRegions::is_count_only(region_id) {
+                let Some(backed) = self.counting.lock().unwrap().unwatch(region_id) else {
+                    return Ok(Unwatch::NotTracked);
+                };
+                let mut counted = self.callback.counted.lock().unwrap();
+                for backend_id in backed {
+                    counted.remove(&backend_id);
+                    // The region is gone either way; its chunk watches
+                    // cannot be retried under its id
+                    let _ = self.backend_for(backend_id).unwatch(backend_id);
+                }
+                return Ok(Unwatch::Removed);
+            }
         
-        unsafe {
-            let region_id = memwatch_watch_with_max_value_bytes(addr, size, c_name.as_ptr(), ptr::null_mut(), max_value_bytes);
-            if region_id > 0 {
-                Ok(region_id)
-            } else {
-                Err("Failed to watch vector".to_string())
+            if !self.regions.lock().unwrap().contains_key(&region_id) {
+                return Ok(Unwatch::NotTracked);
+            }
+            self.reads.lock().unwrap().untrack(region_id);
+            if self.quarantine.lock().unwrap().enabled() {
+                // Writes made before this call must not count as late writes
+                let _ = self.flush_pending();
+                if let Some(info) = self.forget_region(region_id) {
+                    self.quarantine.lock().unwrap().admit(region_id, info);
+                }
+                self.release_quarantined();
+                return Ok(Unwatch::Removed);
+            }
+            self.release(region_id)?;
+            self.forget_region(region_id);
+            Ok(Unwatch::Removed)
+        })
+    }
+    
+    /// Move a region to `buffer`, the new home of its contents (e.g. a block
+    /// moved by realloc), with its name, options, field layout and region
+    /// callback. Returns the id it is watched under there: an Unwatched
+    /// marker ends the old id, Watched and Relocated markers start the new
+    /// one. On error the region stays where it was.
+    pub fn relocate(&self, region_id: u32, buffer: &[u8]) -> Result<u32, String> {
+        access::internal(|| {
+            let info = self.regions.lock().unwrap().get(&region_id).cloned().ok_or_else(|| format!("Unknown region {}", region_id))?;
+            let backend = self.backend_for(region_id);
+            let new_addr = buffer.as_ptr() as u64;
+            let new_id = backend.watch(buffer, &info.name, info.max_value_bytes)?;
+            if info.paused {
+                if let Err(e) = backend.pause(new_id) {
+                    let _ = backend.unwatch(new_id);
+                    return Err(e);
+                }
+            }
+            // Changes at the old address are reported under the old id
+            if let Err(e) = self.flush_pending().map_err(|e| e.to_string()).and_then(|_| self.release(region_id).map_err(|e| e.to_string())) {
+                let _ = backend.unwatch(new_id);
+                return Err(e);
+            }
+            self.reads.lock().unwrap().untrack(region_id);
+            let layout = self.layouts.lock().unwrap().remove(&region_id);
+            self.forget_region(region_id);
+            self.register_region(new_id, &info.name, new_addr, buffer.len(), info.max_value_bytes, info.site);
+            if let Some(moved) = self.regions.lock().unwrap().get_mut(&new_id) {
+                moved.tags = info.tags;
+                moved.sampler = info.sampler;
+                moved.coalesce = info.coalesce;
+                moved.paused = info.paused;
+            }
+            if let Some(layout) = layout {
+                self.layouts.lock().unwrap().insert(new_id, layout);
             }
+            let mut callbacks = self.region_callbacks.lock().unwrap();
+            if let Some(callback) = callbacks.remove(&region_id) {
+                callbacks.insert(new_id, callback);
+            }
+            drop(callbacks);
+            self.push_marker(new_id, &info.name, EventKind::Relocated { old_addr: info.addr, new_addr });
+            Ok(new_id)
+        })
+    }
+
+    /// Keep unwatched regions protected for `quarantine.duration` (and up to
+    /// `quarantine.max_bytes`) so late writers show up as UseAfterUnwatch
+    /// events. `None` releases every quarantined region at the next check.
+    pub fn set_quarantine(&self, quarantine: Option<Quarantine>) {
+        self.quarantine.lock().unwrap().set_policy(quarantine);
+    }
+    
+    /// Cap the preview bytes of all events per second; previews shrink under
+    /// bursts and recover once rates drop. `None` removes the cap.
+    pub fn set_preview_budget(&self, budget: Option<PreviewBudget>) {
+        self.previews.lock().unwrap().set_budget(budget);
+    }
+    
+    /// Watch the native worker and report (optionally restart) it when it
+    /// stops making progress; see the watchdog module. `None` turns the
+    /// watchdog off.
+    pub fn set_watchdog(&self, watchdog: Option<Watchdog>) {
+        self.watchdog.lock().unwrap().set_policy(watchdog);
+    }
+    
+    /// Sample the worker for the watchdog, queueing a WorkerStalled marker
+    /// (and restarting the backend) on a stall
+    fn check_worker(&self) -> Result<(), CheckError> {
+        if self.capabilities.mode != WatchMode::Protect || !self.watchdog.lock().unwrap().enabled() {
+            return Ok(());
         }
+        let Ok(stats) = self.backend().stats() else {
+            return Ok(());
+        };
+        let Some(stall) = self.watchdog.lock().unwrap().observe(&stats, std::time::Instant::now()) else {
+            return Ok(());
+        };
+        let kind = EventKind::WorkerStalled {
+            thread_id: stall.thread_id,
+            stalled_ms: stall.stalled_for.as_millis() as u64,
+        };
+        self.push_marker(0, "memwatch worker", kind);
+        if stall.restart {
+            self.restart_backend()?;
+        }
+        Ok(())
     }
     
-    /// Stop watching a region
-    pub fn unwatch(&self, region_id: u32) -> bool {
-        unsafe {
-            memwatch_unwatch(region_id) && 
-            self.tracked_objects.lock().unwrap().remove(&region_id).is_some()
+    /// Re-initialize the backend and re-watch every live region at its
+    /// address. Regions that come back under a new id are reported as
+    /// unwatched under the old and watched under the new one.
+    fn restart_backend(&self) -> Result<(), String> {
+        let backend = self.backend();
+        backend.shutdown();
+        backend.init()?;
+        let has_callback = self.callback.callback.lock().unwrap_or_else(|e| e.into_inner()).is_some();
+        if has_callback || self.callback.wakers.is_armed() {
+            backend.set_callback(Some(&self.callback))?;
         }
+        self.quarantine.lock().unwrap().clear();
+        self.watchdog.lock().unwrap().rearm();
+        // Hardware breakpoints do not depend on the backend's worker
+        let mut live: Vec<(u32, RegionInfo)> = {
+            let mut regions = self.regions.lock().unwrap();
+            let ids: Vec<u32> = regions.keys().copied().filter(|&id| !hwbreak::owns(id)).collect();
+            ids.into_iter().filter_map(|id| Some((id, regions.remove(&id)?))).collect()
+        };
+        live.sort_unstable_by_key(|(id, _)| *id);
+        let mut old_layouts = std::mem::take(&mut *self.layouts.lock().unwrap());
+        for (old_id, info) in live {
+            // Regions stay valid until unwatched, as for the backend itself
+            let buffer = unsafe { std::slice::from_raw_parts(info.addr as *const u8, info.size) };
+            let new_id = backend.watch(buffer, &info.name, info.max_value_bytes)?;
+            if info.paused {
+                backend.pause(new_id)?;
+            }
+            if let Some(fields) = old_layouts.remove(&old_id) {
+                self.layouts.lock().unwrap().insert(new_id, fields);
+            }
+            let mut callbacks = self.region_callbacks.lock().unwrap();
+            if let Some(callback) = callbacks.remove(&old_id) {
+                callbacks.insert(new_id, callback);
+            }
+            drop(callbacks);
+            if new_id != old_id {
+                self.push_marker(old_id, &info.name, EventKind::Unwatched);
+                self.push_marker(new_id, &info.name, EventKind::Watched { addr: info.addr, size: info.size });
+            }
+            self.regions.lock().unwrap().insert(new_id, info);
+        }
+        // Count-only chunks the restarted backend watched
+        let backed = self.counting.lock().unwrap().backed_chunks();
+        let mut moved = HashMap::new();
+        for (old_id, start, len, name) in backed.into_iter().filter(|(id, ..)| !hwbreak::owns(*id)) {
+            let chunk = unsafe { std::slice::from_raw_parts(start as *const u8, len) };
+            moved.insert(old_id, backend.watch(chunk, &name, 0)?);
+        }
+        if !moved.is_empty() {
+            self.counting.lock().unwrap().rebind(&moved);
+            let mut counted = self.callback.counted.lock().unwrap();
+            *counted = counted.drain().map(|(id, writes)| (moved.get(&id).copied().unwrap_or(id), writes)).collect();
+        }
+        Ok(())
+    }
+    
+    /// Drop the backend watch of a region
+    fn release(&self, region_id: u32) -> Result<(), NativeError> {
+        self.backend_for(region_id).unwatch(region_id)?;
+        self.tracked_objects.lock().unwrap().remove(&region_id);
+        Ok(())
+    }
+    
+    /// Release quarantined regions that are due; failures are retried at the
+    /// next check
+    fn release_quarantined(&self) {
+        let due = self.quarantine.lock().unwrap().due(std::time::Instant::now());
+        for region_id in due {
+            if self.release(region_id).is_ok() {
+                self.quarantine.lock().unwrap().remove(region_id);
+            }
+        }
+    }
+    
+    /// Stop watching every region (count-only ones included); returns how
+    /// many were removed
+    pub fn unwatch_all(&self) -> usize {
+        let mut ids: Vec<u32> = self.regions.lock().unwrap().keys().copied().collect();
+        ids.sort_unstable();
+        ids.extend(self.counting.lock().unwrap().ids());
+        ids.into_iter()
+            .filter(|&id| self.unwatch(id) == Ok(Unwatch::Removed))
+            .count()
+    }
+    
+    /// Drop recorded history: pending markers, timelines, write counters,
+    /// the causality graph and what anomaly detectors have learned. Watches
+    /// stay in place.
+    pub fn clear_history(&self) {
+        self.markers.lock().unwrap().clear();
+        self.unstamped.lock().unwrap().clear();
+        self.undelivered.lock().unwrap().clear();
+        self.timeline.lock().unwrap().clear();
+        self.recent.lock().unwrap().clear();
+        self.usage.lock().unwrap().reset_counts();
+        self.threads.lock().unwrap().reset_counts();
+        if let Some(causality) = self.causality.lock().unwrap().as_mut() {
+            causality.reset();
+        }
+        self.counting.lock().unwrap().reset_counts();
+        for detector in self.detectors.lock().unwrap().iter_mut() {
+            detector.reset();
+        }
+    }
+    
+    /// Full teardown and re-init of the native layer; also clears the poison
+    /// flag. Callback, sinks, detectors, shadow pages and sequence store are
+    /// kept.
+    pub fn reset(&self) -> Result<(), String> {
+        access::internal(|| {
+            self.unwatch_all();
+            self.quarantine.lock().unwrap().clear();
+            self.clear_history();
+            self.tracked_objects.lock().unwrap().clear();
+            for backend in self.backends() {
+                backend.shutdown();
+                backend.init()?;
+            }
+            self.poisoned.store(false, Ordering::SeqCst);
+            Ok(())
+        })
+    }
+    
+    fn register_region(&self, region_id: u32, name: &str, addr: u64, size: usize, max_value_bytes: i32, site: Option<AllocationSite>) {
+        let base = self.capabilities.page_size;
+        // Only protected regions care; smaps is too costly to read otherwise
+        let protected = self.capabilities.mode == WatchMode::Protect && !hwbreak::owns(region_id);
+        let page_size = if protected {
+            capabilities::region_page_size(addr as usize, base)
+        } else {
+            base
+        };
+        let info = RegionInfo {
+            name: name.to_string(),
+            addr,
+            size,
+            site,
+            max_value_bytes,
+            freed: false,
+            page_size,
+            tags: Vec::new(),
+            sampler: None,
+            coalesce: None,
+            paused: false,
+        };
+        let aliased = self.reused_regions(&info);
+        let colocated = if protected {
+            self.colocated_regions(&info)
+        } else {
+            Vec::new()
+        };
+        self.regions.lock().unwrap().insert(region_id, info);
+        if let Some(shadow) = self.shadow.lock().unwrap().as_mut() {
+            shadow.add_region(addr, size);
+        }
+        self.push_marker(region_id, name, EventKind::Watched { addr, size });
+        if page_size > base && size < page_size {
+            self.push_marker(region_id, name, EventKind::HugePageWarning { page_size: page_size as u64 });
+        }
+        for (other, extra_faults) in colocated {
+            self.push_marker(region_id, name, EventKind::SharedPageWarning { other, extra_faults });
+        }
+        for (old_id, old_name) in aliased {
+            self.push_marker(old_id, &old_name, EventKind::AliasWarning { other: region_id });
+        }
+    }
+    
+    /// Regions whose memory `info` reuses for a different object: overlapping
+    /// ones that were freed, are quarantined, or are tagged with another type
+    fn reused_regions(&self, info: &RegionInfo) -> Vec<(u32, String)> {
+        let type_name = |site: Option<AllocationSite>| site.and_then(|s| s.type_name);
+        let retyped = |old: &RegionInfo| {
+            matches!((type_name(old.site), type_name(info.site)), (Some(a), Some(b)) if a != b)
+        };
+        let mut reused: Vec<(u32, String)> = self
+            .regions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, old)| old.overlaps(info) && (old.freed || retyped(old)))
+            .map(|(&id, old)| (id, old.name.clone()))
+            .collect();
+        reused.extend(self.quarantine.lock().unwrap().overlapping(info));
+        reused.sort_unstable();
+        reused
+    }
+
+    fn forget_region(&self, region_id: u32) -> Option<RegionInfo> {
+        let info = self.regions.lock().unwrap().remove(&region_id)?;
+        self.lock_pairs.lock().unwrap().remove(&region_id);
+        self.decoders.lock().unwrap().forget(region_id);
+        self.layouts.lock().unwrap().remove(&region_id);
+        self.triggers.lock().unwrap().forget_region(region_id);
+        self.groups.lock().unwrap().forget_region(region_id);
+        if let Some(shadow) = self.shadow.lock().unwrap().as_mut() {
+            shadow.remove_region(info.addr, info.size);
+        }
+        let mut marker = lifecycle::marker(region_id, &info.name, EventKind::Unwatched);
+        marker.allocated_at = info.site;
+        self.queue_marker(marker);
+        Some(info)
+    }
+    
+    /// Queue a lifecycle marker to be returned by the next check_changes()
+    pub(crate) fn push_marker(&self, region_id: u32, name: &str, kind: EventKind) {
+        self.queue_marker(lifecycle::marker(region_id, name, kind));
+    }
+
+    fn queue_marker(&self, marker: ChangeEvent) {
+        self.markers.lock().unwrap().push_back(marker);
+        self.callback.wakers.wake_all();
     }
     
     /// Set callback for change events
+    ///
+    /// The native backend calls it on its worker thread as each fault is
+    /// recorded, ahead of (and independently from) check_changes(). Events
+    /// are raw: no markers, shadow diffs, hooks or sequence numbers. A
+    /// panicking callback is caught at the FFI boundary and reported as a
+    /// CallbackPanicked marker; see the panics module. The callback must
+    /// not call set_callback() itself.
     pub fn set_callback<F>(&self, callback: Option<F>) -> Result<(), String>
     where
         F: Fn(&ChangeEvent) + Send + 'static,
     {
-        if let Some(cb) = callback {
-            let boxed = Box::new(cb);
-            *self.callback.lock().unwrap() = Some(boxed);
-            
-            unsafe {
-                let result = memwatch_set_callback(ptr::null_mut(), ptr::null_mut());
-                if result != 0 {
-                    return Err(format!("Failed to set callback: {}", result));
+        // Waiters of wait_for_change() keep the trampoline registered
+        let enabled = callback.is_some() || self.callback.wakers.is_armed();
+        // A panicking callback poisons the slot; replacing it is still fine
+        *self.callback.callback.lock().unwrap_or_else(|e| e.into_inner()) =
+            callback.map(|cb| Box::new(cb) as ChangeEventCallback);
+        self.callback.failures.store(0, Ordering::SeqCst);
+        for backend in self.backends() {
+            backend.set_callback(enabled.then_some(&self.callback))?;
+        }
+        Ok(())
+    }
+    
+    /// Drop changes `filter` rejects, in check_changes() and ahead of the
+    /// set_callback() closure; None removes the filter. See the filter
+    /// module.
+    pub fn set_filter(&self, filter: Option<Filter>) {
+        *self.callback.filter.lock().unwrap_or_else(|e| e.into_inner()) = filter.clone();
+        *self.filter.lock().unwrap() = filter;
+    }
+
+    /// Synchronously check for changes (polling mode)
+    ///
+    /// A native failure or malformed native entry poisons the watcher: later
+    /// calls return `CheckError::Poisoned` until reset().
+    pub fn check_changes(&self) -> Result<Vec<ChangeEvent>, CheckError> {
+        self.check_changes_with_capacity(MAX_EVENTS)
+    }
+
+    /// check_changes() taking up to `max_events` changes from the backend
+    /// (plus any queued markers) instead of 16
+    pub fn check_changes_with_capacity(&self, max_events: usize) -> Result<Vec<ChangeEvent>, CheckError> {
+        if self.is_poisoned() {
+            return Err(CheckError::Poisoned);
+        }
+        self.collect_changes(Some(max_events)).inspect_err(|e| {
+            if e.poisons() {
+                self.poisoned.store(true, Ordering::SeqCst);
+            }
+        })
+    }
+
+    /// check_changes() until the backend has nothing left, as one batch;
+    /// for catching up after a burst. Coalesced changes are released
+    /// without waiting for their window. At most MAX_DRAIN_BATCHES polls
+    /// are taken, so a writer outpacing the drain leaves the rest for the
+    /// next call instead of keeping it here; on an error, the events taken
+    /// so far are kept for the next call.
+    pub fn drain_all_changes(&self) -> Result<DrainedChanges, CheckError> {
+        if self.is_poisoned() {
+            return Err(CheckError::Poisoned);
+        }
+        let events = self.collect_changes(None).inspect_err(|e| {
+            if e.poisons() {
+                self.poisoned.store(true, Ordering::SeqCst);
+            }
+        })?;
+        let drops = self.backend().stats().map(|stats| stats.ring_drop_count).unwrap_or(0);
+        let seen = self.ring_drops_seen.swap(drops, Ordering::SeqCst);
+        Ok(DrainedChanges {
+            events,
+            // The counter restarts with the core (watchdog restarts)
+            dropped_count: drops.checked_sub(seen).unwrap_or(drops),
+        })
+    }
+    
+    /// Whether an earlier native failure left the watcher unusable
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::SeqCst)
+    }
+    
+    /// Record that the block behind a region was freed. The region stays
+    /// watched; writes from now on show up in usage_report() as writes after
+    /// free. Pending changes are queued ahead of the Freed marker first, so
+    /// writes made before the free are not misreported.
+    pub fn mark_freed(&self, region_id: u32) -> Result<(), CheckError> {
+        let name = match self.regions.lock().unwrap().get_mut(&region_id) {
+            Some(info) => {
+                info.freed = true;
+                info.name.clone()
+            }
+            None => return Err(CheckError::Pipeline(format!("Unknown region {}", region_id))),
+        };
+        self.flush_pending()?;
+        self.push_marker(region_id, &name, EventKind::Freed);
+        Ok(())
+    }
+    
+    /// Stop watching a region for now, keeping its id and settings: its
+    /// protection is lifted, so writes run at full speed and go unreported
+    /// until resume(). Changes made before the pause are queued ahead of the
+    /// Paused marker.
+    pub fn pause(&self, region_id: u32) -> Result<(), CheckError> {
+        let name = match self.regions.lock().unwrap().get(&region_id) {
+            Some(info) if info.paused => return Ok(()),
+            Some(info) => info.name.clone(),
+            None => return Err(CheckError::Pipeline(format!("Unknown region {}", region_id))),
+        };
+        self.flush_pending()?;
+        self.backend_for(region_id).pause(region_id).map_err(CheckError::Pipeline)?;
+        if let Some(info) = self.regions.lock().unwrap().get_mut(&region_id) {
+            info.paused = true;
+        }
+        self.push_marker(region_id, &name, EventKind::Paused);
+        Ok(())
+    }
+
+    /// Watch a paused region again. Its contents as of now are the new
+    /// baseline: writes made while paused are never reported.
+    pub fn resume(&self, region_id: u32) -> Result<(), CheckError> {
+        let (name, addr, size) = match self.regions.lock().unwrap().get(&region_id) {
+            Some(info) if !info.paused => return Ok(()),
+            Some(info) => (info.name.clone(), info.addr, info.size),
+            None => return Err(CheckError::Pipeline(format!("Unknown region {}", region_id))),
+        };
+        self.backend_for(region_id).resume(region_id).map_err(CheckError::Pipeline)?;
+        if let Some(shadow) = self.shadow.lock().unwrap().as_mut() {
+            shadow.refresh(addr, size);
+        }
+        if let Some(info) = self.regions.lock().unwrap().get_mut(&region_id) {
+            info.paused = false;
+        }
+        self.push_marker(region_id, &name, EventKind::Resumed);
+        Ok(())
+    }
+
+    /// Pause every region; returns how many were paused
+    pub fn pause_all(&self) -> usize {
+        let mut ids: Vec<u32> = self.regions.lock().unwrap().iter().filter(|(_, info)| !info.paused).map(|(&id, _)| id).collect();
+        ids.sort_unstable();
+        ids.into_iter().filter(|&id| self.pause(id).is_ok()).count()
+    }
+
+    /// Resume every paused region; returns how many were resumed
+    pub fn resume_all(&self) -> usize {
+        let mut ids: Vec<u32> = self.regions.lock().unwrap().iter().filter(|(_, info)| info.paused).map(|(&id, _)| id).collect();
+        ids.sort_unstable();
+        ids.into_iter().filter(|&id| self.resume(id).is_ok()).count()
+    }
+
+    /// Whether a region is between pause() and resume()
+    pub fn is_paused(&self, region_id: u32) -> bool {
+        self.regions.lock().unwrap().get(&region_id).is_some_and(|info| info.paused)
+    }
+
+    /// Move every change waiting in the backend into the marker queue, so
+    /// it is delivered ahead of markers pushed next
+    fn flush_pending(&self) -> Result<(), CheckError> {
+        access::internal(|| {
+            if self.is_poisoned() {
+                return Err(CheckError::Poisoned);
+            }
+            let pending = self.poll_backend(None).inspect_err(|e| {
+                if e.poisons() {
+                    self.poisoned.store(true, Ordering::SeqCst);
                 }
+            })?;
+            self.markers.lock().unwrap().extend(pending);
+            Ok(())
+        })
+    }
+
+    /// Up to `max_events` changes from the backend, or all of them with
+    /// None. On an error, what was already taken goes to the marker queue
+    /// for the next check.
+    fn poll_backend(&self, max_events: Option<usize>) -> Result<Vec<ChangeEvent>, CheckError> {
+        let mut events = Vec::new();
+        let polled = self.poll_backend_into(&mut events, max_events);
+        self.counting.lock().unwrap().count(&mut events);
+        if polled.is_err() {
+            self.markers.lock().unwrap().extend(events);
+            return polled.map(|_| Vec::new());
+        }
+        Ok(events)
+    }
+
+    fn poll_backend_into(&self, events: &mut Vec<ChangeEvent>, max_events: Option<usize>) -> Result<(), CheckError> {
+        if let Some(max_events) = max_events {
+            events.extend(self.backend().poll(max_events)?);
+            if !self.hardware.is_empty() {
+                events.extend(self.hardware.poll(max_events.saturating_sub(events.len()))?);
             }
-        } else {
-            *self.callback.lock().unwrap() = None;
-            unsafe {
-                memwatch_set_callback(ptr::null_mut(), ptr::null_mut());
+            return Ok(());
+        }
+        for _ in 0..MAX_DRAIN_BATCHES {
+            let batch = self.backend().poll(DRAIN_BATCH)?;
+            let more = batch.len() == DRAIN_BATCH;
+            events.extend(batch);
+            if !more {
+                break;
             }
         }
-        
+        if !self.hardware.is_empty() {
+            events.extend(self.hardware.poll(usize::MAX)?);
+        }
         Ok(())
     }
     
-    /// Synchronously check for changes (polling mode)
-    pub fn check_changes(&self) -> Result<Vec<ChangeEvent>, String> {
-        const MAX_EVENTS: usize = 16;
-        let mut c_events = vec![
-            ChangeEventC {
-                seq: 0,
-                timestamp_ns: 0,
-                adapter_id: 0,
-                region_id: 0,
-                variable_name: ptr::null(),
-                file: ptr::null(),
-                function: ptr::null(),
-                line: 0,
-                fault_ip: 0,
-                old_preview: ptr::null(),
-                old_preview_size: 0,
-                new_preview: ptr::null(),
-                new_preview_size: 0,
+    /// Never-written regions and writes after free, from every event
+    /// returned by check_changes() so far
+    pub fn usage_report(&self) -> UsageReport {
+        self.usage.lock().unwrap().report()
+    }
+    
+    fn collect_changes(&self, max_events: Option<usize>) -> Result<Vec<ChangeEvent>, CheckError> {
+        // Polls, hooks and sinks read watched memory on our behalf, and
+        // allocate blocks that are not the program's
+        access::internal(|| {
+            if self.reads.lock().unwrap().is_empty() {
+                return self.collect_pending(max_events);
+            }
+            self.reads.lock().unwrap().collect();
+            let result = self.collect_pending(max_events);
+            self.reads.lock().unwrap().rearm();
+            result
+        })
+    }
+
+    fn collect_pending(&self, max_events: Option<usize>) -> Result<Vec<ChangeEvent>, CheckError> {
+        self.tick_counts();
+        self.check_worker()?;
+        self.soak_tick();
+        self.report_callback_panics();
+        let mut events: Vec<ChangeEvent> = self.markers.lock().unwrap().drain(..).collect();
+        events.iter().for_each(|event| self.trace(event, || TraceStep::Marker));
+        let mut polled = match self.poll_backend(max_events) {
+            Ok(polled) => polled,
+            Err(e) => {
+                // Ahead of whatever the failed poll left in the queue
+                let mut markers = self.markers.lock().unwrap();
+                for event in events.into_iter().rev() {
+                    markers.push_front(event);
+                }
+                return Err(e);
+            }
+        };
+        polled.iter().for_each(|event| self.trace(event, || TraceStep::Produced));
+        self.sample(&mut polled);
+        {
+            let quarantine = self.quarantine.lock().unwrap();
+            for event in &mut polled {
+                if let Some(info) = quarantine.get(event.region_id) {
+                    event.kind = EventKind::UseAfterUnwatch;
+                    event.allocated_at = info.site;
+                }
+            }
+        }
+        events.extend(polled);
+        self.release_quarantined();
+        if self.capabilities.mode == WatchMode::Protect {
+            if let Some(shadow) = self.shadow.lock().unwrap().as_mut() {
+                shadow.apply(&mut events, &self.regions.lock().unwrap());
+            }
+        }
+        diff::annotate(&mut events);
+        let events = {
+            let regions = self.regions.lock().unwrap();
+            let window = |region_id| regions.get(&region_id).and_then(|info| info.coalesce);
+            let mut coalescer = self.coalescer.lock().unwrap();
+            let mut events = coalescer.apply(events, window, std::time::Instant::now());
+            if max_events.is_none() {
+                events.extend(coalescer.flush());
+            }
+            events
+        };
+        let mut events = watchable::split_by_field(events, &self.layouts.lock().unwrap());
+        self.previews.lock().unwrap().apply(&mut events, std::time::Instant::now());
+        {
+            let regions = self.regions.lock().unwrap();
+            for event in events.iter_mut().filter(|e| e.allocated_at.is_none()) {
+                event.allocated_at = regions.get(&event.region_id).and_then(|info| info.site);
+            }
+        }
+        self.groups.lock().unwrap().tag(&mut events);
+        if let Some(filter) = self.filter.lock().unwrap().as_mut() {
+            events.retain(|event| {
+                let keep = filter.accepts(event);
+                if !keep {
+                    self.trace(event, || TraceStep::Filtered { filter: filter.to_string() });
+                }
+                keep
+            });
+        }
+        let mut events = anomaly::detect(&mut self.detectors.lock().unwrap(), events);
+        self.apply_hooks(&mut events);
+        let (events, breaks) = self.triggers.lock().unwrap().fire(events);
+        let mut events = std::mem::take(&mut *self.unstamped.lock().unwrap()).into_iter().chain(events).collect::<Vec<_>>();
+        if let Err(e) = self.sequence.lock().unwrap().stamp(&mut events) {
+            *self.unstamped.lock().unwrap() = events;
+            return Err(e.into());
+        }
+        // From here on, a failure keeps the batch for the next check
+        let mut failed = None;
+        if let Some(blobs) = self.blobs.lock().unwrap().as_ref() {
+            failed = blobs.store(&mut events).err();
+        }
+        self.threads.lock().unwrap().record(&mut events);
+        #[cfg(feature = "tracing")]
+        spans::tag(&mut events);
+        {
+            let mut timeline = self.timeline.lock().unwrap();
+            for event in &events {
+                timeline.record(event);
+            }
+        }
+        {
+            let mut usage = self.usage.lock().unwrap();
+            let mut groups = self.groups.lock().unwrap();
+            for event in &events {
+                usage.record(event);
+                groups.record(event);
+            }
+        }
+        if let Some(causality) = self.causality.lock().unwrap().as_mut() {
+            for event in &events {
+                causality.record(event);
+            }
+        }
+        {
+            let mut recent = self.recent.lock().unwrap();
+            recent.extend(events.iter().cloned());
+            let excess = recent.len().saturating_sub(RECENT_EVENTS);
+            recent.drain(..excess);
+        }
+        if breaks {
+            // Stops with the batch, Triggered markers included, in `events`
+            trigger::break_into_debugger();
+        }
+        self.dispatch_region_callbacks(&events);
+        self.dispatch_subscriptions(&events);
+        if let Err(e) = self.deliver_to_sinks(&events) {
+            failed.get_or_insert(e);
+        }
+        let mut undelivered = self.undelivered.lock().unwrap();
+        if let Some(error) = failed {
+            undelivered.extend(events);
+            return Err(CheckError::Pipeline(error));
+        }
+        let events = std::mem::take(&mut *undelivered).into_iter().chain(events).collect::<Vec<_>>();
+        drop(undelivered);
+        events.iter().for_each(|event| self.trace(event, || TraceStep::Returned));
+        Ok(events)
+    }
+    
+    /// Change counts and bytes changed per `bucket` for a region, covering
+    /// the events returned by check_changes() so far, within the timeline's
+    /// per-region bound (see the timeline module)
+    pub fn timeline(&self, region_id: u32, bucket: std::time::Duration) -> Vec<timeline::BucketStats> {
+        self.timeline.lock().unwrap().query(region_id, bucket)
+    }
+    
+    /// The last events returned by check_changes() (up to 1024), oldest first
+    pub fn recent_events(&self) -> Vec<ChangeEvent> {
+        self.recent.lock().unwrap().iter().cloned().collect()
+    }
+    
+    /// Current contents of a region, read without waiting for a write.
+    /// Watched pages stay readable (only writes fault), so this never
+    /// touches their protection. Freed regions are refused.
+    pub fn peek(&self, region_id: u32) -> Result<Vec<u8>, String> {
+        let regions = self.regions.lock().unwrap();
+        let info = regions.get(&region_id).ok_or_else(|| format!("Unknown region {}", region_id))?;
+        if info.freed {
+            return Err(format!("Region {} was freed", region_id));
+        }
+        // SAFETY: registered regions are valid for reads until unwatched
+        Ok(access::internal(|| unsafe { std::slice::from_raw_parts(info.addr as *const u8, info.size) }.to_vec()))
+    }
+
+    /// Current value of a region as a `T`, read from its first bytes
+    ///
+    /// # Safety
+    ///
+    /// Those bytes must be a valid `T`, e.g. the region was registered from
+    /// a `T` (or a slice of them) that is still alive.
+    pub unsafe fn peek_as<T: Copy>(&self, region_id: u32) -> Result<T, String> {
+        let bytes = self.peek(region_id)?;
+        if bytes.len() < std::mem::size_of::<T>() {
+            return Err(format!(
+                "Region {} holds {} bytes, {} needs {}",
+                region_id,
+                bytes.len(),
+                std::any::type_name::<T>(),
+                std::mem::size_of::<T>()
+            ));
+        }
+        Ok(std::ptr::read_unaligned(bytes.as_ptr() as *const T))
+    }
+
+    /// Write `bytes` at `offset` into a region on the caller's behalf. The
+    /// write is reported as a change with `source: EventSource::Poke`,
+    /// attributed to the calling line and thread. The region is paused in
+    /// its backend for the write (no change recorded), so a concurrent
+    /// writer in that window goes unreported.
+    ///
+    /// # Safety
+    ///
+    /// watch() only borrowed the region's memory shared: it must be
+    /// writable, and no `&` reference to the poked bytes may be live, e.g.
+    /// the region was watched from a buffer the caller owns and only reads
+    /// through raw pointers or after the poke.
+    #[track_caller]
+    pub unsafe fn poke(&self, region_id: u32, offset: usize, bytes: &[u8]) -> Result<(), String> {
+        let caller = std::panic::Location::caller();
+        let info = self
+            .regions
+            .lock()
+            .unwrap()
+            .get(&region_id)
+            .cloned()
+            .ok_or_else(|| format!("Unknown region {}", region_id))?;
+        if info.freed {
+            return Err(format!("Region {} was freed", region_id));
+        }
+        if offset.checked_add(bytes.len()).is_none_or(|end| end > info.size) {
+            return Err(format!(
+                "Poke of {} bytes at offset {} is outside region {} ({} bytes)",
+                bytes.len(),
+                offset,
+                region_id,
+                info.size
+            ));
+        }
+
+        // Earlier writes belong to earlier events
+        self.flush_pending()?;
+        let old = self.peek(region_id)?;
+        let backend = self.backend_for(region_id);
+        // Backends that cannot pause (hardware breakpoints) skip the write
+        // in resync() instead
+        let lifted = !info.paused && backend.pause(region_id).is_ok();
+        let target = (info.addr as usize + offset) as *mut u8;
+        for (i, &byte) in bytes.iter().enumerate() {
+            // SAFETY: inside the region, checked above; writable and not
+            // borrowed, as the caller guarantees
+            unsafe { std::ptr::write_volatile(target.add(i), byte) };
+        }
+        if lifted {
+            backend.resume(region_id)?;
+        }
+        backend.resync(region_id);
+        let new = self.peek(region_id)?;
+        if new[offset..offset + bytes.len()] != *bytes {
+            return Err(format!("Poke of region {} at offset {} did not take", region_id, offset));
+        }
+
+        let mut event = lifecycle::marker(region_id, &info.name, EventKind::Change);
+        event.where_.file = Some(caller.file().to_string());
+        event.where_.line = caller.line();
+        event.old_preview = old[..old.len().min(polling::PREVIEW_SIZE)].to_vec();
+        event.new_preview = new[..new.len().min(polling::PREVIEW_SIZE)].to_vec();
+        event.old_value = old;
+        event.new_value = new;
+        event.source = EventSource::Poke;
+        self.queue_marker(event);
+        Ok(())
+    }
+
+    /// Lifecycle markers of a region as (timestamp_ns, kind), oldest first
+    pub fn timeline_markers(&self, region_id: u32) -> Vec<(u64, EventKind)> {
+        self.timeline.lock().unwrap().markers(region_id)
+    }
+
+    /// Starts (ns) of a region's first and last active millisecond, cheaper
+    /// than a timeline() query that spans them
+    pub fn timeline_span(&self, region_id: u32) -> Option<(u64, u64)> {
+        self.timeline.lock().unwrap().span(region_id)
+    }
+    
+    /// Add a detector; its findings appear as `EventKind::Anomaly` events
+    pub fn add_detector<D: AnomalyDetector + 'static>(&self, detector: D) {
+        self.detectors.lock().unwrap().push(Box::new(detector));
+    }
+    
+    /// Register a preview decoder under its name, replacing any of that name
+    pub fn add_decoder<D: PreviewDecoder + 'static>(&self, decoder: D) {
+        self.decoders.lock().unwrap().add(Arc::new(decoder));
+    }
+    
+    /// Load a decoder plugin from a shared library (see `decode`); returns
+    /// the name it registered under
+    pub fn load_decoder(&self, path: &str) -> Result<String, String> {
+        let decoder = LibraryDecoder::load(path)?;
+        Ok(self.decoders.lock().unwrap().add(Arc::new(decoder)))
+    }
+    
+    /// Decode the values of `region_id` with the decoder named `decoder`
+    pub fn set_region_decoder(&self, region_id: u32, decoder: &str) -> Result<(), String> {
+        if !self.regions.lock().unwrap().contains_key(&region_id) {
+            return Err(format!("Unknown region {}", region_id));
+        }
+        self.decoders.lock().unwrap().assign(region_id, decoder)
+    }
+    
+    /// Structured old and new values of a change, when its region has a
+    /// decoder
+    pub fn decode(&self, event: &ChangeEvent) -> Option<DecodedChange> {
+        if event.kind.is_marker() {
+            return None;
+        }
+        self.decoders.lock().unwrap().decode(event)
+    }
+    
+    /// Expect `region_id` to be written only while `lock` is held (by the
+    /// writing thread, when events carry one); other writes are followed by
+    /// an `Anomaly(UnlockedWrite)` event. Pass a TrackedMutex or LockMonitor.
+    pub fn watch_lock_pairing(&self, region_id: u32, lock: &impl AsRef<LockMonitor>) -> Result<(), String> {
+        if !self.regions.lock().unwrap().contains_key(&region_id) {
+            return Err(format!("Unknown region {}", region_id));
+        }
+        // The detector holds the only other reference to the pairs
+        if Arc::strong_count(&self.lock_pairs) == 1 {
+            self.add_detector(LockPairing { pairs: self.lock_pairs.clone() });
+        }
+        self.lock_pairs.lock().unwrap().insert(region_id, lock.as_ref().clone());
+        Ok(())
+    }
+    
+    /// Add a pre-persist hook. Hooks see every change before it is sequenced,
+    /// returned or written to sinks, and can veto recording it or raise an
+    /// alert. Advisory only: the write has already happened.
+    pub fn add_pre_persist_hook<F>(&self, hook: F)
+    where
+        F: Fn(&ChangeEvent) -> Decision + Send + 'static,
+    {
+        self.hooks.lock().unwrap().push(Box::new(hook));
+    }
+    
+    /// Alerts raised by hooks since the last call
+    pub fn take_alerts(&self) -> Vec<Alert> {
+        self.alerts.lock().unwrap().take()
+    }
+
+    /// Hold back alerts on a subject the same hook raised one for less than
+    /// `window` ago, reporting them as one aggregate alert; see the policy
+    /// module. None reports every alert.
+    pub fn set_alert_suppression(&self, window: Option<std::time::Duration>) {
+        self.alerts.lock().unwrap().set_suppression(window);
+    }
+    
+    fn apply_hooks(&self, events: &mut Vec<ChangeEvent>) {
+        let hooks = self.hooks.lock().unwrap();
+        if hooks.is_empty() {
+            return;
+        }
+        let mut alerts = self.alerts.lock().unwrap();
+        events.retain(|event| {
+            let keep = event.kind.is_marker()
+                || policy::evaluate(
+                    &hooks,
+                    event,
+                    || event.variable_name.clone().unwrap_or_else(|| format!("region {}", event.region_id)),
+                    event.timestamp_ns,
+                    &mut alerts,
+                );
+            if !keep {
+                self.trace(event, || TraceStep::Vetoed);
+            }
+            keep
+        });
+    }
+    
+    /// Add a sink that receives every event returned by check_changes()
+    pub fn add_sink<S: EventSink + 'static>(&self, mut sink: S) {
+        sink.metadata(&self.metadata());
+        self.sinks.lock().unwrap().push((Box::new(sink), 0));
+    }
+    
+    /// Run watch_with_callback() handlers. They are taken out of the map
+    /// while running, so a handler may watch or unwatch regions itself.
+    fn dispatch_region_callbacks(&self, events: &[ChangeEvent]) {
+        let mut callbacks = std::mem::take(&mut *self.region_callbacks.lock().unwrap());
+        if callbacks.is_empty() {
+            return;
+        }
+        for event in events {
+            let Some((callback, failures)) = callbacks.get_mut(&event.region_id) else {
+                continue;
             };
-            MAX_EVENTS
-        ];
-        
-        unsafe {
-            let count = memwatch_check_changes(c_events.as_mut_ptr(), MAX_EVENTS as c_int);
-            
-            let mut result = Vec::with_capacity(count as usize);
-            for i in 0..(count as usize) {
-                let c_evt = &c_events[i];
-                
-                result.push(ChangeEvent {
-                    seq: c_evt.seq,
-                    timestamp_ns: c_evt.timestamp_ns,
-                    adapter_id: c_evt.adapter_id,
-                    region_id: c_evt.region_id,
-                    variable_name: if c_evt.variable_name.is_null() {
-                        None
-                    } else {
-                        Some(CString::from_raw(c_evt.variable_name as *mut c_char).into_string().unwrap_or_default())
-                    },
-                    where_: Location {
-                        file: if c_evt.file.is_null() {
-                            None
-                        } else {
-                            Some(CString::from_raw(c_evt.file as *mut c_char).into_string().unwrap_or_default())
-                        },
-                        function: if c_evt.function.is_null() {
-                            None
-                        } else {
-                            Some(CString::from_raw(c_evt.function as *mut c_char).into_string().unwrap_or_default())
-                        },
-                        line: c_evt.line,
-                        fault_ip: c_evt.fault_ip,
-                    },
-                    old_preview: if c_evt.old_preview.is_null() {
-                        Vec::new()
-                    } else {
-                        std::slice::from_raw_parts(c_evt.old_preview, c_evt.old_preview_size).to_vec()
-                    },
-                    new_preview: if c_evt.new_preview.is_null() {
-                        Vec::new()
-                    } else {
-                        std::slice::from_raw_parts(c_evt.new_preview, c_evt.new_preview_size).to_vec()
-                    },
-                    old_value: Vec::new(),
-                    new_value: Vec::new(),
-                    storage_key_old: None,
-                    storage_key_new: None,
-                });
-                
-                memwatch_free_event(&mut c_events[i]);
-            }
-            
-            Ok(result)
-        }
-    }
-    
-    /// Get statistics
+            let mut remove = event.kind == EventKind::Unwatched;
+            let start = std::time::Instant::now();
+            let called = panics::guarded(|| callback(event));
+            let to = || "region callback".to_string();
+            match &called {
+                Ok(()) => self.trace(event, || TraceStep::Delivered { to: to(), micros: pipeline_trace::micros_since(start) }),
+                Err(message) => self.trace(event, || TraceStep::Failed { to: to(), error: message.clone() }),
+            }
+            if let Err(message) = called {
+                *failures += 1;
+                let disabled = panics::exhausted(*failures, self.failure_limit());
+                let name = event.variable_name.as_deref().unwrap_or_default();
+                let observer = panics::Observer::RegionCallback;
+                self.queue_marker(panics::marker(event.region_id, name, observer, *failures, disabled, &message));
+                remove |= disabled;
+            }
+            if remove {
+                callbacks.remove(&event.region_id);
+            }
+        }
+        self.region_callbacks.lock().unwrap().extend(callbacks);
+    }
+
+    fn deliver_to_sinks(&self, events: &[ChangeEvent]) -> Result<(), String> {
+        let delivered = self.write_sinks(events);
+        let mut failing_since = self.sink_failing_since.lock().unwrap();
+        match delivered {
+            Ok(()) => *failing_since = None,
+            Err(_) => {
+                failing_since.get_or_insert_with(std::time::Instant::now);
+            }
+        }
+        delivered
+    }
+    
+    /// Deliver to every sink. A panicking sink loses the rest of the batch
+    /// but does not keep the others from theirs.
+    fn write_sinks(&self, events: &[ChangeEvent]) -> Result<(), String> {
+        let mut sinks = self.sinks.lock().unwrap();
+        let mut delivered = Ok(());
+        let mut index = 0;
+        let mut original_index = 0;
+        while index < sinks.len() {
+            let (sink, failures) = &mut sinks[index];
+            let to = || format!("sink {}", original_index);
+            let written = panics::guarded(|| {
+                for event in events {
+                    let start = std::time::Instant::now();
+                    if let Err(error) = sink.write(event) {
+                        self.trace(event, || TraceStep::Failed { to: to(), error: error.clone() });
+                        return Err(error);
+                    }
+                    self.trace(event, || TraceStep::Delivered { to: to(), micros: pipeline_trace::micros_since(start) });
+                }
+                sink.flush()
+            });
+            let mut disabled = false;
+            match written {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    if delivered.is_ok() {
+                        delivered = Err(e);
+                    }
+                }
+                Err(message) => {
+                    *failures += 1;
+                    disabled = panics::exhausted(*failures, self.failure_limit());
+                    let observer = panics::Observer::Sink { index: original_index };
+                    self.queue_marker(panics::marker(0, "event sink", observer, *failures, disabled, &message));
+                }
+            }
+            if disabled {
+                sinks.remove(index);
+            } else {
+                index += 1;
+            }
+            original_index += 1;
+        }
+        delivered
+    }
+    
+    /// Get statistics. mprotect_page_count counts huge pages as one page
+    /// each, where the core counts base pages.
     pub fn get_stats(&self) -> Result<Stats, String> {
-        unsafe {
-            let mut c_stats = std::mem::zeroed::<StatsC>();
-            let result = memwatch_get_stats(&mut c_stats);
-            
-            if result != 0 {
-                return Err(format!("Failed to get stats: {}", result));
-            }
-            
-            Ok(Stats {
-                num_tracked_regions: c_stats.num_tracked_regions,
-                num_active_watchpoints: c_stats.num_active_watchpoints,
-                total_events: c_stats.total_events,
-                ring_write_count: c_stats.ring_write_count,
-                ring_drop_count: c_stats.ring_drop_count,
-                storage_bytes_used: c_stats.storage_bytes_used,
-                mprotect_page_count: c_stats.mprotect_page_count,
-                worker_thread_id: c_stats.worker_thread_id,
-                worker_cycles: c_stats.worker_cycles,
-            })
+        let mut stats = self.backend().stats()?;
+        if self.capabilities.mode == WatchMode::Protect {
+            let base = self.capabilities.page_size;
+            let (mut as_base, mut as_huge) = (0, 0);
+            for info in self.regions.lock().unwrap().values().filter(|info| info.page_size > base) {
+                as_base += info.pages(base);
+                as_huge += info.pages(info.page_size);
+            }
+            stats.mprotect_page_count = (stats.mprotect_page_count as u64).saturating_sub(as_base).saturating_add(as_huge) as u32;
         }
+        stats.sampled_out_count = self.sampled_out.load(Ordering::Relaxed);
+        stats.consumers = self.consumer_stats();
+        stats.events_per_thread = self.threads.lock().unwrap().stats();
+        Ok(stats)
+    }
+
+    /// Drop changes their region's sampler (or soak mode) rejects; see the
+    /// sampling and soak modules
+    fn sample(&self, events: &mut Vec<ChangeEvent>) {
+        let now = std::time::Instant::now();
+        let mut regions = self.regions.lock().unwrap();
+        let mut soak = self.soak.lock().unwrap();
+        let before = events.len();
+        events.retain(|event| {
+            let keep = event.kind.is_marker()
+                || (regions.get_mut(&event.region_id).and_then(|info| info.sampler.as_mut()).is_none_or(|sampler| sampler.keep(now))
+                    && soak.as_mut().is_none_or(|soak| soak.keep()));
+            if !keep {
+                self.trace(event, || TraceStep::SampledOut);
+            }
+            keep
+        });
+        self.sampled_out.fetch_add((before - events.len()) as u64, Ordering::Relaxed);
     }
 }
 
+/// Events drained from the backend per poll
+const MAX_EVENTS: usize = 16;
+
+/// Events per poll while draining the backend
+const DRAIN_BATCH: usize = 256;
+
+/// Polls per drain, so a steady writer cannot keep one going forever
+const MAX_DRAIN_BATCHES: usize = 64;
+
+/// Events kept for recent_events() and forensic captures
+const RECENT_EVENTS: usize = 1024;
+
 impl Drop for MemWatch {
     fn drop(&mut self) {
-        unsafe {
-            memwatch_shutdown();
+        for backend in self.backends() {
+            let _ = backend.set_callback(None);
+            backend.shutdown();
         }
     }
 }
@@ -306,3 +1803,620 @@ impl Default for MemWatch {
         Self::new().expect("Failed to initialize MemWatch")
     }
 }
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use fake_native::FakeEvent;
+    use std::time::Duration;
+
+    fn protected_watcher() -> MemWatch {
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Protect;
+        watcher
+    }
+
+    #[test]
+    fn test_callback_fires_through_trampoline() {
+        let _guard = fake_native::lock();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let watcher = {
+            let watcher = protected_watcher();
+            let seen = Arc::clone(&seen);
+            watcher.set_callback(Some(move |e: &ChangeEvent| seen.lock().unwrap().push((e.region_id, e.new_preview.clone())))).unwrap();
+            // The slot must survive moves of the watcher
+            Box::new(watcher)
+        };
+        assert!(fake_native::fire(FakeEvent::change(3, &[1, 2])));
+        assert_eq!(*seen.lock().unwrap(), vec![(3, vec![1, 2])]);
+
+        // A panic stays on the Rust side of the boundary
+        watcher.set_callback(Some(|_: &ChangeEvent| panic!("callback failed"))).unwrap();
+        assert!(fake_native::fire(FakeEvent::change(3, &[4])));
+
+        watcher.set_callback(None::<fn(&ChangeEvent)>).unwrap();
+        assert!(!fake_native::fire(FakeEvent::change(3, &[5])));
+        assert_eq!(seen.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_relocate_moves_a_region() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        let old = [0u8; 8];
+        let mut new = vec![0u8; 8];
+        let options = options::WatchOptions { tags: vec!["hot".to_string()], ..Default::default() };
+        let old_id = watcher.watch_with_options(&old, "moving", &options).unwrap();
+        watcher.check_changes().unwrap();
+
+        let new_id = watcher.relocate(old_id, &new).unwrap();
+        new[0] = 1;
+        let kinds: Vec<(u32, EventKind)> = watcher.check_changes().unwrap().iter().map(|e| (e.region_id, e.kind)).collect();
+        let (old_addr, new_addr) = (old.as_ptr() as u64, new.as_ptr() as u64);
+        assert_eq!(
+            kinds,
+            [
+                (old_id, EventKind::Unwatched),
+                (new_id, EventKind::Watched { addr: new_addr, size: 8 }),
+                (new_id, EventKind::Relocated { old_addr, new_addr }),
+                (new_id, EventKind::Change),
+            ]
+        );
+        assert_eq!(watcher.regions.lock().unwrap()[&new_id].tags, ["hot"]);
+        assert!(watcher.relocate(old_id, &new).is_err());
+    }
+
+    #[test]
+    fn test_watchdog_reports_stall_and_restarts() {
+        let _guard = fake_native::lock();
+        let watcher = protected_watcher();
+        let buffer = [0u8; 8];
+        let old_id = watcher.watch(&buffer, "buffer").unwrap();
+        watcher.set_watchdog(Some(Watchdog { stall_after: std::time::Duration::ZERO, restart: true }));
+        assert_eq!(watcher.check_changes().unwrap().len(), 1);
+
+        let kinds: Vec<_> = watcher.check_changes().unwrap().into_iter().map(|e| (e.region_id, e.kind)).collect();
+        assert!(matches!(kinds[0], (0, EventKind::WorkerStalled { thread_id: 1, .. })));
+        let new_id = kinds[2].0;
+        assert_eq!(&kinds[1..], [(old_id, EventKind::Unwatched), (new_id, EventKind::Watched { addr: buffer.as_ptr() as u64, size: 8 })]);
+        assert_eq!(fake_native::state().inits, 2);
+
+        // Reported once per stall; progress re-arms
+        assert!(watcher.check_changes().unwrap().is_empty());
+        fake_native::state().worker_cycles = 5;
+        assert!(watcher.check_changes().unwrap().is_empty());
+        assert_eq!(watcher.unwatch(new_id).unwrap(), Unwatch::Removed);
+    }
+
+    #[test]
+    fn test_native_error_poisons_until_reset() {
+        let _guard = fake_native::lock();
+        let watcher = protected_watcher();
+        fake_native::state().check_result = Some(-5);
+
+        let err = watcher.check_changes().unwrap_err();
+        assert_eq!(err, CheckError::Native(NativeError { call: "memwatch_check_changes", code: -5 }));
+        assert!(watcher.is_poisoned());
+
+        fake_native::state().check_result = None;
+        assert_eq!(watcher.check_changes().unwrap_err(), CheckError::Poisoned);
+        watcher.reset().unwrap();
+        assert!(watcher.check_changes().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_failed_delivery_keeps_the_batch() {
+        struct FlakySink(Arc<AtomicBool>);
+
+        impl EventSink for FlakySink {
+            fn write(&mut self, _event: &ChangeEvent) -> Result<(), String> {
+                match self.0.swap(false, Ordering::SeqCst) {
+                    true => Err("disk full".to_string()),
+                    false => Ok(()),
+                }
+            }
+        }
+
+        let _guard = fake_native::lock();
+        let watcher = protected_watcher();
+        let failing = Arc::new(AtomicBool::new(true));
+        watcher.add_sink(FlakySink(Arc::clone(&failing)));
+        fake_native::state().events = vec![FakeEvent::change(1, &[1])];
+        assert!(matches!(watcher.check_changes().unwrap_err(), CheckError::Pipeline(_)));
+
+        fake_native::state().events = vec![FakeEvent::change(1, &[2])];
+        let previews: Vec<Vec<u8>> = watcher.check_changes().unwrap().into_iter().map(|e| e.new_preview).collect();
+        assert_eq!(previews, vec![vec![1], vec![2]]);
+        assert!(watcher.check_changes().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_malformed_entries_are_rejected_and_freed() {
+        let _guard = fake_native::lock();
+        let watcher = protected_watcher();
+        let mut broken = FakeEvent::change(1, &[]);
+        broken.null_preview = true;
+        broken.new_preview_size = 64;
+        fake_native::state().events = vec![FakeEvent::change(1, &[1]), broken];
+
+        let err = watcher.check_changes().unwrap_err();
+        assert!(matches!(err, CheckError::Malformed { index: 1, .. }));
+        assert_eq!(fake_native::state().freed, 2);
+        assert!(watcher.is_poisoned());
+
+        watcher.reset().unwrap();
+        fake_native::state().check_result = Some(100);
+        assert!(matches!(watcher.check_changes(), Err(CheckError::Malformed { index: 16, .. })));
+    }
+
+    #[test]
+    fn test_native_strings_are_copied() {
+        let _guard = fake_native::lock();
+        let watcher = protected_watcher();
+        fake_native::state().events = vec![FakeEvent::change(3, &[9, 9])];
+
+        let events = watcher.check_changes().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].variable_name.as_deref(), Some("fake"));
+        assert_eq!(events[0].where_.function.as_deref(), Some("fake_writer"));
+        assert_eq!(events[0].new_preview, vec![9, 9]);
+        assert_eq!(fake_native::state().freed, 1);
+    }
+
+    #[test]
+    fn test_capacity_and_drain_all() {
+        let _guard = fake_native::lock();
+        let watcher = protected_watcher();
+        fake_native::state().events = (0..600).map(|i| FakeEvent::change(3, &[i as u8])).collect();
+
+        assert_eq!(watcher.check_changes().unwrap().len(), 16);
+        assert_eq!(watcher.check_changes_with_capacity(100).unwrap().len(), 100);
+        fake_native::state().ring_drops = 7;
+        let drained = watcher.drain_all_changes().unwrap();
+        assert_eq!((drained.events.len(), drained.dropped_count), (484, 7));
+        assert_eq!(drained.events.last().unwrap().new_preview, [(599 % 256) as u8]);
+
+        fake_native::state().ring_drops = 9;
+        let drained = watcher.drain_all_changes().unwrap();
+        assert_eq!((drained.events.len(), drained.dropped_count), (0, 2));
+
+        // A backlog past the cap takes more than one drain
+        fake_native::state().events = (0..MAX_DRAIN_BATCHES * DRAIN_BATCH + 5).map(|_| FakeEvent::change(3, &[1])).collect();
+        assert_eq!(watcher.drain_all_changes().unwrap().events.len(), MAX_DRAIN_BATCHES * DRAIN_BATCH);
+        assert_eq!(watcher.drain_all_changes().unwrap().events.len(), 5);
+    }
+
+    #[test]
+    fn test_huge_pages_count_once_in_stats() {
+        let _guard = fake_native::lock();
+        let watcher = protected_watcher();
+        let base = watcher.capabilities.page_size;
+        let buffer = vec![0u8; 3 * base];
+        let region_id = watcher.watch(&buffer, "buffer").unwrap();
+        let base_pages = watcher.regions.lock().unwrap()[&region_id].pages(base) as u32;
+        fake_native::state().protected_pages = base_pages + 5;
+        assert_eq!(watcher.get_stats().unwrap().mprotect_page_count, base_pages + 5);
+
+        // As if the buffer sat inside one huge page
+        watcher.regions.lock().unwrap().get_mut(&region_id).unwrap().page_size = 2 << 20;
+        let huge_pages = watcher.regions.lock().unwrap()[&region_id].pages(2 << 20) as u32;
+        assert_eq!(watcher.get_stats().unwrap().mprotect_page_count, huge_pages + 5);
+    }
+
+    #[test]
+    fn test_region_callbacks_get_their_own_events() {
+        let _guard = fake_native::lock();
+        let watcher = protected_watcher();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let observer = |tag: &'static str| {
+            let seen = Arc::clone(&seen);
+            move |event: &ChangeEvent| seen.lock().unwrap().push((tag, event.kind.as_str()))
+        };
+        let (a, b, c) = ([0u8; 8], [0u8; 8], [0u8; 8]);
+        let a_id = watcher.watch_with_callback(&a, "a", observer("a")).unwrap();
+        let b_id = watcher.watch_with_callback(&b, "b", observer("b")).unwrap();
+        let c_id = watcher.watch(&c, "c").unwrap();
+        fake_native::state().events = vec![FakeEvent::change(a_id, &[1]), FakeEvent::change(c_id, &[1])];
+        watcher.check_changes().unwrap();
+        watcher.unwatch(b_id).unwrap();
+        fake_native::state().events = vec![FakeEvent::change(b_id, &[1])];
+        watcher.check_changes().unwrap();
+
+        let mut seen = seen.lock().unwrap().clone();
+        seen.retain(|(_, kind)| *kind != "shared_page_warning");
+        assert_eq!(seen, [("a", "watched"), ("b", "watched"), ("a", "change"), ("b", "unwatched")]);
+        assert!(watcher.region_callbacks.lock().unwrap().contains_key(&a_id));
+        assert!(!watcher.region_callbacks.lock().unwrap().contains_key(&b_id));
+    }
+
+    #[test]
+    fn test_hooks_veto_events_and_raise_alerts() {
+        let _guard = fake_native::lock();
+        let watcher = protected_watcher();
+        watcher.add_pre_persist_hook(|event| match event.new_preview.first() {
+            Some(0) => Decision::Veto,
+            Some(255) => Decision::Alert("saturated".to_string()),
+            _ => Decision::Record,
+        });
+        fake_native::state().events = vec![FakeEvent::change(1, &[0]), FakeEvent::change(1, &[255]), FakeEvent::change(1, &[3])];
+
+        let events = watcher.check_changes().unwrap();
+        assert_eq!(events.iter().map(|e| e.new_preview[0]).collect::<Vec<_>>(), vec![255, 3]);
+        assert_eq!(events[0].global_seq + 1, events[1].global_seq);
+        let alerts = watcher.take_alerts();
+        assert_eq!((alerts.len(), alerts[0].message.as_str()), (1, "saturated"));
+    }
+
+    #[test]
+    fn test_events_carry_allocation_site() {
+        struct Packet {
+            _len: u16,
+        }
+        let _guard = fake_native::lock();
+        let watcher = protected_watcher();
+        let packets = vec![Packet { _len: 60 }, Packet { _len: 1500 }];
+        let id = watch_typed!(watcher, packets, "rx_queue").unwrap();
+        let line = line!() - 1;
+        fake_native::state().events = vec![FakeEvent::change(id, &[1])];
+
+        let events = watcher.check_changes().unwrap();
+        let expected = format!("Vec<Packet> allocated at {}:{}", file!(), line);
+        assert!(events.iter().all(|e| e.allocated_at.map(|site| site.to_string()) == Some(expected.clone())));
+        assert_eq!(lifecycle::short_type_name("core::option::Option<(u8, alloc::string::String)>"), "Option<(u8, String)>");
+    }
+
+    #[test]
+    fn test_usage_report_finds_unused_and_freed_writes() {
+        let _guard = fake_native::lock();
+        let watcher = protected_watcher();
+        let (used, unused) = ([0u8; 8], [0u8; 32]);
+        let used_id = watcher.watch(&used, "used").unwrap();
+        let unused_id = watcher.watch(&unused, "unused").unwrap();
+
+        fake_native::state().events = vec![FakeEvent::change(used_id, &[1])];
+        watcher.mark_freed(used_id).unwrap();
+        fake_native::state().events = vec![FakeEvent::change(used_id, &[2])];
+        // The two stack buffers may share a page
+        let kinds: Vec<_> = watcher
+            .check_changes()
+            .unwrap()
+            .iter()
+            .filter(|e| !matches!(e.kind, EventKind::SharedPageWarning { .. }))
+            .map(|e| e.kind.as_str())
+            .collect();
+        assert_eq!(kinds, ["watched", "watched", "change", "freed", "change"]);
+
+        let report = watcher.usage_report();
+        assert_eq!(report.never_written.iter().map(|u| u.region_id).collect::<Vec<_>>(), vec![unused_id]);
+        assert_eq!(report.wasted_bytes(), 32);
+        let freed = &report.written_after_free[0];
+        assert_eq!((freed.region_id, freed.writes, freed.writes_after_free), (used_id, 1, 1));
+        assert_eq!(freed.first_write_after_free.as_ref().unwrap().new_preview, vec![2]);
+        assert!(watcher.mark_freed(9999).is_err());
+    }
+
+    #[test]
+    fn test_quarantine_reports_late_writes() {
+        let _guard = fake_native::lock();
+        let watcher = protected_watcher();
+        let buffer = [0u8; 16];
+        let id = watcher.watch(&buffer, "session").unwrap();
+        watcher.set_quarantine(Some(Quarantine { duration: Duration::from_secs(60), max_bytes: 1 << 20 }));
+
+        fake_native::state().events = vec![FakeEvent::change(id, &[1])];
+        assert_eq!(watcher.unwatch(id), Ok(Unwatch::Removed));
+        assert_eq!(watcher.unwatch(id), Ok(Unwatch::NotTracked));
+        fake_native::state().events = vec![FakeEvent::change(id, &[2])];
+        let kinds: Vec<_> = watcher.check_changes().unwrap().iter().map(|e| e.kind.as_str()).collect();
+        assert_eq!(kinds, ["watched", "change", "unwatched", "use_after_unwatch"]);
+
+        // Over the byte budget: released at the next check
+        watcher.set_quarantine(Some(Quarantine { duration: Duration::from_secs(60), max_bytes: 0 }));
+        watcher.check_changes().unwrap();
+        fake_native::state().events = vec![FakeEvent::change(id, &[3])];
+        assert_eq!(watcher.check_changes().unwrap()[0].kind, EventKind::Change);
+    }
+
+    #[test]
+    fn test_reused_memory_raises_alias_warning() {
+        let _guard = fake_native::lock();
+        let watcher = protected_watcher();
+        let block = vec![0u8; 64];
+        let old = watcher.watch(&block[..32], "old").unwrap();
+        let nested = watcher.watch(&block[8..16], "nested").unwrap();
+        watcher.mark_freed(old).unwrap();
+        let new = watcher.watch(&block[16..48], "new").unwrap();
+        let typed = watch_typed!(watcher, block, "typed").unwrap();
+        let site = AllocationSite::caller().with_type_name("Packet");
+        let packet = watcher.watch_tagged(&block[40..], "packet", site).unwrap();
+
+        let warnings: Vec<_> = watcher
+            .check_changes()
+            .unwrap()
+            .into_iter()
+            .filter_map(|e| match e.kind {
+                EventKind::AliasWarning { other } => Some((e.region_id, other)),
+                _ => None,
+            })
+            .collect();
+        // `nested` overlaps live, untyped `old` and is not a reuse
+        assert_eq!(warnings, vec![(old, new), (old, typed), (typed, packet)]);
+        assert!(nested < new);
+    }
+
+    #[test]
+    fn test_writes_outside_paired_lock_are_flagged() {
+        let _guard = fake_native::lock();
+        let watcher = protected_watcher();
+        let counter = [0u8; 8];
+        let id = watcher.watch(&counter, "counter").unwrap();
+        let lock = locks::TrackedMutex::new(());
+        watcher.watch_lock_pairing(id, &lock).unwrap();
+        watcher.watch_lock_pairing(id, &lock).unwrap();
+
+        let locked_at = {
+            let _held = lock.lock().unwrap();
+            locks::monotonic_ns()
+        };
+        let unlocked_at = locks::monotonic_ns();
+        let mut locked = FakeEvent::change(id, &[1]);
+        locked.timestamp_ns = locked_at;
+        let mut unlocked = FakeEvent::change(id, &[2]);
+        unlocked.timestamp_ns = unlocked_at;
+        fake_native::state().events = vec![locked, unlocked];
+
+        let kinds: Vec<_> = watcher.check_changes().unwrap().iter().map(|e| e.kind).collect();
+        assert_eq!(
+            kinds[1..],
+            [EventKind::Change, EventKind::Change, EventKind::Anomaly(anomaly::AnomalyKind::UnlockedWrite)]
+        );
+        assert!(watcher.watch_lock_pairing(9999, &lock).is_err());
+    }
+
+    #[test]
+    fn test_peek_reads_current_values() {
+        let _guard = fake_native::lock();
+        let watcher = protected_watcher();
+        let mut counters = vec![7u32, 9];
+        let id = watcher.watch_vec(&counters, "counters").unwrap();
+        counters[1] = 10;
+
+        assert_eq!(watcher.peek(id).unwrap(), [7, 0, 0, 0, 10, 0, 0, 0]);
+        assert_eq!(unsafe { watcher.peek_as::<[u32; 2]>(id) }, Ok([7, 10]));
+        assert!(unsafe { watcher.peek_as::<[u32; 3]>(id) }.is_err());
+        assert!(watcher.peek(9999).is_err());
+        watcher.mark_freed(id).unwrap();
+        assert!(watcher.peek(id).is_err());
+    }
+
+    #[test]
+    fn test_watch_guard_unwatches_on_drop() {
+        let _guard = fake_native::lock();
+        for mode in [WatchMode::Protect, WatchMode::Snapshot] {
+            let mut watcher = MemWatch::new().unwrap();
+            watcher.capabilities.mode = mode;
+            let mut balance = [0u8; 8];
+            let id = {
+                let mut guard = watcher.watch_guarded(&mut balance, "balance").unwrap();
+                guard[0] = 42;
+                assert_eq!(watcher.peek(guard.region_id()).unwrap()[0], 42);
+                guard.region_id()
+            };
+            assert_eq!(balance[0], 42);
+            assert_eq!(watcher.unwatch(id), Ok(Unwatch::NotTracked));
+
+            let guard = watcher.watch_guarded(&mut balance, "balance").unwrap();
+            assert_eq!(guard.unwatch(), Ok(Unwatch::Removed));
+        }
+    }
+
+    #[test]
+    fn test_watch_struct_reports_changed_fields() {
+        struct Config {
+            timeout_ms: u64,
+            retries: u32,
+            verbose: bool,
+        }
+        impl Watchable for Config {
+            const FIELDS: &'static [FieldInfo] = &[
+                FieldInfo { name: "timeout_ms", offset: std::mem::offset_of!(Config, timeout_ms), size: 8 },
+                FieldInfo { name: "retries", offset: std::mem::offset_of!(Config, retries), size: 4 },
+                FieldInfo { name: "verbose", offset: std::mem::offset_of!(Config, verbose), size: 1 },
+            ];
+        }
+
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        let mut config = Config { timeout_ms: 100, retries: 1, verbose: false };
+        let id = watcher.watch_struct(&config, "Config").unwrap();
+        watcher.check_changes().unwrap();
+
+        config.retries = 3;
+        config.verbose = true;
+        let events = watcher.check_changes().unwrap();
+        let names: Vec<_> = events.iter().map(|e| e.variable_name.as_deref().unwrap()).collect();
+        assert_eq!(names, ["Config.retries", "Config.verbose"]);
+        assert!(events[0].allocated_at.and_then(|site| site.type_name).unwrap().ends_with("Config"));
+
+        config.timeout_ms = 250;
+        let events = watcher.check_changes().unwrap();
+        assert_eq!(events[0].variable_name.as_deref(), Some("Config.timeout_ms"));
+        assert_eq!(config.timeout_ms + u64::from(config.retries), 253);
+        watcher.unwatch(id).unwrap();
+        assert!(watcher.layouts.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_poke_writes_and_reports_once() {
+        let _guard = fake_native::lock();
+        for mode in [WatchMode::Protect, WatchMode::Snapshot] {
+            let mut watcher = MemWatch::new().unwrap();
+            watcher.capabilities.mode = mode;
+            let mut config = vec![0u8; 4];
+            let target = config.as_mut_ptr();
+            let id = watcher.watch(&config, "config").unwrap();
+            watcher.check_changes().unwrap();
+
+            // SAFETY: `config` is only read through `target` below
+            unsafe { watcher.poke(id, 2, &[7, 8]) }.unwrap();
+            let line = line!() - 1;
+            let observed: Vec<u8> = (0..4).map(|i| unsafe { std::ptr::read_volatile(target.add(i)) }).collect();
+            assert_eq!(observed, [0, 0, 7, 8]);
+            assert!(unsafe { watcher.poke(id, 3, &[1, 2]) }.is_err());
+            assert!(unsafe { watcher.poke(9999, 0, &[1]) }.is_err());
+
+            let events = watcher.check_changes().unwrap();
+            assert_eq!(events.len(), 1, "{:?}", mode);
+            assert_eq!((events[0].kind, events[0].source), (EventKind::Change, EventSource::Poke));
+            assert_eq!((events[0].where_.file.as_deref(), events[0].where_.line), (Some(file!()), line));
+            assert_eq!((events[0].old_value.as_slice(), events[0].new_value.as_slice()), (&[0, 0, 0, 0][..], &[0, 0, 7, 8][..]));
+            assert!(watcher.check_changes().unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_decoder_renders_region_changes() {
+        let _guard = fake_native::lock();
+        let watcher = protected_watcher();
+        let message = [0x08, 0x01];
+        let id = watcher.watch(&message, "request").unwrap();
+        watcher.add_decoder(decode::ProtobufWireDecoder);
+        assert!(watcher.set_region_decoder(id, "flatbuffers").is_err());
+        watcher.set_region_decoder(id, "protobuf").unwrap();
+
+        fake_native::state().events = vec![FakeEvent::change(id, &[0x08, 0x02])];
+        let events = watcher.check_changes().unwrap();
+        assert!(watcher.decode(&events[0]).is_none());
+        let decoded = watcher.decode(&events[1]).unwrap();
+        assert_eq!(decoded.new.unwrap()[0].value, "varint 2");
+        watcher.unwatch(id).unwrap();
+        assert!(watcher.decode(&events[1]).is_none());
+    }
+
+    #[test]
+    fn test_unwatch_failure_keeps_region_for_retry() {
+        let _guard = fake_native::lock();
+        let watcher = protected_watcher();
+        let data = vec![0u8; 8];
+        let id = watcher.watch(&data, "buf").unwrap();
+
+        fake_native::state().unwatch_ok = false;
+        assert!(watcher.unwatch(id).is_err());
+        fake_native::state().unwatch_ok = true;
+        assert_eq!(watcher.unwatch(id), Ok(Unwatch::Removed));
+        assert_eq!(watcher.unwatch(id), Ok(Unwatch::NotTracked));
+    }
+
+    #[test]
+    fn test_changes_with_values_carry_deltas() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        let mut table = vec![0u8; 512];
+        let mut counter = [0u8; 8];
+        watcher.watch_with_max_value_bytes(&table, "table", -1).unwrap();
+        watcher.watch_with_max_value_bytes(&counter, "counter", 0).unwrap();
+        watcher.check_changes().unwrap();
+
+        table[10..12].fill(1);
+        table[300] = 2;
+        counter[0] = 1;
+        let events = watcher.check_changes().unwrap();
+        let deltas = |name: &str| events.iter().find(|e| e.variable_name.as_deref() == Some(name)).unwrap().deltas.clone();
+        let ranges: Vec<(usize, usize)> = deltas("table").iter().map(|r| (r.offset, r.len)).collect();
+        assert_eq!(ranges, [(10, 2), (300, 1)]);
+        assert!(deltas("counter").is_empty());
+        assert_eq!(table[300] + counter[0], 3);
+    }
+
+    #[test]
+    fn test_pause_keeps_region_and_resumes_from_live_contents() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        let mut config = vec![0u8; 8];
+        let id = watcher.watch_with_max_value_bytes(&config, "config", -1).unwrap();
+        watcher.check_changes().unwrap();
+
+        config[0] = 1;
+        watcher.pause(id).unwrap();
+        assert!(watcher.is_paused(id));
+        config[1] = 2;
+        let kinds: Vec<EventKind> = watcher.check_changes().unwrap().iter().map(|e| e.kind).collect();
+        assert_eq!(kinds, [EventKind::Change, EventKind::Paused]);
+        config[2] = 3;
+        assert!(watcher.check_changes().unwrap().is_empty());
+
+        watcher.resume(id).unwrap();
+        assert!(!watcher.is_paused(id));
+        config[3] = 4;
+        let events = watcher.check_changes().unwrap();
+        assert_eq!(events.iter().map(|e| e.kind).collect::<Vec<_>>(), [EventKind::Resumed, EventKind::Change]);
+        assert_eq!((events[1].region_id, events[1].old_value.as_slice()), (id, &[1, 2, 3, 0, 0, 0, 0, 0][..]));
+        assert!(watcher.pause(9999).is_err());
+        assert_eq!(config[3], 4);
+    }
+
+    #[test]
+    fn test_pause_all_reaches_the_native_core() {
+        let _guard = fake_native::lock();
+        let watcher = protected_watcher();
+        let data = [0u8; 16];
+        let a = watcher.watch(&data[..8], "a").unwrap();
+        let b = watcher.watch(&data[8..], "b").unwrap();
+        watcher.pause(a).unwrap();
+        assert_eq!(watcher.pause_all(), 1);
+        assert_eq!(fake_native::state().paused, [a, b]);
+        assert_eq!(watcher.regions.lock().unwrap().len(), 2);
+        assert_eq!(watcher.resume_all(), 2);
+        assert!(fake_native::state().paused.is_empty());
+        let markers: Vec<(u32, EventKind)> =
+            watcher.check_changes().unwrap().iter().filter(|e| e.kind == EventKind::Paused || e.kind == EventKind::Resumed).map(|e| (e.region_id, e.kind)).collect();
+        assert_eq!(markers, [(a, EventKind::Paused), (b, EventKind::Paused), (a, EventKind::Resumed), (b, EventKind::Resumed)]);
+    }
+}
+
+/// ChangeEventC against memwatch_change_event_t in
+/// include/memwatch_unified.h, as laid out by the C compiler on LP64
+#[cfg(all(test, target_pointer_width = "64"))]
+mod layout_tests {
+    use std::mem::{offset_of, size_of};
+
+    use super::ChangeEventC;
+
+    #[test]
+    fn test_change_event_matches_the_header() {
+        let fields = [
+            ("seq", offset_of!(ChangeEventC, seq), 0),
+            ("timestamp_ns", offset_of!(ChangeEventC, timestamp_ns), 8),
+            ("adapter_id", offset_of!(ChangeEventC, adapter_id), 16),
+            ("region_id", offset_of!(ChangeEventC, region_id), 20),
+            ("variable_name", offset_of!(ChangeEventC, variable_name), 24),
+            ("file", offset_of!(ChangeEventC, file), 32),
+            ("function", offset_of!(ChangeEventC, function), 40),
+            ("line", offset_of!(ChangeEventC, line), 48),
+            ("fault_ip", offset_of!(ChangeEventC, fault_ip), 56),
+            ("old_preview", offset_of!(ChangeEventC, old_preview), 64),
+            ("old_preview_size", offset_of!(ChangeEventC, old_preview_size), 72),
+            ("new_preview", offset_of!(ChangeEventC, new_preview), 80),
+            ("new_preview_size", offset_of!(ChangeEventC, new_preview_size), 88),
+            ("old_value", offset_of!(ChangeEventC, old_value), 96),
+            ("old_value_size", offset_of!(ChangeEventC, old_value_size), 104),
+            ("new_value", offset_of!(ChangeEventC, new_value), 112),
+            ("new_value_size", offset_of!(ChangeEventC, new_value_size), 120),
+            ("storage_key_old", offset_of!(ChangeEventC, storage_key_old), 128),
+            ("storage_key_new", offset_of!(ChangeEventC, storage_key_new), 136),
+            ("user_data", offset_of!(ChangeEventC, user_data), 144),
+            ("thread_id", offset_of!(ChangeEventC, thread_id), 152),
+            ("thread_name", offset_of!(ChangeEventC, thread_name), 160),
+            ("backtrace", offset_of!(ChangeEventC, backtrace), 168),
+            ("backtrace_len", offset_of!(ChangeEventC, backtrace_len), 176),
+        ];
+        for (name, offset, expected) in fields {
+            assert_eq!(offset, expected, "offset of {}", name);
+        }
+        assert_eq!(size_of::<ChangeEventC>(), 184);
+    }
+}
@@ -0,0 +1,287 @@
+// Region registry and lifecycle markers
+//
+// Adding, removing, pausing, resuming or relocating a region queues a marker
+// event that check_changes() returns ahead of the next batch of changes, so
+// exported histories describe their own regions.
+
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::anomaly::AnomalyKind;
+use crate::panics::Observer;
+use crate::sampling::Sampler;
+use crate::{ChangeEvent, Location};
+
+/// What an event in the stream describes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EventKind {
+    /// Watched memory was written
+    #[default]
+    Change,
+    /// Region registered
+    Watched { addr: u64, size: usize },
+    /// Region removed
+    Unwatched,
+    /// Watching suspended, registration kept
+    Paused,
+    /// Watching restarted after a pause
+    Resumed,
+    /// Region moved to a new address (MemWatch::relocate(), or a realloc
+    /// seen by TrackingAllocator), reported under its new id
+    Relocated { old_addr: u64, new_addr: u64 },
+    /// Finding of an anomaly detector, placed after the triggering change
+    Anomaly(AnomalyKind),
+    /// Block freed by its owner; the region stays watched so later writes
+    /// are reported as writes after free
+    Freed,
+    /// Write to a quarantined region after it was unwatched
+    UseAfterUnwatch,
+    /// Region `other`, registered later, reuses this region's memory for a
+    /// different object; changes there may show up under both ids
+    AliasWarning { other: u32 },
+    /// The native worker made no progress for `stalled_ms` (region 0); see
+    /// the watchdog module
+    WorkerStalled { thread_id: u32, stalled_ms: u64 },
+    /// The region lies in huge pages (hugetlbfs) and is smaller than one,
+    /// so a whole `page_size` page is protected and faults on every write
+    /// to it
+    HugePageWarning { page_size: u64 },
+    /// Region `other` has bytes on this region's pages, so writes to either
+    /// fault for both; `extra_faults` is the writes seen on `other` so far.
+    /// See the isolation module.
+    SharedPageWarning { other: u32, extra_faults: u64 },
+    /// An observer panicked for the `failures`th time and was `disabled`
+    /// (removed) if that reached the limit; new_value holds the panic
+    /// message. See the panics module.
+    CallbackPanicked { observer: Observer, failures: u32, disabled: bool },
+    /// Trigger `trigger` (see add_trigger()) fired on the preceding change,
+    /// at `count` changes or bytes
+    Triggered { trigger: u32, count: u64 },
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Change => "change",
+            EventKind::Watched { .. } => "watched",
+            EventKind::Unwatched => "unwatched",
+            EventKind::Paused => "paused",
+            EventKind::Resumed => "resumed",
+            EventKind::Relocated { .. } => "relocated",
+            EventKind::Anomaly(_) => "anomaly",
+            EventKind::Freed => "freed",
+            EventKind::UseAfterUnwatch => "use_after_unwatch",
+            EventKind::AliasWarning { .. } => "alias_warning",
+            EventKind::WorkerStalled { .. } => "worker_stalled",
+            EventKind::HugePageWarning { .. } => "huge_page_warning",
+            EventKind::SharedPageWarning { .. } => "shared_page_warning",
+            EventKind::CallbackPanicked { .. } => "callback_panicked",
+            EventKind::Triggered { .. } => "triggered",
+        }
+    }
+
+    pub fn is_marker(&self) -> bool {
+        *self != EventKind::Change
+    }
+}
+
+/// Who made a change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EventSource {
+    /// Observed by the backend (markers included)
+    #[default]
+    Observed,
+    /// Written by MemWatch::poke() on the caller's behalf
+    Poke,
+}
+
+impl EventSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventSource::Observed => "observed",
+            EventSource::Poke => "poke",
+        }
+    }
+}
+
+/// Rust-side bookkeeping for a watched region
+#[derive(Debug, Clone)]
+pub(crate) struct RegionInfo {
+    pub name: String,
+    pub addr: u64,
+    pub size: usize,
+    pub site: Option<AllocationSite>,
+    /// As passed to the backend, for re-watching after a restart
+    pub max_value_bytes: i32,
+    /// Marked freed by mark_freed()
+    pub freed: bool,
+    /// Protection granularity of the backing pages
+    pub page_size: usize,
+    /// Set through WatchOptions::tags
+    pub tags: Vec<String>,
+    /// Set through WatchOptions::sample_rate and max_events_per_sec
+    pub sampler: Option<Sampler>,
+    /// Set through WatchOptions::coalesce_ms
+    pub coalesce: Option<Duration>,
+    /// Between pause() and resume()
+    pub paused: bool,
+}
+
+impl RegionInfo {
+    /// Start addresses of the pages protecting the region covers
+    pub fn page_starts(&self) -> impl Iterator<Item = u64> {
+        let page_size = self.page_size as u64;
+        let first = self.addr / page_size * page_size;
+        (first..self.addr + self.size.max(1) as u64).step_by(self.page_size)
+    }
+
+    /// Whether [addr, addr + len) has bytes on the region's pages
+    pub fn shares_page(&self, addr: u64, len: usize) -> bool {
+        let page_size = self.page_size as u64;
+        let first = self.addr / page_size * page_size;
+        let end = (self.addr + self.size.max(1) as u64).div_ceil(page_size) * page_size;
+        addr < end && first < addr + len.max(1) as u64
+    }
+
+    /// Pages of `page_size` that protecting the region covers
+    pub fn pages(&self, page_size: usize) -> u64 {
+        let first = self.addr / page_size as u64;
+        let end = (self.addr + self.size.max(1) as u64).div_ceil(page_size as u64);
+        end - first
+    }
+
+    pub fn overlaps(&self, other: &RegionInfo) -> bool {
+        self.addr < other.addr + other.size as u64 && other.addr < self.addr + self.size as u64
+    }
+}
+
+/// Where (and as what type) a watched heap block was allocated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct AllocationSite {
+    pub file: &'static str,
+    pub line: u32,
+    /// Full Rust type name, when registered with `watch_typed!`
+    pub type_name: Option<&'static str>,
+}
+
+impl AllocationSite {
+    /// The caller's location; use from `#[track_caller]` allocation helpers
+    /// to tag blocks with their callers' sites
+    #[track_caller]
+    pub fn caller() -> Self {
+        let location = std::panic::Location::caller();
+        AllocationSite {
+            file: location.file(),
+            line: location.line(),
+            type_name: None,
+        }
+    }
+
+    pub fn with_type_name(mut self, type_name: &'static str) -> Self {
+        self.type_name = Some(type_name);
+        self
+    }
+}
+
+impl fmt::Display for AllocationSite {
+    /// e.g. "Vec<Packet> allocated at net/rx.rs:120"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(type_name) = self.type_name {
+            write!(f, "{} ", short_type_name(type_name))?;
+        }
+        write!(f, "allocated at {}:{}", self.file, self.line)
+    }
+}
+
+/// Drop module paths: "alloc::vec::Vec<net::Packet>" -> "Vec<Packet>"
+pub fn short_type_name(type_name: &str) -> String {
+    let mut out = String::with_capacity(type_name.len());
+    let mut segment = String::new();
+    for c in type_name.chars() {
+        if c.is_alphanumeric() || c == '_' || c == ':' {
+            segment.push(c);
+        } else {
+            out.push_str(segment.rsplit("::").next().unwrap_or(""));
+            segment.clear();
+            out.push(c);
+        }
+    }
+    out.push_str(segment.rsplit("::").next().unwrap_or(""));
+    out
+}
+
+#[doc(hidden)]
+pub fn type_name_of<T: ?Sized>(_: &T) -> &'static str {
+    std::any::type_name::<T>()
+}
+
+#[doc(hidden)]
+pub fn slice_bytes<T>(items: &[T]) -> &[u8] {
+    // SAFETY: any initialized slice can be viewed as bytes for reading
+    unsafe { std::slice::from_raw_parts(items.as_ptr() as *const u8, std::mem::size_of_val(items)) }
+}
+
+/// Watch the heap buffer of a Vec (or anything that derefs to a slice),
+/// tagged with its type name and the macro's call site:
+///
+/// ```ignore
+/// let id = memwatch::watch_typed!(watcher, packets, "rx_queue")?;
+/// ```
+#[macro_export]
+macro_rules! watch_typed {
+    ($watch:expr, $value:expr, $name:expr) => {{
+        let value = &$value;
+        $watch.watch_tagged(
+            $crate::lifecycle::slice_bytes(&value[..]),
+            $name,
+            $crate::lifecycle::AllocationSite {
+                file: file!(),
+                line: line!(),
+                type_name: Some($crate::lifecycle::type_name_of(value)),
+            },
+        )
+    }};
+}
+
+pub(crate) fn now_ns() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+/// Marker event for `region_id`. Markers carry seq 0; the native ring only
+/// numbers changes.
+pub(crate) fn marker(region_id: u32, name: &str, kind: EventKind) -> ChangeEvent {
+    ChangeEvent {
+        seq: 0,
+        timestamp_ns: now_ns(),
+        adapter_id: 0,
+        region_id,
+        variable_name: Some(name.to_string()),
+        group: None,
+        where_: Location::default(),
+        old_preview: Vec::new(),
+        new_preview: Vec::new(),
+        old_value: Vec::new(),
+        new_value: Vec::new(),
+        storage_key_old: None,
+        storage_key_new: None,
+        kind,
+        epoch: 0,
+        global_seq: 0,
+        tid: Some(crate::process::current_tid()),
+        thread_name: None,
+        frames: Vec::new(),
+        allocated_at: None,
+        source: EventSource::Observed,
+        preview_limit: None,
+        deltas: Vec::new(),
+        span_id: None,
+        span_name: None,
+        write_count: 1,
+    }
+}
@@ -0,0 +1,234 @@
+// Lock pairing
+//
+// A region paired with a lock is expected to be written only while that lock
+// is held. LockMonitor records when the lock was held (and by which thread) on
+// the monotonic clock the native core stamps events with; a change whose
+// timestamp falls outside every hold interval becomes an
+// `Anomaly(UnlockedWrite)` event. Events that carry a tid must also match the
+// holder. Only meaningful in Protect mode, where events are stamped at the
+// faulting write.
+
+use std::collections::{HashMap, VecDeque};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, LockResult, Mutex, MutexGuard, PoisonError};
+
+use crate::anomaly::{Anomaly, AnomalyDetector, AnomalyKind};
+use crate::process::current_tid;
+use crate::{ChangeEvent, EventKind, EventSource};
+
+/// Hold intervals kept per lock; older writes are not judged
+const MAX_INTERVALS: usize = 4096;
+
+#[derive(Default)]
+struct LockHistory {
+    /// (tid, acquired_ns) of the current holder
+    holder: Option<(u32, u64)>,
+    /// (tid, acquired_ns, released_ns), oldest first
+    released: VecDeque<(u32, u64, u64)>,
+    /// Nothing is known about times up to here
+    known_from_ns: u64,
+}
+
+/// Records when a lock is held. Use TrackedMutex, or call acquired() right
+/// after taking any other lock (e.g. a parking_lot guard) and keep the token
+/// until it is released.
+#[derive(Clone)]
+pub struct LockMonitor {
+    history: Arc<Mutex<LockHistory>>,
+}
+
+/// Marks the lock as held by the current thread until dropped
+pub struct Held {
+    monitor: LockMonitor,
+    tid: u32,
+    acquired_ns: u64,
+}
+
+impl LockMonitor {
+    pub fn new() -> Self {
+        LockMonitor {
+            history: Arc::new(Mutex::new(LockHistory {
+                known_from_ns: monotonic_ns(),
+                ..LockHistory::default()
+            })),
+        }
+    }
+
+    pub fn acquired(&self) -> Held {
+        let (tid, acquired_ns) = (current_tid(), monotonic_ns());
+        self.history().holder = Some((tid, acquired_ns));
+        Held {
+            monitor: self.clone(),
+            tid,
+            acquired_ns,
+        }
+    }
+
+    /// Whether the lock was held at `timestamp_ns` (by `tid` when given);
+    /// None when that time is outside the recorded history
+    pub(crate) fn held_at(&self, timestamp_ns: u64, tid: Option<u32>) -> Option<bool> {
+        let history = self.history();
+        if timestamp_ns < history.known_from_ns {
+            return None;
+        }
+        let by = |holder: u32| tid.is_none_or(|tid| tid == holder);
+        let current = history
+            .holder
+            .is_some_and(|(holder, since)| since <= timestamp_ns && by(holder));
+        Some(
+            current
+                || history
+                    .released
+                    .iter()
+                    .any(|&(holder, start, end)| start <= timestamp_ns && timestamp_ns <= end && by(holder)),
+        )
+    }
+
+    fn history(&self) -> MutexGuard<'_, LockHistory> {
+        self.history.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for LockMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AsRef<LockMonitor> for LockMonitor {
+    fn as_ref(&self) -> &LockMonitor {
+        self
+    }
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        let mut history = self.monitor.history();
+        if history.holder.is_some_and(|(tid, _)| tid == self.tid) {
+            history.holder = None;
+        }
+        history.released.push_back((self.tid, self.acquired_ns, monotonic_ns()));
+        if history.released.len() > MAX_INTERVALS {
+            if let Some((_, _, end)) = history.released.pop_front() {
+                history.known_from_ns = end;
+            }
+        }
+    }
+}
+
+/// std Mutex that records its hold intervals for lock pairing
+pub struct TrackedMutex<T> {
+    inner: Mutex<T>,
+    monitor: LockMonitor,
+}
+
+/// Guard of a TrackedMutex; the release is recorded before unlocking
+pub struct TrackedGuard<'a, T> {
+    _held: Held,
+    guard: MutexGuard<'a, T>,
+}
+
+impl<T> TrackedMutex<T> {
+    pub fn new(value: T) -> Self {
+        TrackedMutex {
+            inner: Mutex::new(value),
+            monitor: LockMonitor::new(),
+        }
+    }
+
+    pub fn lock(&self) -> LockResult<TrackedGuard<'_, T>> {
+        let wrap = |guard| TrackedGuard {
+            _held: self.monitor.acquired(),
+            guard,
+        };
+        match self.inner.lock() {
+            Ok(guard) => Ok(wrap(guard)),
+            Err(poisoned) => Err(PoisonError::new(wrap(poisoned.into_inner()))),
+        }
+    }
+
+    pub fn monitor(&self) -> &LockMonitor {
+        &self.monitor
+    }
+}
+
+impl<T> AsRef<LockMonitor> for TrackedMutex<T> {
+    fn as_ref(&self) -> &LockMonitor {
+        &self.monitor
+    }
+}
+
+impl<T> Deref for TrackedGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for TrackedGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+pub(crate) type LockPairs = Arc<Mutex<HashMap<u32, LockMonitor>>>;
+
+/// Detector flagging writes to paired regions outside their lock
+pub(crate) struct LockPairing {
+    pub pairs: LockPairs,
+}
+
+impl AnomalyDetector for LockPairing {
+    fn observe(&mut self, event: &ChangeEvent) -> Option<Anomaly> {
+        if event.kind != EventKind::Change || event.source == EventSource::Poke {
+            return None;
+        }
+        let pairs = self.pairs.lock().unwrap();
+        match pairs.get(&event.region_id)?.held_at(event.timestamp_ns, event.tid) {
+            Some(false) => Some(Anomaly {
+                kind: AnomalyKind::UnlockedWrite,
+                score: 1.0,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// CLOCK_MONOTONIC in nanoseconds, the clock of native event timestamps
+#[cfg(unix)]
+pub(crate) fn monotonic_ns() -> u64 {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // SAFETY: ts is a valid timespec to write into
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+/// Nanoseconds since first use; there are no native timestamps to match
+#[cfg(not(unix))]
+pub(crate) fn monotonic_ns() -> u64 {
+    static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+    START.get_or_init(std::time::Instant::now).elapsed().as_nanos() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hold_intervals() {
+        let mutex = TrackedMutex::new(0u32);
+        {
+            let mut guard = mutex.lock().unwrap();
+            *guard += 1;
+            assert_eq!(mutex.monitor().held_at(monotonic_ns(), None), Some(true));
+            assert_eq!(mutex.monitor().held_at(monotonic_ns(), Some(current_tid() + 1)), Some(false));
+        }
+        let after = monotonic_ns();
+        let monitor = mutex.monitor();
+        let known_from_ns = monitor.history().known_from_ns;
+        assert_eq!(monitor.held_at(known_from_ns - 1, None), None);
+        assert_eq!(monitor.held_at(after, None), Some(false));
+        assert_eq!(*mutex.lock().unwrap(), 1);
+    }
+}
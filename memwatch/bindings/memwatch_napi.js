@@ -0,0 +1,42 @@
+/**
+ * memwatch - Node.js binding backed by the Rust crate (make build-rust-napi)
+ *
+ * Same unified API as memwatch.js, plus SQLTracker (track_query, get_changes)
+ * and async iteration over change events:
+ *
+ *   for await (const event of watcher.events()) { ... }
+ */
+
+const native = require('./memwatch_rs.node');
+
+const { MemWatch, SQLTracker } = native;
+
+/**
+ * Async iterator over change events
+ * @param {number} timeout_ms - How long each native wait may block (default 1000)
+ */
+MemWatch.prototype.events = async function* (timeout_ms = 1000) {
+  while (true) {
+    const batch = await this.next_events(timeout_ms);
+    for (const event of batch) {
+      yield event;
+    }
+  }
+};
+
+MemWatch.prototype[Symbol.asyncIterator] = function () {
+  return this.events();
+};
+
+/**
+ * Factory function (matches all language bindings)
+ */
+function create() {
+  return new MemWatch();
+}
+
+module.exports = {
+  MemWatch,
+  SQLTracker,
+  create,
+};
@@ -0,0 +1,252 @@
+// Node.js binding via N-API (feature = "napi")
+//
+// Built as a cdylib with `make build-rust-napi` (`cargo rustc --lib
+// --crate-type cdylib --features napi`), which copies the output to
+// memwatch_rs.node; load it through memwatch_napi.js, which adds async
+// iteration over events. Method names follow the unified snake_case API of
+// memwatch.js. Events come through MemWatch::check_changes(), so filters,
+// hooks, sinks and markers apply as they do in Rust. SQLTracker wraps
+// sql_tracker::SQLTracker.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+
+use crate::sql_tracker::{SQLChange, SQLTracker};
+use crate::{ChangeEvent, MemWatch, Unwatch};
+
+#[napi(object, js_name = "Location")]
+pub struct JsLocation {
+    pub file: Option<String>,
+    pub function: Option<String>,
+    pub line: u32,
+    pub fault_ip: BigInt,
+}
+
+#[napi(object, js_name = "ChangeEvent")]
+pub struct JsChangeEvent {
+    pub seq: u32,
+    pub timestamp_ns: BigInt,
+    pub adapter_id: u32,
+    pub region_id: u32,
+    pub variable_name: Option<String>,
+    #[napi(js_name = "where")]
+    pub where_: JsLocation,
+    pub old_preview: Buffer,
+    pub new_preview: Buffer,
+    /// "change" or a lifecycle marker ("watched", "unwatched", ...)
+    pub kind: String,
+    pub epoch: u32,
+    pub global_seq: BigInt,
+}
+
+impl From<ChangeEvent> for JsChangeEvent {
+    fn from(event: ChangeEvent) -> Self {
+        JsChangeEvent {
+            seq: event.seq,
+            timestamp_ns: BigInt::from(event.timestamp_ns),
+            adapter_id: event.adapter_id,
+            region_id: event.region_id,
+            variable_name: event.variable_name,
+            where_: JsLocation {
+                file: event.where_.file,
+                function: event.where_.function,
+                line: event.where_.line,
+                fault_ip: BigInt::from(event.where_.fault_ip),
+            },
+            old_preview: event.old_preview.into(),
+            new_preview: event.new_preview.into(),
+            kind: event.kind.as_str().to_string(),
+            epoch: event.epoch,
+            global_seq: BigInt::from(event.global_seq),
+        }
+    }
+}
+
+#[napi(object, js_name = "Stats")]
+pub struct JsStats {
+    pub num_tracked_regions: u32,
+    pub num_active_watchpoints: u32,
+    pub total_events: BigInt,
+    pub ring_write_count: BigInt,
+    pub ring_drop_count: BigInt,
+    pub storage_bytes_used: BigInt,
+    pub mprotect_page_count: u32,
+    pub worker_thread_id: u32,
+    pub worker_cycles: BigInt,
+    pub sampled_out_count: BigInt,
+}
+
+#[napi(object, js_name = "SQLChange")]
+pub struct JsSqlChange {
+    pub timestamp_ns: BigInt,
+    pub table_name: String,
+    pub column_name: String,
+    pub operation: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub rows_affected: i32,
+    pub database: Option<String>,
+    pub full_query: String,
+}
+
+impl From<SQLChange> for JsSqlChange {
+    fn from(change: SQLChange) -> Self {
+        JsSqlChange {
+            timestamp_ns: BigInt::from(change.timestamp_ns),
+            table_name: change.table_name,
+            column_name: change.column_name,
+            operation: change.operation.as_str().to_string(),
+            old_value: change.old_value.map(|v| v.to_string()),
+            new_value: change.new_value.map(|v| v.to_string()),
+            rows_affected: change.rows_affected,
+            database: change.database,
+            full_query: change.full_query,
+        }
+    }
+}
+
+/// Background wait for the next batch of events (backs the async iterator)
+pub struct NextEvents {
+    watcher: Arc<MemWatch>,
+    timeout: Duration,
+}
+
+impl Task for NextEvents {
+    type Output = Vec<ChangeEvent>;
+    type JsValue = Vec<JsChangeEvent>;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        const MAX_EVENTS: usize = 64;
+        const MAX_IDLE: Duration = Duration::from_millis(50);
+        let deadline = Instant::now() + self.timeout;
+        let mut idle = Duration::from_millis(1);
+        loop {
+            // Created before checking, so a change recorded meanwhile wakes it
+            let changed = self.watcher.wait_for_change().map_err(Error::from_reason)?;
+            let events = self.watcher.check_changes_with_capacity(MAX_EVENTS).map_err(|e| Error::from_reason(e.to_string()))?;
+            if !events.is_empty() || Instant::now() >= deadline {
+                return Ok(events);
+            }
+            // Changes nothing wakes for (Snapshot mode) are polled for
+            let until = if self.watcher.wakes_on_change() { deadline } else { deadline.min(Instant::now() + idle) };
+            changed.wait_until(until);
+            idle = (idle * 2).min(MAX_IDLE);
+        }
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output.into_iter().map(JsChangeEvent::from).collect())
+    }
+}
+
+/// Memory watcher exposed to JavaScript
+#[napi(js_name = "MemWatch")]
+pub struct JsMemWatch {
+    // Shared with next_events() tasks on the libuv pool
+    inner: Arc<MemWatch>,
+    // Keeps watched Buffers alive (and their backing store in place) until unwatch
+    buffers: HashMap<u32, Buffer>,
+}
+
+#[napi]
+impl JsMemWatch {
+    #[napi(constructor)]
+    pub fn new() -> Result<Self> {
+        Ok(JsMemWatch {
+            inner: Arc::new(MemWatch::new().map_err(Error::from_reason)?),
+            buffers: HashMap::new(),
+        })
+    }
+
+    /// Watch a Buffer; max_value_bytes: 0 = none, >0 = limit to N bytes, -1 = full (default 256)
+    #[napi]
+    pub fn watch(&mut self, buffer: Buffer, name: Option<String>, max_value_bytes: Option<i32>) -> Result<u32> {
+        let region_id = self
+            .inner
+            .watch_with_max_value_bytes(&buffer, name.as_deref().unwrap_or(""), max_value_bytes.unwrap_or(256))
+            .map_err(Error::from_reason)?;
+        self.buffers.insert(region_id, buffer);
+        Ok(region_id)
+    }
+
+    #[napi]
+    pub fn unwatch(&mut self, region_id: u32) -> Result<bool> {
+        let outcome = self.inner.unwatch(region_id).map_err(|e| Error::from_reason(e.to_string()))?;
+        self.buffers.remove(&region_id);
+        Ok(outcome == Unwatch::Removed)
+    }
+
+    #[napi(js_name = "check_changes")]
+    pub fn check_changes(&self) -> Result<Vec<JsChangeEvent>> {
+        let events = self.inner.check_changes().map_err(|e| Error::from_reason(e.to_string()))?;
+        Ok(events.into_iter().map(JsChangeEvent::from).collect())
+    }
+
+    /// Resolve with the next non-empty batch, or an empty array after timeout_ms (default 1000)
+    #[napi(js_name = "next_events", ts_return_type = "Promise<ChangeEvent[]>")]
+    pub fn next_events(&self, timeout_ms: Option<u32>) -> AsyncTask<NextEvents> {
+        AsyncTask::new(NextEvents {
+            watcher: Arc::clone(&self.inner),
+            timeout: Duration::from_millis(timeout_ms.unwrap_or(1000) as u64),
+        })
+    }
+
+    #[napi(js_name = "get_stats")]
+    pub fn get_stats(&self) -> Result<JsStats> {
+        let stats = self.inner.get_stats().map_err(Error::from_reason)?;
+        Ok(JsStats {
+            num_tracked_regions: stats.num_tracked_regions,
+            num_active_watchpoints: stats.num_active_watchpoints,
+            total_events: BigInt::from(stats.total_events),
+            ring_write_count: BigInt::from(stats.ring_write_count),
+            ring_drop_count: BigInt::from(stats.ring_drop_count),
+            storage_bytes_used: BigInt::from(stats.storage_bytes_used),
+            mprotect_page_count: stats.mprotect_page_count,
+            worker_thread_id: stats.worker_thread_id,
+            worker_cycles: BigInt::from(stats.worker_cycles),
+            sampled_out_count: BigInt::from(stats.sampled_out_count),
+        })
+    }
+}
+
+/// SQL change tracker exposed to JavaScript
+#[napi(js_name = "SQLTracker")]
+pub struct JsSqlTracker {
+    inner: SQLTracker,
+}
+
+#[napi]
+impl JsSqlTracker {
+    /// storage_path is handed to the native tracker when it is loaded
+    #[napi(constructor)]
+    pub fn new(storage_path: Option<String>) -> Self {
+        JsSqlTracker { inner: SQLTracker::new(storage_path.as_deref()) }
+    }
+
+    /// Track a SQL query; returns the number of column changes recorded
+    #[napi(js_name = "track_query")]
+    pub fn track_query(
+        &mut self,
+        query: String,
+        rows_affected: Option<i32>,
+        database: Option<String>,
+        old_value: Option<String>,
+        new_value: Option<String>,
+    ) -> i32 {
+        self.inner.track_query(&query, rows_affected.unwrap_or(0), database.as_deref(), old_value.as_deref(), new_value.as_deref())
+    }
+
+    /// Changes so far, optionally only of one table, column or operation ("UPDATE", ...)
+    #[napi(js_name = "get_changes")]
+    pub fn get_changes(&self, table: Option<String>, column: Option<String>, operation: Option<String>) -> Vec<JsSqlChange> {
+        self.inner
+            .get_changes(table.as_deref(), column.as_deref(), operation.as_deref())
+            .into_iter()
+            .map(JsSqlChange::from)
+            .collect()
+    }
+}
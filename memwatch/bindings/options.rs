@@ -0,0 +1,164 @@
+// Per-region watch options
+//
+// watch_with_options() takes what the watch_with_*() variants take one at
+// a time, plus settings only a few regions need:
+//
+//   let options = WatchOptions {
+//       max_value_bytes: Some(-1),
+//       backend: RegionBackend::HardwareBreakpoint,
+//       tags: vec!["config".to_string()],
+//       ..Default::default()
+//   };
+//   let id = watcher.watch_with_options(counter_bytes, "counter", &options)?;
+//
+// Unset fields keep the watcher's defaults. Tags are free-form labels for
+// finding regions again (regions_tagged()); they survive backend restarts.
+// track_reads reports accesses as well as changes; see the access module.
+// sample_rate and max_events_per_sec thin out hot regions; see the sampling
+// module. coalesce_ms folds bursts of writes into single changes; see the
+// coalesce module.
+
+use std::time::Duration;
+
+use crate::access;
+use crate::backend::Backend;
+use crate::capabilities::WatchMode;
+use crate::sampling::Sampler;
+use crate::{AllocationSite, MemWatch};
+
+/// How a region is watched
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RegionBackend {
+    /// The watcher's mode: page protection or snapshots
+    #[default]
+    Default,
+    /// A CPU debug register (Linux perf events), for aligned regions of 1,
+    /// 2, 4 or 8 bytes; no page faults, neighbours unaffected, but few
+    /// registers and no write locations. See the hwbreak module.
+    HardwareBreakpoint,
+}
+
+/// Settings of one watch; see watch_with_options()
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WatchOptions {
+    /// 0 = no values, >0 = limit to N bytes, -1 = full values; None keeps
+    /// the watcher's default
+    pub max_value_bytes: Option<i32>,
+    pub backend: RegionBackend,
+    /// Labels for regions_tagged() and region_tags()
+    pub tags: Vec<String>,
+    /// Where the watched block was allocated, as for watch_tagged()
+    pub allocated_at: Option<AllocationSite>,
+    /// Also report reads (and first writes) as AccessEvents; see the access
+    /// module
+    pub track_reads: bool,
+    /// Keep one change in N
+    pub sample_rate: Option<u32>,
+    /// Keep at most N changes per second
+    pub max_events_per_sec: Option<u32>,
+    /// Merge changes of the region made within N ms of the first into one
+    pub coalesce_ms: Option<u32>,
+}
+
+impl MemWatch {
+    /// Watch a buffer with per-region settings
+    pub fn watch_with_options(&self, buffer: &[u8], name: &str, options: &WatchOptions) -> Result<u32, String> {
+        access::internal(|| {
+            let max_value_bytes = options.max_value_bytes.unwrap_or(self.max_value_bytes);
+            let region_id = match options.backend {
+                RegionBackend::Default => self.watch_buffer(buffer, name, max_value_bytes, options.allocated_at)?,
+                RegionBackend::HardwareBreakpoint => {
+                    let region_id = self.hardware.watch(buffer, name, max_value_bytes)?;
+                    let addr = buffer.as_ptr() as u64;
+                    self.register_region(region_id, name, addr, buffer.len(), max_value_bytes, options.allocated_at);
+                    region_id
+                }
+            };
+            if let Some(info) = self.regions.lock().unwrap().get_mut(&region_id) {
+                info.tags = options.tags.clone();
+                info.sampler = Sampler::new(options.sample_rate, options.max_events_per_sec);
+                info.coalesce = options.coalesce_ms.map(|ms| Duration::from_millis(ms as u64));
+            }
+            if options.track_reads {
+                let writes_fault = self.capabilities.mode == WatchMode::Protect;
+                let tracked = self.reads.lock().unwrap().track(region_id, buffer.as_ptr() as u64, buffer.len(), name, writes_fault);
+                if let Err(e) = tracked {
+                    let _ = self.unwatch(region_id);
+                    return Err(format!("Cannot track reads of {}: {}", name, e));
+                }
+            }
+            Ok(region_id)
+        })
+    }
+
+    /// Tags a region was watched with
+    pub fn region_tags(&self, region_id: u32) -> Vec<String> {
+        self.regions.lock().unwrap().get(&region_id).map_or_else(Vec::new, |info| info.tags.clone())
+    }
+
+    /// Live regions carrying `tag`, by id
+    pub fn regions_tagged(&self, tag: &str) -> Vec<u32> {
+        let mut ids: Vec<u32> = self
+            .regions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, info)| info.tags.iter().any(|t| t == tag))
+            .map(|(&id, _)| id)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native;
+
+    #[test]
+    fn test_options_set_limits_and_tags() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        let mut config = vec![0u8; 512];
+        let options = WatchOptions {
+            max_value_bytes: Some(-1),
+            tags: vec!["config".to_string(), "hot".to_string()],
+            ..WatchOptions::default()
+        };
+        let id = watcher.watch_with_options(&config, "config", &options).unwrap();
+        let counter = [0u8; 4];
+        let other = watcher.watch(&counter, "other").unwrap();
+        assert_eq!(watcher.regions_tagged("hot"), [id]);
+        assert_eq!(watcher.region_tags(id), ["config", "hot"]);
+        assert!(watcher.region_tags(other).is_empty());
+
+        watcher.check_changes().unwrap();
+        config[400] = 1;
+        let events = watcher.check_changes().unwrap();
+        assert_eq!(events[0].new_value.len(), 512);
+        watcher.unwatch(id).unwrap();
+        assert!(watcher.regions_tagged("hot").is_empty());
+    }
+
+    #[test]
+    fn test_sampled_regions_keep_one_change_in_n() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        let mut hot = vec![0u8; 8];
+        let options = WatchOptions { sample_rate: Some(2), ..WatchOptions::default() };
+        watcher.watch_with_options(&hot, "hot", &options).unwrap();
+        watcher.check_changes().unwrap();
+
+        let mut kept = 0;
+        for i in 1..=4 {
+            hot[0] = i;
+            kept += watcher.check_changes().unwrap().len();
+        }
+        assert_eq!(kept, 2);
+        assert_eq!(watcher.get_stats().unwrap().sampled_out_count, 2);
+    }
+}
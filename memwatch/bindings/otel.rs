@@ -0,0 +1,205 @@
+// OpenTelemetry export (feature `otel`)
+//
+// OtelSink hands every delivered event to OpenTelemetry, so memory changes
+// show up next to the application's distributed traces:
+//
+//   let logger = provider.logger("memwatch");
+//   watcher.add_sink(OtelSink::new(logger));
+//
+// By default (OtelTarget::SpanOrLog) an event becomes an event on the span
+// active where check_changes() runs, when one is recording, and a log
+// record otherwise; the record then carries the active trace context, if
+// any. Events and records are named "memwatch.<kind>" and carry the
+// attributes of attributes(): region id and name, source location, value
+// previews as hex, the event's own timestamp and sequence. Timestamps
+// stay attributes because native changes use CLOCK_MONOTONIC; spans and
+// records are stamped when delivered.
+
+use opentelemetry::logs::{AnyValue, LogRecord, Logger, Severity};
+use opentelemetry::trace::get_active_span;
+use opentelemetry::{KeyValue, Value};
+
+use crate::export::hex;
+use crate::sink::EventSink;
+use crate::{ChangeEvent, EventKind};
+
+/// What an OtelSink emits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OtelTarget {
+    /// Span events on the active span; events without one are skipped
+    Span,
+    /// Log records only
+    Log,
+    /// A span event when a span is recording, else a log record
+    #[default]
+    SpanOrLog,
+}
+
+/// Sink emitting events as OpenTelemetry span events or log records
+pub struct OtelSink<L: Logger> {
+    logger: L,
+    target: OtelTarget,
+}
+
+impl<L: Logger> OtelSink<L> {
+    pub fn new(logger: L) -> Self {
+        OtelSink { logger, target: OtelTarget::default() }
+    }
+
+    pub fn with_target(mut self, target: OtelTarget) -> Self {
+        self.target = target;
+        self
+    }
+
+    fn log(&self, event: &ChangeEvent) {
+        let severity = severity(&event.kind);
+        if !self.logger.event_enabled(severity, "memwatch", Some(event_name(&event.kind))) {
+            return;
+        }
+        let mut record = self.logger.create_log_record();
+        record.set_event_name(event_name(&event.kind));
+        record.set_target("memwatch");
+        record.set_severity_number(severity);
+        record.set_severity_text(severity.name());
+        record.set_observed_timestamp(std::time::SystemTime::now());
+        record.set_body(AnyValue::from(body(event)));
+        record.add_attributes(attributes(event).into_iter().map(|kv| (kv.key, any_value(kv.value))));
+        self.logger.emit(record);
+    }
+}
+
+impl<L: Logger + Send> EventSink for OtelSink<L> {
+    fn write(&mut self, event: &ChangeEvent) -> Result<(), String> {
+        let on_span = self.target != OtelTarget::Log
+            && get_active_span(|span| {
+                if !span.is_recording() {
+                    return false;
+                }
+                span.add_event(event_name(&event.kind), attributes(event));
+                true
+            });
+        if !on_span && self.target != OtelTarget::Span {
+            self.log(event);
+        }
+        Ok(())
+    }
+}
+
+/// Attributes describing `event`, as OtelSink attaches them
+pub fn attributes(event: &ChangeEvent) -> Vec<KeyValue> {
+    let mut attributes = vec![
+        KeyValue::new("memwatch.event.kind", event.kind.as_str()),
+        KeyValue::new("memwatch.event.source", event.source.as_str()),
+        KeyValue::new("memwatch.region.id", event.region_id as i64),
+        KeyValue::new("memwatch.timestamp_ns", event.timestamp_ns as i64),
+        KeyValue::new("memwatch.global_seq", event.global_seq as i64),
+    ];
+    if let Some(name) = &event.variable_name {
+        attributes.push(KeyValue::new("memwatch.region.name", name.clone()));
+    }
+    if let Some(group) = &event.group {
+        attributes.push(KeyValue::new("memwatch.region.group", group.clone()));
+    }
+    if let Some(file) = &event.where_.file {
+        attributes.push(KeyValue::new("code.file.path", file.clone()));
+    }
+    if let Some(function) = &event.where_.function {
+        attributes.push(KeyValue::new("code.function.name", function.clone()));
+    }
+    if event.where_.line > 0 {
+        attributes.push(KeyValue::new("code.line.number", event.where_.line as i64));
+    }
+    if let Some(tid) = event.tid {
+        attributes.push(KeyValue::new("thread.id", tid as i64));
+    }
+    if !event.old_preview.is_empty() {
+        attributes.push(KeyValue::new("memwatch.old_preview", hex(&event.old_preview)));
+    }
+    if !event.new_preview.is_empty() {
+        attributes.push(KeyValue::new("memwatch.new_preview", hex(&event.new_preview)));
+    }
+    attributes
+}
+
+fn event_name(kind: &EventKind) -> &'static str {
+    match kind {
+        EventKind::Change => "memwatch.change",
+        EventKind::Watched { .. } => "memwatch.watched",
+        EventKind::Unwatched => "memwatch.unwatched",
+        EventKind::Paused => "memwatch.paused",
+        EventKind::Resumed => "memwatch.resumed",
+        EventKind::Relocated { .. } => "memwatch.relocated",
+        EventKind::Anomaly(_) => "memwatch.anomaly",
+        EventKind::Freed => "memwatch.freed",
+        EventKind::UseAfterUnwatch => "memwatch.use_after_unwatch",
+        EventKind::AliasWarning { .. } => "memwatch.alias_warning",
+        EventKind::WorkerStalled { .. } => "memwatch.worker_stalled",
+        EventKind::HugePageWarning { .. } => "memwatch.huge_page_warning",
+        EventKind::SharedPageWarning { .. } => "memwatch.shared_page_warning",
+        EventKind::CallbackPanicked { .. } => "memwatch.callback_panicked",
+        EventKind::Triggered { .. } => "memwatch.triggered",
+    }
+}
+
+/// Warn for markers reporting a problem, Info otherwise
+fn severity(kind: &EventKind) -> Severity {
+    match kind {
+        EventKind::Anomaly(_)
+        | EventKind::UseAfterUnwatch
+        | EventKind::AliasWarning { .. }
+        | EventKind::WorkerStalled { .. }
+        | EventKind::HugePageWarning { .. }
+        | EventKind::SharedPageWarning { .. }
+        | EventKind::CallbackPanicked { .. } => Severity::Warn,
+        _ => Severity::Info,
+    }
+}
+
+/// "<kind> <region name> at <file>:<line>"
+fn body(event: &ChangeEvent) -> String {
+    let mut body = event.kind.as_str().to_string();
+    match &event.variable_name {
+        Some(name) => body.push_str(&format!(" {}", name)),
+        None => body.push_str(&format!(" region {}", event.region_id)),
+    }
+    if let Some(file) = &event.where_.file {
+        body.push_str(&format!(" at {}:{}", file, event.where_.line));
+    }
+    body
+}
+
+fn any_value(value: Value) -> AnyValue {
+    match value {
+        Value::Bool(b) => AnyValue::Boolean(b),
+        Value::I64(i) => AnyValue::Int(i),
+        Value::F64(f) => AnyValue::Double(f),
+        other => AnyValue::from(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::marker;
+
+    #[test]
+    fn test_attributes_carry_region_location_and_previews() {
+        let mut event = marker(7, "orders", EventKind::Change);
+        event.where_.file = Some("orders.rs".to_string());
+        event.where_.line = 42;
+        event.old_preview = vec![0x00, 0x01];
+        event.new_preview = vec![0xff];
+        let attributes = attributes(&event);
+        let get = |key: &str| attributes.iter().find(|kv| kv.key.as_str() == key).map(|kv| kv.value.clone());
+
+        assert_eq!(get("memwatch.region.name"), Some(Value::from("orders")));
+        assert_eq!(get("memwatch.region.id"), Some(Value::I64(7)));
+        assert_eq!(get("code.file.path"), Some(Value::from("orders.rs")));
+        assert_eq!(get("code.line.number"), Some(Value::I64(42)));
+        assert_eq!(get("memwatch.old_preview"), Some(Value::from("0001")));
+        assert_eq!(get("memwatch.new_preview"), Some(Value::from("ff")));
+        assert_eq!(get("code.function.name"), None);
+        assert_eq!(body(&event), "change orders at orders.rs:42");
+        assert_eq!(severity(&EventKind::UseAfterUnwatch), Severity::Warn);
+    }
+}
@@ -0,0 +1,169 @@
+// Panicking observers
+//
+// A panic in set_callback()'s callback, a watch_with_callback() handler, a
+// subscriber or a sink no longer unwinds through the worker or
+// check_changes(): it is caught where the observer is called and reported as
+// a CallbackPanicked marker with the next batch (region 0, or the handler's
+// region), carrying the panic message as UTF-8 in new_value. After `limit` panics (see
+// set_callback_failure_limit(), 3 by default) the observer is removed and
+// the marker says so; delivery to every other observer carries on.
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::{lifecycle, ChangeEvent, EventKind, MemWatch};
+
+/// Panics an observer may cause before it is removed
+pub const DEFAULT_FAILURE_LIMIT: u32 = 3;
+
+/// Which kind of observer panicked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Observer {
+    /// The set_callback() callback, on the native worker thread
+    Callback,
+    /// A watch_with_callback() handler of the marker's region
+    RegionCallback,
+    /// The sink at `index` in the order sinks were added
+    Sink { index: usize },
+    /// The subscriber of subscribe() with this id
+    Subscription { id: u32 },
+}
+
+impl Observer {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Observer::Callback => "callback",
+            Observer::RegionCallback => "region_callback",
+            Observer::Sink { .. } => "sink",
+            Observer::Subscription { .. } => "subscription",
+        }
+    }
+}
+
+/// Run `f`, turning a panic into its message
+pub(crate) fn guarded<R>(f: impl FnOnce() -> R) -> Result<R, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| message(payload.as_ref()))
+}
+
+fn message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "non-string panic payload".to_string()
+    }
+}
+
+/// Whether an observer that has now panicked `failures` times is removed
+pub(crate) fn exhausted(failures: u32, limit: u32) -> bool {
+    limit > 0 && failures >= limit
+}
+
+/// CallbackPanicked marker for `region_id` (0 outside region handlers)
+pub(crate) fn marker(region_id: u32, name: &str, observer: Observer, failures: u32, disabled: bool, message: &str) -> ChangeEvent {
+    let mut event = lifecycle::marker(region_id, name, EventKind::CallbackPanicked { observer, failures, disabled });
+    event.new_value = message.as_bytes().to_vec();
+    event
+}
+
+impl MemWatch {
+    /// Remove a callback, region handler or sink after it panicked `limit`
+    /// times; 0 keeps them however often they panic
+    pub fn set_callback_failure_limit(&self, limit: u32) {
+        self.callback.failure_limit.store(limit, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    pub(crate) fn failure_limit(&self) -> u32 {
+        self.callback.failure_limit.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Queue markers for panics of the native callback since the last check
+    pub(crate) fn report_callback_panics(&self) {
+        let panics = std::mem::take(&mut *self.callback.panics.lock().unwrap_or_else(|e| e.into_inner()));
+        for (failures, disabled, message) in panics {
+            self.queue_marker(marker(0, "event callback", Observer::Callback, failures, disabled, &message));
+        }
+    }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native::{self, FakeEvent};
+    use crate::sink::EventSink;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct Exploding;
+
+    impl EventSink for Exploding {
+        fn write(&mut self, _event: &ChangeEvent) -> Result<(), String> {
+            panic!("sink broke");
+        }
+    }
+
+    struct Counting(Arc<AtomicUsize>);
+
+    impl EventSink for Counting {
+        fn write(&mut self, _event: &ChangeEvent) -> Result<(), String> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    fn panicked(events: &[ChangeEvent]) -> Vec<(u32, Observer, u32, bool, String)> {
+        events
+            .iter()
+            .filter_map(|event| match event.kind {
+                EventKind::CallbackPanicked { observer, failures, disabled } => {
+                    Some((event.region_id, observer, failures, disabled, String::from_utf8_lossy(&event.new_value).into_owned()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_panicking_observers_are_reported_and_removed() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Protect;
+        watcher.set_callback_failure_limit(2);
+        let delivered = Arc::new(AtomicUsize::new(0));
+        watcher.add_sink(Exploding);
+        watcher.add_sink(Counting(Arc::clone(&delivered)));
+        watcher.set_callback(Some(|_: &ChangeEvent| panic!("callback broke"))).unwrap();
+        let balance = [0u8; 4];
+        let region_id = watcher.watch_with_callback(&balance, "balance", |_| panic!("handler broke")).unwrap();
+
+        let mut returned = 0;
+        let mut reported = Vec::new();
+        for value in 1..=4 {
+            fake_native::fire(FakeEvent::change(region_id, &[value]));
+            fake_native::state().events.push(FakeEvent::change(region_id, &[value]));
+            let events = watcher.check_changes().unwrap();
+            returned += events.len();
+            reported.extend(panicked(&events));
+        }
+        for (observer, region, message) in [
+            (Observer::Callback, 0, "callback broke"),
+            (Observer::RegionCallback, region_id, "handler broke"),
+            (Observer::Sink { index: 0 }, 0, "sink broke"),
+        ] {
+            let seen: Vec<_> = reported.iter().filter(|r| r.1 == observer).cloned().collect();
+            assert_eq!(
+                seen,
+                [(region, observer, 1, false, message.to_string()), (region, observer, 2, true, message.to_string())]
+            );
+        }
+
+        // All three are gone; the healthy sink kept receiving everything
+        assert!(watcher.callback.callback.lock().unwrap_or_else(|e| e.into_inner()).is_none());
+        assert!(watcher.region_callbacks.lock().unwrap().is_empty());
+        assert_eq!(watcher.sinks.lock().unwrap().len(), 1);
+        assert_eq!(delivered.load(Ordering::SeqCst), returned);
+    }
+}
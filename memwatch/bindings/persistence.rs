@@ -0,0 +1,170 @@
+// Durable event log
+//
+// JsonlSink appends every delivered event as one JSON line (the export
+// format, see ExportedEvent::to_json) and syncs the file after each batch, so
+// a crash loses at most the batch in flight. With rotation, a file that would
+// grow past `max_bytes` is renamed to "<path>.1" (older ones shift to .2, ..)
+// and a fresh file is started; only `keep` rotated files are retained.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::export::ValueMode;
+use crate::sink::EventSink;
+use crate::ChangeEvent;
+
+/// Size-based rotation of a JsonlSink
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    /// Start a new file before one grows past this many bytes
+    pub max_bytes: u64,
+    /// Rotated files kept next to the live one; older ones are deleted
+    pub keep: usize,
+}
+
+/// Sink appending events to a JSONL file
+pub struct JsonlSink {
+    path: PathBuf,
+    out: BufWriter<File>,
+    written: u64,
+    rotation: Option<Rotation>,
+    values: ValueMode,
+}
+
+impl JsonlSink {
+    /// Open (or create) the log at `path`, appending to an existing file
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        let (out, written) = open_log(&path)?;
+        Ok(JsonlSink {
+            path,
+            out,
+            written,
+            rotation: None,
+            values: ValueMode::Raw,
+        })
+    }
+
+    /// Rotate by size; None keeps a single growing file
+    pub fn with_rotation(mut self, rotation: Option<Rotation>) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Value representation in logged events (e.g. hashed for sensitive data)
+    pub fn with_value_mode(mut self, values: ValueMode) -> Self {
+        self.values = values;
+        self
+    }
+
+    /// Path of the live file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn rotate(&mut self, keep: usize) -> Result<(), String> {
+        self.out.flush().map_err(|e| format!("Failed to write {}: {}", self.path.display(), e))?;
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        if keep == 0 {
+            fs::remove_file(&self.path)
+        } else {
+            let _ = fs::remove_file(rotated(keep));
+            for n in (1..keep).rev() {
+                if rotated(n).exists() {
+                    fs::rename(rotated(n), rotated(n + 1))
+                        .map_err(|e| format!("Failed to rotate {}: {}", rotated(n).display(), e))?;
+                }
+            }
+            fs::rename(&self.path, rotated(1))
+        }
+        .map_err(|e| format!("Failed to rotate {}: {}", self.path.display(), e))?;
+        (self.out, self.written) = open_log(&self.path)?;
+        Ok(())
+    }
+}
+
+impl EventSink for JsonlSink {
+    fn write(&mut self, event: &ChangeEvent) -> Result<(), String> {
+        let mut line = self.values.export(event).to_json();
+        line.push('\n');
+        if let Some(rotation) = self.rotation {
+            if self.written > 0 && self.written + line.len() as u64 > rotation.max_bytes {
+                self.rotate(rotation.keep)?;
+            }
+        }
+        self.out
+            .write_all(line.as_bytes())
+            .map_err(|e| format!("Failed to write {}: {}", self.path.display(), e))?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.out
+            .flush()
+            .and_then(|_| self.out.get_ref().sync_data())
+            .map_err(|e| format!("Failed to write {}: {}", self.path.display(), e))
+    }
+}
+
+fn open_log(path: &Path) -> Result<(BufWriter<File>, u64), String> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let written = file.metadata().map(|m| m.len()).unwrap_or(0);
+    Ok((BufWriter::new(file), written))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::marker;
+    use crate::EventKind;
+
+    #[test]
+    fn test_rotates_by_size_and_keeps_newest() {
+        let dir = std::env::temp_dir().join(format!("memwatch_jsonl_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("events.jsonl");
+
+        let line_len = ValueMode::Raw.export(&marker(1, "balance", EventKind::Unwatched)).to_json().len() as u64 + 1;
+        let mut sink = JsonlSink::open(&path).unwrap().with_rotation(Some(Rotation {
+            max_bytes: line_len * 2,
+            keep: 2,
+        }));
+        for id in 1..=7 {
+            sink.write(&marker(id, "balance", EventKind::Unwatched)).unwrap();
+        }
+        sink.flush().unwrap();
+
+        let region_ids = |path: PathBuf| -> Vec<String> {
+            fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .map(|line| line.split("\"region_id\":").nth(1).unwrap().split(',').next().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(region_ids(path.clone()), ["7"]);
+        assert_eq!(region_ids(dir.join("events.jsonl.1")), ["5", "6"]);
+        assert_eq!(region_ids(dir.join("events.jsonl.2")), ["3", "4"]);
+        assert!(!dir.join("events.jsonl.3").exists());
+
+        // Reopening appends and keeps counting towards the limit
+        drop(sink);
+        let mut sink = JsonlSink::open(&path).unwrap().with_rotation(Some(Rotation {
+            max_bytes: line_len * 2,
+            keep: 2,
+        }));
+        sink.write(&marker(8, "balance", EventKind::Unwatched)).unwrap();
+        sink.write(&marker(9, "balance", EventKind::Unwatched)).unwrap();
+        sink.flush().unwrap();
+        assert_eq!(region_ids(path), ["9"]);
+        assert_eq!(region_ids(dir.join("events.jsonl.1")), ["7", "8"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,207 @@
+// Pipeline trace
+//
+// With enable_pipeline_trace(), check_changes() logs what happens to each
+// event on its way through the Rust layer, to answer "why did my callback
+// never see this write":
+//
+//   watcher.enable_pipeline_trace();
+//   watcher.check_changes()?;
+//   for entry in watcher.pipeline_trace() {
+//       eprintln!("{}", entry);   // region 3 'orders' change @1712: delivered to sink 0 in 12 us
+//   }
+//
+// Steps are logged in pipeline order: produced (by the backend, or queued
+// as a marker), sampled out, dropped by the set_filter() filter, vetoed by
+// a hook, then per observer delivered (with the time the observer took),
+// skipped by a subscription filter, queued for a worker subscriber or
+// failed, and finally returned by check_changes(). The set_callback()
+// closure runs on the native worker thread before any of this and is not
+// traced. Only the last TRACE_ENTRIES entries are kept. Tracing costs an
+// atomic load per step while disabled.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+use crate::lifecycle::now_ns;
+use crate::{ChangeEvent, MemWatch};
+
+/// Entries kept by the trace
+pub const TRACE_ENTRIES: usize = 4096;
+
+/// What happened to an event at one step
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceStep {
+    /// Taken from the backend
+    Produced,
+    /// Queued by the Rust layer (lifecycle and diagnostic markers)
+    Marker,
+    /// Dropped by the region's WatchOptions::sample_rate or
+    /// max_events_per_sec
+    SampledOut,
+    /// Dropped by the set_filter() filter, shown as its expression
+    Filtered { filter: String },
+    /// Vetoed by a pre-persist hook (add_hook(), builder filter())
+    Vetoed,
+    /// Handed to an observer, which returned after `micros`
+    Delivered { to: String, micros: u64 },
+    /// Rejected by the filter of a subscription
+    Skipped { to: String },
+    /// Queued for an observer running elsewhere
+    Queued { to: String },
+    /// The observer failed or panicked
+    Failed { to: String, error: String },
+    /// Returned by check_changes()
+    Returned,
+}
+
+/// One step of one event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// When the step happened (wall clock)
+    pub at_ns: u64,
+    pub region_id: u32,
+    pub variable_name: Option<String>,
+    /// EventKind::as_str() of the event
+    pub kind: &'static str,
+    /// The event's own timestamp, which tells events of a region apart
+    pub event_timestamp_ns: u64,
+    pub step: TraceStep,
+}
+
+impl fmt::Display for TraceEntry {
+    /// "region 3 'orders' change @1712: delivered to sink 0 in 12 us"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "region {}", self.region_id)?;
+        if let Some(name) = &self.variable_name {
+            write!(f, " '{}'", name)?;
+        }
+        write!(f, " {} @{}: ", self.kind, self.event_timestamp_ns)?;
+        match &self.step {
+            TraceStep::Produced => write!(f, "produced by the backend"),
+            TraceStep::Marker => write!(f, "queued as a marker"),
+            TraceStep::SampledOut => write!(f, "sampled out"),
+            TraceStep::Filtered { filter } => write!(f, "dropped by filter `{}`", filter),
+            TraceStep::Vetoed => write!(f, "vetoed by a hook"),
+            TraceStep::Delivered { to, micros } => write!(f, "delivered to {} in {} us", to, micros),
+            TraceStep::Skipped { to } => write!(f, "skipped by the filter of {}", to),
+            TraceStep::Queued { to } => write!(f, "queued for {}", to),
+            TraceStep::Failed { to, error } => write!(f, "{} failed: {}", to, error),
+            TraceStep::Returned => write!(f, "returned by check_changes()"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PipelineTrace {
+    entries: VecDeque<TraceEntry>,
+}
+
+impl PipelineTrace {
+    pub(crate) fn record(&mut self, event: &ChangeEvent, step: TraceStep) {
+        if self.entries.len() == TRACE_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(TraceEntry {
+            at_ns: now_ns(),
+            region_id: event.region_id,
+            variable_name: event.variable_name.clone(),
+            kind: event.kind.as_str(),
+            event_timestamp_ns: event.timestamp_ns,
+            step,
+        });
+    }
+
+    pub(crate) fn entries(&self) -> Vec<TraceEntry> {
+        self.entries.iter().cloned().collect()
+    }
+}
+
+impl MemWatch {
+    /// Start logging what check_changes() does with each event; see the
+    /// pipeline_trace module
+    pub fn enable_pipeline_trace(&self) {
+        self.tracing_pipeline.store(true, Ordering::Relaxed);
+    }
+
+    /// Stop logging; the entries so far are kept
+    pub fn disable_pipeline_trace(&self) {
+        self.tracing_pipeline.store(false, Ordering::Relaxed);
+    }
+
+    /// The logged steps, oldest first
+    pub fn pipeline_trace(&self) -> Vec<TraceEntry> {
+        self.pipeline_trace.lock().unwrap().entries()
+    }
+
+    /// Log a step of `event` if tracing is on
+    pub(crate) fn trace(&self, event: &ChangeEvent, step: impl FnOnce() -> TraceStep) {
+        if self.tracing_pipeline.load(Ordering::Relaxed) {
+            self.pipeline_trace.lock().unwrap().record(event, step());
+        }
+    }
+}
+
+/// Microseconds since `start`, for TraceStep::Delivered
+pub(crate) fn micros_since(start: Instant) -> u64 {
+    start.elapsed().as_micros().min(u64::MAX as u128) as u64
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use crate::capabilities::WatchMode;
+    use crate::fake_native;
+    use crate::filter::Filter;
+    use crate::sink::EventSink;
+    use crate::{ChangeEvent, MemWatch, WatchOptions};
+
+    struct Refusing;
+
+    impl EventSink for Refusing {
+        fn write(&mut self, _event: &ChangeEvent) -> Result<(), String> {
+            Err("disk full".to_string())
+        }
+    }
+
+    #[test]
+    fn test_trace_follows_events_through_the_pipeline() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        let mut kept = vec![0u8; 4];
+        let mut dropped = vec![0u8; 4];
+        let mut sampled = vec![0u8; 4];
+        watcher.watch(&kept, "kept").unwrap();
+        watcher.watch(&dropped, "dropped").unwrap();
+        let options = WatchOptions { sample_rate: Some(2), ..WatchOptions::default() };
+        watcher.watch_with_options(&sampled, "sampled", &options).unwrap();
+        watcher.set_filter(Some(Filter::parse("name != 'dropped'").unwrap()));
+        watcher.check_changes().unwrap();
+        assert!(watcher.pipeline_trace().is_empty());
+
+        watcher.enable_pipeline_trace();
+        sampled[0] = 1;
+        let returned = watcher.check_changes().unwrap();
+        assert_eq!(returned.len(), 1);
+        watcher.add_sink(Refusing);
+        kept[0] = 1;
+        dropped[0] = 1;
+        sampled[0] = 2;
+        let _ = watcher.check_changes();
+        watcher.disable_pipeline_trace();
+
+        let trace = watcher.pipeline_trace();
+        let steps = |name: &str| -> Vec<String> {
+            trace
+                .iter()
+                .filter(|e| e.variable_name.as_deref() == Some(name))
+                .map(|e| e.to_string().split_once(": ").unwrap().1.to_string())
+                .collect()
+        };
+        // A failing sink fails check_changes(), so nothing is returned
+        assert_eq!(steps("kept"), ["produced by the backend", "sink 0 failed: disk full"]);
+        assert_eq!(steps("dropped"), ["produced by the backend", "dropped by filter `name != 'dropped'`"]);
+        assert_eq!(steps("sampled"), ["produced by the backend", "returned by check_changes()", "produced by the backend", "sampled out"]);
+    }
+}
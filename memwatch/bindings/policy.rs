@@ -0,0 +1,196 @@
+// Pre-persist policy hooks
+//
+// Hooks run synchronously on every change before it is recorded (sequenced,
+// returned, written to sinks, or stored by the SQL tracker) and may veto the
+// record or raise an alert. This is advisory, logging-level control: the
+// memory write or SQL statement has already happened by the time a hook sees
+// it, so a veto only keeps the change out of the history; it never blocks or
+// rolls back the application.
+//
+// With a suppression window set, an alert on a subject its rule (hook)
+// already raised one for less than `window` ago is held back and counted
+// instead, whatever its message says (messages often carry the offending
+// value). Once the window is over, the next alert or take_alerts() reports
+// the count as one aggregate alert with the last held message ("42 more
+// similar alerts suppressed in the last 60s: ..."), so a runaway writer
+// yields a handful of alerts, not thousands. Windows that are over are
+// dropped then, so only subjects alerted on within the last window are
+// remembered.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// What a hook decided for one change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// Record normally
+    Record,
+    /// Leave the change out of the history
+    Veto,
+    /// Record and raise an alert
+    Alert(String),
+    /// Leave the change out of the history but raise an alert
+    AlertAndVeto(String),
+}
+
+/// Raised by a hook
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alert {
+    pub timestamp_ns: u64,
+    /// Region name or `table.column`
+    pub subject: String,
+    pub message: String,
+    /// Index of the hook that raised it, in the order hooks were added
+    pub rule: usize,
+    /// For aggregate alerts, how many identical alerts were held back;
+    /// 0 otherwise
+    pub suppressed: u64,
+}
+
+/// Alerts raised but not yet taken, with duplicate suppression
+#[derive(Default)]
+pub(crate) struct AlertQueue {
+    pending: Vec<Alert>,
+    window: Option<Duration>,
+    /// (rule, subject) -> (window start, alerts held back, last held message)
+    windows: HashMap<(usize, String), (Instant, u64, String)>,
+}
+
+impl AlertQueue {
+    pub(crate) fn set_suppression(&mut self, window: Option<Duration>) {
+        self.window = window;
+        self.windows.clear();
+    }
+
+    pub(crate) fn push(&mut self, alert: Alert) {
+        self.push_at(alert, Instant::now());
+    }
+
+    fn push_at(&mut self, alert: Alert, now: Instant) {
+        let Some(window) = self.window else {
+            self.pending.push(alert);
+            return;
+        };
+        self.expire(window, now, alert.timestamp_ns);
+        let key = (alert.rule, alert.subject.clone());
+        if let Some((_, held, message)) = self.windows.get_mut(&key) {
+            *held += 1;
+            *message = alert.message;
+            return;
+        }
+        self.windows.insert(key, (now, 0, String::new()));
+        self.pending.push(alert);
+    }
+
+    /// Drop windows that are over, queueing aggregates of what they held
+    fn expire(&mut self, window: Duration, now: Instant, timestamp_ns: u64) {
+        let mut ended = Vec::new();
+        self.windows.retain(|(rule, subject), (start, held, message)| {
+            if now.duration_since(*start) < window {
+                return true;
+            }
+            if *held > 0 {
+                ended.push(Alert {
+                    timestamp_ns,
+                    subject: subject.clone(),
+                    message: format!("{} more similar alerts suppressed in the last {}s: {}", held, window.as_secs(), message),
+                    rule: *rule,
+                    suppressed: *held,
+                });
+            }
+            false
+        });
+        ended.sort_by_key(|alert| alert.rule);
+        self.pending.extend(ended);
+    }
+
+    pub(crate) fn take(&mut self) -> Vec<Alert> {
+        self.take_at(Instant::now(), crate::lifecycle::now_ns())
+    }
+
+    /// Pending alerts, plus aggregates of windows that are over
+    fn take_at(&mut self, now: Instant, now_ns: u64) -> Vec<Alert> {
+        if let Some(window) = self.window {
+            self.expire(window, now, now_ns);
+        }
+        std::mem::take(&mut self.pending)
+    }
+}
+
+pub(crate) type Hook<T> = Box<dyn Fn(&T) -> Decision + Send>;
+
+/// Run `hooks` in order; returns whether to record, pushing raised alerts.
+/// A veto stops later hooks from running.
+pub(crate) fn evaluate<T>(hooks: &[Hook<T>], change: &T, subject: impl Fn() -> String, timestamp_ns: u64, alerts: &mut AlertQueue) -> bool {
+    for (rule, hook) in hooks.iter().enumerate() {
+        let (record, message) = match hook(change) {
+            Decision::Record => (true, None),
+            Decision::Veto => (false, None),
+            Decision::Alert(message) => (true, Some(message)),
+            Decision::AlertAndVeto(message) => (false, Some(message)),
+        };
+        if let Some(message) = message {
+            alerts.push(Alert { timestamp_ns, subject: subject(), message, rule, suppressed: 0 });
+        }
+        if !record {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alert(rule: usize, message: &str) -> Alert {
+        alert_on(rule, "balance", message)
+    }
+
+    fn alert_on(rule: usize, subject: &str, message: &str) -> Alert {
+        Alert { timestamp_ns: 1, subject: subject.to_string(), message: message.to_string(), rule, suppressed: 0 }
+    }
+
+    #[test]
+    fn test_suppresses_duplicates_per_rule_and_aggregates() {
+        let mut queue = AlertQueue::default();
+        queue.set_suppression(Some(Duration::from_secs(60)));
+        let start = Instant::now();
+        for i in 0..42 {
+            queue.push_at(alert(0, "negative"), start + Duration::from_millis(i));
+        }
+        // Same rule and subject: held back, whatever the message
+        queue.push_at(alert(0, "overdrawn"), start + Duration::from_millis(42));
+        queue.push_at(alert(1, "negative"), start);
+        queue.push_at(alert_on(0, "limit", "negative"), start);
+        let taken = queue.take_at(start + Duration::from_secs(1), 5);
+        let messages: Vec<(usize, &str, &str)> = taken.iter().map(|a| (a.rule, a.subject.as_str(), a.message.as_str())).collect();
+        assert_eq!(messages, [(0, "balance", "negative"), (1, "balance", "negative"), (0, "limit", "negative")]);
+
+        // The window ends: its count comes out with the next take
+        let taken = queue.take_at(start + Duration::from_secs(61), 7);
+        assert_eq!(taken.len(), 1);
+        assert_eq!((taken[0].rule, taken[0].suppressed, taken[0].timestamp_ns), (0, 42, 7));
+        assert_eq!(taken[0].message, "42 more similar alerts suppressed in the last 60s: overdrawn");
+        assert!(queue.windows.is_empty());
+
+        // Or ahead of the first alert after the window
+        queue.push_at(alert(0, "negative"), start + Duration::from_secs(70));
+        queue.push_at(alert(0, "negative"), start + Duration::from_secs(71));
+        queue.push_at(alert(0, "negative"), start + Duration::from_secs(131));
+        let suppressed: Vec<u64> = queue.take_at(start + Duration::from_secs(132), 9).iter().map(|a| a.suppressed).collect();
+        assert_eq!(suppressed, [0, 1, 0]);
+
+        // Windows that are over are dropped as alerts come in
+        for i in 0..100 {
+            queue.push_at(alert_on(0, &format!("account {}", i), "negative"), start + Duration::from_secs(200 + i * 61));
+        }
+        assert_eq!(queue.windows.len(), 1);
+        assert_eq!(queue.take_at(start + Duration::from_secs(200 + 99 * 61), 0).len(), 100);
+
+        queue.set_suppression(None);
+        queue.push_at(alert(0, "negative"), start);
+        queue.push_at(alert(0, "negative"), start);
+        assert_eq!(queue.take_at(start, 0).len(), 2);
+    }
+}
@@ -0,0 +1,181 @@
+// Snapshot polling for environments without page protection
+//
+// Each watched region keeps a copy of its last seen contents; every poll
+// compares the live bytes against it and emits one event per changed region.
+// Source locations are unavailable in this mode.
+
+use std::collections::HashMap;
+
+use crate::lifecycle::{now_ns, EventSource};
+use crate::{ChangeEvent, EventKind, Location};
+
+pub(crate) const PREVIEW_SIZE: usize = 256;
+
+struct PolledRegion {
+    addr: usize,
+    len: usize,
+    name: String,
+    max_value_bytes: i32,
+    last: Vec<u8>,
+    paused: bool,
+}
+
+impl PolledRegion {
+    fn current(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; self.len];
+        unsafe {
+            for (i, b) in bytes.iter_mut().enumerate() {
+                *b = std::ptr::read_volatile((self.addr + i) as *const u8);
+            }
+        }
+        bytes
+    }
+
+    fn value(&self, bytes: &[u8]) -> Vec<u8> {
+        match self.max_value_bytes {
+            0 => Vec::new(),
+            n if n < 0 => bytes.to_vec(),
+            n => bytes[..bytes.len().min(n as usize)].to_vec(),
+        }
+    }
+}
+
+/// Regions watched by snapshot comparison
+#[derive(Default)]
+pub(crate) struct PollingRegions {
+    next_region_id: u32,
+    next_seq: u32,
+    total_events: u64,
+    regions: HashMap<u32, PolledRegion>,
+}
+
+impl PollingRegions {
+    pub(crate) fn watch(&mut self, buffer: &[u8], name: &str, max_value_bytes: i32) -> u32 {
+        self.next_region_id += 1;
+        let region_id = self.next_region_id;
+        self.regions.insert(
+            region_id,
+            PolledRegion {
+                addr: buffer.as_ptr() as usize,
+                len: buffer.len(),
+                name: name.to_string(),
+                max_value_bytes,
+                last: buffer.to_vec(),
+                paused: false,
+            },
+        );
+        region_id
+    }
+
+    pub(crate) fn unwatch(&mut self, region_id: u32) -> bool {
+        self.regions.remove(&region_id).is_some()
+    }
+
+    /// Take the live contents as the new snapshot, so the next poll does
+    /// not report writes already accounted for
+    pub(crate) fn resync(&mut self, region_id: u32) {
+        if let Some(region) = self.regions.get_mut(&region_id) {
+            region.last = region.current();
+        }
+    }
+
+    /// Skip a region in polls until resume(); false if unknown
+    pub(crate) fn pause(&mut self, region_id: u32) -> bool {
+        self.regions.get_mut(&region_id).map(|region| region.paused = true).is_some()
+    }
+
+    /// Poll a paused region again, from its live contents
+    pub(crate) fn resume(&mut self, region_id: u32) -> bool {
+        let Some(region) = self.regions.get_mut(&region_id) else {
+            return false;
+        };
+        region.paused = false;
+        region.last = region.current();
+        true
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.regions.len()
+    }
+
+    pub(crate) fn total_events(&self) -> u64 {
+        self.total_events
+    }
+
+    /// Compare every region against its snapshot; up to `max_events` events
+    pub(crate) fn poll(&mut self, max_events: usize) -> Vec<ChangeEvent> {
+        let timestamp_ns = now_ns();
+
+        let mut ids: Vec<u32> = self.regions.keys().copied().collect();
+        ids.sort_unstable();
+
+        let mut events = Vec::new();
+        for region_id in ids {
+            if events.len() >= max_events {
+                break;
+            }
+            let region = self.regions.get_mut(&region_id).expect("id taken from map");
+            if region.paused {
+                continue;
+            }
+            let current = region.current();
+            if current == region.last {
+                continue;
+            }
+
+            self.next_seq = self.next_seq.wrapping_add(1);
+            self.total_events += 1;
+            events.push(ChangeEvent {
+                seq: self.next_seq,
+                timestamp_ns,
+                adapter_id: 0,
+                region_id,
+                variable_name: Some(region.name.clone()),
+                group: None,
+                where_: Location::default(),
+                old_preview: region.last[..region.len.min(PREVIEW_SIZE)].to_vec(),
+                new_preview: current[..region.len.min(PREVIEW_SIZE)].to_vec(),
+                old_value: region.value(&region.last),
+                new_value: region.value(&current),
+                storage_key_old: None,
+                storage_key_new: None,
+                kind: EventKind::Change,
+                epoch: 0,
+                global_seq: 0,
+                tid: None,
+                thread_name: None,
+                frames: Vec::new(),
+                allocated_at: None,
+                source: EventSource::Observed,
+                preview_limit: None,
+                deltas: Vec::new(),
+                span_id: None,
+                span_name: None,
+                write_count: 1,
+            });
+            region.last = current;
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poll_detects_change_once() {
+        let mut data = vec![0u8; 8];
+        let mut regions = PollingRegions::default();
+        let id = regions.watch(&data, "buf", -1);
+
+        assert!(regions.poll(16).is_empty());
+        data[3] = 7;
+        let events = regions.poll(16);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].region_id, id);
+        assert_eq!(events[0].old_value[3], 0);
+        assert_eq!(events[0].new_value[3], 7);
+        assert!(regions.poll(16).is_empty());
+    }
+}
@@ -0,0 +1,193 @@
+// Security-relevant region presets
+//
+// Convenience registrations for memory that should never change once the
+// program is running: writable import tables (GOT/PLT), the vtable pointer of
+// an object, and the stack protector canary. Any event on these regions is a
+// tampering signal.
+
+#[cfg(target_os = "linux")]
+use std::ffi::CStr;
+#[cfg(target_os = "linux")]
+use std::os::raw::{c_int, c_void};
+
+use crate::MemWatch;
+
+#[cfg(target_os = "linux")]
+const DT_NULL: i64 = 0;
+#[cfg(target_os = "linux")]
+const DT_PLTRELSZ: i64 = 2;
+#[cfg(target_os = "linux")]
+const DT_PLTGOT: i64 = 3;
+#[cfg(target_os = "linux")]
+const DT_RELA: i64 = 7;
+#[cfg(target_os = "linux")]
+const DT_PLTREL: i64 = 20;
+/// _DYNAMIC, _dl_runtime_resolve link map, resolver entry
+#[cfg(target_os = "linux")]
+const GOT_PLT_RESERVED: usize = 3;
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct Elf64Dyn {
+    d_tag: i64,
+    d_val: u64,
+}
+
+/// Writable GOT/PLT table of one loaded object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GotPlt {
+    /// Shared object path, or "main" for the executable
+    pub object: String,
+    pub addr: usize,
+    pub len: usize,
+}
+
+/// Writable GOT/PLT tables of all loaded objects. Objects linked with full
+/// RELRO are skipped: the loader already made their tables read-only.
+#[cfg(target_os = "linux")]
+pub fn got_plt_tables() -> Vec<GotPlt> {
+    let mut tables = Vec::new();
+    // SAFETY: the callback only reads the program headers handed to it
+    unsafe { libc::dl_iterate_phdr(Some(collect_got_plt), &mut tables as *mut Vec<GotPlt> as *mut c_void) };
+    tables
+}
+
+/// No ELF loader to ask outside Linux
+#[cfg(not(target_os = "linux"))]
+pub fn got_plt_tables() -> Vec<GotPlt> {
+    Vec::new()
+}
+
+#[cfg(target_os = "linux")]
+unsafe extern "C" fn collect_got_plt(info: *mut libc::dl_phdr_info, _size: usize, data: *mut c_void) -> c_int {
+    let info = &*info;
+    let tables = &mut *(data as *mut Vec<GotPlt>);
+    let base = info.dlpi_addr as usize;
+    let phdrs = std::slice::from_raw_parts(info.dlpi_phdr, info.dlpi_phnum as usize);
+    let Some(dynamic) = phdrs.iter().find(|p| p.p_type == libc::PT_DYNAMIC) else {
+        return 0;
+    };
+
+    let (mut pltgot, mut pltrelsz, mut rela) = (0usize, 0usize, false);
+    let mut entry = (base + dynamic.p_vaddr as usize) as *const Elf64Dyn;
+    while (*entry).d_tag != DT_NULL {
+        match (*entry).d_tag {
+            DT_PLTGOT => pltgot = (*entry).d_val as usize,
+            DT_PLTRELSZ => pltrelsz = (*entry).d_val as usize,
+            DT_PLTREL => rela = (*entry).d_val as i64 == DT_RELA,
+            _ => {}
+        }
+        entry = entry.add(1);
+    }
+    if pltgot == 0 || pltrelsz == 0 {
+        return 0;
+    }
+    // glibc relocates d_ptr entries in place, other loaders may not
+    if pltgot < base {
+        pltgot += base;
+    }
+
+    let relocation_size = if rela { 24 } else { 16 };
+    let len = (GOT_PLT_RESERVED + pltrelsz / relocation_size) * std::mem::size_of::<usize>();
+    let relro = phdrs.iter().filter(|p| p.p_type == libc::PT_GNU_RELRO).any(|p| {
+        let start = base + p.p_vaddr as usize;
+        pltgot >= start && pltgot + len <= start + p.p_memsz as usize
+    });
+    if relro {
+        return 0;
+    }
+
+    tables.push(GotPlt {
+        object: object_name(info),
+        addr: pltgot,
+        len,
+    });
+    0
+}
+
+/// Path of a loaded object, or "main" for the executable
+#[cfg(target_os = "linux")]
+pub(crate) unsafe fn object_name(info: &libc::dl_phdr_info) -> String {
+    let name = if info.dlpi_name.is_null() { "" } else { CStr::from_ptr(info.dlpi_name).to_str().unwrap_or("") };
+    if name.is_empty() { "main".to_string() } else { name.to_string() }
+}
+
+/// Watch every writable GOT/PLT table, named "got.plt:<object>"; returns the
+/// region ids. With lazy binding the loader itself fills entries on first
+/// call, so run with LD_BIND_NOW=1 (or link with -z now) to only see
+/// tampering.
+pub fn watch_got_plt(watch: &MemWatch) -> Result<Vec<u32>, String> {
+    got_plt_tables()
+        .into_iter()
+        .map(|table| {
+            // SAFETY: the table is mapped for the lifetime of its object
+            let bytes = unsafe { std::slice::from_raw_parts(table.addr as *const u8, table.len) };
+            watch.watch(bytes, &format!("got.plt:{}", table.object))
+        })
+        .collect()
+}
+
+/// Watch the vtable pointer of `obj`, i.e. its first pointer-sized word. For
+/// `#[repr(C)]` mirrors of C++ objects, whose vptr comes first; Rust trait
+/// object vtables live in read-only memory and need no watching.
+pub fn watch_vtable_of<T>(watch: &MemWatch, obj: &T, name: &str) -> Result<u32, String> {
+    let word = std::mem::size_of::<usize>();
+    if std::mem::size_of::<T>() < word {
+        return Err(format!("{} is smaller than a vtable pointer", std::any::type_name::<T>()));
+    }
+    // SAFETY: obj is at least one word long
+    let bytes = unsafe { std::slice::from_raw_parts(obj as *const T as *const u8, word) };
+    watch.watch(bytes, &format!("vtable:{}", name))
+}
+
+/// Count writes to the calling thread's stack protector canary. The canary
+/// shares its page with hot thread-local data, so it is watched count-only
+/// (by a hardware breakpoint where perf events allow, so its neighbours do
+/// not fault); any nonzero count in write_counts() is tampering.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub fn watch_stack_canary(watch: &MemWatch) -> Result<u32, String> {
+    let tcb: usize;
+    // SAFETY: on x86_64 Linux fs:0 holds the thread control block's own address
+    unsafe { std::arch::asm!("mov {}, fs:0", out(reg) tcb, options(nostack, readonly, preserves_flags)) };
+    // SAFETY: glibc and musl keep the canary at fs:0x28 for the thread's lifetime
+    let canary = unsafe { std::slice::from_raw_parts((tcb + 0x28) as *const u8, 8) };
+    watch.watch_count_only(canary, "stack_canary")
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native;
+
+    #[repr(C)]
+    struct CppObject {
+        vptr: usize,
+    }
+
+    #[test]
+    fn test_presets_register_regions() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Protect;
+
+        for table in got_plt_tables() {
+            assert!(table.len >= GOT_PLT_RESERVED * 8 && table.addr % 8 == 0, "{:?}", table);
+        }
+        assert_eq!(watch_got_plt(&watcher).unwrap().len(), got_plt_tables().len());
+
+        let obj = CppObject { vptr: 0x1000 };
+        assert!(watch_vtable_of(&watcher, &obj, "obj").is_ok());
+        assert!(watch_vtable_of(&watcher, &1u8, "byte").is_err());
+        assert_eq!(obj.vptr, 0x1000);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_stack_canary_is_count_only() {
+        let watcher = MemWatch::new().unwrap();
+        let canary = watch_stack_canary(&watcher).unwrap();
+        let counts = watcher.write_counts();
+        assert_eq!(counts.iter().find(|c| c.region_id == canary).map(|c| c.total), Some(0));
+    }
+}
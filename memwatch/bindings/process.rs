@@ -0,0 +1,186 @@
+// Process metadata for exports
+//
+// Exported histories get merged across processes and hosts, so each export
+// file starts with a header describing the writing process, and every
+// exported event carries the pid (plus the tid when known). Application
+// metadata of the watcher (git sha, build id, ..., see
+// MemWatch::set_metadata) goes into the header too, so histories of
+// different builds can be told apart.
+
+use std::fs;
+use std::sync::OnceLock;
+
+use sha2::{Digest, Sha256};
+
+use crate::export::{hex, json_opt_str, json_str};
+
+/// Describes the process that produced an export
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessMetadata {
+    pub pid: u32,
+    pub exe_name: String,
+    /// Hex SHA-256 of the executable (None if it cannot be read)
+    pub exe_sha256: Option<String>,
+    pub hostname: String,
+    /// Container id from /proc/self/cgroup (docker, containerd, podman)
+    pub container_id: Option<String>,
+    pub crate_version: &'static str,
+}
+
+impl ProcessMetadata {
+    /// Header record written once at the start of an export file
+    pub fn to_json(&self) -> String {
+        self.to_json_with(&[])
+    }
+
+    /// to_json(), with application metadata (omitted when empty)
+    pub fn to_json_with(&self, metadata: &[(String, String)]) -> String {
+        format!(
+            "{{\"process\":{{\"pid\":{},\"exe\":{},\"exe_sha256\":{},\"hostname\":{},\"container_id\":{},\"memwatch_version\":{}{}}}}}",
+            self.pid,
+            json_str(&self.exe_name),
+            json_opt_str(self.exe_sha256.as_deref()),
+            json_str(&self.hostname),
+            json_opt_str(self.container_id.as_deref()),
+            json_str(self.crate_version),
+            metadata_json(metadata).map(|m| format!(",\"metadata\":{}", m)).unwrap_or_default(),
+        )
+    }
+}
+
+/// Metadata of the current process, gathered (and the executable hashed) on
+/// first use
+pub fn current() -> &'static ProcessMetadata {
+    static CURRENT: OnceLock<ProcessMetadata> = OnceLock::new();
+    CURRENT.get_or_init(|| {
+        let exe = std::env::current_exe().ok();
+        ProcessMetadata {
+            pid: std::process::id(),
+            exe_name: exe
+                .as_ref()
+                .and_then(|path| path.file_name())
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "unknown".to_string()),
+            exe_sha256: exe.and_then(|path| fs::read(path).ok()).map(|bytes| hex(&Sha256::digest(bytes))),
+            hostname: hostname(),
+            container_id: fs::read_to_string("/proc/self/cgroup").ok().and_then(|s| container_id(&s)),
+            crate_version: env!("CARGO_PKG_VERSION"),
+        }
+    })
+}
+
+/// `{"key":"value",..}`, or None when there is nothing to write
+pub(crate) fn metadata_json(metadata: &[(String, String)]) -> Option<String> {
+    if metadata.is_empty() {
+        return None;
+    }
+    let fields: Vec<String> = metadata.iter().map(|(k, v)| format!("{}:{}", json_str(k), json_str(v))).collect();
+    Some(format!("{{{}}}", fields.join(",")))
+}
+
+/// Kernel thread id of the calling thread
+#[cfg(target_os = "linux")]
+pub(crate) fn current_tid() -> u32 {
+    // SAFETY: gettid has no preconditions
+    unsafe { libc::gettid() as u32 }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn current_tid() -> u32 {
+    0
+}
+
+/// Name of a thread of this process, None once it has exited
+#[cfg(target_os = "linux")]
+pub(crate) fn thread_name(tid: u32) -> Option<String> {
+    let comm = fs::read_to_string(format!("/proc/self/task/{}/comm", tid)).ok()?;
+    Some(comm.trim_end().to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn thread_name(_tid: u32) -> Option<String> {
+    None
+}
+
+/// Whether a debugger (any ptrace tracer) is attached to this process
+#[cfg(target_os = "linux")]
+pub(crate) fn debugger_attached() -> bool {
+    let status = fs::read_to_string("/proc/self/status").unwrap_or_default();
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("TracerPid:"))
+        .is_some_and(|pid| pid.trim() != "0")
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn debugger_attached() -> bool {
+    false
+}
+
+/// Difference between where the executable was loaded and its link-time
+/// addresses (nonzero for position-independent executables)
+#[cfg(target_os = "linux")]
+pub(crate) fn load_bias() -> u64 {
+    unsafe extern "C" fn first(info: *mut libc::dl_phdr_info, _size: libc::size_t, out: *mut libc::c_void) -> libc::c_int {
+        // SAFETY: called by dl_iterate_phdr with a valid info and our pointer
+        unsafe { *(out as *mut u64) = (*info).dlpi_addr };
+        // The main executable comes first; stop there
+        1
+    }
+    let mut bias = 0u64;
+    // SAFETY: `first` only writes through the pointer it is given
+    unsafe { libc::dl_iterate_phdr(Some(first), &mut bias as *mut u64 as *mut libc::c_void) };
+    bias
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn load_bias() -> u64 {
+    0
+}
+
+#[cfg(unix)]
+pub(crate) fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: gethostname writes at most buf.len() bytes into buf
+    let rc = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if rc != 0 {
+        return "localhost".to_string();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn hostname() -> String {
+    "localhost".to_string()
+}
+
+/// First 64-hex-digit path component in a cgroup file, e.g.
+/// "0::/system.slice/docker-<id>.scope" or "12:pids:/docker/<id>"
+fn container_id(cgroup: &str) -> Option<String> {
+    cgroup
+        .lines()
+        .filter_map(|line| line.splitn(3, ':').nth(2))
+        .flat_map(|path| path.split('/'))
+        .map(|part| part.strip_suffix(".scope").unwrap_or(part))
+        .map(|part| part.rsplit('-').next().unwrap_or(part))
+        .find(|id| id.len() == 64 && id.bytes().all(|b| b.is_ascii_hexdigit()))
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_container_id_from_cgroup() {
+        let id = "4f1c".repeat(16);
+        assert_eq!(container_id(&format!("0::/system.slice/docker-{}.scope\n", id)), Some(id.clone()));
+        assert_eq!(container_id(&format!("12:pids:/docker/{}\n", id)), Some(id));
+        assert_eq!(container_id("0::/user.slice/user-1000.slice\n"), None);
+
+        let header = current().to_json();
+        assert!(header.starts_with(&format!("{{\"process\":{{\"pid\":{},", std::process::id())));
+        assert!(header.contains(&format!("\"memwatch_version\":\"{}\"", env!("CARGO_PKG_VERSION"))));
+    }
+}
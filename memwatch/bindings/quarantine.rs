@@ -0,0 +1,89 @@
+// Quarantine for unwatched regions
+//
+// With a quarantine set, unwatch() ends a region logically (Unwatched marker,
+// id no longer tracked) but leaves the backend watch in place for a while.
+// Late writers then still fault and are reported as UseAfterUnwatch events
+// with full attribution. Regions are truly released once they are older than
+// the configured duration, or oldest first while the quarantine holds more
+// than its byte budget.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::lifecycle::RegionInfo;
+
+/// How long unwatched regions stay protected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quarantine {
+    pub duration: Duration,
+    /// Release oldest regions first while more bytes than this are held
+    pub max_bytes: usize,
+}
+
+struct Quarantined {
+    region_id: u32,
+    info: RegionInfo,
+    since: Instant,
+}
+
+#[derive(Default)]
+pub(crate) struct QuarantineList {
+    policy: Option<Quarantine>,
+    regions: VecDeque<Quarantined>,
+}
+
+impl QuarantineList {
+    pub(crate) fn set_policy(&mut self, policy: Option<Quarantine>) {
+        self.policy = policy;
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.policy.is_some()
+    }
+
+    pub(crate) fn admit(&mut self, region_id: u32, info: RegionInfo) {
+        self.regions.push_back(Quarantined {
+            region_id,
+            info,
+            since: Instant::now(),
+        });
+    }
+
+    /// Quarantined regions overlapping `info`, as (id, name)
+    pub(crate) fn overlapping(&self, info: &RegionInfo) -> Vec<(u32, String)> {
+        self.regions
+            .iter()
+            .filter(|q| q.info.overlaps(info))
+            .map(|q| (q.region_id, q.info.name.clone()))
+            .collect()
+    }
+
+    pub(crate) fn get(&self, region_id: u32) -> Option<&RegionInfo> {
+        self.regions.iter().find(|q| q.region_id == region_id).map(|q| &q.info)
+    }
+
+    /// Regions to release now, oldest first: all of them when the quarantine
+    /// was switched off, otherwise expired ones and those over the budget
+    pub(crate) fn due(&self, now: Instant) -> Vec<u32> {
+        let Some(policy) = self.policy else {
+            return self.regions.iter().map(|q| q.region_id).collect();
+        };
+        let mut held: usize = self.regions.iter().map(|q| q.info.size).sum();
+        let mut due = Vec::new();
+        for q in &self.regions {
+            if held > policy.max_bytes || now.duration_since(q.since) >= policy.duration {
+                held -= q.info.size;
+                due.push(q.region_id);
+            }
+        }
+        due
+    }
+
+    pub(crate) fn remove(&mut self, region_id: u32) {
+        self.regions.retain(|q| q.region_id != region_id);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.regions.clear();
+    }
+}
@@ -0,0 +1,180 @@
+// Time-travel replay
+//
+// A Recorder is a sink writing every delivered event, in order and with raw
+// values, to a fresh JSONL log (the export format, see persistence). A
+// Replayer reads such a log back and answers "what did this region hold at
+// sequence number N / at time T": the new value of the region's last change
+// up to that point, or, before its first change, that change's old value.
+//
+// Reconstruction is only as complete as the values the events carry: whole
+// regions with shadow pages, in Snapshot mode or with max_value_bytes -1,
+// at most max_value_bytes otherwise (see storage::history, which keeps the
+// same values compacted). Sequence numbers are global_seq; use a sequence
+// store to keep them increasing across restarts.
+
+use std::fs;
+use std::path::Path;
+
+use crate::export::parse_hex;
+use crate::json::JsonValue;
+use crate::persistence::JsonlSink;
+use crate::sink::EventSink;
+use crate::ChangeEvent;
+
+/// Sink recording a replayable event log
+pub struct Recorder {
+    log: JsonlSink,
+}
+
+impl Recorder {
+    /// Start a recording at `path`, replacing any earlier one
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        if path.exists() {
+            fs::remove_file(path).map_err(|e| format!("Failed to replace {}: {}", path.display(), e))?;
+        }
+        Ok(Recorder { log: JsonlSink::open(path)? })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.log.path()
+    }
+}
+
+impl EventSink for Recorder {
+    fn write(&mut self, event: &ChangeEvent) -> Result<(), String> {
+        self.log.write(event)
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.log.flush()
+    }
+}
+
+/// One recorded change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedChange {
+    pub global_seq: u64,
+    pub timestamp_ns: u64,
+    pub old: Vec<u8>,
+    pub new: Vec<u8>,
+}
+
+/// Region states reconstructed from a Recorder log
+#[derive(Debug, Default)]
+pub struct Replayer {
+    /// (region id, name, changes in log order)
+    regions: Vec<(u32, Option<String>, Vec<RecordedChange>)>,
+}
+
+impl Replayer {
+    /// Read a log written by a Recorder (or a JsonlSink with raw values)
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let mut replayer = Replayer::default();
+        for (number, line) in text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let bad = || format!("Malformed event at {}:{}", path.display(), number + 1);
+            let event = JsonValue::parse(line).ok_or_else(bad)?;
+            let region_id = event.get("region_id").and_then(JsonValue::as_u64).and_then(|id| u32::try_from(id).ok()).ok_or_else(bad)?;
+            let name = event.get("variable_name").and_then(JsonValue::as_str).map(str::to_string);
+            let index = match replayer.regions.iter().position(|(id, _, _)| *id == region_id) {
+                Some(index) => index,
+                None => {
+                    replayer.regions.push((region_id, None, Vec::new()));
+                    replayer.regions.len() - 1
+                }
+            };
+            let region = &mut replayer.regions[index];
+            if region.1.is_none() {
+                region.1 = name;
+            }
+            if event.get("kind").and_then(JsonValue::as_str) != Some("change") {
+                continue;
+            }
+            let value = |key: &str| {
+                event
+                    .get(key)
+                    .and_then(JsonValue::as_str)
+                    .and_then(parse_hex)
+                    .ok_or_else(|| format!("{} at {}:{} is not a raw value", key, path.display(), number + 1))
+            };
+            region.2.push(RecordedChange {
+                global_seq: event.get("global_seq").and_then(JsonValue::as_u64).ok_or_else(bad)?,
+                timestamp_ns: event.get("timestamp_ns").and_then(JsonValue::as_u64).ok_or_else(bad)?,
+                old: value("old")?,
+                new: value("new")?,
+            });
+        }
+        Ok(replayer)
+    }
+
+    /// Recorded regions as (id, name), in order of first appearance
+    pub fn regions(&self) -> Vec<(u32, Option<String>)> {
+        self.regions.iter().map(|(id, name, _)| (*id, name.clone())).collect()
+    }
+
+    /// Recorded changes of a region, oldest first
+    pub fn changes(&self, region_id: u32) -> &[RecordedChange] {
+        self.regions.iter().find(|(id, _, _)| *id == region_id).map_or(&[], |(_, _, changes)| changes)
+    }
+
+    /// Contents of a region once the change with `global_seq` (and every
+    /// earlier one) was made; None without recorded changes
+    pub fn state_at_seq(&self, region_id: u32, global_seq: u64) -> Option<Vec<u8>> {
+        let changes = self.changes(region_id);
+        state_after(changes, changes.partition_point(|change| change.global_seq <= global_seq))
+    }
+
+    /// Contents of a region at `timestamp_ns`, changes made at that very
+    /// time included; None without recorded changes
+    pub fn state_at_time(&self, region_id: u32, timestamp_ns: u64) -> Option<Vec<u8>> {
+        let changes = self.changes(region_id);
+        state_after(changes, changes.partition_point(|change| change.timestamp_ns <= timestamp_ns))
+    }
+}
+
+/// State once the first `applied` changes were made
+fn state_after(changes: &[RecordedChange], applied: usize) -> Option<Vec<u8>> {
+    match applied {
+        0 => changes.first().map(|change| change.old.clone()),
+        n => Some(changes[n - 1].new.clone()),
+    }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native;
+    use crate::MemWatch;
+
+    #[test]
+    fn test_replays_region_states_by_seq_and_time() {
+        let _guard = fake_native::lock();
+        let path = std::env::temp_dir().join(format!("memwatch_replay_{}.jsonl", std::process::id()));
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        watcher.add_sink(Recorder::create(&path).unwrap());
+        let mut balance = [0u8; 4];
+        let id = watcher.watch_with_max_value_bytes(&balance, "balance", -1).unwrap();
+        watcher.check_changes().unwrap();
+
+        let mut seqs = Vec::new();
+        for value in 1..=3u8 {
+            balance[value as usize] = value;
+            let events = watcher.check_changes().unwrap();
+            seqs.push((events[0].global_seq, events[0].timestamp_ns));
+        }
+
+        let replayer = Replayer::open(&path).unwrap();
+        assert_eq!(replayer.regions(), [(id, Some("balance".to_string()))]);
+        assert_eq!(replayer.changes(id).len(), 3);
+        assert_eq!(replayer.state_at_seq(id, 0).unwrap(), [0, 0, 0, 0]);
+        assert_eq!(replayer.state_at_seq(id, seqs[1].0).unwrap(), [0, 1, 2, 0]);
+        assert_eq!(replayer.state_at_seq(id, u64::MAX).unwrap(), [0, 1, 2, 3]);
+        assert_eq!(replayer.state_at_time(id, seqs[0].1).unwrap(), [0, 1, 0, 0]);
+        assert_eq!(replayer.state_at_seq(id + 1, 1), None);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,184 @@
+// Rendered reports
+//
+// timeline_chart() draws the change rate of a few regions over time, with
+// lifecycle markers as labelled vertical lines. The output format follows the
+// file extension: .png renders a bitmap, anything else SVG.
+//
+// region_graph_dot() lives in the graph module, which needs no `charts`;
+// it is re-exported here.
+
+use std::path::Path;
+use std::time::Duration;
+
+use plotters::coord::Shift;
+use plotters::prelude::*;
+
+use crate::timeline::BucketStats;
+use crate::MemWatch;
+
+pub use crate::graph::region_graph_dot;
+
+const CHART_SIZE: (u32, u32) = (1024, 480);
+/// Target number of buckets across the charted span
+const CHART_BUCKETS: u64 = 120;
+
+struct Series {
+    label: String,
+    /// (seconds since chart start, events per second)
+    points: Vec<(f64, f64)>,
+    markers: Vec<(f64, &'static str)>,
+}
+
+/// Render change-rate lines for `region_ids` into `path` (.svg or .png)
+pub fn timeline_chart(watch: &MemWatch, region_ids: &[u32], path: impl AsRef<Path>) -> Result<(), String> {
+    let path = path.as_ref();
+    let series = collect_series(watch, region_ids);
+    let is_png = path
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("png"))
+        .unwrap_or(false);
+
+    if is_png {
+        render_png(path, &series)
+    } else {
+        let root = SVGBackend::new(path, CHART_SIZE).into_drawing_area();
+        draw(&root, &series)?;
+        root.present().map_err(|e| format!("Failed to write chart {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn render_png(path: &Path, series: &[Series]) -> Result<(), String> {
+    let root = BitMapBackend::new(path, CHART_SIZE).into_drawing_area();
+    draw(&root, series)?;
+    root.present().map_err(|e| format!("Failed to write chart {}: {}", path.display(), e))
+}
+
+/// plotters has no image encoder on wasm32
+#[cfg(target_arch = "wasm32")]
+fn render_png(path: &Path, _series: &[Series]) -> Result<(), String> {
+    Err(format!("Failed to write chart {}: PNG output is not supported on this target, use .svg", path.display()))
+}
+
+fn collect_series(watch: &MemWatch, region_ids: &[u32]) -> Vec<Series> {
+    let mut first_ns = u64::MAX;
+    let mut last_ns = 0;
+    for &region_id in region_ids {
+        // The span alone sizes the buckets; a query at base width would fill
+        // every empty millisecond in between
+        if let Some((first, last)) = watch.timeline_span(region_id) {
+            first_ns = first_ns.min(first);
+            last_ns = last_ns.max(last);
+        }
+        for (timestamp_ns, _) in watch.timeline_markers(region_id) {
+            first_ns = first_ns.min(timestamp_ns);
+            last_ns = last_ns.max(timestamp_ns);
+        }
+    }
+    if first_ns == u64::MAX {
+        first_ns = 0;
+    }
+
+    let bucket = Duration::from_nanos(((last_ns - first_ns) / CHART_BUCKETS).max(1_000_000));
+    let seconds = |ns: u64| ns.saturating_sub(first_ns) as f64 / 1e9;
+    let regions = watch.regions.lock().unwrap().clone();
+
+    region_ids
+        .iter()
+        .map(|&region_id| {
+            let label = regions
+                .get(&region_id)
+                .map(|info| info.name.clone())
+                .unwrap_or_else(|| format!("region {}", region_id));
+            let points = watch
+                .timeline(region_id, bucket)
+                .iter()
+                .map(|stats: &BucketStats| (seconds(stats.start_ns), stats.events as f64 / bucket.as_secs_f64()))
+                .collect();
+            let markers = watch
+                .timeline_markers(region_id)
+                .into_iter()
+                .map(|(timestamp_ns, kind)| (seconds(timestamp_ns), kind.as_str()))
+                .collect();
+            Series { label, points, markers }
+        })
+        .collect()
+}
+
+fn draw<DB: DrawingBackend>(root: &DrawingArea<DB, Shift>, series: &[Series]) -> Result<(), String> {
+    let err = |e: DrawingAreaErrorKind<DB::ErrorType>| format!("Failed to draw chart: {}", e);
+
+    let x_max = series
+        .iter()
+        .flat_map(|s| s.points.iter().map(|p| p.0).chain(s.markers.iter().map(|m| m.0)))
+        .fold(0.0, f64::max)
+        .max(1.0);
+    let y_max = series
+        .iter()
+        .flat_map(|s| s.points.iter().map(|p| p.1))
+        .fold(0.0, f64::max)
+        .max(1.0)
+        * 1.1;
+
+    root.fill(&WHITE).map_err(err)?;
+    let mut chart = ChartBuilder::on(root)
+        .caption("memwatch change rate", ("sans-serif", 20))
+        .margin(12)
+        .x_label_area_size(32)
+        .y_label_area_size(48)
+        .build_cartesian_2d(0.0..x_max, 0.0..y_max)
+        .map_err(err)?;
+    chart
+        .configure_mesh()
+        .x_desc("seconds")
+        .y_desc("changes/s")
+        .draw()
+        .map_err(err)?;
+
+    for (i, s) in series.iter().enumerate() {
+        let color = Palette99::pick(i).to_rgba();
+        chart
+            .draw_series(LineSeries::new(s.points.iter().copied(), color.stroke_width(2)))
+            .map_err(err)?
+            .label(s.label.clone())
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 16, y)], color));
+        for &(x, kind) in &s.markers {
+            chart
+                .draw_series(std::iter::once(PathElement::new(vec![(x, 0.0), (x, y_max)], color.mix(0.5))))
+                .map_err(err)?;
+            chart
+                .draw_series(std::iter::once(Text::new(kind, (x, y_max * 0.95), ("sans-serif", 12))))
+                .map_err(err)?;
+        }
+    }
+
+    chart
+        .configure_series_labels()
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()
+        .map_err(err)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_svg_contains_series_and_markers() {
+        let series = vec![Series {
+            label: "balance".to_string(),
+            points: vec![(0.0, 2.0), (1.0, 5.0), (2.0, 0.0)],
+            markers: vec![(0.0, "watched"), (2.0, "unwatched")],
+        }];
+        let mut svg = String::new();
+        {
+            let root = SVGBackend::with_string(&mut svg, CHART_SIZE).into_drawing_area();
+            draw(&root, &series).unwrap();
+            root.present().unwrap();
+        }
+        assert!(svg.contains("balance"));
+        assert!(svg.contains("unwatched"));
+    }
+}
@@ -0,0 +1,82 @@
+// Per-region sampling and rate limits
+//
+// WatchOptions::sample_rate keeps one change in N of a region (the first,
+// then every Nth), and WatchOptions::max_events_per_sec caps what is left
+// per one-second window. Changes dropped either way are counted in
+// Stats::sampled_out_count, apart from the ring's own drops. Markers are
+// never sampled.
+//
+// Sampling happens as the Rust layer takes changes from the backend, before
+// any other processing. The C core has no per-region sampling, so a sampled
+// region still takes ring slots; drain often (or raise the ring capacity)
+// when hot regions share a watcher with ones that matter.
+
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub(crate) struct Sampler {
+    sample_rate: u32,
+    max_per_sec: Option<u32>,
+    /// Changes offered so far
+    seen: u64,
+    window_start: Option<Instant>,
+    kept_in_window: u32,
+}
+
+impl Sampler {
+    /// None when neither setting limits anything
+    pub(crate) fn new(sample_rate: Option<u32>, max_per_sec: Option<u32>) -> Option<Sampler> {
+        let sample_rate = sample_rate.unwrap_or(1).max(1);
+        if sample_rate == 1 && max_per_sec.is_none() {
+            return None;
+        }
+        Some(Sampler {
+            sample_rate,
+            max_per_sec,
+            seen: 0,
+            window_start: None,
+            kept_in_window: 0,
+        })
+    }
+
+    /// Whether to keep the next change
+    pub(crate) fn keep(&mut self, now: Instant) -> bool {
+        self.seen += 1;
+        if !(self.seen - 1).is_multiple_of(self.sample_rate as u64) {
+            return false;
+        }
+        let Some(max) = self.max_per_sec else {
+            return true;
+        };
+        let start = *self.window_start.get_or_insert(now);
+        if now.duration_since(start) >= WINDOW {
+            self.window_start = Some(now);
+            self.kept_in_window = 0;
+        }
+        if self.kept_in_window >= max {
+            return false;
+        }
+        self.kept_in_window += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_rate_and_window_cap() {
+        assert!(Sampler::new(Some(1), None).is_none());
+        let now = Instant::now();
+        let mut sampler = Sampler::new(Some(3), None).unwrap();
+        let kept: Vec<bool> = (0..7).map(|_| sampler.keep(now)).collect();
+        assert_eq!(kept, [true, false, false, true, false, false, true]);
+
+        let mut sampler = Sampler::new(None, Some(2)).unwrap();
+        assert_eq!((0..5).filter(|_| sampler.keep(now)).count(), 2);
+        assert!(sampler.keep(now + WINDOW));
+    }
+}
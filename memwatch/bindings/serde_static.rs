@@ -0,0 +1,104 @@
+// Deserialize for types holding &'static str
+//
+// AllocationSite and AnomalyKind::Custom hold &'static str: source paths,
+// type and detector names known at compile time. serde's derive would only
+// deserialize them from 'static input, so they are read as owned strings
+// here and interned, each distinct string leaked once and shared after
+// that: reading many events costs memory per distinct name, not per event.
+// storage::SqliteStore interns the names it reads back the same way. Past
+// MAX_INTERNED distinct strings (input that never repeats, or a hostile
+// stream), new ones read as UNKNOWN instead of leaking more.
+
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+/// Distinct strings leaked at most
+const MAX_INTERNED: usize = 4096;
+
+/// Stands in for strings read once MAX_INTERNED are held
+const UNKNOWN: &str = "<unknown>";
+
+static INTERNED: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+
+/// The one leaked copy of `s`, or UNKNOWN when too many are held
+pub(crate) fn intern(s: &str) -> &'static str {
+    intern_in(&mut INTERNED.lock().unwrap_or_else(|e| e.into_inner()), s, MAX_INTERNED)
+}
+
+fn intern_in(interned: &mut BTreeSet<&'static str>, s: &str, max: usize) -> &'static str {
+    if let Some(&existing) = interned.get(s) {
+        return existing;
+    }
+    if interned.len() >= max {
+        return UNKNOWN;
+    }
+    let leaked: &'static str = Box::leak(s.to_string().into_boxed_str());
+    interned.insert(leaked);
+    leaked
+}
+
+#[cfg(feature = "serde")]
+mod de {
+    use serde::{Deserialize, Deserializer};
+
+    use super::intern;
+    use crate::anomaly::AnomalyKind;
+    use crate::AllocationSite;
+
+    /// AllocationSite with owned strings, as serialized
+    #[derive(Deserialize)]
+    #[serde(rename = "AllocationSite")]
+    struct Site {
+        file: String,
+        line: u32,
+        type_name: Option<String>,
+    }
+
+    impl<'de> Deserialize<'de> for AllocationSite {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let site = Site::deserialize(deserializer)?;
+            Ok(AllocationSite {
+                file: intern(&site.file),
+                line: site.line,
+                type_name: site.type_name.as_deref().map(intern),
+            })
+        }
+    }
+
+    /// AnomalyKind with an owned Custom name, as serialized
+    #[derive(Deserialize)]
+    #[serde(rename = "AnomalyKind")]
+    enum Kind {
+        ChangeRate,
+        NewWriter,
+        UnlockedWrite,
+        Custom(String),
+    }
+
+    impl<'de> Deserialize<'de> for AnomalyKind {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            Ok(match Kind::deserialize(deserializer)? {
+                Kind::ChangeRate => AnomalyKind::ChangeRate,
+                Kind::NewWriter => AnomalyKind::NewWriter,
+                Kind::UnlockedWrite => AnomalyKind::UnlockedWrite,
+                Kind::Custom(name) => AnomalyKind::Custom(intern(&name)),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interning_is_bounded() {
+        assert!(std::ptr::eq(intern("src/interned.rs"), intern("src/interned.rs")));
+
+        let mut interned = BTreeSet::new();
+        let names: Vec<&str> = (0..5).map(|i| intern_in(&mut interned, &format!("src/generated_{}.rs", i), 3)).collect();
+        assert_eq!(names, ["src/generated_0.rs", "src/generated_1.rs", "src/generated_2.rs", UNKNOWN, UNKNOWN]);
+        // Strings held already still resolve
+        assert_eq!(intern_in(&mut interned, "src/generated_1.rs", 3), "src/generated_1.rs");
+    }
+}
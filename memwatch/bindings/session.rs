@@ -0,0 +1,424 @@
+// Session bundles
+//
+// A session records one run into a single directory, conventionally named
+// `<name>.mwsession`, that can be attached to a bug report:
+//
+//   events.jsonl          process header, then exported events
+//   sql.jsonl             SQLChange::to_json() lines
+//   snapshots/<id>.bin    region contents when the session finished
+//   manifest.json         written last by Session::finish()
+//
+// The manifest lists every file with its schema, size and SHA-256:
+//
+//   {"format":"memwatch-session/1","created_ns":N,"finished_ns":N,
+//    "metadata":{"git_sha":".."},
+//    "files":[{"path":"events.jsonl","schema":"memwatch-event/1",
+//              "bytes":N,"sha256":".."},
+//             {"path":"snapshots/3.bin","schema":"raw","bytes":N,
+//              "sha256":"..","region_id":3,"region_name":".."}, ..]}
+//
+// "metadata" (application metadata, see MemWatch::set_metadata) is omitted
+// when none was set. SessionBundle::open() refuses bundles whose files do
+// not match it, so consumers never read a half-written or edited session. pack() writes the
+// bundle as one uncompressed tar archive (`tar xf` restores the directory).
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use sha2::{Digest, Sha256};
+
+use crate::export::{hex, json_str, ValueMode};
+use crate::json::JsonValue;
+use crate::lifecycle::now_ns;
+use crate::sink::EventSink;
+use crate::sql_tracker::SQLChange;
+use crate::{process, ChangeEvent, MemWatch};
+
+const FORMAT: &str = "memwatch-session/1";
+const MANIFEST: &str = "manifest.json";
+pub const EVENTS_FILE: &str = "events.jsonl";
+pub const SQL_FILE: &str = "sql.jsonl";
+pub const EVENT_SCHEMA: &str = "memwatch-event/1";
+pub const SQL_SCHEMA: &str = "memwatch-sql/1";
+
+/// A session being recorded; a sink for events, and cheap to clone
+#[derive(Clone)]
+pub struct Session {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    dir: PathBuf,
+    created_ns: u64,
+    events: BufWriter<File>,
+    /// Written with the first event, once add_sink() passed the metadata
+    header_written: bool,
+    metadata: Vec<(String, String)>,
+    sql: Option<BufWriter<File>>,
+    values: ValueMode,
+}
+
+impl Inner {
+    fn write_header(&mut self) -> Result<(), String> {
+        if !self.header_written {
+            self.header_written = true;
+            let header = process::current().to_json_with(&self.metadata);
+            writeln!(self.events, "{}", header).map_err(|e| format!("Failed to write session: {}", e))?;
+        }
+        Ok(())
+    }
+}
+
+/// One file of a finished bundle, as listed in its manifest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleFile {
+    /// Relative to the bundle directory
+    pub path: String,
+    pub schema: String,
+    pub bytes: u64,
+    pub sha256: String,
+    /// Set for snapshots
+    pub region_id: Option<u32>,
+    pub region_name: Option<String>,
+}
+
+/// A finished, verified session bundle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionBundle {
+    pub dir: PathBuf,
+    pub created_ns: u64,
+    pub finished_ns: u64,
+    /// Application metadata when the session finished, sorted by key
+    pub metadata: Vec<(String, String)>,
+    pub files: Vec<BundleFile>,
+}
+
+impl Session {
+    /// Start a session in `dir` (created if missing; must not hold a
+    /// finished bundle)
+    pub fn create(dir: impl AsRef<Path>) -> Result<Self, String> {
+        let dir = dir.as_ref().to_path_buf();
+        if dir.join(MANIFEST).exists() {
+            return Err(format!("{} already holds a finished session", dir.display()));
+        }
+        fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let path = dir.join(EVENTS_FILE);
+        let file = File::create(&path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        Ok(Session {
+            inner: Arc::new(Mutex::new(Inner {
+                dir,
+                created_ns: now_ns(),
+                events: BufWriter::new(file),
+                header_written: false,
+                metadata: Vec::new(),
+                sql: None,
+                values: ValueMode::Raw,
+            })),
+        })
+    }
+
+    /// Value representation of recorded events (e.g. hashed for sensitive
+    /// data)
+    pub fn with_value_mode(self, values: ValueMode) -> Self {
+        self.inner.lock().unwrap().values = values;
+        self
+    }
+
+    /// Add a SQL change to the session
+    pub fn record_sql(&self, change: &SQLChange) -> Result<(), String> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.sql.is_none() {
+            let path = inner.dir.join(SQL_FILE);
+            let file = File::create(&path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+            inner.sql = Some(BufWriter::new(file));
+        }
+        let sql = inner.sql.as_mut().unwrap();
+        writeln!(sql, "{}", change.to_json()).map_err(|e| format!("Failed to write session: {}", e))
+    }
+
+    /// Snapshot every live region of `watch`, write the manifest and return
+    /// the bundle. Events still pending in `watch` are not collected here;
+    /// call check_changes() first to include them.
+    pub fn finish(self, watch: &MemWatch) -> Result<SessionBundle, String> {
+        let mut inner = self.inner.lock().unwrap();
+        let dir = inner.dir.clone();
+        let mut metadata = watch.metadata();
+        if !inner.header_written {
+            inner.metadata = metadata.clone();
+        }
+        inner.write_header()?;
+        inner.events.flush().map_err(|e| format!("Failed to write session: {}", e))?;
+        if let Some(sql) = inner.sql.as_mut() {
+            sql.flush().map_err(|e| format!("Failed to write session: {}", e))?;
+        }
+
+        let mut files = vec![bundle_file(&dir, EVENTS_FILE, EVENT_SCHEMA)?];
+        if inner.sql.is_some() {
+            files.push(bundle_file(&dir, SQL_FILE, SQL_SCHEMA)?);
+        }
+
+        let mut regions: Vec<(u32, String)> = {
+            let registered = watch.regions.lock().unwrap();
+            registered.iter().filter(|(_, info)| !info.freed).map(|(&id, info)| (id, info.name.clone())).collect()
+        };
+        regions.sort_unstable();
+        fs::create_dir_all(dir.join("snapshots")).map_err(|e| format!("Failed to create snapshots: {}", e))?;
+        for (region_id, name) in regions {
+            let bytes = watch.peek(region_id)?;
+            let path = format!("snapshots/{}.bin", region_id);
+            fs::write(dir.join(&path), &bytes).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+            let mut file = bundle_file(&dir, &path, "raw")?;
+            file.region_id = Some(region_id);
+            file.region_name = Some(name);
+            files.push(file);
+        }
+
+        metadata.sort();
+        let bundle = SessionBundle {
+            dir,
+            created_ns: inner.created_ns,
+            finished_ns: now_ns(),
+            metadata,
+            files,
+        };
+        fs::write(bundle.dir.join(MANIFEST), bundle.manifest_json())
+            .map_err(|e| format!("Failed to write manifest: {}", e))?;
+        Ok(bundle)
+    }
+}
+
+impl EventSink for Session {
+    fn write(&mut self, event: &ChangeEvent) -> Result<(), String> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.write_header()?;
+        let line = inner.values.export(event).to_json();
+        writeln!(inner.events, "{}", line).map_err(|e| format!("Failed to write session: {}", e))
+    }
+
+    fn metadata(&mut self, metadata: &[(String, String)]) {
+        self.inner.lock().unwrap().metadata = metadata.to_vec();
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.write_header()?;
+        inner.events.flush().map_err(|e| format!("Failed to write session: {}", e))
+    }
+}
+
+impl SessionBundle {
+    /// Read a bundle's manifest and check every listed file against it
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, String> {
+        let dir = dir.as_ref().to_path_buf();
+        let path = dir.join(MANIFEST);
+        let text = fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let bad = || format!("Malformed manifest {}", path.display());
+        let manifest = JsonValue::parse(&text).ok_or_else(bad)?;
+        let format = manifest.get("format").and_then(JsonValue::as_str).ok_or_else(bad)?;
+        if format != FORMAT {
+            return Err(format!("Unsupported session format {}", format));
+        }
+        let files = match manifest.get("files") {
+            Some(JsonValue::Array(files)) => files.iter().map(parse_file).collect::<Option<Vec<_>>>().ok_or_else(bad)?,
+            _ => return Err(bad()),
+        };
+        let metadata = match manifest.get("metadata") {
+            Some(JsonValue::Object(fields)) => fields
+                .iter()
+                .map(|(key, value)| value.as_str().map(|value| (key.clone(), value.to_string())))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(bad)?,
+            None => Vec::new(),
+            Some(_) => return Err(bad()),
+        };
+        let bundle = SessionBundle {
+            created_ns: manifest.get("created_ns").and_then(JsonValue::as_u64).ok_or_else(bad)?,
+            finished_ns: manifest.get("finished_ns").and_then(JsonValue::as_u64).ok_or_else(bad)?,
+            dir,
+            metadata,
+            files,
+        };
+        for file in &bundle.files {
+            let actual = bundle_file(&bundle.dir, &file.path, &file.schema)?;
+            if (actual.bytes, &actual.sha256) != (file.bytes, &file.sha256) {
+                return Err(format!("{} does not match the session manifest", file.path));
+            }
+        }
+        Ok(bundle)
+    }
+
+    /// A listed file by path, e.g. SQL_FILE
+    pub fn file(&self, path: &str) -> Option<&BundleFile> {
+        self.files.iter().find(|file| file.path == path)
+    }
+
+    /// Region contents when the session finished
+    pub fn snapshot(&self, region_id: u32) -> Result<Option<Vec<u8>>, String> {
+        let Some(file) = self.files.iter().find(|file| file.region_id == Some(region_id)) else {
+            return Ok(None);
+        };
+        fs::read(self.dir.join(&file.path)).map(Some).map_err(|e| format!("Failed to read {}: {}", file.path, e))
+    }
+
+    /// Write the bundle, manifest included, as one tar archive whose entries
+    /// sit under the bundle's directory name
+    pub fn pack(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        let root = self.dir.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_else(|| "session".to_string());
+        let mut out = Vec::new();
+        let entries = self.files.iter().map(|file| file.path.as_str()).chain([MANIFEST]);
+        for entry in entries {
+            let contents = fs::read(self.dir.join(entry)).map_err(|e| format!("Failed to read {}: {}", entry, e))?;
+            tar_entry(&mut out, &format!("{}/{}", root, entry), &contents)?;
+        }
+        // Two zero blocks end the archive
+        out.resize(out.len() + 1024, 0);
+        fs::write(path, out).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    fn manifest_json(&self) -> String {
+        let files: Vec<String> = self
+            .files
+            .iter()
+            .map(|file| {
+                let region = match (file.region_id, &file.region_name) {
+                    (Some(id), Some(name)) => format!(",\"region_id\":{},\"region_name\":{}", id, json_str(name)),
+                    _ => String::new(),
+                };
+                format!(
+                    "{{\"path\":{},\"schema\":{},\"bytes\":{},\"sha256\":\"{}\"{}}}",
+                    json_str(&file.path),
+                    json_str(&file.schema),
+                    file.bytes,
+                    file.sha256,
+                    region
+                )
+            })
+            .collect();
+        format!(
+            "{{\"format\":\"{}\",\"created_ns\":{},\"finished_ns\":{}{},\"files\":[{}]}}\n",
+            FORMAT,
+            self.created_ns,
+            self.finished_ns,
+            process::metadata_json(&self.metadata).map(|m| format!(",\"metadata\":{}", m)).unwrap_or_default(),
+            files.join(",")
+        )
+    }
+}
+
+fn bundle_file(dir: &Path, path: &str, schema: &str) -> Result<BundleFile, String> {
+    let contents = fs::read(dir.join(path)).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    Ok(BundleFile {
+        path: path.to_string(),
+        schema: schema.to_string(),
+        bytes: contents.len() as u64,
+        sha256: hex(&Sha256::digest(&contents)),
+        region_id: None,
+        region_name: None,
+    })
+}
+
+fn parse_file(value: &JsonValue) -> Option<BundleFile> {
+    let text = |key| value.get(key).and_then(JsonValue::as_str).map(str::to_string);
+    let path = text("path")?;
+    // Listed paths stay inside the bundle
+    if path.starts_with('/') || path.split('/').any(|part| part == "..") {
+        return None;
+    }
+    Some(BundleFile {
+        path,
+        schema: text("schema")?,
+        bytes: value.get("bytes")?.as_u64()?,
+        sha256: text("sha256")?,
+        region_id: value.get("region_id").and_then(JsonValue::as_u64).and_then(|id| u32::try_from(id).ok()),
+        region_name: text("region_name"),
+    })
+}
+
+/// Append one ustar entry (header plus contents padded to 512 bytes)
+fn tar_entry(out: &mut Vec<u8>, name: &str, contents: &[u8]) -> Result<(), String> {
+    if name.len() > 100 {
+        return Err(format!("Path too long for tar: {}", name));
+    }
+    let mut header = [0u8; 512];
+    let mut put = |offset: usize, field: &[u8]| header[offset..offset + field.len()].copy_from_slice(field);
+    put(0, name.as_bytes());
+    put(100, b"0000644\0");
+    put(108, b"0000000\0");
+    put(116, b"0000000\0");
+    put(124, format!("{:011o}\0", contents.len()).as_bytes());
+    put(136, format!("{:011o}\0", now_ns() / 1_000_000_000).as_bytes());
+    put(148, b"        ");
+    put(156, b"0");
+    put(257, b"ustar\x0000");
+    let checksum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    out.extend_from_slice(&header);
+    out.extend_from_slice(contents);
+    out.resize(out.len().next_multiple_of(512), 0);
+    Ok(())
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native;
+    use crate::sql_tracker::{SQLOperation, ValueSource};
+
+    #[test]
+    fn test_finish_writes_verifiable_bundle() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Protect;
+        let dir = std::env::temp_dir().join(format!("memwatch_session_{}.mwsession", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        watcher.set_metadata("git_sha", "0c1d2e3");
+        let session = Session::create(&dir).unwrap();
+        watcher.add_sink(session.clone());
+        let balance = vec![5u8; 4];
+        let id = watcher.watch(&balance, "balance").unwrap();
+        watcher.check_changes().unwrap();
+        session
+            .record_sql(&SQLChange {
+                timestamp_ns: 1,
+                table_name: "accounts".to_string(),
+                column_name: "balance".to_string(),
+                operation: SQLOperation::Update,
+                old_value: None,
+                new_value: None,
+                rows_affected: 1,
+                database: None,
+                full_query: "UPDATE accounts SET balance = 0".to_string(),
+                source: ValueSource::Caller,
+                duration_ns: None,
+                params: Vec::new(),
+                column_type: None,
+            })
+            .unwrap();
+        let bundle = session.finish(&watcher).unwrap();
+        assert!(Session::create(&dir).is_err());
+
+        let opened = SessionBundle::open(&dir).unwrap();
+        assert_eq!(opened, bundle);
+        assert_eq!(opened.snapshot(id).unwrap(), Some(balance.clone()));
+        assert_eq!(opened.file(SQL_FILE).map(|f| f.schema.as_str()), Some(SQL_SCHEMA));
+        let events = fs::read_to_string(dir.join(EVENTS_FILE)).unwrap();
+        assert!(events.starts_with("{\"process\":") && events.contains("\"kind\":\"watched\""));
+        assert!(events.lines().next().unwrap().contains("\"metadata\":{\"git_sha\":\"0c1d2e3\"}"));
+        assert!(opened.metadata.contains(&("git_sha".to_string(), "0c1d2e3".to_string())));
+
+        let tar = dir.with_extension("tar");
+        opened.pack(&tar).unwrap();
+        assert_eq!(fs::metadata(&tar).unwrap().len() % 512, 0);
+
+        fs::write(dir.join(SQL_FILE), "{}\n").unwrap();
+        assert!(SessionBundle::open(&dir).unwrap_err().contains("sql.jsonl"));
+
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_file(&tar);
+    }
+}
@@ -0,0 +1,205 @@
+// Copy-on-write shadow pages
+//
+// The native ring only carries previews, so when several writes hit the same
+// page between two drains the intermediate old values are lost. With shadow
+// pages enabled the Rust layer keeps a copy of every watched page as of the
+// last drain and rebuilds each batch per region: the first event's old value
+// comes from the shadow, each later event starts from the previous one's new
+// value, and the last event ends at the live contents. Shadows are refreshed
+// after every drain, i.e. before the first write of the next batch.
+//
+// Previews cover only the start of a region, so a write past them leaves a
+// rebuilt value equal to the one before it. Each change's old and new
+// values are compared in full, byte for byte, before it is emitted; one
+// that differs nowhere is dropped and its writes are counted in the
+// region's next change, whose values take in what it wrote.
+
+use std::collections::HashMap;
+
+use crate::lifecycle::RegionInfo;
+use crate::{ChangeEvent, EventSource};
+
+pub(crate) struct ShadowPages {
+    page_size: usize,
+    pages: HashMap<usize, Vec<u8>>,
+    refs: HashMap<usize, usize>,
+}
+
+impl ShadowPages {
+    pub(crate) fn new(page_size: usize) -> Self {
+        ShadowPages {
+            page_size,
+            pages: HashMap::new(),
+            refs: HashMap::new(),
+        }
+    }
+
+    fn page_range(&self, addr: u64, size: usize) -> impl Iterator<Item = usize> {
+        let page_size = self.page_size;
+        let first = addr as usize / page_size * page_size;
+        let end = addr as usize + size.max(1);
+        (first..end).step_by(page_size)
+    }
+
+    fn copy_page(&self, page: usize) -> Vec<u8> {
+        // A page is mapped as a whole, so reading outside the region is safe
+        (0..self.page_size)
+            .map(|i| unsafe { std::ptr::read_volatile((page + i) as *const u8) })
+            .collect()
+    }
+
+    pub(crate) fn add_region(&mut self, addr: u64, size: usize) {
+        for page in self.page_range(addr, size).collect::<Vec<_>>() {
+            *self.refs.entry(page).or_insert(0) += 1;
+            if !self.pages.contains_key(&page) {
+                let copy = self.copy_page(page);
+                self.pages.insert(page, copy);
+            }
+        }
+    }
+
+    pub(crate) fn remove_region(&mut self, addr: u64, size: usize) {
+        for page in self.page_range(addr, size).collect::<Vec<_>>() {
+            if let Some(count) = self.refs.get_mut(&page) {
+                *count -= 1;
+                if *count == 0 {
+                    self.refs.remove(&page);
+                    self.pages.remove(&page);
+                }
+            }
+        }
+    }
+
+    /// Region bytes as of the last drain
+    fn read(&self, addr: u64, size: usize) -> Vec<u8> {
+        let addr = addr as usize;
+        (addr..addr + size)
+            .map(|a| {
+                let page = a / self.page_size * self.page_size;
+                self.pages.get(&page).map(|p| p[a - page]).unwrap_or(0)
+            })
+            .collect()
+    }
+
+    /// Copy the region's pages again, e.g. after writes nobody reported
+    pub(crate) fn refresh(&mut self, addr: u64, size: usize) {
+        for page in self.page_range(addr, size).collect::<Vec<_>>() {
+            let copy = self.copy_page(page);
+            self.pages.insert(page, copy);
+        }
+    }
+
+    /// Fill exact old/new values for a drained batch, drop changes that
+    /// changed nothing and refresh the shadows
+    pub(crate) fn apply(&mut self, events: &mut Vec<ChangeEvent>, regions: &HashMap<u32, RegionInfo>) {
+        let mut last_new: HashMap<u32, (usize, Vec<u8>)> = HashMap::new();
+        for (index, event) in events.iter_mut().enumerate() {
+            if event.kind.is_marker() {
+                continue;
+            }
+            let Some(info) = regions.get(&event.region_id) else {
+                continue;
+            };
+            // Pokes carry exact values; later writes start from them
+            if event.source == EventSource::Poke {
+                last_new.insert(event.region_id, (index, event.new_value.clone()));
+                continue;
+            }
+            let old = match last_new.remove(&event.region_id) {
+                Some((_, previous)) => previous,
+                None => self.read(info.addr, info.size),
+            };
+            let mut new = old.clone();
+            let n = event.new_preview.len().min(new.len());
+            new[..n].copy_from_slice(&event.new_preview[..n]);
+            event.old_value = old;
+            event.new_value = new.clone();
+            last_new.insert(event.region_id, (index, new));
+        }
+
+        // The last event of each region ends at the live contents
+        for (region_id, (index, _)) in last_new {
+            let info = &regions[&region_id];
+            let live = unsafe { std::slice::from_raw_parts(info.addr as *const u8, info.size) };
+            events[index].new_value = live.to_vec();
+            self.refresh(info.addr, info.size);
+        }
+
+        let mut unreported: HashMap<u32, u32> = HashMap::new();
+        events.retain_mut(|event| {
+            if event.kind.is_marker() || event.source == EventSource::Poke || !regions.contains_key(&event.region_id) {
+                return true;
+            }
+            if event.old_value == event.new_value {
+                *unreported.entry(event.region_id).or_insert(0) += event.write_count;
+                return false;
+            }
+            if let Some(writes) = unreported.remove(&event.region_id) {
+                event.write_count = event.write_count.saturating_add(writes);
+            }
+            true
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::marker;
+    use crate::EventKind;
+
+    #[test]
+    fn test_batch_gets_chained_exact_values() {
+        let mut data = vec![0u8; 16];
+        let addr = data.as_ptr() as u64;
+        let mut regions = HashMap::new();
+        regions.insert(1, RegionInfo { name: "buf".to_string(), addr, size: 16, site: None, max_value_bytes: 256, freed: false, page_size: 4096, tags: Vec::new(), sampler: None, coalesce: None, paused: false });
+
+        let mut shadow = ShadowPages::new(4096);
+        shadow.add_region(addr, 16);
+
+        data[0] = 1;
+        data[0] = 2;
+        let mut first = marker(1, "buf", EventKind::Change);
+        first.new_preview = vec![1];
+        let mut second = marker(1, "buf", EventKind::Change);
+        second.new_preview = vec![2];
+        let mut batch = vec![first, second];
+
+        shadow.apply(&mut batch, &regions);
+        assert_eq!(batch[0].old_value[0], 0);
+        assert_eq!(batch[0].new_value[0], 1);
+        assert_eq!(batch[1].old_value[0], 1);
+        assert_eq!(batch[1].new_value, data);
+    }
+
+    #[test]
+    fn test_changes_past_the_preview_are_compared_in_full() {
+        let mut data = [0u8; 16];
+        let addr = data.as_ptr() as u64;
+        let mut regions = HashMap::new();
+        regions.insert(1, RegionInfo { name: "buf".to_string(), addr, size: 16, site: None, max_value_bytes: 256, freed: false, page_size: 4096, tags: Vec::new(), sampler: None, coalesce: None, paused: false });
+
+        let mut shadow = ShadowPages::new(4096);
+        shadow.add_region(addr, 16);
+
+        // Two writes to the last byte; the 8-byte previews show neither
+        data[15] = 1;
+        data[15] = 2;
+        let change = || {
+            let mut event = marker(1, "buf", EventKind::Change);
+            event.new_preview = vec![0; 8];
+            event
+        };
+        let mut batch = vec![change(), change()];
+        shadow.apply(&mut batch, &regions);
+        assert_eq!(batch.len(), 1);
+        assert_eq!((batch[0].old_value[15], batch[0].write_count), (0, 2));
+        assert_eq!(batch[0].new_value, data);
+
+        // A reported write that left the bytes as they were is no change
+        let mut batch = vec![change()];
+        shadow.apply(&mut batch, &regions);
+        assert!(batch.is_empty());
+    }
+}
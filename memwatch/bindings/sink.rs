@@ -0,0 +1,20 @@
+// Event sinks
+//
+// Sinks receive every event check_changes() returns, after sequencing, in
+// the order they were added.
+
+use crate::ChangeEvent;
+
+/// Destination for delivered events (files, audit logs, network, ...)
+pub trait EventSink: Send {
+    fn write(&mut self, event: &ChangeEvent) -> Result<(), String>;
+
+    /// Application metadata of the watcher the sink is added to, passed by
+    /// add_sink() before any event; sinks with a header record put it there
+    fn metadata(&mut self, _metadata: &[(String, String)]) {}
+
+    /// Called once after each delivered batch
+    fn flush(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+// Region snapshots
+//
+// snapshot() copies the full contents of a region at a checkpoint,
+// independently of the event stream (nothing is consumed or reported), and
+// Snapshot::diff() compares two of them byte range by byte range (see the
+// diff module). restore() writes a snapshot back through poke(), so the
+// restore shows up in the stream as one poked change.
+//
+// save() writes one file: a JSON header line, then the raw contents
+//
+//   {"format":"memwatch-snapshot/1","region_id":3,"name":"balance",
+//    "addr":N,"taken_ns":N,"bytes":N,"sha256":".."}\n<bytes>
+//
+// and load() refuses files whose contents do not match the header.
+
+use std::fs;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use crate::diff::{self, ByteRange};
+use crate::export::{hex, json_str};
+use crate::json::JsonValue;
+use crate::lifecycle::now_ns;
+use crate::MemWatch;
+
+const FORMAT: &str = "memwatch-snapshot/1";
+
+/// Contents of a region at one point in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub region_id: u32,
+    pub name: String,
+    /// Address of the region when the snapshot was taken
+    pub addr: u64,
+    pub taken_ns: u64,
+    pub bytes: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Byte ranges that differ from `self` to `other`; a size change shows
+    /// as a range past the shorter snapshot
+    pub fn diff(&self, other: &Snapshot) -> Vec<ByteRange> {
+        diff::compute_delta(&self.bytes, &other.bytes)
+    }
+
+    /// Write the snapshot to `path`, replacing any file there
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        let header = format!(
+            "{{\"format\":{},\"region_id\":{},\"name\":{},\"addr\":{},\"taken_ns\":{},\"bytes\":{},\"sha256\":{}}}\n",
+            json_str(FORMAT),
+            self.region_id,
+            json_str(&self.name),
+            self.addr,
+            self.taken_ns,
+            self.bytes.len(),
+            json_str(&hex(&Sha256::digest(&self.bytes)))
+        );
+        let mut out = header.into_bytes();
+        out.extend_from_slice(&self.bytes);
+        fs::write(path, out).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// Read a snapshot written by save()
+    pub fn load(path: impl AsRef<Path>) -> Result<Snapshot, String> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let bad = || format!("Malformed snapshot {}", path.display());
+        let split = data.iter().position(|&b| b == b'\n').ok_or_else(bad)?;
+        let header = std::str::from_utf8(&data[..split]).ok().and_then(JsonValue::parse).ok_or_else(bad)?;
+        let format = header.get("format").and_then(JsonValue::as_str).ok_or_else(bad)?;
+        if format != FORMAT {
+            return Err(format!("Unsupported snapshot format {}", format));
+        }
+        let number = |key: &str| header.get(key).and_then(JsonValue::as_u64).ok_or_else(bad);
+        let bytes = data[split + 1..].to_vec();
+        let sha256 = header.get("sha256").and_then(JsonValue::as_str).ok_or_else(bad)?;
+        if number("bytes")? != bytes.len() as u64 || sha256 != hex(&Sha256::digest(&bytes)) {
+            return Err(format!("{} does not match its header", path.display()));
+        }
+        Ok(Snapshot {
+            region_id: u32::try_from(number("region_id")?).map_err(|_| bad())?,
+            name: header.get("name").and_then(JsonValue::as_str).ok_or_else(bad)?.to_string(),
+            addr: number("addr")?,
+            taken_ns: number("taken_ns")?,
+            bytes,
+        })
+    }
+}
+
+impl MemWatch {
+    /// Copy the current contents of a region; see the snapshot module
+    pub fn snapshot(&self, region_id: u32) -> Result<Snapshot, String> {
+        let bytes = self.peek(region_id)?;
+        let regions = self.regions.lock().unwrap();
+        let info = regions.get(&region_id).ok_or_else(|| format!("Unknown region {}", region_id))?;
+        Ok(Snapshot {
+            region_id,
+            name: info.name.clone(),
+            addr: info.addr,
+            taken_ns: now_ns(),
+            bytes,
+        })
+    }
+
+    /// Write a snapshot back into its region (which must still have the
+    /// snapshot's size), reported as a poked change
+    ///
+    /// # Safety
+    ///
+    /// As for poke(): the region must be writable and not borrowed.
+    #[track_caller]
+    pub unsafe fn restore(&self, snapshot: &Snapshot) -> Result<(), String> {
+        let size = self.peek(snapshot.region_id)?.len();
+        if size != snapshot.bytes.len() {
+            return Err(format!(
+                "Snapshot of {} bytes does not fit region {} ({} bytes)",
+                snapshot.bytes.len(),
+                snapshot.region_id,
+                size
+            ));
+        }
+        self.poke(snapshot.region_id, 0, &snapshot.bytes)
+    }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native;
+    use crate::EventSource;
+
+    #[test]
+    fn test_snapshot_diff_save_and_restore() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        let mut table = vec![0u8; 256];
+        let id = watcher.watch(&table, "table").unwrap();
+
+        let before = watcher.snapshot(id).unwrap();
+        table[4..6].fill(9);
+        table[200] = 1;
+        let after = watcher.snapshot(id).unwrap();
+        assert_eq!(before.diff(&after), [ByteRange { offset: 4, len: 2 }, ByteRange { offset: 200, len: 1 }]);
+
+        let path = std::env::temp_dir().join(format!("memwatch_snapshot_{}.bin", std::process::id()));
+        before.save(&path).unwrap();
+        assert_eq!(Snapshot::load(&path).unwrap(), before);
+        let mut data = fs::read(&path).unwrap();
+        *data.last_mut().unwrap() ^= 1;
+        fs::write(&path, data).unwrap();
+        assert!(Snapshot::load(&path).unwrap_err().contains("does not match"));
+        let _ = fs::remove_file(&path);
+
+        watcher.check_changes().unwrap();
+        // SAFETY: `table` is not borrowed during the restore
+        unsafe { watcher.restore(&before) }.unwrap();
+        assert_eq!(table[4], 0);
+        let events = watcher.check_changes().unwrap();
+        assert_eq!(events.iter().filter(|e| e.source == EventSource::Poke).count(), 1);
+        assert!(unsafe { watcher.restore(&Snapshot { bytes: vec![0; 3], ..before }) }.is_err());
+    }
+}
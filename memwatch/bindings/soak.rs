@@ -0,0 +1,245 @@
+// Soak mode for long runs
+//
+// Instrumentation left on for days to catch a rare corruption has to keep
+// itself in check. enable_soak_mode(interval) makes check_changes() report
+// its self-stats once per interval:
+//
+//   watcher.enable_soak_mode(Duration::from_secs(60));
+//   watcher.on_soak_report(|report| eprintln!("memwatch soak: {}", report));
+//   // memwatch soak: up 3600s, 1520 events, ring pressure 0.00% (0 dropped),
+//   //   storage 12582912 bytes (+4096), sink lag 0ms, sampling 1 in 1
+//
+// and, when an interval goes over a limit (more than MAX_RING_PRESSURE of
+// the ring's events dropped, storage growing by more than
+// MAX_STORAGE_GROWTH, or sinks failing for longer than the interval),
+// doubles the soak sampling rate: from then on only one change in N is
+// kept, on top of any per-region sampling, and the rest is counted in
+// Stats::sampled_out_count. The rate only goes up, to MAX_SAMPLE_RATE;
+// enabling soak mode again starts over at 1 in 1.
+//
+// Reports go to the on_soak_report() handler and to `tracing` at info level
+// (feature `tracing`); soak_reports() keeps the last few. Nothing runs between checks, so the
+// interval is a minimum: a watcher checked once an hour reports hourly.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::{MemWatch, Stats};
+
+/// Share of the ring's events dropped in an interval that tightens sampling
+pub const MAX_RING_PRESSURE: f64 = 0.01;
+/// Storage growth in an interval that tightens sampling
+pub const MAX_STORAGE_GROWTH: u64 = 64 << 20;
+/// Tightest soak sampling: one change in this many
+pub const MAX_SAMPLE_RATE: u32 = 1024;
+/// Reports kept for soak_reports()
+const KEPT_REPORTS: usize = 64;
+
+/// See MemWatch::on_soak_report()
+pub(crate) type SoakHandler = Box<dyn Fn(&SoakReport) + Send>;
+
+/// Self-stats over one soak interval
+#[derive(Debug, Clone, PartialEq)]
+pub struct SoakReport {
+    /// Since soak mode was enabled
+    pub uptime: Duration,
+    /// Events the backend produced in the interval
+    pub events: u64,
+    /// Share of the ring's events dropped in the interval, 0.0-1.0
+    pub ring_pressure: f64,
+    pub ring_drops: u64,
+    pub storage_bytes: u64,
+    /// Storage added in the interval (0 if it shrank)
+    pub storage_growth: u64,
+    /// How long sink delivery has been failing
+    pub sink_lag: Duration,
+    /// Soak sampling after this report: one change in N
+    pub sample_rate: u32,
+    /// Whether this interval went over a limit and doubled the rate
+    pub tightened: bool,
+}
+
+impl fmt::Display for SoakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "up {}s, {} events, ring pressure {:.2}% ({} dropped), storage {} bytes (+{}), sink lag {}ms, sampling 1 in {}",
+            self.uptime.as_secs(),
+            self.events,
+            self.ring_pressure * 100.0,
+            self.ring_drops,
+            self.storage_bytes,
+            self.storage_growth,
+            self.sink_lag.as_millis(),
+            self.sample_rate
+        )?;
+        if self.tightened {
+            write!(f, " (tightened)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Counters at the start of an interval
+struct Baseline {
+    at: Instant,
+    total_events: u64,
+    ring_writes: u64,
+    ring_drops: u64,
+    storage_bytes: u64,
+}
+
+pub(crate) struct Soak {
+    interval: Duration,
+    started: Instant,
+    baseline: Option<Baseline>,
+    sample_rate: u32,
+    /// Changes offered to keep()
+    seen: u64,
+    reports: VecDeque<SoakReport>,
+}
+
+impl Soak {
+    pub(crate) fn new(interval: Duration, now: Instant) -> Self {
+        Soak { interval, started: now, baseline: None, sample_rate: 1, seen: 0, reports: VecDeque::new() }
+    }
+
+    /// Whether observe() has something to do
+    pub(crate) fn due(&self, now: Instant) -> bool {
+        self.baseline.as_ref().is_none_or(|baseline| now.saturating_duration_since(baseline.at) >= self.interval)
+    }
+
+    /// Close the interval at `now`; None for the first sample, which only
+    /// sets the baseline
+    pub(crate) fn observe(&mut self, stats: &Stats, sink_lag: Duration, now: Instant) -> Option<SoakReport> {
+        let next = Baseline {
+            at: now,
+            total_events: stats.total_events,
+            ring_writes: stats.ring_write_count,
+            ring_drops: stats.ring_drop_count,
+            storage_bytes: stats.storage_bytes_used,
+        };
+        // Counters restart with the backend (watchdog restarts)
+        let delta = |now: u64, then: u64| now.checked_sub(then).unwrap_or(now);
+        let last = self.baseline.replace(next)?;
+        let ring_writes = delta(stats.ring_write_count, last.ring_writes);
+        let ring_drops = delta(stats.ring_drop_count, last.ring_drops);
+        let ring_pressure = if ring_drops == 0 { 0.0 } else { ring_drops as f64 / ring_writes.max(ring_drops) as f64 };
+        let storage_growth = stats.storage_bytes_used.saturating_sub(last.storage_bytes);
+        let tightened = (ring_pressure > MAX_RING_PRESSURE || storage_growth > MAX_STORAGE_GROWTH || sink_lag > self.interval)
+            && self.sample_rate < MAX_SAMPLE_RATE;
+        if tightened {
+            self.sample_rate = (self.sample_rate * 2).min(MAX_SAMPLE_RATE);
+        }
+        let report = SoakReport {
+            uptime: now.saturating_duration_since(self.started),
+            events: delta(stats.total_events, last.total_events),
+            ring_pressure,
+            ring_drops,
+            storage_bytes: stats.storage_bytes_used,
+            storage_growth,
+            sink_lag,
+            sample_rate: self.sample_rate,
+            tightened,
+        };
+        if self.reports.len() == KEPT_REPORTS {
+            self.reports.pop_front();
+        }
+        self.reports.push_back(report.clone());
+        Some(report)
+    }
+
+    /// Whether to keep the next change
+    pub(crate) fn keep(&mut self) -> bool {
+        self.seen += 1;
+        (self.seen - 1).is_multiple_of(self.sample_rate as u64)
+    }
+}
+
+impl MemWatch {
+    /// Report self-stats every `interval` and tighten sampling under pressure;
+    /// see the soak module
+    pub fn enable_soak_mode(&self, interval: Duration) {
+        *self.soak.lock().unwrap() = Some(Soak::new(interval, Instant::now()));
+    }
+
+    /// Call `handler` with every soak report, on the thread running
+    /// check_changes(); replaces the previous handler
+    pub fn on_soak_report(&self, handler: impl Fn(&SoakReport) + Send + 'static) {
+        *self.soak_handler.lock().unwrap() = Some(Box::new(handler));
+    }
+
+    /// Stop soak reporting and soak sampling
+    pub fn disable_soak_mode(&self) {
+        *self.soak.lock().unwrap() = None;
+    }
+
+    /// The last soak reports, oldest first; empty unless enabled
+    pub fn soak_reports(&self) -> Vec<SoakReport> {
+        self.soak.lock().unwrap().as_ref().map(|soak| soak.reports.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    /// Report the soak interval if one has passed
+    pub(crate) fn soak_tick(&self) {
+        let now = Instant::now();
+        if !self.soak.lock().unwrap().as_ref().is_some_and(|soak| soak.due(now)) {
+            return;
+        }
+        let Ok(stats) = self.backend().stats() else {
+            return;
+        };
+        let sink_lag = self.sink_failing_since.lock().unwrap().map(|since| since.elapsed()).unwrap_or_default();
+        let Some(report) = self.soak.lock().unwrap().as_mut().and_then(|soak| soak.observe(&stats, sink_lag, now)) else {
+            return;
+        };
+        #[cfg(feature = "tracing")]
+        tracing::info!(target: "memwatch::soak", "{}", report);
+        if let Some(handler) = self.soak_handler.lock().unwrap().as_ref() {
+            handler(&report);
+        }
+    }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native::{self, FakeEvent};
+    use crate::EventKind;
+
+    #[test]
+    fn test_soak_reports_and_tightens_on_drops() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Protect;
+        let buffer = [0u8; 4];
+        let region = watcher.watch(&buffer, "hot").unwrap();
+        watcher.enable_soak_mode(Duration::ZERO);
+        let handled = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = std::sync::Arc::clone(&handled);
+        watcher.on_soak_report(move |report| sink.lock().unwrap().push(report.clone()));
+        watcher.check_changes().unwrap();
+        assert!(watcher.soak_reports().is_empty());
+
+        watcher.check_changes().unwrap();
+        fake_native::state().ring_drops = 10;
+        watcher.check_changes().unwrap();
+        let reports = watcher.soak_reports();
+        assert_eq!(reports.len(), 2);
+        assert_eq!((reports[0].ring_drops, reports[0].sample_rate, reports[0].tightened), (0, 1, false));
+        assert_eq!((reports[1].ring_drops, reports[1].ring_pressure, reports[1].sample_rate), (10, 1.0, 2));
+        assert!(reports[1].to_string().contains("ring pressure 100.00% (10 dropped)"), "{}", reports[1]);
+        assert_eq!(*handled.lock().unwrap(), reports);
+
+        // One change in two from here on
+        fake_native::state().ring_drops = 0;
+        fake_native::state().events.extend((0..4).map(|i| FakeEvent::change(region, &[i])));
+        let kept = watcher.check_changes().unwrap().iter().filter(|e| e.kind == EventKind::Change).count();
+        assert_eq!(kept, 2);
+        assert_eq!(watcher.get_stats().unwrap().sampled_out_count, 2);
+
+        watcher.disable_soak_mode();
+        assert!(watcher.soak_reports().is_empty());
+    }
+}
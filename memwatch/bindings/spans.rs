@@ -0,0 +1,173 @@
+// `tracing` integration (feature `tracing`)
+//
+// check_changes() tags every event with the span current where it runs
+// (ChangeEvent::span_id, span_name), so a change can be traced back to the
+// request or job whose span was active when it was delivered:
+//
+//   let _guard = tracing::info_span!("handle_order", id = 17).entered();
+//   for event in watcher.check_changes()? {
+//       assert_eq!(event.span_name.as_deref(), Some("handle_order"));
+//   }
+//
+// Events that already carry a span (replayed ones) keep it. Changes are
+// reported when polled, so the span is the delivering one: poll inside the
+// span of the work that writes, or with watch_with_callback() handlers.
+//
+// TracingSink goes the other way and logs delivered events through
+// `tracing` (target "memwatch"), inside the same span: changes and
+// lifecycle markers at INFO, markers reporting a problem at WARN.
+
+use crate::export::hex;
+use crate::sink::EventSink;
+use crate::{ChangeEvent, EventKind};
+
+/// Tag untagged events with the current span, if there is one
+pub(crate) fn tag(events: &mut [ChangeEvent]) {
+    let span = tracing::Span::current();
+    let (Some(id), Some(metadata)) = (span.id(), span.metadata()) else {
+        return;
+    };
+    for event in events.iter_mut().filter(|e| e.span_id.is_none()) {
+        event.span_id = Some(id.into_u64());
+        event.span_name = Some(metadata.name().to_string());
+    }
+}
+
+/// Sink logging events as `tracing` events
+#[derive(Debug, Default)]
+pub struct TracingSink;
+
+impl EventSink for TracingSink {
+    fn write(&mut self, event: &ChangeEvent) -> Result<(), String> {
+        let region = event.variable_name.as_deref().unwrap_or_default();
+        let file = event.where_.file.as_deref().unwrap_or_default();
+        let function = event.where_.function.as_deref().unwrap_or_default();
+        if warns(&event.kind) {
+            tracing::warn!(
+                target: "memwatch",
+                region_id = event.region_id,
+                region,
+                kind = event.kind.as_str(),
+                file,
+                line = event.where_.line,
+                function,
+                "{} {}",
+                event.kind.as_str(),
+                region
+            );
+        } else {
+            let (old, new) = (hex(&event.old_preview), hex(&event.new_preview));
+            tracing::info!(
+                target: "memwatch",
+                region_id = event.region_id,
+                region,
+                kind = event.kind.as_str(),
+                file,
+                line = event.where_.line,
+                function,
+                old,
+                new,
+                "{} {}",
+                event.kind.as_str(),
+                region
+            );
+        }
+        Ok(())
+    }
+}
+
+fn warns(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Anomaly(_)
+            | EventKind::UseAfterUnwatch
+            | EventKind::AliasWarning { .. }
+            | EventKind::WorkerStalled { .. }
+            | EventKind::HugePageWarning { .. }
+            | EventKind::SharedPageWarning { .. }
+            | EventKind::CallbackPanicked { .. }
+    )
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Level, Metadata, Subscriber};
+    use tracing_core::span::Current;
+
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native;
+    use crate::MemWatch;
+
+    /// Keeps a span stack and the levels of logged events
+    #[derive(Default)]
+    struct Recorder {
+        next_id: AtomicU64,
+        spans: Mutex<Vec<&'static Metadata<'static>>>,
+        stack: Mutex<Vec<Id>>,
+        levels: Arc<Mutex<Vec<Level>>>,
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            self.spans.lock().unwrap().push(span.metadata());
+            Id::from_u64(self.next_id.fetch_add(1, Ordering::SeqCst) + 1)
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            self.levels.lock().unwrap().push(*event.metadata().level());
+        }
+
+        fn enter(&self, span: &Id) {
+            self.stack.lock().unwrap().push(span.clone());
+        }
+
+        fn exit(&self, _: &Id) {
+            self.stack.lock().unwrap().pop();
+        }
+
+        fn current_span(&self) -> Current {
+            match self.stack.lock().unwrap().last() {
+                Some(id) => Current::new(id.clone(), self.spans.lock().unwrap()[id.into_u64() as usize - 1]),
+                None => Current::none(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_events_carry_the_current_span() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        let mut buf = vec![0u8; 4];
+        watcher.watch(&buf, "orders").unwrap();
+        watcher.add_sink(TracingSink);
+        let recorder = Recorder::default();
+        let levels = Arc::clone(&recorder.levels);
+
+        tracing::subscriber::with_default(recorder, || {
+            let untagged = watcher.check_changes().unwrap();
+            assert_eq!(untagged[0].span_id, None);
+
+            let span = tracing::info_span!("handle_order");
+            let _entered = span.enter();
+            buf[0] = 1;
+            let events = watcher.check_changes().unwrap();
+            assert_eq!(events[0].span_id, span.id().map(|id| id.into_u64()));
+            assert_eq!(events[0].span_name.as_deref(), Some("handle_order"));
+        });
+        assert_eq!(*levels.lock().unwrap(), [Level::INFO, Level::INFO]);
+    }
+}
@@ -0,0 +1,176 @@
+// Driver-side SQL tracking
+//
+// Wraps a driver call so rows_affected, duration and (where the driver knows
+// it) the database name come from the driver instead of the caller. Works
+// with any driver whose execute result implements `RowsAffected`: diesel's
+// `QueryResult<usize>`, rusqlite's `Result<usize>`, or sqlx's query result
+// mapped through `.rows_affected()`.
+
+use std::future::Future;
+use std::time::Instant;
+
+#[cfg(feature = "rusqlite")]
+use crate::sql_schema::ColumnType;
+use crate::sql_tracker::{Execution, SQLTracker};
+
+/// Driver return values that carry an affected-row count
+pub trait RowsAffected {
+    fn rows_affected(&self) -> Option<u64>;
+}
+
+impl RowsAffected for usize {
+    fn rows_affected(&self) -> Option<u64> {
+        Some(*self as u64)
+    }
+}
+
+impl RowsAffected for u64 {
+    fn rows_affected(&self) -> Option<u64> {
+        Some(*self)
+    }
+}
+
+impl<T: RowsAffected, E> RowsAffected for Result<T, E> {
+    fn rows_affected(&self) -> Option<u64> {
+        self.as_ref().ok().and_then(T::rows_affected)
+    }
+}
+
+impl SQLTracker {
+    /// Run `execute`, then track `query` with the driver's row count and the
+    /// measured duration. Failed statements are not tracked. The driver
+    /// result is returned unchanged.
+    pub fn execute_tracked<R: RowsAffected>(
+        &mut self,
+        query: &str,
+        database: Option<&str>,
+        execute: impl FnOnce() -> R,
+    ) -> R {
+        let started = Instant::now();
+        let result = execute();
+        self.record_execution(query, database, started, &result);
+        result
+    }
+
+    /// Async form of execute_tracked (e.g. for sqlx)
+    pub async fn execute_tracked_async<R: RowsAffected, F: Future<Output = R>>(
+        &mut self,
+        query: &str,
+        database: Option<&str>,
+        execute: F,
+    ) -> R {
+        let started = Instant::now();
+        let result = execute.await;
+        self.record_execution(query, database, started, &result);
+        result
+    }
+
+    fn record_execution(&mut self, query: &str, database: Option<&str>, started: Instant, result: &impl RowsAffected) {
+        let Some(rows_affected) = result.rows_affected() else {
+            return;
+        };
+        self.track_execution(
+            query,
+            &Execution {
+                rows_affected: Some(rows_affected),
+                database: database.map(str::to_string),
+                duration_ns: Some(started.elapsed().as_nanos() as u64),
+            },
+        );
+    }
+}
+
+/// Tracked execution on a rusqlite connection; the database name is the
+/// file stem of the main database ("main" for in-memory databases)
+#[cfg(feature = "rusqlite")]
+pub trait TrackedConnection {
+    fn execute_tracked<P: rusqlite::Params>(
+        &self,
+        tracker: &mut SQLTracker,
+        sql: &str,
+        params: P,
+    ) -> rusqlite::Result<usize>;
+}
+
+#[cfg(feature = "rusqlite")]
+impl TrackedConnection for rusqlite::Connection {
+    fn execute_tracked<P: rusqlite::Params>(
+        &self,
+        tracker: &mut SQLTracker,
+        sql: &str,
+        params: P,
+    ) -> rusqlite::Result<usize> {
+        let database = self
+            .path()
+            .and_then(|p| std::path::Path::new(p).file_stem())
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "main".to_string());
+        tracker.execute_tracked(sql, Some(&database), || self.execute(sql, params))
+    }
+}
+
+/// Register the schema of every table in a rusqlite connection's main
+/// database; returns how many tables were registered
+#[cfg(feature = "rusqlite")]
+impl SQLTracker {
+    pub fn load_schema(&mut self, conn: &rusqlite::Connection) -> rusqlite::Result<usize> {
+        let mut tables = conn.prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'")?;
+        let names: Vec<String> = tables.query_map([], |row| row.get(0))?.collect::<rusqlite::Result<_>>()?;
+        for table in &names {
+            let mut info = conn.prepare("SELECT name, type FROM pragma_table_info(?1)")?;
+            let columns: Vec<(String, String)> =
+                info.query_map([table], |row| Ok((row.get(0)?, row.get(1)?)))?.collect::<rusqlite::Result<_>>()?;
+            let columns: Vec<(&str, ColumnType)> =
+                columns.iter().map(|(name, declared)| (name.as_str(), ColumnType::from_declared(declared))).collect();
+            self.register_schema(table, &columns);
+        }
+        Ok(names.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql_tracker::ValueSource;
+
+    #[test]
+    fn test_driver_values_are_marked() {
+        let mut tracker = SQLTracker::pure_rust(None);
+        let result: Result<usize, ()> =
+            tracker.execute_tracked("UPDATE accounts SET balance = 0", Some("bank"), || Ok(42));
+        assert_eq!(result, Ok(42));
+        let failed: Result<usize, ()> = tracker.execute_tracked("UPDATE accounts SET balance = 1", None, || Err(()));
+        assert!(failed.is_err());
+        tracker.track_query("DELETE FROM sessions", 3, None, None, None);
+
+        let changes = tracker.all_changes();
+        assert_eq!(changes.len(), 2);
+        assert_eq!((changes[0].rows_affected, changes[0].source), (42, ValueSource::Driver));
+        assert_eq!(changes[0].database.as_deref(), Some("bank"));
+        assert!(changes[0].duration_ns.is_some());
+        assert_eq!(changes[1].source, ValueSource::Caller);
+    }
+
+    #[cfg(feature = "rusqlite")]
+    #[test]
+    fn test_rusqlite_connection_backfills_rows() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE t (x INTEGER); INSERT INTO t VALUES (1), (2), (3);").unwrap();
+        let mut tracker = SQLTracker::pure_rust(None);
+        assert_eq!(conn.execute_tracked(&mut tracker, "UPDATE t SET x = 0", []).unwrap(), 3);
+        assert_eq!(tracker.all_changes()[0].rows_affected, 3);
+        assert_eq!(tracker.all_changes()[0].database.as_deref(), Some("main"));
+    }
+
+    #[cfg(feature = "rusqlite")]
+    #[test]
+    fn test_rusqlite_schema_is_loaded() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE users (id INTEGER PRIMARY KEY, email VARCHAR(64));").unwrap();
+        let mut tracker = SQLTracker::pure_rust(None);
+        assert_eq!(tracker.load_schema(&conn).unwrap(), 1);
+        tracker.track_query("UPDATE users SET email = 'x', name = 'y'", 1, None, None, None);
+        assert_eq!(tracker.all_changes()[0].column_type, Some(ColumnType::Text));
+        assert_eq!(tracker.take_schema_violations()[0].column_name, "name");
+    }
+}
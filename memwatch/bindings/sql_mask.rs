@@ -0,0 +1,120 @@
+// Masking of sensitive SQL values
+//
+// Mask rules name columns whose values must not be kept as they are:
+//
+//   tracker.add_mask("users", "ssn", Mask::KeepLast(4));
+//   tracker.add_mask("*", "password", Mask::Redact);
+//
+// The SQL tracker applies them to every change before hooks see it and
+// before it is stored or written anywhere: old/new values and params of
+// masked columns are replaced, and the statement text of a change touching
+// a masked column is kept with all of its literals turned into `?`.
+//
+// The native tracker stores each query it parses, literals included, so a
+// tracker with mask rules parses in Rust only: raw values never cross into
+// C, whatever the columns.
+
+use crate::export::hash_value;
+use crate::sql_parser::{self, TokenKind};
+use crate::sql_value::SqlValue;
+
+/// How a masked value is replaced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mask {
+    /// "***"
+    Redact,
+    /// "sha256:<digest>" of the salted value, so equal values stay
+    /// comparable
+    Hash { salt: Vec<u8> },
+    /// The last N characters, the rest as '*'
+    KeepLast(usize),
+}
+
+impl Mask {
+    /// Masked form of `value`; NULL stays NULL
+    pub fn apply(&self, value: &SqlValue) -> SqlValue {
+        if *value == SqlValue::Null {
+            return SqlValue::Null;
+        }
+        let text = value.to_string();
+        SqlValue::Text(match self {
+            Mask::Redact => "***".to_string(),
+            Mask::Hash { salt } => format!("sha256:{}", hash_value(salt, text.as_bytes()).digest),
+            Mask::KeepLast(keep) => {
+                let chars: Vec<char> = text.chars().collect();
+                let hidden = chars.len().saturating_sub(*keep);
+                "*".repeat(hidden) + &chars[hidden..].iter().collect::<String>()
+            }
+        })
+    }
+}
+
+/// Mask rules by (table, column), lowercase; table "*" matches any table
+#[derive(Debug, Default)]
+pub(crate) struct MaskRules {
+    rules: Vec<(String, String, Mask)>,
+}
+
+impl MaskRules {
+    pub(crate) fn add(&mut self, table: &str, column: &str, mask: Mask) {
+        let (table, column) = (table.to_ascii_lowercase(), column.to_ascii_lowercase());
+        self.rules.retain(|(t, c, _)| (t, c) != (&table, &column));
+        self.rules.push((table, column, mask));
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Rule for a column; an exact table beats "*"
+    pub(crate) fn get(&self, table: &str, column: &str) -> Option<&Mask> {
+        let table = table.to_ascii_lowercase();
+        let bare = table.rsplit('.').next().unwrap_or(&table).to_string();
+        let column = column.to_ascii_lowercase();
+        let rule = |wanted: &str| self.rules.iter().find(|(t, c, _)| t == wanted && *c == column).map(|(_, _, mask)| mask);
+        rule(&table).or_else(|| rule(&bare)).or_else(|| rule("*"))
+    }
+}
+
+/// `sql` with every string, blob and number literal replaced by `?`
+pub(crate) fn strip_literals(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut copied = 0;
+    for token in sql_parser::tokenize(sql) {
+        if matches!(token.kind, TokenKind::String | TokenKind::Number) {
+            out.push_str(&sql[copied..token.start]);
+            out.push('?');
+            copied = token.start + token.text.len();
+        }
+    }
+    out.push_str(&sql[copied..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_masks_and_literal_stripping() {
+        let ssn = SqlValue::Text("123-45-6789".to_string());
+        assert_eq!(Mask::Redact.apply(&ssn), SqlValue::Text("***".to_string()));
+        assert_eq!(Mask::KeepLast(4).apply(&ssn), SqlValue::Text("*******6789".to_string()));
+        assert_eq!(Mask::KeepLast(4).apply(&SqlValue::Null), SqlValue::Null);
+        let hash = |v: &str| Mask::Hash { salt: b"s".to_vec() }.apply(&SqlValue::Text(v.to_string()));
+        assert_eq!(hash("a"), hash("a"));
+        assert_ne!(hash("a"), hash("b"));
+
+        let mut rules = MaskRules::default();
+        rules.add("*", "password", Mask::Redact);
+        rules.add("Users", "password", Mask::KeepLast(1));
+        assert_eq!(rules.get("main.users", "PASSWORD"), Some(&Mask::KeepLast(1)));
+        assert_eq!(rules.get("admins", "password"), Some(&Mask::Redact));
+        assert_eq!(rules.get("users", "name"), None);
+
+        assert_eq!(
+            strip_literals("UPDATE users SET ssn = '123-45-6789', age = 41 WHERE id = 7"),
+            "UPDATE users SET ssn = ?, age = ? WHERE id = ?"
+        );
+    }
+}
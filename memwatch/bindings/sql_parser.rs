@@ -0,0 +1,437 @@
+// SQL statement parsing in Rust
+//
+// A tokenizer plus a small parser for the statement shapes the SQL tracker
+// records: INSERT, UPDATE, DELETE and SELECT, with their table, columns and
+// the values assigned to them. Tokens know about quoted identifiers, string
+// literals ('' escapes), comments and parentheses, so a WHERE inside a
+// string or a subquery is never taken for the statement's own. Anything
+// else (DDL, vendor statements) parses as Unknown.
+
+use crate::sql_tracker::SQLOperation;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TokenKind {
+    /// Keyword or bare identifier
+    Word,
+    /// "name", `name` or [name]
+    QuotedIdent,
+    /// 'text', with an optional X prefix for blobs
+    String,
+    Number,
+    /// Any other single character: ( ) , . = ; ...
+    Punct,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Token<'a> {
+    pub kind: TokenKind,
+    /// Source text, quotes included
+    pub text: &'a str,
+    /// Byte offset in the statement
+    pub start: usize,
+}
+
+impl Token<'_> {
+    fn is_keyword(&self, keyword: &str) -> bool {
+        self.kind == TokenKind::Word && self.text.eq_ignore_ascii_case(keyword)
+    }
+
+    fn is_punct(&self, c: char) -> bool {
+        self.kind == TokenKind::Punct && self.text.starts_with(c)
+    }
+
+    fn is_ident(&self) -> bool {
+        matches!(self.kind, TokenKind::Word | TokenKind::QuotedIdent)
+    }
+
+    fn end(&self) -> usize {
+        self.start + self.text.len()
+    }
+}
+
+/// What a statement does to which columns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub operation: SQLOperation,
+    /// Unquoted, schema-qualified as written ("db.accounts")
+    pub table: Option<String>,
+    /// Columns written (INSERT, UPDATE) or read (SELECT) with the source
+    /// text of the value assigned, if any; "*" when not listed
+    pub columns: Vec<(String, Option<String>)>,
+    /// WHERE clause on the statement itself (not in a subquery)
+    pub has_where: bool,
+}
+
+/// Parse one statement
+pub fn parse(sql: &str) -> Statement {
+    let tokens = tokenize(sql);
+    let body = statement_start(&tokens);
+    let tokens = &tokens[body..];
+    let operation = match tokens.first() {
+        Some(t) if t.is_keyword("INSERT") => SQLOperation::Insert,
+        Some(t) if t.is_keyword("UPDATE") => SQLOperation::Update,
+        Some(t) if t.is_keyword("DELETE") => SQLOperation::Delete,
+        Some(t) if t.is_keyword("SELECT") => SQLOperation::Select,
+        _ => SQLOperation::Unknown,
+    };
+    let top = top_level(tokens);
+    let (table, columns) = match operation {
+        SQLOperation::Insert => parse_insert(sql, tokens, &top),
+        SQLOperation::Update => parse_update(sql, tokens, &top),
+        SQLOperation::Delete => (table_after(tokens, &top, "FROM"), star()),
+        SQLOperation::Select => (table_after(tokens, &top, "FROM"), parse_select(sql, tokens, &top)),
+        SQLOperation::Unknown => (None, Vec::new()),
+    };
+    Statement {
+        operation,
+        table,
+        columns,
+        has_where: top.iter().any(|&i| tokens[i].is_keyword("WHERE")),
+    }
+}
+
+/// Split a statement into tokens; whitespace and comments are dropped
+pub(crate) fn tokenize(sql: &str) -> Vec<Token<'_>> {
+    let bytes = sql.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let next = bytes.get(i + 1).copied();
+        let start = i;
+        let kind = match c {
+            _ if c.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            b'-' if next == Some(b'-') => {
+                i = find_from(bytes, i, b"\n").map_or(bytes.len(), |end| end + 1);
+                continue;
+            }
+            b'/' if next == Some(b'*') => {
+                i = find_from(bytes, i + 2, b"*/").map_or(bytes.len(), |end| end + 2);
+                continue;
+            }
+            b'\'' => {
+                i = quoted_end(bytes, i, b'\'');
+                TokenKind::String
+            }
+            b'x' | b'X' if next == Some(b'\'') => {
+                i = quoted_end(bytes, i + 1, b'\'');
+                TokenKind::String
+            }
+            b'"' | b'`' => {
+                i = quoted_end(bytes, i, c);
+                TokenKind::QuotedIdent
+            }
+            b'[' => {
+                i = find_from(bytes, i, b"]").map_or(bytes.len(), |end| end + 1);
+                TokenKind::QuotedIdent
+            }
+            b'0'..=b'9' => {
+                i = number_end(bytes, i);
+                TokenKind::Number
+            }
+            b'.' if next.is_some_and(|n| n.is_ascii_digit()) => {
+                i = number_end(bytes, i);
+                TokenKind::Number
+            }
+            _ if is_word_byte(c) => {
+                while i < bytes.len() && is_word_byte(bytes[i]) {
+                    i += 1;
+                }
+                TokenKind::Word
+            }
+            _ => {
+                i += sql[i..].chars().next().map_or(1, char::len_utf8);
+                TokenKind::Punct
+            }
+        };
+        tokens.push(Token { kind, text: &sql[start..i], start });
+    }
+    tokens
+}
+
+/// Identifier without its quotes
+pub(crate) fn unquote_ident(text: &str) -> String {
+    let inner = |open: char, close: char| text.strip_prefix(open).and_then(|t| t.strip_suffix(close));
+    if let Some(name) = inner('"', '"') {
+        name.replace("\"\"", "\"")
+    } else if let Some(name) = inner('`', '`') {
+        name.replace("``", "`")
+    } else if let Some(name) = inner('[', ']') {
+        name.to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+fn is_word_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'$' || b >= 0x80
+}
+
+fn find_from(bytes: &[u8], from: usize, needle: &[u8]) -> Option<usize> {
+    bytes[from.min(bytes.len())..].windows(needle.len()).position(|w| w == needle).map(|p| from + p)
+}
+
+/// End of a quoted run starting at `open`; doubled quotes and backslash
+/// escapes stay inside, an unterminated run ends the statement
+fn quoted_end(bytes: &[u8], open: usize, quote: u8) -> usize {
+    let mut i = open + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b if b == quote && bytes.get(i + 1) == Some(&quote) => i += 2,
+            b if b == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+fn number_end(bytes: &[u8], mut i: usize) -> usize {
+    while i < bytes.len() {
+        match bytes[i] {
+            b'e' | b'E' if matches!(bytes.get(i + 1), Some(b'+' | b'-')) => i += 2,
+            b if b.is_ascii_alphanumeric() || b == b'.' || b == b'_' => i += 1,
+            _ => break,
+        }
+    }
+    i
+}
+
+/// Index of the statement proper, after any leading WITH clause
+fn statement_start(tokens: &[Token]) -> usize {
+    if !tokens.first().is_some_and(|t| t.is_keyword("WITH")) {
+        return 0;
+    }
+    let verbs = ["INSERT", "UPDATE", "DELETE", "SELECT"];
+    top_level(tokens)
+        .into_iter()
+        .find(|&i| i > 0 && verbs.iter().any(|verb| tokens[i].is_keyword(verb)))
+        .unwrap_or(tokens.len())
+}
+
+/// Indices of tokens outside parentheses, up to the first `;`
+fn top_level(tokens: &[Token]) -> Vec<usize> {
+    let mut depth = 0usize;
+    let mut top = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        if token.is_punct('(') {
+            depth += 1;
+        } else if token.is_punct(')') {
+            depth = depth.saturating_sub(1);
+        } else if depth == 0 && token.is_punct(';') {
+            break;
+        } else if depth == 0 {
+            top.push(i);
+        }
+    }
+    top
+}
+
+/// Index of the terminating `;`, or the token count
+fn statement_end(tokens: &[Token]) -> usize {
+    tokens.iter().position(|t| t.is_punct(';')).unwrap_or(tokens.len())
+}
+
+/// Dotted name starting at `at`, and the index after it
+fn name_at(tokens: &[Token], at: usize) -> Option<(String, usize)> {
+    let first = tokens.get(at).filter(|t| t.is_ident())?;
+    let mut name = unquote_ident(first.text);
+    let mut i = at + 1;
+    while tokens.get(i).is_some_and(|t| t.is_punct('.')) && tokens.get(i + 1).is_some_and(Token::is_ident) {
+        name.push('.');
+        name.push_str(&unquote_ident(tokens[i + 1].text));
+        i += 2;
+    }
+    Some((name, i))
+}
+
+/// Table named after the first top-level `keyword`
+fn table_after(tokens: &[Token], top: &[usize], keyword: &str) -> Option<String> {
+    let at = top.iter().find(|&&i| tokens[i].is_keyword(keyword))?;
+    name_at(tokens, at + 1).map(|(name, _)| name)
+}
+
+fn star() -> Vec<(String, Option<String>)> {
+    vec![("*".to_string(), None)]
+}
+
+/// Source text spanning `tokens`
+fn span(sql: &str, tokens: &[Token]) -> Option<String> {
+    let (first, last) = (tokens.first()?, tokens.last()?);
+    Some(sql[first.start..last.end()].to_string())
+}
+
+/// Items of the list in `tokens[from..to]`, split at commas outside
+/// parentheses
+fn list_items<'t, 'a>(tokens: &'t [Token<'a>], from: usize, to: usize) -> Vec<&'t [Token<'a>]> {
+    let mut items = Vec::new();
+    let mut depth = 0usize;
+    let mut item_start = from;
+    for i in from..to {
+        let token = &tokens[i];
+        if token.is_punct('(') {
+            depth += 1;
+        } else if token.is_punct(')') {
+            depth = depth.saturating_sub(1);
+        } else if depth == 0 && token.is_punct(',') {
+            items.push(&tokens[item_start..i]);
+            item_start = i + 1;
+        }
+    }
+    items.push(&tokens[item_start..to]);
+    items.retain(|item| !item.is_empty());
+    items
+}
+
+/// Index of the `)` closing the group opened at `open`
+fn group_end(tokens: &[Token], open: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        if token.is_punct('(') {
+            depth += 1;
+        } else if token.is_punct(')') {
+            depth -= 1;
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+/// INSERT [OR ..] INTO table [(columns)] VALUES (row), ..: columns paired
+/// with the values of the first row
+fn parse_insert(sql: &str, tokens: &[Token], top: &[usize]) -> (Option<String>, Vec<(String, Option<String>)>) {
+    let Some(&into) = top.iter().find(|&&i| tokens[i].is_keyword("INTO")) else {
+        return (None, Vec::new());
+    };
+    let Some((table, mut i)) = name_at(tokens, into + 1) else {
+        return (None, Vec::new());
+    };
+    let mut columns = Vec::new();
+    if tokens.get(i).is_some_and(|t| t.is_punct('(')) {
+        let Some(close) = group_end(tokens, i) else {
+            return (Some(table), star());
+        };
+        columns = list_items(tokens, i + 1, close)
+            .iter()
+            .map(|item| unquote_ident(item.last().map_or("", |t| t.text)))
+            .collect();
+        i = close + 1;
+    }
+    if columns.is_empty() {
+        return (Some(table), star());
+    }
+    let row: Vec<Option<String>> = match tokens.get(i..i + 2) {
+        Some([values, open]) if values.is_keyword("VALUES") && open.is_punct('(') => match group_end(tokens, i + 1) {
+            Some(close) => list_items(tokens, i + 2, close).iter().map(|item| span(sql, item)).collect(),
+            None => Vec::new(),
+        },
+        _ => Vec::new(),
+    };
+    let mut row = row.into_iter();
+    (Some(table), columns.into_iter().map(|c| (c, row.next().flatten())).collect())
+}
+
+/// UPDATE [OR ..] table [alias] SET column = value, .. [FROM ..] [WHERE ..]
+fn parse_update(sql: &str, tokens: &[Token], top: &[usize]) -> (Option<String>, Vec<(String, Option<String>)>) {
+    let mut at = 1;
+    if tokens.get(at).is_some_and(|t| t.is_keyword("OR")) {
+        at += 2;
+    }
+    if tokens.get(at).is_some_and(|t| t.is_keyword("ONLY")) {
+        at += 1;
+    }
+    let Some((table, _)) = name_at(tokens, at) else {
+        return (None, Vec::new());
+    };
+    let Some(set) = top.iter().position(|&i| tokens[i].is_keyword("SET")) else {
+        return (Some(table), Vec::new());
+    };
+    let ends = ["WHERE", "FROM", "RETURNING", "ORDER", "LIMIT"];
+    let end = top[set + 1..]
+        .iter()
+        .find(|&&i| ends.iter().any(|keyword| tokens[i].is_keyword(keyword)))
+        .copied()
+        .unwrap_or_else(|| statement_end(tokens));
+    let columns = list_items(tokens, top[set] + 1, end)
+        .into_iter()
+        .filter_map(|item| {
+            let eq = item.iter().position(|t| t.is_punct('='))?;
+            let column = item[..eq].last().filter(|t| t.is_ident())?;
+            Some((unquote_ident(column.text), span(sql, &item[eq + 1..])))
+        })
+        .collect();
+    (Some(table), columns)
+}
+
+/// SELECT [DISTINCT | ALL] item, .. FROM: each item as written
+fn parse_select(sql: &str, tokens: &[Token], top: &[usize]) -> Vec<(String, Option<String>)> {
+    let mut from = 1;
+    if tokens.get(from).is_some_and(|t| t.is_keyword("DISTINCT") || t.is_keyword("ALL")) {
+        from += 1;
+    }
+    let to = top.iter().find(|&&i| tokens[i].is_keyword("FROM")).copied().unwrap_or_else(|| statement_end(tokens)).max(from);
+    let columns: Vec<(String, Option<String>)> =
+        list_items(tokens, from, to).iter().filter_map(|item| span(sql, item)).map(|c| (c, None)).collect();
+    if columns.is_empty() {
+        star()
+    } else {
+        columns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns(statement: &Statement) -> Vec<(&str, Option<&str>)> {
+        statement.columns.iter().map(|(c, v)| (c.as_str(), v.as_deref())).collect()
+    }
+
+    #[test]
+    fn test_tokenizer_skips_comments_and_keeps_literals() {
+        let kinds: Vec<_> = tokenize("SELECT 'a -- b', \"x\"\"y\" /* WHERE */ -- tail\n FROM t")
+            .iter()
+            .map(|t| (t.kind, t.text))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (TokenKind::Word, "SELECT"),
+                (TokenKind::String, "'a -- b'"),
+                (TokenKind::Punct, ","),
+                (TokenKind::QuotedIdent, "\"x\"\"y\""),
+                (TokenKind::Word, "FROM"),
+                (TokenKind::Word, "t"),
+            ]
+        );
+        assert_eq!(unquote_ident("\"x\"\"y\""), "x\"y");
+    }
+
+    #[test]
+    fn test_keywords_inside_strings_and_subqueries_are_not_structure() {
+        let update = parse("UPDATE accounts SET note = 'see WHERE clause', balance = balance - 5");
+        assert_eq!((update.table.as_deref(), update.has_where), (Some("accounts"), false));
+        assert_eq!(columns(&update), [("note", Some("'see WHERE clause'")), ("balance", Some("balance - 5"))]);
+
+        let nested = parse("UPDATE a.t x SET x.v = (SELECT max(v) FROM u WHERE u.id = 1)");
+        assert_eq!((nested.table.as_deref(), nested.has_where), (Some("a.t"), false));
+        assert_eq!(columns(&nested)[0].0, "v");
+
+        let with = parse("WITH old AS (SELECT id FROM t WHERE stale) DELETE FROM [t] WHERE id IN (SELECT id FROM old)");
+        assert_eq!((with.operation, with.table.as_deref(), with.has_where), (SQLOperation::Delete, Some("t"), true));
+        assert_eq!(parse("CREATE TABLE t (id INT)").operation, SQLOperation::Unknown);
+    }
+
+    #[test]
+    fn test_insert_pairs_columns_with_first_row() {
+        let insert = parse("INSERT OR REPLACE INTO \"users\" (name, age) VALUES ('O''Brien, Pat', -4), ('x', 1)");
+        assert_eq!(insert.table.as_deref(), Some("users"));
+        assert_eq!(columns(&insert), [("name", Some("'O''Brien, Pat'")), ("age", Some("-4"))]);
+        assert_eq!(columns(&parse("INSERT INTO log SELECT * FROM staging")), [("*", None)]);
+    }
+}
@@ -0,0 +1,211 @@
+// Replaying tracked SQL changes against a shadow database
+//
+// A verification tool for migrations and CDC pipelines: re-execute the
+// INSERT, UPDATE and DELETE statements behind recorded changes, in order,
+// on a copy of the database and compare the rows each one affects with the
+// rows_affected recorded at the time. Changes of one statement (one per
+// column) are executed once. Placeholders (`?`, `?N`, `$N`, `:N`) of the
+// assigned values take the bound values recorded in params; statements
+// with placeholders left over (in WHERE, inside expressions, or where
+// masking stripped the literals) are skipped, as are SELECTs. A recorded
+// rows_affected of -1 (unknown) is not compared.
+//
+//   let report = sql_replay::replay_to("shadow.db", tracker.all_changes())?;
+//   assert!(report.divergences.is_empty());
+
+use crate::sql_parser::{self, Token, TokenKind};
+use crate::sql_tracker::{self, SQLChange, SQLOperation};
+use crate::sql_value::SqlValue;
+
+/// Where replayed statements are executed
+pub trait ShadowDatabase {
+    /// Execute one statement, returning the rows it affected
+    fn execute(&mut self, sql: &str) -> Result<u64, String>;
+}
+
+/// A replayed statement whose outcome differs from the recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Statement as executed, placeholders substituted
+    pub query: String,
+    pub expected_rows: i32,
+    /// None when the statement failed
+    pub actual_rows: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// Outcome of a replay
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// Statements executed
+    pub executed: usize,
+    /// Executed statements affecting the recorded number of rows (or with
+    /// none recorded)
+    pub matched: usize,
+    pub divergences: Vec<Divergence>,
+    /// Statements not executed, with the reason
+    pub skipped: Vec<(String, String)>,
+}
+
+/// Replay `changes` on `db`; see the module comment
+pub fn replay(db: &mut impl ShadowDatabase, changes: &[SQLChange]) -> ReplayReport {
+    let mut report = ReplayReport::default();
+    let mut previous: Option<(u64, &str)> = None;
+    for change in changes {
+        let statement = (change.timestamp_ns, change.full_query.as_str());
+        if previous == Some(statement) {
+            continue;
+        }
+        previous = Some(statement);
+        if !matches!(change.operation, SQLOperation::Insert | SQLOperation::Update | SQLOperation::Delete) {
+            continue;
+        }
+        let query = match substitute(change) {
+            Ok(query) => query,
+            Err(reason) => {
+                report.skipped.push((change.full_query.clone(), reason));
+                continue;
+            }
+        };
+        report.executed += 1;
+        match db.execute(&query) {
+            Ok(rows) if change.rows_affected < 0 || rows == change.rows_affected as u64 => report.matched += 1,
+            Ok(rows) => report.divergences.push(Divergence {
+                query,
+                expected_rows: change.rows_affected,
+                actual_rows: Some(rows),
+                error: None,
+            }),
+            Err(e) => report.divergences.push(Divergence {
+                query,
+                expected_rows: change.rows_affected,
+                actual_rows: None,
+                error: Some(e),
+            }),
+        }
+    }
+    report
+}
+
+/// Replay `changes` on the SQLite database at `conn_str` (a path, or
+/// ":memory:")
+#[cfg(feature = "rusqlite")]
+pub fn replay_to(conn_str: &str, changes: &[SQLChange]) -> Result<ReplayReport, String> {
+    let mut conn = rusqlite::Connection::open(conn_str).map_err(|e| format!("Failed to open {}: {}", conn_str, e))?;
+    Ok(replay(&mut conn, changes))
+}
+
+#[cfg(feature = "rusqlite")]
+impl ShadowDatabase for rusqlite::Connection {
+    fn execute(&mut self, sql: &str) -> Result<u64, String> {
+        rusqlite::Connection::execute(self, sql, []).map(|rows| rows as u64).map_err(|e| e.to_string())
+    }
+}
+
+/// The change's statement with its placeholders replaced by bound values.
+/// Placeholders of the assigned values come first in the text, in the
+/// order of params, so the unbound statement's params say which value each
+/// one took.
+fn substitute(change: &SQLChange) -> Result<String, String> {
+    let sql = &change.full_query;
+    let unbound = sql_tracker::parse_changes(sql, change.rows_affected, None, None, None);
+    let mut values = Vec::new();
+    for ((_, raw), (_, bound)) in unbound.first().map_or(&[][..], |c| &c.params[..]).iter().zip(&change.params) {
+        let SqlValue::Expr(raw) = raw else {
+            continue;
+        };
+        let tokens = sql_parser::tokenize(raw);
+        let placeholders = placeholders(&tokens).count();
+        if placeholders == 0 {
+            continue;
+        }
+        if placeholders > 1 || tokens.len() > 2 || matches!(bound, SqlValue::Expr(_)) {
+            return Err(format!("no bound value for {}", raw));
+        }
+        values.push(bound.to_sql());
+    }
+
+    let tokens = sql_parser::tokenize(sql);
+    let mut values = values.into_iter();
+    let mut out = String::with_capacity(sql.len());
+    let mut copied = 0;
+    for (start, end) in placeholders(&tokens) {
+        let value = values.next().ok_or_else(|| format!("no bound value for {}", &sql[start..end]))?;
+        out.push_str(&sql[copied..start]);
+        out.push_str(&value);
+        copied = end;
+    }
+    out.push_str(&sql[copied..]);
+    Ok(out)
+}
+
+/// Byte ranges of `?`, `?N`, `$N` and `:N` placeholders
+fn placeholders<'a>(tokens: &'a [Token<'a>]) -> impl Iterator<Item = (usize, usize)> + 'a {
+    tokens.iter().enumerate().filter_map(|(i, token)| {
+        let number = tokens.get(i + 1).filter(|n| n.kind == TokenKind::Number && n.start == token.start + 1);
+        let end = token.start + token.text.len();
+        match token.text {
+            "?" => Some((token.start, number.map_or(end, |n| n.start + n.text.len()))),
+            ":" => number.map(|n| (token.start, n.start + n.text.len())),
+            // "$N" is one word
+            _ if token.text.strip_prefix('$').is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit())) => Some((token.start, end)),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql_tracker::SQLTracker;
+
+    /// Deletes nothing, affects one row otherwise
+    struct Scripted(Vec<String>);
+
+    impl ShadowDatabase for Scripted {
+        fn execute(&mut self, sql: &str) -> Result<u64, String> {
+            self.0.push(sql.to_string());
+            if sql.contains("missing") {
+                return Err("no such table: missing".to_string());
+            }
+            Ok(if sql.starts_with("DELETE") { 0 } else { 1 })
+        }
+    }
+
+    #[test]
+    fn test_replay_reports_divergent_row_counts() {
+        let mut tracker = SQLTracker::pure_rust(None);
+        tracker.track_query("INSERT INTO users (name, age) VALUES ('Ann', 30)", 1, None, None, None);
+        let binds = [SqlValue::Text("O'Neil".to_string()), SqlValue::Int(31)];
+        tracker.track_query_with_binds("UPDATE users SET name = ?, age = $2", 1, None, &binds);
+        tracker.track_query("DELETE FROM users WHERE age > 40", 2, None, None, None);
+        tracker.track_query("UPDATE missing SET x = 1", -1, None, None, None);
+        tracker.track_query_with_binds("UPDATE users SET age = ? WHERE id = ?", 1, None, &[SqlValue::Int(5)]);
+        tracker.track_query("SELECT name FROM users", 0, None, None, None);
+
+        let mut db = Scripted(Vec::new());
+        let report = replay(&mut db, tracker.all_changes());
+        assert_eq!(db.0[1], "UPDATE users SET name = 'O''Neil', age = 31");
+        assert_eq!((report.executed, report.matched), (4, 2));
+        assert_eq!(report.divergences[0].query, "DELETE FROM users WHERE age > 40");
+        assert_eq!((report.divergences[0].expected_rows, report.divergences[0].actual_rows), (2, Some(0)));
+        assert!(report.divergences[1].error.as_deref().unwrap().contains("missing"));
+        assert_eq!(report.skipped.len(), 1);
+    }
+
+    #[cfg(feature = "rusqlite")]
+    #[test]
+    fn test_replay_to_sqlite() {
+        let path = std::env::temp_dir().join(format!("memwatch_shadow_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch("CREATE TABLE t (x INTEGER); INSERT INTO t VALUES (1), (2);").unwrap();
+        drop(conn);
+
+        let mut tracker = SQLTracker::pure_rust(None);
+        tracker.track_query("UPDATE t SET x = 0", 3, None, None, None);
+        let report = replay_to(path.to_str().unwrap(), tracker.all_changes()).unwrap();
+        assert_eq!(report.divergences[0].actual_rows, Some(2));
+        let _ = std::fs::remove_file(&path);
+    }
+}
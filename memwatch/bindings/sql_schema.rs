@@ -0,0 +1,125 @@
+// Table schemas for the SQL tracker
+//
+// Registered schemas (register_schema(), or load_schema() from a rusqlite
+// connection) let the tracker type each change's column and flag queries
+// naming a column the table does not have:
+//
+//   tracker.register_schema("users", &[("id", ColumnType::Int), ("email", ColumnType::Text)]);
+//
+// Only registered tables are checked. Names compare case-insensitively, and
+// a schema-qualified table ("main.users") matches its bare name. Column
+// lists that are not plain names (SELECT expressions, "*") are not checked.
+
+use std::collections::HashMap;
+
+/// Declared type of a column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ColumnType {
+    Int,
+    Float,
+    Text,
+    Blob,
+    Bool,
+    /// Anything else (NUMERIC, DATE, ..)
+    Any,
+}
+
+impl ColumnType {
+    /// Type of a declared SQL type, by SQLite's affinity rules
+    pub fn from_declared(declared: &str) -> ColumnType {
+        let declared = declared.to_ascii_uppercase();
+        let has = |part: &str| declared.contains(part);
+        if has("BOOL") {
+            ColumnType::Bool
+        } else if has("INT") {
+            ColumnType::Int
+        } else if has("CHAR") || has("CLOB") || has("TEXT") {
+            ColumnType::Text
+        } else if has("BLOB") || declared.trim().is_empty() {
+            ColumnType::Blob
+        } else if has("REAL") || has("FLOA") || has("DOUB") {
+            ColumnType::Float
+        } else {
+            ColumnType::Any
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ColumnType::Int => "int",
+            ColumnType::Float => "float",
+            ColumnType::Text => "text",
+            ColumnType::Blob => "blob",
+            ColumnType::Bool => "bool",
+            ColumnType::Any => "any",
+        }
+    }
+}
+
+/// A query naming a column its table's schema does not have
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaViolation {
+    pub timestamp_ns: u64,
+    pub table_name: String,
+    pub column_name: String,
+    pub full_query: String,
+}
+
+/// Registered schemas, by lowercase table name
+#[derive(Debug, Default)]
+pub(crate) struct SchemaRegistry {
+    tables: HashMap<String, Vec<(String, ColumnType)>>,
+}
+
+/// What a registry knows about one column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Lookup {
+    /// Table not registered, or not a plain column name
+    Unchecked,
+    Known(ColumnType),
+    Unknown,
+}
+
+impl SchemaRegistry {
+    pub(crate) fn register(&mut self, table: &str, columns: &[(&str, ColumnType)]) {
+        let columns = columns.iter().map(|(name, kind)| (name.to_ascii_lowercase(), *kind)).collect();
+        self.tables.insert(table.to_ascii_lowercase(), columns);
+    }
+
+    pub(crate) fn lookup(&self, table: &str, column: &str) -> Lookup {
+        let table = table.to_ascii_lowercase();
+        let bare = table.rsplit('.').next().unwrap_or(&table);
+        let Some(columns) = self.tables.get(&table).or_else(|| self.tables.get(bare)) else {
+            return Lookup::Unchecked;
+        };
+        if column.is_empty() || !column.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+            return Lookup::Unchecked;
+        }
+        let column = column.to_ascii_lowercase();
+        match columns.iter().find(|(name, _)| *name == column) {
+            Some((_, kind)) => Lookup::Known(*kind),
+            None => Lookup::Unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_declared_types_and_lookup() {
+        assert_eq!(ColumnType::from_declared("VARCHAR(64)"), ColumnType::Text);
+        assert_eq!(ColumnType::from_declared("bigint"), ColumnType::Int);
+        assert_eq!(ColumnType::from_declared("DOUBLE PRECISION"), ColumnType::Float);
+        assert_eq!(ColumnType::from_declared("NUMERIC(10,2)"), ColumnType::Any);
+
+        let mut schemas = SchemaRegistry::default();
+        schemas.register("Users", &[("id", ColumnType::Int), ("email", ColumnType::Text)]);
+        assert_eq!(schemas.lookup("main.users", "EMAIL"), Lookup::Known(ColumnType::Text));
+        assert_eq!(schemas.lookup("users", "emial"), Lookup::Unknown);
+        assert_eq!(schemas.lookup("users", "count(*)"), Lookup::Unchecked);
+        assert_eq!(schemas.lookup("orders", "id"), Lookup::Unchecked);
+    }
+}
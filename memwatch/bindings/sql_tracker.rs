@@ -1,13 +1,31 @@
-/// Universal SQL Tracker for Rust
-/// Track SQL column-level changes across all databases
+// Universal SQL Tracker for Rust
+// Track SQL column-level changes across all databases
+//
+// Two modes: `Native` drives libsql_tracker (resolved at runtime with dlsym,
+// so the crate links without it) and reads the created changes back out of
+// the tracker; `PureRust` parses (see sql_parser) and stores everything in
+// Rust. new() picks Native when the library is loaded and falls back to
+// PureRust otherwise.
 
-use libc::c_int;
-use std::ffi::{CString, CStr};
-use std::ptr::null_mut;
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use std::sync::{Mutex, MutexGuard};
+#[cfg(unix)]
+use std::{ffi::CStr, os::raw::c_void, sync::OnceLock};
+
+use crate::export::{json_opt_str, json_str};
+use crate::lifecycle::now_ns;
+use crate::policy::{self, Alert, AlertQueue, Decision, Hook};
+use crate::sql_parser::{self, TokenKind};
+use crate::sql_mask::{self, Mask, MaskRules};
+use crate::sql_schema::{ColumnType, Lookup, SchemaRegistry, SchemaViolation};
+pub use crate::sql_value::SqlValue;
 
 // SQL operation types
 #[repr(C)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SQLOperation {
     Unknown = 0,
     Insert = 1,
@@ -26,84 +44,333 @@ impl SQLOperation {
             _ => "UNKNOWN",
         }
     }
+
+    fn from_code(code: c_int) -> Self {
+        match code {
+            1 => SQLOperation::Insert,
+            2 => SQLOperation::Update,
+            3 => SQLOperation::Delete,
+            4 => SQLOperation::Select,
+            _ => SQLOperation::Unknown,
+        }
+    }
+}
+
+/// Who supplied rows_affected / database for a change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ValueSource {
+    /// Passed in by the caller of track_query
+    #[default]
+    Caller,
+    /// Read from the database driver's return value
+    Driver,
+}
+
+impl ValueSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ValueSource::Caller => "caller",
+            ValueSource::Driver => "driver",
+        }
+    }
+}
+
+/// What a driver reported for one executed statement
+#[derive(Debug, Clone, Default)]
+pub struct Execution {
+    pub rows_affected: Option<u64>,
+    pub database: Option<String>,
+    pub duration_ns: Option<u64>,
+}
+
+/// Where parsing and storage happen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SqlMode {
+    /// libsql_tracker parses; changes are copied back into Rust
+    Native,
+    /// Parsing and storage entirely in Rust, no native library needed
+    PureRust,
+}
+
+// Mirrors of include/sql_tracker.h
+const MAX_TABLE_NAME: usize = 256;
+const MAX_COLUMN_NAME: usize = 256;
+const MAX_VALUE_LENGTH: usize = 1024;
+const MAX_QUERY_LENGTH: usize = 4096;
+const MAX_DATABASE_NAME: usize = 256;
+
+#[repr(C)]
+struct SQLChangeC {
+    timestamp_ns: u64,
+    table_name: [c_char; MAX_TABLE_NAME],
+    column_name: [c_char; MAX_COLUMN_NAME],
+    operation: c_int,
+    old_value: [c_char; MAX_VALUE_LENGTH],
+    new_value: [c_char; MAX_VALUE_LENGTH],
+    rows_affected: c_int,
+    database: [c_char; MAX_DATABASE_NAME],
+    full_query: [c_char; MAX_QUERY_LENGTH],
+}
+
+#[repr(C)]
+struct SQLTrackerC {
+    changes: *mut SQLChangeC,
+    change_count: c_int,
+    max_changes: c_int,
+    storage_path: *mut c_char,
 }
 
-// FFI declarations for native library
-#[link(name = "sql_tracker")]
-extern "C" {
-    pub fn sql_tracker_init(storage_path: *const i8) -> *mut std::ffi::c_void;
-    pub fn sql_tracker_track_query(
-        tracker: *mut std::ffi::c_void,
-        query: *const i8,
-        rows_affected: c_int,
-        database: *const i8,
-        old_value: *const i8,
-        new_value: *const i8,
-    ) -> c_int;
-    pub fn sql_tracker_free(tracker: *mut std::ffi::c_void);
+type InitFn = unsafe extern "C" fn(*const c_char) -> *mut SQLTrackerC;
+type TrackFn = unsafe extern "C" fn(
+    *mut SQLTrackerC,
+    *const c_char,
+    c_int,
+    *const c_char,
+    *const c_char,
+    *const c_char,
+) -> c_int;
+type FreeFn = unsafe extern "C" fn(*mut SQLTrackerC);
+
+/// libsql_tracker entry points, if the library is loaded in this process
+#[derive(Clone, Copy)]
+struct NativeApi {
+    init: InitFn,
+    track_query: TrackFn,
+    free: FreeFn,
+}
+
+impl NativeApi {
+    #[cfg(not(unix))]
+    fn resolve() -> Option<NativeApi> {
+        None
+    }
+
+    #[cfg(unix)]
+    fn resolve() -> Option<NativeApi> {
+        static API: OnceLock<Option<NativeApi>> = OnceLock::new();
+        *API.get_or_init(|| unsafe {
+            let lookup = |name: &CStr| libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr());
+            let (init, track, free) = (
+                lookup(c"sql_tracker_init"),
+                lookup(c"sql_tracker_track_query"),
+                lookup(c"sql_tracker_free"),
+            );
+            if init.is_null() || track.is_null() || free.is_null() {
+                return None;
+            }
+            Some(NativeApi {
+                init: std::mem::transmute::<*mut c_void, InitFn>(init),
+                track_query: std::mem::transmute::<*mut c_void, TrackFn>(track),
+                free: std::mem::transmute::<*mut c_void, FreeFn>(free),
+            })
+        })
+    }
 }
 
 /// Single column change from SQL operation
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SQLChange {
     pub timestamp_ns: u64,
     pub table_name: String,
     pub column_name: String,
     pub operation: SQLOperation,
-    pub old_value: Option<String>,
-    pub new_value: Option<String>,
+    /// Typed values: caller-supplied, or literals taken from the query
+    pub old_value: Option<SqlValue>,
+    pub new_value: Option<SqlValue>,
     pub rows_affected: i32,
     pub database: Option<String>,
     pub full_query: String,
+    /// Origin of rows_affected and database
+    pub source: ValueSource,
+    /// Statement execution time, when measured around the driver call
+    pub duration_ns: Option<u64>,
+    /// Every column the statement writes (INSERT: first row, UPDATE: SET)
+    /// with the value it assigns, in statement order; bind placeholders
+    /// stay `Expr` unless bound with track_query_with_binds(). Empty for
+    /// DELETE and SELECT.
+    pub params: Vec<(String, SqlValue)>,
+    /// Declared type of the column, when its table's schema is registered
+    pub column_type: Option<ColumnType>,
 }
 
 impl SQLChange {
-    pub fn to_dict(&self) -> std::collections::HashMap<String, String> {
-        let mut map = std::collections::HashMap::new();
+    pub fn to_dict(&self) -> HashMap<String, String> {
+        let mut map = HashMap::new();
         map.insert("timestamp_ns".to_string(), self.timestamp_ns.to_string());
         map.insert("table_name".to_string(), self.table_name.clone());
         map.insert("column_name".to_string(), self.column_name.clone());
         map.insert("operation".to_string(), self.operation.as_str().to_string());
         if let Some(ref old) = self.old_value {
-            map.insert("old_value".to_string(), old.clone());
+            map.insert("old_value".to_string(), old.to_string());
         }
         if let Some(ref new) = self.new_value {
-            map.insert("new_value".to_string(), new.clone());
+            map.insert("new_value".to_string(), new.to_string());
         }
         map.insert("rows_affected".to_string(), self.rows_affected.to_string());
         if let Some(ref db) = self.database {
             map.insert("database".to_string(), db.clone());
         }
         map.insert("full_query".to_string(), self.full_query.clone());
+        map.insert("source".to_string(), self.source.as_str().to_string());
+        if let Some(duration_ns) = self.duration_ns {
+            map.insert("duration_ns".to_string(), duration_ns.to_string());
+        }
         map
     }
+
+    /// Single-line JSON with a fixed field order; values as their literal text
+    pub fn to_json(&self) -> String {
+        let value = |v: &Option<SqlValue>| json_opt_str(v.as_ref().map(|v| v.to_string()).as_deref());
+        let params: Vec<String> = self
+            .params
+            .iter()
+            .map(|(column, value)| format!("{}:{}", json_str(column), json_str(&value.to_string())))
+            .collect();
+        format!(
+            "{{\"timestamp_ns\":{},\"table_name\":{},\"column_name\":{},\"operation\":\"{}\",\"old_value\":{},\"new_value\":{},\"rows_affected\":{},\"database\":{},\"full_query\":{},\"source\":\"{}\",\"duration_ns\":{},\"params\":{{{}}},\"column_type\":{}}}",
+            self.timestamp_ns,
+            json_str(&self.table_name),
+            json_str(&self.column_name),
+            self.operation.as_str(),
+            value(&self.old_value),
+            value(&self.new_value),
+            self.rows_affected,
+            json_opt_str(self.database.as_deref()),
+            json_str(&self.full_query),
+            self.source.as_str(),
+            self.duration_ns.map(|d| d.to_string()).unwrap_or_else(|| "null".to_string()),
+            params.join(","),
+            json_opt_str(self.column_type.map(|t| t.as_str())),
+        )
+    }
+
+    /// Whether the statement has a WHERE clause
+    pub fn has_where_clause(&self) -> bool {
+        sql_parser::parse(&self.full_query).has_where
+    }
+
+    /// new - old for numeric values (e.g. balance decreased by more than 1000)
+    pub fn numeric_delta(&self) -> Option<f64> {
+        crate::sql_value::numeric_delta(self.old_value.as_ref(), self.new_value.as_ref())
+    }
+
+    /// Value the statement assigns to `column`, if it writes it
+    pub fn param(&self, column: &str) -> Option<&SqlValue> {
+        self.params.iter().find(|(name, _)| name == column).map(|(_, value)| value)
+    }
+
+    fn from_c(c: &SQLChangeC) -> Self {
+        let text = |chars: &[c_char]| {
+            let bytes: Vec<u8> = chars.iter().take_while(|&&c| c != 0).map(|&c| c as u8).collect();
+            String::from_utf8_lossy(&bytes).into_owned()
+        };
+        let optional = |chars: &[c_char]| Some(text(chars)).filter(|s| !s.is_empty());
+        SQLChange {
+            timestamp_ns: c.timestamp_ns,
+            table_name: text(&c.table_name),
+            column_name: text(&c.column_name),
+            operation: SQLOperation::from_code(c.operation),
+            old_value: optional(&c.old_value).map(|v| SqlValue::infer(&v)),
+            new_value: optional(&c.new_value).map(|v| SqlValue::infer(&v)),
+            rows_affected: c.rows_affected,
+            database: optional(&c.database),
+            params: {
+                let statement = sql_parser::parse(&text(&c.full_query));
+                params(statement.operation, &statement.columns)
+            },
+            full_query: text(&c.full_query),
+            source: ValueSource::Caller,
+            duration_ns: None,
+            column_type: None,
+        }
+    }
+}
+
+/// Owned native tracker handle; never null
+struct NativeTracker {
+    api: NativeApi,
+    handle: *mut SQLTrackerC,
+}
+
+impl Drop for NativeTracker {
+    fn drop(&mut self) {
+        unsafe { (self.api.free)(self.handle) }
+    }
 }
 
 /// SQL Tracker instance
 pub struct SQLTracker {
-    tracker: *mut std::ffi::c_void,
+    native: Option<NativeTracker>,
     storage_path: Option<String>,
     changes: Vec<SQLChange>,
+    hooks: Vec<Hook<SQLChange>>,
+    alerts: AlertQueue,
+    schemas: SchemaRegistry,
+    violations: Vec<SchemaViolation>,
+    masks: MaskRules,
 }
 
+// The native handle is owned exclusively and only used through &mut self
+unsafe impl Send for SQLTracker {}
+
 impl SQLTracker {
-    /// Create new tracker
+    /// Create new tracker: native when libsql_tracker is available, pure Rust otherwise
     pub fn new(storage_path: Option<&str>) -> Self {
-        unsafe {
-            let path_c = storage_path.map(|p| CString::new(p).unwrap());
+        let native = NativeApi::resolve().and_then(|api| {
+            let path_c = storage_path.and_then(|p| CString::new(p).ok());
             let path_ptr = path_c.as_ref().map(|p| p.as_ptr()).unwrap_or(std::ptr::null());
-            
-            let tracker = sql_tracker_init(path_ptr);
-            
-            SQLTracker {
-                tracker,
-                storage_path: storage_path.map(|s| s.to_string()),
-                changes: Vec::new(),
-            }
+            let handle = unsafe { (api.init)(path_ptr) };
+            (!handle.is_null()).then_some(NativeTracker { api, handle })
+        });
+        SQLTracker {
+            native,
+            storage_path: storage_path.map(|s| s.to_string()),
+            changes: Vec::new(),
+            hooks: Vec::new(),
+            alerts: AlertQueue::default(),
+            schemas: SchemaRegistry::default(),
+            violations: Vec::new(),
+            masks: MaskRules::default(),
+        }
+    }
+
+    /// Create a tracker that never touches the native library
+    pub fn pure_rust(storage_path: Option<&str>) -> Self {
+        SQLTracker {
+            native: None,
+            storage_path: storage_path.map(|s| s.to_string()),
+            changes: Vec::new(),
+            hooks: Vec::new(),
+            alerts: AlertQueue::default(),
+            schemas: SchemaRegistry::default(),
+            violations: Vec::new(),
+            masks: MaskRules::default(),
+        }
+    }
+
+    /// Native unless the library is missing or mask rules are set
+    pub fn mode(&self) -> SqlMode {
+        if self.native().is_some() {
+            SqlMode::Native
+        } else {
+            SqlMode::PureRust
         }
     }
-    
-    /// Track a SQL query
+
+    pub fn storage_path(&self) -> Option<&str> {
+        self.storage_path.as_deref()
+    }
+
+    /// The native tracker, unless masks are set: it would persist the raw
+    /// query before masking could apply
+    fn native(&self) -> Option<&NativeTracker> {
+        self.native.as_ref().filter(|_| self.masks.is_empty())
+    }
+
+    /// Track a SQL query; returns the number of column changes recorded
+    /// (after pre-persist hooks)
     pub fn track_query(
         &mut self,
         query: &str,
@@ -112,23 +379,189 @@ impl SQLTracker {
         old_value: Option<&str>,
         new_value: Option<&str>,
     ) -> i32 {
-        unsafe {
-            let query_c = CString::new(query).unwrap();
-            let db_c = database.map(|d| CString::new(d).unwrap());
-            let old_c = old_value.map(|o| CString::new(o).unwrap());
-            let new_c = new_value.map(|n| CString::new(n).unwrap());
-            
-            sql_tracker_track_query(
-                self.tracker,
-                query_c.as_ptr(),
-                rows_affected,
-                db_c.as_ref().map(|c| c.as_ptr()).unwrap_or(std::ptr::null()),
-                old_c.as_ref().map(|c| c.as_ptr()).unwrap_or(std::ptr::null()),
-                new_c.as_ref().map(|c| c.as_ptr()).unwrap_or(std::ptr::null()),
-            )
+        let created = match self.native() {
+            Some(native) => track_native(native, query, rows_affected, database, old_value, new_value),
+            None => parse_changes(query, rows_affected, database, old_value, new_value),
+        };
+        self.record(created)
+    }
+
+    /// Track a prepared statement executed with `binds`: its placeholders
+    /// (`?` in order, `?N`, `$N` or `:N` by position) take the bound values
+    /// in `params` and, for bare placeholders, in `new_value`
+    pub fn track_query_with_binds(&mut self, query: &str, rows_affected: i32, database: Option<&str>, binds: &[SqlValue]) -> i32 {
+        let mut created = match self.native() {
+            Some(native) => track_native(native, query, rows_affected, database, None, None),
+            None => parse_changes(query, rows_affected, database, None, None),
+        };
+        for change in &mut created {
+            bind(&mut change.params, binds);
+            if matches!(change.new_value, Some(SqlValue::Expr(_))) {
+                if let Some(value) = change.param(&change.column_name).filter(|v| !matches!(v, SqlValue::Expr(_))) {
+                    change.new_value = Some(value.clone());
+                }
+            }
+        }
+        self.record(created)
+    }
+
+    /// Type columns of `table` and check queries against them; replaces an
+    /// earlier schema of the table. See the sql_schema module.
+    pub fn register_schema(&mut self, table: &str, columns: &[(&str, ColumnType)]) {
+        self.schemas.register(table, columns);
+    }
+
+    /// Queries naming columns their registered schema lacks, since the last
+    /// call
+    pub fn take_schema_violations(&mut self) -> Vec<SchemaViolation> {
+        std::mem::take(&mut self.violations)
+    }
+
+    /// Replace values of `table.column` ("*" for any table) before any
+    /// hook, storage or export sees them; from then on queries bypass the
+    /// native tracker. See the sql_mask module.
+    pub fn add_mask(&mut self, table: &str, column: &str, mask: Mask) {
+        self.masks.add(table, column, mask);
+    }
+
+    /// Whether a mask rule covers `table.column`
+    pub fn is_masked(&self, table: &str, column: &str) -> bool {
+        self.masks.get(table, column).is_some()
+    }
+
+    /// Track the result of a SELECT: one Select change per column read,
+    /// with rows_affected the number of rows and new_value the first row's
+    /// value (masked like any other), so hooks see reads as well as writes.
+    /// Each row lists (column, value). Returns the number of changes
+    /// recorded.
+    pub fn track_select_result(&mut self, query: &str, rows: &[Vec<(String, SqlValue)>]) -> i32 {
+        let normalized = normalize(query);
+        let statement = sql_parser::parse(&normalized);
+        let Some(table_name) = statement.table.filter(|_| statement.operation == SQLOperation::Select) else {
+            return 0;
+        };
+        let mut columns: Vec<String> = Vec::new();
+        for (column, _) in rows.iter().flatten() {
+            if !columns.contains(column) {
+                columns.push(column.clone());
+            }
+        }
+        if rows.is_empty() {
+            columns = statement.columns.into_iter().map(|(column, _)| column).collect();
+        }
+        let timestamp_ns = now_ns();
+        let first = rows.first();
+        let created = columns
+            .into_iter()
+            .map(|column_name| SQLChange {
+                timestamp_ns,
+                table_name: table_name.clone(),
+                new_value: first.and_then(|row| row.iter().find(|(c, _)| *c == column_name)).map(|(_, v)| v.clone()),
+                column_name,
+                operation: SQLOperation::Select,
+                old_value: None,
+                rows_affected: rows.len().min(i32::MAX as usize) as i32,
+                database: None,
+                full_query: normalized.clone(),
+                source: ValueSource::Caller,
+                duration_ns: None,
+                params: Vec::new(),
+                column_type: None,
+            })
+            .collect();
+        self.record(created)
+    }
+
+    /// Mask and type `created`, then run hooks over it, keeping what they
+    /// let through
+    fn record(&mut self, mut created: Vec<SQLChange>) -> i32 {
+        if !self.masks.is_empty() {
+            let mut masked = false;
+            for change in &mut created {
+                masked |= self.mask(change);
+            }
+            // The statement's literals would give the values away
+            if masked {
+                for change in &mut created {
+                    change.full_query = sql_mask::strip_literals(&change.full_query);
+                }
+            }
         }
+        for change in &mut created {
+            match self.schemas.lookup(&change.table_name, &change.column_name) {
+                Lookup::Known(column_type) => change.column_type = Some(column_type),
+                Lookup::Unknown => self.violations.push(SchemaViolation {
+                    timestamp_ns: change.timestamp_ns,
+                    table_name: change.table_name.clone(),
+                    column_name: change.column_name.clone(),
+                    full_query: change.full_query.clone(),
+                }),
+                Lookup::Unchecked => {}
+            }
+        }
+        let before = self.changes.len();
+        for change in created {
+            let subject = || format!("{}.{}", change.table_name, change.column_name);
+            if policy::evaluate(&self.hooks, &change, subject, change.timestamp_ns, &mut self.alerts) {
+                self.changes.push(change);
+            }
+        }
+        (self.changes.len() - before) as i32
+    }
+
+    /// Mask the values of one change; true if any was masked
+    fn mask(&self, change: &mut SQLChange) -> bool {
+        let table = change.table_name.clone();
+        let mut masked = false;
+        if let Some(mask) = self.masks.get(&table, &change.column_name) {
+            change.old_value = change.old_value.as_ref().map(|v| mask.apply(v));
+            change.new_value = change.new_value.as_ref().map(|v| mask.apply(v));
+            masked = true;
+        }
+        for (column, value) in &mut change.params {
+            if let Some(mask) = self.masks.get(&table, column) {
+                *value = mask.apply(value);
+                masked = true;
+            }
+        }
+        masked
+    }
+
+    /// Add a pre-persist hook that can veto recording a change or raise an
+    /// alert, e.g. for an UPDATE without WHERE on `payments`. Advisory only:
+    /// the statement itself is not blocked.
+    pub fn add_pre_persist_hook<F>(&mut self, hook: F)
+    where
+        F: Fn(&SQLChange) -> Decision + Send + 'static,
+    {
+        self.hooks.push(Box::new(hook));
     }
-    
+
+    /// Alerts raised by hooks since the last call
+    pub fn take_alerts(&mut self) -> Vec<Alert> {
+        self.alerts.take()
+    }
+
+    /// Hold back alerts on a subject the same hook raised one for less than
+    /// `window` ago, reporting them as one aggregate alert; see the policy
+    /// module. None reports every alert.
+    pub fn set_alert_suppression(&mut self, window: Option<std::time::Duration>) {
+        self.alerts.set_suppression(window);
+    }
+
+    /// Track a query whose rows_affected, database and duration come from
+    /// the driver (see `sql_driver`); returns the number of changes recorded
+    pub fn track_execution(&mut self, query: &str, execution: &Execution) -> i32 {
+        let rows = execution.rows_affected.map(|r| r.min(i32::MAX as u64) as i32).unwrap_or(-1);
+        let before = self.changes.len();
+        let count = self.track_query(query, rows, execution.database.as_deref(), None, None);
+        for change in &mut self.changes[before..] {
+            change.source = ValueSource::Driver;
+            change.duration_ns = execution.duration_ns;
+        }
+        count
+    }
+
     /// Get changes with optional filters
     pub fn get_changes(
         &self,
@@ -159,19 +592,19 @@ impl SQLTracker {
             .cloned()
             .collect()
     }
-    
+
     /// Get all changes
     pub fn all_changes(&self) -> &[SQLChange] {
         &self.changes
     }
-    
+
     /// Get summary statistics
     pub fn summary(&self) -> Summary {
         let mut summary = Summary::default();
-        
+
         for change in &self.changes {
             summary.total_changes += 1;
-            
+
             match change.operation {
                 SQLOperation::Insert => summary.insert_count += 1,
                 SQLOperation::Update => summary.update_count += 1,
@@ -179,89 +612,212 @@ impl SQLTracker {
                 SQLOperation::Select => summary.select_count += 1,
                 _ => {}
             }
-            
+
             summary.tables.entry(change.table_name.clone())
                 .and_modify(|e| *e += 1)
                 .or_insert(1);
-            
+
             summary.columns.insert(format!("{}.{}", change.table_name, change.column_name));
         }
-        
+
         summary
     }
 }
 
-impl Drop for SQLTracker {
-    fn drop(&mut self) {
-        unsafe {
-            if !self.tracker.is_null() {
-                sql_tracker_free(self.tracker);
+/// Run the native parser and copy the changes it appended
+fn track_native(
+    native: &NativeTracker,
+    query: &str,
+    rows_affected: i32,
+    database: Option<&str>,
+    old_value: Option<&str>,
+    new_value: Option<&str>,
+) -> Vec<SQLChange> {
+    let Ok(query_c) = CString::new(query) else {
+        return Vec::new();
+    };
+    let db_c = database.and_then(|d| CString::new(d).ok());
+    let old_c = old_value.and_then(|o| CString::new(o).ok());
+    let new_c = new_value.and_then(|n| CString::new(n).ok());
+    let ptr_of = |c: &Option<CString>| c.as_ref().map(|c| c.as_ptr()).unwrap_or(std::ptr::null());
+
+    unsafe {
+        let created = (native.api.track_query)(
+            native.handle,
+            query_c.as_ptr(),
+            rows_affected,
+            ptr_of(&db_c),
+            ptr_of(&old_c),
+            ptr_of(&new_c),
+        );
+        let tracker = &*native.handle;
+        if created <= 0 || tracker.changes.is_null() || created > tracker.change_count {
+            return Vec::new();
+        }
+        let all = std::slice::from_raw_parts(tracker.changes, tracker.change_count as usize);
+        all[(tracker.change_count - created) as usize..]
+            .iter()
+            .map(SQLChange::from_c)
+            .collect()
+    }
+}
+
+/// Pure-Rust counterpart of sql_tracker_track_query
+pub(crate) fn parse_changes(
+    query: &str,
+    rows_affected: i32,
+    database: Option<&str>,
+    old_value: Option<&str>,
+    new_value: Option<&str>,
+) -> Vec<SQLChange> {
+    let normalized = normalize(query);
+    let statement = sql_parser::parse(&normalized);
+    let Some(table_name) = statement.table.filter(|_| statement.operation != SQLOperation::Unknown) else {
+        return Vec::new();
+    };
+
+    let timestamp_ns = now_ns();
+    let old_value = old_value.map(SqlValue::infer);
+    let new_value = new_value.map(SqlValue::infer);
+    let params = params(statement.operation, &statement.columns);
+    statement
+        .columns
+        .into_iter()
+        .map(|(column_name, literal)| SQLChange {
+            timestamp_ns,
+            table_name: table_name.clone(),
+            column_name,
+            operation: statement.operation,
+            old_value: old_value.clone(),
+            new_value: new_value.clone().or_else(|| literal.as_deref().map(SqlValue::parse)),
+            rows_affected,
+            database: database.map(str::to_string),
+            full_query: normalized.clone(),
+            source: ValueSource::Caller,
+            duration_ns: None,
+            params: params.clone(),
+            column_type: None,
+        })
+        .collect()
+}
+
+/// Columns an INSERT or UPDATE assigns a value to, with that value
+fn params(operation: SQLOperation, columns: &[(String, Option<String>)]) -> Vec<(String, SqlValue)> {
+    if !matches!(operation, SQLOperation::Insert | SQLOperation::Update) {
+        return Vec::new();
+    }
+    columns
+        .iter()
+        .filter_map(|(column, literal)| Some((column.clone(), SqlValue::parse(literal.as_deref()?))))
+        .collect()
+}
+
+/// Replace placeholder values with `binds`; anonymous `?` count across
+/// every value, in order, so one inside an expression still takes its slot
+fn bind(params: &mut [(String, SqlValue)], binds: &[SqlValue]) {
+    let mut next = 0;
+    for (_, value) in params.iter_mut() {
+        let SqlValue::Expr(text) = value else {
+            continue;
+        };
+        let tokens = sql_parser::tokenize(text);
+        // "?" not followed by a number ("?2" is numbered)
+        let anonymous = tokens
+            .iter()
+            .enumerate()
+            .filter(|(i, t)| t.text == "?" && !tokens.get(i + 1).is_some_and(|n| n.kind == TokenKind::Number && n.start == t.start + 1))
+            .count();
+        let slot = if text == "?" {
+            Some(next)
+        } else {
+            text.strip_prefix(['?', '$', ':']).and_then(|n| n.parse::<usize>().ok()).and_then(|n| n.checked_sub(1))
+        };
+        next += anonymous;
+        if let Some(bound) = slot.and_then(|slot| binds.get(slot)) {
+            *value = bound.clone();
+        }
+    }
+}
+
+/// Collapse whitespace outside string literals
+fn normalize(query: &str) -> String {
+    let mut out = String::with_capacity(query.len());
+    let mut quote: Option<char> = None;
+    let mut prev = '\0';
+    for c in query.trim().chars() {
+        match quote {
+            Some(q) => {
+                if c == q && prev != '\\' {
+                    quote = None;
+                }
+                out.push(c);
+            }
+            None if matches!(c, '\'' | '"' | '`') => {
+                quote = Some(c);
+                out.push(c);
+            }
+            None if c.is_whitespace() => {
+                if !out.ends_with(' ') {
+                    out.push(' ');
+                }
             }
+            None => out.push(c),
         }
+        prev = c;
     }
+    out
 }
 
 /// Summary statistics
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Summary {
     pub total_changes: usize,
     pub insert_count: usize,
     pub update_count: usize,
     pub delete_count: usize,
     pub select_count: usize,
-    pub tables: std::collections::HashMap<String, usize>,
-    pub columns: std::collections::HashSet<String>,
+    pub tables: HashMap<String, usize>,
+    pub columns: HashSet<String>,
 }
 
 // Global tracker
-static mut GLOBAL_TRACKER: Option<SQLTracker> = None;
+static GLOBAL_TRACKER: Mutex<Option<SQLTracker>> = Mutex::new(None);
 
-/// Initialize global tracker
-pub fn init(storage_path: Option<&str>) -> &'static mut SQLTracker {
-    unsafe {
-        if GLOBAL_TRACKER.is_some() {
-            GLOBAL_TRACKER = None;
-        }
-        GLOBAL_TRACKER = Some(SQLTracker::new(storage_path));
-        GLOBAL_TRACKER.as_mut().unwrap()
-    }
+/// Initialize (or replace) the global tracker
+pub fn init(storage_path: Option<&str>) -> MutexGuard<'static, Option<SQLTracker>> {
+    let mut global = GLOBAL_TRACKER.lock().unwrap_or_else(|e| e.into_inner());
+    *global = Some(SQLTracker::new(storage_path));
+    global
 }
 
-/// Get global tracker (must be initialized first)
-pub fn get() -> &'static mut SQLTracker {
-    unsafe {
-        if GLOBAL_TRACKER.is_none() {
-            GLOBAL_TRACKER = Some(SQLTracker::new(None));
-        }
-        GLOBAL_TRACKER.as_mut().unwrap()
+/// Get the global tracker, creating a default one on first use
+pub fn get() -> MutexGuard<'static, Option<SQLTracker>> {
+    let mut global = GLOBAL_TRACKER.lock().unwrap_or_else(|e| e.into_inner());
+    if global.is_none() {
+        *global = Some(SQLTracker::new(None));
     }
+    global
 }
 
-/// Example usage:
-/// 
-/// ```
-/// use sqltracker::*;
-/// 
-/// fn main() {
-///     let mut tracker = SQLTracker::new(Some("/tmp/sql_changes.jsonl"));
-///     
-///     tracker.track_query(
-///         "INSERT INTO users (name, email) VALUES ('Alice', 'alice@example.com')",
-///         1,
-///         Some("mydb"),
-///         None,
-///         None
-///     );
-///     
-///     let summary = tracker.summary();
-///     println!("Total changes: {}", summary.total_changes);
-/// }
-/// ```
+// Example usage:
+//
+//     use memwatch::sql_tracker::SQLTracker;
+//
+//     let mut tracker = SQLTracker::new(Some("/tmp/sql_changes.jsonl"));
+//     tracker.track_query(
+//         "INSERT INTO users (name, email) VALUES ('Alice', 'alice@example.com')",
+//         1,
+//         Some("mydb"),
+//         None,
+//         None,
+//     );
+//     println!("Total changes: {}", tracker.summary().total_changes);
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    
+
     #[test]
     fn test_operation_to_string() {
         assert_eq!(SQLOperation::Insert.as_str(), "INSERT");
@@ -269,4 +825,141 @@ mod tests {
         assert_eq!(SQLOperation::Delete.as_str(), "DELETE");
         assert_eq!(SQLOperation::Select.as_str(), "SELECT");
     }
+
+    #[test]
+    fn test_pure_rust_mode_extracts_columns() {
+        let mut tracker = SQLTracker::pure_rust(None);
+        assert_eq!(tracker.mode(), SqlMode::PureRust);
+
+        let created = tracker.track_query(
+            "INSERT INTO users (name, email) VALUES ('Alice', 'alice@example.com')",
+            1,
+            Some("mydb"),
+            None,
+            None,
+        );
+        assert_eq!(created, 2);
+        assert_eq!(tracker.track_query("update `accounts`  SET balance = 10, owner='x' WHERE id = 1", 1, None, None, None), 2);
+        assert_eq!(tracker.track_query("DELETE FROM sessions WHERE expired = 1", 3, None, None, None), 1);
+        assert_eq!(tracker.track_query("VACUUM", 0, None, None, None), 0);
+
+        let updates = tracker.get_changes(Some("accounts"), None, Some("UPDATE"));
+        let columns: Vec<&str> = updates.iter().map(|c| c.column_name.as_str()).collect();
+        assert_eq!(columns, vec!["balance", "owner"]);
+        assert_eq!(tracker.get_changes(Some("users"), Some("email"), None)[0].database.as_deref(), Some("mydb"));
+        assert_eq!(tracker.summary().delete_count, 1);
+    }
+
+    #[test]
+    fn test_values_are_typed_from_query_literals() {
+        let mut tracker = SQLTracker::pure_rust(None);
+        tracker.track_query("INSERT INTO users (name, age, note) VALUES ('O''Brien, Pat', 41, NULL), ('x', 1, 2)", 1, None, None, None);
+        let values: Vec<Option<SqlValue>> = tracker.all_changes().iter().map(|c| c.new_value.clone()).collect();
+        assert_eq!(values, vec![Some(SqlValue::Text("O'Brien, Pat".to_string())), Some(SqlValue::Int(41)), Some(SqlValue::Null)]);
+
+        tracker.track_query("UPDATE accounts SET balance = 3500.5 WHERE id = 7", 1, None, Some("5000"), None);
+        let update = tracker.get_changes(Some("accounts"), None, None).remove(0);
+        assert_eq!(update.new_value, Some(SqlValue::Float(3500.5)));
+        assert_eq!(update.numeric_delta(), Some(-1499.5));
+    }
+
+    #[test]
+    fn test_params_carry_assigned_values() {
+        let mut tracker = SQLTracker::pure_rust(None);
+        tracker.track_query("INSERT INTO users (name, age) VALUES ('Ann', 30)", 1, None, None, None);
+        let insert = &tracker.all_changes()[0];
+        assert_eq!(insert.params, vec![("name".to_string(), SqlValue::Text("Ann".to_string())), ("age".to_string(), SqlValue::Int(30))]);
+        assert!(insert.to_json().contains(",\"params\":{\"name\":\"Ann\",\"age\":\"30\"},"));
+
+        tracker.track_query_with_binds(
+            "UPDATE accounts SET balance = ?, note = lower(?), owner = $1 WHERE id = ?",
+            1,
+            None,
+            &[SqlValue::Int(10), SqlValue::Text("X".to_string()), SqlValue::Int(7)],
+        );
+        let update = tracker.get_changes(Some("accounts"), Some("balance"), None).remove(0);
+        assert_eq!(update.new_value, Some(SqlValue::Int(10)));
+        assert_eq!(update.param("note"), Some(&SqlValue::Expr("lower(?)".to_string())));
+        assert_eq!(update.param("owner"), Some(&SqlValue::Int(10)));
+
+        tracker.track_query("DELETE FROM sessions WHERE id = 1", 1, None, None, None);
+        assert!(tracker.get_changes(Some("sessions"), None, None)[0].params.is_empty());
+    }
+
+    #[test]
+    fn test_masks_keep_queries_out_of_the_native_tracker() {
+        let mut tracker = SQLTracker::new(None);
+        tracker.add_mask("users", "ssn", Mask::Redact);
+        assert_eq!(tracker.mode(), SqlMode::PureRust);
+        assert_eq!(tracker.track_query("UPDATE users SET ssn = '123-45-6789' WHERE id = 1", 1, None, None, None), 1);
+        let change = &tracker.all_changes()[0];
+        assert_eq!(change.new_value, Some(SqlValue::Text("***".to_string())));
+        assert!(!change.full_query.contains("6789"), "{}", change.full_query);
+    }
+
+    #[test]
+    fn test_select_results_are_tracked_and_masked() {
+        let mut tracker = SQLTracker::pure_rust(None);
+        tracker.add_mask("users", "ssn", Mask::KeepLast(4));
+        let reads = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = std::sync::Arc::clone(&reads);
+        tracker.add_pre_persist_hook(move |change| {
+            if change.operation == SQLOperation::Select {
+                seen.lock().unwrap().push((change.column_name.clone(), change.new_value.clone()));
+            }
+            Decision::Record
+        });
+
+        let row = |name: &str, ssn: &str| vec![("name".to_string(), SqlValue::Text(name.to_string())), ("ssn".to_string(), SqlValue::Text(ssn.to_string()))];
+        let rows = [row("Ann", "123-45-6789"), row("Bob", "987-65-4321")];
+        assert_eq!(tracker.track_select_result("SELECT name, ssn FROM users WHERE ssn = '123-45-6789'", &rows), 2);
+        let masked = Some(SqlValue::Text("*******6789".to_string()));
+        assert_eq!(*reads.lock().unwrap(), [("name".to_string(), Some(SqlValue::Text("Ann".to_string()))), ("ssn".to_string(), masked.clone())]);
+        let ssn = tracker.get_changes(Some("users"), Some("ssn"), Some("SELECT")).remove(0);
+        assert_eq!((ssn.rows_affected, ssn.new_value), (2, masked));
+        assert_eq!(ssn.full_query, "SELECT name, ssn FROM users WHERE ssn = ?");
+        assert!(!tracker.all_changes()[0].full_query.contains("6789"));
+
+        tracker.track_query("UPDATE users SET ssn = '555-12-3456', name = 'Cy' WHERE id = 3", 1, None, None, None);
+        let name = tracker.get_changes(Some("users"), Some("name"), Some("UPDATE")).remove(0);
+        assert_eq!(name.param("ssn"), Some(&SqlValue::Text("*******3456".to_string())));
+        assert_eq!(name.full_query, "UPDATE users SET ssn = ?, name = ? WHERE id = ?");
+        assert_eq!(tracker.track_select_result("SELECT * FROM users", &[]), 1);
+    }
+
+    #[test]
+    fn test_registered_schemas_type_and_check_columns() {
+        let mut tracker = SQLTracker::pure_rust(None);
+        tracker.register_schema("users", &[("id", ColumnType::Int), ("email", ColumnType::Text)]);
+        tracker.track_query("UPDATE users SET email = 'a@b.c', emial = 'x' WHERE id = 1", 1, None, None, None);
+        tracker.track_query("UPDATE orders SET total = 1", 1, None, None, None);
+
+        let types: Vec<Option<ColumnType>> = tracker.all_changes().iter().map(|c| c.column_type).collect();
+        assert_eq!(types, vec![Some(ColumnType::Text), None, None]);
+        let violations = tracker.take_schema_violations();
+        assert_eq!(violations.len(), 1);
+        assert_eq!((violations[0].table_name.as_str(), violations[0].column_name.as_str()), ("users", "emial"));
+        assert!(tracker.take_schema_violations().is_empty());
+    }
+
+    #[test]
+    fn test_hooks_veto_and_alert() {
+        let mut tracker = SQLTracker::pure_rust(None);
+        tracker.add_pre_persist_hook(|change| {
+            if change.table_name == "payments" && change.operation == SQLOperation::Update && !change.has_where_clause() {
+                Decision::AlertAndVeto("UPDATE without WHERE on payments".to_string())
+            } else if change.column_name == "audit_note" {
+                Decision::Veto
+            } else {
+                Decision::Record
+            }
+        });
+
+        assert_eq!(tracker.track_query("UPDATE payments SET status = 'void'", 500, None, None, None), 0);
+        assert_eq!(tracker.track_query("UPDATE payments SET status = 'paid', audit_note = 'x' WHERE id = 1", 1, None, None, None), 1);
+        let alerts = tracker.take_alerts();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].subject, "payments.status");
+        assert!(tracker.take_alerts().is_empty());
+    }
 }
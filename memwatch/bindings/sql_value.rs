@@ -0,0 +1,146 @@
+// Typed SQL literal values
+//
+// Old/new values of SQL changes are parsed from their literal text so
+// policies can compare numbers instead of strings (e.g. "balance decreased
+// by more than 1000").
+
+use std::fmt;
+
+use crate::export::parse_hex;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SqlValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+    /// X'..' or 0x.. literal
+    Blob(Vec<u8>),
+    /// Not a literal (column reference, function call, arithmetic, parameter)
+    Expr(String),
+}
+
+impl SqlValue {
+    /// Parse one SQL literal from a query; anything that is not a literal
+    /// becomes `Expr`
+    pub fn parse(literal: &str) -> SqlValue {
+        let s = literal.trim();
+        if s.eq_ignore_ascii_case("NULL") {
+            return SqlValue::Null;
+        }
+        if s.eq_ignore_ascii_case("TRUE") {
+            return SqlValue::Bool(true);
+        }
+        if s.eq_ignore_ascii_case("FALSE") {
+            return SqlValue::Bool(false);
+        }
+        if let Some(text) = unquote(s, '\'') {
+            return SqlValue::Text(text.replace("''", "'"));
+        }
+        if let Some(hex) = s
+            .strip_prefix(['x', 'X'])
+            .and_then(|rest| unquote(rest, '\''))
+            .or_else(|| s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).map(str::to_string))
+        {
+            if let Some(bytes) = parse_hex(&hex) {
+                return SqlValue::Blob(bytes);
+            }
+        }
+        if let Ok(i) = s.parse::<i64>() {
+            return SqlValue::Int(i);
+        }
+        if s.contains(['.', 'e', 'E']) {
+            if let Ok(f) = s.parse::<f64>() {
+                if f.is_finite() {
+                    return SqlValue::Float(f);
+                }
+            }
+        }
+        SqlValue::Expr(s.to_string())
+    }
+
+    /// Type a raw value handed in by a caller: like parse(), but plain text
+    /// is `Text` rather than `Expr`
+    pub fn infer(raw: &str) -> SqlValue {
+        match SqlValue::parse(raw) {
+            SqlValue::Expr(_) => SqlValue::Text(raw.to_string()),
+            value => value,
+        }
+    }
+
+    /// Numeric view of Int/Float/Bool values
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            SqlValue::Int(i) => Some(*i as f64),
+            SqlValue::Float(f) => Some(*f),
+            SqlValue::Bool(b) => Some(*b as i64 as f64),
+            _ => None,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            SqlValue::Null => "null",
+            SqlValue::Bool(_) => "bool",
+            SqlValue::Int(_) => "int",
+            SqlValue::Float(_) => "float",
+            SqlValue::Text(_) => "text",
+            SqlValue::Blob(_) => "blob",
+            SqlValue::Expr(_) => "expr",
+        }
+    }
+
+    /// SQL literal form, text quoted; expressions as they are
+    pub fn to_sql(&self) -> String {
+        match self {
+            SqlValue::Text(s) => format!("'{}'", s.replace('\'', "''")),
+            value => value.to_string(),
+        }
+    }
+}
+
+/// new - old when both sides are numeric
+pub fn numeric_delta(old: Option<&SqlValue>, new: Option<&SqlValue>) -> Option<f64> {
+    Some(new?.as_f64()? - old?.as_f64()?)
+}
+
+impl fmt::Display for SqlValue {
+    /// SQL literal form, except that text is shown without quotes
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SqlValue::Null => f.write_str("NULL"),
+            SqlValue::Bool(b) => f.write_str(if *b { "TRUE" } else { "FALSE" }),
+            SqlValue::Int(i) => write!(f, "{}", i),
+            SqlValue::Float(x) => write!(f, "{}", x),
+            SqlValue::Text(s) | SqlValue::Expr(s) => f.write_str(s),
+            SqlValue::Blob(bytes) => write!(f, "X'{}'", crate::export::hex(bytes).to_uppercase()),
+        }
+    }
+}
+
+fn unquote(s: &str, quote: char) -> Option<String> {
+    let inner = s.strip_prefix(quote)?.strip_suffix(quote)?;
+    Some(inner.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_literal_types() {
+        assert_eq!(SqlValue::parse("NULL"), SqlValue::Null);
+        assert_eq!(SqlValue::parse("true"), SqlValue::Bool(true));
+        assert_eq!(SqlValue::parse("-42"), SqlValue::Int(-42));
+        assert_eq!(SqlValue::parse("3.5"), SqlValue::Float(3.5));
+        assert_eq!(SqlValue::parse("'it''s'"), SqlValue::Text("it's".to_string()));
+        assert_eq!(SqlValue::parse("X'0aFF'"), SqlValue::Blob(vec![0x0a, 0xff]));
+        assert_eq!(SqlValue::parse("0x01"), SqlValue::Blob(vec![1]));
+        assert_eq!(SqlValue::parse("balance - 10"), SqlValue::Expr("balance - 10".to_string()));
+        assert_eq!(SqlValue::infer("alice"), SqlValue::Text("alice".to_string()));
+        assert_eq!(numeric_delta(Some(&SqlValue::Int(5000)), Some(&SqlValue::Float(3500.5))), Some(-1499.5));
+        assert_eq!(numeric_delta(Some(&SqlValue::Null), Some(&SqlValue::Int(1))), None);
+    }
+}
@@ -0,0 +1,315 @@
+// Content-addressed value storage
+//
+// A BlobStore keeps values as files named by the SHA-256 of their contents,
+// fanned out over directories by the first two hex digits:
+//
+//   <dir>/3f/a1c9...   (the remaining 62 digits)
+//
+// The key is the full 64-digit digest, so identical values are stored once,
+// however many events carry them. A blob is written to a temporary file and
+// renamed into place, so a crash never leaves a partial blob under a key,
+// and an existing blob is never rewritten. The blobs of one check are
+// written first and made durable together (one syncfs on Linux) before any
+// is renamed, so a poll pays for one sync rather than one per value.
+//
+// With a store set, check_changes() puts the old and new values of each
+// change of at least min_bytes (MIN_VALUE_BYTES unless set) and fills in
+// storage_key_old / storage_key_new; the values stay in the event too, so
+// sinks can drop them and keep the keys. fetch_value(key) reads one back,
+// or a value the C core stored under a key of its own:
+//
+//   watcher.set_blob_store(Some(BlobStore::open("blobs")?));
+//   for event in watcher.check_changes()? {
+//       if let Some(key) = &event.storage_key_new {
+//           let value = watcher.fetch_value(key)?;
+//       }
+//   }
+//
+// Values are the bytes events carry: whole regions with shadow pages or in
+// Snapshot mode, at most max_value_bytes otherwise.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::export::hex;
+use crate::{ChangeEvent, EventKind, MemWatch};
+
+/// Smallest value stored by default, as for the C core's storage keys
+pub const MIN_VALUE_BYTES: usize = 4096;
+
+/// Values on disk by content hash; cheap to clone
+#[derive(Debug, Clone)]
+pub struct BlobStore {
+    dir: PathBuf,
+    min_bytes: usize,
+}
+
+impl BlobStore {
+    /// Open (or create) the store in `dir`
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, String> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        Ok(BlobStore { dir, min_bytes: MIN_VALUE_BYTES })
+    }
+
+    /// Store values from `min_bytes` up (0 stores every non-empty value)
+    pub fn with_min_bytes(mut self, min_bytes: usize) -> Self {
+        self.min_bytes = min_bytes;
+        self
+    }
+
+    /// Store a value, returning its key; a value already stored is not
+    /// written again
+    pub fn put(&self, value: &[u8]) -> Result<String, String> {
+        let mut batch = Batch::default();
+        let key = self.stage(value, &mut batch)?;
+        self.commit(batch)?;
+        Ok(key)
+    }
+
+    /// Write `value` to a temporary file unless it is stored or staged
+    /// already; commit() makes it durable and puts it in place
+    fn stage(&self, value: &[u8], batch: &mut Batch) -> Result<String, String> {
+        let key = hex(&Sha256::digest(value));
+        let path = self.path(&key);
+        if path.exists() || batch.pending.iter().any(|(_, staged, _)| *staged == path) {
+            return Ok(key);
+        }
+        let parent = path.parent().expect("blob path has a fan-out directory");
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        // Unique per writer, so concurrent puts of one value cannot collide
+        let tmp = parent.join(format!(".{}.{}.{:?}", &key[2..], std::process::id(), std::thread::current().id()));
+        let file = fs::File::create(&tmp).and_then(|mut file| file.write_all(value).map(|_| file));
+        match file {
+            Ok(file) => batch.pending.push((tmp, path, file)),
+            Err(e) => {
+                let _ = fs::remove_file(&tmp);
+                return Err(format!("Failed to write {}: {}", path.display(), e));
+            }
+        }
+        Ok(key)
+    }
+
+    /// Sync the staged blobs, then rename them into place
+    fn commit(&self, mut batch: Batch) -> Result<(), String> {
+        if batch.pending.is_empty() {
+            return Ok(());
+        }
+        self.sync(&batch).map_err(|e| format!("Failed to sync {}: {}", self.dir.display(), e))?;
+        while let Some((tmp, path, _)) = batch.pending.pop() {
+            if let Err(e) = fs::rename(&tmp, &path) {
+                let _ = fs::remove_file(&tmp);
+                return Err(format!("Failed to write {}: {}", path.display(), e));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn sync(&self, batch: &Batch) -> std::io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        // One flush of the whole filesystem beats an fsync per blob
+        let (_, _, file) = &batch.pending[0];
+        if unsafe { libc::syncfs(file.as_raw_fd()) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn sync(&self, batch: &Batch) -> std::io::Result<()> {
+        batch.pending.iter().try_for_each(|(_, _, file)| file.sync_all())
+    }
+
+    /// The value stored under `key`, checked against it
+    pub fn get(&self, key: &str) -> Result<Vec<u8>, String> {
+        if !is_key(key) {
+            return Err(format!("Bad blob key {:?}", key));
+        }
+        let path = self.path(key);
+        let value = fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if hex(&Sha256::digest(&value)) != key {
+            return Err(format!("Blob {} does not match its key", path.display()));
+        }
+        Ok(value)
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        is_key(key) && self.path(key).exists()
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(&key[..2]).join(&key[2..])
+    }
+
+    /// Put the values of changes that have none stored yet and set their
+    /// keys; the batch is synced once
+    pub(crate) fn store(&self, events: &mut [ChangeEvent]) -> Result<(), String> {
+        let mut batch = Batch::default();
+        let mut keys = Vec::new();
+        for (i, event) in events.iter().enumerate().filter(|(_, e)| e.kind == EventKind::Change) {
+            if event.storage_key_old.is_none() && self.wants(&event.old_value) {
+                keys.push((i, false, self.stage(&event.old_value, &mut batch)?));
+            }
+            if event.storage_key_new.is_none() && self.wants(&event.new_value) {
+                keys.push((i, true, self.stage(&event.new_value, &mut batch)?));
+            }
+        }
+        // No event carries a key before its blob is in place
+        self.commit(batch)?;
+        for (i, new, key) in keys {
+            if new {
+                events[i].storage_key_new = Some(key);
+            } else {
+                events[i].storage_key_old = Some(key);
+            }
+        }
+        Ok(())
+    }
+
+    fn wants(&self, value: &[u8]) -> bool {
+        !value.is_empty() && value.len() >= self.min_bytes
+    }
+}
+
+/// Blobs written but not yet synced and renamed: (temporary, final, file)
+#[derive(Default)]
+struct Batch {
+    pending: Vec<(PathBuf, PathBuf, fs::File)>,
+}
+
+impl Drop for Batch {
+    /// A batch given up on leaves no temporary files behind
+    fn drop(&mut self) {
+        for (tmp, _, _) in &self.pending {
+            let _ = fs::remove_file(tmp);
+        }
+    }
+}
+
+/// 64 lowercase hex digits; anything else could name a path outside the store
+fn is_key(key: &str) -> bool {
+    key.len() == 64 && key.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+impl MemWatch {
+    /// Store change values in `store` and key events to them; see the
+    /// storage::BlobStore docs. `None` stops storing.
+    pub fn set_blob_store(&self, store: Option<BlobStore>) {
+        *self.blobs.lock().unwrap() = store;
+    }
+
+    /// A value stored under storage_key_old or storage_key_new: by the blob
+    /// store for its own keys, else by the backend (the C core keys large
+    /// values its own way)
+    pub fn fetch_value(&self, key: &str) -> Result<Vec<u8>, String> {
+        if is_key(key) {
+            if let Some(store) = self.blobs.lock().unwrap().as_ref() {
+                if store.contains(key) {
+                    return store.get(key);
+                }
+            }
+        }
+        self.backend().fetch_value(key).map_err(|e| {
+            if self.blobs.lock().unwrap().is_none() {
+                format!("{}; no blob store set, see set_blob_store()", e)
+            } else {
+                e
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("memwatch_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn files(dir: &Path) -> usize {
+        fs::read_dir(dir).unwrap().map(|entry| fs::read_dir(entry.unwrap().path()).unwrap().count()).sum()
+    }
+
+    #[test]
+    fn test_values_are_stored_once_by_content() {
+        let dir = temp_dir("blobs");
+        let store = BlobStore::open(&dir).unwrap();
+        let key = store.put(b"hello").unwrap();
+        assert_eq!(key, "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824");
+        assert_eq!(store.put(b"hello").unwrap(), key);
+        let other = store.put(b"world").unwrap();
+        assert_eq!(files(&dir), 2);
+        assert_eq!(store.get(&key).unwrap(), b"hello");
+        assert_eq!(store.get(&other).unwrap(), b"world");
+        assert!(store.contains(&key));
+
+        assert!(store.get("../../etc/passwd").is_err());
+        assert!(store.get(&"0".repeat(64)).is_err());
+        fs::write(store.path(&key), b"tampered").unwrap();
+        assert!(store.get(&key).unwrap_err().contains("does not match"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(not(any(feature = "native", feature = "pure")))]
+    #[test]
+    fn test_check_changes_keys_values_to_the_store() {
+        use crate::capabilities::WatchMode;
+        use crate::fake_native;
+        use crate::options::WatchOptions;
+
+        let _guard = fake_native::lock();
+        let dir = temp_dir("blobs_watch");
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        assert!(watcher.fetch_value(&"0".repeat(64)).is_err());
+        watcher.set_blob_store(Some(BlobStore::open(&dir).unwrap().with_min_bytes(8)));
+        let mut buffer = vec![0u8; 16];
+        let options = WatchOptions { max_value_bytes: Some(-1), ..Default::default() };
+        watcher.watch_with_options(&buffer, "buffer", &options).unwrap();
+
+        buffer[3] = 1;
+        let events: Vec<ChangeEvent> = watcher.check_changes().unwrap().into_iter().filter(|e| e.kind == EventKind::Change).collect();
+        assert_eq!(events.len(), 1);
+        let (old, new) = (events[0].storage_key_old.as_ref().unwrap(), events[0].storage_key_new.as_ref().unwrap());
+        assert_eq!(watcher.fetch_value(old).unwrap(), [0u8; 16]);
+        assert_eq!(watcher.fetch_value(new).unwrap(), buffer);
+
+        // Back to all zeroes: the old value of the first change, not stored again
+        buffer[3] = 0;
+        let events: Vec<ChangeEvent> = watcher.check_changes().unwrap().into_iter().filter(|e| e.kind == EventKind::Change).collect();
+        assert_eq!(events[0].storage_key_new.as_ref(), Some(old));
+        assert_eq!(files(&dir), 2);
+
+        // Keys the C core handed out resolve through it
+        fake_native::state().values.push(("region:1:seq:7".to_string(), vec![7u8; 5000]));
+        watcher.capabilities.mode = WatchMode::Protect;
+        assert_eq!(watcher.fetch_value("region:1:seq:7").unwrap(), vec![7u8; 5000]);
+        assert!(watcher.fetch_value("region:1:seq:8").is_err());
+        assert_eq!(watcher.fetch_value(old).unwrap(), [0u8; 16]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_a_batch_is_staged_then_committed() {
+        let dir = temp_dir("blobs_batch");
+        let store = BlobStore::open(&dir).unwrap().with_min_bytes(1);
+        let change = |old: &[u8], new: &[u8]| crate::custom_backend::change_event(1, "buffer", old, new);
+        let mut events = vec![change(b"a", b"b"), change(b"b", b"c"), change(b"c", b"a")];
+        store.store(&mut events).unwrap();
+        // Three distinct values, each written once, no temporary files left
+        assert_eq!(files(&dir), 3);
+        for event in &events {
+            assert_eq!(store.get(event.storage_key_old.as_ref().unwrap()).unwrap(), event.old_value);
+            assert_eq!(store.get(event.storage_key_new.as_ref().unwrap()).unwrap(), event.new_value);
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,659 @@
+// Persistent value history with snapshot + delta compaction
+//
+// The store directory holds two files:
+//
+//   base     "<region_id>\t<from_seq>\t<hex>": region contents before the
+//            change with global_seq from_seq; later lines win
+//   deltas   "<region_id>\t<global_seq>\t<timestamp_ns>\t<offset>\t<len>\t<hex>":
+//            each change as the changed byte range, and the value length
+//            after it
+//
+// Replay starts from a region's base and applies its deltas in order, so its
+// cost grows with the deltas kept. compact() folds all but the most recent
+// deltas of every region into its base; a Compactor does that periodically
+// on a background thread. The base is replaced before the deltas, so a crash
+// in between only leaves deltas that replay skips as already folded.
+//
+// Each region's stored changes are also indexed in memory by timestamp, so
+// first_change_after(), last_change_before() and nth_change() are binary
+// searches rather than scans of the deltas file.
+//
+// With a Retention set, compaction also folds changes older than the raw
+// window, whatever keep_deltas says, and keeps them as per-minute aggregates
+// in a third file:
+//
+//   aggregates  "<region_id>\t<span_secs>\t<start_ns>\t<changes>\t<bytes_changed>\t<first_seq>\t<last_seq>"
+//
+// Minute buckets older than the minute window are rolled into hour buckets,
+// and hour buckets past their window are dropped, so always-on recording
+// stays bounded. Aggregates are replaced after the base: a crash in between
+// loses the counts of that compaction's folded changes rather than doubling
+// them.
+//
+// Values are the bytes events carry: whole regions with shadow pages or in
+// Snapshot mode, at most max_value_bytes otherwise. Global sequence numbers
+// only keep increasing across restarts with sequence persistence enabled.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::export::{hex, parse_hex};
+use crate::lifecycle::now_ns;
+use crate::sink::EventSink;
+use crate::ChangeEvent;
+
+const BASE: &str = "base";
+const DELTAS: &str = "deltas";
+const AGGREGATES: &str = "aggregates";
+const MINUTE: Duration = Duration::from_secs(60);
+const HOUR: Duration = Duration::from_secs(3600);
+
+/// Snapshot + delta history of region values; a sink, and cheap to clone
+#[derive(Clone)]
+pub struct HistoryStore {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    dir: PathBuf,
+    deltas: BufWriter<File>,
+    /// Regions with a base line
+    based: HashSet<u32>,
+    /// Stored changes per region, by (timestamp_ns, global_seq)
+    index: HashMap<u32, Vec<IndexedChange>>,
+    keep_deltas: usize,
+    retention: Option<Retention>,
+}
+
+/// How long each tier of history is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    /// Individual changes, replayable with state_at()
+    pub raw: Duration,
+    /// Per-minute aggregates
+    pub minutes: Duration,
+    /// Per-hour aggregates; None keeps them for good
+    pub hours: Option<Duration>,
+}
+
+impl Default for Retention {
+    /// Raw changes for an hour, minutes for a day, hours for good
+    fn default() -> Self {
+        Retention {
+            raw: HOUR,
+            minutes: Duration::from_secs(24 * 3600),
+            hours: None,
+        }
+    }
+}
+
+/// Changes of a region within one minute or hour, kept after the changes
+/// themselves were folded away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Aggregate {
+    pub region_id: u32,
+    /// Bucket start, nanoseconds since the Unix epoch
+    pub start_ns: u64,
+    /// Bucket length: a minute or an hour
+    pub span: Duration,
+    pub changes: u64,
+    /// Lengths of the changed byte ranges, summed
+    pub bytes_changed: u64,
+    pub first_seq: u64,
+    pub last_seq: u64,
+}
+
+/// A stored change, as found by the time index; pass its global_seq to
+/// state_at() for the contents right after it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IndexedChange {
+    pub timestamp_ns: u64,
+    pub global_seq: u64,
+}
+
+struct Delta {
+    region_id: u32,
+    seq: u64,
+    timestamp_ns: u64,
+    offset: usize,
+    len: usize,
+    bytes: Vec<u8>,
+}
+
+impl Delta {
+    fn apply(&self, value: &mut Vec<u8>) {
+        value.resize(self.len, 0);
+        value[self.offset..self.offset + self.bytes.len()].copy_from_slice(&self.bytes);
+    }
+
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\n",
+            self.region_id,
+            self.seq,
+            self.timestamp_ns,
+            self.offset,
+            self.len,
+            hex(&self.bytes)
+        )
+    }
+
+    fn parse(line: &str) -> Option<Delta> {
+        let mut fields = line.split('\t');
+        let delta = Delta {
+            region_id: fields.next()?.parse().ok()?,
+            seq: fields.next()?.parse().ok()?,
+            timestamp_ns: fields.next()?.parse().ok()?,
+            offset: fields.next()?.parse().ok()?,
+            len: fields.next()?.parse().ok()?,
+            bytes: parse_hex(fields.next()?)?,
+        };
+        (delta.offset + delta.bytes.len() <= delta.len).then_some(delta)
+    }
+
+    fn indexed(&self) -> IndexedChange {
+        IndexedChange {
+            timestamp_ns: self.timestamp_ns,
+            global_seq: self.seq,
+        }
+    }
+
+    fn aggregate(&self) -> Aggregate {
+        Aggregate {
+            region_id: self.region_id,
+            start_ns: bucket_start(self.timestamp_ns, MINUTE),
+            span: MINUTE,
+            changes: 1,
+            bytes_changed: self.bytes.len() as u64,
+            first_seq: self.seq,
+            last_seq: self.seq,
+        }
+    }
+}
+
+impl Aggregate {
+    fn end_ns(&self) -> u64 {
+        self.start_ns + self.span.as_nanos() as u64
+    }
+
+    fn merge(&mut self, other: &Aggregate) {
+        self.changes += other.changes;
+        self.bytes_changed += other.bytes_changed;
+        self.first_seq = self.first_seq.min(other.first_seq);
+        self.last_seq = self.last_seq.max(other.last_seq);
+    }
+
+    fn to_line(self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            self.region_id,
+            self.span.as_secs(),
+            self.start_ns,
+            self.changes,
+            self.bytes_changed,
+            self.first_seq,
+            self.last_seq
+        )
+    }
+
+    fn parse(line: &str) -> Option<Aggregate> {
+        let mut fields = line.split('\t');
+        Some(Aggregate {
+            region_id: fields.next()?.parse().ok()?,
+            span: Duration::from_secs(fields.next()?.parse().ok()?),
+            start_ns: fields.next()?.parse().ok()?,
+            changes: fields.next()?.parse().ok()?,
+            bytes_changed: fields.next()?.parse().ok()?,
+            first_seq: fields.next()?.parse().ok()?,
+            last_seq: fields.next()?.parse().ok()?,
+        })
+    }
+}
+
+/// Stops periodic compaction when dropped
+pub struct Compactor {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HistoryStore {
+    /// Open (or create) the store in `dir`; compaction keeps the last
+    /// `keep_deltas` changes of each region replayable one by one
+    pub fn open(dir: impl AsRef<Path>, keep_deltas: usize) -> Result<Self, String> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let base = read_base(&dir)?;
+        let stored = read_deltas(&dir)?
+            .into_iter()
+            .filter(|delta| base.get(&delta.region_id).is_some_and(|(from_seq, _)| delta.seq >= *from_seq));
+        let index = build_index(stored);
+        Ok(HistoryStore {
+            inner: Arc::new(Mutex::new(Inner {
+                deltas: open_deltas(&dir)?,
+                dir,
+                based: base.into_keys().collect(),
+                index,
+                keep_deltas,
+                retention: None,
+            })),
+        })
+    }
+
+    /// Downsample instead of only folding: see Retention
+    pub fn with_retention(self, retention: Retention) -> Self {
+        self.inner.lock().unwrap().retention = Some(retention);
+        self
+    }
+
+    /// Aggregates kept for a region, oldest first (minutes and hours mixed)
+    pub fn aggregates(&self, region_id: u32) -> Result<Vec<Aggregate>, String> {
+        let inner = self.inner.lock().unwrap();
+        let mut aggregates: Vec<Aggregate> =
+            read_aggregates(&inner.dir)?.into_iter().filter(|a| a.region_id == region_id).collect();
+        aggregates.sort_by_key(|a| a.start_ns);
+        Ok(aggregates)
+    }
+
+    /// Earliest stored change of a region later than `timestamp_ns`
+    pub fn first_change_after(&self, region_id: u32, timestamp_ns: u64) -> Option<IndexedChange> {
+        let inner = self.inner.lock().unwrap();
+        let changes = inner.index.get(&region_id)?;
+        changes.get(changes.partition_point(|c| c.timestamp_ns <= timestamp_ns)).copied()
+    }
+
+    /// Latest stored change of a region earlier than `timestamp_ns`
+    pub fn last_change_before(&self, region_id: u32, timestamp_ns: u64) -> Option<IndexedChange> {
+        let inner = self.inner.lock().unwrap();
+        let changes = inner.index.get(&region_id)?;
+        changes.partition_point(|c| c.timestamp_ns < timestamp_ns).checked_sub(1).map(|i| changes[i])
+    }
+
+    /// The `n`th stored change of a region, oldest first; compaction drops
+    /// folded changes, so positions shift after it
+    pub fn nth_change(&self, region_id: u32, n: usize) -> Option<IndexedChange> {
+        self.inner.lock().unwrap().index.get(&region_id)?.get(n).copied()
+    }
+
+    /// Contents of a region after every change up to `global_seq`; None when
+    /// nothing is stored for it or that state was already folded away
+    pub fn state_at(&self, region_id: u32, global_seq: u64) -> Result<Option<Vec<u8>>, String> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.flush()?;
+        let Some((from_seq, mut value)) = read_base(&inner.dir)?.remove(&region_id) else {
+            return Ok(None);
+        };
+        if global_seq + 1 < from_seq {
+            return Ok(None);
+        }
+        for delta in read_deltas(&inner.dir)? {
+            if delta.region_id == region_id && delta.seq >= from_seq && delta.seq <= global_seq {
+                delta.apply(&mut value);
+            }
+        }
+        Ok(Some(value))
+    }
+
+    /// Fold all but the last keep_deltas changes of each region into its
+    /// base (and, with a Retention, downsample); returns how many deltas
+    /// were folded
+    pub fn compact(&self) -> Result<usize, String> {
+        self.compact_at(now_ns())
+    }
+
+    fn compact_at(&self, now_ns: u64) -> Result<usize, String> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.flush()?;
+        let mut base = read_base(&inner.dir)?;
+        let mut by_region: BTreeMap<u32, Vec<Delta>> = BTreeMap::new();
+        for delta in read_deltas(&inner.dir)? {
+            if base.get(&delta.region_id).is_some_and(|(from_seq, _)| delta.seq >= *from_seq) {
+                by_region.entry(delta.region_id).or_default().push(delta);
+            }
+        }
+
+        let retention = inner.retention;
+        let raw_cutoff = retention.map(|r| now_ns.saturating_sub(r.raw.as_nanos() as u64));
+        let mut aggregates = match retention {
+            Some(_) => read_aggregates(&inner.dir)?,
+            None => Vec::new(),
+        };
+        let mut folded = 0;
+        let mut kept = Vec::new();
+        for (region_id, mut deltas) in by_region {
+            let expired = raw_cutoff.map_or(0, |cutoff| deltas.iter().take_while(|d| d.timestamp_ns < cutoff).count());
+            let split = deltas.len().saturating_sub(inner.keep_deltas).max(expired);
+            let (from_seq, value) = base.get_mut(&region_id).expect("filtered on base");
+            for delta in &deltas[..split] {
+                delta.apply(value);
+                *from_seq = delta.seq + 1;
+                if retention.is_some() {
+                    aggregates.push(delta.aggregate());
+                }
+            }
+            folded += split;
+            kept.extend(deltas.drain(split..));
+        }
+
+        let lines: String = base
+            .iter()
+            .map(|(id, (from_seq, value))| format!("{}\t{}\t{}\n", id, from_seq, hex(value)))
+            .collect();
+        replace(&inner.dir.join(BASE), &lines)?;
+        if let Some(retention) = retention {
+            let aggregates = downsample(aggregates, &retention, now_ns);
+            replace(&inner.dir.join(AGGREGATES), &aggregates.iter().map(|a| a.to_line()).collect::<String>())?;
+        }
+        replace(&inner.dir.join(DELTAS), &kept.iter().map(Delta::to_line).collect::<String>())?;
+        inner.deltas = open_deltas(&inner.dir)?;
+        inner.based = base.into_keys().collect();
+        inner.index = build_index(kept);
+        Ok(folded)
+    }
+
+    /// Compact every `interval` on a background thread until the returned
+    /// Compactor is dropped. Failed runs are retried on the next tick.
+    pub fn compact_every(&self, interval: Duration) -> Compactor {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let store = self.clone();
+        let signal = stop.clone();
+        let thread = thread::spawn(move || {
+            let (stopped, wake) = &*signal;
+            let mut stopped = stopped.lock().unwrap();
+            while !*stopped {
+                stopped = wake.wait_timeout(stopped, interval).unwrap().0;
+                if !*stopped {
+                    let _ = store.compact();
+                }
+            }
+        });
+        Compactor {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Inner {
+    fn flush(&mut self) -> Result<(), String> {
+        self.deltas.flush().map_err(|e| format!("Failed to write history: {}", e))
+    }
+}
+
+impl EventSink for HistoryStore {
+    fn write(&mut self, event: &ChangeEvent) -> Result<(), String> {
+        if event.kind.is_marker() || event.new_value.is_empty() {
+            return Ok(());
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.based.contains(&event.region_id) {
+            let line = format!("{}\t{}\t{}\n", event.region_id, event.global_seq, hex(&event.old_value));
+            append(&inner.dir.join(BASE), &line)?;
+            inner.based.insert(event.region_id);
+        }
+
+        let (old, new) = (&event.old_value, &event.new_value);
+        let changed = |i: &usize| old.get(*i) != new.get(*i);
+        let start = (0..new.len()).find(changed).unwrap_or(new.len());
+        let end = (start..new.len()).rev().find(changed).map_or(start, |i| i + 1);
+        let delta = Delta {
+            region_id: event.region_id,
+            seq: event.global_seq,
+            timestamp_ns: event.timestamp_ns,
+            offset: start,
+            len: new.len(),
+            bytes: new[start..end].to_vec(),
+        };
+        inner
+            .deltas
+            .write_all(delta.to_line().as_bytes())
+            .map_err(|e| format!("Failed to write history: {}", e))?;
+        let changes = inner.index.entry(event.region_id).or_default();
+        let change = delta.indexed();
+        changes.insert(changes.partition_point(|c| *c <= change), change);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        self.inner.lock().unwrap().flush()
+    }
+}
+
+impl Drop for Compactor {
+    fn drop(&mut self) {
+        let (stopped, wake) = &*self.stop;
+        *stopped.lock().unwrap() = true;
+        wake.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn build_index(deltas: impl IntoIterator<Item = Delta>) -> HashMap<u32, Vec<IndexedChange>> {
+    let mut index: HashMap<u32, Vec<IndexedChange>> = HashMap::new();
+    for delta in deltas {
+        index.entry(delta.region_id).or_default().push(delta.indexed());
+    }
+    for changes in index.values_mut() {
+        changes.sort_unstable();
+    }
+    index
+}
+
+fn bucket_start(timestamp_ns: u64, span: Duration) -> u64 {
+    timestamp_ns - timestamp_ns % span.as_nanos() as u64
+}
+
+/// Merge buckets, roll minutes past their window into hours and drop hours
+/// past theirs
+fn downsample(aggregates: Vec<Aggregate>, retention: &Retention, now_ns: u64) -> Vec<Aggregate> {
+    let minute_cutoff = now_ns.saturating_sub(retention.minutes.as_nanos() as u64);
+    let hour_cutoff = retention.hours.map_or(0, |hours| now_ns.saturating_sub(hours.as_nanos() as u64));
+    let mut buckets: BTreeMap<(u32, u64, u64), Aggregate> = BTreeMap::new();
+    for mut aggregate in aggregates {
+        if aggregate.span == MINUTE && aggregate.end_ns() <= minute_cutoff {
+            aggregate.start_ns = bucket_start(aggregate.start_ns, HOUR);
+            aggregate.span = HOUR;
+        }
+        if aggregate.span == HOUR && aggregate.end_ns() <= hour_cutoff {
+            continue;
+        }
+        let key = (aggregate.region_id, aggregate.start_ns, aggregate.span.as_secs());
+        match buckets.get_mut(&key) {
+            Some(bucket) => bucket.merge(&aggregate),
+            None => {
+                buckets.insert(key, aggregate);
+            }
+        }
+    }
+    buckets.into_values().collect()
+}
+
+fn read_aggregates(dir: &Path) -> Result<Vec<Aggregate>, String> {
+    let path = dir.join(AGGREGATES);
+    read_lines(&path)?
+        .iter()
+        .map(|line| Aggregate::parse(line).ok_or_else(|| format!("Corrupt history aggregates: {}", path.display())))
+        .collect()
+}
+
+fn open_deltas(dir: &Path) -> Result<BufWriter<File>, String> {
+    let path = dir.join(DELTAS);
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map(BufWriter::new)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))
+}
+
+fn read_lines(path: &Path) -> Result<Vec<String>, String> {
+    match File::open(path) {
+        Ok(file) => BufReader::new(file)
+            .lines()
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+/// Base of every region as (from_seq, contents)
+fn read_base(dir: &Path) -> Result<HashMap<u32, (u64, Vec<u8>)>, String> {
+    let path = dir.join(BASE);
+    let mut base = HashMap::new();
+    for line in read_lines(&path)? {
+        let (region_id, from_seq, value) =
+            parse_base_line(&line).ok_or_else(|| format!("Corrupt history base: {}", path.display()))?;
+        base.insert(region_id, (from_seq, value));
+    }
+    Ok(base)
+}
+
+fn parse_base_line(line: &str) -> Option<(u32, u64, Vec<u8>)> {
+    let mut fields = line.split('\t');
+    Some((fields.next()?.parse().ok()?, fields.next()?.parse().ok()?, parse_hex(fields.next()?)?))
+}
+
+fn read_deltas(dir: &Path) -> Result<Vec<Delta>, String> {
+    let path = dir.join(DELTAS);
+    read_lines(&path)?
+        .iter()
+        .map(|line| Delta::parse(line).ok_or_else(|| format!("Corrupt history deltas: {}", path.display())))
+        .collect()
+}
+
+fn append(path: &Path, contents: &str) -> Result<(), String> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Atomically replace a file's contents
+fn replace(path: &Path, contents: &str) -> Result<(), String> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents)
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::marker;
+    use crate::EventKind;
+
+    fn change(region_id: u32, global_seq: u64, old: &[u8], new: &[u8]) -> ChangeEvent {
+        let mut event = marker(region_id, "buf", EventKind::Change);
+        event.global_seq = global_seq;
+        event.timestamp_ns = global_seq * 10;
+        event.old_value = old.to_vec();
+        event.new_value = new.to_vec();
+        event
+    }
+
+    #[test]
+    fn test_compaction_keeps_recent_states() {
+        let dir = std::env::temp_dir().join(format!("memwatch_history_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut store = HistoryStore::open(&dir, 2).unwrap();
+        let mut value = vec![0u8; 4];
+        for seq in 0..5u64 {
+            let old = value.clone();
+            value[seq as usize % 4] = seq as u8 + 1;
+            store.write(&change(1, seq, &old, &value)).unwrap();
+        }
+        store.write(&change(2, 5, &[9], &[8, 7])).unwrap();
+        store.flush().unwrap();
+
+        assert_eq!(store.state_at(1, 2).unwrap(), Some(vec![1, 2, 3, 0]));
+        assert_eq!(store.compact().unwrap(), 3);
+        assert_eq!(read_deltas(&dir).unwrap().len(), 3);
+        assert_eq!(store.state_at(1, 1).unwrap(), None);
+        assert_eq!(store.state_at(1, 2).unwrap(), Some(vec![1, 2, 3, 0]));
+        assert_eq!(store.state_at(1, 4).unwrap(), Some(vec![5, 2, 3, 4]));
+        assert_eq!(store.state_at(2, 5).unwrap(), Some(vec![8, 7]));
+        assert_eq!(store.state_at(3, 5).unwrap(), None);
+
+        drop(store);
+        let store = HistoryStore::open(&dir, 2).unwrap();
+        assert_eq!(store.compact().unwrap(), 0);
+        assert_eq!(store.state_at(1, 4).unwrap(), Some(vec![5, 2, 3, 4]));
+        drop(store.compact_every(Duration::from_secs(60)));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_time_index_survives_reopen_and_compaction() {
+        let dir = std::env::temp_dir().join(format!("memwatch_history_index_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut store = HistoryStore::open(&dir, 2).unwrap();
+        for seq in 0..4u64 {
+            store.write(&change(1, seq, &[seq as u8], &[seq as u8 + 1])).unwrap();
+        }
+        store.flush().unwrap();
+
+        let at = |seq: u64| Some(IndexedChange { timestamp_ns: seq * 10, global_seq: seq });
+        assert_eq!(store.first_change_after(1, 15), at(2));
+        assert_eq!(store.first_change_after(1, 20), at(3));
+        assert_eq!(store.first_change_after(1, 30), None);
+        assert_eq!(store.last_change_before(1, 20), at(1));
+        assert_eq!(store.last_change_before(1, 0), None);
+        assert_eq!(store.nth_change(1, 3), at(3));
+        assert_eq!(store.nth_change(2, 0), None);
+
+        drop(store);
+        let store = HistoryStore::open(&dir, 2).unwrap();
+        assert_eq!(store.nth_change(1, 0), at(0));
+        store.compact().unwrap();
+        assert_eq!(store.nth_change(1, 0), at(2));
+        assert_eq!(store.last_change_before(1, 100), at(3));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_retention_downsamples_old_changes() {
+        let dir = std::env::temp_dir().join(format!("memwatch_history_tiers_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let retention = Retention { hours: Some(Duration::from_secs(30 * 24 * 3600)), ..Retention::default() };
+        let mut store = HistoryStore::open(&dir, usize::MAX).unwrap().with_retention(retention);
+        let now = 100 * 24 * 3600 * 1_000_000_000u64;
+        let ago = |secs: u64| now - secs * 1_000_000_000;
+        let mut value = vec![0u8; 2];
+        for (seq, timestamp_ns) in [ago(3 * 86400), ago(3 * 86400 - 120), ago(7200), ago(7190), ago(600)].into_iter().enumerate() {
+            let old = value.clone();
+            value[seq % 2] += 1;
+            let mut event = change(1, seq as u64, &old, &value);
+            event.timestamp_ns = timestamp_ns;
+            store.write(&event).unwrap();
+        }
+
+        assert_eq!(store.compact_at(now).unwrap(), 4);
+        assert_eq!(store.nth_change(1, 0).map(|c| c.global_seq), Some(4));
+        assert_eq!(store.state_at(1, 4).unwrap(), Some(vec![3, 2]));
+        let tiers: Vec<_> = store.aggregates(1).unwrap().iter().map(|a| (a.span, a.changes, a.first_seq, a.last_seq)).collect();
+        assert_eq!(tiers, [(HOUR, 2, 0, 1), (MINUTE, 2, 2, 3)]);
+        assert_eq!(store.aggregates(1).unwrap()[1].start_ns, bucket_start(ago(7200), MINUTE));
+
+        // Two days on, everything is hours; after forty, all has expired
+        let days = |n: u64| now + n * 86400 * 1_000_000_000;
+        assert_eq!(store.compact_at(days(2)).unwrap(), 1);
+        let tiers: Vec<_> = store.aggregates(1).unwrap().iter().map(|a| (a.span, a.changes)).collect();
+        assert_eq!(tiers, [(HOUR, 2), (HOUR, 2), (HOUR, 1)]);
+        store.compact_at(days(40)).unwrap();
+        assert!(store.aggregates(1).unwrap().is_empty());
+        assert_eq!(store.state_at(1, 4).unwrap(), Some(vec![3, 2]));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
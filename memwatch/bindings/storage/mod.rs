@@ -0,0 +1,13 @@
+// Persistent storage for the Rust layer
+
+mod blob;
+mod history;
+mod sequence;
+#[cfg(feature = "rusqlite")]
+mod sqlite;
+
+pub use blob::{BlobStore, MIN_VALUE_BYTES};
+pub use history::{Aggregate, Compactor, HistoryStore, IndexedChange, Retention};
+pub use sequence::SequenceStore;
+#[cfg(feature = "rusqlite")]
+pub use sqlite::SqliteStore;
@@ -0,0 +1,114 @@
+// Event sequencing that survives restarts
+//
+// The state file holds "<epoch> <next_seq>". Every open bumps the epoch, and
+// sequence numbers are reserved in blocks ahead of use, so a crash can skip
+// numbers but never reuse them.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::ChangeEvent;
+
+const RESERVE_BLOCK: u64 = 1024;
+
+/// Source of `(epoch, global_seq)` stamps
+#[derive(Debug, Default)]
+pub struct SequenceStore {
+    path: Option<PathBuf>,
+    epoch: u32,
+    next_seq: u64,
+    reserved_until: u64,
+}
+
+impl SequenceStore {
+    /// Process-local sequencing (epoch 0, nothing persisted)
+    pub fn in_memory() -> Self {
+        SequenceStore::default()
+    }
+
+    /// Load the state file (created if missing) and start a new epoch
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        let (epoch, next_seq) = match fs::read_to_string(&path) {
+            Ok(content) => parse_state(&content)
+                .ok_or_else(|| format!("Corrupt sequence file: {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (0, 0),
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+
+        let mut store = SequenceStore {
+            path: Some(path),
+            epoch: epoch.wrapping_add(1),
+            next_seq,
+            reserved_until: next_seq,
+        };
+        store.reserve()?;
+        Ok(store)
+    }
+
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Next global sequence number
+    pub fn next_seq(&mut self) -> Result<u64, String> {
+        if self.path.is_some() && self.next_seq >= self.reserved_until {
+            self.reserve()?;
+        }
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        Ok(seq)
+    }
+
+    /// Stamp `epoch` and `global_seq` on a batch of events
+    pub fn stamp(&mut self, events: &mut [ChangeEvent]) -> Result<(), String> {
+        for event in events {
+            event.epoch = self.epoch;
+            event.global_seq = self.next_seq()?;
+        }
+        Ok(())
+    }
+
+    fn reserve(&mut self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let reserved_until = self.next_seq + RESERVE_BLOCK;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, format!("{} {}\n", self.epoch, reserved_until))
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| format!("Failed to persist sequence to {}: {}", path.display(), e))?;
+        self.reserved_until = reserved_until;
+        Ok(())
+    }
+}
+
+fn parse_state(content: &str) -> Option<(u32, u64)> {
+    let mut parts = content.split_whitespace();
+    let epoch = parts.next()?.parse().ok()?;
+    let next_seq = parts.next()?.parse().ok()?;
+    Some((epoch, next_seq))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_epoch_and_seq_continue_across_opens() {
+        let path = std::env::temp_dir().join(format!("memwatch_seq_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut first = SequenceStore::open(&path).unwrap();
+        assert_eq!(first.epoch(), 1);
+        assert_eq!(first.next_seq().unwrap(), 0);
+        let last = first.next_seq().unwrap();
+        drop(first);
+
+        let mut second = SequenceStore::open(&path).unwrap();
+        assert_eq!(second.epoch(), 2);
+        assert!(second.next_seq().unwrap() > last);
+
+        let _ = fs::remove_file(&path);
+    }
+}
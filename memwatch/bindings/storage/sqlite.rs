@@ -0,0 +1,500 @@
+// Queryable event history in SQLite (feature `rusqlite`)
+//
+// A SqliteStore keeps every event it is given, values included, in one
+// table of a SQLite database, so postmortem tools can slice a run without
+// re-parsing an exported stream:
+//
+//   let store = SqliteStore::open("events.db")?.with_compression();
+//   watcher.add_sink(Box::new(store.clone()));
+//   ...
+//   let balance = store.events_for_region(3)?;
+//   let burst = store.events_between(from_ns, to_ns)?;
+//   let writes = store.events_matching(&Filter::parse("function contains 'flush'")?)?;
+//
+// Queries return events in the order they were stored, with every field
+// of the event as it was delivered. events_matching() selects rows with the
+// filter's predicates as SQL (all but size and rate), then runs the filter
+// over them (markers pass, as they do in check_changes(), and rate
+// predicates count in stored order); the table is plain SQL too, indexed on
+// region_id and timestamp_ns, for anything the filter language cannot say.
+//
+// As a sink, the store writes each delivered batch in one transaction,
+// committed by the sink's flush() (or when the last clone is dropped).
+//
+// With compression, old and new values are deflated before they are
+// stored; previews stay as they are. A store can hold both kinds of rows,
+// so compression can be switched on for an existing database. Timestamps,
+// fault addresses and other 64-bit fields are stored as their bit pattern
+// in SQLite's signed integers.
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, Row};
+
+use crate::anomaly::AnomalyKind;
+use crate::diff::ByteRange;
+use crate::lifecycle::{AllocationSite, EventSource};
+use crate::panics::Observer;
+use crate::serde_static::intern;
+use crate::sink::EventSink;
+use crate::{ChangeEvent, EventKind, Filter, Location};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS events (
+        epoch INTEGER NOT NULL,
+        global_seq INTEGER NOT NULL,
+        seq INTEGER NOT NULL,
+        timestamp_ns INTEGER NOT NULL,
+        adapter_id INTEGER NOT NULL,
+        region_id INTEGER NOT NULL,
+        name TEXT,
+        group_name TEXT,
+        kind TEXT NOT NULL,
+        kind_args TEXT NOT NULL,
+        source TEXT NOT NULL,
+        file TEXT,
+        function TEXT,
+        line INTEGER NOT NULL,
+        fault_ip INTEGER NOT NULL,
+        tid INTEGER,
+        thread_name TEXT,
+        allocated_file TEXT,
+        allocated_line INTEGER,
+        allocated_type TEXT,
+        old_preview BLOB NOT NULL,
+        new_preview BLOB NOT NULL,
+        old_value BLOB NOT NULL,
+        new_value BLOB NOT NULL,
+        compressed INTEGER NOT NULL,
+        storage_key_old TEXT,
+        storage_key_new TEXT,
+        frames BLOB NOT NULL,
+        deltas BLOB NOT NULL,
+        preview_limit INTEGER,
+        span_id INTEGER,
+        span_name TEXT,
+        write_count INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS events_region ON events (region_id, timestamp_ns);
+    CREATE INDEX IF NOT EXISTS events_time ON events (timestamp_ns);
+";
+
+const COLUMNS: &str = "epoch, global_seq, seq, timestamp_ns, adapter_id, region_id, name, group_name, kind, kind_args, \
+    source, file, function, line, fault_ip, tid, thread_name, allocated_file, allocated_line, allocated_type, \
+    old_preview, new_preview, old_value, new_value, compressed, storage_key_old, storage_key_new, frames, deltas, \
+    preview_limit, span_id, span_name, write_count";
+
+/// Deflate level for compressed values
+const COMPRESSION_LEVEL: u8 = 6;
+
+/// Events in a SQLite database; a sink, and cheap to clone
+#[derive(Clone)]
+pub struct SqliteStore {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    conn: Connection,
+    compress: bool,
+    /// A batch is being written in a transaction
+    in_batch: bool,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        if self.in_batch {
+            let _ = self.conn.execute_batch("COMMIT");
+        }
+    }
+}
+
+impl SqliteStore {
+    /// Open (or create) the database at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let conn = Connection::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        Self::with_connection(conn)
+    }
+
+    /// A store that lives only as long as the process
+    pub fn in_memory() -> Result<Self, String> {
+        Self::with_connection(Connection::open_in_memory().map_err(|e| e.to_string())?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, String> {
+        conn.execute_batch(SCHEMA).map_err(|e| format!("Failed to create the events table: {}", e))?;
+        Ok(SqliteStore { inner: Arc::new(Mutex::new(Inner { conn, compress: false, in_batch: false })) })
+    }
+
+    /// Deflate old and new values of events stored from now on
+    pub fn with_compression(self) -> Self {
+        self.inner.lock().unwrap().compress = true;
+        self
+    }
+
+    /// Events of one region
+    pub fn events_for_region(&self, region_id: u32) -> Result<Vec<ChangeEvent>, String> {
+        self.select("WHERE region_id = ?1", [region_id as i64])
+    }
+
+    /// Events with from_ns <= timestamp_ns <= to_ns
+    pub fn events_between(&self, from_ns: u64, to_ns: u64) -> Result<Vec<ChangeEvent>, String> {
+        // Bit patterns past i64::MAX compare as negative; no clock gets there
+        self.select("WHERE timestamp_ns BETWEEN ?1 AND ?2", [from_ns as i64, to_ns as i64])
+    }
+
+    /// Events the filter accepts; see the module comment
+    pub fn events_matching(&self, filter: &Filter) -> Result<Vec<ChangeEvent>, String> {
+        let mut params = Vec::new();
+        let condition = format!("WHERE kind != 'change' OR {}", filter.sql_condition(&mut params));
+        let mut events = self.select(&condition, rusqlite::params_from_iter(params))?;
+        let mut filter = filter.clone();
+        events.retain(|event| filter.accepts(event));
+        Ok(events)
+    }
+
+    /// Events stored so far
+    pub fn len(&self) -> Result<u64, String> {
+        let inner = self.inner.lock().unwrap();
+        let count: i64 = inner.conn.query_row("SELECT COUNT(*) FROM events", [], |row| row.get(0)).map_err(|e| e.to_string())?;
+        Ok(count as u64)
+    }
+
+    pub fn is_empty(&self) -> Result<bool, String> {
+        Ok(self.len()? == 0)
+    }
+
+    fn select<P: rusqlite::Params>(&self, condition: &str, params: P) -> Result<Vec<ChangeEvent>, String> {
+        let inner = self.inner.lock().unwrap();
+        let sql = format!("SELECT {} FROM events {} ORDER BY rowid", COLUMNS, condition);
+        let mut statement = inner.conn.prepare_cached(&sql).map_err(|e| e.to_string())?;
+        let rows = statement.query_map(params, event_from_row).map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| format!("Failed to read stored events: {}", e))
+    }
+}
+
+impl EventSink for SqliteStore {
+    fn write(&mut self, event: &ChangeEvent) -> Result<(), String> {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.in_batch {
+            inner.conn.execute_batch("BEGIN").map_err(|e| format!("Failed to start a batch: {}", e))?;
+            inner.in_batch = true;
+        }
+        let value = |bytes: &[u8]| {
+            if inner.compress {
+                miniz_oxide::deflate::compress_to_vec(bytes, COMPRESSION_LEVEL)
+            } else {
+                bytes.to_vec()
+            }
+        };
+        let site = event.allocated_at.as_ref();
+        let mut statement = inner
+            .conn
+            .prepare_cached(&format!("INSERT INTO events ({}) VALUES ({})", COLUMNS, vec!["?"; 33].join(", ")))
+            .map_err(|e| e.to_string())?;
+        statement
+            .execute(params![
+                event.epoch,
+                event.global_seq as i64,
+                event.seq,
+                event.timestamp_ns as i64,
+                event.adapter_id,
+                event.region_id,
+                event.variable_name,
+                event.group,
+                event.kind.as_str(),
+                kind_args(&event.kind),
+                event.source.as_str(),
+                event.where_.file,
+                event.where_.function,
+                event.where_.line,
+                event.where_.fault_ip as i64,
+                event.tid,
+                event.thread_name,
+                site.map(|site| site.file),
+                site.map(|site| site.line),
+                site.and_then(|site| site.type_name),
+                event.old_preview,
+                event.new_preview,
+                value(&event.old_value),
+                value(&event.new_value),
+                inner.compress,
+                event.storage_key_old,
+                event.storage_key_new,
+                event.frames.iter().flat_map(|frame| frame.to_le_bytes()).collect::<Vec<u8>>(),
+                event.deltas.iter().flat_map(|d| [d.offset as u64, d.len as u64]).flat_map(u64::to_le_bytes).collect::<Vec<u8>>(),
+                event.preview_limit.map(|limit| limit as i64),
+                event.span_id.map(|id| id as i64),
+                event.span_name,
+                event.write_count,
+            ])
+            .map_err(|e| format!("Failed to store event: {}", e))?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.in_batch {
+            inner.conn.execute_batch("COMMIT").map_err(|e| format!("Failed to commit stored events: {}", e))?;
+            inner.in_batch = false;
+        }
+        Ok(())
+    }
+}
+
+/// The payload of an event kind, as space-separated fields after its name
+fn kind_args(kind: &EventKind) -> String {
+    match *kind {
+        EventKind::Watched { addr, size } => format!("{} {}", addr, size),
+        EventKind::Relocated { old_addr, new_addr } => format!("{} {}", old_addr, new_addr),
+        EventKind::Anomaly(anomaly) => anomaly.as_str().to_string(),
+        EventKind::AliasWarning { other } => other.to_string(),
+        EventKind::WorkerStalled { thread_id, stalled_ms } => format!("{} {}", thread_id, stalled_ms),
+        EventKind::HugePageWarning { page_size } => page_size.to_string(),
+        EventKind::SharedPageWarning { other, extra_faults } => format!("{} {}", other, extra_faults),
+        EventKind::CallbackPanicked { observer, failures, disabled } => {
+            let index = match observer {
+                Observer::Sink { index } => index as u64,
+                Observer::Subscription { id } => id as u64,
+                Observer::Callback | Observer::RegionCallback => 0,
+            };
+            format!("{} {} {} {}", observer.as_str(), index, failures, disabled)
+        }
+        EventKind::Triggered { trigger, count } => format!("{} {}", trigger, count),
+        EventKind::Change
+        | EventKind::Unwatched
+        | EventKind::Paused
+        | EventKind::Resumed
+        | EventKind::Freed
+        | EventKind::UseAfterUnwatch => String::new(),
+    }
+}
+
+fn parse_kind(name: &str, args: &str) -> Result<EventKind, String> {
+    let bad = || format!("Bad stored kind {:?} ({:?})", name, args);
+    let fields: Vec<&str> = args.split_whitespace().collect();
+    let number = |i: usize| -> Result<u64, String> { fields.get(i).and_then(|f| f.parse().ok()).ok_or_else(bad) };
+    Ok(match name {
+        "change" => EventKind::Change,
+        "watched" => EventKind::Watched { addr: number(0)?, size: number(1)? as usize },
+        "unwatched" => EventKind::Unwatched,
+        "paused" => EventKind::Paused,
+        "resumed" => EventKind::Resumed,
+        "relocated" => EventKind::Relocated { old_addr: number(0)?, new_addr: number(1)? },
+        "anomaly" => EventKind::Anomaly(match args {
+            "change_rate" => AnomalyKind::ChangeRate,
+            "new_writer" => AnomalyKind::NewWriter,
+            "unlocked_write" => AnomalyKind::UnlockedWrite,
+            custom => AnomalyKind::Custom(intern(custom)),
+        }),
+        "freed" => EventKind::Freed,
+        "use_after_unwatch" => EventKind::UseAfterUnwatch,
+        "alias_warning" => EventKind::AliasWarning { other: number(0)? as u32 },
+        "worker_stalled" => EventKind::WorkerStalled { thread_id: number(0)? as u32, stalled_ms: number(1)? },
+        "huge_page_warning" => EventKind::HugePageWarning { page_size: number(0)? },
+        "shared_page_warning" => EventKind::SharedPageWarning { other: number(0)? as u32, extra_faults: number(1)? },
+        "callback_panicked" => EventKind::CallbackPanicked {
+            observer: match fields.first() {
+                Some(&"callback") => Observer::Callback,
+                Some(&"region_callback") => Observer::RegionCallback,
+                Some(&"sink") => Observer::Sink { index: number(1)? as usize },
+                Some(&"subscription") => Observer::Subscription { id: number(1)? as u32 },
+                _ => return Err(bad()),
+            },
+            failures: number(2)? as u32,
+            disabled: fields.get(3) == Some(&"true"),
+        },
+        "triggered" => EventKind::Triggered { trigger: number(0)? as u32, count: number(1)? },
+        _ => return Err(bad()),
+    })
+}
+
+fn u64s(bytes: &[u8]) -> impl Iterator<Item = u64> + '_ {
+    bytes.chunks_exact(8).map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+}
+
+fn event_from_row(row: &Row) -> rusqlite::Result<ChangeEvent> {
+    let invalid = |column: usize, message: String| rusqlite::Error::FromSqlConversionFailure(column, Type::Text, message.into());
+    let compressed: bool = row.get(24)?;
+    let value = |column: usize| -> rusqlite::Result<Vec<u8>> {
+        let bytes: Vec<u8> = row.get(column)?;
+        if !compressed {
+            return Ok(bytes);
+        }
+        miniz_oxide::inflate::decompress_to_vec(&bytes)
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(column, Type::Blob, format!("{:?}", e).into()))
+    };
+    let kind_name: String = row.get(8)?;
+    let kind_args: String = row.get(9)?;
+    let source: String = row.get(10)?;
+    let allocated_file: Option<String> = row.get(17)?;
+    let allocated_type: Option<String> = row.get(19)?;
+    let frames: Vec<u8> = row.get(27)?;
+    let deltas: Vec<u8> = row.get(28)?;
+    let ranges: Vec<u64> = u64s(&deltas).collect();
+    Ok(ChangeEvent {
+        epoch: row.get(0)?,
+        global_seq: row.get::<_, i64>(1)? as u64,
+        seq: row.get(2)?,
+        timestamp_ns: row.get::<_, i64>(3)? as u64,
+        adapter_id: row.get(4)?,
+        region_id: row.get(5)?,
+        variable_name: row.get(6)?,
+        group: row.get(7)?,
+        kind: parse_kind(&kind_name, &kind_args).map_err(|e| invalid(8, e))?,
+        source: match source.as_str() {
+            "observed" => EventSource::Observed,
+            "poke" => EventSource::Poke,
+            other => return Err(invalid(10, format!("Bad stored source {:?}", other))),
+        },
+        where_: Location {
+            file: row.get(11)?,
+            function: row.get(12)?,
+            line: row.get(13)?,
+            fault_ip: row.get::<_, i64>(14)? as u64,
+        },
+        tid: row.get(15)?,
+        thread_name: row.get(16)?,
+        allocated_at: match allocated_file {
+            Some(file) => Some(AllocationSite {
+                file: intern(&file),
+                line: row.get(18)?,
+                type_name: allocated_type.as_deref().map(intern),
+            }),
+            None => None,
+        },
+        old_preview: row.get(20)?,
+        new_preview: row.get(21)?,
+        old_value: value(22)?,
+        new_value: value(23)?,
+        storage_key_old: row.get(25)?,
+        storage_key_new: row.get(26)?,
+        frames: u64s(&frames).collect(),
+        deltas: ranges.chunks_exact(2).map(|pair| ByteRange { offset: pair[0] as usize, len: pair[1] as usize }).collect(),
+        preview_limit: row.get::<_, Option<i64>>(29)?.map(|limit| limit as usize),
+        span_id: row.get::<_, Option<i64>>(30)?.map(|id| id as u64),
+        span_name: row.get(31)?,
+        write_count: row.get(32)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::marker;
+
+    fn change(region_id: u32, timestamp_ns: u64, function: &str, old: &[u8], new: &[u8]) -> ChangeEvent {
+        let mut event = marker(region_id, "buf", EventKind::Change);
+        event.timestamp_ns = timestamp_ns;
+        event.global_seq = timestamp_ns / 10;
+        event.where_.function = Some(function.to_string());
+        event.old_value = old.to_vec();
+        event.new_value = new.to_vec();
+        event.deltas = crate::diff::compute_delta(old, new);
+        event
+    }
+
+    #[test]
+    fn test_events_round_trip_and_queries_slice_them() {
+        let path = std::env::temp_dir().join(format!("memwatch_sqlite_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut store = SqliteStore::open(&path).unwrap().with_compression();
+        let mut first = change(1, 100, "insert", &[0; 64], &[7; 64]);
+        first.group = Some("cache".to_string());
+        first.frames = vec![0x1000, u64::MAX];
+        first.allocated_at = Some(AllocationSite { file: "src/cache.rs", line: 12, type_name: Some("Vec<u8>") });
+        first.write_count = 3;
+        store.write(&first).unwrap();
+        store.write(&change(2, 200, "flush", &[1], &[2])).unwrap();
+        let mut alarm = marker(2, "buf", EventKind::Anomaly(AnomalyKind::Custom("too_hot")));
+        alarm.timestamp_ns = 250;
+        store.write(&alarm).unwrap();
+        store.write(&marker(1, "buf", EventKind::Watched { addr: 0xdead0000, size: 64 })).unwrap();
+        store.write(&change(1, 300, "flush", &[7], &[8])).unwrap();
+        drop(store);
+
+        let store = SqliteStore::open(&path).unwrap();
+        assert_eq!(store.len().unwrap(), 5);
+        let region: Vec<ChangeEvent> = store.events_for_region(1).unwrap();
+        assert_eq!(region.len(), 3);
+        let stored = &region[0];
+        assert_eq!((stored.old_value.as_slice(), stored.new_value.as_slice()), (&[0u8; 64][..], &[7u8; 64][..]));
+        assert_eq!((stored.group.as_deref(), stored.write_count, stored.frames.as_slice()), (Some("cache"), 3, &[0x1000, u64::MAX][..]));
+        assert_eq!(stored.deltas, first.deltas);
+        assert_eq!(stored.allocated_at, first.allocated_at);
+        assert_eq!(region[1].kind, EventKind::Watched { addr: 0xdead0000, size: 64 });
+
+        let between: Vec<(u32, EventKind)> = store.events_between(150, 250).unwrap().iter().map(|e| (e.region_id, e.kind)).collect();
+        assert_eq!(between, [(2, EventKind::Change), (2, EventKind::Anomaly(AnomalyKind::Custom("too_hot")))]);
+
+        // Markers pass the filter, as in check_changes()
+        let flushes = store.events_matching(&Filter::parse("function = 'flush' and region_id = 1").unwrap()).unwrap();
+        let matched: Vec<(EventKind, u64)> = flushes.iter().map(|e| (e.kind, e.timestamp_ns)).collect();
+        assert_eq!(matched.len(), 3);
+        assert_eq!(matched[2], (EventKind::Change, 300));
+        assert!(flushes.iter().all(|e| e.kind.is_marker() || e.where_.function.as_deref() == Some("flush")));
+
+        drop(store);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_batches_commit_on_flush() {
+        let mut store = SqliteStore::in_memory().unwrap();
+        let autocommit = |store: &SqliteStore| store.inner.lock().unwrap().conn.is_autocommit();
+        store.write(&change(1, 100, "f", &[0], &[1])).unwrap();
+        store.write(&change(1, 200, "f", &[1], &[2])).unwrap();
+        assert!(!autocommit(&store));
+        store.flush().unwrap();
+        assert!(autocommit(&store));
+        assert_eq!(store.len().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_filters_select_in_sql() {
+        let condition = |source: &str| {
+            let mut params = Vec::new();
+            (Filter::parse(source).unwrap().sql_condition(&mut params), params)
+        };
+        assert_eq!(
+            condition("region_id in (1, 2) and function contains 'flush'"),
+            ("(region_id IN (1, 2) AND instr(COALESCE(function, ''), ?) > 0)".to_string(), vec!["flush".to_string()])
+        );
+        // Only exact conditions are negated
+        assert_eq!(condition("not (name = 'a' and size > 4) or line >= 3"), ("(1 OR line >= 3)".to_string(), vec![]));
+        assert_eq!(condition("not name != 'a'").0, "NOT COALESCE(name, '') NOT IN (?)");
+
+        let mut store = SqliteStore::in_memory().unwrap();
+        for (region_id, function, new) in [(1, "flush", &[1u8, 2][..]), (1, "load", &[3]), (2, "flush", &[4]), (1, "flush", &[5])] {
+            store.write(&change(region_id, 100, function, &vec![0; new.len()], new)).unwrap();
+        }
+        store.write(&marker(3, "buf", EventKind::Unwatched)).unwrap();
+        store.flush().unwrap();
+        let matched = store.events_matching(&Filter::parse("region_id = 1 and function = 'flush' and size >= 2").unwrap()).unwrap();
+        let matched: Vec<(u32, EventKind)> = matched.iter().map(|e| (e.region_id, e.kind)).collect();
+        assert_eq!(matched, [(1, EventKind::Change), (3, EventKind::Unwatched)]);
+    }
+
+    #[test]
+    fn test_every_kind_survives_storage() {
+        let kinds = [
+            EventKind::Relocated { old_addr: 1, new_addr: 2 },
+            EventKind::Anomaly(AnomalyKind::NewWriter),
+            EventKind::AliasWarning { other: 4 },
+            EventKind::WorkerStalled { thread_id: 9, stalled_ms: 500 },
+            EventKind::HugePageWarning { page_size: 2 << 20 },
+            EventKind::SharedPageWarning { other: 3, extra_faults: 17 },
+            EventKind::CallbackPanicked { observer: Observer::Sink { index: 2 }, failures: 3, disabled: true },
+            EventKind::CallbackPanicked { observer: Observer::Callback, failures: 1, disabled: false },
+            EventKind::Triggered { trigger: 1, count: 100 },
+            EventKind::UseAfterUnwatch,
+        ];
+        for kind in kinds {
+            assert_eq!(parse_kind(kind.as_str(), &kind_args(&kind)), Ok(kind));
+        }
+        assert!(parse_kind("watched", "1").is_err());
+        assert!(parse_kind("exploded", "").is_err());
+    }
+}
@@ -0,0 +1,152 @@
+// Async event stream (tokio feature)
+//
+// event_stream() spawns a pump task on the current tokio runtime that drains
+// the watcher and forwards events through a bounded channel, so async
+// services can `while let Some(event) = stream.recv().await` instead of
+// sleeping between check_changes() calls themselves. The pump drains again
+// immediately while events keep arriving. Once the ring is empty it waits
+// for wait_for_change(), so the native worker wakes it as soon as a fault is
+// recorded; WAKE_FALLBACK only bounds the wait in case a wake is lost.
+// Without a worker (Snapshot mode) nothing wakes it for changes, and it
+// polls instead, backing off up to MAX_IDLE. A full channel pauses the pump,
+// leaving events in the ring.
+//
+// The pump consumes events like any other check_changes() caller, so a
+// watcher should have one consumer. A check_changes() or wait_for_change()
+// error ends the stream; is_poisoned() and reset() apply as usual.
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_core::Stream;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::{ChangeEvent, MemWatch};
+
+/// Events buffered between the pump and the consumer
+const CHANNEL_CAPACITY: usize = 1024;
+const MIN_IDLE: Duration = Duration::from_millis(1);
+const MAX_IDLE: Duration = Duration::from_millis(50);
+/// Longest wait for a wake from the worker
+const WAKE_FALLBACK: Duration = Duration::from_secs(1);
+
+/// Stream of delivered events; stops its pump when dropped
+pub struct EventStream {
+    events: mpsc::Receiver<ChangeEvent>,
+    pump: JoinHandle<()>,
+}
+
+impl MemWatch {
+    /// Stream every event check_changes() would return. Must be called from
+    /// within a tokio runtime.
+    pub fn event_stream(self: &Arc<Self>) -> EventStream {
+        let (tx, events) = mpsc::channel(CHANNEL_CAPACITY);
+        let pump = tokio::spawn(pump(Arc::clone(self), tx));
+        EventStream { events, pump }
+    }
+}
+
+impl EventStream {
+    /// Next event; None once the stream has ended
+    pub async fn recv(&mut self) -> Option<ChangeEvent> {
+        self.events.recv().await
+    }
+}
+
+impl Stream for EventStream {
+    type Item = ChangeEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<ChangeEvent>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        self.pump.abort();
+    }
+}
+
+async fn pump(watch: Arc<MemWatch>, tx: mpsc::Sender<ChangeEvent>) {
+    let mut idle = MIN_IDLE;
+    loop {
+        // Created before checking, so a change recorded meanwhile wakes it
+        let Ok(changed) = watch.wait_for_change() else {
+            return;
+        };
+        let Ok(events) = watch.check_changes() else {
+            return;
+        };
+        if events.is_empty() {
+            let timeout = if watch.wakes_on_change() { WAKE_FALLBACK } else { idle };
+            tokio::select! {
+                _ = changed => {}
+                _ = tokio::time::sleep(timeout) => {}
+                _ = tx.closed() => return,
+            }
+            idle = (idle * 2).min(MAX_IDLE);
+            continue;
+        }
+        idle = MIN_IDLE;
+        for event in events {
+            if tx.send(event).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native::{self, FakeEvent};
+    use crate::EventKind;
+
+    #[test]
+    fn test_stream_yields_changes() {
+        let _guard = fake_native::lock();
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        let watcher = Arc::new(watcher);
+        let mut balance = vec![0u8; 4];
+        watcher.watch(&balance, "balance").unwrap();
+
+        runtime.block_on(async {
+            let mut stream = watcher.event_stream();
+            assert_eq!(stream.recv().await.unwrap().kind, EventKind::Watched { addr: balance.as_ptr() as u64, size: 4 });
+            balance[0] = 7;
+            let event = tokio::time::timeout(Duration::from_secs(5), stream.recv()).await.unwrap().unwrap();
+            assert_eq!((event.kind, event.new_preview[0]), (EventKind::Change, 7));
+        });
+        // The pump was aborted with the stream, releasing its handle
+        drop(runtime);
+        assert_eq!(Arc::strong_count(&watcher), 1);
+    }
+
+    #[test]
+    fn test_worker_wakes_idle_pump() {
+        let _guard = fake_native::lock();
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Protect;
+        let watcher = Arc::new(watcher);
+        let balance = [0u8; 4];
+        let region_id = watcher.watch(&balance, "balance").unwrap();
+
+        runtime.block_on(async {
+            let mut stream = watcher.event_stream();
+            stream.recv().await.unwrap();
+            // Let the pump go idle, then record a change as the worker would
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            fake_native::state().events.push(FakeEvent::change(region_id, &[7]));
+            assert!(fake_native::fire(FakeEvent::change(region_id, &[7])));
+            let event = tokio::time::timeout(WAKE_FALLBACK / 2, stream.recv()).await.unwrap().unwrap();
+            assert_eq!(event.new_preview, [7]);
+        });
+    }
+}
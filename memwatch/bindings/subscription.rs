@@ -0,0 +1,302 @@
+// Filtered subscriptions
+//
+// subscribe() registers an observer with a filter and a delivery mode of its
+// own, so observers of different subsystems share a watcher without each
+// re-filtering the whole stream:
+//
+//   let orders = Filter::parse("name contains 'order' and size >= 8")?;
+//   let id = watcher.subscribe(Some(orders), Delivery::Worker, |event| ship(event));
+//   ...
+//   watcher.unsubscribe(id);
+//
+// Subscribers see the events check_changes() returns (processed, markers
+// included) that their filter accepts (markers always pass); each filter
+// keeps its own rate counters. Inline subscribers run in check_changes() on
+// the calling thread, in subscription order, after watch_with_callback()
+// handlers.
+// Worker subscribers each get a thread and a queue of their own: a slow one
+// delays neither check_changes() nor the others, and still sees its events
+// in order. With MemWatchBuilder::dispatch() they share a bounded pool
+// instead; see the dispatch module. Panics are caught and reported as CallbackPanicked markers
+// (Observer::Subscription) as for other observers, a worker's by the first
+// check_changes() after them; see the panics module.
+
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use crate::dispatch::{ConsumerStats, Consumer, Metrics};
+use crate::filter::Filter;
+use crate::panics::{self, Observer};
+use crate::pipeline_trace::{micros_since, TraceStep};
+use crate::{CallbackSlot, ChangeEvent, ChangeEventCallback, MemWatch};
+
+/// Where a subscriber runs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Delivery {
+    /// In check_changes(), on the calling thread
+    #[default]
+    Inline,
+    /// Off the calling thread, fed through a queue: on a thread of the
+    /// subscriber's own, or on the dispatch pool if there is one
+    Worker,
+}
+
+#[derive(Default)]
+pub(crate) struct Subscriptions {
+    next_id: u32,
+    entries: Vec<Subscription>,
+}
+
+struct Subscription {
+    id: u32,
+    filter: Option<Filter>,
+    target: Target,
+    metrics: Arc<Mutex<Metrics>>,
+}
+
+enum Target {
+    /// The callback and its panics so far
+    Inline(ChangeEventCallback, u32),
+    Worker(Worker),
+    /// On the watcher's dispatch pool
+    Pooled(Arc<Consumer>),
+}
+
+struct Worker {
+    /// Events and when they were handed over
+    queue: Option<Sender<(ChangeEvent, Instant)>>,
+    thread: Option<JoinHandle<()>>,
+    /// (failures, removed, message) of panics not yet reported
+    panics: Arc<Mutex<Vec<(u32, bool, String)>>>,
+}
+
+impl Worker {
+    fn spawn(id: u32, callback: ChangeEventCallback, slot: Arc<CallbackSlot>, metrics: Arc<Mutex<Metrics>>) -> Result<Worker, String> {
+        let (queue, events) = mpsc::channel::<(ChangeEvent, Instant)>();
+        let panics = Arc::new(Mutex::new(Vec::new()));
+        let reported = Arc::clone(&panics);
+        let thread = thread::Builder::new()
+            .name(format!("memwatch-subscriber-{}", id))
+            .spawn(move || {
+                let mut failures = 0;
+                for (event, since) in events {
+                    match panics::guarded(|| callback(&event)) {
+                        Ok(()) => metrics.lock().unwrap_or_else(|e| e.into_inner()).delivered(since),
+                        Err(message) => {
+                            failures += 1;
+                            let disabled = panics::exhausted(failures, slot.failure_limit.load(Ordering::SeqCst));
+                            reported.lock().unwrap_or_else(|e| e.into_inner()).push((failures, disabled, message));
+                            if disabled {
+                                break;
+                            }
+                        }
+                    }
+                }
+            })
+            .map_err(|e| format!("Failed to start subscriber thread: {}", e))?;
+        Ok(Worker { queue: Some(queue), thread: Some(thread), panics })
+    }
+}
+
+impl Drop for Worker {
+    /// Close the queue and wait for what is queued to be delivered
+    fn drop(&mut self) {
+        self.queue.take();
+        if let Some(thread) = self.thread.take() {
+            // A subscriber unsubscribing itself cannot wait for itself
+            if thread.thread().id() != thread::current().id() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+impl MemWatch {
+    /// Hand the events `filter` accepts (all with None) to `callback`, run
+    /// as `delivery` says; returns an id for unsubscribe(). See the
+    /// subscription module.
+    pub fn subscribe<F>(&self, filter: Option<Filter>, delivery: Delivery, callback: F) -> Result<u32, String>
+    where
+        F: Fn(&ChangeEvent) + Send + 'static,
+    {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        subscriptions.next_id += 1;
+        let id = subscriptions.next_id;
+        let callback: ChangeEventCallback = Box::new(callback);
+        let metrics = Arc::new(Mutex::new(Metrics::default()));
+        let slot = Arc::clone(&self.callback);
+        let target = match (delivery, &self.dispatch) {
+            (Delivery::Inline, _) => Target::Inline(callback, 0),
+            (Delivery::Worker, Some(pool)) => Target::Pooled(pool.consumer(callback, slot, Arc::clone(&metrics))),
+            (Delivery::Worker, None) => Target::Worker(Worker::spawn(id, callback, slot, Arc::clone(&metrics))?),
+        };
+        subscriptions.entries.push(Subscription { id, filter, target, metrics });
+        Ok(id)
+    }
+
+    /// Remove a subscriber, after a worker subscriber has seen what was
+    /// queued for it; false if there was none with this id
+    pub fn unsubscribe(&self, id: u32) -> bool {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        let Some(index) = subscriptions.entries.iter().position(|s| s.id == id) else {
+            return false;
+        };
+        let removed = subscriptions.entries.remove(index);
+        drop(subscriptions);
+        if let Target::Pooled(consumer) = &removed.target {
+            consumer.wait_idle();
+        }
+        drop(removed);
+        true
+    }
+
+    /// Delivery figures of the current subscribers, in subscription order
+    pub(crate) fn consumer_stats(&self) -> Vec<ConsumerStats> {
+        let subscriptions = self.subscriptions.lock().unwrap();
+        subscriptions.entries.iter().map(|s| s.metrics.lock().unwrap_or_else(|e| e.into_inner()).stats(s.id)).collect()
+    }
+
+    /// Run or queue subscribers. They are taken out of the registry while
+    /// running, so an inline subscriber may subscribe others itself.
+    pub(crate) fn dispatch_subscriptions(&self, events: &[ChangeEvent]) {
+        let mut entries = std::mem::take(&mut self.subscriptions.lock().unwrap().entries);
+        entries.retain_mut(|subscription| self.deliver(subscription, events));
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        entries.append(&mut subscriptions.entries);
+        subscriptions.entries = entries;
+    }
+
+    /// Deliver `events` to one subscriber; false once it is removed
+    fn deliver(&self, subscription: &mut Subscription, events: &[ChangeEvent]) -> bool {
+        let name = format!("subscription {}", subscription.id);
+        let observer = Observer::Subscription { id: subscription.id };
+        let to = || format!("subscription {}", subscription.id);
+        let mut accepted = events.iter().filter(|event| {
+            let accepted = subscription.filter.as_mut().is_none_or(|filter| filter.accepts(event));
+            if !accepted {
+                self.trace(event, || TraceStep::Skipped { to: to() });
+            }
+            accepted
+        });
+        match &mut subscription.target {
+            Target::Inline(callback, failures) => {
+                for event in accepted {
+                    let since = Instant::now();
+                    let called = panics::guarded(|| callback(event));
+                    match &called {
+                        Ok(()) => self.trace(event, || TraceStep::Delivered { to: to(), micros: micros_since(since) }),
+                        Err(message) => self.trace(event, || TraceStep::Failed { to: to(), error: message.clone() }),
+                    }
+                    if let Err(message) = called {
+                        *failures += 1;
+                        let disabled = panics::exhausted(*failures, self.failure_limit());
+                        self.queue_marker(panics::marker(0, &name, observer, *failures, disabled, &message));
+                        if disabled {
+                            return false;
+                        }
+                    } else {
+                        subscription.metrics.lock().unwrap_or_else(|e| e.into_inner()).delivered(since);
+                    }
+                }
+                true
+            }
+            Target::Worker(worker) => {
+                let queue = worker.queue.as_ref();
+                let open = queue.is_some_and(|queue| accepted.all(|event| {
+                    self.trace(event, || TraceStep::Queued { to: to() });
+                    queue.send((event.clone(), Instant::now())).is_ok()
+                }));
+                let mut stopped = !open;
+                for (failures, disabled, message) in worker.panics.lock().unwrap_or_else(|e| e.into_inner()).drain(..) {
+                    self.queue_marker(panics::marker(0, &name, observer, failures, disabled, &message));
+                    stopped |= disabled;
+                }
+                !stopped
+            }
+            Target::Pooled(consumer) => {
+                if let Some(pool) = &self.dispatch {
+                    accepted.for_each(|event| {
+                        self.trace(event, || TraceStep::Queued { to: to() });
+                        pool.enqueue(consumer, event)
+                    });
+                }
+                for (failures, disabled, message) in consumer.panics.lock().unwrap_or_else(|e| e.into_inner()).drain(..) {
+                    self.queue_marker(panics::marker(0, &name, observer, failures, disabled, &message));
+                }
+                !consumer.disabled()
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native;
+    use crate::EventKind;
+
+    #[test]
+    fn test_subscribers_get_their_filtered_events() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        let mut a = vec![0u8; 4];
+        let mut b = vec![0u8; 4];
+        let a_id = watcher.watch(&a, "orders").unwrap();
+        watcher.watch(&b, "users").unwrap();
+
+        let inline = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&inline);
+        let filter = Filter::parse("name = 'orders'").unwrap();
+        watcher.subscribe(Some(filter), Delivery::Inline, move |e| seen.lock().unwrap().push((e.region_id, e.kind))).unwrap();
+        let worker = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&worker);
+        let worker_id = watcher.subscribe(None, Delivery::Worker, move |e| seen.lock().unwrap().push(e.region_id)).unwrap();
+        watcher.subscribe(None, Delivery::Inline, |_| panic!("subscriber broke")).unwrap();
+
+        let mut events = watcher.check_changes().unwrap();
+        a[0] = 1;
+        b[0] = 1;
+        events.extend(watcher.check_changes().unwrap());
+        events.extend(watcher.check_changes().unwrap());
+        assert!(watcher.unsubscribe(worker_id));
+        assert!(!watcher.unsubscribe(worker_id));
+
+        // Watched markers of both regions, both changes, panic markers
+        assert!(worker.lock().unwrap().len() >= 4);
+        let inline = inline.lock().unwrap();
+        let changes: Vec<u32> = inline.iter().filter(|(_, kind)| *kind == EventKind::Change).map(|&(id, _)| id).collect();
+        assert_eq!(changes, [a_id]);
+        let panicked = |e: &&ChangeEvent| matches!(e.kind, EventKind::CallbackPanicked { observer: Observer::Subscription { .. }, .. });
+        assert!(events.iter().any(|e| panicked(&e)));
+    }
+
+    #[test]
+    fn test_pooled_subscribers_report_consumer_stats() {
+        let _guard = fake_native::lock();
+        let config = crate::DispatchConfig { workers: 2, queue: 16 };
+        let mut watcher = MemWatch::builder().dispatch(config).build().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        let mut a = vec![0u8; 4];
+        watcher.watch(&a, "orders").unwrap();
+
+        let pooled = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&pooled);
+        let pooled_id = watcher.subscribe(None, Delivery::Worker, move |e| seen.lock().unwrap().push(e.kind == EventKind::Change)).unwrap();
+        let inline_id = watcher.subscribe(None, Delivery::Inline, |_| ()).unwrap();
+        watcher.check_changes().unwrap();
+        a[0] = 1;
+        watcher.check_changes().unwrap();
+        assert!(watcher.unsubscribe(pooled_id));
+
+        // The Watched marker, then the change
+        assert_eq!(*pooled.lock().unwrap(), [false, true]);
+        let consumers = watcher.get_stats().unwrap().consumers;
+        assert_eq!(consumers.len(), 1);
+        assert_eq!((consumers[0].subscription, consumers[0].delivered, consumers[0].dropped), (inline_id, 2, 0));
+    }
+}
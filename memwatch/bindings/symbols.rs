@@ -0,0 +1,213 @@
+// Watching globals by symbol name
+//
+// watch_symbol() finds a static's address and size in the symbol table, so
+// globals can be watched without plumbing their addresses around:
+//
+//   watcher.watch_symbol("MY_GLOBAL_TABLE")?;
+//   watcher.watch_symbol("myapp::stats::COUNTERS")?;
+//
+// The executable's own .symtab (or .dynsym, once stripped) is read from
+// /proc/self/exe on first use and kept; Rust names are matched by their
+// demangled path or its last component, C names as they are. Names the
+// executable does not define are looked up with dlsym() in the loaded
+// shared libraries, with the size from dladdr1() (glibc).
+//
+// Only data symbols are found: functions and thread-locals are not
+// watchable memory. Linux only (ELF64, little-endian).
+
+use std::sync::OnceLock;
+
+use crate::MemWatch;
+
+/// A data symbol of the executable, at its address in this process
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Symbol {
+    /// Demangled path for Rust symbols, the raw name otherwise
+    name: String,
+    addr: u64,
+    size: u64,
+}
+
+static SYMBOLS: OnceLock<Vec<Symbol>> = OnceLock::new();
+
+/// Address and size of the data symbol `name`
+pub fn lookup(name: &str) -> Result<(u64, usize), String> {
+    let symbols = SYMBOLS.get_or_init(|| executable_symbols().unwrap_or_default());
+    let exact: Vec<&Symbol> = symbols.iter().filter(|s| s.name == name).collect();
+    let candidates = if exact.is_empty() {
+        symbols.iter().filter(|s| s.name.rsplit("::").next() == Some(name)).collect()
+    } else {
+        exact
+    };
+    match candidates[..] {
+        [symbol] => Ok((symbol.addr, symbol.size as usize)),
+        [a, b, ..] => Err(format!("{} is ambiguous: {} or {}", name, a.name, b.name)),
+        [] => shared_library_symbol(name).ok_or_else(|| format!("No data symbol named {}", name)),
+    }
+}
+
+impl MemWatch {
+    /// Watch the static or global named `name`; see the symbols module
+    pub fn watch_symbol(&self, name: &str) -> Result<u32, String> {
+        let (addr, size) = lookup(name)?;
+        if size == 0 {
+            return Err(format!("{} has no size in the symbol table", name));
+        }
+        // SAFETY: data symbols stay mapped for as long as their object is
+        // loaded, which for the executable is the life of the process
+        let buffer = unsafe { std::slice::from_raw_parts(addr as *const u8, size) };
+        self.watch(buffer, name)
+    }
+}
+
+fn executable_symbols() -> Result<Vec<Symbol>, String> {
+    let elf = std::fs::read("/proc/self/exe").map_err(|e| e.to_string())?;
+    let bias = crate::process::load_bias();
+    let mut symbols = read_symbols(&elf, elf::SHT_SYMTAB)?;
+    if symbols.is_empty() {
+        symbols = read_symbols(&elf, elf::SHT_DYNSYM)?;
+    }
+    for symbol in symbols.iter_mut() {
+        symbol.addr = symbol.addr.wrapping_add(bias);
+    }
+    symbols.sort_by(|a, b| (&a.name, a.addr).cmp(&(&b.name, b.addr)));
+    symbols.dedup();
+    Ok(symbols)
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn shared_library_symbol(name: &str) -> Option<(u64, usize)> {
+    let c_name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: dlsym and dladdr1 only read the name and write the info and
+    // symbol pointer we pass; the symbol entry lives in the loaded object
+    unsafe {
+        let addr = libc::dlsym(libc::RTLD_DEFAULT, c_name.as_ptr());
+        if addr.is_null() {
+            return None;
+        }
+        let mut info: libc::Dl_info = std::mem::zeroed();
+        let mut entry: *mut libc::c_void = std::ptr::null_mut();
+        if libc::dladdr1(addr, &mut info, &mut entry, elf::RTLD_DL_SYMENT) == 0 || entry.is_null() {
+            return None;
+        }
+        let symbol = &*(entry as *const libc::Elf64_Sym);
+        (symbol.st_info & 0xf == elf::STT_OBJECT).then_some((addr as u64, symbol.st_size as usize))
+    }
+}
+
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+fn shared_library_symbol(_name: &str) -> Option<(u64, usize)> {
+    None
+}
+
+/// The little of ELF64 read here
+mod elf {
+    pub const SHT_SYMTAB: u32 = 2;
+    pub const SHT_DYNSYM: u32 = 11;
+    pub const STT_OBJECT: u8 = 1;
+    /// dladdr1() flag for the symbol entry (glibc's dlfcn.h; not in libc)
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    pub const RTLD_DL_SYMENT: i32 = 1;
+    /// Section header and symbol entry sizes
+    pub const SHDR_SIZE: usize = 64;
+    pub const SYM_SIZE: usize = 24;
+}
+
+fn u16_at(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn u64_at(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+/// Defined data symbols of the first section of type `section_type`, at
+/// their link-time addresses
+fn read_symbols(elf: &[u8], section_type: u32) -> Result<Vec<Symbol>, String> {
+    if elf.get(..6) != Some(b"\x7fELF\x02\x01") {
+        return Err("Not a little-endian ELF64 file".to_string());
+    }
+    let truncated = || "Truncated ELF file".to_string();
+    let section_offset = u64_at(elf, 0x28).ok_or_else(truncated)? as usize;
+    let section_count = u16_at(elf, 0x3c).ok_or_else(truncated)? as usize;
+    let section = |index: usize| section_offset + index * elf::SHDR_SIZE;
+
+    let mut symbols = Vec::new();
+    let Some(table) = (0..section_count).map(section).find(|&at| u32_at(elf, at + 4) == Some(section_type)) else {
+        return Ok(symbols);
+    };
+    let (offset, size) = (u64_at(elf, table + 0x18), u64_at(elf, table + 0x20));
+    let strings = section(u32_at(elf, table + 0x28).ok_or_else(truncated)? as usize);
+    let strings_offset = u64_at(elf, strings + 0x18).ok_or_else(truncated)? as usize;
+    let entries = elf.get(offset.ok_or_else(truncated)? as usize..).ok_or_else(truncated)?;
+    let entries = entries.get(..size.ok_or_else(truncated)? as usize).ok_or_else(truncated)?;
+
+    for entry in entries.chunks_exact(elf::SYM_SIZE) {
+        let (info, section_index) = (entry[4], u16_at(entry, 6).unwrap_or(0));
+        if info & 0xf != elf::STT_OBJECT || section_index == 0 {
+            continue;
+        }
+        let name_at = strings_offset + u32_at(entry, 0).unwrap_or(0) as usize;
+        let Some(raw) = elf.get(name_at..).and_then(|rest| rest.split(|&b| b == 0).next()) else {
+            continue;
+        };
+        let raw = String::from_utf8_lossy(raw);
+        let name = demangle(&raw).unwrap_or_else(|| raw.into_owned());
+        symbols.push(Symbol { name, addr: u64_at(entry, 8).unwrap_or(0), size: u64_at(entry, 16).unwrap_or(0) });
+    }
+    Ok(symbols)
+}
+
+/// Path of a legacy-mangled Rust symbol, without its hash:
+/// "_ZN5myapp5stats8COUNTERS17h0123456789abcdefE" is "myapp::stats::COUNTERS"
+fn demangle(symbol: &str) -> Option<String> {
+    let mut rest = symbol.strip_prefix("_ZN")?;
+    let mut path = Vec::new();
+    while !rest.starts_with('E') {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let len: usize = rest[..digits].parse().ok()?;
+        path.push(rest.get(digits..digits + len)?);
+        rest = &rest[digits + len..];
+    }
+    let is_hash = |part: &&str| part.len() == 17 && part.starts_with('h') && part[1..].chars().all(|c| c.is_ascii_hexdigit());
+    if path.last().is_some_and(is_hash) {
+        path.pop();
+    }
+    (!path.is_empty()).then(|| path.join("::"))
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native;
+
+    static MY_GLOBAL_TABLE: [u64; 4] = [1, 2, 3, 4];
+
+    #[test]
+    fn test_demangle() {
+        assert_eq!(demangle("_ZN5myapp5stats8COUNTERS17h0123456789abcdefE").as_deref(), Some("myapp::stats::COUNTERS"));
+        assert_eq!(demangle("_ZN4core3fmt3h00E").as_deref(), Some("core::fmt::h00"));
+        assert_eq!(demangle("global_counter"), None);
+        assert_eq!(demangle("_ZN50short"), None);
+    }
+
+    #[test]
+    fn test_watch_symbol_finds_statics() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        let table = std::hint::black_box(&MY_GLOBAL_TABLE);
+
+        assert_eq!(lookup("MY_GLOBAL_TABLE"), Ok((table.as_ptr() as u64, 32)));
+        assert_eq!(lookup("memwatch::symbols::tests::MY_GLOBAL_TABLE").map(|(addr, _)| addr), Ok(table.as_ptr() as u64));
+        watcher.watch_symbol("MY_GLOBAL_TABLE").unwrap();
+        assert!(watcher.watch_symbol("NO_SUCH_GLOBAL").is_err());
+        let regions: Vec<(u64, usize)> = watcher.regions.lock().unwrap().values().map(|r| (r.addr, r.size)).collect();
+        assert_eq!(regions, [(table.as_ptr() as u64, 32)]);
+    }
+}
@@ -0,0 +1,210 @@
+// Alert message templates
+//
+// A Template renders a change into text, handlebars style:
+//
+//   "{{region}} changed at {{location.file}}:{{location.line}}: {{old_hex}} -> {{new_hex}}"
+//
+// Variables: region, region_id, kind, seq, global_seq, timestamp_ns, tid,
+// location.file, location.function, location.line, location.fault_ip,
+// old_hex, new_hex (full values when captured, previews otherwise), and
+// decoded.old / decoded.new ("field=value, .." through the region's
+// decoder, hex without one). Helpers take a variable and an argument:
+//
+//   {{hex region}}           the variable's text as hex
+//   {{truncate new_hex 16}}  at most 16 characters, ".." marking a cut
+//
+// Missing values (no file, no tid) render as "?". Templates are checked
+// when parsed, so a typo fails at startup, not in the first alert.
+// add_alert_template() raises alerts with a template from a hook.
+
+use std::sync::{Arc, Mutex};
+
+use crate::decode::{DecodedChange, DecodedField, DecoderRegistry};
+use crate::export::hex;
+use crate::policy::Decision;
+use crate::{ChangeEvent, MemWatch};
+
+const VARIABLES: &[&str] = &[
+    "region",
+    "region_id",
+    "kind",
+    "seq",
+    "global_seq",
+    "timestamp_ns",
+    "tid",
+    "location.file",
+    "location.function",
+    "location.line",
+    "location.fault_ip",
+    "old_hex",
+    "new_hex",
+    "decoded.old",
+    "decoded.new",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Variable(String),
+    Hex(String),
+    Truncate(String, usize),
+}
+
+/// Parsed message template; see the template module
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let end = rest[start..].find("}}").ok_or_else(|| format!("Unclosed {{{{ in template {:?}", source))?;
+            let expr = &rest[start + 2..start + end];
+            parts.push(parse_expr(expr.trim())?);
+            rest = &rest[start + end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        Ok(Template { parts })
+    }
+
+    /// Render `event`; `decoded` feeds decoded.old / decoded.new
+    pub fn render(&self, event: &ChangeEvent, decoded: Option<&DecodedChange>) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Variable(name) => out.push_str(&variable(name, event, decoded)),
+                Part::Hex(name) => out.push_str(&hex(variable(name, event, decoded).as_bytes())),
+                Part::Truncate(name, limit) => {
+                    let value = variable(name, event, decoded);
+                    match value.char_indices().nth(*limit) {
+                        Some((cut, _)) => {
+                            out.push_str(&value[..cut]);
+                            out.push_str("..");
+                        }
+                        None => out.push_str(&value),
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+fn parse_expr(expr: &str) -> Result<Part, String> {
+    let words: Vec<&str> = expr.split_whitespace().collect();
+    let known = |name: &str| {
+        if VARIABLES.contains(&name) {
+            Ok(name.to_string())
+        } else {
+            Err(format!("Unknown template variable {:?}", name))
+        }
+    };
+    match words.as_slice() {
+        [name] => Ok(Part::Variable(known(name)?)),
+        ["hex", name] => Ok(Part::Hex(known(name)?)),
+        ["truncate", name, limit] => {
+            let limit = limit.parse().map_err(|_| format!("Bad truncate length {:?}", limit))?;
+            Ok(Part::Truncate(known(name)?, limit))
+        }
+        _ => Err(format!("Bad template expression {{{{{}}}}}", expr)),
+    }
+}
+
+fn variable(name: &str, event: &ChangeEvent, decoded: Option<&DecodedChange>) -> String {
+    let missing = || "?".to_string();
+    let old = if event.old_value.is_empty() { &event.old_preview } else { &event.old_value };
+    let new = if event.new_value.is_empty() { &event.new_preview } else { &event.new_value };
+    let fields = |fields: Option<&Vec<DecodedField>>, raw: &[u8]| match fields {
+        Some(fields) => fields.iter().map(|f| format!("{}={}", f.name, f.value)).collect::<Vec<_>>().join(", "),
+        None => hex(raw),
+    };
+    match name {
+        "region" => event.variable_name.clone().unwrap_or_else(|| format!("region {}", event.region_id)),
+        "region_id" => event.region_id.to_string(),
+        "kind" => event.kind.as_str().to_string(),
+        "seq" => event.seq.to_string(),
+        "global_seq" => event.global_seq.to_string(),
+        "timestamp_ns" => event.timestamp_ns.to_string(),
+        "tid" => event.tid.map_or_else(missing, |tid| tid.to_string()),
+        "location.file" => event.where_.file.clone().unwrap_or_else(missing),
+        "location.function" => event.where_.function.clone().unwrap_or_else(missing),
+        "location.line" => event.where_.line.to_string(),
+        "location.fault_ip" => format!("0x{:x}", event.where_.fault_ip),
+        "old_hex" => hex(old),
+        "new_hex" => hex(new),
+        "decoded.old" => fields(decoded.and_then(|d| d.old.as_ref()), old),
+        "decoded.new" => fields(decoded.and_then(|d| d.new.as_ref()), new),
+        _ => missing(),
+    }
+}
+
+impl MemWatch {
+    /// Raise an alert rendered from `template` for every change `when`
+    /// accepts (decoded.* through the region's decoder); added as a
+    /// pre-persist hook
+    pub fn add_alert_template<F>(&self, when: F, template: Template)
+    where
+        F: Fn(&ChangeEvent) -> bool + Send + 'static,
+    {
+        let decoders: Arc<Mutex<DecoderRegistry>> = Arc::clone(&self.decoders);
+        self.add_pre_persist_hook(move |event| {
+            if !when(event) {
+                return Decision::Record;
+            }
+            let decoded = decoders.lock().unwrap().decode(event);
+            Decision::Alert(template.render(event, decoded.as_ref()))
+        });
+    }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::decode::{PreviewDecoder, TlvDecoder};
+    use crate::fake_native;
+
+    #[test]
+    fn test_parse_rejects_typos() {
+        assert!(Template::parse("{{region}} at {{location.file}}").is_ok());
+        assert!(Template::parse("{{regoin}}").unwrap_err().contains("regoin"));
+        assert!(Template::parse("{{truncate new_hex many}}").is_err());
+        assert!(Template::parse("{{shout region}}").is_err());
+        assert!(Template::parse("{{region").is_err());
+    }
+
+    #[test]
+    fn test_alerts_render_templates() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        watcher.add_decoder(TlvDecoder { tag_bytes: 1, len_bytes: 1 });
+        let mut message = [1u8, 1, 5, 0, 0, 0, 0, 0];
+        let id = watcher.watch_with_max_value_bytes(&message, "message", -1).unwrap();
+        watcher.set_region_decoder(id, "tlv").unwrap();
+        watcher.check_changes().unwrap();
+
+        let template = Template::parse(
+            "{{region}} changed at {{location.file}}:{{location.line}}: {{truncate old_hex 6}} -> {{decoded.new}} ({{hex kind}})",
+        )
+        .unwrap();
+        watcher.add_alert_template(|event| event.variable_name.as_deref() == Some("message"), template);
+        message[2] = 9;
+        watcher.check_changes().unwrap();
+        let alerts = watcher.take_alerts();
+        let fields = TlvDecoder { tag_bytes: 1, len_bytes: 1 }.decode(&message).unwrap();
+        let fields: Vec<String> = fields.iter().map(|f| format!("{}={}", f.name, f.value)).collect();
+        let expected = format!("message changed at ?:0: 010105.. -> {} ({})", fields.join(", "), hex(b"change"));
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].message, expected);
+    }
+}
@@ -0,0 +1,96 @@
+// Thread attribution
+//
+// The native core records the writing thread at fault time
+// (ChangeEventC::thread_id, thread_name) and the Rust layer keeps it as
+// ChangeEvent::tid and thread_name. Names the core leaves out are read from
+// /proc/self/task/<tid>/comm when the event is collected, once per thread.
+// Stats::events_per_thread counts the changes of each thread since the last
+// clear_history(), busiest first, to find the thread clobbering shared
+// state. Changes without a thread (snapshot polling, hardware breakpoints)
+// are not counted.
+
+use std::collections::HashMap;
+
+use crate::process::thread_name;
+use crate::{ChangeEvent, EventKind};
+
+/// Changes written by one thread
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ThreadStats {
+    pub tid: u32,
+    pub name: Option<String>,
+    pub events: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ThreadCounts {
+    /// Names seen or looked up, kept across clear_history()
+    names: HashMap<u32, Option<String>>,
+    events: HashMap<u32, u64>,
+}
+
+impl ThreadCounts {
+    /// Name unnamed events and count changes
+    pub(crate) fn record(&mut self, events: &mut [ChangeEvent]) {
+        for event in events.iter_mut() {
+            let Some(tid) = event.tid else {
+                continue;
+            };
+            match &event.thread_name {
+                Some(name) => {
+                    self.names.insert(tid, Some(name.clone()));
+                }
+                None => event.thread_name = self.names.entry(tid).or_insert_with(|| thread_name(tid)).clone(),
+            }
+            if event.kind == EventKind::Change {
+                *self.events.entry(tid).or_default() += 1;
+            }
+        }
+    }
+
+    pub(crate) fn stats(&self) -> Vec<ThreadStats> {
+        let mut stats: Vec<ThreadStats> = self
+            .events
+            .iter()
+            .map(|(&tid, &events)| ThreadStats { tid, name: self.names.get(&tid).cloned().flatten(), events })
+            .collect();
+        stats.sort_by(|a, b| b.events.cmp(&a.events).then(a.tid.cmp(&b.tid)));
+        stats
+    }
+
+    pub(crate) fn reset_counts(&mut self) {
+        self.events.clear();
+    }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use crate::capabilities::WatchMode;
+    use crate::fake_native::{self, FakeEvent};
+    use crate::MemWatch;
+
+    #[test]
+    fn test_changes_are_attributed_to_their_thread() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Protect;
+        let buffer = [0u8; 8];
+        let region_id = watcher.watch(&buffer, "shared").unwrap();
+        let by = |tid| FakeEvent { tid, ..FakeEvent::change(region_id, &[1]) };
+        fake_native::state().events.extend([by(41), by(42), by(42), by(0)]);
+
+        let events = watcher.check_changes().unwrap();
+        let changes: Vec<(Option<u32>, Option<&str>)> = events[1..].iter().map(|e| (e.tid, e.thread_name.as_deref())).collect();
+        assert_eq!(changes, [(Some(41), Some("fake-worker")), (Some(42), Some("fake-worker")), (Some(42), Some("fake-worker")), (None, None)]);
+        // The Watched marker names the registering thread
+        assert_eq!(events[0].tid, Some(crate::process::current_tid()));
+        assert!(events[0].thread_name.is_some());
+
+        let per_thread = watcher.get_stats().unwrap().events_per_thread;
+        let counts: Vec<(u32, u64)> = per_thread.iter().map(|t| (t.tid, t.events)).collect();
+        assert_eq!(counts, [(42, 2), (41, 1)]);
+        watcher.clear_history();
+        assert!(watcher.get_stats().unwrap().events_per_thread.is_empty());
+    }
+}
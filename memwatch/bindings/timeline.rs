@@ -0,0 +1,163 @@
+// Per-region change timeline
+//
+// Every change event lands in a sparse 1 ms base bucket as it is returned by
+// check_changes(), so recording is O(1) per event. Queries merge base buckets
+// into the requested width without rescanning history. Lifecycle markers are
+// kept alongside so charts can annotate them.
+//
+// History is bounded per region: the newest MAX_BUCKETS active milliseconds
+// and MAX_MARKERS markers are kept, older ones dropped first.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use crate::{ChangeEvent, EventKind};
+
+const BASE_BUCKET_NS: u64 = 1_000_000;
+/// Active base buckets kept per region
+const MAX_BUCKETS: usize = 1 << 16;
+/// Lifecycle markers kept per region
+const MAX_MARKERS: usize = 1024;
+
+/// Activity of one region during one time bucket
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BucketStats {
+    /// Bucket start, nanoseconds since the Unix epoch
+    pub start_ns: u64,
+    pub events: u64,
+    /// Bytes that differ between old and new contents, summed over events
+    pub bytes_changed: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct Timeline {
+    regions: HashMap<u32, BTreeMap<u64, BucketStats>>,
+    markers: HashMap<u32, Vec<(u64, EventKind)>>,
+}
+
+impl Timeline {
+    pub(crate) fn record(&mut self, event: &ChangeEvent) {
+        if event.kind.is_marker() {
+            let markers = self.markers.entry(event.region_id).or_default();
+            if markers.len() == MAX_MARKERS {
+                markers.remove(0);
+            }
+            markers.push((event.timestamp_ns, event.kind));
+            return;
+        }
+        let index = event.timestamp_ns / BASE_BUCKET_NS;
+        let buckets = self.regions.entry(event.region_id).or_default();
+        let bucket = buckets.entry(index).or_insert(BucketStats {
+            start_ns: index * BASE_BUCKET_NS,
+            ..BucketStats::default()
+        });
+        bucket.events += 1;
+        bucket.bytes_changed += bytes_changed(event);
+        if buckets.len() > MAX_BUCKETS {
+            buckets.pop_first();
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.regions.clear();
+        self.markers.clear();
+    }
+
+    pub(crate) fn markers(&self, region_id: u32) -> Vec<(u64, EventKind)> {
+        self.markers.get(&region_id).cloned().unwrap_or_default()
+    }
+
+    /// Starts of the first and last active base buckets, without filling
+    /// the gaps between them
+    pub(crate) fn span(&self, region_id: u32) -> Option<(u64, u64)> {
+        let base = self.regions.get(&region_id)?;
+        Some((base.first_key_value()?.1.start_ns, base.last_key_value()?.1.start_ns))
+    }
+
+    /// Buckets of width `bucket` (rounded to whole milliseconds, minimum one)
+    /// from the first to the last active bucket, empty ones included
+    pub(crate) fn query(&self, region_id: u32, bucket: Duration) -> Vec<BucketStats> {
+        let Some(base) = self.regions.get(&region_id) else {
+            return Vec::new();
+        };
+        let width = (bucket.as_nanos() as u64 / BASE_BUCKET_NS).max(1) * BASE_BUCKET_NS;
+
+        let mut out: Vec<BucketStats> = Vec::new();
+        for stats in base.values() {
+            let start_ns = stats.start_ns / width * width;
+            if let Some(last) = out.last() {
+                let mut next = last.start_ns + width;
+                while next < start_ns {
+                    out.push(BucketStats { start_ns: next, ..BucketStats::default() });
+                    next += width;
+                }
+            }
+            match out.last_mut() {
+                Some(last) if last.start_ns == start_ns => {
+                    last.events += stats.events;
+                    last.bytes_changed += stats.bytes_changed;
+                }
+                _ => out.push(BucketStats { start_ns, ..*stats }),
+            }
+        }
+        out
+    }
+}
+
+pub(crate) fn bytes_changed(event: &ChangeEvent) -> u64 {
+    let (old, new) = if !event.old_value.is_empty() || !event.new_value.is_empty() {
+        (&event.old_value, &event.new_value)
+    } else {
+        (&event.old_preview, &event.new_preview)
+    };
+    let differing = old.iter().zip(new.iter()).filter(|(a, b)| a != b).count();
+    (differing + old.len().abs_diff(new.len())) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::marker;
+    use crate::EventKind;
+
+    fn change(timestamp_ns: u64, old: &[u8], new: &[u8]) -> ChangeEvent {
+        let mut event = marker(1, "buf", EventKind::Change);
+        event.timestamp_ns = timestamp_ns;
+        event.old_preview = old.to_vec();
+        event.new_preview = new.to_vec();
+        event
+    }
+
+    #[test]
+    fn test_buckets_merge_and_fill_gaps() {
+        let mut timeline = Timeline::default();
+        timeline.record(&change(1_000_000_000, &[0, 0], &[1, 0]));
+        timeline.record(&change(1_400_000_000, &[1, 0], &[2, 2]));
+        timeline.record(&change(3_100_000_000, &[2, 2], &[2, 3]));
+        timeline.record(&marker(1, "buf", EventKind::Unwatched));
+
+        let buckets = timeline.query(1, Duration::from_secs(1));
+        assert_eq!(buckets.len(), 3);
+        assert_eq!((buckets[0].events, buckets[0].bytes_changed), (2, 3));
+        assert_eq!(buckets[1], BucketStats { start_ns: 2_000_000_000, events: 0, bytes_changed: 0 });
+        assert_eq!((buckets[2].start_ns, buckets[2].events), (3_000_000_000, 1));
+        assert!(timeline.query(2, Duration::from_secs(1)).is_empty());
+        assert_eq!(timeline.markers(1).len(), 1);
+        assert_eq!(timeline.span(1), Some((1_000_000_000, 3_100_000_000)));
+        assert_eq!(timeline.span(2), None);
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut timeline = Timeline::default();
+        for i in 0..MAX_BUCKETS as u64 + 10 {
+            timeline.record(&change(i * BASE_BUCKET_NS, &[0], &[1]));
+        }
+        for _ in 0..MAX_MARKERS + 10 {
+            timeline.record(&marker(1, "buf", EventKind::Unwatched));
+        }
+        assert_eq!(timeline.span(1), Some((10 * BASE_BUCKET_NS, (MAX_BUCKETS as u64 + 9) * BASE_BUCKET_NS)));
+        assert_eq!(timeline.query(1, Duration::from_millis(1)).len(), MAX_BUCKETS);
+        assert_eq!(timeline.markers(1).len(), MAX_MARKERS);
+    }
+}
@@ -0,0 +1,202 @@
+// Counted triggers
+//
+// A trigger fires once, on the change of its region that crosses its
+// threshold, for regions whose first few changes are expected and only
+// later ones are suspicious:
+//
+//   watcher.add_trigger(Trigger::nth_change(region, 5));
+//   watcher.add_trigger(Trigger::after_total_bytes(region, 4096).break_into_debugger());
+//
+// Firing places a Triggered marker right after that change (react to it
+// with watch_with_callback() or subscribe()) and disarms the trigger. With
+// break_into_debugger() it also raises SIGTRAP in check_changes() while a
+// debugger is attached, stopping with the change in hand; without one the
+// marker is all that happens.
+//
+// Counting starts when the trigger is added and covers the changes
+// check_changes() returns, after filters, sampling and hooks. Bytes are the
+// bytes that differ: from deltas when both values were captured, from the
+// previews otherwise.
+
+use crate::{lifecycle, process, ChangeEvent, EventKind, MemWatch};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Threshold {
+    Changes(u64),
+    Bytes(u64),
+}
+
+/// Fires once a region has seen enough changes; see the trigger module
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trigger {
+    region_id: u32,
+    threshold: Threshold,
+    break_into_debugger: bool,
+}
+
+impl Trigger {
+    /// Fire on the `n`th change of `region_id` (n = 0 fires on the first)
+    pub fn nth_change(region_id: u32, n: u64) -> Trigger {
+        Trigger { region_id, threshold: Threshold::Changes(n.max(1)), break_into_debugger: false }
+    }
+
+    /// Fire on the change that brings the bytes changed in `region_id` to
+    /// `bytes` or more
+    pub fn after_total_bytes(region_id: u32, bytes: u64) -> Trigger {
+        Trigger { region_id, threshold: Threshold::Bytes(bytes), break_into_debugger: false }
+    }
+
+    /// Also raise SIGTRAP when firing while a debugger is attached
+    pub fn break_into_debugger(mut self) -> Trigger {
+        self.break_into_debugger = true;
+        self
+    }
+
+    pub fn region_id(&self) -> u32 {
+        self.region_id
+    }
+}
+
+struct Armed {
+    id: u32,
+    trigger: Trigger,
+    /// Changes or bytes counted so far
+    count: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct Triggers {
+    armed: Vec<Armed>,
+    next_id: u32,
+}
+
+impl Triggers {
+    pub(crate) fn add(&mut self, trigger: Trigger) -> u32 {
+        self.next_id += 1;
+        self.armed.push(Armed { id: self.next_id, trigger, count: 0 });
+        self.next_id
+    }
+
+    pub(crate) fn remove(&mut self, id: u32) -> bool {
+        let before = self.armed.len();
+        self.armed.retain(|armed| armed.id != id);
+        self.armed.len() != before
+    }
+
+    pub(crate) fn forget_region(&mut self, region_id: u32) {
+        self.armed.retain(|armed| armed.trigger.region_id != region_id);
+    }
+
+    /// Count a batch, placing a Triggered marker after each change that
+    /// fires a trigger. True if one of those asked to break.
+    pub(crate) fn fire(&mut self, events: Vec<ChangeEvent>) -> (Vec<ChangeEvent>, bool) {
+        if self.armed.is_empty() {
+            return (events, false);
+        }
+        let mut out = Vec::with_capacity(events.len());
+        let mut breaks = false;
+        for event in events {
+            let mut fired = Vec::new();
+            if !event.kind.is_marker() {
+                for armed in self.armed.iter_mut().filter(|a| a.trigger.region_id == event.region_id) {
+                    let (step, threshold) = match armed.trigger.threshold {
+                        Threshold::Changes(n) => (1, n),
+                        Threshold::Bytes(n) => (changed_bytes(&event), n),
+                    };
+                    armed.count += step;
+                    if armed.count >= threshold {
+                        fired.push((armed.id, armed.count));
+                        breaks |= armed.trigger.break_into_debugger;
+                    }
+                }
+                self.armed.retain(|armed| !fired.iter().any(|&(id, _)| id == armed.id));
+            }
+            let name = event.variable_name.clone().unwrap_or_default();
+            let (region_id, timestamp_ns, where_) = (event.region_id, event.timestamp_ns, event.where_.clone());
+            out.push(event);
+            for (trigger, count) in fired {
+                let mut marker = lifecycle::marker(region_id, &name, EventKind::Triggered { trigger, count });
+                marker.timestamp_ns = timestamp_ns;
+                marker.where_ = where_.clone();
+                out.push(marker);
+            }
+        }
+        (out, breaks)
+    }
+}
+
+fn changed_bytes(event: &ChangeEvent) -> u64 {
+    if !event.deltas.is_empty() {
+        return event.deltas.iter().map(|range| range.len as u64).sum();
+    }
+    let (old, new) = (&event.old_preview, &event.new_preview);
+    let differing = old.iter().zip(new).filter(|(a, b)| a != b).count();
+    (differing + old.len().abs_diff(new.len())) as u64
+}
+
+/// Stop in the attached debugger, if there is one
+pub(crate) fn break_into_debugger() {
+    if process::debugger_attached() {
+        #[cfg(unix)]
+        // SAFETY: raising SIGTRAP has no preconditions; the tracer takes it
+        unsafe {
+            libc::raise(libc::SIGTRAP);
+        }
+    }
+}
+
+impl MemWatch {
+    /// Arm a trigger; returns its id, carried by its Triggered marker
+    pub fn add_trigger(&self, trigger: Trigger) -> u32 {
+        self.triggers.lock().unwrap().add(trigger)
+    }
+
+    /// Disarm a trigger that has not fired; false if it is unknown or has
+    /// fired already
+    pub fn remove_trigger(&self, id: u32) -> bool {
+        self.triggers.lock().unwrap().remove(id)
+    }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::capabilities::WatchMode;
+    use crate::fake_native;
+
+    #[test]
+    fn test_triggers_fire_once_past_their_threshold() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Snapshot;
+        let mut buf = vec![0u8; 8];
+        let region_id = watcher.watch(&buf, "counters").unwrap();
+        let third = watcher.add_trigger(Trigger::nth_change(region_id, 3));
+        let bytes = watcher.add_trigger(Trigger::after_total_bytes(region_id, 6));
+        let removed = watcher.add_trigger(Trigger::nth_change(region_id, 1));
+        assert!(watcher.remove_trigger(removed));
+        watcher.check_changes().unwrap();
+
+        let mut fired = Vec::new();
+        for round in 1..=5u8 {
+            buf[0] = round;
+            buf[1] = round;
+            for event in watcher.check_changes().unwrap() {
+                if let EventKind::Triggered { trigger, count } = event.kind {
+                    fired.push((round, trigger, count));
+                }
+            }
+        }
+        assert_eq!(fired, [(3, third, 3), (3, bytes, 6)]);
+        assert!(!watcher.remove_trigger(third));
+    }
+
+    #[test]
+    fn test_changed_bytes() {
+        let mut event = lifecycle::marker(1, "r", EventKind::Change);
+        (event.old_preview, event.new_preview) = (vec![1, 2, 3], vec![1, 9, 3, 4]);
+        assert_eq!(changed_bytes(&event), 2);
+        event.deltas = vec![crate::diff::ByteRange { offset: 0, len: 5 }];
+        assert_eq!(changed_bytes(&event), 5);
+    }
+}
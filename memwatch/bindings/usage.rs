@@ -0,0 +1,106 @@
+// Region usage: never-written blocks and writes after free
+//
+// Built from the event stream alone. A region that was watched and never
+// written is wasted memory; a write to a region after its Freed marker is a
+// use-after-free write. mark_freed() keeps the region protected, so such
+// writes still fault and arrive with full attribution.
+
+use std::collections::BTreeMap;
+
+use crate::{AllocationSite, ChangeEvent, EventKind};
+
+/// Lifetime summary of one region
+#[derive(Debug, Clone)]
+pub struct RegionUsage {
+    pub region_id: u32,
+    pub name: String,
+    pub size: usize,
+    pub allocated_at: Option<AllocationSite>,
+    /// Changes before the region was freed
+    pub writes: u64,
+    pub freed: bool,
+    /// Still watched (no Unwatched marker yet)
+    pub live: bool,
+    pub writes_after_free: u64,
+    /// First offending change, with its attribution
+    pub first_write_after_free: Option<ChangeEvent>,
+}
+
+/// Result of MemWatch::usage_report()
+#[derive(Debug, Clone, Default)]
+pub struct UsageReport {
+    /// Regions never written since they were watched, by region id
+    pub never_written: Vec<RegionUsage>,
+    /// Regions written after mark_freed(), by region id
+    pub written_after_free: Vec<RegionUsage>,
+}
+
+impl UsageReport {
+    /// Bytes held by never-written regions
+    pub fn wasted_bytes(&self) -> usize {
+        self.never_written.iter().map(|usage| usage.size).sum()
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct UsageTracker {
+    regions: BTreeMap<u32, RegionUsage>,
+}
+
+impl UsageTracker {
+    pub(crate) fn record(&mut self, event: &ChangeEvent) {
+        if let EventKind::Watched { size, .. } = event.kind {
+            self.regions.insert(
+                event.region_id,
+                RegionUsage {
+                    region_id: event.region_id,
+                    name: event.variable_name.clone().unwrap_or_default(),
+                    size,
+                    allocated_at: event.allocated_at,
+                    writes: 0,
+                    freed: false,
+                    live: true,
+                    writes_after_free: 0,
+                    first_write_after_free: None,
+                },
+            );
+            return;
+        }
+        let Some(usage) = self.regions.get_mut(&event.region_id) else {
+            return;
+        };
+        match event.kind {
+            EventKind::Change if usage.freed => {
+                usage.writes_after_free += 1;
+                if usage.first_write_after_free.is_none() {
+                    usage.first_write_after_free = Some(event.clone());
+                }
+            }
+            EventKind::Change => usage.writes += 1,
+            EventKind::Freed => usage.freed = true,
+            EventKind::Unwatched => usage.live = false,
+            _ => {}
+        }
+    }
+
+    /// Changes recorded for a region, before and after free
+    pub(crate) fn writes(&self, region_id: u32) -> u64 {
+        self.regions.get(&region_id).map(|usage| usage.writes + usage.writes_after_free).unwrap_or(0)
+    }
+
+    /// Forget counts but keep the regions and their freed state
+    pub(crate) fn reset_counts(&mut self) {
+        for usage in self.regions.values_mut() {
+            usage.writes = 0;
+            usage.writes_after_free = 0;
+            usage.first_write_after_free = None;
+        }
+    }
+
+    pub(crate) fn report(&self) -> UsageReport {
+        UsageReport {
+            never_written: self.regions.values().filter(|u| u.writes == 0).cloned().collect(),
+            written_after_free: self.regions.values().filter(|u| u.writes_after_free > 0).cloned().collect(),
+        }
+    }
+}
@@ -0,0 +1,192 @@
+// vsock transport for VM guests (Linux)
+//
+// A process inside a VM can stream events to a collector on the host over
+// vsock, with no network set up in the guest: the host is always CID 2 and
+// the collector only needs a port.
+//
+//   // host
+//   memwatch-collect serve events.store --listen vsock:any:7077
+//   // guest
+//   let sink = StreamSink::connect_vsock("vsock:host:7077".parse()?, &ProcessIdentity::current())?;
+//
+// The streams are the same as over TCP; see the collect module.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::str::FromStr;
+
+/// Any CID, for listening
+pub const CID_ANY: u32 = libc::VMADDR_CID_ANY;
+/// The hypervisor's host, as seen from a guest
+pub const CID_HOST: u32 = libc::VMADDR_CID_HOST;
+
+/// A vsock address: context id (the VM) and port
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VsockAddr {
+    pub cid: u32,
+    pub port: u32,
+}
+
+impl fmt::Display for VsockAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cid {
+            CID_ANY => write!(f, "vsock:any:{}", self.port),
+            CID_HOST => write!(f, "vsock:host:{}", self.port),
+            cid => write!(f, "vsock:{}:{}", cid, self.port),
+        }
+    }
+}
+
+impl FromStr for VsockAddr {
+    type Err = String;
+
+    /// "vsock:<cid>:<port>", the CID a number, "any" or "host"
+    fn from_str(s: &str) -> Result<Self, String> {
+        let bad = || format!("Bad vsock address {:?}, expected vsock:<cid>:<port>", s);
+        let (cid, port) = s.strip_prefix("vsock:").and_then(|rest| rest.split_once(':')).ok_or_else(bad)?;
+        let cid = match cid {
+            "any" => CID_ANY,
+            "host" => CID_HOST,
+            cid => cid.parse().map_err(|_| bad())?,
+        };
+        Ok(VsockAddr { cid, port: port.parse().map_err(|_| bad())? })
+    }
+}
+
+impl VsockAddr {
+    fn to_sockaddr(self) -> libc::sockaddr_vm {
+        // SAFETY: sockaddr_vm is plain data; all-zero is its unset state
+        let mut addr: libc::sockaddr_vm = unsafe { std::mem::zeroed() };
+        addr.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+        addr.svm_cid = self.cid;
+        addr.svm_port = self.port;
+        addr
+    }
+
+    fn from_sockaddr(addr: &libc::sockaddr_vm) -> Self {
+        VsockAddr { cid: addr.svm_cid, port: addr.svm_port }
+    }
+}
+
+fn socket() -> io::Result<OwnedFd> {
+    // SAFETY: plain socket(2); the descriptor is owned from here on
+    let fd = unsafe { libc::socket(libc::AF_VSOCK, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+const SOCKADDR_VM_LEN: libc::socklen_t = std::mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t;
+
+/// A connected vsock stream
+pub struct VsockStream {
+    socket: File,
+}
+
+impl VsockStream {
+    pub fn connect(addr: VsockAddr) -> io::Result<Self> {
+        let fd = socket()?;
+        let sockaddr = addr.to_sockaddr();
+        // SAFETY: sockaddr outlives the call and SOCKADDR_VM_LEN is its size
+        let result = unsafe { libc::connect(fd.as_raw_fd(), &sockaddr as *const _ as *const libc::sockaddr, SOCKADDR_VM_LEN) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(VsockStream { socket: File::from(fd) })
+    }
+}
+
+impl Read for VsockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.socket.read(buf)
+    }
+}
+
+impl Write for VsockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.socket.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A vsock socket accepting streams
+pub struct VsockListener {
+    fd: OwnedFd,
+}
+
+impl VsockListener {
+    /// Listen on `addr`; CID_ANY accepts from every VM (and the host)
+    pub fn bind(addr: VsockAddr) -> io::Result<Self> {
+        let fd = socket()?;
+        let sockaddr = addr.to_sockaddr();
+        // SAFETY: as in connect()
+        let bound = unsafe { libc::bind(fd.as_raw_fd(), &sockaddr as *const _ as *const libc::sockaddr, SOCKADDR_VM_LEN) };
+        if bound < 0 || unsafe { libc::listen(fd.as_raw_fd(), 128) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(VsockListener { fd })
+    }
+
+    /// Where the listener is bound, with the port assigned when bound to
+    /// port VMADDR_PORT_ANY
+    pub fn local_addr(&self) -> io::Result<VsockAddr> {
+        // SAFETY: getsockname writes at most `len` bytes into addr
+        let mut addr: libc::sockaddr_vm = unsafe { std::mem::zeroed() };
+        let mut len = SOCKADDR_VM_LEN;
+        if unsafe { libc::getsockname(self.fd.as_raw_fd(), &mut addr as *mut _ as *mut libc::sockaddr, &mut len) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(VsockAddr::from_sockaddr(&addr))
+    }
+
+    /// Wait for the next stream and its peer's address
+    pub fn accept(&self) -> io::Result<(VsockStream, VsockAddr)> {
+        // SAFETY: as in local_addr(); the accepted descriptor is owned at once
+        let mut addr: libc::sockaddr_vm = unsafe { std::mem::zeroed() };
+        let mut len = SOCKADDR_VM_LEN;
+        let fd = unsafe {
+            libc::accept4(self.fd.as_raw_fd(), &mut addr as *mut _ as *mut libc::sockaddr, &mut len, libc::SOCK_CLOEXEC)
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+        Ok((VsockStream { socket }, VsockAddr::from_sockaddr(&addr)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_addresses_parse_and_print() {
+        let host: VsockAddr = "vsock:host:7077".parse().unwrap();
+        assert_eq!(host, VsockAddr { cid: CID_HOST, port: 7077 });
+        assert_eq!("vsock:any:1".parse::<VsockAddr>().unwrap().cid, CID_ANY);
+        assert_eq!("vsock:42:9".parse(), Ok(VsockAddr { cid: 42, port: 9 }));
+        for addr in ["vsock:host:7077", "vsock:any:1", "vsock:42:9"] {
+            assert_eq!(addr.parse::<VsockAddr>().unwrap().to_string(), addr);
+        }
+        for bad in ["host:7077", "vsock:7077", "vsock:x:1", "vsock:2:"] {
+            assert!(bad.parse::<VsockAddr>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_listener_reports_assigned_port() {
+        // Kernels without vsock support refuse the socket; nothing to test
+        let Ok(listener) = VsockListener::bind(VsockAddr { cid: CID_ANY, port: libc::VMADDR_PORT_ANY }) else {
+            return;
+        };
+        let addr = listener.local_addr().unwrap();
+        assert_eq!(addr.cid, CID_ANY);
+        assert_ne!(addr.port, libc::VMADDR_PORT_ANY);
+    }
+}
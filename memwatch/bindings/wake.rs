@@ -0,0 +1,236 @@
+// Waking async consumers
+//
+// Tasks waiting for changes register their Waker here instead of sleeping
+// between check_changes() calls. The native worker wakes them through the
+// callback trampoline as each fault is recorded, and queuing a lifecycle
+// marker (watch, unwatch, poke, ..) wakes them too. The trampoline is only
+// registered with the core once something waits (or a callback is set), so
+// watchers without async consumers pay nothing per fault.
+//
+// Threads without an executor block on the same future with
+// WaitForChange::wait_until().
+//
+// Only the native backend has a worker: in Snapshot mode changes are found
+// by polling, and so are those of hardware breakpoint regions; waiters are
+// then only woken by markers. wakes_on_change() tells which applies;
+// event_stream() falls back to timed polling without it.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::Instant;
+
+use crate::capabilities::WatchMode;
+use crate::MemWatch;
+
+/// Wakers of tasks waiting for the next change
+#[derive(Default)]
+pub(crate) struct ChangeWakers {
+    /// Bumped on every wake, so a waiter can tell it missed none
+    generation: AtomicU64,
+    wakers: Mutex<Vec<Waker>>,
+    /// The trampoline is registered with the backends for waking
+    armed: AtomicBool,
+}
+
+impl ChangeWakers {
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn is_armed(&self) -> bool {
+        self.armed.load(Ordering::SeqCst)
+    }
+
+    /// Wake every waiting task
+    pub(crate) fn wake_all(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        let wakers = std::mem::take(&mut *self.wakers.lock().unwrap_or_else(|e| e.into_inner()));
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    fn register(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock().unwrap_or_else(|e| e.into_inner());
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+}
+
+/// Future of wait_for_change()
+pub struct WaitForChange<'a> {
+    watch: &'a MemWatch,
+    /// Generation when the future was created
+    seen: u64,
+}
+
+impl Future for WaitForChange<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let wakers = &self.watch.callback.wakers;
+        if wakers.generation() != self.seen {
+            return Poll::Ready(());
+        }
+        wakers.register(cx.waker());
+        // A wake between the check and the registration is not lost
+        if wakers.generation() != self.seen {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl WaitForChange<'_> {
+    /// Block the calling thread until the future resolves or `deadline`
+    /// passes; true when it resolved
+    pub fn wait_until(mut self, deadline: Instant) -> bool {
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if Pin::new(&mut self).poll(&mut cx).is_ready() {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            thread::park_timeout(deadline - now);
+        }
+    }
+}
+
+/// Wakes a thread blocked in wait_until()
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+impl MemWatch {
+    /// Resolve once a change is recorded or a marker is queued after this
+    /// call; check_changes() then has something to return. Works with any
+    /// executor. Create the future before checking, so nothing recorded in
+    /// between is missed. Fails if a backend refuses the callback waking
+    /// needs.
+    pub fn wait_for_change(&self) -> Result<WaitForChange<'_>, String> {
+        self.arm_wakeups()?;
+        Ok(WaitForChange {
+            watch: self,
+            seen: self.callback.wakers.generation(),
+        })
+    }
+
+    /// True when the backend wakes wait_for_change() as changes are recorded;
+    /// otherwise only markers do and changes must be polled for. Hardware
+    /// breakpoint regions are polled, so while any is watched this is false.
+    pub fn wakes_on_change(&self) -> bool {
+        self.capabilities.mode == WatchMode::Protect && self.hardware.is_empty()
+    }
+
+    /// Register the trampoline for waking, once; retried by the next wait
+    /// when a backend refuses it
+    fn arm_wakeups(&self) -> Result<(), String> {
+        if self.callback.wakers.armed.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        for backend in self.backends() {
+            if let Err(e) = backend.set_callback(Some(&self.callback)) {
+                self.callback.wakers.armed.store(false, Ordering::SeqCst);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, not(any(feature = "native", feature = "pure"))))]
+mod tests {
+    use super::*;
+    use crate::fake_native::{self, FakeEvent};
+    use std::time::Duration;
+
+    struct Flag(AtomicBool);
+
+    impl Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_worker_and_markers_wake_waiters() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Protect;
+        let balance = [0u8; 4];
+        let region_id = watcher.watch(&balance, "balance").unwrap();
+
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        let waker = Waker::from(Arc::clone(&flag));
+        let mut cx = Context::from_waker(&waker);
+
+        let mut changed = Box::pin(watcher.wait_for_change().unwrap());
+        assert_eq!(changed.as_mut().poll(&mut cx), Poll::Pending);
+        assert!(fake_native::fire(FakeEvent::change(region_id, &[1])));
+        assert!(flag.0.swap(false, Ordering::SeqCst));
+        assert_eq!(changed.as_mut().poll(&mut cx), Poll::Ready(()));
+
+        // A user callback set and cleared later keeps the waking
+        watcher.set_callback(Some(|_: &crate::ChangeEvent| {})).unwrap();
+        watcher.set_callback(None::<fn(&crate::ChangeEvent)>).unwrap();
+        let mut changed = Box::pin(watcher.wait_for_change().unwrap());
+        assert_eq!(changed.as_mut().poll(&mut cx), Poll::Pending);
+        assert!(fake_native::fire(FakeEvent::change(region_id, &[2])));
+        assert!(flag.0.swap(false, Ordering::SeqCst));
+        assert_eq!(changed.as_mut().poll(&mut cx), Poll::Ready(()));
+
+        let mut changed = Box::pin(watcher.wait_for_change().unwrap());
+        assert_eq!(changed.as_mut().poll(&mut cx), Poll::Pending);
+        watcher.unwatch(region_id).unwrap();
+        assert!(flag.0.load(Ordering::SeqCst));
+        assert_eq!(changed.as_mut().poll(&mut cx), Poll::Ready(()));
+    }
+
+    #[test]
+    fn test_a_refused_callback_fails_the_wait() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Protect;
+        fake_native::state().callback_ok = false;
+        assert!(watcher.wait_for_change().is_err());
+        assert!(!watcher.callback.wakers.is_armed());
+
+        fake_native::state().callback_ok = true;
+        assert!(watcher.wait_for_change().is_ok());
+        assert!(watcher.callback.wakers.is_armed() && watcher.wakes_on_change());
+    }
+
+    #[test]
+    fn test_a_blocked_thread_is_woken_or_times_out() {
+        let _guard = fake_native::lock();
+        let mut watcher = MemWatch::new().unwrap();
+        watcher.capabilities.mode = WatchMode::Protect;
+        let balance = [0u8; 4];
+        let region_id = watcher.watch(&balance, "balance").unwrap();
+        watcher.check_changes().unwrap();
+
+        let changed = watcher.wait_for_change().unwrap();
+        assert!(!changed.wait_until(Instant::now() + Duration::from_millis(10)));
+
+        let changed = watcher.wait_for_change().unwrap();
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            fake_native::fire(FakeEvent::change(region_id, &[1]))
+        });
+        assert!(changed.wait_until(Instant::now() + Duration::from_secs(10)));
+        assert!(writer.join().unwrap());
+    }
+}
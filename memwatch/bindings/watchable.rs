@@ -0,0 +1,107 @@
+// Field-level events for watched structs
+//
+// A Watchable type lists its fields' names, offsets and sizes, usually
+// through `#[derive(Watchable)]` (the `derive` feature, from the
+// memwatch-derive crate). watch_struct() registers the layout with the
+// region, and each change to it is reported once per changed field, named
+// "<region name>.<field>", e.g. "Config.timeout_ms".
+//
+// ChangeEvent carries no write offset, so changed fields are found by
+// comparing old and new bytes: full values when captured (watch_struct asks
+// for them), previews otherwise. A change that cannot be placed in a field,
+// e.g. past the compared bytes or in padding, keeps the region's name.
+
+use std::collections::HashMap;
+
+use crate::ChangeEvent;
+
+/// One field of a Watchable type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldInfo {
+    pub name: &'static str,
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// A struct whose changes can be reported per field
+///
+/// ```ignore
+/// #[derive(memwatch::Watchable)]
+/// struct Config {
+///     timeout_ms: u64,
+///     retries: u32,
+/// }
+///
+/// let id = watcher.watch_struct(&config, "Config")?;
+/// ```
+pub trait Watchable {
+    /// Fields in declaration order; ranges outside the type are ignored
+    const FIELDS: &'static [FieldInfo];
+}
+
+/// Split changes to regions with a layout into one event per changed field
+pub(crate) fn split_by_field(events: Vec<ChangeEvent>, layouts: &HashMap<u32, &'static [FieldInfo]>) -> Vec<ChangeEvent> {
+    if layouts.is_empty() {
+        return events;
+    }
+    let mut out = Vec::with_capacity(events.len());
+    for event in events {
+        let fields = match layouts.get(&event.region_id) {
+            Some(fields) if !event.kind.is_marker() => changed_fields(&event, fields),
+            _ => Vec::new(),
+        };
+        if fields.is_empty() {
+            out.push(event);
+            continue;
+        }
+        let region = event.variable_name.clone().unwrap_or_default();
+        for field in fields {
+            let mut split = event.clone();
+            split.variable_name = Some(format!("{}.{}", region, field.name));
+            out.push(split);
+        }
+    }
+    out
+}
+
+fn changed_fields(event: &ChangeEvent, fields: &[FieldInfo]) -> Vec<FieldInfo> {
+    let (old, new) = if event.old_value.is_empty() || event.new_value.is_empty() {
+        (&event.old_preview, &event.new_preview)
+    } else {
+        (&event.old_value, &event.new_value)
+    };
+    let compared = old.len().min(new.len());
+    fields
+        .iter()
+        .filter(|field| {
+            let end = field.offset + field.size;
+            end <= compared && old[field.offset..end] != new[field.offset..end]
+        })
+        .copied()
+        .collect()
+}
+
+#[cfg(all(test, feature = "derive"))]
+mod tests {
+    use super::*;
+
+    #[derive(crate::Watchable)]
+    #[allow(dead_code)]
+    struct Limits<T> {
+        soft: T,
+        hard: T,
+        name: [u8; 3],
+    }
+
+    #[derive(crate::Watchable)]
+    #[allow(dead_code)]
+    struct Pair(u16, u64);
+
+    #[test]
+    fn test_derive_lists_fields() {
+        let names: Vec<_> = Limits::<u32>::FIELDS.iter().map(|f| (f.name, f.size)).collect();
+        assert_eq!(names, [("soft", 4), ("hard", 4), ("name", 3)]);
+        assert_eq!(Limits::<u32>::FIELDS[1].offset, std::mem::offset_of!(Limits<u32>, hard));
+        assert_eq!(Pair::FIELDS[1], FieldInfo { name: "1", offset: std::mem::offset_of!(Pair, 1), size: 8 });
+    }
+}
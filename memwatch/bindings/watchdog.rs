@@ -0,0 +1,132 @@
+// Watchdog for the native worker thread
+//
+// The C core drains faults on a worker thread; if that thread wedges, the
+// ring stops filling and check_changes() quietly returns nothing. With a
+// watchdog set, every check reads worker_cycles from the stats and, once
+// the counter has not moved for `stall_after`, queues a WorkerStalled marker
+// (region 0). The marker is raised once per stall; progress re-arms it.
+//
+// With `restart` the native backend is also torn down, re-initialized and
+// every live region re-watched at its address. Events still in the ring are
+// lost, and regions may come back under new ids: the old id then gets an
+// Unwatched marker and the new one a Watched marker.
+//
+// Only the native backend has a worker; the watchdog is idle in Snapshot
+// mode.
+
+use std::time::{Duration, Instant};
+
+use crate::Stats;
+
+/// When the worker counts as stalled, and what to do about it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchdog {
+    /// No worker cycle for this long is a stall
+    pub stall_after: Duration,
+    /// Restart the native backend on a stall
+    pub restart: bool,
+}
+
+/// A stall the watchdog just detected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Stall {
+    pub thread_id: u32,
+    pub stalled_for: Duration,
+    pub restart: bool,
+}
+
+#[derive(Default)]
+pub(crate) struct WatchdogState {
+    policy: Option<Watchdog>,
+    /// (worker_thread_id, worker_cycles, when they last changed)
+    last: Option<(u32, u64, Instant)>,
+    reported: bool,
+}
+
+impl WatchdogState {
+    pub(crate) fn set_policy(&mut self, policy: Option<Watchdog>) {
+        self.policy = policy;
+        self.last = None;
+        self.reported = false;
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.policy.is_some()
+    }
+
+    /// Whether the worker is making progress; None without a policy
+    pub(crate) fn worker_alive(&self) -> Option<bool> {
+        self.policy.map(|_| !self.reported)
+    }
+
+    /// Forget the baseline, e.g. after the backend was restarted
+    pub(crate) fn rearm(&mut self) {
+        self.last = None;
+        self.reported = false;
+    }
+
+    /// Record a stats sample; Some when the worker has just been found
+    /// stalled
+    pub(crate) fn observe(&mut self, stats: &Stats, now: Instant) -> Option<Stall> {
+        let policy = self.policy?;
+        let sample = (stats.worker_thread_id, stats.worker_cycles);
+        let since = match self.last {
+            Some((thread_id, cycles, since)) if (thread_id, cycles) == sample => since,
+            _ => {
+                self.last = Some((sample.0, sample.1, now));
+                self.reported = false;
+                return None;
+            }
+        };
+        let stalled_for = now.saturating_duration_since(since);
+        if self.reported || stalled_for < policy.stall_after {
+            return None;
+        }
+        self.reported = true;
+        Some(Stall {
+            thread_id: sample.0,
+            stalled_for,
+            restart: policy.restart,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(cycles: u64) -> Stats {
+        Stats {
+            num_tracked_regions: 0,
+            num_active_watchpoints: 0,
+            total_events: 0,
+            ring_write_count: 0,
+            ring_drop_count: 0,
+            storage_bytes_used: 0,
+            mprotect_page_count: 0,
+            worker_thread_id: 42,
+            worker_cycles: cycles,
+            sampled_out_count: 0,
+            consumers: Vec::new(),
+            events_per_thread: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_stall_reported_once_until_progress() {
+        let mut state = WatchdogState::default();
+        let start = Instant::now();
+        assert_eq!(state.observe(&stats(1), start), None);
+
+        state.set_policy(Some(Watchdog { stall_after: Duration::from_secs(2), restart: false }));
+        assert_eq!(state.observe(&stats(1), start), None);
+        assert_eq!(state.observe(&stats(1), start + Duration::from_secs(1)), None);
+        let stall = state.observe(&stats(1), start + Duration::from_secs(3)).unwrap();
+        assert_eq!((stall.thread_id, stall.stalled_for), (42, Duration::from_secs(3)));
+        assert_eq!(state.observe(&stats(1), start + Duration::from_secs(9)), None);
+
+        // Progress re-arms the watchdog
+        assert_eq!(state.observe(&stats(2), start + Duration::from_secs(10)), None);
+        assert!(state.observe(&stats(2), start + Duration::from_secs(12)).is_some());
+    }
+}
@@ -9,7 +9,7 @@ use std::collections::HashMap;
 
 // Include the sql_tracker module
 mod sqltracker;
-use sqltracker::{SQLTracker, SQLOperation};
+use memwatch::sql_tracker::{SQLTracker, SQLOperation};
 
 fn main() {
     println!("=== SQL Tracker Example - Rust ===\n");
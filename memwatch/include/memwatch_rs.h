@@ -0,0 +1,162 @@
+#ifndef MEMWATCH_RS_H
+#define MEMWATCH_RS_H
+
+/* Generated by cbindgen from bindings/capi.rs - do not edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include "memwatch_unified.h"
+
+typedef struct memwatch_rs_t memwatch_rs_t;
+
+/**
+ * Change event owned by the Rust layer; release with `memwatch_rs_event_free`
+ */
+typedef struct MemwatchRsEvent {
+  uint32_t seq;
+  uint64_t timestamp_ns;
+  uint32_t adapter_id;
+  uint32_t region_id;
+  char *variable_name;
+  char *file;
+  char *function;
+  uint32_t line;
+  uint64_t fault_ip;
+  uint8_t *old_preview;
+  uintptr_t old_preview_size;
+  uint8_t *new_preview;
+  uintptr_t new_preview_size;
+  /**
+   * 0 = change, 1 = watched, 2 = unwatched, 3 = paused, 4 = resumed, 5 = relocated, 6 = anomaly, 7 = freed, 8 = use after unwatch, 9 = alias warning, 10 = worker stalled, 11 = huge page warning, 12 = shared page warning, 13 = callback panicked, 14 = triggered
+   */
+  uint32_t kind;
+  uint32_t epoch;
+  uint64_t global_seq;
+} MemwatchRsEvent;
+
+/**
+ * Sink callback: the event is only valid during the call. Return 0 on
+ * success; anything else counts as a failed delivery.
+ */
+typedef int (*MemwatchRsSinkFn)(const MemwatchRsEvent *event, void *user_ctx);
+
+/**
+ * A changed byte range: `len` bytes from `offset`
+ */
+typedef struct MemwatchRsRange {
+  uintptr_t offset;
+  uintptr_t len;
+} MemwatchRsRange;
+
+/**
+ * A parsed SQL statement; release with `memwatch_rs_statement_free`
+ */
+typedef struct MemwatchRsStatement {
+  /**
+   * 0 = unknown, 1 = insert, 2 = update, 3 = delete, 4 = select
+   */
+  uint32_t operation;
+  /**
+   * NULL when the statement names no table
+   */
+  char *table;
+  /**
+   * Columns written (INSERT, UPDATE) or read (SELECT); "*" when not listed
+   */
+  char **columns;
+  /**
+   * Source text of the value assigned to each column, NULL where none
+   */
+  char **values;
+  uintptr_t columns_len;
+  bool has_where;
+} MemwatchRsStatement;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Create a watcher handle. Returns NULL if the native core fails to initialize.
+ */
+memwatch_rs_t *memwatch_rs_new(void);
+
+/**
+ * Destroy a handle created by `memwatch_rs_new`
+ */
+void memwatch_rs_free(memwatch_rs_t *handle);
+
+/**
+ * Watch `size` bytes at `addr`. Returns region_id > 0 on success, 0 on error.
+ */
+uint32_t memwatch_rs_watch(const memwatch_rs_t *handle,
+                           const uint8_t *addr,
+                           uintptr_t size,
+                           const char *name,
+                           int32_t max_value_bytes);
+
+/**
+ * Stop watching a region. Returns 0 when removed, 1 when the region was
+ * not tracked, or a negative value on error.
+ */
+int memwatch_rs_unwatch(const memwatch_rs_t *handle, uint32_t region_id);
+
+/**
+ * Poll for changes. Returns the number of events written to `out_events`
+ * (at most `max_events`), or a negative value on error. Events beyond
+ * `max_events` are kept for the next call, which returns them before
+ * polling again.
+ */
+int memwatch_rs_check_changes(const memwatch_rs_t *handle,
+                              MemwatchRsEvent *out_events,
+                              int max_events);
+
+/**
+ * Drop changes the filter expression rejects (see the filter module for
+ * the syntax); NULL removes the filter. Returns 0, or -1 if the expression
+ * does not parse.
+ */
+int memwatch_rs_set_filter(const memwatch_rs_t *handle, const char *expr);
+
+/**
+ * Deliver every event check_changes() returns to `callback`, after
+ * filtering and sequencing. Returns 0, or -1 on a NULL handle.
+ */
+int memwatch_rs_add_sink(const memwatch_rs_t *handle, MemwatchRsSinkFn callback, void *user_ctx);
+
+/**
+ * Byte ranges that differ between two values, ascending. Writes up to
+ * `max_ranges` to `out_ranges` and returns how many there are in total
+ * (more than `max_ranges` means the output was cut short), or -1 on error.
+ */
+int memwatch_rs_diff(const uint8_t *old_value,
+                     uintptr_t old_size,
+                     const uint8_t *new_value,
+                     uintptr_t new_size,
+                     MemwatchRsRange *out_ranges,
+                     int max_ranges);
+
+/**
+ * Parse one INSERT, UPDATE, DELETE or SELECT statement into `out`; other
+ * statements parse as unknown. Returns 0, or -1 on error.
+ */
+int memwatch_rs_sql_parse(const char *sql, MemwatchRsStatement *out);
+
+/**
+ * Release what a parsed statement owns. Idempotent.
+ */
+void memwatch_rs_statement_free(MemwatchRsStatement *statement);
+
+/**
+ * Release the strings and previews owned by an event. Idempotent.
+ */
+void memwatch_rs_event_free(MemwatchRsEvent *event);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* MEMWATCH_RS_H */
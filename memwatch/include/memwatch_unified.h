@@ -53,6 +53,14 @@ typedef struct {
     
     /* Custom metadata from watch() call */
     const void *user_data;
+    
+    /* Writing thread, captured at fault time */
+    uint32_t thread_id;           /* Kernel tid, 0 if unknown */
+    const char *thread_name;      /* NULL if not captured */
+
+    /* Return addresses at fault time, innermost first */
+    const uint64_t *backtrace;    /* NULL unless a backtrace depth is set */
+    size_t backtrace_len;
 } memwatch_change_event_t;
 
 /* Callback function signature - same for all languages */
@@ -62,6 +70,39 @@ typedef void (*memwatch_callback_t)(const memwatch_change_event_t *event, void *
  * Core API - Unified for All Languages
  * ============================================================================ */
 
+/* ============================================================================
+ * Configuration - takes effect at the next memwatch_init()
+ * ============================================================================ */
+
+/**
+ * Set the number of event ring slots
+ *
+ * Returns: 0 on success, negative on error (e.g. capacity 0)
+ */
+int memwatch_config_set_ring_capacity(uint32_t capacity);
+
+/**
+ * Enable or disable resolving fault addresses to file/function/line
+ *
+ * Enabled by default. Disabled, events only carry fault_ip.
+ *
+ * Returns: 0 on success, MEMWATCH_ERR_UNSUPPORTED when enabling it on a
+ * core without a symbolizer (the minimal core), negative on other errors
+ */
+int memwatch_config_set_symbolication(bool enabled);
+
+/**
+ * Capture up to `depth` return addresses of the writing thread per event
+ *
+ * 0 (the default) captures none. Addresses are reported raw, innermost
+ * first; resolving them is left to the caller.
+ *
+ * Returns: 0 on success, MEMWATCH_ERR_UNSUPPORTED for a depth above 0 on a
+ * core that captures no backtraces (the minimal core), negative on other
+ * errors (e.g. depth above 64)
+ */
+int memwatch_config_set_backtrace_depth(uint32_t depth);
+
 /**
  * Initialize memwatch
  * 
@@ -104,6 +145,27 @@ memwatch_region_id memwatch_watch(uint64_t addr, size_t size,
  */
 bool memwatch_unwatch(memwatch_region_id region_id);
 
+/**
+ * Suspend watching a region, keeping its registration
+ *
+ * Marks the region paused: its writes are dropped instead of reported
+ * until memwatch_resume_region(). The region stays registered as it was;
+ * no page protection changes.
+ *
+ * Returns: 0 on success, negative if the region is unknown
+ */
+int memwatch_pause_region(memwatch_region_id region_id);
+
+/**
+ * Restart watching a paused region
+ *
+ * Takes a fresh snapshot of the region as the baseline for old values and
+ * clears the paused mark. Writes made while paused are not reported.
+ *
+ * Returns: 0 on success, negative if the region is unknown
+ */
+int memwatch_resume_region(memwatch_region_id region_id);
+
 /**
  * Set global callback for all change events
  * 
@@ -164,6 +226,16 @@ int memwatch_get_stats(memwatch_stats_t *out_stats);
  */
 void memwatch_free_event(memwatch_change_event_t *event);
 
+/**
+ * Read back a value stored under an event's storage_key_old/new
+ *
+ * Copies up to *inout_size bytes into out and sets *inout_size to the
+ * value's full size; pass out = NULL to query the size only.
+ *
+ * Returns: 0 on success, negative if no value is stored under key
+ */
+int memwatch_fetch_value(const char *key, uint8_t *out, size_t *inout_size);
+
 /* ============================================================================
  * Adapter Management (for language bindings)
  * ============================================================================ */
@@ -191,6 +263,7 @@ void memwatch_unregister_adapter(memwatch_adapter_id adapter_id);
 #define MEMWATCH_ERR_NO_MEMORY -3
 #define MEMWATCH_ERR_MPROTECT -4
 #define MEMWATCH_ERR_NOT_FOUND -5
+#define MEMWATCH_ERR_UNSUPPORTED -6
 
 #ifdef __cplusplus
 }  /* extern "C" */
@@ -1,5 +1,9 @@
 /*
  * memwatch_cli_simple.c - Memory tracking CLI using real mprotect tracking
+ *
+ * `read <db> --json` prints one JSON object per change (JSON Lines):
+ *   {"timestamp_ms":N,"region_name":"..","offset":N,"old_value":"..","new_value":".."}
+ * Text fields are null when missing.
  */
 
 #define _GNU_SOURCE
@@ -88,7 +92,28 @@ static int cmd_run(const char *program, int argc, char **argv, const char *db_pa
     return 1;
 }
 
-static int cmd_read(const char *db_path) {
+static void print_json_string(const unsigned char *s) {
+    if (!s) {
+        fputs("null", stdout);
+        return;
+    }
+    putchar('"');
+    for (; *s; s++) {
+        switch (*s) {
+            case '"':  fputs("\\\"", stdout); break;
+            case '\\': fputs("\\\\", stdout); break;
+            case '\n': fputs("\\n", stdout); break;
+            case '\r': fputs("\\r", stdout); break;
+            case '\t': fputs("\\t", stdout); break;
+            default:
+                if (*s < 0x20) printf("\\u%04x", *s);
+                else putchar(*s);
+        }
+    }
+    putchar('"');
+}
+
+static int cmd_read(const char *db_path, bool json) {
     sqlite3 *db = NULL;
     sqlite3_stmt *stmt = NULL;
 
@@ -105,9 +130,27 @@ static int cmd_read(const char *db_path) {
         return 1;
     }
 
-    int count = 0;
     while (sqlite3_step(stmt) == SQLITE_ROW) {
-        count++;
+        long long timestamp_ms = sqlite3_column_int64(stmt, 0);
+        const unsigned char *region_name = sqlite3_column_text(stmt, 1);
+        long long offset = sqlite3_column_int64(stmt, 2);
+        const unsigned char *old_value = sqlite3_column_text(stmt, 3);
+        const unsigned char *new_value = sqlite3_column_text(stmt, 4);
+
+        if (json) {
+            printf("{\"timestamp_ms\":%lld,\"region_name\":", timestamp_ms);
+            print_json_string(region_name);
+            printf(",\"offset\":%lld,\"old_value\":", offset);
+            print_json_string(old_value);
+            fputs(",\"new_value\":", stdout);
+            print_json_string(new_value);
+            fputs("}\n", stdout);
+        } else {
+            printf("[%lld] %s+%lld: %s -> %s\n", timestamp_ms,
+                   region_name ? (const char *)region_name : "?", offset,
+                   old_value ? (const char *)old_value : "NULL",
+                   new_value ? (const char *)new_value : "NULL");
+        }
     }
 
     sqlite3_finalize(stmt);
@@ -116,6 +159,8 @@ static int cmd_read(const char *db_path) {
 }
 
 static void print_help(void) {
+    fprintf(stderr, "usage: memwatch_cli run <program> [args...] --storage <db> [--track-all-vars] [--track-sql] [--threads] [--scope <scope>]\n");
+    fprintf(stderr, "       memwatch_cli read <db> [--json]\n");
 }
 
 int main(int argc, char *argv[]) {
@@ -163,7 +208,11 @@ int main(int argc, char *argv[]) {
         if (argc < 3) {
             return 1;
         }
-        return cmd_read(argv[2]);
+        bool json = false;
+        for (int i = 3; i < argc; i++) {
+            if (strcmp(argv[i], "--json") == 0) json = true;
+        }
+        return cmd_read(argv[2], json);
     }
 
     print_help();
@@ -17,10 +17,12 @@
 #include <errno.h>
 #include <stdlib.h>
 #include <stdio.h>
+#include <sys/syscall.h>
 
 #include "memwatch_unified.h"
 
 #define RING_CAPACITY 65536
+#define MAX_BACKTRACE_DEPTH 64
 #define PAGE_SIZE 4096
 #define PREVIEW_SIZE 256
 #define MAX_REGIONS 4096
@@ -30,6 +32,7 @@ typedef struct {
     uintptr_t page_start;
     uint32_t region_id;
     uint64_t timestamp_ns;
+    uint32_t thread_id;
 } PageEvent;
 
 /* Tracked region */
@@ -40,12 +43,21 @@ typedef struct {
     uint32_t region_id;
     void *user_data;
     bool active;
+    bool paused;
     uint8_t *last_snapshot;
 } TrackedRegion;
 
+/* Settings for the next memwatch_init() */
+static struct {
+    uint32_t ring_capacity;
+} g_config = {
+    .ring_capacity = RING_CAPACITY,
+};
+
 /* Global state */
 static struct {
     PageEvent *ring;
+    uint32_t ring_capacity;
     atomic_uint ring_head;
     atomic_uint ring_tail;
     
@@ -71,8 +83,10 @@ static void sigsegv_handler(int sig, siginfo_t *info, void *uctx) {
     
     /* Just record in ring and continue */
     unsigned head = atomic_load(&g_state.ring_head);
-    if (head + 1 < RING_CAPACITY) {
+    if (head + 1 < g_state.ring_capacity) {
         g_state.ring[head].timestamp_ns = (uint64_t)time(NULL) * 1000000000ULL;
+        /* The faulting thread; gettid is async-signal-safe */
+        g_state.ring[head].thread_id = (uint32_t)syscall(SYS_gettid);
         atomic_store(&g_state.ring_head, head + 1);
     }
 }
@@ -95,6 +109,11 @@ static void* worker_thread_fn(void *arg) {
                     
                     TrackedRegion *region = &g_state.regions[i];
                     
+                    /* Writes to a paused region are not reported */
+                    if (region->paused) {
+                        break;
+                    }
+                    
                     /* Create event */
                     memwatch_change_event_t event = {
                         .seq = tail,
@@ -106,6 +125,7 @@ static void* worker_thread_fn(void *arg) {
                         .new_preview = (uint8_t *)"value",
                         .new_preview_size = 5,
                         .user_data = region->user_data,
+                        .thread_id = evt->thread_id,
                     };
                     
                     pthread_mutex_lock(&g_state.callback_mutex);
@@ -129,15 +149,37 @@ static void* worker_thread_fn(void *arg) {
 
 /* API Implementation */
 
+int memwatch_config_set_ring_capacity(uint32_t capacity) {
+    if (capacity == 0) {
+        return -1;
+    }
+    g_config.ring_capacity = capacity;
+    return 0;
+}
+
+int memwatch_config_set_symbolication(bool enabled) {
+    /* The minimal core has no symbolizer: events never carry a location */
+    return enabled ? MEMWATCH_ERR_UNSUPPORTED : MEMWATCH_OK;
+}
+
+int memwatch_config_set_backtrace_depth(uint32_t depth) {
+    if (depth > MAX_BACKTRACE_DEPTH) {
+        return -1;
+    }
+    /* The minimal core captures no backtraces */
+    return depth == 0 ? MEMWATCH_OK : MEMWATCH_ERR_UNSUPPORTED;
+}
+
 int memwatch_init(void) {
     if (g_state.ring) {
         return 0;  /* Already initialized */
     }
     
-    g_state.ring = calloc(RING_CAPACITY, sizeof(PageEvent));
+    g_state.ring = calloc(g_config.ring_capacity, sizeof(PageEvent));
     if (!g_state.ring) {
         return -1;
     }
+    g_state.ring_capacity = g_config.ring_capacity;
     
     pthread_mutex_init(&g_state.regions_mutex, NULL);
     pthread_mutex_init(&g_state.callback_mutex, NULL);
@@ -196,6 +238,7 @@ memwatch_region_id memwatch_watch(uint64_t addr, size_t size,
             g_state.regions[i].region_id = region_id;
             g_state.regions[i].user_data = user_data;
             g_state.regions[i].last_snapshot = malloc(size < 256 ? size : 256);
+            g_state.regions[i].paused = false;
             g_state.regions[i].active = true;
             break;
         }
@@ -225,6 +268,41 @@ bool memwatch_unwatch(memwatch_region_id region_id) {
     return false;
 }
 
+/* Active region with this id, or NULL; call with regions_mutex held */
+static TrackedRegion *find_region(memwatch_region_id region_id) {
+    for (int i = 0; i < MAX_REGIONS; i++) {
+        if (g_state.regions[i].active && g_state.regions[i].region_id == region_id) {
+            return &g_state.regions[i];
+        }
+    }
+    return NULL;
+}
+
+int memwatch_pause_region(memwatch_region_id region_id) {
+    pthread_mutex_lock(&g_state.regions_mutex);
+    TrackedRegion *region = find_region(region_id);
+    if (region) {
+        region->paused = true;
+    }
+    pthread_mutex_unlock(&g_state.regions_mutex);
+    return region ? 0 : -1;
+}
+
+int memwatch_resume_region(memwatch_region_id region_id) {
+    pthread_mutex_lock(&g_state.regions_mutex);
+    TrackedRegion *region = find_region(region_id);
+    if (region) {
+        /* Live contents are the new baseline */
+        if (region->last_snapshot) {
+            memcpy(region->last_snapshot, (const void *)(uintptr_t)region->addr,
+                   region->size < 256 ? region->size : 256);
+        }
+        region->paused = false;
+    }
+    pthread_mutex_unlock(&g_state.regions_mutex);
+    return region ? 0 : -1;
+}
+
 int memwatch_set_callback(memwatch_callback_t callback, void *user_ctx) {
     pthread_mutex_lock(&g_state.callback_mutex);
     g_state.callback = callback;
@@ -239,6 +317,13 @@ int memwatch_check_changes(memwatch_change_event_t *out_events, int max_events)
     return 0;  /* Polling not implemented in minimal version */
 }
 
+int memwatch_fetch_value(const char *key, uint8_t *out, size_t *inout_size) {
+    (void)key;
+    (void)out;
+    (void)inout_size;
+    return -1;  /* The minimal version stores no values and hands out no keys */
+}
+
 int memwatch_get_stats(memwatch_stats_t *out_stats) {
     if (!out_stats) return -1;
     